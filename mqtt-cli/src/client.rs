@@ -1,173 +1,528 @@
-use std::{net::TcpStream, io::{Write, Read}};
+use std::{collections::BTreeMap, io::{self, Write, Read}, sync::atomic::{AtomicBool, Ordering}, time::{Duration, Instant}};
 
-use mqtt::{error::MqttError, packet::{Connect, Connack, Publish, Disconnect, Puback, PacketType, Pubrec, Pubrel, Pubcomp, ConnackProperties, Subscribe}, types::{QoS, ReasonCode}};
+use mqtt::{backoff::Backoff, error::MqttError, packet::{Connack, Connect, Packet, PacketIter, PacketType, Pingreq, Publish, Disconnect, ConnectProperties, Subscribe}, session::{ClientIdHook, ClientSession, Direction, DueAction, Handshake, HandshakeStep, NegotiatedSession, PublishOutcome, Redirect, SessionConfig, SubscribeOutcome}, transport::{TcpTransport, Transport}, types::{QoS, ReasonCode}};
 
 use crate::{Session, CmdResult};
 
 pub struct Client {
     session: Session,
-    client_id: String,
+    state: ClientSession,
     packet_id: Option<u16>,
     connected: bool,
-    stream: TcpStream,
+    /// as first sent, to connect again with after losing the connection
+    connect: Option<Connect>,
+    stream: Box<dyn Transport>,
+    /// bytes read from the stream that don't make up a complete packet yet
+    inbound: Vec<u8>,
+    /// how often each reason code was received, for the summary
+    reason_codes: BTreeMap<ReasonCode, usize>,
 }
 
+const BUFFER_SIZE: usize = 4096;
+
+/// How often [Client::listen] checks whether to stop while nothing arrives.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
 impl Client {
 
     pub fn connect(session: Session) -> Result<Self, MqttError> {
-        let addr = session.addr();
-        println!("Connecting to {:?}", addr);
+        let (host, port) = session.addr();
+        let stream = open_retrying((host, port), &session)?;
+        let mut client = Self::new(session, stream);
+        client.resume()?;
+        let connect = client.connect_packet();
+        // the last will is only meant for the first connection
+        let mut again = connect.clone();
+        again.will = None;
+        client.connect = Some(again);
 
-        let stream = TcpStream::connect(&addr).unwrap_or_else(|e| {
-            panic!("Error establishing connection to server: {:?}", e)
-        });
+        loop {
+            let connack = client.handshake(connect.clone())?;
+            let redirect = match Redirect::from_connack(&connack)? {
+                Some(redirect) => redirect,
+                None => {
+                    // a resumed session the server doesn't know anymore has to be subscribed again
+                    if client.connected && client.session.store().is_some() && !connack.session_present {
+                        client.resubscribe()?;
+                    }
+                    return Ok(client)
+                },
+            };
+
+            match client.state.follow_redirect(&redirect) {
+                Some(reference) => {
+                    println!("Redirected to {}", reference);
+                    client.stream = open(reference.addr(port), client.session.connect_timeout())?;
+                    client.inbound.clear();
+                },
+                None => return Err(MqttError::Message(format!(
+                    "Connection refused with reason code {:?}, server redirects to: {}",
+                    connack.reason_code, targets(&redirect)))),
+            }
+        }
+    }
 
-        let mut client = Client {
+    fn new(session: Session, stream: Box<dyn Transport>) -> Self {
+        let config = SessionConfig {
+            redirect_policy: session.redirect_policy(),
+            request_response_information: session.request_response_information(),
+            keep_alive: Some(session.keep_alive()),
+            on_assigned_client_id: Some(ClientIdHook::new(|id| println!("Assigned client identifier: {}", id))),
+            ..Default::default()
+        };
+        Client {
             session,
-            client_id: String::new(),
+            state: ClientSession::new(config),
             packet_id: None,
             connected: false,
+            connect: None,
             stream,
+            inbound: Vec::new(),
+            reason_codes: BTreeMap::new(),
+        }
+    }
+
+    /// Takes up the session saved by an earlier run, if there is one.
+    fn resume(&mut self) -> CmdResult {
+        let Some(snapshot) = self.session.store().map(|store| store.load()).transpose()?.flatten() else {
+            return Ok(())
         };
-        let connect = Connect::default();
+        println!(
+            "Resuming session {} with {} subscription(s) and {} message(s) awaiting PUBREL",
+            snapshot.client_id.as_deref().unwrap_or("without client identifier"),
+            snapshot.subscriptions.len(),
+            snapshot.awaiting_pubrel.len());
+        self.state.restore(snapshot);
+        Ok(())
+    }
+
+    fn connect_packet(&mut self) -> Connect {
+        let mut connect = Connect::default();
+        if let Some(will) = self.session.take_will() {
+            if let Some(delay) = will.properties.as_ref().and_then(|p| p.will_delay_interval) {
+                // the server publishes the will when the session ends at the latest, so it must outlive the delay
+                connect.properties = Some(ConnectProperties { session_expiry_interval: Some(delay), ..Default::default() });
+            }
+            connect.will = Some(will);
+        }
+        if !self.session.user_properties().is_empty() {
+            let properties = connect.properties.get_or_insert_with(ConnectProperties::default);
+            properties.user_property = self.session.user_properties().clone();
+        }
+        if self.session.store().is_some() {
+            connect.clean_start = false;
+            let properties = connect.properties.get_or_insert_with(ConnectProperties::default);
+            let expiry = properties.session_expiry_interval.unwrap_or_default().max(self.session.session_expiry());
+            properties.session_expiry_interval = Some(expiry);
+        }
+        self.state.prepare_connect(&mut connect);
+        connect
+    }
+
+    fn handshake(&mut self, connect: Connect) -> Result<Connack, MqttError> {
         println!("CONNECT: {:?}", connect);
 
-        client.send(connect)?;
-        let connack_bytes = client.receive()?;
-        let connack = Connack::try_from(&connack_bytes[..])?;
+        let mut handshake = Handshake::new(&connect);
+        self.send(connect)?;
+
+        // a server that doesn't answer at all, or in another protocol, would otherwise leave us hanging or fail to
+        // decode whatever it sent
+        let timeout = self.session.connect_timeout();
+        self.set_read_timeout(Some(timeout))?;
+        match self.peek()? {
+            Some(byte) => Handshake::check_first_byte(byte)?,
+            None => return Err(MqttError::Message(format!("No response to CONNECT within {:?}", timeout))),
+        }
+
+        let connack = loop {
+            match handshake.on_packet(self.receive()?)? {
+                HandshakeStep::Challenge(data) => {
+                    self.session.debug(format!("AUTH challenge: {:?}", data));
+                    let auth = handshake.respond(None)?;
+                    self.send(auth)?;
+                },
+                HandshakeStep::Done(connack) => break *connack,
+            }
+        };
+        self.set_read_timeout(None)?;
         
         println!("CONNACK: {:?}", connack);
         
-        client.connected = true;
-
-        if let Some(ConnackProperties { assigned_client_identifier, .. }) = connack.properties {
-            if let Some(s) = assigned_client_identifier {
-                client.client_id = s;
-            }
+        self.connected = !connack.reason_code.is_err();
+        self.state.on_connack(&connack);
+        if let Some(negotiated) = self.state.negotiated().filter(|_| self.connected) {
+            println!("{}", keep_alive(negotiated));
         }
 
-        Ok(client)
+        Ok(connack)
+    }
+
+    /// A response topic based on the response information the server sent when connecting, see
+    /// [ClientSession::response_topic].
+    pub fn response_topic(&self, suffix: &str) -> Result<String, MqttError> {
+        self.state.response_topic(suffix)
+            .ok_or_else(|| MqttError::Message("Server didn't send any response information".to_string()))
     }
 
-    pub fn publish(&mut self, packet: Publish) -> CmdResult {
-        let qos = packet.qos_level.clone();
+    /// Publishes a message and waits for its flow to complete with QoS 1 or 2. The outcome is `None` for QoS 0, which
+    /// isn't acknowledged.
+    pub fn publish(&mut self, mut packet: Publish) -> Result<Option<PublishOutcome>, MqttError> {
+        self.state.prepare_publish(&mut packet)?;
+        let qos = packet.qos_level;
         self.packet_id = packet.packet_identifier;
         println!("PUBLISH: {:?}", packet);
+        if let Some(id) = packet.packet_identifier {
+            self.trace_flow("PUBLISH", Direction::Outgoing, id);
+        }
         self.send(packet)?;
         match qos {
-            QoS::AtMostOnce => Ok(()),
-            els => self.handle_pub_qos(els),
+            QoS::AtMostOnce => Ok(None),
+            _ => self.handle_pub_qos().map(Some),
+        }
+    }
+
+    /// Sends all `packets` in a single write, there is no acknowledgement to wait for with QoS 0.
+    pub fn publish_batch(&mut self, packets: Vec<Publish>) -> CmdResult {
+        println!("PUBLISH: {} packets", packets.len());
+
+        let mut batch: Vec<u8> = Vec::new();
+        for mut packet in packets {
+            self.state.prepare_publish(&mut packet)?;
+            let mut encoded = self.state.encode(packet)?;
+            self.state.on_packet_sent(PacketType::PUBLISH, encoded.len());
+            batch.append(&mut encoded);
+        }
+
+        self.session.debug(format!("Sending {} bytes to server", batch.len()));
+        match self.stream.write_all(&batch) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(MqttError::Message(format!("Error sending PUBLISH batch: {:?}", e))),
+        }
+    }
+
+    /// Publishes a QoS 1 or 2 message without printing anything and returns how long its flow took to complete, from
+    /// sending the `PUBLISH` to receiving the `PUBACK` or `PUBCOMP`. The packet identifier is allocated here.
+    pub fn round_trip(&mut self, mut packet: Publish) -> Result<Duration, MqttError> {
+        packet.packet_identifier = Some(self.next_packet_id());
+        self.state.prepare_publish(&mut packet)?;
+        let start = Instant::now();
+        self.send(packet)?;
+
+        loop {
+            match self.receive()? {
+                Packet::Puback(puback) => {
+                    self.state.on_puback(&puback)?;
+                    break
+                },
+                Packet::Pubrec(pubrec) => match self.state.on_pubrec(&pubrec)? {
+                    Some(pubrel) => self.send(pubrel)?,
+                    None => return Err(MqttError::Message(format!("PUBLISH {}", PublishOutcome::from(&pubrec)))),
+                },
+                Packet::Pubcomp(pubcomp) => {
+                    self.state.on_pubcomp(&pubcomp)?;
+                    break
+                },
+                Packet::Disconnect(disconnect) => return Err(self.on_disconnect(disconnect, "PUBLISH")),
+                other => self.on_unsolicited(other)?,
+            }
+        }
+
+        let elapsed = start.elapsed();
+        self.state.completed();
+        Ok(elapsed)
+    }
+
+    /// Publishes a message without printing anything, waiting for its flow to complete with QoS 1 or 2. The packet
+    /// identifier is allocated here.
+    pub fn forward(&mut self, mut packet: Publish) -> CmdResult {
+        match packet.qos_level {
+            QoS::AtMostOnce => {
+                self.state.prepare_publish(&mut packet)?;
+                self.send(packet)
+            },
+            _ => self.round_trip(packet).map(|_| ()),
         }
     }
 
-    pub fn subscribe(&mut self, packet: Subscribe) -> CmdResult {
+    /// Subscribes and returns the outcome for each topic filter, in the order of the `SUBSCRIBE`.
+    pub fn subscribe(&mut self, mut packet: Subscribe) -> Result<Vec<SubscribeOutcome>, MqttError> {
+        self.state.prepare_subscribe(&mut packet)?;
         println!("SUBSCRIBE: {:?}", packet);
         self.send(packet)?;
 
-        let response = self.receive()?;
-        match PacketType::try_from(response[0])? {
-            PacketType::SUBACK => {
-                let suback = mqtt::packet::Suback::try_from(&response[..])?;
-                println!("SUBACK: {:?}", suback);
-                //self.listen();
+        loop {
+            match self.receive()? {
+                Packet::Suback(suback) => {
+                    println!("SUBACK: {:?}", suback);
+                    self.state.on_suback(&suback);
+                    self.save_state()?;
+                    let outcomes = SubscribeOutcome::from_suback(&suback);
+                    for (index, outcome) in outcomes.iter().enumerate() {
+                        println!("Subscription {}: {}", index + 1, outcome);
+                    }
+                    return Ok(outcomes)
+                },
+                Packet::Disconnect(disconnect) => return Err(self.on_disconnect(disconnect, "SUBSCRIBE")),
+                other => self.on_unsolicited(other)?,
+            }
+        }
+    }
+
+    /// Prints incoming messages until `stop` is set, acknowledging them as their QoS requires: `PUBACK` for QoS 1,
+    /// `PUBREC` and later `PUBCOMP` for QoS 2. Sends `PINGREQ` as the keep alive requires, if there is one.
+    ///
+    /// If the connection is lost and the session allows [reconnecting](Session::backoff), connects again and
+    /// subscribes to everything the server forgot along with the session.
+    pub fn listen(&mut self, stop: &AtomicBool) -> CmdResult {
+        loop {
+            match self.next_publish(stop) {
+                Ok(Some(publish)) => self.on_publish(publish)?,
+                Ok(None) => return Ok(()),
+                Err(e) if !self.connected && self.session.backoff().is_some() => {
+                    println!("{}, reconnecting", e);
+                    self.reconnect()?;
+                },
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Connects to the server again after losing the connection. Without a session present, the server doesn't know
+    /// the subscriptions anymore, so they are made again. A client identifier the server assigned is reused.
+    fn reconnect(&mut self) -> CmdResult {
+        let mut connect = self.connect.clone().unwrap_or_default();
+        self.state.prepare_connect(&mut connect);
+        self.stream = open_retrying(self.session.addr(), &self.session)?;
+        self.inbound.clear();
+        let connack = self.handshake(connect)?;
+        if connack.reason_code.is_err() {
+            return Err(MqttError::Message(format!("Reconnecting refused with reason code {:?}", connack.reason_code)))
+        }
+        if connack.session_present {
+            return Ok(())
+        }
+        self.resubscribe()
+    }
+
+    /// Makes all subscriptions of the session again.
+    fn resubscribe(&mut self) -> CmdResult {
+        let packet_id = &mut self.packet_id;
+        for subscribe in self.state.resubscribe_packets(|| next_packet_id(packet_id))? {
+            self.subscribe(subscribe)?;
+        }
+        Ok(())
+    }
+
+    /// Whether the session has a subscription to exactly this topic filter, such as one resumed from an earlier run.
+    pub fn is_subscribed(&self, filter: &str) -> bool {
+        self.state.subscriptions().iter().any(|s| s.topic_filter.filter == filter)
+    }
+
+    /// Waits for the next incoming message, `None` once `stop` is set. Anything else arriving in the meantime is
+    /// handled as with [listen](Self::listen). The message still needs to be [acknowledged](Self::acknowledge).
+    pub fn next_publish(&mut self, stop: &AtomicBool) -> Result<Option<Publish>, MqttError> {
+        let mut next = None;
+        while next.is_none() && !stop.load(Ordering::Relaxed) {
+            let now = self.state.clock().now();
+            for action in self.state.advance(now) {
+                if let DueAction::SendPingreq = action {
+                    self.session.debug("PINGREQ".to_string());
+                    self.send(Pingreq {})?;
+                }
+            }
+
+            let timeout = self.state.time_until_due(now).map_or(POLL_INTERVAL, |t| t.min(POLL_INTERVAL));
+            self.set_read_timeout(Some(timeout))?;
+            match self.poll()? {
+                Some(Packet::Publish(publish)) => next = Some(publish),
+                Some(Packet::Disconnect(disconnect)) => return Err(self.on_disconnect(disconnect, "SUBSCRIBE")),
+                Some(packet) => self.on_unsolicited(packet)?,
+                None => {},
+            }
+        }
+        self.set_read_timeout(None)?;
+        Ok(next)
+    }
+
+    /// Deals with a packet that arrived while waiting for something else: incoming messages and `PINGRESP` are handled
+    /// as usual, a protocol error such as a second `CONNACK` closes the connection and anything else, like an
+    /// acknowledgement for a flow that's long gone, is ignored.
+    fn on_unsolicited(&mut self, packet: Packet) -> CmdResult {
+        match packet {
+            Packet::Publish(publish) => self.on_publish(publish),
+            Packet::Pingresp(_) => {
+                self.session.debug("PINGRESP".to_string());
                 Ok(())
             },
-            PacketType::DISCONNECT => {
-                let disconnect = Disconnect::try_from(&response[..])?;
-                println!("DISCONNECT: {:?}", disconnect);
-                self.connected = false;
-                Err(MqttError::Message(format!("Server disconnected after SUBSCRIBE with reason code {:?}", disconnect.reason_code)))
+            Packet::Pubrel(pubrel) => {
+                println!("PUBREL: {:?}", pubrel);
+                self.trace_flow("PUBREL", Direction::Incoming, pubrel.packet_identifier);
+                let pubcomp = self.state.on_pubrel(&pubrel)?;
+                println!("PUBCOMP: {:?}", pubcomp);
+                self.send(pubcomp)?;
+                self.save_state()
             },
-            _=> {
-                Err(MqttError::ProtocolError(format!("Unexpected response message: {:?}", response)))
+            other => match self.state.check_unsolicited(&other) {
+                Ok(()) => {
+                    println!("Ignoring unexpected {}: {:?}", other.packet_type(), other);
+                    Ok(())
+                },
+                Err(disconnect) => {
+                    println!("DISCONNECT: {:?}", disconnect);
+                    self.send(disconnect)?;
+                    self.connected = false;
+                    Err(MqttError::ProtocolError(format!("Unexpected {} from the server", other.packet_type())))
+                },
             },
         }
     }
 
-    /// clones the `TcpStream` of this client and spawns a new thread to listen to incoming messages.
-    pub fn listen(&mut self) {
-        // FIXME don't just unwrap!
-        let mut stream = self.stream.try_clone().unwrap();
-        std::thread::spawn(move || {
-            loop {
-                if let Ok(rec) = receive_raw(&mut stream) {
-                    if rec.len() > 0 {
-                        match PacketType::try_from(rec[0]).unwrap() {
-                            PacketType::PUBLISH => {
-                                let publ = Publish::try_from(&rec[..]).unwrap();
-                                println!("Received PUBLISH: {:?}", publ)
-                            },
-                            els => println!("Received unexepcted packet {:?}: {:?}", els, rec),
-                        }
-                    }
+    /// Prints a message unless it is a redelivery of a QoS 2 one printed already, then acknowledges it.
+    fn on_publish(&mut self, publish: Publish) -> CmdResult {
+        match self.is_redelivery(&publish) {
+            true => println!("Received PUBLISH {} again, ignoring it", publish.packet_identifier.unwrap_or_default()),
+            false => println!("Received PUBLISH: {:?}", publish),
+        }
+        self.acknowledge(&publish)
+    }
+
+    /// Whether this is a QoS 2 message received before, still waiting for its `PUBREL`.
+    pub fn is_redelivery(&self, publish: &Publish) -> bool {
+        publish.qos_level == QoS::ExactlyOnce
+            && publish.packet_identifier.is_some_and(|id| self.state.is_awaiting_pubrel(id))
+    }
+
+    /// Sends the `PUBACK` or `PUBREC` an incoming message requires, if any.
+    pub fn acknowledge(&mut self, publish: &Publish) -> CmdResult {
+        match self.state.on_incoming_publish(publish) {
+            Some(ack) => {
+                println!("{}: {:?}", ack.packet_type(), ack);
+                let awaiting_pubrel = ack.packet_type() == PacketType::PUBREC;
+                if let Packet::Pubrec(pubrec) = &ack {
+                    self.trace_flow("PUBREC", Direction::Incoming, pubrec.packet_identifier);
                 }
-            }
-        });
+                self.send(ack)?;
+                if awaiting_pubrel {
+                    self.save_state()?;
+                }
+                Ok(())
+            },
+            None => Ok(()),
+        }
+    }
+
+    /// Saves the session for the next run, if it is to be [resumed](Session::store).
+    fn save_state(&self) -> CmdResult {
+        match self.session.store() {
+            Some(store) => store.save(&self.state.snapshot()),
+            None => Ok(()),
+        }
+    }
+
+    /// Tells which flow a packet belongs to in the debug output, as packet identifiers are used again.
+    fn trace_flow(&self, packet: &str, direction: Direction, packet_identifier: u16) {
+        if let Some(flow_id) = self.state.flow_id(direction, packet_identifier) {
+            self.session.debug(format!("{} {} belongs to flow {}", packet, packet_identifier, flow_id));
+        }
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> CmdResult {
+        self.stream.set_read_timeout(timeout)
+            .map_err(|e| MqttError::Message(format!("Error setting read timeout: {:?}", e)))
     }
 
     pub fn disconnect(&mut self) -> CmdResult {
         if !self.connected {
             return Ok(())
         }
+        self.save_state()?;
+
+        if self.session.die() {
+            println!("Dropping the connection without DISCONNECT, the server should publish the will");
+            self.connected = false;
+            return match self.stream.shutdown() {
+                Ok(_) => Ok(()),
+                Err(e) => Err(MqttError::Message(format!("Error closing stream: {:?}", e))),
+            }
+        }
 
         let disconnect = Disconnect::default();
         println!("DISCONNECT: {:?}", disconnect);
         self.send(disconnect)?;
-        match self.stream.shutdown(std::net::Shutdown::Both) {
+
+        if self.session.stats() {
+            println!();
+            println!("{}", self.state.stats());
+        }
+
+        match self.stream.shutdown() {
             Ok(_) => Ok(()),
             Err(e) => Err(MqttError::Message(format!("Error closing stream: {:?}", e))),
         }
         
     }
 
-    fn handle_pub_qos(&mut self, qos: QoS) -> CmdResult {
-        let response = self.receive()?;
-        match PacketType::try_from(response[0])? {
-            PacketType::DISCONNECT => {
-                let disconnect = Disconnect::try_from(&response[..])?;
-                println!("DISCONNECT: {:?}", disconnect);
-                self.connected = false;
-                Err(MqttError::Message(format!("Server disconnected after PUBLISH with reason code {:?}", disconnect.reason_code)))
-            },
-            PacketType::PUBACK => {
-                println!("PUBACK {:?}", Puback::try_from(&response[..]));
-                Ok(())
-            },
-            PacketType::PUBREC => {
-                let pubrec = Pubrec::try_from(&response[..])?;
-                println!("PUBREC: {:?}", pubrec);
-                let reason_code = match Some(pubrec.packet_identifier) == self.packet_id {
-                    true => ReasonCode::Success,
-                    false => ReasonCode::PacketIdentifierNotFound,
-                };
-                let pubrel = Pubrel::new(pubrec.packet_identifier, reason_code)?;
-                self.send(pubrel)?;
-                self.handle_pub_qos(qos)
-            },
-            PacketType::PUBREL => {
-                let pubrel = Pubrel::try_from(&response[..])?;
-                println!("PUBREL: {:?}", pubrel);
-                let reason_code = match Some(pubrel.packet_identifier) == self.packet_id {
-                    true => ReasonCode::Success,
-                    false => ReasonCode::PacketIdentifierNotFound,
-                };
-                let pubcomp = Pubcomp::new(pubrel.packet_identifier, reason_code)?;
-                self.send(pubcomp)
-            },
-            PacketType::PUBCOMP => {
-                let pubcomp = Pubcomp::try_from(&response[..])?;
-                println!("PUBCOMP: {:?}", pubcomp);
-                Ok(())
+    /// Waits for the acknowledgements of the message just sent and tells what became of it.
+    fn handle_pub_qos(&mut self) -> Result<PublishOutcome, MqttError> {
+        let mut received_pubrec = None;
+        loop {
+            match self.receive()? {
+                Packet::Disconnect(disconnect) => return Err(self.on_disconnect(disconnect, "PUBLISH")),
+                Packet::Puback(puback) => {
+                    println!("PUBACK {:?}", puback);
+                    self.trace_flow("PUBACK", Direction::Outgoing, puback.packet_identifier);
+                    self.state.on_puback(&puback)?;
+                    return Ok(PublishOutcome::from(&puback))
+                },
+                Packet::Pubrec(pubrec) => {
+                    println!("PUBREC: {:?}", pubrec);
+                    self.trace_flow("PUBREC", Direction::Outgoing, pubrec.packet_identifier);
+                    match self.state.on_pubrec(&pubrec)? {
+                        Some(pubrel) => {
+                            self.send(pubrel)?;
+                            received_pubrec = Some(pubrec);
+                        },
+                        None => return Ok(PublishOutcome::from(&pubrec)),
+                    }
+                },
+                Packet::Pingresp(_) => self.session.debug("PINGRESP".to_string()),
+                Packet::Pubcomp(pubcomp) => {
+                    println!("PUBCOMP: {:?}", pubcomp);
+                    self.trace_flow("PUBCOMP", Direction::Outgoing, pubcomp.packet_identifier);
+                    self.state.on_pubcomp(&pubcomp)?;
+                    return Ok(match &received_pubrec {
+                        Some(pubrec) => PublishOutcome::of_exactly_once(pubrec, &pubcomp),
+                        None => PublishOutcome::from(&pubcomp),
+                    })
+                },
+                other => self.on_unsolicited(other)?,
             }
-            _=> {
-                println!("RESPONSE_NOT_YET_IMPLEMENTED: {:?}", response);
-                Err(MqttError::ProtocolError(format!("Unexpected response message: {:?}", response)))
-            },
         }
     }
 
-    fn send<P: Into<Vec<u8>>>(&mut self, packet: P) -> CmdResult {
-        let binary = packet.into();
+    /// The next packet identifier for an outgoing flow, see [next_packet_id].
+    fn next_packet_id(&mut self) -> u16 {
+        next_packet_id(&mut self.packet_id)
+    }
+
+    /// The server ended the connection after `sent`, possibly pointing to another server to use. Returns the error to
+    /// end the command with.
+    fn on_disconnect(&mut self, disconnect: Disconnect, sent: &str) -> MqttError {
+        println!("DISCONNECT: {:?}", disconnect);
+        self.connected = false;
+        match Redirect::from_disconnect(&disconnect) {
+            Ok(Some(redirect)) => println!("Server redirects to: {}", targets(&redirect)),
+            Ok(None) => {},
+            Err(e) => return e,
+        }
+        MqttError::Message(format!("Server disconnected after {} with reason code {:?}", sent, disconnect.reason_code))
+    }
+
+    fn send<P: Into<Packet>>(&mut self, packet: P) -> CmdResult {
+        let packet = packet.into();
+        let packet_type = packet.packet_type();
+        let binary = self.state.encode(packet)?;
+        self.state.on_packet_sent(packet_type, binary.len());
     
         self.session.debug(format!("Sending {} bytes to server", binary.len()));
         self.session.debug(format!("{:?}", binary));
@@ -179,38 +534,151 @@ impl Client {
         Ok(())
     }
     
-    fn receive(&mut self) -> Result<Vec<u8>, MqttError> {
-        let mut buff: [u8; 4048] = [0; 4048];
+    /// Returns the next packet from the server, reading from the stream until one is complete. Anything read past 
+    /// that packet is kept for the next call. Fails if a read timeout is set and passes first.
+    fn receive(&mut self) -> Result<Packet, MqttError> {
+        self.poll()?.ok_or_else(|| MqttError::Message("Timed out waiting for the server".to_string()))
+    }
+
+    /// The first byte not consumed yet, reading from the stream if there is none. `None` if the read timeout passes.
+    fn peek(&mut self) -> Result<Option<u8>, MqttError> {
+        while self.inbound.is_empty() {
+            if !self.read_more()? {
+                return Ok(None)
+            }
+        }
+        Ok(self.inbound.first().copied())
+    }
+
+    /// Like [receive](Self::receive), but returns `None` if the read timeout passes before a packet is complete.
+    fn poll(&mut self) -> Result<Option<Packet>, MqttError> {
+        loop {
+            let mut packets = PacketIter::new(&self.inbound)
+                .maximum_packet_size(self.state.incoming_maximum_packet_size());
+            let next = packets.next();
+            let consumed = self.inbound.len() - packets.remaining();
+
+            match next {
+                Some(Ok((packet, len))) => {
+                    self.state.on_packet_received(packet.packet_type(), len);
+                    for code in packet.reason_codes() {
+                        *self.reason_codes.entry(*code).or_default() += 1;
+                    }
+                    self.inbound.drain(..consumed);
+                    return Ok(Some(packet))
+                },
+                Some(Err(e)) => {
+                    // a broken fixed header leaves nothing to resync on
+                    match consumed {
+                        0 => self.inbound.clear(),
+                        _ => { self.inbound.drain(..consumed); },
+                    }
+
+                    // nothing sensible to do with a connection that sends garbage
+                    let disconnect = e.to_disconnect();
+                    println!("DISCONNECT: {:?}", disconnect);
+                    self.send(disconnect)?;
+                    self.connected = false;
+                    return Err(e)
+                },
+                None => {},
+            }
+
+            if !self.read_more()? {
+                return Ok(None)
+            }
+        }
+    }
+
+    /// Reads what the server sent next into the inbound buffer. Returns `false` if the read timeout passes first.
+    fn read_more(&mut self) -> Result<bool, MqttError> {
+        let mut buff: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
         match self.stream.read(&mut buff) {
+            Ok(0) => {
+                self.connected = false;
+                Err(MqttError::Message("Connection closed by server".to_string()))
+            },
             Ok(num_bytes) => {
                 self.session.debug(format!("Read {} bytes from server", num_bytes));
-                let mut result: Vec<u8> = Vec::with_capacity(num_bytes);
-                result.extend_from_slice(&buff[..num_bytes]);
-                self.session.debug(format!("{:?}", result));
-                return Ok(result)
+                self.session.debug(format!("{:?}", &buff[..num_bytes]));
+                self.inbound.extend_from_slice(&buff[..num_bytes]);
+                Ok(true)
+            },
+            Err(e) if matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock) => Ok(false),
+            Err(e) => {
+                self.connected = false;
+                Err(MqttError::Message(format!("Error reading from stream: {:?}", e)))
             },
-            Err(e) => return Err(MqttError::Message(format!("Error reading from stream: {:?}", e))),
         }
     }
 }
 
-/// need this function so there's no pointers to or ownership issues with the `Client` itself.
-fn receive_raw<R: Read>(stream: &mut R) -> Result<Vec<u8>, MqttError> 
-{
-    const BUFFER_SIZE: usize = 4096;
-    let mut buff: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
-    match stream.read(&mut buff) {
-        Ok(num_bytes) => {
-            let mut result: Vec<u8> = Vec::with_capacity(num_bytes);
-            result.extend_from_slice(&buff[..num_bytes]);
-
-            if num_bytes == BUFFER_SIZE {
-                result.extend_from_slice(&receive_raw(stream)?);
-            }
+/// Counts `current` up to the next packet identifier for an outgoing flow, wrapping around and skipping 0.
+fn next_packet_id(current: &mut Option<u16>) -> u16 {
+    let next = match *current {
+        Some(u16::MAX) | None => 1,
+        Some(id) => id + 1,
+    };
+    *current = Some(next);
+    next
+}
+
+/// Prints the summary on the way out, which may well be because of an error.
+impl Drop for Client {
+    fn drop(&mut self) {
+        if !self.session.summary() {
+            return
+        }
+        println!();
+        println!("Reason codes received:");
+        for (code, count) in &self.reason_codes {
+            println!("{:>6}x {} (0x{:02X})", count, code, u8::from(*code));
+        }
+    }
+}
 
-            return Ok(result)
-        },
-        Err(e) => return Err(MqttError::Message(format!("Error reading from stream: {:?}", e))),
+/// Opens the connection like [open], trying again with the session's [backoff](Session::backoff) as long as the
+/// server can't be reached.
+fn open_retrying(addr: (String, u16), session: &Session) -> Result<Box<dyn Transport>, MqttError> {
+    let mut backoff = session.backoff();
+    loop {
+        match open(addr.clone(), session.connect_timeout()) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => match backoff.as_mut().and_then(Backoff::next_delay) {
+                Some(delay) => {
+                    println!("{}, trying again in {:?}", e, delay);
+                    std::thread::sleep(delay);
+                },
+                None => return Err(e),
+            },
+        }
     }
 }
 
+fn open(addr: (String, u16), timeout: Duration) -> Result<Box<dyn Transport>, MqttError> {
+    println!("Connecting to {:?}", addr);
+    match TcpTransport::connect_timeout(&addr, timeout) {
+        Ok(stream) => Ok(Box::new(stream)),
+        Err(e) => Err(MqttError::Message(format!("Error establishing connection to server: {:?}", e))),
+    }
+}
+
+/// The keep alive the connection uses, and where it comes from.
+fn keep_alive(negotiated: &NegotiatedSession) -> String {
+    if negotiated.is_keep_alive_disabled_by_server() {
+        return "Keep alive: disabled by the server".to_string()
+    }
+    match (negotiated.keep_alive(), negotiated.is_keep_alive_overridden()) {
+        (0, _) => "Keep alive: disabled".to_string(),
+        (secs, true) => format!("Keep alive: {}s as set by the server, {}s requested", secs, negotiated.requested_keep_alive),
+        (secs, false) => format!("Keep alive: {}s", secs),
+    }
+}
+
+/// The servers a redirect points to, for printing.
+fn targets(redirect: &Redirect) -> String {
+    match redirect.references.is_empty() {
+        true => "(none given)".to_string(),
+        false => redirect.references.iter().map(|r| r.to_string()).collect::<Vec<_>>().join(" "),
+    }
+}
@@ -0,0 +1,55 @@
+use std::path::Path;
+
+use clap::Parser;
+use mqtt::{error::MqttError, packet::PacketDump};
+
+use crate::CmdResult;
+
+#[derive(Debug, Parser)]
+pub struct DecodeCmd {
+    /// Packets to decode: the path of a file with the raw bytes, such as a capture, or the bytes in hex. Whitespace,
+    /// `:` and a leading `0x` are ignored in hex, so `30 07 00 03 61 2f 62 68 69` or `0x300700...` both work
+    input: String,
+}
+
+impl DecodeCmd {
+
+    /// Prints each packet in the input, doesn't need a server. Fails if any of them didn't decode.
+    pub fn execute(&self) -> CmdResult {
+        let bytes = match Path::new(&self.input).is_file() {
+            true => std::fs::read(&self.input)
+                .map_err(|e| MqttError::Message(format!("Error reading {}: {}", self.input, e)))?,
+            false => from_hex(&self.input)?,
+        };
+
+        let mut offset = 0;
+        let (mut total, mut failed) = (0, 0);
+        while offset < bytes.len() {
+            let dump = PacketDump::new(&bytes[offset..])
+                .map_err(|e| MqttError::Message(format!("Not a packet at offset {}: {}", offset, e)))?;
+            println!("@{} {}", offset, dump);
+            total += 1;
+            failed += usize::from(dump.decoded.is_err());
+            offset += dump.len();
+        }
+
+        match failed {
+            0 => Ok(()),
+            _ => Err(MqttError::Message(format!("{} of {} packets failed to decode", failed, total))),
+        }
+    }
+}
+
+fn from_hex(input: &str) -> Result<Vec<u8>, MqttError> {
+    let digits: String = input.trim().trim_start_matches("0x")
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ':')
+        .collect();
+    if digits.is_empty() || digits.len() % 2 != 0 || !digits.is_ascii() {
+        return Err(MqttError::Message(format!("Neither a file nor an even number of hex digits: {}", input)))
+    }
+    (0..digits.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16)
+            .map_err(|_| MqttError::Message(format!("Invalid hex digits: {}", &digits[i..i + 2]))))
+        .collect()
+}
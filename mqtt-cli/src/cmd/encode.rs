@@ -0,0 +1,109 @@
+use std::io::Write;
+
+use clap::{Parser, ValueEnum};
+use mqtt::{
+    error::MqttError,
+    packet::{
+        Auth, Connack, Connect, Disconnect, Packet, Pingreq, Pingresp, Puback, Pubcomp, Publish, Pubrec, Pubrel,
+        Suback, Subscribe, Unsuback, Unsubscribe,
+    },
+};
+use serde::de::DeserializeOwned;
+
+use crate::CmdResult;
+
+#[derive(Debug, Parser)]
+pub struct EncodeCmd {
+    /// Type of the packet
+    #[arg(short = 'T', long = "type", value_enum)]
+    packet_type: PacketKind,
+
+    /// The fields of the packet as JSON, such as `{"topic_name": "a/b", "qos_level": 1, "packet_identifier": 7}`.
+    /// `@path` reads it from a file. Fields left out take their defaults, without any at all only packets that have
+    /// no required fields can be encoded, such as `pingreq` or `disconnect`
+    #[arg(long, conflicts_with = "toml")]
+    json: Option<String>,
+
+    /// The fields of the packet as TOML, otherwise the same as `--json`
+    #[arg(long)]
+    toml: Option<String>,
+
+    /// Writes the encoded bytes as they are instead of in hex, to pipe them into a file or another tool
+    #[arg(short, long)]
+    binary: bool,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum PacketKind {
+    Connect,
+    Connack,
+    Publish,
+    Puback,
+    Pubrec,
+    Pubrel,
+    Pubcomp,
+    Subscribe,
+    Suback,
+    Unsubscribe,
+    Unsuback,
+    Pingreq,
+    Pingresp,
+    Disconnect,
+    Auth,
+}
+
+impl EncodeCmd {
+
+    /// Prints the packet described, doesn't need a server.
+    pub fn execute(&self) -> CmdResult {
+        let encoded: Vec<u8> = self.packet()?.try_into()?;
+        match self.binary {
+            true => std::io::stdout().write_all(&encoded)
+                .map_err(|e| MqttError::Message(format!("Error writing to stdout: {}", e))),
+            false => {
+                println!("{}", encoded.iter().map(|b| format!("{:02x}", b)).collect::<Vec<String>>().join(" "));
+                Ok(())
+            },
+        }
+    }
+
+    fn packet(&self) -> Result<Packet, MqttError> {
+        Ok(match self.packet_type {
+            PacketKind::Connect => Packet::from(self.parse::<Connect>()?),
+            PacketKind::Connack => Packet::from(self.parse::<Connack>()?),
+            PacketKind::Publish => Packet::from(self.parse::<Publish>()?),
+            PacketKind::Puback => Packet::from(self.parse::<Puback>()?),
+            PacketKind::Pubrec => Packet::from(self.parse::<Pubrec>()?),
+            PacketKind::Pubrel => Packet::from(self.parse::<Pubrel>()?),
+            PacketKind::Pubcomp => Packet::from(self.parse::<Pubcomp>()?),
+            PacketKind::Subscribe => Packet::from(self.parse::<Subscribe>()?),
+            PacketKind::Suback => Packet::from(self.parse::<Suback>()?),
+            PacketKind::Unsubscribe => Packet::from(self.parse::<Unsubscribe>()?),
+            PacketKind::Unsuback => Packet::from(self.parse::<Unsuback>()?),
+            PacketKind::Pingreq => Packet::from(self.parse::<Pingreq>()?),
+            PacketKind::Pingresp => Packet::from(self.parse::<Pingresp>()?),
+            PacketKind::Disconnect => Packet::from(self.parse::<Disconnect>()?),
+            PacketKind::Auth => Packet::from(self.parse::<Auth>()?),
+        })
+    }
+
+    fn parse<T: DeserializeOwned>(&self) -> Result<T, MqttError> {
+        let invalid = |e: &dyn std::fmt::Display| {
+            MqttError::Message(format!("Invalid {:?} description: {}", self.packet_type, e))
+        };
+        match (&self.json, &self.toml) {
+            (_, Some(toml)) => toml::from_str(&read(toml)?).map_err(|e| invalid(&e)),
+            (Some(json), None) => serde_json::from_str(&read(json)?).map_err(|e| invalid(&e)),
+            (None, None) => serde_json::from_str("{}").map_err(|e| invalid(&e)),
+        }
+    }
+}
+
+/// The argument itself, or the contents of the file it names with a leading `@`.
+fn read(arg: &str) -> Result<String, MqttError> {
+    match arg.strip_prefix('@') {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(|e| MqttError::Message(format!("Error reading {}: {}", path, e))),
+        None => Ok(arg.to_string()),
+    }
+}
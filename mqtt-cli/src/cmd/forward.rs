@@ -0,0 +1,119 @@
+use std::str::FromStr;
+
+use clap::Parser;
+use mqtt::{
+    addr::BrokerAddr,
+    error::MqttError,
+    packet::{Subscribe, SubscribeProperties, TopicFilter},
+    router::{BridgeTag, TopicRewrite},
+    session::SubscribeOutcome,
+    types::{QoS, ValidationMode},
+};
+
+use crate::{client::Client, Session, CmdResult};
+
+#[derive(Debug, Parser)]
+pub struct ForwardCmd {
+    /// Server and topic filter to subscribe to, such as `mqtt://localhost/sensors/#`
+    #[arg(long, value_name = "URL")]
+    from: Endpoint,
+
+    /// Server and topic to publish to, such as `mqtt://localhost:1884/site-1/sensors/#`. A trailing `#` stands for
+    /// the topic levels matched by the `#` of `--from`, without it all messages go to the same topic.
+    #[arg(long, value_name = "URL")]
+    to: Endpoint,
+
+    /// Quality of Service level to publish with, instead of the one received
+    #[arg(short, long)]
+    qos: Option<u8>,
+
+    /// Retain flag to publish with, instead of the one received
+    #[arg(long)]
+    retain: Option<bool>,
+
+    /// Tags forwarded messages with this name and skips the ones tagged with it already, so that forwarding in both
+    /// directions doesn't send messages around in circles
+    #[arg(long, value_name = "NAME")]
+    tag: Option<String>,
+}
+
+/// A server and a topic, given as a URL with the topic as its path: `mqtt://localhost:1884/some/topic`.
+#[derive(Debug, Clone)]
+struct Endpoint {
+    addr: BrokerAddr,
+    topic: String,
+}
+
+impl FromStr for Endpoint {
+    type Err = MqttError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let authority = s.find("://").map_or(0, |index| index + 3);
+        match s[authority..].split_once('/') {
+            Some((server, topic)) => Ok(Self {
+                addr: s[..authority + server.len()].parse()?,
+                topic: topic.to_string(),
+            }),
+            None => Err(MqttError::Message(format!("No topic in {}", s))),
+        }
+    }
+}
+
+impl ForwardCmd {
+
+    pub fn execute(&self, session: Session) -> CmdResult {
+        let rewrite = TopicRewrite::new(&self.from.topic, &self.to.topic)?;
+        let qos = self.qos.map(QoS::try_from).transpose()?;
+        let tag = self.tag.as_deref().map(BridgeTag::new);
+
+        // at most what's going to be forwarded, keeping the retain flag for messages that are published retained
+        let mut filter = TopicFilter::validated(rewrite.filter().to_string(), ValidationMode::Strict)?;
+        filter.maximum_qos = qos.unwrap_or(QoS::ExactlyOnce);
+        filter.retain_as_published = true;
+        let properties = match session.user_properties().is_empty() {
+            true => None,
+            false => Some(SubscribeProperties { user_property: session.user_properties().clone(), ..Default::default() }),
+        };
+        let subscribe = Subscribe {
+            packet_identifier: session.packet_identifier(),
+            properties,
+            topic_filter: vec![filter],
+        };
+
+        // the last will is meant for the connection the messages come from
+        let target = session.clone().with_addr(super::socket_addr(&self.to.addr)?).with_will(None, false);
+        let mut source = Client::connect(session.with_addr(super::socket_addr(&self.from.addr)?))?;
+        let mut target = Client::connect(target)?;
+        if source.subscribe(subscribe)?.iter().all(SubscribeOutcome::is_err) {
+            source.disconnect()?;
+            return Err(MqttError::Message("Server refused the subscription".to_string()))
+        }
+
+        println!();
+        println!("##################################################");
+        println!("now forwarding messages, press 'ENTER' to quit");
+        println!("##################################################");
+        println!();
+
+        let stop = super::stop_on_enter();
+        while let Some(publish) = source.next_publish(&stop)? {
+            let mut forwarded = publish.clone();
+            let forward = !source.is_redelivery(&publish) && rewrite.rewrite(&mut forwarded)
+                && tag.as_ref().is_none_or(|tag| tag.forward(&mut forwarded));
+
+            // acknowledged only once the target has it, so that nothing gets lost in between
+            if forward {
+                forwarded.qos_level = qos.unwrap_or(forwarded.qos_level);
+                forwarded.retain = self.retain.unwrap_or(forwarded.retain);
+                println!("Forwarding {} to {}", publish.topic_name, forwarded.topic_name);
+                target.forward(forwarded)?;
+            } else {
+                println!("Not forwarding {}", publish.topic_name);
+            }
+            source.acknowledge(&publish)?;
+        }
+
+        source.disconnect()?;
+        target.disconnect()
+    }
+}
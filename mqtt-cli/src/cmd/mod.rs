@@ -1,9 +1,17 @@
+pub mod decode;
+pub mod encode;
+pub mod forward;
+pub mod probe;
 pub mod publish;
 pub mod subscribe;
+pub mod will;
+
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
 
 use clap::{Parser, Subcommand};
+use mqtt::{addr::BrokerAddr, error::MqttError};
 
-use self::{subscribe::SubscribeCmd, publish::PublishCmd};
+use self::{decode::DecodeCmd, encode::EncodeCmd, forward::ForwardCmd, probe::ProbeCmd, subscribe::SubscribeCmd, publish::PublishCmd, will::WillArgs};
 
 #[derive(Debug, Parser)]
 #[command(name = "mqtt-cli", about = "MQTT command line client", disable_help_flag = true)]
@@ -17,13 +25,41 @@ pub struct MqttCli {
     #[arg(global = true, short, long)]
     pub verbose: bool,
 
-    /// optional server host name, defaults to `localhost`
+    /// prints connection statistics when disconnecting
     #[arg(global = true, short, long)]
-    pub host: Option<String>,
+    pub stats: bool,
 
-    /// optional port number, defaults to `1883` (TODO: `8883` when using TLS)
-    #[arg(global = true, short, long)]
-    pub port: Option<u16>,
+    /// server to connect to, such as `mqtt://test.mosquitto.org:1884`. The port defaults to `1883`, only `mqtt://` is
+    /// supported for now
+    #[arg(global = true, short, long, default_value = "mqtt://localhost")]
+    pub url: BrokerAddr,
+
+    /// follows up to this many redirects to other servers when connecting
+    #[arg(global = true, long)]
+    pub max_redirects: Option<u8>,
+
+    /// seconds to wait for the server to accept the connection and answer `CONNECT`, defaults to `10`
+    #[arg(global = true, long, value_name = "SECS")]
+    pub connect_timeout: Option<u64>,
+
+    /// seconds after which to send `PINGREQ` while waiting for messages with nothing else to send. `0`, the
+    /// default, disables keep alive
+    #[arg(global = true, long, value_name = "SECS")]
+    pub keep_alive: Option<u16>,
+
+    /// tries connecting up to this many more times if the server can't be reached, waiting longer between attempts
+    /// each time. Also connects again if the connection is lost while waiting for messages, subscribing again if the
+    /// server didn't keep the session
+    #[arg(global = true, long, value_name = "ATTEMPTS")]
+    pub reconnect: Option<u32>,
+
+    /// user property to send with CONNECT and the PUBLISH or SUBSCRIBE of the command, as `key=value`. May be
+    /// repeated, the properties are sent in the given order.
+    #[arg(global = true, long = "user-property", value_name = "KEY=VALUE", value_parser = parse_user_property)]
+    pub user_properties: Vec<(String, String)>,
+
+    #[command(flatten)]
+    pub will: WillArgs,
 }
 
 #[derive(Debug, Subcommand)]
@@ -33,4 +69,44 @@ pub enum Command {
 
     /// subscribes to a topic
     Sub(SubscribeCmd),
-}
\ No newline at end of file
+
+    /// measures the round-trip latency of QoS 1 and 2 messages
+    Probe(ProbeCmd),
+
+    /// republishes messages from one broker or topic to another
+    Forward(ForwardCmd),
+
+    /// prints the packets in captured bytes, without connecting to a broker
+    Decode(DecodeCmd),
+
+    /// prints the packet described in JSON or TOML, without connecting to a broker
+    Encode(EncodeCmd),
+}
+
+/// Host and port to connect to, as long as the client supports the scheme of `url`, which is only `mqtt://` for now.
+pub fn socket_addr(url: &BrokerAddr) -> Result<(String, u16), MqttError> {
+    if url.is_tls() || url.is_websocket() {
+        return Err(MqttError::Message(format!("{}:// is not supported yet", url.scheme)))
+    }
+    Ok((url.host.clone(), url.port))
+}
+
+/// A flag that is set once the user presses `ENTER`.
+fn stop_on_enter() -> Arc<AtomicBool> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let quit = stop.clone();
+    std::thread::spawn(move || {
+        if let Err(e) = std::io::stdin().read_line(&mut String::new()) {
+            println!("error reading user input: {:?}", e);
+        }
+        quit.store(true, Ordering::Relaxed);
+    });
+    stop
+}
+/// Splits a user property argument at the first `=`, so the value may contain more of them.
+fn parse_user_property(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) => Ok((key.to_string(), value.to_string())),
+        None => Err(format!("expected `key=value`, got `{}`", s)),
+    }
+}
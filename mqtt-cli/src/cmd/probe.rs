@@ -0,0 +1,74 @@
+use std::time::Duration;
+
+use clap::Parser;
+use mqtt::{error::MqttError, packet::Publish, types::{QoS, TopicName}};
+
+use crate::{client::Client, Session, CmdResult};
+
+/// The percentiles to report, besides minimum and maximum.
+const PERCENTILES: [f64; 3] = [50.0, 90.0, 99.0];
+
+#[derive(Debug, Parser)]
+pub struct ProbeCmd {
+    /// Topic to publish the probe messages to
+    #[arg(short, long, default_value = "mqtt-cli/probe")]
+    topic: String,
+
+    /// Quality of Service levels to measure, comma separated. 1 (PUBLISH to PUBACK), 2 (PUBLISH to PUBCOMP)
+    #[arg(short, long, value_delimiter = ',', default_values_t = [1, 2])]
+    qos: Vec<u8>,
+
+    /// Number of messages to send per QoS level
+    #[arg(short, long, default_value_t = 100)]
+    count: usize,
+
+    /// Size of each message payload in bytes
+    #[arg(short = 'z', long, default_value_t = 16)]
+    size: usize,
+}
+
+impl ProbeCmd {
+
+    pub fn execute(&self, session: Session) -> CmdResult {
+        let topic = TopicName::try_from(self.topic.as_str())?;
+        let levels = self.qos.iter()
+            .map(|qos| match QoS::try_from(*qos)? {
+                QoS::AtMostOnce => Err(MqttError::Message("QoS 0 has no acknowledgement to measure".to_string())),
+                level => Ok(level),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if self.count == 0 {
+            return Err(MqttError::Message("The count must be at least 1".to_string()))
+        }
+
+        let mut client = Client::connect(session)?;
+
+        for qos in levels {
+            let mut samples = Vec::with_capacity(self.count);
+            for _ in 0..self.count {
+                let publish = Publish::builder(topic.clone()).payload(vec![0; self.size]).qos(qos).build()?;
+                samples.push(client.round_trip(publish)?);
+            }
+            println!("{}", report(qos, &mut samples));
+        }
+
+        client.disconnect()
+    }
+}
+
+/// One line with minimum, percentiles and maximum of `samples`, which must not be empty.
+fn report(qos: QoS, samples: &mut [Duration]) -> String {
+    samples.sort();
+    let mut line = format!("QoS {} ({} messages): min {:?}", qos as u8, samples.len(), samples[0]);
+    for p in PERCENTILES {
+        line.push_str(&format!(", p{} {:?}", p, percentile(samples, p)));
+    }
+    line.push_str(&format!(", max {:?}", samples[samples.len() - 1]));
+    line
+}
+
+/// Nearest-rank percentile of sorted `samples`.
+fn percentile(samples: &[Duration], p: f64) -> Duration {
+    let rank = (p / 100.0 * samples.len() as f64).ceil() as usize;
+    samples[rank.clamp(1, samples.len()) - 1]
+}
@@ -1,5 +1,5 @@
 use clap::Parser;
-use mqtt::types::QoS;
+use mqtt::{error::MqttError, packet::{Publish, PublishProperties}, session::PublishOutcome, types::QoS};
 
 use crate::{client::Client, Session, CmdResult};
 
@@ -16,28 +16,74 @@ pub struct PublishCmd {
     /// Quality of Service level. 0 (at most once), 1 (at least once), 2 (exactly once)
     #[arg(short, long)]
     qos: Option<u8>,
+
+    /// Number of times to send the message. Bursts are written in a single batch and are only supported for QoS 0.
+    #[arg(short, long, default_value_t = 1)]
+    count: usize,
+
+    /// Asks the server for response information and sets the response topic of the message to this suffix below it
+    #[arg(long)]
+    response: Option<String>,
+
+    /// Prints how often each reason code was received at exit
+    #[arg(long)]
+    summary: bool,
 }
 
 impl PublishCmd {
 
     pub fn execute(&self, session: Session) -> CmdResult {
-        let mut publish = mqtt::packet::Publish::new(
-            self.topic.clone(), 
-            self.message.clone().into_bytes());
-
+        let session = session
+            .with_response_information(self.response.is_some())
+            .with_summary(self.summary);
+        let mut builder = Publish::builder(self.topic.as_str())
+            .payload(self.message.clone().into_bytes())
+            .user_properties(session.user_properties());
         if let Some(qos) = self.qos {
-            publish.qos_level = QoS::try_from(qos)?;
-            if qos == 1 || qos == 2 {
-                publish.packet_identifier = Some(session.packet_identifier())
+            builder = match QoS::try_from(qos)? {
+                QoS::AtMostOnce => builder,
+                qos => builder.qos(qos).packet_identifier(session.packet_identifier()),
+            };
+        }
+        let publish = builder.build()?;
+
+        if self.count > 1 {
+            if publish.qos_level != QoS::AtMostOnce {
+                return Err(MqttError::Message("Sending more than one message is only supported for QoS 0".to_string()))
             }
+            
+            let mut client = Client::connect(session)?;
+            let burst = (0..self.count)
+                .map(|_| self.with_response_topic(&client, publish.clone()))
+                .collect::<Result<_, _>>()?;
+            client.publish_batch(burst)?;
+            return client.disconnect()
         }
-        
+
         let mut client = Client::connect(session)?;
+        let publish = self.with_response_topic(&client, publish)?;
         
-        client.publish( publish)?;
+        let outcome = client.publish(publish)?;
+        if let Some(outcome) = outcome {
+            println!("PUBLISH {}", outcome);
+        }
 
         client.disconnect()?;
 
-        Ok(())
+        match outcome {
+            Some(PublishOutcome::Failed(reason_code)) => {
+                Err(MqttError::Message(format!("PUBLISH rejected with reason code {:?}", reason_code)))
+            },
+            _ => Ok(()),
+        }
     }
-}
\ No newline at end of file
+
+    /// Sets the response topic if one was asked for, which needs the response information of the connected server.
+    fn with_response_topic(&self, client: &Client, mut publish: Publish) -> Result<Publish, MqttError> {
+        if let Some(suffix) = &self.response {
+            let properties = publish.properties.get_or_insert_with(PublishProperties::default);
+            properties.response_topic = Some(client.response_topic(suffix)?);
+        }
+        Ok(publish)
+    }
+}
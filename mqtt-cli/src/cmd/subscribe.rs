@@ -1,6 +1,11 @@
+use std::{path::PathBuf, rc::Rc};
+
 use clap::Parser;
-use mqtt::{types::QoS, error::MqttError};
-use crate::{Session, client::Client, CmdResult};
+use mqtt::{error::MqttError, packet::SubscribeProperties, session::SubscribeOutcome, types::{QoS, ValidationMode}};
+use crate::{Session, client::Client, store::FileStore, CmdResult};
+
+/// How long the server keeps a session to resume unless `--session-expiry` says otherwise, one hour.
+const DEFAULT_SESSION_EXPIRY: u32 = 3600;
 
 #[derive(Debug, Parser)]
 pub struct SubscribeCmd {
@@ -11,36 +16,61 @@ pub struct SubscribeCmd {
     /// Quality of Service level. 1 or 2. 0 is the default, no need to expliclty specify in that case.
     #[arg(short, long)]
     qos: Option<u8>,
+
+    /// Prints how often each reason code was received at exit
+    #[arg(long)]
+    summary: bool,
+
+    /// Keeps the session in this file to resume it with the next run: the client identifier, the subscriptions and
+    /// the QoS 2 messages not released yet. Connects without clean start, so the server keeps the session as well.
+    #[arg(long, value_name = "FILE")]
+    resume: Option<PathBuf>,
+
+    /// Seconds the server keeps the session to resume after disconnecting, defaults to one hour
+    #[arg(long, value_name = "SECS", requires = "resume")]
+    session_expiry: Option<u32>,
 }
 
 impl SubscribeCmd {
 
     pub fn execute(&self, session: Session) -> CmdResult {
-        let mut topic = mqtt::packet::TopicFilter::new(self.topic.clone());
+        let mut topic = mqtt::packet::TopicFilter::validated(self.topic.clone(), ValidationMode::Strict)?;
         if let Some(qos) = self.qos {
             topic.maximum_qos = QoS::try_from(qos)?;
         }
 
+        let properties = match session.user_properties().is_empty() {
+            true => None,
+            false => Some(SubscribeProperties { user_property: session.user_properties().clone(), ..Default::default() }),
+        };
+
         let subscribe = mqtt::packet::Subscribe{
             packet_identifier: session.packet_identifier(),
-            properties: None,
+            properties,
             topic_filter: vec![topic],
         };
 
+        let store = self.resume.clone().map(|path| Rc::new(FileStore::new(path)) as _);
+        let session = session
+            .with_summary(self.summary)
+            .with_store(store, self.session_expiry.unwrap_or(DEFAULT_SESSION_EXPIRY));
         let mut client = Client::connect(session)?;
 
-        client.subscribe(subscribe)?;
-        client.listen();
+        if client.is_subscribed(&self.topic) {
+            println!("Resumed the subscription to {}", self.topic);
+        } else if client.subscribe(subscribe)?.iter().all(SubscribeOutcome::is_err) {
+            client.disconnect()?;
+            return Err(MqttError::Message("Server refused the subscription".to_string()))
+        }
 
         println!();
         println!("##################################################");
         println!("now listening for messages, press 'ENTER' to quit");
         println!("##################################################");
         println!();
-        
-        match std::io::stdin().read_line(&mut String::new()) {
-            Ok(_) => client.disconnect(),
-            Err(e) => Err(MqttError::Message(format!("error reading user input: {:?}", e))),
-        }
+
+        let stop = super::stop_on_enter();
+        client.listen(&stop)?;
+        client.disconnect()
     }
 }
\ No newline at end of file
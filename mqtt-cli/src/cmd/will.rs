@@ -0,0 +1,57 @@
+use clap::Args;
+use std::time::Duration;
+
+use mqtt::{error::MqttError, packet::LastWill, types::QoS};
+
+/// Options for the last will, which apply to all commands.
+#[derive(Debug, Args)]
+pub struct WillArgs {
+    /// Topic of the last will message, the will is only sent if this is set
+    #[arg(global = true, long)]
+    pub will_topic: Option<String>,
+
+    /// Payload of the last will message, empty by default
+    #[arg(global = true, long)]
+    pub will_payload: Option<String>,
+
+    /// Quality of Service level of the last will message, defaults to 1
+    #[arg(global = true, long)]
+    pub will_qos: Option<u8>,
+
+    /// Whether the server should retain the last will message
+    #[arg(global = true, long)]
+    pub will_retain: bool,
+
+    /// Seconds the server waits after losing the connection before publishing the will
+    #[arg(global = true, long)]
+    pub will_delay: Option<u32>,
+
+    /// Drops the connection without sending DISCONNECT at the end, so the server publishes the will
+    #[arg(global = true, long)]
+    pub die: bool,
+}
+
+impl WillArgs {
+
+    /// Builds the last will if a will topic was specified.
+    pub fn last_will(&self) -> Result<Option<LastWill>, MqttError> {
+        let topic = match &self.will_topic {
+            Some(topic) => topic.clone(),
+            None => return Ok(None),
+        };
+
+        let payload = self.will_payload.clone().unwrap_or_default();
+        let mut will = LastWill::new(topic, payload.as_bytes())?;
+        will.retain = self.will_retain;
+
+        if let Some(qos) = self.will_qos {
+            will.qos = QoS::try_from(qos)?;
+        }
+
+        if let Some(delay) = self.will_delay {
+            will.set_will_delay(Duration::from_secs(delay.into()))?;
+        }
+
+        Ok(Some(will))
+    }
+}
@@ -5,6 +5,9 @@
 mod client;
 mod cmd;
 mod session;
+mod store;
+
+use std::time::Duration;
 
 use clap::Parser;
 use cmd::{Command, MqttCli};
@@ -15,14 +18,27 @@ type CmdResult = Result<(), MqttError>;
 
 fn main() -> CmdResult {
     let args = MqttCli::parse();
+    // commands that work offline, without a session
+    match &args.command {
+        Command::Decode(decode) => return decode.execute(),
+        Command::Encode(encode) => return encode.execute(),
+        _ => {},
+    }
 
-    let host = args.host.unwrap_or(String::from("localhost"));
-    let port = args.port.unwrap_or(1883);
-
-    let session = Session::new(args.verbose, (host, port));
+    let session = Session::new(args.verbose, args.stats, cmd::socket_addr(&args.url)?)
+        .with_will(args.will.last_will()?, args.will.die)
+        .with_max_redirects(args.max_redirects)
+        .with_connect_timeout(args.connect_timeout.map(Duration::from_secs))
+        .with_keep_alive(args.keep_alive)
+        .with_reconnect_attempts(args.reconnect)
+        .with_user_properties(args.user_properties.into());
 
     match args.command {
         Command::Pub(publ) => publ.execute(session),
         Command::Sub(sub) => sub.execute(session),
+        Command::Probe(probe) => probe.execute(session),
+        Command::Forward(forward) => forward.execute(session),
+        Command::Decode(decode) => decode.execute(),
+        Command::Encode(encode) => encode.execute(),
     }
 }
@@ -1,18 +1,147 @@
+use std::{rc::Rc, time::Duration};
+
+use mqtt::{backoff::{Backoff, BackoffPolicy}, packet::LastWill, session::{RedirectPolicy, SessionStore}, types::UserProperties};
+
+#[derive(Clone)]
 pub struct Session {
     debug: bool,
+    stats: bool,
     addr: (String, u16),
+    will: Option<LastWill>,
+    die: bool,
+    max_redirects: Option<u8>,
+    request_response_information: bool,
+    user_properties: UserProperties,
+    connect_timeout: Duration,
+    keep_alive: u16,
+    reconnect_attempts: Option<u32>,
+    summary: bool,
+    store: Option<Rc<dyn SessionStore>>,
+    session_expiry: u32,
 }
 
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
 impl Session {
 
-    pub fn new(debug: bool, addr: (String, u16)) -> Self {
-        Self { debug, addr }
+    pub fn new(debug: bool, stats: bool, addr: (String, u16)) -> Self {
+        Self { debug, stats, addr, will: None, die: false, max_redirects: None, request_response_information: false,
+            user_properties: UserProperties::new(), connect_timeout: DEFAULT_CONNECT_TIMEOUT, keep_alive: 0,
+            reconnect_attempts: None, summary: false, store: None, session_expiry: 0 }
+    }
+
+    /// Sets the server to connect to.
+    pub fn with_addr(self, addr: (String, u16)) -> Self {
+        Self { addr, ..self }
+    }
+
+    /// Sets the last will to send with `CONNECT` and whether to end the connection without `DISCONNECT`.
+    pub fn with_will(self, will: Option<LastWill>, die: bool) -> Self {
+        Self { will, die, ..self }
+    }
+
+    /// Sets how many server redirects to follow when connecting, none if `None`.
+    pub fn with_max_redirects(self, max_redirects: Option<u8>) -> Self {
+        Self { max_redirects, ..self }
+    }
+
+    /// Sets whether to ask the server for response information when connecting.
+    pub fn with_response_information(self, request_response_information: bool) -> Self {
+        Self { request_response_information, ..self }
+    }
+
+    /// Sets the user properties to send with `CONNECT`, `PUBLISH` and `SUBSCRIBE`.
+    pub fn with_user_properties(self, user_properties: UserProperties) -> Self {
+        Self { user_properties, ..self }
+    }
+
+    /// Sets how long to wait for the connection and the answer to `CONNECT`, the default if `None`.
+    pub fn with_connect_timeout(self, connect_timeout: Option<Duration>) -> Self {
+        Self { connect_timeout: connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT), ..self }
+    }
+
+    /// Sets the keep alive in seconds to ask for with `CONNECT`, disabled if `None` or `0`.
+    pub fn with_keep_alive(self, keep_alive: Option<u16>) -> Self {
+        Self { keep_alive: keep_alive.unwrap_or(0), ..self }
+    }
+
+    /// Sets how many more times to try connecting if the server can't be reached, not at all if `None`.
+    pub fn with_reconnect_attempts(self, reconnect_attempts: Option<u32>) -> Self {
+        Self { reconnect_attempts, ..self }
+    }
+
+    /// Sets whether to print a breakdown of the reason codes received when the client is done.
+    pub fn with_summary(self, summary: bool) -> Self {
+        Self { summary, ..self }
+    }
+
+    /// Sets where to keep the session between runs, to resume it with the next one, and how long the server keeps it
+    /// after disconnecting.
+    pub fn with_store(self, store: Option<Rc<dyn SessionStore>>, session_expiry: u32) -> Self {
+        Self { store, session_expiry, ..self }
+    }
+
+    pub fn summary(&self) -> bool {
+        self.summary
+    }
+
+    pub fn store(&self) -> Option<&dyn SessionStore> {
+        self.store.as_deref()
+    }
+
+    /// Seconds the server keeps the session after disconnecting, `0` if it ends with the connection.
+    pub fn session_expiry(&self) -> u32 {
+        self.session_expiry
+    }
+
+    pub fn connect_timeout(&self) -> Duration {
+        self.connect_timeout
+    }
+
+    pub fn keep_alive(&self) -> u16 {
+        self.keep_alive
+    }
+
+    /// The delays between attempts to connect, `None` if there is only one attempt.
+    pub fn backoff(&self) -> Option<Backoff> {
+        self.reconnect_attempts
+            .map(|attempts| Backoff::new(BackoffPolicy { max_attempts: Some(attempts), ..Default::default() }))
+    }
+
+    pub fn user_properties(&self) -> &UserProperties {
+        &self.user_properties
+    }
+
+    pub fn request_response_information(&self) -> bool {
+        self.request_response_information
+    }
+
+    pub fn redirect_policy(&self) -> RedirectPolicy {
+        match self.max_redirects {
+            Some(max) => RedirectPolicy::Follow(max),
+            None => RedirectPolicy::Report,
+        }
+    }
+
+    /// Returns the last will, which is only needed once for `CONNECT`.
+    pub fn take_will(&mut self) -> Option<LastWill> {
+        self.will.take()
+    }
+
+    /// Whether to drop the connection instead of disconnecting cleanly.
+    pub fn die(&self) -> bool {
+        self.die
     }
 
     pub fn addr(&self) -> (String, u16) {
         self.addr.clone()
     }
 
+    /// Whether to print connection statistics when disconnecting.
+    pub fn stats(&self) -> bool {
+        self.stats
+    }
+
     pub fn debug(&self, msg: String) {
         if self.debug {
             println!("[DEBUG] {}", msg)
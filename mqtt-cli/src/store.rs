@@ -0,0 +1,40 @@
+use std::{fs, io::ErrorKind, path::PathBuf};
+
+use mqtt::{error::MqttError, session::{SessionSnapshot, SessionStore}};
+
+/// Keeps the session of `sub --resume` in a JSON file between runs.
+pub struct FileStore {
+    path: PathBuf,
+}
+
+impl FileStore {
+
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    fn error(&self, what: &str, e: impl std::fmt::Display) -> MqttError {
+        MqttError::Message(format!("Cannot {} state file {}: {}", what, self.path.display(), e))
+    }
+}
+
+impl SessionStore for FileStore {
+
+    /// Nothing to resume if the file doesn't exist yet.
+    fn load(&self) -> Result<Option<SessionSnapshot>, MqttError> {
+        match fs::read_to_string(&self.path) {
+            Ok(json) => serde_json::from_str(&json).map(Some).map_err(|e| self.error("parse", e)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(self.error("read", e)),
+        }
+    }
+
+    /// Writes a temporary file next to the state file first, so that an interrupted save leaves the previous state
+    /// intact.
+    fn save(&self, snapshot: &SessionSnapshot) -> Result<(), MqttError> {
+        let json = serde_json::to_string_pretty(snapshot).map_err(|e| self.error("encode", e))?;
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, json).map_err(|e| self.error("write", e))?;
+        fs::rename(&tmp, &self.path).map_err(|e| self.error("write", e))
+    }
+}
@@ -0,0 +1,237 @@
+use std::time::{Duration, Instant};
+
+use mqtt::{
+    packet::{Connect, Packet, Pingreq, Publish, Subscribe, TopicFilter},
+    types::{validate_topic, QoS, ValidationMode},
+};
+
+use crate::connection::{describe, Connection, Response};
+
+/// Where to find the broker and how long to wait for it.
+pub struct Target {
+    pub addr: String,
+
+    /// How long to wait for any single response.
+    pub timeout: Duration,
+}
+
+#[derive(Debug)]
+pub enum Outcome {
+    Pass,
+    Fail(String),
+
+    /// The check couldn't run against this broker, with the reason why.
+    Skip(String),
+}
+
+/// A single scripted exchange with the broker.
+pub struct Check {
+    pub name: &'static str,
+    pub description: &'static str,
+    run: fn(&Target, &str) -> Outcome,
+}
+
+impl Check {
+
+    /// Runs the check on a connection of its own, using `client_id` wherever it needs to connect.
+    pub fn run(&self, target: &Target, client_id: &str) -> Outcome {
+        (self.run)(target, client_id)
+    }
+}
+
+/// All checks, in the order they're run.
+pub const CHECKS: &[Check] = &[
+    Check {
+        name: "connect",
+        description: "a valid CONNECT is accepted",
+        run: connect,
+    },
+    Check {
+        name: "pingreq",
+        description: "a PINGREQ is answered with a PINGRESP",
+        run: pingreq,
+    },
+    Check {
+        name: "connect-reserved-flag",
+        description: "CONNECT with the reserved connect flag set is rejected [MQTT-3.1.2-3]",
+        run: connect_reserved_flag,
+    },
+    Check {
+        name: "connect-invalid-utf8",
+        description: "CONNECT with a client identifier that isn't valid UTF-8 is rejected [MQTT-1.5.4-1]",
+        run: connect_invalid_utf8,
+    },
+    Check {
+        name: "second-connect",
+        description: "a second CONNECT on the same connection is a protocol error [MQTT-3.1.0-2]",
+        run: second_connect,
+    },
+    Check {
+        name: "subscribe-reserved-bits",
+        description: "SUBSCRIBE with the reserved fixed header bits cleared is rejected [MQTT-3.8.1-1]",
+        run: subscribe_reserved_bits,
+    },
+    Check {
+        name: "publish-qos-3",
+        description: "PUBLISH with both QoS bits set is rejected [MQTT-3.3.1-4]",
+        run: publish_qos_3,
+    },
+    Check {
+        name: "publish-null-character",
+        description: "PUBLISH to a topic containing U+0000 is rejected [MQTT-1.5.4-2]",
+        run: publish_null_character,
+    },
+    Check {
+        name: "oversized-remaining-length",
+        description: "a remaining length of more than four bytes is rejected [MQTT-1.5.5]",
+        run: oversized_remaining_length,
+    },
+    Check {
+        name: "keep-alive-expiry",
+        description: "a silent client is disconnected after one and a half times the keep alive [MQTT-3.1.2-22]",
+        run: keep_alive_expiry,
+    },
+];
+
+/// Keep alive requested by [keep_alive_expiry], in seconds.
+const KEEP_ALIVE: u16 = 1;
+
+/// The longest keep alive [keep_alive_expiry] is prepared to wait out if the broker overrides [KEEP_ALIVE].
+const MAX_KEEP_ALIVE: u16 = 30;
+
+fn connect(target: &Target, client_id: &str) -> Outcome {
+    match Connection::connected(&target.addr, client_id, 60, target.timeout) {
+        Ok(_) => Outcome::Pass,
+        Err(e) => Outcome::Fail(e),
+    }
+}
+
+fn pingreq(target: &Target, client_id: &str) -> Outcome {
+    let mut connection = match Connection::connected(&target.addr, client_id, 60, target.timeout) {
+        Ok((connection, _)) => connection,
+        Err(e) => return Outcome::Skip(e),
+    };
+    if let Err(e) = connection.send(Pingreq{}) {
+        return Outcome::Fail(e)
+    }
+
+    match connection.receive(target.timeout) {
+        Response::Packet(packet) if matches!(*packet, Packet::Pingresp(_)) => Outcome::Pass,
+        other => Outcome::Fail(format!("expected a PINGRESP, got {}", describe(&other))),
+    }
+}
+
+fn connect_reserved_flag(target: &Target, client_id: &str) -> Outcome {
+    let mut bytes: Vec<u8> = Connect::with_client_id_str(client_id).unwrap().try_into().unwrap();
+    // the connect flags follow the remaining length (single byte here), protocol name and version
+    bytes[9] |= 0b0000_0001;
+    refused(target, &bytes)
+}
+
+fn connect_invalid_utf8(target: &Target, _: &str) -> Outcome {
+    // CONNECT with an empty variable header and client identifier 0xC3 0x28, an invalid two byte sequence
+    let bytes = [
+        0x10, 0x0F, 0x00, 0x04, b'M', b'Q', b'T', b'T', 0x05, 0x02, 0x00, 0x3C, 0x00, 0x00, 0x02, 0xC3, 0x28,
+    ];
+    refused(target, &bytes)
+}
+
+fn second_connect(target: &Target, client_id: &str) -> Outcome {
+    after_connect(target, client_id, Connect::with_client_id_str(client_id).unwrap().try_into().unwrap())
+}
+
+fn subscribe_reserved_bits(target: &Target, client_id: &str) -> Outcome {
+    let topic_filter = vec![TopicFilter::validated("a/b".into(), ValidationMode::Strict).unwrap()];
+    let subscribe = Subscribe { packet_identifier: 1, properties: None, topic_filter };
+    let mut bytes: Vec<u8> = subscribe.try_into().unwrap();
+    bytes[0] &= 0xF0;
+    after_connect(target, client_id, bytes)
+}
+
+fn publish_qos_3(target: &Target, client_id: &str) -> Outcome {
+    let publish = Publish::builder("a/b").payload(vec![1, 2, 3]).qos(QoS::AtLeastOnce).packet_identifier(1).build().unwrap();
+    let mut bytes: Vec<u8> = publish.try_into().unwrap();
+    bytes[0] |= 0b0000_0110;
+    after_connect(target, client_id, bytes)
+}
+
+// the topic is invalid on purpose, which the validated constructors wouldn't allow
+#[allow(deprecated)]
+fn publish_null_character(target: &Target, client_id: &str) -> Outcome {
+    let topic = "a/\u{0}/b";
+    // make sure this is something the library itself would never let through, even when lenient
+    if validate_topic(topic, ValidationMode::Lenient).is_ok() {
+        return Outcome::Skip("topic unexpectedly passes validation".into())
+    }
+    after_connect(target, client_id, Publish::new(topic.into(), vec![1]).try_into().unwrap())
+}
+
+fn oversized_remaining_length(target: &Target, client_id: &str) -> Outcome {
+    after_connect(target, client_id, vec![0x30, 0xFF, 0xFF, 0xFF, 0xFF, 0x01])
+}
+
+fn keep_alive_expiry(target: &Target, client_id: &str) -> Outcome {
+    let (mut connection, connack) = match Connection::connected(&target.addr, client_id, KEEP_ALIVE, target.timeout) {
+        Ok(connected) => connected,
+        Err(e) => return Outcome::Skip(e),
+    };
+
+    let keep_alive = connack.properties.and_then(|p| p.server_keep_alive).unwrap_or(KEEP_ALIVE);
+    if keep_alive == 0 {
+        return Outcome::Skip("broker turned keep alive off".into())
+    }
+    if keep_alive > MAX_KEEP_ALIVE {
+        return Outcome::Skip(format!("broker keep alive of {}s is too long to wait for", keep_alive))
+    }
+
+    // give the broker some slack on top of the one and a half times
+    let start = Instant::now();
+    match connection.receive(Duration::from_millis(u64::from(keep_alive) * 1500) + target.timeout) {
+        Response::Closed => Outcome::Pass,
+        Response::Packet(packet) if matches!(*packet, Packet::Disconnect(_)) => Outcome::Pass,
+        Response::Timeout => Outcome::Fail(format!("still connected after {:?}", start.elapsed())),
+        other => Outcome::Fail(format!("expected the connection to be closed, got {}", describe(&other))),
+    }
+}
+
+/// Sends `bytes` instead of a valid `CONNECT` and expects the broker to refuse.
+fn refused(target: &Target, bytes: &[u8]) -> Outcome {
+    let mut connection = match Connection::open(&target.addr) {
+        Ok(connection) => connection,
+        Err(e) => return Outcome::Skip(e),
+    };
+    if let Err(e) = connection.send_raw(bytes) {
+        return Outcome::Fail(e)
+    }
+    expect_closed(&mut connection, target)
+}
+
+/// Sends `bytes` after connecting properly and expects the broker to disconnect.
+fn after_connect(target: &Target, client_id: &str, bytes: Vec<u8>) -> Outcome {
+    let mut connection = match Connection::connected(&target.addr, client_id, 60, target.timeout) {
+        Ok((connection, _)) => connection,
+        Err(e) => return Outcome::Skip(e),
+    };
+    if let Err(e) = connection.send_raw(&bytes) {
+        return Outcome::Fail(e)
+    }
+    expect_closed(&mut connection, target)
+}
+
+/// The broker may send a `CONNACK` or `DISCONNECT` with an error reason code before closing the connection
+/// [MQTT-4.13.1-1], or just close it.
+fn expect_closed(connection: &mut Connection, target: &Target) -> Outcome {
+    match connection.receive(target.timeout) {
+        Response::Closed => Outcome::Pass,
+        Response::Packet(packet) if refusal(&packet) => Outcome::Pass,
+        other => Outcome::Fail(format!("expected the connection to be closed, got {}", describe(&other))),
+    }
+}
+
+fn refusal(packet: &Packet) -> bool {
+    match packet {
+        Packet::Connack(p) => p.reason_code.is_err(),
+        Packet::Disconnect(p) => p.reason_code.is_err(),
+        _ => false,
+    }
+}
@@ -0,0 +1,115 @@
+use std::{io::{ErrorKind, Read, Write}, net::TcpStream, time::{Duration, Instant}};
+
+use mqtt::{error::MqttError, packet::{Connack, Connect, Packet, PacketIter}};
+
+const BUFFER_SIZE: usize = 4096;
+
+/// What came back from the broker while waiting for a packet.
+#[derive(Debug)]
+pub enum Response {
+    /// A complete packet that passed decoding.
+    Packet(Box<Packet>),
+
+    /// The broker closed the connection, or reset it.
+    Closed,
+
+    /// Nothing arrived in time.
+    Timeout,
+
+    /// The broker sent something that isn't a valid packet.
+    Invalid(MqttError),
+}
+
+/// A bare connection to the broker that sends whatever it's given, valid or not.
+pub struct Connection {
+    stream: TcpStream,
+    /// bytes read from the stream that don't make up a complete packet yet
+    inbound: Vec<u8>,
+}
+
+impl Connection {
+
+    pub fn open(addr: &str) -> Result<Self, String> {
+        let stream = TcpStream::connect(addr).map_err(|e| format!("cannot connect to {}: {}", addr, e))?;
+        Ok(Self { stream, inbound: Vec::new() })
+    }
+
+    /// Opens a connection and completes a regular `CONNECT`, returning the broker's `CONNACK` along with it.
+    pub fn connected(addr: &str, client_id: &str, keep_alive: u16, timeout: Duration) -> Result<(Self, Connack), String> {
+        let mut connect = Connect::with_client_id_str(client_id).map_err(|e| e.to_string())?;
+        connect.keep_alive = keep_alive;
+
+        let mut connection = Self::open(addr)?;
+        connection.send(connect)?;
+        match connection.receive(timeout) {
+            Response::Packet(packet) => match *packet {
+                Packet::Connack(connack) if !connack.reason_code.is_err() => Ok((connection, connack)),
+                Packet::Connack(connack) => Err(format!("connection refused: {:?}", connack.reason_code)),
+                other => Err(format!("expected a CONNACK, got {}", other.packet_type())),
+            },
+            other => Err(format!("expected a CONNACK, got {}", describe(&other))),
+        }
+    }
+
+    pub fn send<P: TryInto<Vec<u8>, Error: std::fmt::Display>>(&mut self, packet: P) -> Result<(), String> {
+        let bytes = packet.try_into().map_err(|e| format!("error encoding: {}", e))?;
+        self.send_raw(&bytes)
+    }
+
+    pub fn send_raw(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.stream.write_all(bytes).map_err(|e| format!("error sending: {}", e))
+    }
+
+    /// Waits for the next packet for at most `timeout`.
+    pub fn receive(&mut self, timeout: Duration) -> Response {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let mut packets = PacketIter::new(&self.inbound);
+            let next = packets.next();
+            let consumed = self.inbound.len() - packets.remaining();
+
+            match next {
+                Some(Ok((packet, _))) => {
+                    self.inbound.drain(..consumed);
+                    return Response::Packet(Box::new(packet))
+                },
+                Some(Err(e)) => {
+                    self.inbound.clear();
+                    return Response::Invalid(e)
+                },
+                None => {},
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Response::Timeout
+            }
+            if self.stream.set_read_timeout(Some(remaining)).is_err() {
+                return Response::Closed
+            }
+
+            let mut buff: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
+            match self.stream.read(&mut buff) {
+                Ok(0) => return Response::Closed,
+                Ok(len) => self.inbound.extend_from_slice(&buff[..len]),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => return Response::Timeout,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {},
+                Err(_) => return Response::Closed,
+            }
+        }
+    }
+}
+
+/// Short description of a response for the report.
+pub fn describe(response: &Response) -> String {
+    match response {
+        Response::Packet(packet) => match packet.as_ref() {
+            Packet::Connack(p) => format!("CONNACK {:?}", p.reason_code),
+            Packet::Disconnect(p) => format!("DISCONNECT {:?}", p.reason_code),
+            p => p.packet_type().to_string(),
+        },
+        Response::Closed => "closed connection".to_string(),
+        Response::Timeout => "nothing".to_string(),
+        Response::Invalid(e) => format!("an invalid packet ({})", e),
+    }
+}
@@ -0,0 +1,80 @@
+mod checks;
+mod connection;
+
+use std::{process::ExitCode, time::Duration};
+
+use clap::Parser;
+
+use crate::checks::{Outcome, Target, CHECKS};
+
+#[derive(Debug, Parser)]
+#[command(name = "mqtt-conformance", about = "Checks how an MQTT broker deals with packets that violate the spec", disable_help_flag = true)]
+pub struct ConformanceCli {
+
+    /// optional server host name, defaults to `localhost`
+    #[arg(short, long)]
+    pub host: Option<String>,
+
+    /// optional port number, defaults to `1883`
+    #[arg(short, long)]
+    pub port: Option<u16>,
+
+    /// seconds to wait for a response from the broker, defaults to `5`
+    #[arg(short, long)]
+    pub timeout: Option<u64>,
+
+    /// runs only the checks with these names
+    #[arg(short, long)]
+    pub only: Vec<String>,
+
+    /// lists all checks without running them
+    #[arg(short, long)]
+    pub list: bool,
+
+    /// prints help, `-h` is taken by the host
+    #[arg(long, action = clap::ArgAction::Help)]
+    pub help: Option<bool>,
+}
+
+fn main() -> ExitCode {
+    let cli = ConformanceCli::parse();
+
+    if cli.list {
+        for check in CHECKS {
+            println!("{:<28} {}", check.name, check.description);
+        }
+        return ExitCode::SUCCESS
+    }
+
+    let target = Target {
+        addr: format!("{}:{}", cli.host.unwrap_or("localhost".into()), cli.port.unwrap_or(mqtt::addr::DEFAULT_PORT)),
+        timeout: Duration::from_secs(cli.timeout.unwrap_or(5)),
+    };
+    println!("Checking {}", target.addr);
+
+    let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+    let selected = CHECKS.iter().filter(|c| cli.only.is_empty() || cli.only.iter().any(|n| n == c.name));
+    for (index, check) in selected.enumerate() {
+        let client_id = format!("conformance-{}-{}", std::process::id(), index);
+        match check.run(&target, &client_id) {
+            Outcome::Pass => {
+                passed += 1;
+                println!("PASS {:<28} {}", check.name, check.description);
+            },
+            Outcome::Fail(reason) => {
+                failed += 1;
+                println!("FAIL {:<28} {}\n     {}", check.name, check.description, reason);
+            },
+            Outcome::Skip(reason) => {
+                skipped += 1;
+                println!("SKIP {:<28} {}\n     {}", check.name, check.description, reason);
+            },
+        }
+    }
+
+    println!("\n{} passed, {} failed, {} skipped", passed, failed, skipped);
+    match failed {
+        0 => ExitCode::SUCCESS,
+        _ => ExitCode::FAILURE,
+    }
+}
@@ -1,19 +1,34 @@
-use quote::quote;
+use quote::{format_ident, quote};
 
 use crate::utils::PropertyFieldMeta;
 
-/// Generates an `impl crate::packet::Decodeable for` the annotated struct.
+/// Generates an `impl crate::packet::Decodeable for` the annotated struct, strict and lenient, and an
+/// `impl TryFrom<Vec<MqttProperty>> for` it. Properties that may only occur once are tracked to catch duplicates.
 pub fn generate_decode(
     name: &syn::Ident,
-    fields: &Vec<PropertyFieldMeta>,
+    fields: &[PropertyFieldMeta],
 ) -> quote::__private::TokenStream {
 
+    let namestr = name.to_string();
+
     let decode_fields = fields.iter().map(|f| {
         let prop_path = f.prop_ident_as_path();
         let drep = f.data_rep_as_path();
         let assignment = assignment(f);
+        let once = match f.list || f.map {
+            true => quote!{},
+            false => quote!{
+                if seen.contains(&prop.identifier) {
+                    return super::violation::tolerate(violations, super::Violation::DuplicateProperty {
+                        properties: #namestr, identifier: prop.identifier.value(),
+                    })
+                }
+                seen.push(prop.identifier);
+            },
+        };
         quote!{
             #prop_path => {
+                #once
                 if let #drep(v) = prop.value {
                     #assignment
                 }
@@ -22,19 +37,51 @@ pub fn generate_decode(
         }
     });
 
-    let namestr = name.to_string();
+    // structs with nothing but user properties and lists have nothing to track
+    let seen = match fields.iter().any(|f| !(f.list || f.map)) {
+        true => format_ident!("seen"),
+        false => format_ident!("_seen"),
+    };
 
     quote! {
         impl crate::packet::Decodeable for #name {
             fn decode(src: &[u8]) -> std::result::Result<super::DecodingResult<Self>, crate::error::MqttError> {
-                
+                Self::decode_with(src, None)
+            }
+
+            fn decode_lenient(
+                src: &[u8],
+                violations: &mut Vec<super::Violation>,
+            ) -> std::result::Result<super::DecodingResult<Self>, crate::error::MqttError> {
+                Self::decode_with(src, Some(violations))
+            }
+        }
+
+        impl TryFrom<std::vec::Vec<crate::packet::properties::MqttProperty>> for #name {
+            type Error = crate::error::MqttError;
+
+            fn try_from(
+                properties: std::vec::Vec<crate::packet::properties::MqttProperty>,
+            ) -> std::result::Result<Self, Self::Error> {
                 let mut result = Self::default();
+                let mut seen = Vec::new();
+                for prop in properties {
+                    prop.validate()?;
+                    result.insert_property(prop, &mut seen, &mut None)?;
+                }
+                Ok(result)
+            }
+        }
+
+        impl #name {
+            fn decode_with(
+                src: &[u8],
+                mut violations: Option<&mut Vec<super::Violation>>,
+            ) -> std::result::Result<super::DecodingResult<Self>, crate::error::MqttError> {
+                let mut result = Self::default();
+                let mut seen = Vec::new();
                 let bytes_read = super::properties::parse_properties(src, |prop| {
-                    match prop.identifier {
-                        #(#decode_fields,)*
-                        _=> return Err(crate::error::MqttError::Message(
-                            format!("Unknown property identifier: [{:?}] for {}", prop.identifier, #namestr)))
-                    }
+                    result.insert_property(prop, &mut seen, &mut violations)
                 })?;
 
                 let value = match bytes_read {
@@ -44,6 +91,22 @@ pub fn generate_decode(
 
                 Ok(super::DecodingResult{ bytes_read, value })
             }
+
+            /// Sets the field of `prop`, or adds it to the list of them.
+            fn insert_property(
+                &mut self,
+                prop: crate::packet::properties::MqttProperty,
+                #seen: &mut Vec<crate::packet::properties::PropertyIdentifier>,
+                violations: &mut Option<&mut Vec<super::Violation>>,
+            ) -> std::result::Result<(), crate::error::MqttError> {
+                let result = self;
+                match prop.identifier {
+                    #(#decode_fields,)*
+                    _=> super::violation::tolerate(violations, super::Violation::UnexpectedProperty {
+                        properties: #namestr, identifier: prop.identifier.value(),
+                    }),
+                }
+            }
         }
     }
 }
@@ -53,6 +116,9 @@ pub fn generate_decode(
 /// This is only pseudo-exhausting at the moment!
 fn assignment(field: &PropertyFieldMeta) -> quote::__private::TokenStream {
     let fname = &field.name;
+    if field.list {
+        return quote!{ result.#fname.push(v) }
+    }
     match field.ty_readable.as_str() {
         // don't need this, single bytes are only ever used as bools: "u8" => quote!{ property.value.try_into()? },
         "u16" => quote!{ result.#fname = Some(v) },
@@ -60,8 +126,8 @@ fn assignment(field: &PropertyFieldMeta) -> quote::__private::TokenStream {
         "bool" => quote!{ result.#fname = Some(prop.value.try_into()?) },
         "String" => quote!{ result.#fname = v.value },
         "Vec" => quote!{ result.#fname = Some(v.clone_inner()) },
-        "HashMap" => quote!{ 
-            result.#fname.insert(v.key.value.unwrap(), v.value.value.unwrap_or(String::new()));
+        "UserProperties" => quote!{ 
+            result.#fname.push(v.key.value.unwrap_or_default(), v.value.value.unwrap_or_default());
          },
         "QoS" => quote!{ result.#fname = Some(QoS::try_from(v)?) },
         "VariableByteInteger" => quote!{ result.#fname = Some(v) },
@@ -30,8 +30,10 @@ fn default_value(field: &syn::Field) -> quote::__private::TokenStream {
             let ty = &segment.ident;
             if ty == "Option" {
                 return quote! { None };
-            } else if ty == "HashMap" {
-                return quote! { std::collections::HashMap::new() };
+            } else if ty == "Vec" {
+                return quote! { std::vec::Vec::new() };
+            } else if ty == "UserProperties" {
+                return quote! { crate::types::UserProperties::new() };
             } else if ty == "bool" {
                 return quote! { true };
             }
@@ -2,46 +2,104 @@ use quote::{format_ident, quote};
 
 use crate::utils::PropertyFieldMeta;
 
-/// Generates an `impl From<SRC_TYPE> for std::vec::Vec<u8> where `SRC_TYPE` is the annotated type.
+/// Generates an `impl TryFrom<SRC_TYPE> for std::vec::Vec<u8> where `SRC_TYPE` is the annotated type, on top of an
+/// `impl From<SRC_TYPE> for std::vec::Vec<MqttProperty>`. The former encodes each field straight into the bytes
+/// rather than going through the latter.
 pub fn generate_encode(
     name: &syn::Ident,
-    fields: &Vec<PropertyFieldMeta>,
+    fields: &[PropertyFieldMeta],
 ) -> quote::__private::TokenStream {
-    let into_fields = fields.iter().map(|f| quote_field(f));
+    let push = quote!{ result.push(crate::packet::properties::MqttProperty { identifier, value }); };
+    let into_fields = fields.iter().map(|f| quote_field(f, quote!{}, &push));
+    let iter_fields = fields.iter().map(|f| quote_field(f, quote!{ .clone() }, &push));
+    let encode = quote!{ super::properties::encode_and_append_property(identifier, value, &mut result)?; };
+    let encode_fields = fields.iter().map(|f| quote_field(f, quote!{}, &encode));
+    let count_fields = fields.iter().map(|f| {
+        let name = &f.name;
+        match f.optional {
+            true => quote!{ usize::from(self.#name.is_some()) },
+            false => quote!{ self.#name.len() },
+        }
+    });
 
     quote! {
-        impl From<#name> for std::vec::Vec<u8> {
+        impl From<#name> for std::vec::Vec<crate::packet::properties::MqttProperty> {
             fn from(src: #name) -> Self {
-                let mut result: std::vec::Vec<u8> = Vec::new();
+                let mut result: std::vec::Vec<crate::packet::properties::MqttProperty> = Vec::new();
 
                 #(#into_fields;)*
 
-                super::encode_and_insert(
-                    crate::types::VariableByteInteger::from(result.len() as u32),
-                    0,
-                    &mut result
-                );
                 result
             }
         }
+
+        impl #name {
+            /// The number of properties set, counting each entry of those that may occur more than once.
+            pub fn len(&self) -> usize {
+                0 #(+ #count_fields)*
+            }
+
+            /// `true` if no property is set, which encodes the same as no properties at all.
+            pub fn is_empty(&self) -> bool {
+                self.len() == 0
+            }
+
+            /// The properties that are set, in the order they are encoded.
+            pub fn iter(&self) -> impl Iterator<Item = crate::packet::properties::MqttProperty> {
+                let src = self;
+                let mut result: std::vec::Vec<crate::packet::properties::MqttProperty> = Vec::new();
+
+                #(#iter_fields;)*
+
+                result.into_iter()
+            }
+        }
+
+        impl TryFrom<#name> for std::vec::Vec<u8> {
+            type Error = crate::error::MqttError;
+
+            fn try_from(src: #name) -> std::result::Result<Self, Self::Error> {
+                let mut result: std::vec::Vec<u8> = Vec::new();
+
+                #(#encode_fields;)*
+
+                super::insert_properties_length(&mut result);
+                Ok(result)
+            }
+        }
     }
 }
 
-fn quote_field(field: &PropertyFieldMeta) -> quote::__private::TokenStream {
+/// Runs `action` with the `identifier` and `value` of each property of the field, taking it from `src` as is or as
+/// `src.field #access`. Pushing it to a `result` of `MqttProperty` or encoding it straight into one of bytes.
+fn quote_field(
+    field: &PropertyFieldMeta,
+    access: quote::__private::TokenStream,
+    action: &quote::__private::TokenStream,
+) -> quote::__private::TokenStream {
     let name = &field.name;
     let prop_ident = field.prop_ident_as_path();
 
     let (drepr, dval) = map_data_types(field);
     
     let assign_and_encode = quote!{
-        let val = crate::packet::properties::DataRepresentation::#drepr(#dval);
-        super::properties::encode_and_append_property(#prop_ident, val, &mut result);
+        let identifier = #prop_ident;
+        let value = crate::packet::properties::DataRepresentation::#drepr(#dval);
+        #action
     };
 
     if field.map {
-        // we only support HashMap<String, String> at the moment
+        // user properties are the only name-value pairs
+        return quote! {
+            for (k, v) in src.#name #access {
+                #assign_and_encode
+            }
+        };
+    }
+
+    if field.list {
         return quote! {
-            for (k, v) in src.#name {
+            for v in src.#name #access {
                 #assign_and_encode
             }
         };
@@ -49,12 +107,12 @@ fn quote_field(field: &PropertyFieldMeta) -> quote::__private::TokenStream {
 
     match field.optional {
         true => quote!{
-            if let Some(v) = src.#name {
+            if let Some(v) = src.#name #access {
                 #assign_and_encode
             }
         },
         false => quote!{
-            let v = drc.#name;
+            let v = src.#name #access;
             #assign_and_encode
         },
     }
@@ -90,9 +148,9 @@ fn map_data_types(field: &PropertyFieldMeta) -> (syn::Ident, quote::__private::T
         ),
         "Vec" => (
             format_ident!("{}", "BinaryData"),
-            quote! { crate::types::BinaryData::new(v).unwrap() },
+            quote! { crate::types::BinaryData::from(v) },
         ),
-        "HashMap" => (
+        "UserProperties" => (
             format_ident!("{}", "UTF8Pair"),
             quote!{ crate::types::UTF8StringPair::new(k, v) }
         ),
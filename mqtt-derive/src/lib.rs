@@ -9,12 +9,13 @@ mod default;
 mod encode;
 mod utils;
 
-/// Generates implementations of `Default`, `Decodeable` and `Into<Vec<u8>>` for a struct with 
-/// `#[derive(MqttProperties)]` attribute.
+/// Generates implementations of `Default`, `Decodeable`, `Into<Vec<u8>>` and conversions from and into
+/// `Vec<MqttProperty>` for a struct with `#[derive(MqttProperties)]` attribute.
 /// 
 /// This will only work for structs representing MQTT packet properties, and will only work if:
 /// - the properties consist only of fields that are `Option` of one of the following rust datatypes: `u16`, 
-/// `u32`, `bool`, `String` or `Vec<u8>`, or a `HashMap<String, String>`
+///   `u32`, `bool`, `String` or `Vec<u8>`, `UserProperties`, or a `Vec` of one of the others for
+///   properties that may occur more than once
 /// - the properties are located within the mqtt::packet module
 /// 
 /// TODO better error handling, especially using spans to locate issues with individual fields
@@ -2,10 +2,11 @@ use quote::format_ident;
 
 pub struct PropertyFieldMeta {
     pub name: syn::Ident,
-    pub ty: syn::Type,
     pub ty_readable: String,
     pub optional: bool,
     pub map: bool,
+    /// `Vec` of anything but `u8`, for properties that may occur more than once
+    pub list: bool,
     pub prop_ident: String,
 }
 
@@ -31,7 +32,7 @@ impl PropertyFieldMeta {
             "bool" => "Byte",
             "String" => "UTF8",
             "Vec" => "BinaryData",
-            "HashMap" => "UTF8Pair",
+            "UserProperties" => "UTF8Pair",
             "QoS" => "Byte",
             "VariableByteInteger" => "VariByteInt",
             els => panic!("Cannot convert {:?} of type {:?}", self.name, els)
@@ -59,7 +60,7 @@ fn map_field_meta(field: &syn::Field) -> PropertyFieldMeta {
         None => format_ident!("unknown"), // FIXME
     };
     let prop_ident = map_enum_variant(&name.to_string());
-    let (ty, optional, map) = extract_type(field);
+    let (ty, optional, map, list) = extract_type(field);
 
     let ty_readable = match &ty {
         syn::Type::Path(p) => p.path.segments[0].ident.to_string(),
@@ -68,39 +69,54 @@ fn map_field_meta(field: &syn::Field) -> PropertyFieldMeta {
 
     PropertyFieldMeta {
         name,
-        ty,
         ty_readable,
         optional,
         map,
+        list,
         prop_ident,
     }
 }
 
-fn extract_type(field: &syn::Field) -> (syn::Type, bool, bool) {
+fn extract_type(field: &syn::Field) -> (syn::Type, bool, bool, bool) {
     if let syn::Type::Path(ref p) = &field.ty {
         if let Some(segment) = p.path.segments.first() {
             let ty = &segment.ident;
-            let is_map = ty.to_string() == "HashMap".to_string();
+            let is_map = ty == "UserProperties";
             if ty == "Option" {
-                if let syn::PathArguments::AngleBracketed(ref ab) = segment.arguments {
-                    if let syn::GenericArgument::Type(ref t) = ab.args.first().unwrap() {
-                        return (t.to_owned(), true, is_map);
+                if let Some(t) = first_type_argument(segment) {
+                    return (t.to_owned(), true, is_map, false);
+                }
+                // FIXME add more sophisticated handling for user properties and whatnot
+                return (field.ty.to_owned(), true, is_map, false);
+            } else if ty == "Vec" {
+                // a plain `Vec<u8>` is binary data, anything else is a property that may occur more than once
+                if let Some(t) = first_type_argument(segment) {
+                    if !matches!(t, syn::Type::Path(tp) if tp.path.is_ident("u8")) {
+                        return (t.to_owned(), false, false, true);
                     }
                 }
-                // FIXME add more sophisticated handling for HashMaps and whatnot
-                return (field.ty.to_owned(), true, is_map);
+                return (field.ty.to_owned(), false, false, false);
             } else {
-                return (field.ty.to_owned(), false, is_map);
+                return (field.ty.to_owned(), false, is_map, false);
             }
         }
     }
 
     // this isn't right, we should return an error here...
-    return (field.ty.to_owned(), false, false);
+    (field.ty.to_owned(), false, false, false)
+}
+
+fn first_type_argument(segment: &syn::PathSegment) -> Option<&syn::Type> {
+    if let syn::PathArguments::AngleBracketed(ref ab) = segment.arguments {
+        if let Some(syn::GenericArgument::Type(ref t)) = ab.args.first() {
+            return Some(t)
+        }
+    }
+    None
 }
 
 // simply reformats from `abc_def_ghi` to `AbcDefGhi`.
-fn map_enum_variant(field_name: &String) -> String {
+fn map_enum_variant(field_name: &str) -> String {
     let mut result = String::new();
     for part in field_name.split('_') {
         let mut chars = part.chars();
@@ -0,0 +1,86 @@
+//! Compares sending a burst of QoS 0 `PUBLISH` packets over a loopback TCP connection one write per packet against
+//! the batched variants, with and without a pool of encoding buffers.
+//!
+//! Run with `cargo bench -p mqtt --bench batch_encoding`.
+
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+
+use mqtt::{codec::pool::BufferPool, packet::{encode_batch, write_batch, Packet, Publish}, types::TopicName};
+
+const MESSAGES: usize = 100_000;
+const BATCH_SIZE: usize = 100;
+
+fn main() {
+    run("one write per packet", |stream, packets| {
+        for packet in packets {
+            let bytes: Vec<u8> = packet.try_into().unwrap();
+            stream.write_all(&bytes).unwrap();
+        }
+    });
+
+    run("encode_batch", |stream, packets| {
+        let mut buf = Vec::new();
+        encode_batch(packets, &mut buf).unwrap();
+        stream.write_all(&buf).unwrap();
+    });
+
+    run("write_batch", |stream, packets| {
+        write_batch(packets, stream).unwrap();
+    });
+
+    let mut pool = BufferPool::new(1);
+    run("encode_batch, pooled", |stream, packets| {
+        let buf = pool.encode_batch(packets).unwrap();
+        stream.write_all(&buf).unwrap();
+        pool.put(buf);
+    });
+}
+
+fn run<F: FnMut(&mut TcpStream, Vec<Packet>)>(name: &str, mut send: F) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let reader = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = [0; 64 * 1024];
+        let mut total = 0;
+        loop {
+            match stream.read(&mut buf).unwrap() {
+                0 => return total,
+                n => total += n,
+            }
+        }
+    });
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_nodelay(true).unwrap();
+
+    let mut elapsed = Duration::ZERO;
+    for _ in 0..MESSAGES / BATCH_SIZE {
+        let packets = burst();
+        let start = Instant::now();
+        send(&mut stream, packets);
+        elapsed += start.elapsed();
+    }
+    drop(stream);
+    let bytes = reader.join().unwrap();
+
+    println!(
+        "{:<22} {:>10.0} msg/s ({} bytes in {:?})",
+        name,
+        MESSAGES as f64 / elapsed.as_secs_f64(),
+        bytes,
+        elapsed,
+    );
+}
+
+fn burst() -> Vec<Packet> {
+    let topic = TopicName::try_from("bench/topic").unwrap();
+    (0..BATCH_SIZE)
+        .map(|i| Publish::with_topic_name(topic.clone(), i.to_be_bytes().to_vec()).into())
+        .collect()
+}
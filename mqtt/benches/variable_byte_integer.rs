@@ -0,0 +1,61 @@
+//! Measures encoding (into a vector of its own and appended to a buffer) and decoding of variable byte integers, which
+//! every packet has at least one of for its remaining length, for each of the four encoded lengths. Encoding and
+//! decoding a whole `PUBACK` shows the common case of a short packet.
+//!
+//! Run with `cargo bench -p mqtt --bench variable_byte_integer`.
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use mqtt::{packet::{Packet, Puback}, types::{ReasonCode, VariableByteInteger}};
+
+const ITERATIONS: u32 = 10_000_000;
+
+/// One value per encoded length.
+const VALUES: [u32; 4] = [100, 10_000, 1_000_000, 100_000_000];
+
+fn main() {
+    for value in VALUES {
+        let vbi = VariableByteInteger::from(value);
+        let encoded: Vec<u8> = vbi.into();
+        let len = encoded.len();
+
+        run(&format!("encode, {} byte(s)", len), || {
+            let bytes: Vec<u8> = black_box(vbi).into();
+            black_box(bytes);
+        });
+
+        let mut buf = Vec::with_capacity(8);
+        run(&format!("write_to, {} byte(s)", len), || {
+            buf.clear();
+            black_box(vbi).write_to(&mut buf);
+            black_box(&buf);
+        });
+
+        run(&format!("decode, {} byte(s)", len), || {
+            black_box(VariableByteInteger::try_from(black_box(&encoded[..])).unwrap());
+        });
+    }
+
+    run("encode PUBACK", || {
+        let bytes: Vec<u8> = black_box(Puback::new(1, ReasonCode::Success).unwrap()).try_into().unwrap();
+        black_box(bytes);
+    });
+
+    let puback: Vec<u8> = Puback::new(1, ReasonCode::Success).unwrap().try_into().unwrap();
+    run("decode PUBACK", || {
+        black_box(Packet::try_from(black_box(&puback[..])).unwrap());
+    });
+}
+
+fn run<F: FnMut()>(name: &str, mut op: F) {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        op();
+    }
+    let elapsed: Duration = start.elapsed();
+
+    println!("{:<22} {:>8.2} ns/op", name, elapsed.as_nanos() as f64 / f64::from(ITERATIONS));
+}
@@ -0,0 +1,38 @@
+//! Refuses compilers older than the `rust-version` of the manifest with a message saying so, instead of the errors
+//! about unknown methods they would otherwise run into. Cargo checks `rust-version` itself, but not with
+//! `--ignore-rust-version` and not for compilers from before it knew the field.
+
+use std::{env, process::Command};
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=RUSTC");
+
+    // plain syntax only from here on, the point is to get through to old compilers
+    let required = env::var("CARGO_PKG_RUST_VERSION").ok().and_then(|version| parse(&version));
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+    // if the version can't be told for some reason, the compiler has to speak for itself
+    let found = rustc_version(&rustc);
+
+    if let (Some(required), Some(found)) = (required, found) {
+        if found < required {
+            panic!(
+                "mqtt requires Rust {}.{} or newer, but {} is {}.{}. Update with `rustup update`.",
+                required.0, required.1, rustc, found.0, found.1,
+            );
+        }
+    }
+}
+
+/// Major and minor version from the output of `rustc --version`, such as `rustc 1.82.0 (f6e511eec 2024-10-15)`.
+fn rustc_version(rustc: &str) -> Option<(u32, u32)> {
+    let output = Command::new(rustc).arg("--version").output().ok()?;
+    let output = String::from_utf8(output.stdout).ok()?;
+    parse(output.strip_prefix("rustc ")?)
+}
+
+/// Major and minor version from the start of `1.82`, `1.82.0` or `1.83.0-nightly`.
+fn parse(version: &str) -> Option<(u32, u32)> {
+    let mut parts = version.split(|c: char| !c.is_ascii_digit());
+    Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+}
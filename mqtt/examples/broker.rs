@@ -0,0 +1,259 @@
+//! A minimal broker: accepts clients over TCP, keeps their subscriptions in a `SubscriptionTree` and forwards every
+//! message to the matching subscribers. Each client has a thread of its own, whose `ServerSession` answers `PINGREQ`
+//! and watches the keep alive.
+//!
+//! Deliberately small: messages are delivered with QoS 0, QoS 2 isn't supported and there are no sessions outliving
+//! the connection, retained messages or wills.
+//!
+//! Run with `cargo run -p mqtt --example broker -- [port]`, which defaults to `1883`. The `client` and
+//! `request_response` examples work against it.
+
+use std::{
+    collections::HashMap,
+    env,
+    io::{ErrorKind, Read},
+    net::TcpListener,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use mqtt::{
+    error::MqttError,
+    packet::{
+        ConnackProperties, Connect, Disconnect, Packet, PacketIter, Puback, Publish, Suback, Subscribe,
+        Unsuback, Unsubscribe,
+    },
+    router::SubscriptionTree,
+    session::{Authenticator, ServerHandshake, ServerLimits, ServerSession},
+    transport::{TcpTransport, Transport},
+    types::{QoS, ReasonCode, TopicName, ValidationMode},
+};
+
+/// How long to wait for the next packet when there's no keep alive to watch.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What all connections share: the subscriptions and where to send messages for each client.
+#[derive(Default)]
+struct Broker {
+    subscriptions: SubscriptionTree<String>,
+    clients: HashMap<String, Box<dyn Transport>>,
+}
+
+fn main() -> Result<(), MqttError> {
+    let port: u16 = env::args().nth(1).map_or(Ok(mqtt::addr::DEFAULT_PORT), |p| p.parse())
+        .map_err(|e| MqttError::Message(format!("Invalid port: {}", e)))?;
+    let listener = TcpListener::bind(("0.0.0.0", port))
+        .map_err(|e| MqttError::Message(format!("Cannot listen on port {}: {}", port, e)))?;
+    println!("Listening on port {}", port);
+
+    let broker = Arc::new(Mutex::new(Broker::default()));
+    let limits = ServerLimits {
+        maximum_client_id_length: Some(64),
+        assigned_client_id_prefix: Some("client-".into()),
+        ..Default::default()
+    };
+    // anyone may connect
+    let handshake = Arc::new(ServerHandshake::new(limits, |_: &Connect, _: &str| Ok(())));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                println!("Error accepting connection: {}", e);
+                continue
+            },
+        };
+        let broker = broker.clone();
+        let handshake = handshake.clone();
+        thread::spawn(move || {
+            let mut connection = Connection::new(TcpTransport::from(stream));
+            if let Err(e) = connection.serve(&broker, &handshake) {
+                println!("Connection closed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// A client connection along with whatever has been read but doesn't make up a whole packet yet.
+struct Connection {
+    transport: TcpTransport,
+    session: ServerSession,
+    inbound: Vec<u8>,
+}
+
+impl Connection {
+
+    fn new(transport: TcpTransport) -> Self {
+        Self { transport, session: ServerSession::default(), inbound: Vec::new() }
+    }
+
+    /// Runs the connection from `CONNECT` to `DISCONNECT`, or until the client goes away.
+    fn serve<A>(&mut self, broker: &Mutex<Broker>, handshake: &ServerHandshake<A>) -> Result<(), MqttError>
+    where
+        A: Authenticator,
+    {
+        let connect = match self.receive()? {
+            Some(Packet::Connect(connect)) => connect,
+            other => return Err(MqttError::ProtocolError(format!("Expected CONNECT, got {:?}", other))),
+        };
+        let client_id = self.accept(&connect, handshake)?;
+        println!("{} connected", client_id);
+
+        let writer = self.transport.try_clone().map_err(|e| MqttError::Message(e.to_string()))?;
+        if let Some(mut previous) = lock(broker).clients.insert(client_id.clone(), writer) {
+            // the spec has the new connection take over
+            send(&mut previous, Disconnect { reason_code: ReasonCode::SessionTakenOver, properties: None }).ok();
+            previous.shutdown().ok();
+        }
+
+        let mut filters = Vec::new();
+        let result = self.run(broker, &client_id, &mut filters);
+
+        let mut broker = lock(broker);
+        broker.clients.remove(&client_id);
+        for filter in filters {
+            broker.subscriptions.unsubscribe(&client_id, &filter);
+        }
+        println!("{} disconnected", client_id);
+        result
+    }
+
+    /// Sends the `CONNACK`, returning the client identifier: the one the client sent or one assigned to it. Fails after
+    /// sending it if the client was refused.
+    fn accept<A>(&mut self, connect: &Connect, handshake: &ServerHandshake<A>) -> Result<String, MqttError>
+    where
+        A: Authenticator,
+    {
+        let mut accepted = match handshake.accept(connect) {
+            Ok(accepted) => accepted,
+            Err(connack) => {
+                let reason_code = connack.reason_code;
+                send(&mut self.transport, *connack)?;
+                return Err(MqttError::Message(format!("Refused client with {}", reason_code)))
+            },
+        };
+        self.session.on_connect(connect, accepted.server_keep_alive);
+
+        let properties = accepted.connack.properties.get_or_insert_with(ConnackProperties::default);
+        properties.maximum_qos = Some(QoS::AtLeastOnce);
+        properties.retain_available = Some(false);
+        send(&mut self.transport, accepted.connack)?;
+        Ok(accepted.client_id)
+    }
+
+    /// Handles everything after the `CONNECT`, recording the filters subscribed to.
+    fn run(&mut self, broker: &Mutex<Broker>, client_id: &String, filters: &mut Vec<String>) -> Result<(), MqttError> {
+        while let Some(packet) = self.receive()? {
+            if let Some(response) = self.session.on_packet_received(&packet) {
+                send(&mut self.transport, response)?;
+            }
+
+            match packet {
+                Packet::Publish(publish) => {
+                    match publish.qos_level {
+                        QoS::AtMostOnce => (),
+                        QoS::AtLeastOnce => {
+                            let packet_identifier = publish.packet_identifier.unwrap_or_default();
+                            send(&mut self.transport, Puback::new(packet_identifier, ReasonCode::Success)?)?;
+                        },
+                        QoS::ExactlyOnce => {
+                            let disconnect = Disconnect { reason_code: ReasonCode::QoSNotSupported, properties: None };
+                            return send(&mut self.transport, disconnect)
+                        },
+                    }
+                    forward(&mut lock(broker), client_id, publish);
+                },
+                Packet::Subscribe(subscribe) => {
+                    lock(broker).subscriptions.subscribe_all(client_id.clone(), &subscribe);
+                    filters.extend(subscribe.topic_filter.iter().map(|f| f.filter.clone()));
+                    send(&mut self.transport, suback(&subscribe))?;
+                },
+                Packet::Unsubscribe(unsubscribe) => {
+                    let unsuback = unsubscribe_all(&mut lock(broker), client_id, &unsubscribe);
+                    send(&mut self.transport, unsuback)?;
+                },
+                Packet::Disconnect(_) => return Ok(()),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits for the next packet, `None` once the client has closed the connection. Closes it with a `DISCONNECT`
+    /// if the client's keep alive runs out.
+    fn receive(&mut self) -> Result<Option<Packet>, MqttError> {
+        loop {
+            // first, so that whatever was just read counts as received now
+            let now = self.session.clock().now();
+            if let Some(disconnect) = self.session.advance(now) {
+                send(&mut self.transport, disconnect)?;
+                return Err(MqttError::Message("Keep alive timeout".to_string()))
+            }
+
+            if let Some(result) = PacketIter::new(&self.inbound).next() {
+                let (packet, len) = result?;
+                self.inbound.drain(..len);
+                return Ok(Some(packet))
+            }
+
+            let timeout = self.session.time_until_due(now).unwrap_or(READ_TIMEOUT);
+            self.transport.set_read_timeout(Some(timeout))
+                .map_err(|e| MqttError::Message(e.to_string()))?;
+
+            let mut buf = [0; 4096];
+            match self.transport.read(&mut buf) {
+                Ok(0) => return Ok(None),
+                Ok(n) => self.inbound.extend_from_slice(&buf[..n]),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => (),
+                Err(e) => return Err(MqttError::Message(format!("Error receiving: {}", e))),
+            }
+        }
+    }
+}
+
+/// Sends a copy of the message to every client with a matching subscription, with QoS 0 and the identifiers of the
+/// subscriptions it matched.
+fn forward(broker: &mut Broker, publisher: &String, publish: Publish) {
+    // the session already checked the topic when decoding, which is as lenient as this
+    let Ok(topic) = TopicName::new(publish.topic_name.as_str(), ValidationMode::Lenient) else { return };
+    for subscriber in broker.subscriptions.matches_from(&publish.topic_name, publisher) {
+        let mut copy = Publish::with_topic_name(topic.clone(), publish.payload.clone());
+        copy.properties = publish.properties.clone();
+        subscriber.annotate(&mut copy);
+
+        if let Some(transport) = broker.clients.get_mut(&subscriber.client) {
+            if let Err(e) = send(transport, copy) {
+                println!("Cannot forward to {}: {}", subscriber.client, e);
+            }
+        }
+    }
+}
+
+/// Grants QoS 0 (the success code) for each filter, as that's what messages are delivered with.
+fn suback(subscribe: &Subscribe) -> Suback {
+    Suback {
+        packet_identifier: subscribe.packet_identifier,
+        properties: None,
+        reason_codes: vec![ReasonCode::Success; subscribe.topic_filter.len()],
+    }
+}
+
+fn unsubscribe_all(broker: &mut Broker, client_id: &String, unsubscribe: &Unsubscribe) -> Unsuback {
+    let reason_codes = unsubscribe.topic_filter.iter()
+        .map(|filter| match broker.subscriptions.unsubscribe(client_id, filter) {
+            true => ReasonCode::Success,
+            false => ReasonCode::NoSubscriptionExisted,
+        })
+        .collect();
+    Unsuback { packet_identifier: unsubscribe.packet_identifier, properties: None, reason_codes }
+}
+
+fn send<T: Transport + ?Sized, P: Into<Packet>>(transport: &mut T, packet: P) -> Result<(), MqttError> {
+    let bytes: Vec<u8> = packet.into().try_into()?;
+    transport.write_all(&bytes).map_err(|e| MqttError::Message(format!("Error sending: {}", e)))
+}
+
+fn lock(broker: &Mutex<Broker>) -> std::sync::MutexGuard<'_, Broker> {
+    broker.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,69 @@
+//! A synchronous client built from a `TcpTransport` and a `ClientSession`: connects, subscribes to a topic, publishes
+//! a QoS 1 message to it and waits for both the `PUBACK` and the message itself to come back.
+//!
+//! Needs a broker, such as a local mosquitto or the `broker` example. Run with
+//! `cargo run -p mqtt --example client -- [host] [port]`, which defaults to `localhost 1883`.
+
+mod common;
+
+use mqtt::{
+    error::MqttError,
+    packet::{Disconnect, Packet, Publish, Subscribe, TopicFilter},
+    session::SessionConfig,
+    types::{QoS, ValidationMode},
+};
+
+use common::Client;
+
+const TOPIC: &str = "rust-mqtt/examples/client";
+
+fn main() -> Result<(), MqttError> {
+    let addr = common::broker_addr()?;
+    let mut client = Client::connect(&addr, SessionConfig { keep_alive: Some(30), ..Default::default() })?;
+    println!("Connected to {}:{}", addr.0, addr.1);
+
+    let mut filter = TopicFilter::validated(TOPIC.into(), ValidationMode::Strict)?;
+    filter.maximum_qos = QoS::AtLeastOnce;
+    let mut subscribe = Subscribe { packet_identifier: 1, properties: None, topic_filter: vec![filter] };
+    client.session.prepare_subscribe(&mut subscribe)?;
+    client.send(subscribe)?;
+    match client.receive()? {
+        Packet::Suback(suback) => {
+            client.session.on_suback(&suback);
+            println!("Subscribed: {:?}", suback.reason_codes);
+        },
+        other => return Err(MqttError::Message(format!("Expected SUBACK, got {:?}", other))),
+    }
+
+    let mut publish = Publish::builder(TOPIC)
+        .payload("hello from rust-mqtt")
+        .qos(QoS::AtLeastOnce)
+        .packet_identifier(2)
+        .build()?;
+    client.session.prepare_publish(&mut publish)?;
+    client.send(publish)?;
+
+    let (mut acknowledged, mut received) = (false, false);
+    while !(acknowledged && received) {
+        match client.receive()? {
+            Packet::Puback(puback) => {
+                client.session.on_puback(&puback)?;
+                println!("Acknowledged: {}", puback.reason_code);
+                acknowledged = true;
+            },
+            Packet::Publish(publish) => {
+                if let Some(ack) = client.session.on_incoming_publish(&publish) {
+                    client.send(ack)?;
+                }
+                println!("Received on {}: {}", publish.topic_name, String::from_utf8_lossy(&publish.payload));
+                received = true;
+            },
+            other => println!("Ignoring {:?}", other),
+        }
+    }
+
+    client.send(Disconnect::default())?;
+    let stats = client.session.stats();
+    println!("Sent {} bytes, received {}", stats.bytes_sent, stats.bytes_received);
+    Ok(())
+}
@@ -0,0 +1,98 @@
+//! The synchronous client shared by the examples that need a broker.
+
+use std::{
+    env,
+    io::{ErrorKind, Read, Write},
+    time::Duration,
+};
+
+use mqtt::{
+    error::MqttError,
+    packet::{Connect, Packet, PacketIter, Pingreq},
+    session::{ClientSession, DueAction, Handshake, HandshakeStep, SessionConfig},
+    transport::{TcpTransport, Transport},
+};
+
+/// How long to wait for the next packet when there's no keep alive to watch.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Host and port of the broker from the command line, `localhost` and `1883` unless given.
+pub fn broker_addr() -> Result<(String, u16), MqttError> {
+    let mut args = env::args().skip(1);
+    let host = args.next().unwrap_or_else(|| "localhost".to_string());
+    let port = args.next().map_or(Ok(mqtt::addr::DEFAULT_PORT), |p| p.parse())
+        .map_err(|e| MqttError::Message(format!("Invalid port: {}", e)))?;
+    Ok((host, port))
+}
+
+/// A connection to a broker along with its session and whatever has been read but doesn't make up a whole packet
+/// yet.
+pub struct Client {
+    transport: TcpTransport,
+    pub session: ClientSession,
+    inbound: Vec<u8>,
+}
+
+impl Client {
+
+    /// Connects and completes the handshake, failing if the broker refuses the connection.
+    pub fn connect((host, port): &(String, u16), config: SessionConfig) -> Result<Self, MqttError> {
+        let transport = TcpTransport::connect((host.as_str(), *port))
+            .map_err(|e| MqttError::Message(format!("Cannot connect to {}:{}: {}", host, port, e)))?;
+        let mut client = Client { transport, session: ClientSession::new(config), inbound: Vec::new() };
+
+        let mut connect = Connect::default();
+        client.session.prepare_connect(&mut connect);
+        let mut handshake = Handshake::new(&connect);
+        client.send(connect)?;
+
+        let connack = match handshake.on_packet(client.receive()?)? {
+            HandshakeStep::Done(connack) => connack,
+            HandshakeStep::Challenge(_) => return Err(MqttError::Message("Unexpected AUTH challenge".to_string())),
+        };
+        if connack.reason_code.is_err() {
+            return Err(MqttError::Message(format!("Connection refused: {}", connack.reason_code)))
+        }
+        client.session.on_connack(&connack);
+        Ok(client)
+    }
+
+    pub fn send<P: Into<Packet>>(&mut self, packet: P) -> Result<(), MqttError> {
+        let packet = packet.into();
+        let packet_type = packet.packet_type();
+        let bytes = self.session.encode(packet)?;
+        self.transport.write_all(&bytes).map_err(|e| MqttError::Message(format!("Error sending: {}", e)))?;
+        self.session.on_packet_sent(packet_type, bytes.len());
+        Ok(())
+    }
+
+    /// Waits for the next packet, sending `PINGREQ` whenever the keep alive says so.
+    pub fn receive(&mut self) -> Result<Packet, MqttError> {
+        loop {
+            if let Some(result) = PacketIter::new(&self.inbound).next() {
+                let (packet, len) = result?;
+                self.inbound.drain(..len);
+                self.session.on_packet_received(packet.packet_type(), len);
+                return Ok(packet)
+            }
+
+            let now = self.session.clock().now();
+            for action in self.session.advance(now) {
+                if let DueAction::SendPingreq = action {
+                    self.send(Pingreq {})?;
+                }
+            }
+            let timeout = self.session.time_until_due(now).unwrap_or(READ_TIMEOUT);
+            self.transport.set_read_timeout(Some(timeout))
+                .map_err(|e| MqttError::Message(e.to_string()))?;
+
+            let mut buf = [0; 4096];
+            match self.transport.read(&mut buf) {
+                Ok(0) => return Err(MqttError::Message("Connection closed by the server".to_string())),
+                Ok(n) => self.inbound.extend_from_slice(&buf[..n]),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => (),
+                Err(e) => return Err(MqttError::Message(format!("Error receiving: {}", e))),
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! Encodes one packet of each family, decodes them back from a single buffer as if read from the network and prints
+//! what went over the wire.
+//!
+//! Run with `cargo run -p mqtt --example packets`.
+
+use mqtt::{
+    error::MqttError,
+    packet::{
+        Auth, Connack, Connect, Disconnect, LastWill, Packet, PacketIter, Pingreq, Pingresp, Puback, Pubcomp,
+        Publish, Pubrec, Pubrel, Suback, Subscribe, TopicFilter, Unsuback, Unsubscribe,
+    },
+    types::{QoS, ReasonCode, ValidationMode},
+};
+
+fn main() -> Result<(), MqttError> {
+    let mut buf = Vec::new();
+    for packet in packets()? {
+        let packet_type = packet.packet_type();
+        let encoded: Vec<u8> = packet.try_into()?;
+        println!("{:<12} {:>3} bytes: {}", format!("{:?}", packet_type), encoded.len(), hex(&encoded));
+        buf.extend_from_slice(&encoded);
+    }
+
+    // everything back-to-back in one buffer, the way it comes in from a stream
+    let mut decoded = Vec::new();
+    for result in PacketIter::new(&buf) {
+        let (packet, _) = result?;
+        decoded.push(packet);
+    }
+
+    assert_eq!(packets()?, decoded);
+    println!("Decoded all {} packets from {} bytes", decoded.len(), buf.len());
+    Ok(())
+}
+
+/// A packet of each type, grouped by the flows they belong to.
+fn packets() -> Result<Vec<Packet>, MqttError> {
+    // connecting, with a last will
+    let mut connect = Connect::with_client_id_str("example")?;
+    connect.keep_alive = 30;
+    connect.will = Some(LastWill::new("clients/example/status".into(), b"gone")?);
+    let connack = Connack { session_present: false, reason_code: ReasonCode::Success, properties: None };
+
+    // publishing with QoS 1 and 2
+    let publish = Publish::builder("sensors/kitchen/temperature")
+        .payload("21.5")
+        .qos(QoS::AtLeastOnce)
+        .packet_identifier(1)
+        .content_type("text/plain")
+        .build()?;
+
+    let exactly_once = Publish::builder("orders/new")
+        .payload(vec![1, 2, 3])
+        .qos(QoS::ExactlyOnce)
+        .packet_identifier(2)
+        .build()?;
+
+    // subscribing and unsubscribing
+    let mut filter = TopicFilter::validated("sensors/+/temperature".into(), ValidationMode::Strict)?;
+    filter.maximum_qos = QoS::AtLeastOnce;
+    let subscribe = Subscribe { packet_identifier: 3, properties: None, topic_filter: vec![filter] };
+    let suback = Suback { packet_identifier: 3, properties: None, reason_codes: vec![ReasonCode::GrantedQoS1] };
+    let unsubscribe = Unsubscribe::new(4, vec!["sensors/+/temperature".into()], ValidationMode::Strict)?;
+    let unsuback = Unsuback { packet_identifier: 4, properties: None, reason_codes: vec![ReasonCode::Success] };
+
+    Ok(vec![
+        connect.into(),
+        connack.into(),
+        publish.into(),
+        Puback::new(1, ReasonCode::Success)?.into(),
+        exactly_once.into(),
+        Pubrec::new(2, ReasonCode::Success)?.into(),
+        Pubrel::new(2, ReasonCode::Success)?.into(),
+        Pubcomp::new(2, ReasonCode::Success)?.into(),
+        subscribe.into(),
+        suback.into(),
+        unsubscribe.into(),
+        unsuback.into(),
+        Pingreq {}.into(),
+        Pingresp {}.into(),
+        Auth { reason_code: ReasonCode::ContinueAuthentication, properties: None }.into(),
+        Disconnect::default().into(),
+    ])
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ")
+}
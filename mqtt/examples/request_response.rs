@@ -0,0 +1,91 @@
+//! Request/response over MQTT 5: a responder answers requests on a topic, a requester sends one with a response topic
+//! and correlation data and waits for the matching answer. Both run on connections of their own.
+//!
+//! The response topic is based on the response information the server sends if asked to, with a fixed topic as
+//! fallback for servers that don't.
+//!
+//! Needs a broker, such as a local mosquitto or the `broker` example. Run with
+//! `cargo run -p mqtt --example request_response -- [host] [port]`, which defaults to `localhost 1883`.
+
+mod common;
+
+use std::{thread, time::{SystemTime, UNIX_EPOCH}};
+
+use mqtt::{
+    error::MqttError,
+    packet::{Disconnect, Packet, Publish, PublishProperties, Subscribe, TopicFilter},
+    session::SessionConfig,
+    types::ValidationMode,
+};
+
+use common::Client;
+
+const REQUEST_TOPIC: &str = "rust-mqtt/examples/requests";
+const FALLBACK_RESPONSE_TOPIC: &str = "rust-mqtt/examples/responses";
+
+fn main() -> Result<(), MqttError> {
+    let addr = common::broker_addr()?;
+
+    let mut responder = Client::connect(&addr, SessionConfig::default())?;
+    subscribe(&mut responder, REQUEST_TOPIC)?;
+    let responder = thread::spawn(move || respond(&mut responder));
+
+    let config = SessionConfig { request_response_information: true, ..Default::default() };
+    let mut requester = Client::connect(&addr, config)?;
+    let response_topic = requester.session.response_topic("example")
+        .unwrap_or_else(|| FALLBACK_RESPONSE_TOPIC.to_string());
+    subscribe(&mut requester, &response_topic)?;
+
+    let request = Publish::builder(REQUEST_TOPIC)
+        .payload("What's the time?")
+        .response_topic(response_topic.clone())
+        .correlation(b"request-1".to_vec())
+        .build()?;
+    println!("Requesting on {}, response expected on {}", REQUEST_TOPIC, response_topic);
+    requester.send(request)?;
+
+    loop {
+        if let Packet::Publish(response) = requester.receive()? {
+            let correlation_data = response.properties.as_ref().and_then(|p| p.correlation_data.as_deref());
+            if correlation_data == Some(b"request-1") {
+                println!("Response: {}", String::from_utf8_lossy(&response.payload));
+                break
+            }
+        }
+    }
+
+    requester.send(Disconnect::default())?;
+    responder.join().map_err(|_| MqttError::Message("Responder panicked".to_string()))?
+}
+
+/// Answers the first request, sending the response to where the requester asked for it along with its correlation
+/// data.
+fn respond(client: &mut Client) -> Result<(), MqttError> {
+    let request = loop {
+        if let Packet::Publish(publish) = client.receive()? {
+            break publish
+        }
+    };
+
+    let properties = request.properties.unwrap_or_default();
+    let response_topic = properties.response_topic
+        .ok_or_else(|| MqttError::Message("Request without response topic".to_string()))?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let answer = format!("{} seconds since the epoch", now.as_secs());
+    let mut response = Publish::with_topic_name(response_topic.try_into()?, answer);
+    response.properties = Some(PublishProperties {
+        correlation_data: properties.correlation_data,
+        ..Default::default()
+    });
+    client.send(response)?;
+    client.send(Disconnect::default())
+}
+
+fn subscribe(client: &mut Client, topic: &str) -> Result<(), MqttError> {
+    let topic_filter = vec![TopicFilter::validated(topic.into(), ValidationMode::Strict)?];
+    client.send(Subscribe { packet_identifier: 1, properties: None, topic_filter })?;
+    match client.receive()? {
+        Packet::Suback(suback) if suback.reason_codes.iter().all(|r| !r.is_err()) => Ok(()),
+        other => Err(MqttError::Message(format!("Subscribing to {} failed: {:?}", topic, other))),
+    }
+}
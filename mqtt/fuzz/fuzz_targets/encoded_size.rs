@@ -0,0 +1,21 @@
+//! Generates packets and checks the length math: what `encoded_size` works out has to be what encoding produces, and
+//! the encoding has to decode again. Off-by-one lengths, such as a remaining length crossing into another byte, are
+//! the errors that slip through hand-written tests most easily.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mqtt::{packet::Packet, test_util::to_hex};
+
+fuzz_target!(|packet: Packet| {
+    let (packet_type, size) = (packet.packet_type(), packet.encoded_size());
+    let Ok(encoded) = Vec::<u8>::try_from(packet) else {
+        return
+    };
+    if encoded.len() != size {
+        panic!("{:?} of {} bytes, encoded_size says {}: {}", packet_type, encoded.len(), size, to_hex(&encoded));
+    }
+    if let Err(e) = Packet::try_from(&encoded[..]) {
+        panic!("{} rejected: {}", to_hex(&encoded), e);
+    }
+});
@@ -0,0 +1,19 @@
+//! Generates packets instead of raw bytes, which reaches deeper into the decoder than mostly malformed input does.
+//! Everything we encode must decode again, and the encoding is what to feed another implementation's decoder.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mqtt::{packet::Packet, test_util::{check_round_trip, to_hex}};
+
+fuzz_target!(|packet: Packet| {
+    let Ok(encoded) = Vec::<u8>::try_from(packet) else {
+        return
+    };
+    if let Err(e) = Packet::try_from(&encoded[..]) {
+        panic!("{} rejected: {}", to_hex(&encoded), e);
+    }
+    if let Err(e) = check_round_trip(&encoded) {
+        panic!("{}", e);
+    }
+});
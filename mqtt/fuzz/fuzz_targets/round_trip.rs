@@ -0,0 +1,14 @@
+//! Feeds raw bytes to the decoder: it must not panic, and whatever it accepts has to encode again into an equivalent
+//! packet. To compare with another implementation, hand the same input to its decoder here and check that both accept
+//! or reject it alike.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mqtt::test_util::check_round_trip;
+
+fuzz_target!(|data: &[u8]| {
+    if let Err(e) = check_round_trip(data) {
+        panic!("{}", e);
+    }
+});
@@ -0,0 +1,257 @@
+//! Where to find a server, given as a URI such as `mqtt://broker.example.com` or `wss://broker.example.com:8084/mqtt`.
+//!
+//! The scheme says how to talk to the server and decides the port unless the URI has one:
+//!
+//! | Scheme | Transport | Default port |
+//! | ------ | --------- | ------------ |
+//! | `mqtt` | TCP | 1883 |
+//! | `mqtts` | TLS | 8883 |
+//! | `ws` | WebSocket | 80 |
+//! | `wss` | WebSocket over TLS | 443 |
+
+use std::{fmt::Display, str::FromStr};
+
+use crate::error::MqttError;
+
+/// The port registered with IANA for MQTT over TCP.
+pub const DEFAULT_PORT: u16 = 1883;
+
+/// The port registered with IANA for MQTT over TLS.
+pub const DEFAULT_TLS_PORT: u16 = 8883;
+
+/// How to talk to a server, as the scheme of its URI says.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scheme {
+    /// `mqtt://`, plain TCP.
+    Mqtt,
+
+    /// `mqtts://`, TCP secured by TLS.
+    Mqtts,
+
+    /// `ws://`, WebSocket.
+    Ws,
+
+    /// `wss://`, WebSocket secured by TLS.
+    Wss,
+}
+
+impl Scheme {
+
+    /// The port to use if the URI doesn't have one. WebSockets use the HTTP ports, as for any other `ws` URI.
+    pub fn default_port(&self) -> u16 {
+        match self {
+            Scheme::Mqtt => DEFAULT_PORT,
+            Scheme::Mqtts => DEFAULT_TLS_PORT,
+            Scheme::Ws => 80,
+            Scheme::Wss => 443,
+        }
+    }
+
+    pub fn is_tls(&self) -> bool {
+        matches!(self, Scheme::Mqtts | Scheme::Wss)
+    }
+
+    pub fn is_websocket(&self) -> bool {
+        matches!(self, Scheme::Ws | Scheme::Wss)
+    }
+}
+
+impl FromStr for Scheme {
+    type Err = MqttError;
+
+    /// Case insensitive, as URI schemes are.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "mqtt" => Ok(Scheme::Mqtt),
+            "mqtts" => Ok(Scheme::Mqtts),
+            "ws" => Ok(Scheme::Ws),
+            "wss" => Ok(Scheme::Wss),
+            _ => Err(MqttError::Message(format!("Unsupported URI scheme: {}", s))),
+        }
+    }
+}
+
+impl Display for Scheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Scheme::Mqtt => "mqtt",
+            Scheme::Mqtts => "mqtts",
+            Scheme::Ws => "ws",
+            Scheme::Wss => "wss",
+        };
+        f.write_str(s)
+    }
+}
+
+/// A server address parsed from a URI: `scheme://host[:port][/path]`. IPv6 addresses go in square brackets, such as
+/// `mqtt://[::1]:1883`.
+///
+/// The path only means something to WebSockets, where it's part of the HTTP request. Brokers commonly expect `/mqtt`
+/// there, so the URI must say so. `mqtt` and `mqtts` URIs can't have a path.
+///
+/// # Examples
+/// ```
+/// use mqtt::addr::{BrokerAddr, Scheme};
+///
+/// let addr: BrokerAddr = "mqtts://broker.example.com".parse().unwrap();
+/// assert_eq!(Scheme::Mqtts, addr.scheme);
+/// assert!(addr.is_tls());
+/// assert_eq!(("broker.example.com", 8883), addr.socket_addr());
+///
+/// let addr: BrokerAddr = "ws://localhost:9001/mqtt".parse().unwrap();
+/// assert!(addr.is_websocket());
+/// assert_eq!(9001, addr.port);
+/// assert_eq!("/mqtt", addr.path);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BrokerAddr {
+    pub scheme: Scheme,
+
+    /// Host name or IP address, without brackets for IPv6.
+    pub host: String,
+
+    /// The port from the URI, or the [default](Scheme::default_port) of the scheme.
+    pub port: u16,
+
+    /// The path of a WebSocket URI, `/` if it has none. Always empty for the other schemes.
+    pub path: String,
+}
+
+impl BrokerAddr {
+
+    pub fn is_tls(&self) -> bool {
+        self.scheme.is_tls()
+    }
+
+    pub fn is_websocket(&self) -> bool {
+        self.scheme.is_websocket()
+    }
+
+    /// Host and port, as taken by [TcpStream::connect](std::net::TcpStream::connect).
+    pub fn socket_addr(&self) -> (&str, u16) {
+        (&self.host, self.port)
+    }
+}
+
+impl FromStr for BrokerAddr {
+    type Err = MqttError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| MqttError::Message(format!("Invalid URI {}: {}", s, reason));
+
+        let (scheme, rest) = s.split_once("://").ok_or_else(|| invalid("no scheme"))?;
+        let scheme = Scheme::from_str(scheme)?;
+
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, ""),
+        };
+        let path = match (scheme.is_websocket(), path) {
+            (true, "") => "/",
+            (true, path) => path,
+            (false, "" | "/") => "",
+            (false, _) => return Err(invalid("only WebSocket URIs can have a path")),
+        };
+
+        let (host, port) = match authority.strip_prefix('[') {
+            // bracketed IPv6 address
+            Some(rest) => match rest.split_once(']') {
+                Some((host, "")) => (host, None),
+                Some((host, port)) => (host, Some(port.strip_prefix(':').ok_or_else(|| invalid("bad port"))?)),
+                None => return Err(invalid("unclosed bracket")),
+            },
+            None => match authority.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+
+        if host.is_empty() {
+            return Err(invalid("no host"))
+        }
+        if host.contains(|c: char| c.is_whitespace() || c == '@') {
+            return Err(invalid("bad host"))
+        }
+        let port = match port {
+            Some(p) => p.parse::<u16>().map_err(|_| invalid("bad port"))?,
+            None => scheme.default_port(),
+        };
+
+        Ok(Self { scheme, host: host.to_string(), port, path: path.to_string() })
+    }
+}
+
+impl Display for BrokerAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.host.contains(':') {
+            true => write!(f, "{}://[{}]:{}{}", self.scheme, self.host, self.port, self.path),
+            false => write!(f, "{}://{}:{}{}", self.scheme, self.host, self.port, self.path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<BrokerAddr, MqttError> {
+        s.parse()
+    }
+
+    #[test]
+    fn default_ports() {
+        assert_eq!(("localhost", 1883), parse("mqtt://localhost").unwrap().socket_addr());
+        assert_eq!(("localhost", 8883), parse("mqtts://localhost").unwrap().socket_addr());
+        assert_eq!(("localhost", 80), parse("ws://localhost").unwrap().socket_addr());
+        assert_eq!(("localhost", 443), parse("wss://localhost").unwrap().socket_addr());
+        assert_eq!(("localhost", 1884), parse("MQTT://localhost:1884/").unwrap().socket_addr());
+    }
+
+    #[test]
+    fn settings() {
+        let addr = parse("wss://broker.example.com:8084/mqtt").unwrap();
+        assert_eq!(
+            BrokerAddr { scheme: Scheme::Wss, host: "broker.example.com".into(), port: 8084, path: "/mqtt".into() },
+            addr);
+        assert!(addr.is_tls() && addr.is_websocket());
+
+        let addr = parse("mqtt://10.0.0.1").unwrap();
+        assert!(!addr.is_tls() && !addr.is_websocket());
+        assert_eq!("", addr.path);
+        assert_eq!("/", parse("ws://localhost").unwrap().path);
+    }
+
+    #[test]
+    fn ipv6() {
+        let addr = parse("mqtt://[fe80::9610:3eff:fe1c]:1884").unwrap();
+        assert_eq!(("fe80::9610:3eff:fe1c", 1884), addr.socket_addr());
+        assert_eq!(("::1", 8883), parse("mqtts://[::1]").unwrap().socket_addr());
+    }
+
+    #[test]
+    fn display() {
+        for s in ["mqtt://localhost:1883", "mqtts://[::1]:8883", "ws://localhost:80/mqtt"] {
+            assert_eq!(s, parse(s).unwrap().to_string());
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        for s in [
+            "localhost",
+            "localhost:1883",
+            "http://localhost",
+            "mqtt://",
+            "mqtt://:1883",
+            "mqtt://localhost:port",
+            "mqtt://localhost:70000",
+            "mqtt://localhost/topic",
+            "mqtt://user@localhost",
+            "mqtt://[::1",
+            "mqtt://::1",
+            "mqtt://[::1]1883",
+        ] {
+            assert!(parse(s).is_err(), "{}", s);
+        }
+    }
+}
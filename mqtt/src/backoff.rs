@@ -0,0 +1,187 @@
+//! How long to wait before trying again after connecting to a server failed.
+//!
+//! A [Backoff] grows the delay exponentially with *decorrelated jitter*: each delay is picked at random between the
+//! initial delay and three times the previous one, capped at a maximum. Clients that lost their server at the same
+//! time thus spread out instead of all coming back at once, without the delays ever collapsing to the initial one.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+/// The limits of a [Backoff].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackoffPolicy {
+    /// The shortest delay, also the first one. Defaults to 1 second.
+    pub initial: Duration,
+
+    /// The longest delay, no matter how many attempts failed before. Defaults to 1 minute.
+    pub maximum: Duration,
+
+    /// How many delays [Backoff::next_delay] hands out before giving up, until it's [reset](Backoff::reset). Unlimited
+    /// by default.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for BackoffPolicy {
+    fn default() -> Self {
+        Self { initial: Duration::from_secs(1), maximum: Duration::from_secs(60), max_attempts: None }
+    }
+}
+
+/// The delays between attempts, see the [module documentation](self).
+///
+/// # Examples
+/// ```
+/// use std::time::Duration;
+/// use mqtt::backoff::{Backoff, BackoffPolicy};
+///
+/// let policy = BackoffPolicy { max_attempts: Some(2), ..Default::default() };
+/// let mut backoff = Backoff::new(policy);
+/// assert_eq!(Some(Duration::from_secs(1)), backoff.next_delay());
+/// assert!(backoff.next_delay().is_some_and(|delay| delay <= Duration::from_secs(3)));
+/// assert_eq!(None, backoff.next_delay());
+///
+/// // connected at last
+/// backoff.reset();
+/// assert_eq!(Some(Duration::from_secs(1)), backoff.next_delay());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    policy: BackoffPolicy,
+    attempts: u32,
+    previous: Duration,
+    random: SplitMix64,
+}
+
+impl Backoff {
+
+    /// Jitters with a random seed, so that clients started at the same time don't wait in lockstep.
+    pub fn new(policy: BackoffPolicy) -> Self {
+        Self::with_seed(policy, RandomState::new().build_hasher().finish())
+    }
+
+    /// Jitters with the given seed: the same seed always gives the same delays, as tests need them.
+    pub fn with_seed(policy: BackoffPolicy, seed: u64) -> Self {
+        Self { policy, attempts: 0, previous: policy.initial, random: SplitMix64(seed) }
+    }
+
+    /// How long to wait before the next attempt, `None` once [max_attempts](BackoffPolicy::max_attempts) are used up.
+    /// The first delay is always the initial one.
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if self.policy.max_attempts.is_some_and(|max| self.attempts >= max) {
+            return None
+        }
+        self.attempts += 1;
+
+        let delay = match self.attempts {
+            1 => self.policy.initial,
+            _ => self.random.between(self.policy.initial, self.previous.saturating_mul(3)),
+        };
+        self.previous = delay.clamp(self.policy.initial.min(self.policy.maximum), self.policy.maximum);
+        Some(self.previous)
+    }
+
+    /// Starts over with the initial delay and all attempts available, to be called once an attempt succeeded.
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+        self.previous = self.policy.initial;
+    }
+
+    /// How many delays were handed out since the last [reset](Backoff::reset).
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub fn policy(&self) -> &BackoffPolicy {
+        &self.policy
+    }
+}
+
+/// Good enough randomness for jitter, without depending on a crate for it.
+#[derive(Debug, Clone)]
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Anywhere from `low` to `high`, both included, in whole milliseconds. `low` if `high` is lower.
+    fn between(&mut self, low: Duration, high: Duration) -> Duration {
+        let low = low.as_millis() as u64;
+        let high = (high.as_millis() as u64).max(low);
+        match high - low {
+            u64::MAX => Duration::from_millis(self.next()),
+            range => Duration::from_millis(low + self.next() % (range + 1)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(max_attempts: Option<u32>) -> BackoffPolicy {
+        BackoffPolicy { initial: Duration::from_millis(100), maximum: Duration::from_secs(10), max_attempts }
+    }
+
+    #[test]
+    fn decorrelated_jitter() {
+        let mut backoff = Backoff::with_seed(policy(None), 7);
+        let mut previous = backoff.next_delay().unwrap();
+        assert_eq!(Duration::from_millis(100), previous);
+
+        for _ in 0..100 {
+            let delay = backoff.next_delay().unwrap();
+            assert!(delay >= Duration::from_millis(100), "{:?} below the initial delay", delay);
+            assert!(delay <= (previous * 3).min(Duration::from_secs(10)), "{:?} after {:?}", delay, previous);
+            previous = delay;
+        }
+        assert_eq!(101, backoff.attempts());
+    }
+
+    #[test]
+    fn reaches_maximum() {
+        let mut backoff = Backoff::with_seed(policy(None), 1);
+        let delays: Vec<_> = std::iter::from_fn(|| backoff.next_delay()).take(200).collect();
+        assert!(delays.contains(&Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn same_seed_same_delays() {
+        let delays = |seed| {
+            let mut backoff = Backoff::with_seed(policy(Some(10)), seed);
+            std::iter::from_fn(|| backoff.next_delay()).collect::<Vec<_>>()
+        };
+        assert_eq!(10, delays(42).len());
+        assert_eq!(delays(42), delays(42));
+        assert_ne!(delays(42), delays(43));
+    }
+
+    #[test]
+    fn max_attempts_and_reset() {
+        let mut backoff = Backoff::with_seed(policy(Some(3)), 0);
+        assert_eq!(3, std::iter::from_fn(|| backoff.next_delay()).count());
+        assert_eq!(None, backoff.next_delay());
+        assert_eq!(3, backoff.attempts());
+
+        backoff.reset();
+        assert_eq!(0, backoff.attempts());
+        assert_eq!(Some(Duration::from_millis(100)), backoff.next_delay());
+    }
+
+    #[test]
+    fn initial_above_maximum() {
+        let policy = BackoffPolicy { initial: Duration::from_secs(5), maximum: Duration::from_secs(1), max_attempts: None };
+        let mut backoff = Backoff::with_seed(policy, 0);
+        assert_eq!(Some(Duration::from_secs(1)), backoff.next_delay());
+        assert_eq!(Some(Duration::from_secs(1)), backoff.next_delay());
+    }
+}
@@ -0,0 +1,28 @@
+//! Big-endian integers as used throughout the protocol. Moved to [wire] along with the other primitives
+//! of the binary format, these are kept for compatibility.
+
+use crate::{error::MqttError, wire};
+
+/// Converts `val` into two Big-Endian bytes and appends them to `vec`.
+#[deprecated(note = "use mqtt::wire::push_be_u16")]
+pub fn push_be_u16(val: u16, vec: &mut Vec<u8>) {
+    wire::push_be_u16(val, vec)
+}
+
+/// Converts `val` into four Big-Endian bytes and appends them to `vec`.
+#[deprecated(note = "use mqtt::wire::push_be_u32")]
+pub fn push_be_u32(val: u32, vec: &mut Vec<u8>) {
+    wire::push_be_u32(val, vec)
+}
+
+/// Converts the first two bytes of the slice into a big-endian u16, any further bytes are ignored.
+#[deprecated(note = "use mqtt::wire::u16_from_be_bytes")]
+pub fn u16_from_be_bytes(src: &[u8]) -> Result<u16, MqttError> {
+    wire::u16_from_be_bytes(src)
+}
+
+/// Converts the first four bytes of the slice into a big-endian u32, any further bytes are ignored.
+#[deprecated(note = "use mqtt::wire::u32_from_be_bytes")]
+pub fn u32_from_be_bytes(src: &[u8]) -> Result<u32, MqttError> {
+    wire::u32_from_be_bytes(src)
+}
@@ -0,0 +1,6 @@
+//! Helpers for working with the binary representation. The primitives of the format itself are in
+//! [wire](crate::wire).
+
+pub mod bytes;
+pub mod pool;
+pub mod ring;
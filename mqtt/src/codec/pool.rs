@@ -0,0 +1,113 @@
+//! Reuse of encoding buffers, for publishers sending many packets in a tight loop.
+
+use crate::{error::MqttError, packet::{encode_batch, Packet}};
+
+/// Buffers to [encode batches](encode_batch) of packets into, handed out and taken back again so that their memory is
+/// reused instead of allocated and grown anew for every batch.
+///
+/// The pool is plain state owned by whoever sends, usually next to the connection. It doesn't lock, keep one per
+/// thread or wrap it in a mutex to share it.
+///
+/// Only the buffer the packets are collected in comes from the pool, each packet is still encoded on its own first.
+/// Sending single packets gains nothing from it.
+///
+/// # Examples
+/// ```
+/// use mqtt::codec::pool::BufferPool;
+/// use mqtt::packet::{Packet, Publish};
+///
+/// let mut pool = BufferPool::new(4);
+/// for i in 0..10 {
+///     let burst = (0..100).map(|j: u8| Packet::from(Publish::with_topic_name("a/b".try_into().unwrap(), vec![i, j])));
+///     let buf = pool.encode_batch(burst).unwrap();
+///     assert_eq!(100 * 10, buf.len());
+///     // write buf to the connection, then hand it back
+///     pool.put(buf);
+/// }
+/// assert_eq!(1, pool.len());
+/// ```
+#[derive(Debug)]
+pub struct BufferPool {
+    buffers: Vec<Vec<u8>>,
+    capacity: usize,
+}
+
+impl BufferPool {
+
+    /// Creates an empty pool that keeps at most `capacity` buffers.
+    pub fn new(capacity: usize) -> Self {
+        Self { buffers: Vec::with_capacity(capacity), capacity }
+    }
+
+    /// An empty buffer, from the pool if it has one.
+    pub fn take(&mut self) -> Vec<u8> {
+        self.buffers.pop().unwrap_or_default()
+    }
+
+    /// Clears `buf` and keeps it for the next [take](Self::take), unless the pool is full already.
+    pub fn put(&mut self, mut buf: Vec<u8>) {
+        if self.buffers.len() < self.capacity {
+            buf.clear();
+            self.buffers.push(buf);
+        }
+    }
+
+    /// Encodes all `packets` into a buffer from the pool, see [encode_batch]. If any of them can't be encoded, the
+    /// buffer goes back into the pool.
+    pub fn encode_batch<I: IntoIterator<Item = Packet>>(&mut self, packets: I) -> Result<Vec<u8>, MqttError> {
+        let mut buf = self.take();
+        match encode_batch(packets, &mut buf) {
+            Ok(()) => Ok(buf),
+            Err(e) => {
+                self.put(buf);
+                Err(e)
+            },
+        }
+    }
+
+    /// The number of buffers in the pool.
+    pub fn len(&self) -> usize {
+        self.buffers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffers.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packet::Publish;
+
+    use super::*;
+
+    #[test]
+    fn reuses_memory() {
+        let mut pool = BufferPool::new(1);
+        let buf = pool.encode_batch([Publish::new_unchecked("a/b".into(), vec![0; 100]).into()]).unwrap();
+        let capacity = buf.capacity();
+        pool.put(buf);
+
+        let buf = pool.take();
+        assert!(buf.is_empty());
+        assert_eq!(capacity, buf.capacity());
+        assert!(pool.is_empty());
+    }
+
+    #[test]
+    fn keeps_at_most_capacity() {
+        let mut pool = BufferPool::new(2);
+        for _ in 0..3 {
+            pool.put(Vec::with_capacity(8));
+        }
+        assert_eq!(2, pool.len());
+    }
+
+    #[test]
+    fn encode_error_returns_buffer() {
+        let mut pool = BufferPool::new(1);
+        let too_long = Publish::new_unchecked("a".repeat(usize::from(u16::MAX) + 1), vec![]);
+        assert!(pool.encode_batch([too_long.into()]).is_err());
+        assert_eq!(1, pool.len());
+    }
+}
@@ -0,0 +1,332 @@
+//! Decoding packets straight from a ring buffer, whose contents wrap around its end into a second slice.
+//!
+//! Buffers that are contiguous already, such as `Vec<u8>` or `bytes::Bytes`, are decoded as slices with
+//! [PacketIter](crate::packet::PacketIter).
+
+use std::collections::VecDeque;
+
+use crate::{error::MqttError, packet::Packet, wire};
+
+/// The bytes of a ring buffer as the two slices they are split into, the first one holding the older bytes. Either may
+/// be empty, such as the second one as long as the contents don't wrap around.
+///
+/// Reading only crosses from one slice into the other where a value happens to, without copying both into one buffer
+/// first.
+///
+/// # Examples
+/// ```
+/// use std::collections::VecDeque;
+/// use mqtt::codec::ring::RingSlices;
+///
+/// // pushing to the front of the buffer wraps around to its end
+/// let mut ring = VecDeque::from([3, 4]);
+/// ring.push_front(2);
+/// ring.push_front(1);
+///
+/// let mut slices = RingSlices::from(&ring);
+/// assert_eq!(4, slices.len());
+/// assert_eq!(Some(3), slices.get(2));
+/// slices.advance(1);
+/// assert_eq!(None, slices.contiguous(3));
+/// assert_eq!(vec![2, 3, 4], slices.to_vec(3));
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RingSlices<'a> {
+    first: &'a [u8],
+    second: &'a [u8],
+}
+
+impl<'a> RingSlices<'a> {
+
+    pub fn new(first: &'a [u8], second: &'a [u8]) -> Self {
+        Self { first, second }
+    }
+
+    pub fn len(&self) -> usize {
+        self.first.len() + self.second.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.first.is_empty() && self.second.is_empty()
+    }
+
+    /// The byte at `index`, counting across both slices.
+    pub fn get(&self, index: usize) -> Option<u8> {
+        match index.checked_sub(self.first.len()) {
+            None => Some(self.first[index]),
+            Some(index) => self.second.get(index).copied(),
+        }
+    }
+
+    /// Skips the first `len` bytes, or all of them if there aren't that many.
+    pub fn advance(&mut self, len: usize) {
+        match len.checked_sub(self.first.len()) {
+            None => self.first = &self.first[len..],
+            Some(rest) => {
+                self.first = &self.second[rest.min(self.second.len())..];
+                self.second = &[];
+            },
+        }
+    }
+
+    /// The first `len` bytes as one slice, if they don't cross from the first slice into the second.
+    pub fn contiguous(&self, len: usize) -> Option<&'a [u8]> {
+        match self.first.is_empty() {
+            true => self.second.get(..len),
+            false => self.first.get(..len),
+        }
+    }
+
+    /// Appends the first `len` bytes to `buf`, as many as there are if fewer.
+    pub fn copy_to(&self, len: usize, buf: &mut Vec<u8>) {
+        let from_first = len.min(self.first.len());
+        buf.extend_from_slice(&self.first[..from_first]);
+        buf.extend_from_slice(&self.second[..(len - from_first).min(self.second.len())]);
+    }
+
+    /// The first `len` bytes copied into a new vector, as many as there are if fewer.
+    pub fn to_vec(&self, len: usize) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(len);
+        self.copy_to(len, &mut buf);
+        buf
+    }
+
+    /// Like [wire::packet_len], the total length of the packet at the beginning, or `None` if it's incomplete. Only
+    /// the fixed header is read, which is at most five bytes.
+    pub fn packet_len(&self) -> Result<Option<usize>, MqttError> {
+        let (header, header_len) = self.fixed_header();
+        Ok(wire::announced_packet_len(&header[..header_len])?.filter(|total| self.len() >= *total))
+    }
+
+    /// Like [wire::check_packet_len], fails if the fixed header at the beginning announces a packet of more than
+    /// `maximum` bytes.
+    pub fn check_packet_len(&self, maximum: u32) -> Result<(), MqttError> {
+        let (header, header_len) = self.fixed_header();
+        wire::check_packet_len(&header[..header_len], maximum)
+    }
+
+    /// The first five bytes, as many as there are if fewer, which is all a fixed header can take up.
+    fn fixed_header(&self) -> ([u8; 5], usize) {
+        let mut header = [0; 5];
+        let header_len = self.len().min(header.len());
+        for (index, byte) in header[..header_len].iter_mut().enumerate() {
+            *byte = self.get(index).unwrap_or_default();
+        }
+        (header, header_len)
+    }
+}
+
+impl<'a> From<&'a VecDeque<u8>> for RingSlices<'a> {
+    fn from(ring: &'a VecDeque<u8>) -> Self {
+        let (first, second) = ring.as_slices();
+        Self::new(first, second)
+    }
+}
+
+impl<'a> From<(&'a [u8], &'a [u8])> for RingSlices<'a> {
+    fn from((first, second): (&'a [u8], &'a [u8])) -> Self {
+        Self::new(first, second)
+    }
+}
+
+/// Decodes packets lying back-to-back in a ring buffer, like [PacketIter](crate::packet::PacketIter) does for a
+/// single slice.
+///
+/// A packet that lies within one of the slices is decoded from it directly. Only one that wraps around from the end of
+/// the buffer to its start is copied into a scratch buffer first, which is kept for the next such packet. Once
+/// iteration ends, the [consumed](RingPacketIter::consumed) bytes can be removed from the ring buffer, leaving the
+/// incomplete packet at the end (if any) to be completed by the next read.
+///
+/// # Examples
+/// ```
+/// use std::collections::VecDeque;
+/// use mqtt::codec::ring::RingPacketIter;
+/// use mqtt::packet::Packet;
+///
+/// // a PINGRESP, a PUBACK wrapping around the end of the buffer and the first byte of another PINGRESP
+/// let mut ring = VecDeque::from([2, 0, 1, 0b11010000]);
+/// for byte in [0b01000000, 0, 0b11010000] {
+///     ring.push_front(byte);
+/// }
+///
+/// let mut iter = RingPacketIter::new(&ring);
+/// assert!(matches!(iter.next(), Some(Ok((Packet::Pingresp(_), 2)))));
+/// assert!(matches!(iter.next(), Some(Ok((Packet::Puback(_), 4)))));
+/// assert!(iter.next().is_none());
+/// assert!(iter.is_partial());
+///
+/// let consumed = iter.consumed();
+/// ring.drain(..consumed);
+/// assert_eq!(1, ring.len());
+/// ```
+#[derive(Debug)]
+pub struct RingPacketIter<'a> {
+    src: RingSlices<'a>,
+    consumed: usize,
+    failed: bool,
+    maximum_packet_size: Option<u32>,
+    /// where a packet that wraps around is put together
+    scratch: Vec<u8>,
+}
+
+impl<'a> RingPacketIter<'a> {
+
+    /// Creates an iterator over all packets in `src`, which may also be [RingSlices].
+    pub fn new(src: impl Into<RingSlices<'a>>) -> Self {
+        Self { src: src.into(), consumed: 0, failed: false, maximum_packet_size: None, scratch: Vec::new() }
+    }
+
+    /// Rejects packets of more than `maximum` bytes as soon as their fixed header is there, like
+    /// [PacketIter::maximum_packet_size](crate::packet::PacketIter::maximum_packet_size).
+    pub fn maximum_packet_size(self, maximum: Option<u32>) -> Self {
+        Self { maximum_packet_size: maximum, ..self }
+    }
+
+    /// The number of bytes taken up by the packets returned so far, including those that failed to decode.
+    pub fn consumed(&self) -> usize {
+        self.consumed
+    }
+
+    /// The number of bytes not yet consumed.
+    pub fn remaining(&self) -> usize {
+        self.src.len()
+    }
+
+    /// Whether the remaining bytes are the beginning of a packet that has not been fully received yet.
+    pub fn is_partial(&self) -> bool {
+        !self.failed && !self.src.is_empty() && matches!(self.src.packet_len(), Ok(None))
+    }
+}
+
+impl Iterator for RingPacketIter<'_> {
+    type Item = Result<(Packet, usize), MqttError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.src.is_empty() {
+            return None
+        }
+
+        if let Some(maximum) = self.maximum_packet_size {
+            if let Err(e) = self.src.check_packet_len(maximum) {
+                self.failed = true;
+                return Some(Err(e))
+            }
+        }
+
+        let len = match self.src.packet_len() {
+            Ok(Some(len)) => len,
+            Ok(None) => return None,
+            Err(e) => {
+                self.failed = true;
+                return Some(Err(e))
+            },
+        };
+
+        let decoded = match self.src.contiguous(len) {
+            Some(packet) => Packet::try_from(packet),
+            None => {
+                self.scratch.clear();
+                self.src.copy_to(len, &mut self.scratch);
+                Packet::try_from(&self.scratch[..])
+            },
+        };
+        self.src.advance(len);
+        self.consumed += len;
+
+        Some(decoded.map(|packet| (packet, len)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packet::{PacketIter, PacketType, Puback, Publish};
+
+    use super::*;
+
+    /// The encoded packets split at every possible point, as a ring buffer may hold them.
+    fn splits(buf: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+        (0..=buf.len()).map(|at| buf.split_at(at))
+    }
+
+    fn encoded() -> Vec<u8> {
+        let mut buf = Vec::new();
+        for packet in [
+            Packet::from(Publish::new_unchecked("a/b".into(), vec![1; 200])),
+            Packet::from(Puback::new(7, crate::types::ReasonCode::Success).unwrap()),
+            Packet::from(Publish::new_unchecked("c".into(), "hello")),
+        ] {
+            buf.append(&mut packet.try_into().unwrap());
+        }
+        buf
+    }
+
+    #[test]
+    fn same_as_contiguous() {
+        let buf = encoded();
+        let expected: Vec<(Packet, usize)> = PacketIter::new(&buf).map(Result::unwrap).collect();
+        for (first, second) in splits(&buf) {
+            let mut iter = RingPacketIter::new((first, second));
+            let decoded: Vec<(Packet, usize)> = iter.by_ref().map(Result::unwrap).collect();
+            assert_eq!(expected, decoded, "split after {}", first.len());
+            assert_eq!(buf.len(), iter.consumed());
+            assert!(!iter.is_partial());
+        }
+    }
+
+    #[test]
+    fn partial() {
+        let buf = encoded();
+        let complete: usize = PacketIter::new(&buf).take(2).map(|p| p.unwrap().1).sum();
+        let incomplete = &buf[..buf.len() - 1];
+        for (first, second) in splits(incomplete) {
+            let mut iter = RingPacketIter::new((first, second));
+            assert_eq!(2, iter.by_ref().count());
+            assert!(iter.is_partial(), "split after {}", first.len());
+            assert_eq!(complete, iter.consumed());
+            assert_eq!(incomplete.len() - complete, iter.remaining());
+        }
+    }
+
+    #[test]
+    fn broken_fixed_header() {
+        let buf = [0b11010000, 0, 0x30, 0xFF, 0xFF, 0xFF, 0xFF, 0x01];
+        for (first, second) in splits(&buf) {
+            let mut iter = RingPacketIter::new((first, second));
+            assert!(matches!(iter.next(), Some(Ok((Packet::Pingresp(_), 2)))));
+            assert!(matches!(iter.next(), Some(Err(MqttError::MalformedPacket(_)))));
+            assert!(iter.next().is_none());
+            assert!(!iter.is_partial());
+        }
+    }
+
+    #[test]
+    fn maximum_packet_size() {
+        let buf = encoded();
+        let size = PacketIter::new(&buf).next().unwrap().unwrap().1;
+        let too_large = MqttError::PacketTooLarge { packet_type: PacketType::PUBLISH, size, maximum: 100 };
+        // from just the fixed header of the first PUBLISH to all of it
+        for end in [3, 50, buf.len()] {
+            for (first, second) in splits(&buf[..end]) {
+                let mut iter = RingPacketIter::new((first, second)).maximum_packet_size(Some(100));
+                assert_eq!(Some(Err(too_large.clone())), iter.next().map(|r| r.map(|_| ())));
+                assert!(iter.next().is_none());
+                assert!(!iter.is_partial());
+                assert_eq!(0, iter.consumed());
+            }
+        }
+    }
+
+    #[test]
+    fn slices() {
+        let mut slices = RingSlices::new(&[1, 2], &[3, 4, 5]);
+        assert_eq!(Some(&[1, 2][..]), slices.contiguous(2));
+        assert_eq!(None, slices.contiguous(3));
+        assert_eq!(None, slices.get(5));
+
+        slices.advance(3);
+        assert_eq!(Some(&[4, 5][..]), slices.contiguous(2));
+        assert_eq!(vec![4, 5], slices.to_vec(10));
+        slices.advance(10);
+        assert!(slices.is_empty());
+    }
+}
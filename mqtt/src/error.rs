@@ -2,12 +2,34 @@
 
 use std::fmt::{self, Display};
 
-use crate::packet::PacketType;
+use crate::{
+    packet::{Connack, ConnackProperties, Disconnect, DisconnectProperties, PacketType},
+    types::ReasonCode,
+};
 
 /// Custom error types.
 /// 
-/// TODO: both malformed packet and protocol error should contain `reason codes`!
+/// Errors map to the reason code to report to the other side of a connection, see [MqttError::reason_code].
+///
+/// More specific errors will be added over time, so matches outside this crate need a wildcard arm. Falling back to
+/// [MqttError::reason_code] or the [Display] output there keeps them meaningful for variants added later.
+///
+/// # Examples
+/// ```
+/// use mqtt::error::MqttError;
+///
+/// fn describe(error: &MqttError) -> String {
+///     match error {
+///         MqttError::MalformedPacket(msg) => format!("garbage on the wire: {}", msg),
+///         MqttError::ProtocolError(msg) => format!("the other side broke the rules: {}", msg),
+///         other => format!("{} ({})", other, other.reason_code()),
+///     }
+/// }
+///
+/// assert_eq!("garbage on the wire: too short", describe(&MqttError::MalformedPacket("too short".into())));
+/// ```
 #[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
 pub enum MqttError {
     
     /// Syntactical error indicating that a control packet could not be fully parsed.
@@ -20,14 +42,87 @@ pub enum MqttError {
 
     /// A general-use error in cases where none of the more specific ones fit.
     Message(String),
+
+    /// A string that can't be encoded because it's longer than the 65,535 bytes its length prefix allows.
+    /// See MQTT spec `1.5.4`.
+    StringTooLong { len: usize },
+
+    /// Binary data that can't be encoded because it's longer than the 65,535 bytes its length prefix allows.
+    /// See MQTT spec `1.5.6`.
+    BinaryDataTooLong { len: usize },
+
+    /// A payload that doesn't match its payload format indicator, i.e. isn't valid UTF-8 although it claims to be.
+    /// See MQTT spec `3.1.3.2.3` and `3.3.2.3.2`.
+    PayloadFormatInvalid(String),
+
+    /// A packet that can't be sent because it's larger than the maximum packet size the other side announced.
+    /// See MQTT spec `3.1.2.11.4` and `3.2.2.3.6`.
+    PacketTooLarge { packet_type: PacketType, size: usize, maximum: u32 },
+
+    /// A topic name that is well-formed, but not allowed, such as one containing wildcard characters.
+    /// See MQTT spec `3.3.2.1`.
+    TopicNameInvalid(String),
+
+    /// A topic alias that is `0`, exceeds the maximum announced by the receiver or isn't mapped to a topic name yet.
+    /// See MQTT spec `3.3.2.3.4`.
+    TopicAliasInvalid(String),
 }
 
 impl MqttError {
+
+    /// The reason code to close a connection with because of this error:
+    /// [MalformedPacket](ReasonCode::MalformedPacket), [ProtocolError](ReasonCode::ProtocolError),
+    /// [PayloadFormatInvalid](ReasonCode::PayloadFormatInvalid), [PacketTooLarge](ReasonCode::PacketTooLarge),
+    /// [TopicNameInvalid](ReasonCode::TopicNameInvalid), [TopicAliasInvalid](ReasonCode::TopicAliasInvalid) or
+    /// [UnspecifiedError](ReasonCode::UnspecifiedError) for anything else.
+    pub fn reason_code(&self) -> ReasonCode {
+        match self {
+            MqttError::MalformedPacket(_) => ReasonCode::MalformedPacket,
+            MqttError::ProtocolError(_) => ReasonCode::ProtocolError,
+            MqttError::PayloadFormatInvalid(_) => ReasonCode::PayloadFormatInvalid,
+            MqttError::PacketTooLarge { .. } => ReasonCode::PacketTooLarge,
+            MqttError::TopicNameInvalid(_) => ReasonCode::TopicNameInvalid,
+            MqttError::TopicAliasInvalid(_) => ReasonCode::TopicAliasInvalid,
+            MqttError::Message(_) |
+            MqttError::StringTooLong { .. } |
+            MqttError::BinaryDataTooLong { .. } => ReasonCode::UnspecifiedError,
+        }
+    }
+
+    /// A `DISCONNECT` to close the connection because of this error, with the [reason code](MqttError::reason_code)
+    /// and the error message as reason string.
+    /// 
+    /// # Examples
+    /// ```
+    /// use mqtt::{error::MqttError, types::{QoS, ReasonCode}};
+    /// 
+    /// let err = QoS::try_from(3).unwrap_err();
+    /// let disconnect = err.to_disconnect();
+    /// assert_eq!(ReasonCode::MalformedPacket, disconnect.reason_code);
+    /// ```
+    pub fn to_disconnect(&self) -> Disconnect {
+        let properties = DisconnectProperties { reason_string: Some(self.to_string()), ..Default::default() };
+        Disconnect { reason_code: self.reason_code(), properties: Some(properties) }
+    }
+
+    /// A `CONNACK` refusing a connection because of this error, for servers that could not process a `CONNECT`. 
+    /// Carries the [reason code](MqttError::reason_code) and the error message as reason string.
+    pub fn to_connack(&self) -> Connack {
+        let properties = ConnackProperties { reason_string: Some(self.to_string()), ..Default::default() };
+        Connack { session_present: false, reason_code: self.reason_code(), properties: Some(properties) }
+    }
+
     pub fn invalid_packet_identifier(packet_type: PacketType, first_byte: &u8) -> Self {
         MqttError::MalformedPacket(format!("Invalid packet identifier for {}: {:08b}", packet_type, first_byte))
     }
 }
 
+impl From<&MqttError> for ReasonCode {
+    fn from(err: &MqttError) -> Self {
+        err.reason_code()
+    }
+}
+
 impl std::error::Error for MqttError {}
 
 impl Display for MqttError {
@@ -36,7 +131,51 @@ impl Display for MqttError {
             MqttError::MalformedPacket(detail) => formatter.write_fmt(format_args!("Malformed Packet: {}", detail)),
             MqttError::ProtocolError(detail) => formatter.write_fmt(format_args!("Protocol Error: {}", detail)),
             MqttError::Message(msg) => formatter.write_str(msg),
+            MqttError::StringTooLong { len } => formatter.write_fmt(
+                format_args!("String of {} bytes exceeds the maximum length of {}", len, u16::MAX)),
+            MqttError::BinaryDataTooLong { len } => formatter.write_fmt(
+                format_args!("Binary data of {} bytes exceeds the maximum length of {}", len, u16::MAX)),
+            MqttError::PayloadFormatInvalid(detail) => formatter.write_fmt(
+                format_args!("Payload Format Invalid: {}", detail)),
+            MqttError::PacketTooLarge { packet_type, size, maximum } => formatter.write_fmt(
+                format_args!("Packet Too Large: {} of {} bytes exceeds the maximum of {}", packet_type, size, maximum)),
+            MqttError::TopicNameInvalid(detail) => formatter.write_fmt(format_args!("Topic Name invalid: {}", detail)),
+            MqttError::TopicAliasInvalid(detail) => formatter.write_fmt(format_args!("Topic Alias invalid: {}", detail)),
             //_ => formatter.write_str("general error"),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reason_code() {
+        assert_eq!(ReasonCode::MalformedPacket, MqttError::MalformedPacket("x".to_string()).reason_code());
+        assert_eq!(ReasonCode::ProtocolError, MqttError::ProtocolError("x".to_string()).reason_code());
+        assert_eq!(ReasonCode::UnspecifiedError, MqttError::Message("x".to_string()).reason_code());
+        assert_eq!(ReasonCode::PayloadFormatInvalid, MqttError::PayloadFormatInvalid("x".to_string()).reason_code());
+        assert_eq!(
+            ReasonCode::PacketTooLarge,
+            MqttError::PacketTooLarge { packet_type: PacketType::PUBLISH, size: 10, maximum: 5 }.reason_code());
+        assert_eq!(ReasonCode::TopicNameInvalid, MqttError::TopicNameInvalid("x".to_string()).reason_code());
+        assert_eq!(ReasonCode::TopicAliasInvalid, MqttError::TopicAliasInvalid("x".to_string()).reason_code());
+        assert_eq!(ReasonCode::ProtocolError, ReasonCode::from(&MqttError::ProtocolError("x".to_string())));
+    }
+
+    #[test]
+    fn to_disconnect() {
+        let disconnect = MqttError::ProtocolError("no".to_string()).to_disconnect();
+        assert_eq!(ReasonCode::ProtocolError, disconnect.reason_code);
+        assert_eq!(Some("Protocol Error: no".to_string()), disconnect.properties.and_then(|p| p.reason_string));
+    }
+
+    #[test]
+    fn to_connack() {
+        let connack = MqttError::MalformedPacket("no".to_string()).to_connack();
+        assert!(!connack.session_present);
+        assert_eq!(ReasonCode::MalformedPacket, connack.reason_code);
+        assert_eq!(Some("Malformed Packet: no".to_string()), connack.properties.and_then(|p| p.reason_string));
+    }
+}
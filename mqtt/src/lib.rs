@@ -2,7 +2,38 @@
 //! 
 //! Whenever documentation in this crate refers to "the specification", it refers to the official 
 //! [OASIS MQTTv5 standard](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html).
+//!
+//! Encoding and decoding with the [packet], [types] and [wire] modules is always available. Everything built on top
+//! of that is behind features, most of them enabled by default. Turn them off with `default-features = false` if all
+//! you need is the packet layer.
+//!
+//! | Feature | Description |
+//! | ------- | ----------- |
+//! | `session` | The [session] module, protocol state spanning more than one packet of a connection |
+//! | `router` | The [router] module, matching topic names against subscriptions |
+//! | `transport` | The [transport] module, byte streams to run MQTT on |
+//! | `tls` | `TlsTransport`, implies `transport` |
+//! | `payload-compression` | `PayloadCompression` in the [session] module, implies `session` |
+//! | `arbitrary` | `Packet::arbitrary`, packets generated from fuzzer input |
+//! | `serde` | `Serialize` and `Deserialize` for the packets of the [packet] module |
+//! | `test-util` | The `test_util` module, helpers for writing tests against this crate |
 
+// tests build their fixtures the same way users do: start from a default and set what's relevant
+#![cfg_attr(test, allow(clippy::field_reassign_with_default))]
+
+pub mod addr;
+pub mod backoff;
+pub mod codec;
 pub mod error;
 pub mod packet;
-pub mod types;
\ No newline at end of file
+#[cfg(feature = "router")]
+pub mod router;
+#[cfg(feature = "session")]
+pub mod session;
+#[cfg(feature = "transport")]
+pub mod transport;
+pub mod types;
+pub mod wire;
+
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
\ No newline at end of file
@@ -0,0 +1,234 @@
+//! Packets built from unstructured fuzzer input, for differential fuzzing against other implementations.
+//!
+//! The packets are valid as far as their structure goes, such as non-empty topics without U+0000 or packet identifiers
+//! where the QoS requires one, so that most of them survive encoding and decoding. Beyond that, anything goes: reason
+//! codes are only restricted where the decoder checks them, and properties only to those their packet allows.
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+use crate::types::{BinaryData, QoS, ReasonCode, UTF8String, UTF8StringPair, VariableByteInteger};
+
+use super::{
+    Auth, Connack, Connect, DataRepresentation, Disconnect, LastWill, MqttProperty, Packet, Pingreq, Pingresp,
+    subscribe::RetainHandling, PropertyIdentifier, Puback, Pubcomp, Publish, Pubrec, Pubrel, Suback, Subscribe, TopicFilter, Unsuback,
+    Unsubscribe,
+};
+
+/// Most entries of anything that may occur more than once, such as topic filters or properties.
+const MAX_ENTRIES: usize = 4;
+
+/// Generates any of the fifteen packet types.
+///
+/// ```
+/// use arbitrary::{Arbitrary, Unstructured};
+/// use mqtt::packet::Packet;
+///
+/// let mut u = Unstructured::new(&[3, 1, 0, 0x61, 0x62, 0, 0]);
+/// let packet = Packet::arbitrary(&mut u).unwrap();
+/// let encoded: Vec<u8> = packet.try_into().unwrap();
+/// assert!(Packet::try_from(&encoded[..]).is_ok());
+/// ```
+impl<'a> Arbitrary<'a> for Packet {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(match u.int_in_range(1..=15)? {
+            1 => Packet::Connect(connect(u)?),
+            2 => Packet::Connack(Connack {
+                session_present: u.arbitrary()?,
+                reason_code: reason_code(u)?,
+                properties: properties(u)?,
+            }),
+            3 => Packet::Publish(publish(u)?),
+            4 => Packet::Puback(Puback {
+                packet_identifier: packet_identifier(u)?,
+                reason_code: acknowledgement(u, |code| Puback::new(1, code).is_ok())?,
+                properties: properties(u)?,
+            }),
+            5 => Packet::Pubrec(Pubrec {
+                packet_identifier: packet_identifier(u)?,
+                reason_code: acknowledgement(u, |code| Pubrec::new(1, code).is_ok())?,
+                properties: properties(u)?,
+            }),
+            6 => Packet::Pubrel(Pubrel {
+                packet_identifier: packet_identifier(u)?,
+                reason_code: acknowledgement(u, |code| Pubrel::new(1, code).is_ok())?,
+                properties: properties(u)?,
+            }),
+            7 => Packet::Pubcomp(Pubcomp {
+                packet_identifier: packet_identifier(u)?,
+                reason_code: acknowledgement(u, |code| Pubcomp::new(1, code).is_ok())?,
+                properties: properties(u)?,
+            }),
+            8 => Packet::Subscribe(Subscribe {
+                packet_identifier: packet_identifier(u)?,
+                properties: properties(u)?,
+                topic_filter: entries(u, topic_filter)?,
+            }),
+            9 => Packet::Suback(Suback {
+                packet_identifier: packet_identifier(u)?,
+                properties: properties(u)?,
+                reason_codes: entries(u, reason_code)?,
+            }),
+            10 => Packet::Unsubscribe(Unsubscribe {
+                packet_identifier: packet_identifier(u)?,
+                properties: properties(u)?,
+                topic_filter: entries(u, topic)?,
+            }),
+            11 => Packet::Unsuback(Unsuback {
+                packet_identifier: packet_identifier(u)?,
+                properties: properties(u)?,
+                reason_codes: entries(u, reason_code)?,
+            }),
+            12 => Packet::Pingreq(Pingreq {}),
+            13 => Packet::Pingresp(Pingresp {}),
+            14 => Packet::Disconnect(Disconnect { reason_code: reason_code(u)?, properties: properties(u)? }),
+            _ => Packet::Auth(Auth { reason_code: reason_code(u)?, properties: properties(u)? }),
+        })
+    }
+}
+
+fn connect(u: &mut Unstructured) -> Result<Connect> {
+    let mut connect = Connect::default();
+    connect.client_id = optional(u, string)?;
+    connect.keep_alive = u.arbitrary()?;
+    connect.clean_start = u.arbitrary()?;
+    connect.properties = properties(u)?;
+    connect.username = optional(u, string)?;
+    connect.password = u.arbitrary()?;
+    if u.arbitrary()? {
+        connect.will = Some(LastWill {
+            qos: qos(u)?,
+            retain: u.arbitrary()?,
+            properties: properties(u)?,
+            will_topic: topic(u)?,
+            will_payload: u.arbitrary()?,
+        });
+    }
+    Ok(connect)
+}
+
+fn publish(u: &mut Unstructured) -> Result<Publish> {
+    let mut publish = Publish::new_unchecked(topic(u)?, u.arbitrary::<Vec<u8>>()?);
+    publish.qos_level = qos(u)?;
+    publish.retain = u.arbitrary()?;
+    if publish.qos_level != QoS::AtMostOnce {
+        publish.dup = u.arbitrary()?;
+        publish.packet_identifier = Some(packet_identifier(u)?);
+    }
+    publish.properties = properties(u)?;
+    Ok(publish)
+}
+
+fn topic_filter(u: &mut Unstructured) -> Result<TopicFilter> {
+    let mut filter = TopicFilter::new_unchecked(topic(u)?);
+    filter.maximum_qos = qos(u)?;
+    filter.no_local = u.arbitrary()?;
+    filter.retain_as_published = u.arbitrary()?;
+    filter.retain_handling = match u.int_in_range(0..=2)? {
+        0 => RetainHandling::OnSubscribe,
+        1 => RetainHandling::NewSubOnly,
+        _ => RetainHandling::Never,
+    };
+    Ok(filter)
+}
+
+/// Properties of any kind, of which only those the packet allows are kept. `None` if that leaves nothing.
+fn properties<T>(u: &mut Unstructured) -> Result<Option<T>>
+where
+    T: TryFrom<Vec<MqttProperty>>,
+{
+    let mut kept = Vec::new();
+    for property in entries(u, property)? {
+        kept.push(property);
+        if T::try_from(kept.clone()).is_err() {
+            kept.pop();
+        }
+    }
+    match kept.is_empty() {
+        true => Ok(None),
+        false => Ok(T::try_from(kept).ok()),
+    }
+}
+
+fn property(u: &mut Unstructured) -> Result<MqttProperty> {
+    use PropertyIdentifier::*;
+
+    let identifier = *u.choose(&[
+        PayloadFormatIndicator, MessageExpiryInterval, ContentType, ResponseTopic, CorrelationData,
+        SubscriptionIdentifier, SessionExpiryInterval, AssignedClientIdentifier, ServerKeepAlive, AuthenticationMethod,
+        AuthenticationData, RequestProblemInformation, WillDelayInterval, RequestResponseInformation,
+        ResponseInformation, ServerReference, ReasonString, ReceiveMaximum, TopicAliasMaximum, TopicAlias, MaximumQos,
+        RetainAvailable, UserProperty, MaximumPacketSize, WildcardSubscriptionAvailable,
+        SubscriptionIdentifierAvailable, SharedSubscriptionAvailable,
+    ])?;
+
+    let value = match identifier {
+        PayloadFormatIndicator | RequestProblemInformation | RequestResponseInformation | RetainAvailable
+        | WildcardSubscriptionAvailable | SubscriptionIdentifierAvailable | SharedSubscriptionAvailable => {
+            DataRepresentation::Byte(u.int_in_range(0..=1)?)
+        },
+        MaximumQos => DataRepresentation::Byte(u.int_in_range(0..=1)?),
+        ServerKeepAlive | TopicAliasMaximum => DataRepresentation::TwoByteInt(u.arbitrary()?),
+        ReceiveMaximum | TopicAlias => DataRepresentation::TwoByteInt(u.int_in_range(1..=u16::MAX)?),
+        MessageExpiryInterval | SessionExpiryInterval | WillDelayInterval => {
+            DataRepresentation::FourByteInt(u.arbitrary()?)
+        },
+        MaximumPacketSize => DataRepresentation::FourByteInt(u.int_in_range(1..=u32::MAX)?),
+        SubscriptionIdentifier => DataRepresentation::VariByteInt(VariableByteInteger {
+            value: u.int_in_range(1..=crate::wire::MAX_VARIABLE_BYTE_INTEGER)?,
+        }),
+        CorrelationData | AuthenticationData => DataRepresentation::BinaryData(BinaryData::from(bytes(u)?)),
+        UserProperty => DataRepresentation::UTF8Pair(UTF8StringPair::new(string(u)?, string(u)?)),
+        _ => DataRepresentation::UTF8(UTF8String::from(string(u)?)),
+    };
+    Ok(MqttProperty { identifier, value })
+}
+
+fn reason_code(u: &mut Unstructured) -> Result<ReasonCode> {
+    Ok(ReasonCode::try_from(u.arbitrary::<u8>()?).unwrap_or(ReasonCode::Success))
+}
+
+/// A reason code the acknowledgement allows, as these are checked when decoding.
+fn acknowledgement(u: &mut Unstructured, allowed: impl Fn(ReasonCode) -> bool) -> Result<ReasonCode> {
+    Ok(Some(reason_code(u)?).filter(|code| allowed(*code)).unwrap_or(ReasonCode::Success))
+}
+
+fn qos(u: &mut Unstructured) -> Result<QoS> {
+    Ok(*u.choose(&[QoS::AtMostOnce, QoS::AtLeastOnce, QoS::ExactlyOnce])?)
+}
+
+fn packet_identifier(u: &mut Unstructured) -> Result<u16> {
+    u.int_in_range(1..=u16::MAX)
+}
+
+/// A topic name or filter of at least one character. Wildcards may end up anywhere, the codec doesn't care.
+fn topic(u: &mut Unstructured) -> Result<String> {
+    let topic = string(u)?;
+    Ok(match topic.is_empty() {
+        true => "t".to_string(),
+        false => topic,
+    })
+}
+
+/// Any string without U+0000, which no string may contain [MQTT-1.5.4-2], short enough for its length prefix.
+fn string(u: &mut Unstructured) -> Result<String> {
+    let s: &str = u.arbitrary()?;
+    Ok(s.chars().filter(|c| *c != '\0').take(u16::MAX as usize / 4).collect())
+}
+
+fn bytes(u: &mut Unstructured) -> Result<Vec<u8>> {
+    let bytes: &[u8] = u.arbitrary()?;
+    Ok(bytes[..bytes.len().min(u16::MAX as usize)].to_vec())
+}
+
+fn optional<T>(u: &mut Unstructured, f: impl Fn(&mut Unstructured) -> Result<T>) -> Result<Option<T>> {
+    match u.arbitrary()? {
+        true => f(u).map(Some),
+        false => Ok(None),
+    }
+}
+
+/// One to [MAX_ENTRIES] of something.
+fn entries<T>(u: &mut Unstructured, f: impl Fn(&mut Unstructured) -> Result<T>) -> Result<Vec<T>> {
+    let len = u.int_in_range(1..=MAX_ENTRIES)?;
+    (0..len).map(|_| f(u)).collect()
+}
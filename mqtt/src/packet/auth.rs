@@ -1,26 +1,29 @@
-use std::collections::HashMap;
-
 use mqtt_derive::MqttProperties;
 
-use crate::{types::ReasonCode, error::MqttError};
+use crate::{types::{ReasonCode, UserProperties}, error::MqttError};
 
-use super::{MqttControlPacket, Decodeable, MqttDataType};
+use super::{properties::check_authentication, MqttControlPacket, MqttDataType, PacketType, Violation};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
 pub struct Auth {
+    #[cfg_attr(feature = "serde", serde(default))]
     pub reason_code: ReasonCode,
     pub properties: Option<AuthProperties>
 }
 
-#[derive(Debug, MqttProperties)]
+#[derive(Debug, Clone, PartialEq, MqttProperties)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
 pub struct AuthProperties {
     pub authentication_method: Option<String>,
     pub authentication_data: Option<Vec<u8>>,
     pub reason_string: Option<String>,
-    pub user_property: HashMap<String, String>,
+    pub user_property: UserProperties,
 }
 
-const FIRST_BYTE: u8 = 0b11110000;
+const FIRST_BYTE: u8 = PacketType::AUTH.to_first_byte(PacketType::AUTH.required_flags());
 
 impl MqttControlPacket<'_> for Auth {
     fn packet_type() -> super::PacketType {
@@ -28,22 +31,28 @@ impl MqttControlPacket<'_> for Auth {
     }
 }
 
-impl From<Auth> for Vec<u8> {
-    fn from(auth: Auth) -> Self {
+impl TryFrom<Auth> for Vec<u8> {
+    type Error = MqttError;
+
+    fn try_from(mut auth: Auth) -> Result<Self, Self::Error> {
+        if let Some(p) = auth.properties.as_mut() {
+            check_authentication("AuthProperties", &p.authentication_method, &mut p.authentication_data, &mut None)?;
+        }
+
         let mut result = Vec::new();
         result.push(FIRST_BYTE);
 
-        if auth.reason_code != ReasonCode::Success || auth.properties.is_some() {
+        if auth.reason_code != ReasonCode::Success || auth.properties.as_ref().is_some_and(|p| !p.is_empty()) {
             result.push(auth.reason_code.into());
             match auth.properties {
-                Some(props) => result.append(&mut props.into()),
+                Some(props) => result.append(&mut props.try_into()?),
                 None => result.push(0),
             }
         }
 
         super::calculate_and_insert_length(&mut result);
 
-        result
+        Ok(result)
     }
 }
 
@@ -51,6 +60,14 @@ impl TryFrom<&[u8]> for Auth {
     type Error = MqttError;
 
     fn try_from(src: &[u8]) -> Result<Self, Self::Error> {
+        Self::decode_from(src, None)
+    }
+}
+
+impl Auth {
+
+    /// Decodes strictly, or leniently if there are `violations` to record what was tolerated in.
+    pub(crate) fn decode_from(src: &[u8], mut violations: Option<&mut Vec<Violation>>) -> Result<Self, MqttError> {
         let mut cursor = 0;
 
         match src[cursor] {
@@ -66,10 +83,17 @@ impl TryFrom<&[u8]> for Auth {
             _ => {
                 let reason_code = ReasonCode::try_from(src[cursor])?;
                 cursor += 1;
-                let props_res = AuthProperties::decode(&src[cursor..])?;
-                (reason_code, props_res.value)
+                let props_res = super::decode_properties::<AuthProperties>(&src[cursor..], violations.as_deref_mut())?;
+                cursor += props_res.bytes_read();
+                let mut properties = props_res.value;
+                if let Some(p) = properties.as_mut() {
+                    check_authentication(
+                        "AuthProperties", &p.authentication_method, &mut p.authentication_data, &mut violations)?;
+                }
+                (reason_code, properties)
             }
         };
+        super::check_remaining_length(PacketType::AUTH, &remain_len, cursor, &mut violations)?;
 
         Ok(Self{
             reason_code,
@@ -85,9 +109,34 @@ mod tests {
     #[test]
     fn encode_and_decode() {
         let auth = Auth { reason_code: ReasonCode::Success, properties: None };
-        let encoded: Vec<u8> = auth.into();
+        let encoded: Vec<u8> = auth.try_into().unwrap();
         assert_eq!(2, encoded.len());
         let decoded = Auth::try_from(&encoded[..]).unwrap();
         assert_eq!(ReasonCode::Success, decoded.reason_code);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn authentication_data_without_method() {
+        let auth = Auth {
+            reason_code: ReasonCode::ContinueAuthentication,
+            properties: Some(AuthProperties { authentication_data: Some(vec![1, 2]), ..Default::default() }),
+        };
+        let error = MqttError::ProtocolError(
+            "Property AuthenticationData (0x16) without AuthenticationMethod (0x15) in AuthProperties".to_string());
+        assert_eq!(Err(error.clone()), Vec::try_from(auth));
+
+        let binary = [0xF0, 7, 0x18, 5, 22, 0, 2, 1, 2];
+        assert_eq!(Err(error), Auth::try_from(&binary[..]));
+
+        let mut violations = Vec::new();
+        let decoded = Auth::decode_from(&binary, Some(&mut violations)).unwrap();
+        assert_eq!(Some(AuthProperties::default()), decoded.properties);
+        assert_eq!(1, violations.len());
+
+        let mut with_method = AuthProperties { authentication_data: Some(vec![1, 2]), ..Default::default() };
+        with_method.authentication_method = Some("SCRAM-SHA-1".into());
+        let auth = Auth { reason_code: ReasonCode::ContinueAuthentication, properties: Some(with_method) };
+        let encoded: Vec<u8> = auth.clone().try_into().unwrap();
+        assert_eq!(Ok(auth), Auth::try_from(&encoded[..]));
+    }
+}
@@ -0,0 +1,1083 @@
+use std::{borrow::Cow, io::{self, IoSlice, Read, Write}};
+
+use crate::{error::MqttError, types::{MqttDataType, QoS, ReasonCode, VariableByteInteger}, wire};
+
+use super::{
+    Auth, Connack, Connect, Disconnect, PacketType, Pingreq, Pingresp, ProtocolVersion, Puback, Pubcomp, Publish,
+    Pubrec, Pubrel, Suback, Subscribe, Unsuback, Unsubscribe, Lenient, Violation,
+};
+
+/// Any one of the MQTT control packets.
+///
+/// Useful wherever packets of different types are handled together, such as when sending several of them in one go.
+/// Every packet struct converts into its variant using `into()`.
+///
+/// # Examples
+/// ```
+/// use mqtt::packet::{Packet, PacketType, Publish};
+///
+/// let packet: Packet = Publish::with_topic_name("some/topic".try_into().unwrap(), vec![]).into();
+/// assert_eq!(PacketType::PUBLISH, packet.packet_type());
+/// ```
+///
+/// Like the packet structs, it decodes from a slice, an owned buffer or straight from a reader, which reads exactly
+/// one packet as its fixed header says:
+/// ```
+/// use std::io::{Cursor, Read};
+/// use mqtt::packet::{Packet, Pingresp, Puback};
+///
+/// let mut stream = Cursor::new(vec![0b11010000, 0, 0b01000000, 2, 0, 7]);
+/// let reader: &mut dyn Read = &mut stream;
+/// assert!(matches!(Packet::try_from(&mut *reader), Ok(Packet::Pingresp(_))));
+/// assert_eq!(7, Puback::try_from(&mut *reader).unwrap().packet_identifier);
+///
+/// assert!(Pingresp::try_from(vec![0b11010000, 0]).is_ok());
+/// ```
+#[derive(Debug, PartialEq)]
+pub enum Packet {
+    Connect(Connect),
+    Connack(Connack),
+    Publish(Publish),
+    Puback(Puback),
+    Pubrec(Pubrec),
+    Pubrel(Pubrel),
+    Pubcomp(Pubcomp),
+    Subscribe(Subscribe),
+    Suback(Suback),
+    Unsubscribe(Unsubscribe),
+    Unsuback(Unsuback),
+    Pingreq(Pingreq),
+    Pingresp(Pingresp),
+    Disconnect(Disconnect),
+    Auth(Auth),
+}
+
+impl Packet {
+
+    /// The type of the wrapped packet.
+    pub fn packet_type(&self) -> PacketType {
+        match self {
+            Packet::Connect(_) => PacketType::CONNECT,
+            Packet::Connack(_) => PacketType::CONNACK,
+            Packet::Publish(_) => PacketType::PUBLISH,
+            Packet::Puback(_) => PacketType::PUBACK,
+            Packet::Pubrec(_) => PacketType::PUBREC,
+            Packet::Pubrel(_) => PacketType::PUBREL,
+            Packet::Pubcomp(_) => PacketType::PUBCOMP,
+            Packet::Subscribe(_) => PacketType::SUBSCRIBE,
+            Packet::Suback(_) => PacketType::SUBACK,
+            Packet::Unsubscribe(_) => PacketType::UNSUBSCRIBE,
+            Packet::Unsuback(_) => PacketType::UNSUBACK,
+            Packet::Pingreq(_) => PacketType::PINGREQ,
+            Packet::Pingresp(_) => PacketType::PINGRESP,
+            Packet::Disconnect(_) => PacketType::DISCONNECT,
+            Packet::Auth(_) => PacketType::AUTH,
+        }
+    }
+
+    /// The reason codes the packet carries: one for most acknowledgements as well as `CONNACK`, `DISCONNECT` and
+    /// `AUTH`, one per topic filter for `SUBACK` and `UNSUBACK`, none for the rest.
+    pub fn reason_codes(&self) -> &[ReasonCode] {
+        match self {
+            Packet::Connack(p) => std::slice::from_ref(&p.reason_code),
+            Packet::Puback(p) => std::slice::from_ref(&p.reason_code),
+            Packet::Pubrec(p) => std::slice::from_ref(&p.reason_code),
+            Packet::Pubrel(p) => std::slice::from_ref(&p.reason_code),
+            Packet::Pubcomp(p) => std::slice::from_ref(&p.reason_code),
+            Packet::Suback(p) => &p.reason_codes,
+            Packet::Unsuback(p) => &p.reason_codes,
+            Packet::Disconnect(p) => std::slice::from_ref(&p.reason_code),
+            Packet::Auth(p) => std::slice::from_ref(&p.reason_code),
+            Packet::Connect(_) | Packet::Publish(_) | Packet::Subscribe(_) | Packet::Unsubscribe(_)
+                | Packet::Pingreq(_) | Packet::Pingresp(_) => &[],
+        }
+    }
+
+    /// The number of bytes the packet takes up when encoded, fixed header included, worked out without encoding it.
+    /// Only meaningful for packets that encode at all, for the others it's whatever the fields add up to.
+    ///
+    /// # Examples
+    /// ```
+    /// use mqtt::packet::{Packet, Publish};
+    ///
+    /// let packet = Packet::from(Publish::with_topic_name("some/topic".try_into().unwrap(), vec![0; 200]));
+    /// assert_eq!(216, packet.encoded_size());
+    /// assert_eq!(216, Vec::try_from(packet).unwrap().len());
+    /// ```
+    pub fn encoded_size(&self) -> usize {
+        let remaining = self.remaining_len();
+        1 + wire::variable_byte_integer_len(remaining as u32) + remaining
+    }
+
+    /// What the remaining length of the fixed header says: the size of variable header and payload.
+    fn remaining_len(&self) -> usize {
+        // reason code and properties of acknowledgements and AUTH are left out if there's nothing to tell
+        fn optional(reason_code: ReasonCode, properties: Option<usize>) -> usize {
+            match (reason_code, properties) {
+                (ReasonCode::Success, None) => 0,
+                (_, properties) => 1 + properties.unwrap_or(1),
+            }
+        }
+        macro_rules! properties {
+            ($packet:expr) => { super::properties_len($packet.properties.iter().flat_map(|p| p.iter())) };
+        }
+        macro_rules! non_empty_properties {
+            ($packet:expr) => { $packet.properties.as_ref().filter(|p| !p.is_empty()).map(|p| super::properties_len(p.iter())) };
+        }
+        let string = |s: &str| 2 + s.len();
+
+        match self {
+            Packet::Connect(p) => {
+                let will = p.will.as_ref().map_or(0, |will| {
+                    properties!(will) + string(&will.will_topic) + 2 + will.will_payload.len()
+                });
+                // protocol name, level, connect flags and keep alive
+                10 + properties!(p)
+                    + string(p.client_id.as_deref().unwrap_or_default())
+                    + will
+                    + p.username.as_deref().map_or(0, string)
+                    + p.password.as_ref().map_or(0, |password| 2 + password.len())
+            },
+            Packet::Connack(p) => 2 + properties!(p),
+            Packet::Publish(p) => {
+                let packet_identifier = if p.qos_level == QoS::AtMostOnce { 0 } else { 2 };
+                string(&p.topic_name) + packet_identifier + p.properties_len() + p.payload.len()
+            },
+            Packet::Puback(p) => 2 + optional(p.reason_code, non_empty_properties!(p)),
+            Packet::Pubrec(p) => 2 + optional(p.reason_code, non_empty_properties!(p)),
+            Packet::Pubrel(p) => 2 + optional(p.reason_code, non_empty_properties!(p)),
+            Packet::Pubcomp(p) => 2 + optional(p.reason_code, non_empty_properties!(p)),
+            Packet::Subscribe(p) => {
+                2 + properties!(p) + p.topic_filter.iter().map(|filter| filter.encoded_len()).sum::<usize>()
+            },
+            Packet::Suback(p) => 2 + properties!(p) + p.reason_codes.len(),
+            Packet::Unsubscribe(p) => {
+                2 + properties!(p) + p.topic_filter.iter().map(|filter| string(filter)).sum::<usize>()
+            },
+            Packet::Unsuback(p) => 2 + properties!(p) + p.reason_codes.len(),
+            Packet::Pingreq(_) | Packet::Pingresp(_) => 0,
+            Packet::Disconnect(p) => 1 + properties!(p),
+            Packet::Auth(p) => optional(p.reason_code, non_empty_properties!(p)),
+        }
+    }
+
+    /// Encodes the packet for a connection speaking `version`, refusing to if the packet uses features that version
+    /// doesn't have.
+    ///
+    /// Only MQTT 5 has an encoding so far, for the older versions this fails even if the packet passes the
+    /// [check](ProtocolVersion::check).
+    pub fn encode_for(self, version: ProtocolVersion) -> Result<Vec<u8>, MqttError> {
+        version.check(&self)?;
+        match version {
+            ProtocolVersion::V5 => self.try_into(),
+            _ => Err(MqttError::Message(format!("Encoding for MQTT {} is not implemented", version))),
+        }
+    }
+
+    /// Encodes the packet for a receiver that accepts at most `maximum_packet_size` bytes, such as a server that
+    /// announced a [maximum](super::ConnackProperties::maximum_packet_size) in its `CONNACK`.
+    ///
+    /// Reason string and user properties are optional for acknowledgements, `CONNACK`, `DISCONNECT` and `AUTH`, and
+    /// left out as far as needed [MQTT-3.4.2-2, MQTT-3.4.2-3 and alike]. Any other packet that's too large, most
+    /// likely a `PUBLISH`, fails with [PacketTooLarge](MqttError::PacketTooLarge) instead of being sent and
+    /// getting the connection closed [MQTT-3.1.2-24].
+    ///
+    /// # Examples
+    /// ```
+    /// use mqtt::{error::MqttError, packet::{Packet, Puback, PubackProperties, Publish}, types::ReasonCode};
+    ///
+    /// let mut puback = Puback::new(1, ReasonCode::NoMatchingSubscribers).unwrap();
+    /// puback.properties = Some(PubackProperties { reason_string: Some("x".repeat(100)), ..Default::default() });
+    /// let encoded = Packet::from(puback).encode_within(Some(32)).unwrap();
+    /// assert_eq!(vec![0b01000000, 4, 0, 1, 0x10, 0], encoded);
+    ///
+    /// let publish = Publish::with_topic_name("some/topic".try_into().unwrap(), vec![0; 100]);
+    /// assert!(matches!(
+    ///     Packet::from(publish).encode_within(Some(32)),
+    ///     Err(MqttError::PacketTooLarge { size: 115, maximum: 32, .. })));
+    /// ```
+    pub fn encode_within(self, maximum_packet_size: Option<u32>) -> Result<Vec<u8>, MqttError> {
+        let Some(maximum) = maximum_packet_size else {
+            return self.try_into()
+        };
+
+        let packet_type = self.packet_type();
+        let encoded = match self {
+            Packet::Connack(p) => super::encode_fitted(p, maximum)?,
+            Packet::Puback(p) => super::encode_fitted(p, maximum)?,
+            Packet::Pubrec(p) => super::encode_fitted(p, maximum)?,
+            Packet::Pubrel(p) => super::encode_fitted(p, maximum)?,
+            Packet::Pubcomp(p) => super::encode_fitted(p, maximum)?,
+            Packet::Suback(p) => super::encode_fitted(p, maximum)?,
+            Packet::Unsuback(p) => super::encode_fitted(p, maximum)?,
+            Packet::Disconnect(p) => super::encode_fitted(p, maximum)?,
+            Packet::Auth(p) => super::encode_fitted(p, maximum)?,
+            packet => packet.try_into()?,
+        };
+
+        match encoded.len() <= maximum as usize {
+            true => Ok(encoded),
+            false => Err(MqttError::PacketTooLarge { packet_type, size: encoded.len(), maximum }),
+        }
+    }
+
+    /// Decodes the packet at the beginning of `src`, which may be followed by more packets or the beginning of one.
+    /// Returns the packet along with the number of bytes it took up, which is where the next packet starts.
+    ///
+    /// Returns a `MalformedPacket` error if `src` doesn't contain the entire packet.
+    ///
+    /// # Examples
+    /// ```
+    /// use mqtt::packet::Packet;
+    ///
+    /// // a PINGRESP followed by a PUBACK
+    /// let buf = [0b11010000, 0, 0b01000000, 2, 0, 1];
+    ///
+    /// let (packet, len) = Packet::decode_with_len(&buf).unwrap();
+    /// assert!(matches!(packet, Packet::Pingresp(_)));
+    /// assert_eq!(2, len);
+    ///
+    /// let (packet, len) = Packet::decode_with_len(&buf[len..]).unwrap();
+    /// assert!(matches!(packet, Packet::Puback(_)));
+    /// assert_eq!(4, len);
+    /// ```
+    pub fn decode_with_len(src: &[u8]) -> Result<(Self, usize), MqttError> {
+        let len = wire::packet_len(src)?.ok_or_else(|| MqttError::MalformedPacket(
+            format!("Incomplete packet of {} bytes", src.len())))?;
+        Ok((Packet::try_from(&src[..len])?, len))
+    }
+
+    /// Decodes a single packet like `try_from`, but tolerates some protocol violations instead of rejecting the
+    /// packet: fixed header flags other than the required ones, properties that don't belong in the packet, repeated
+    /// properties that may only occur once and a remaining length that doesn't match the decoded fields. Everything
+    /// tolerated is listed in the result.
+    ///
+    /// # Examples
+    /// ```
+    /// use mqtt::packet::{Packet, PacketType, Violation};
+    ///
+    /// // a PUBREL with the reserved flags cleared
+    /// let decoded = Packet::decode_lenient(&[0x60, 0x02, 0x00, 0x01]).unwrap();
+    /// assert!(matches!(decoded.value, Packet::Pubrel(_)));
+    /// assert_eq!(vec![Violation::ReservedBits { packet_type: PacketType::PUBREL, flags: 0 }], decoded.violations);
+    /// ```
+    pub fn decode_lenient(src: &[u8]) -> Result<Lenient<Self>, MqttError> {
+        let first_byte = match src.first() {
+            Some(b) => *b,
+            None => return Err(MqttError::MalformedPacket("Empty packet".to_string())),
+        };
+        let packet_type = PacketType::try_from(first_byte)?;
+        let mut violations = Vec::new();
+
+        // the flags of PUBLISH aren't reserved
+        let flags = first_byte & 0x0F;
+        let src = match packet_type != PacketType::PUBLISH && flags != packet_type.required_flags() {
+            true => {
+                violations.push(Violation::ReservedBits { packet_type, flags });
+                let mut fixed = src.to_vec();
+                fixed[0] = packet_type.to_first_byte(packet_type.required_flags());
+                Cow::Owned(fixed)
+            },
+            false => Cow::Borrowed(src),
+        };
+
+        let v = Some(&mut violations);
+        let packet = match packet_type {
+            PacketType::CONNECT => Packet::Connect(Connect::decode_from(&src, v)?),
+            PacketType::CONNACK => Packet::Connack(Connack::decode_from(&src, v)?),
+            PacketType::PUBLISH => Packet::Publish(Publish::decode_from(&src, v)?),
+            PacketType::PUBACK => Packet::Puback(Puback::decode_from(&src, v)?),
+            PacketType::PUBREC => Packet::Pubrec(Pubrec::decode_from(&src, v)?),
+            PacketType::PUBREL => Packet::Pubrel(Pubrel::decode_from(&src, v)?),
+            PacketType::PUBCOMP => Packet::Pubcomp(Pubcomp::decode_from(&src, v)?),
+            PacketType::SUBSCRIBE => Packet::Subscribe(Subscribe::decode_from(&src, v)?),
+            PacketType::SUBACK => Packet::Suback(Suback::decode_from(&src, v)?),
+            PacketType::UNSUBSCRIBE => Packet::Unsubscribe(Unsubscribe::decode_from(&src, v)?),
+            PacketType::UNSUBACK => Packet::Unsuback(Unsuback::decode_from(&src, v)?),
+            PacketType::PINGREQ => Packet::Pingreq(Pingreq::try_from(&src[..])?),
+            PacketType::PINGRESP => Packet::Pingresp(Pingresp::try_from(&src[..])?),
+            PacketType::DISCONNECT => Packet::Disconnect(Disconnect::decode_from(&src, v)?),
+            PacketType::AUTH => Packet::Auth(Auth::decode_from(&src, v)?),
+        };
+
+        Ok(Lenient { value: packet, violations })
+    }
+}
+
+impl TryFrom<Packet> for Vec<u8> {
+    type Error = MqttError;
+
+    fn try_from(packet: Packet) -> Result<Self, Self::Error> {
+        match packet {
+            Packet::Connect(p) => p.try_into(),
+            Packet::Connack(p) => p.try_into(),
+            Packet::Publish(p) => p.try_into(),
+            Packet::Puback(p) => p.try_into(),
+            Packet::Pubrec(p) => p.try_into(),
+            Packet::Pubrel(p) => p.try_into(),
+            Packet::Pubcomp(p) => p.try_into(),
+            Packet::Subscribe(p) => p.try_into(),
+            Packet::Suback(p) => p.try_into(),
+            Packet::Unsubscribe(p) => p.try_into(),
+            Packet::Unsuback(p) => p.try_into(),
+            Packet::Pingreq(p) => Ok(p.into()),
+            Packet::Pingresp(p) => Ok(p.into()),
+            Packet::Disconnect(p) => p.try_into(),
+            Packet::Auth(p) => p.try_into(),
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for Packet {
+    type Error = MqttError;
+
+    /// Decodes a single packet of whichever type the first byte indicates.
+    fn try_from(src: &[u8]) -> Result<Self, Self::Error> {
+        let first_byte = match src.first() {
+            Some(b) => *b,
+            None => return Err(MqttError::MalformedPacket("Empty packet".to_string())),
+        };
+
+        Ok(match PacketType::try_from(first_byte)? {
+            PacketType::CONNECT => Packet::Connect(Connect::try_from(src)?),
+            PacketType::CONNACK => Packet::Connack(Connack::try_from(src)?),
+            PacketType::PUBLISH => Packet::Publish(Publish::try_from(src)?),
+            PacketType::PUBACK => Packet::Puback(Puback::try_from(src)?),
+            PacketType::PUBREC => Packet::Pubrec(Pubrec::try_from(src)?),
+            PacketType::PUBREL => Packet::Pubrel(Pubrel::try_from(src)?),
+            PacketType::PUBCOMP => Packet::Pubcomp(Pubcomp::try_from(src)?),
+            PacketType::SUBSCRIBE => Packet::Subscribe(Subscribe::try_from(src)?),
+            PacketType::SUBACK => Packet::Suback(Suback::try_from(src)?),
+            PacketType::UNSUBSCRIBE => Packet::Unsubscribe(Unsubscribe::try_from(src)?),
+            PacketType::UNSUBACK => Packet::Unsuback(Unsuback::try_from(src)?),
+            PacketType::PINGREQ => Packet::Pingreq(Pingreq::try_from(src)?),
+            PacketType::PINGRESP => Packet::Pingresp(Pingresp::try_from(src)?),
+            PacketType::DISCONNECT => Packet::Disconnect(Disconnect::try_from(src)?),
+            PacketType::AUTH => Packet::Auth(Auth::try_from(src)?),
+        })
+    }
+}
+
+macro_rules! packet_from {
+    ($($variant:ident),*) => {
+        $(
+            impl From<$variant> for Packet {
+                fn from(packet: $variant) -> Self {
+                    Packet::$variant(packet)
+                }
+            }
+        )*
+    };
+}
+
+packet_from!(
+    Connect, Connack, Publish, Puback, Pubrec, Pubrel, Pubcomp, Subscribe, Suback, Unsubscribe, Unsuback, Pingreq,
+    Pingresp, Disconnect, Auth
+);
+
+/// Decoding from an owned buffer, and from a reader by reading exactly one packet, for every packet type.
+macro_rules! decode_from_owned_and_reader {
+    ($($packet:ident),*) => {
+        $(
+            impl TryFrom<Vec<u8>> for $packet {
+                type Error = MqttError;
+
+                fn try_from(src: Vec<u8>) -> Result<Self, Self::Error> {
+                    Self::try_from(&src[..])
+                }
+            }
+
+            impl<'r> TryFrom<&mut (dyn Read + 'r)> for $packet {
+                type Error = MqttError;
+
+                fn try_from(reader: &mut (dyn Read + 'r)) -> Result<Self, Self::Error> {
+                    Self::try_from(read_frame(reader)?)
+                }
+            }
+        )*
+    };
+}
+
+decode_from_owned_and_reader!(
+    Packet, Connect, Connack, Publish, Puback, Pubrec, Pubrel, Pubcomp, Subscribe, Suback, Unsubscribe, Unsuback,
+    Pingreq, Pingresp, Disconnect, Auth
+);
+
+/// Reads a whole packet and nothing more: the fixed header byte by byte up to the end of the remaining length, then
+/// as many bytes as it says. The buffer only grows with the bytes that actually arrive, a header announcing a huge
+/// packet from a peer that never sends it costs nothing.
+fn read_frame(reader: &mut dyn Read) -> Result<Vec<u8>, MqttError> {
+    let read_exact = |reader: &mut dyn Read, buf: &mut [u8]| reader.read_exact(buf)
+        .map_err(|e| MqttError::Message(format!("Error reading packet: {}", e)));
+
+    let mut frame = vec![0];
+    read_exact(reader, &mut frame)?;
+    loop {
+        if frame.len() > 4 {
+            return Err(MqttError::MalformedPacket("Remaining length exceeds four bytes".to_string()))
+        }
+        let mut byte = [0];
+        read_exact(reader, &mut byte)?;
+        frame.push(byte[0]);
+        if byte[0] & 128 == 0 {
+            break
+        }
+    }
+
+    let remaining = VariableByteInteger::try_from(&frame[1..])?.value as usize;
+    let read = reader.take(remaining as u64).read_to_end(&mut frame)
+        .map_err(|e| MqttError::Message(format!("Error reading packet: {}", e)))?;
+    if read < remaining {
+        return Err(MqttError::Message(format!("Error reading packet: got {} of {} remaining bytes", read, remaining)))
+    }
+    Ok(frame)
+}
+
+/// Encodes all `packets` back-to-back and appends them to `buf`, so they can be sent with a single write.
+///
+/// # Examples
+/// ```
+/// use mqtt::packet::{encode_batch, Packet, Publish};
+///
+/// let packets: Vec<Packet> = (0..3)
+///     .map(|i| Publish::with_topic_name("a/b".try_into().unwrap(), vec![i]).into())
+///     .collect();
+///
+/// let mut buf = Vec::new();
+/// encode_batch(packets, &mut buf).unwrap();
+/// assert_eq!(3 * 9, buf.len());
+/// ```
+///
+/// If any of the packets can't be encoded, `buf` is left as it was.
+pub fn encode_batch<I: IntoIterator<Item = Packet>>(packets: I, buf: &mut Vec<u8>) -> Result<(), MqttError> {
+    let len = buf.len();
+    for packet in packets {
+        match Vec::try_from(packet) {
+            Ok(mut encoded) => buf.append(&mut encoded),
+            Err(e) => {
+                buf.truncate(len);
+                return Err(e)
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Encodes all `packets` separately and hands them to the writer as one vectored write (`writev`), saving the copy
+/// into a single buffer that [encode_batch] does.
+///
+/// Keeps writing until everything has been written, returns the total number of bytes. Nothing is written if any of
+/// the packets can't be encoded, which fails with [io::ErrorKind::InvalidInput].
+pub fn write_batch<I, W>(packets: I, writer: &mut W) -> io::Result<usize>
+where
+    I: IntoIterator<Item = Packet>,
+    W: Write,
+{
+    let encoded: Vec<Vec<u8>> = packets.into_iter()
+        .map(Vec::try_from)
+        .collect::<Result<_, _>>()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let total = encoded.iter().map(Vec::len).sum();
+
+    let mut slices: Vec<IoSlice> = encoded.iter().map(|bytes| IoSlice::new(bytes)).collect();
+    let mut remaining = &mut slices[..];
+    while !remaining.is_empty() {
+        match writer.write_vectored(remaining) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write whole batch")),
+            Ok(written) => IoSlice::advance_slices(&mut remaining, written),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(total)
+}
+
+/// Decodes packets lying back-to-back in a buffer, as is usually the case with data read from a network stream.
+///
+/// Each item is a packet along with the number of bytes it took up in the buffer. Iteration ends once the buffer is 
+/// exhausted or only an incomplete packet is left, which is then available via [PacketIter::remainder] to be 
+/// completed by the next read. 
+/// 
+/// A packet that fails to decode is returned as an error and skipped, the iterator then continues with the next one.
+/// If the fixed header itself is broken, there is no telling where the next packet starts: the error is returned 
+/// and iteration ends. The same goes for a packet exceeding the
+/// [maximum packet size](PacketIter::maximum_packet_size), if there is one.
+///
+/// # Examples
+/// ```
+/// use mqtt::packet::{Packet, PacketIter};
+///
+/// // a PINGRESP followed by the first two bytes of a PUBACK
+/// let buf = [0b11010000, 0, 0b01000000, 2];
+/// let mut iter = PacketIter::new(&buf);
+///
+/// assert!(matches!(iter.next(), Some(Ok((Packet::Pingresp(_), 2)))));
+/// assert!(iter.next().is_none());
+/// assert!(iter.is_partial());
+/// assert_eq!(&[0b01000000, 2], iter.remainder());
+/// ```
+#[derive(Debug)]
+pub struct PacketIter<'a> {
+    src: &'a [u8],
+    failed: bool,
+    maximum_packet_size: Option<u32>,
+}
+
+impl<'a> PacketIter<'a> {
+
+    /// Creates an iterator over all packets in `src`.
+    pub fn new(src: &'a [u8]) -> Self {
+        PacketIter { src, failed: false, maximum_packet_size: None }
+    }
+
+    /// Rejects packets of more than `maximum` bytes, such as the maximum packet size announced with `CONNECT` or
+    /// `CONNACK`, with [PacketTooLarge](MqttError::PacketTooLarge). The error comes as soon as the fixed header of such
+    /// a packet is there, without waiting for the rest of it, see [wire::check_packet_len]. The connection then has to
+    /// be closed with a `DISCONNECT` with that [reason code](MqttError::reason_code) [MQTT-3.1.2-24, MQTT-3.2.2-15].
+    ///
+    /// # Examples
+    /// ```
+    /// use mqtt::error::MqttError;
+    /// use mqtt::packet::{PacketIter, PacketType};
+    /// use mqtt::types::ReasonCode;
+    ///
+    /// // a PINGRESP, then the beginning of a PUBLISH of 200 bytes
+    /// let buf = [0b11010000, 0, 0x30, 0xC5, 0x01, 0x00];
+    /// let mut iter = PacketIter::new(&buf).maximum_packet_size(Some(128));
+    ///
+    /// assert!(iter.next().unwrap().is_ok());
+    /// let error = iter.next().unwrap().unwrap_err();
+    /// assert_eq!(MqttError::PacketTooLarge { packet_type: PacketType::PUBLISH, size: 200, maximum: 128 }, error);
+    /// assert_eq!(ReasonCode::PacketTooLarge, error.to_disconnect().reason_code);
+    /// assert!(iter.next().is_none());
+    /// assert!(!iter.is_partial());
+    /// ```
+    pub fn maximum_packet_size(self, maximum: Option<u32>) -> Self {
+        Self { maximum_packet_size: maximum, ..self }
+    }
+
+    /// The bytes not yet consumed. After iteration has ended, this is the incomplete packet at the end of the buffer
+    /// (if any).
+    pub fn remainder(&self) -> &'a [u8] {
+        self.src
+    }
+
+    /// The number of bytes not yet consumed.
+    pub fn remaining(&self) -> usize {
+        self.src.len()
+    }
+
+    /// Whether the remaining bytes are the beginning of a packet that has not been fully received yet.
+    pub fn is_partial(&self) -> bool {
+        !self.failed && !self.src.is_empty() && matches!(wire::packet_len(self.src), Ok(None))
+    }
+}
+
+impl Iterator for PacketIter<'_> {
+    type Item = Result<(Packet, usize), MqttError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.src.is_empty() {
+            return None
+        }
+
+        if let Some(maximum) = self.maximum_packet_size {
+            if let Err(e) = wire::check_packet_len(self.src, maximum) {
+                self.failed = true;
+                return Some(Err(e))
+            }
+        }
+
+        // the length is needed up front to skip a packet that fails to decode
+        let len = match wire::packet_len(self.src) {
+            Ok(Some(len)) => len,
+            Ok(None) => return None,
+            Err(e) => {
+                self.failed = true;
+                return Some(Err(e))
+            },
+        };
+
+        let src = self.src;
+        self.src = &src[len..];
+
+        Some(Packet::decode_with_len(src))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, IoSlice, Write};
+
+    use crate::{
+        packet::{
+            Auth, Connect, Disconnect, DisconnectProperties, LastWill, Pingreq, Puback, PubackProperties, Publish,
+            PublishProperties, Suback, Subscribe, TopicFilter, Unsubscribe,
+        },
+        test_util::from_hex,
+        types::{QoS, UserProperties, ValidationMode},
+    };
+
+    use super::*;
+
+    #[test]
+    fn packet_type() {
+        assert_eq!(PacketType::PINGREQ, Packet::from(Pingreq {}).packet_type());
+        assert_eq!(PacketType::DISCONNECT, Packet::from(Disconnect::default()).packet_type());
+    }
+
+    #[test]
+    fn reason_codes() {
+        assert!(Packet::from(Pingreq {}).reason_codes().is_empty());
+        assert_eq!(
+            [ReasonCode::ServerBusy],
+            Packet::from(Disconnect { reason_code: ReasonCode::ServerBusy, properties: None }).reason_codes());
+
+        let codes = vec![ReasonCode::GrantedQoS1, ReasonCode::NotAuthorized];
+        let suback = Suback { packet_identifier: 1, properties: None, reason_codes: codes.clone() };
+        assert_eq!(codes, Packet::from(suback).reason_codes());
+    }
+
+    #[test]
+    fn encoded_size() {
+        let check = |packet: Packet| {
+            let size = packet.encoded_size();
+            assert_eq!(Vec::try_from(packet).unwrap().len(), size);
+            size
+        };
+
+        // a remaining length of 127 still takes one byte, 128 two
+        assert_eq!(129, check(Publish::new_unchecked("a".into(), vec![0; 123]).into()));
+        assert_eq!(131, check(Publish::new_unchecked("a".into(), vec![0; 124]).into()));
+        assert_eq!(16_386, check(Publish::new_unchecked("a".into(), vec![0; 16_379]).into()));
+        assert_eq!(16_388, check(Publish::new_unchecked("a".into(), vec![0; 16_380]).into()));
+
+        // acknowledgements leave out what they have nothing to tell with
+        assert_eq!(4, check(Puback::new(1, ReasonCode::Success).unwrap().into()));
+        let mut puback = Puback::new(1, ReasonCode::Success).unwrap();
+        puback.properties = Some(PubackProperties::default());
+        assert_eq!(4, check(puback.into()));
+        assert_eq!(6, check(Puback::new(1, ReasonCode::NoMatchingSubscribers).unwrap().into()));
+        assert_eq!(2, check(Auth { reason_code: ReasonCode::Success, properties: None }.into()));
+        assert_eq!(2, check(Pingreq {}.into()));
+
+        let mut connect = Connect::default();
+        connect.client_id = Some("client".into());
+        connect.username = Some("user".into());
+        connect.password = Some(vec![1, 2, 3]);
+        connect.will = Some(LastWill::new("will".into(), &[0; 10]).unwrap());
+        assert_eq!(51, check(connect.into()));
+
+        let subscribe = Subscribe {
+            packet_identifier: 1,
+            properties: None,
+            topic_filter: vec![TopicFilter::new_unchecked("a/+".into()), TopicFilter::new_unchecked("b/#".into())],
+        };
+        assert_eq!(17, check(subscribe.into()));
+        assert_eq!(10, check(Unsubscribe { packet_identifier: 1, properties: None, topic_filter: vec!["a/b".into()] }.into()));
+    }
+
+    #[test]
+    fn encoded_size_of_retained_properties() {
+        // a content type, then two user properties with the same key
+        let bytes = from_hex("30 18 00 01 61 12 03 00 01 78 26 00 01 6b 00 01 31 26 00 01 6b 00 01 32 68 69").unwrap();
+        let publish = Publish::decode_retaining_properties(&bytes).unwrap();
+        assert_eq!(bytes.len(), Packet::from(publish.clone()).encoded_size());
+
+        let mut changed = publish;
+        changed.properties.as_mut().unwrap().content_type = None;
+        let size = Packet::from(changed.clone()).encoded_size();
+        assert_eq!(Vec::try_from(changed).unwrap().len(), size);
+    }
+
+    #[test]
+    fn decode_from_reader() {
+        let mut src = Vec::<u8>::try_from(Packet::from(Publish::new_unchecked("a".into(), vec![0; 200]))).unwrap();
+        src.extend_from_slice(&Disconnect::NORMAL_BYTES);
+        let mut stream = io::Cursor::new(src);
+
+        let publish = Publish::try_from(&mut stream as &mut dyn Read).unwrap();
+        assert_eq!(200, publish.payload.len());
+        assert_eq!(Ok(Packet::from(Disconnect::default())), Packet::try_from(&mut stream as &mut dyn Read));
+        assert_eq!(
+            Err(MqttError::Message("Error reading packet: failed to fill whole buffer".to_string())),
+            Packet::try_from(&mut stream as &mut dyn Read));
+
+        // truncated after the remaining length
+        let mut stream = io::Cursor::new(vec![0b01000000u8, 2, 0]);
+        assert!(Puback::try_from(&mut stream as &mut dyn Read).is_err());
+
+        let mut stream = io::Cursor::new(vec![0b00110000u8, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]);
+        assert_eq!(
+            Err(MqttError::MalformedPacket("Remaining length exceeds four bytes".to_string())),
+            Packet::try_from(&mut stream as &mut dyn Read));
+    }
+
+    #[test]
+    fn decode_from_reader_oversized() {
+        /// Remembers the largest buffer it was asked to fill.
+        struct Reader {
+            inner: io::Cursor<Vec<u8>>,
+            largest_read: usize,
+        }
+
+        impl Read for Reader {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.largest_read = self.largest_read.max(buf.len());
+                self.inner.read(buf)
+            }
+        }
+
+        // announces the largest possible packet, but only a few bytes of it ever arrive
+        let src = vec![0b00110000, 0xFF, 0xFF, 0xFF, 0x7F, 0, 1, b'a'];
+        let mut reader = Reader { inner: io::Cursor::new(src), largest_read: 0 };
+        assert_eq!(
+            Err(MqttError::Message("Error reading packet: got 3 of 268435455 remaining bytes".to_string())),
+            Packet::try_from(&mut reader as &mut dyn Read));
+        assert!(reader.largest_read < 1024, "asked to read {} bytes at once", reader.largest_read);
+    }
+
+    #[test]
+    fn decode_owned() {
+        assert_eq!(Ok(Disconnect::default()), Disconnect::try_from(Disconnect::NORMAL_BYTES.to_vec()));
+    }
+
+    #[test]
+    fn decode_with_len() {
+        let mut buf: Vec<u8> = Publish::new_unchecked("a/b".into(), "hello").try_into().unwrap();
+        let publish_len = buf.len();
+        buf.extend_from_slice(&Disconnect::NORMAL_BYTES);
+
+        let (packet, len) = Packet::decode_with_len(&buf).unwrap();
+        assert!(matches!(packet, Packet::Publish(_)));
+        assert_eq!(publish_len, len);
+        assert_eq!(
+            Ok((Packet::Disconnect(Disconnect::default()), 2)),
+            Packet::decode_with_len(&buf[len..]));
+
+        assert_eq!(
+            Err(MqttError::MalformedPacket("Incomplete packet of 4 bytes".into())),
+            Packet::decode_with_len(&buf[..4]));
+        assert_eq!(Err(MqttError::MalformedPacket("Incomplete packet of 0 bytes".into())), Packet::decode_with_len(&[]));
+    }
+
+    /// Length fields count bytes, not characters: strings of multi-byte characters anywhere in a packet must leave
+    /// the remaining length matching the encoded size and decode to what was encoded.
+    #[test]
+    fn multi_byte_strings() {
+        for s in ["€", "ümlaut/ß", "日本語", "🦀/🚀", "a€b🦀c"] {
+            let mut publish = Publish::new_unchecked(s.into(), s);
+            let mut properties = PublishProperties::default();
+            properties.response_topic = Some(s.into());
+            properties.content_type = Some(s.into());
+            properties.user_property.push(s.into(), s.into());
+            publish.properties = Some(properties);
+
+            let mut puback = Puback::new(1, ReasonCode::Success).unwrap();
+            let mut properties = PubackProperties::default();
+            properties.reason_string = Some(s.into());
+            properties.user_property.push(s.into(), s.into());
+            puback.properties = Some(properties);
+
+            let mut connect = Connect::default();
+            connect.username = Some(s.into());
+            connect.will = Some(LastWill::new(s.into(), s.as_bytes()).unwrap());
+            connect.set_user_property(s.into(), s.into());
+
+            let mut disconnect = Disconnect::default();
+            disconnect.properties = Some(DisconnectProperties { reason_string: Some(s.into()), ..Default::default() });
+
+            let packets = [
+                Packet::from(publish),
+                Packet::from(puback),
+                Packet::from(connect),
+                Packet::from(disconnect),
+                Packet::from(Subscribe {
+                    packet_identifier: 1, properties: None, topic_filter: vec![TopicFilter::new_unchecked(s.into())] }),
+                Packet::from(Unsubscribe::new(1, vec![s.into()], ValidationMode::Strict).unwrap()),
+            ];
+
+            for packet in packets {
+                let description = format!("{:?}", packet);
+                let encoded = Vec::try_from(packet).unwrap();
+                let (decoded, len) = Packet::decode_with_len(&encoded).unwrap();
+                assert_eq!(encoded.len(), len, "{}", description);
+                assert_eq!(description, format!("{:?}", decoded));
+            }
+        }
+    }
+
+    #[test]
+    fn encode_for() {
+        let packet = || Packet::from(Disconnect { reason_code: ReasonCode::ServerBusy, properties: None });
+        assert_eq!(Ok(vec![0xE0, 0x02, 0x89, 0x00]), packet().encode_for(ProtocolVersion::V5));
+        assert_eq!(
+            Err(MqttError::ProtocolError("DISCONNECT reason codes are not supported by MQTT 3.1.1".into())),
+            packet().encode_for(ProtocolVersion::V3_1_1));
+        assert!(Packet::from(Pingreq {}).encode_for(ProtocolVersion::V3_1_1).is_err());
+    }
+
+    #[test]
+    fn encode_within() {
+        let user_property = UserProperties::from(vec![("key".to_string(), "value".to_string())]);
+        let disconnect = || Disconnect { reason_code: ReasonCode::ServerBusy, properties: None }
+            .with_diagnostics(Some("busy".into()), user_property.clone(), None);
+        let full = Vec::try_from(Packet::from(disconnect())).unwrap();
+
+        assert_eq!(full, Packet::from(disconnect()).encode_within(None).unwrap());
+        assert_eq!(full, Packet::from(disconnect()).encode_within(Some(full.len() as u32)).unwrap());
+
+        // the user properties go first, then the reason string
+        let fitted = Packet::from(disconnect()).encode_within(Some(full.len() as u32 - 1)).unwrap();
+        let decoded = Disconnect::try_from(&fitted[..]).unwrap().properties.unwrap();
+        assert_eq!((Some("busy".to_string()), true), (decoded.reason_string, decoded.user_property.is_empty()));
+        assert_eq!(vec![0xE0, 0x02, 0x89, 0x00], Packet::from(disconnect()).encode_within(Some(4)).unwrap());
+
+        // nothing left to leave out
+        assert_eq!(
+            Err(MqttError::PacketTooLarge { packet_type: PacketType::DISCONNECT, size: 4, maximum: 3 }),
+            Packet::from(disconnect()).encode_within(Some(3)));
+        assert_eq!(
+            Err(MqttError::PacketTooLarge { packet_type: PacketType::PUBLISH, size: 6, maximum: 4 }),
+            Packet::from(Publish::new_unchecked("a".into(), vec![])).encode_within(Some(4)));
+    }
+
+    #[test]
+    fn decode_lenient_duplicate_property() {
+        // PUBACK with the reason string twice
+        let src = from_hex("40 0C 0001 10 08 1F000161 1F000162").unwrap();
+        assert_eq!(
+            Err(MqttError::ProtocolError("Duplicate property identifier 0x1F for PubackProperties".into())),
+            Packet::try_from(&src[..]));
+
+        let decoded = Packet::decode_lenient(&src).unwrap();
+        match decoded.value {
+            Packet::Puback(p) => assert_eq!(Some("a".to_string()), p.properties.unwrap().reason_string),
+            els => panic!("Expected PUBACK, got {:?}", els),
+        }
+        assert_eq!(vec![Violation::DuplicateProperty { properties: "PubackProperties", identifier: 0x1F }], decoded.violations);
+    }
+
+    #[test]
+    fn decode_lenient_unexpected_property() {
+        // PUBACK with a session expiry interval
+        let src = from_hex("40 09 0001 10 05 11 00000001").unwrap();
+        assert!(Packet::try_from(&src[..]).is_err());
+
+        let decoded = Packet::decode_lenient(&src).unwrap();
+        match decoded.value {
+            Packet::Puback(p) => assert_eq!(Some(PubackProperties::default()), p.properties),
+            els => panic!("Expected PUBACK, got {:?}", els),
+        }
+        assert_eq!(vec![Violation::UnexpectedProperty { properties: "PubackProperties", identifier: 0x11 }], decoded.violations);
+    }
+
+    #[test]
+    fn decode_lenient_reserved_bits() {
+        let src = [0x60, 0x02, 0x00, 0x01];
+        assert!(Packet::try_from(&src[..]).is_err());
+        let decoded = Packet::decode_lenient(&src).unwrap();
+        assert_eq!(vec![Violation::ReservedBits { packet_type: PacketType::PUBREL, flags: 0 }], decoded.violations);
+
+        // nothing to tolerate
+        let decoded = Packet::decode_lenient(&Pingreq::BYTES).unwrap();
+        assert_eq!(Packet::from(Pingreq {}), decoded.value);
+        assert!(decoded.violations.is_empty());
+    }
+
+    #[test]
+    fn decode_lenient_remaining_length() {
+        // PUBACK with a byte after its properties, UNSUBSCRIBE whose topic filter runs past the remaining length
+        for (hex, packet_type, declared, decoded) in [
+            ("40 05 0001 10 00 FF", PacketType::PUBACK, 5, 4),
+            ("A2 06 0001 00 0003 612F62", PacketType::UNSUBSCRIBE, 6, 8),
+        ] {
+            let src = from_hex(hex).unwrap();
+            assert_eq!(
+                Err(MqttError::MalformedPacket(format!(
+                    "{} with a remaining length of {}, but {} bytes decoded", packet_type, declared, decoded))),
+                Packet::try_from(&src[..]));
+
+            let lenient = Packet::decode_lenient(&src).unwrap();
+            assert_eq!(packet_type, lenient.value.packet_type());
+            assert_eq!(vec![Violation::RemainingLength { packet_type, declared, decoded }], lenient.violations);
+        }
+    }
+
+    #[test]
+    fn encode_batch_concatenates() {
+        let mut buf = vec![0xFF];
+        encode_batch(packets(), &mut buf).unwrap();
+
+        let mut expected = vec![0xFF];
+        for packet in packets() {
+            expected.append(&mut packet.try_into().unwrap());
+        }
+        assert_eq!(expected, buf);
+    }
+
+    #[test]
+    fn write_batch_partial_writes() {
+        let mut writer = Trickle { written: Vec::new(), calls: 0 };
+        let total = write_batch(packets(), &mut writer).unwrap();
+
+        let mut expected = Vec::new();
+        encode_batch(packets(), &mut expected).unwrap();
+        assert_eq!(expected.len(), total);
+        assert_eq!(expected, writer.written);
+        assert!(writer.calls > 1);
+    }
+
+    #[test]
+    fn write_batch_write_zero() {
+        struct Closed;
+        impl Write for Closed {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> { Ok(0) }
+            fn flush(&mut self) -> io::Result<()> { Ok(()) }
+        }
+
+        let err = write_batch(packets(), &mut Closed).unwrap_err();
+        assert_eq!(io::ErrorKind::WriteZero, err.kind());
+    }
+
+    #[test]
+    fn decode_packet() {
+        let mut publish = Publish::new_unchecked("a/b".into(), vec![1, 2, 3]);
+        publish.qos_level = QoS::AtLeastOnce;
+        publish.packet_identifier = Some(12);
+        let bytes: Vec<u8> = publish.try_into().unwrap();
+
+        match Packet::try_from(&bytes[..]) {
+            Ok(Packet::Publish(p)) => assert_eq!(Some(12), p.packet_identifier),
+            other => panic!("expected PUBLISH, got {:?}", other),
+        }
+
+        assert_eq!(
+            Some(MqttError::MalformedPacket("Empty packet".to_string())),
+            Packet::try_from(&[][..]).err());
+    }
+
+    #[test]
+    fn iterate_packets() {
+        let mut buf = Vec::new();
+        encode_batch(packets(), &mut buf).unwrap();
+
+        let mut iter = PacketIter::new(&buf);
+        let types: Vec<(PacketType, usize)> = iter.by_ref()
+            .map(|res| res.map(|(packet, len)| (packet.packet_type(), len)).unwrap())
+            .collect();
+
+        assert_eq!(
+            vec![(PacketType::PUBLISH, 8), (PacketType::PUBLISH, 13), (PacketType::PINGREQ, 2), (PacketType::DISCONNECT, 4)], 
+            types);
+        assert!(iter.remainder().is_empty());
+        assert!(!iter.is_partial());
+    }
+
+    #[test]
+    fn iterate_within_maximum_packet_size() {
+        let mut buf = Vec::new();
+        encode_batch(packets(), &mut buf).unwrap();
+
+        // the second PUBLISH is too large once its fixed header is there, however much of the rest is
+        let too_large = MqttError::PacketTooLarge { packet_type: PacketType::PUBLISH, size: 13, maximum: 12 };
+        for end in [10, 12, buf.len()] {
+            let mut iter = PacketIter::new(&buf[..end]).maximum_packet_size(Some(12));
+            assert!(matches!(iter.next(), Some(Ok((Packet::Publish(_), 8)))));
+            assert_eq!(Some(Err(too_large.clone())), iter.next().map(|r| r.map(|_| ())));
+            assert!(iter.next().is_none());
+            assert!(!iter.is_partial());
+        }
+
+        // without the whole fixed header, there's no telling yet
+        let mut iter = PacketIter::new(&buf[..9]).maximum_packet_size(Some(12));
+        assert!(iter.nth(1).is_none());
+        assert!(iter.is_partial());
+
+        assert_eq!(4, PacketIter::new(&buf).maximum_packet_size(Some(13)).filter(Result::is_ok).count());
+    }
+
+    #[test]
+    fn iterate_partial() {
+        let mut buf = Vec::new();
+        encode_batch(packets(), &mut buf).unwrap();
+
+        // cut off in the middle of the second PUBLISH
+        let mut iter = PacketIter::new(&buf[..12]);
+        assert!(matches!(iter.next(), Some(Ok((Packet::Publish(_), 8)))));
+        assert!(iter.next().is_none());
+        assert!(iter.is_partial());
+        assert_eq!(&buf[8..12], iter.remainder());
+
+        // only the first byte of the fixed header
+        let mut iter = PacketIter::new(&buf[..1]);
+        assert!(iter.next().is_none());
+        assert!(iter.is_partial());
+
+        // incomplete remaining length
+        let mut iter = PacketIter::new(&[0b00110000, 0xFF, 0xFF]);
+        assert!(iter.next().is_none());
+        assert!(iter.is_partial());
+    }
+
+    #[test]
+    fn iterate_skips_invalid_packet() {
+        // a PINGREQ with a bogus payload, then a valid one
+        let buf = [0b11000000, 1, 0, 0b11000000, 0];
+        let mut iter = PacketIter::new(&buf);
+
+        assert!(matches!(iter.next(), Some(Err(MqttError::MalformedPacket(_)))));
+        assert!(matches!(iter.next(), Some(Ok((Packet::Pingreq(_), 2)))));
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn iterate_invalid_remaining_length() {
+        let buf = [0b11000000, 0xFF, 0xFF, 0xFF, 0xFF, 0x01];
+        let mut iter = PacketIter::new(&buf);
+
+        assert_eq!(
+            Some(MqttError::MalformedPacket("Remaining length exceeds four bytes".to_string())),
+            iter.next().and_then(Result::err));
+        assert!(iter.next().is_none());
+        assert!(!iter.is_partial());
+    }
+
+    fn packets() -> Vec<Packet> {
+        let mut qos1 = Publish::new_unchecked("a/b".into(), vec![1, 2, 3]);
+        qos1.qos_level = QoS::AtLeastOnce;
+        qos1.packet_identifier = Some(12);
+
+        vec![
+            Publish::new_unchecked("a/b".into(), vec![]).into(),
+            qos1.into(),
+            Pingreq {}.into(),
+            Disconnect::default().into(),
+        ]
+    }
+
+    /// Accepts at most 3 bytes per call
+    struct Trickle {
+        written: Vec<u8>,
+        calls: usize,
+    }
+
+    impl Write for Trickle {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.calls += 1;
+            let len = buf.len().min(3);
+            self.written.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+            let first = bufs.iter().find(|b| !b.is_empty()).map_or(&[][..], |b| &b[..]);
+            self.write(first)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}
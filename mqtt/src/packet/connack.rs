@@ -1,21 +1,23 @@
-use std::collections::HashMap;
-
 use mqtt_derive::MqttProperties;
 
-use crate::{error::MqttError, types::{MqttDataType, ReasonCode, QoS, VariableByteInteger}};
+use crate::{error::MqttError, types::{MqttDataType, ReasonCode, QoS, VariableByteInteger, UserProperties}};
 
-use super::{MqttControlPacket, PacketType, Decodeable, DecodingResult};
+use super::{MqttControlPacket, PacketType, DecodingResult, Violation};
 
-const FIRST_BYTE: u8 = 0b00100000;
+const FIRST_BYTE: u8 = PacketType::CONNACK.to_first_byte(PacketType::CONNACK.required_flags());
 /// A `CONNACK` MQTT control packet.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
 pub struct Connack {
 
     /// Whether this connect/connack exchange resumes an existing session or starts a new one.
+    #[cfg_attr(feature = "serde", serde(default))]
     pub session_present: bool,
 
     /// Indicates whether the connection attempt was successful, and if not why.
     /// [Anything above 0x80 is an error](crate::types::ReasonCode::is_err()).
+    #[cfg_attr(feature = "serde", serde(default))]
     pub reason_code: ReasonCode,
 
     /// Optional properties sent by the server.
@@ -23,7 +25,9 @@ pub struct Connack {
 }
 
 /// Sums up all properties a server may send.
-#[derive(Debug, MqttProperties)]
+#[derive(Debug, Clone, PartialEq, MqttProperties)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
 pub struct ConnackProperties {
 
     /// Server override for an interval requested by the client 
@@ -53,18 +57,18 @@ pub struct ConnackProperties {
     pub reason_string: Option<String>,
 
     /// Generic key-value properties.
-    pub user_property: HashMap<String, String>,
+    pub user_property: UserProperties,
 
-    /// 
+    /// Whether the server supports wildcard subscriptions.
     pub wildcard_subscription_available: Option<bool>,
 
-    ///
+    /// Whether the server supports subscription identifiers.
     pub subscription_identifier_available: Option<bool>,
 
-    ///
+    /// Whether the server supports shared subscriptions.
     pub shared_subscription_available: Option<bool>,
 
-    ///
+    /// Server override for the [keep alive](super::Connect::keep_alive) requested by the client.
     pub server_keep_alive: Option<u16>,
 
     /// Application-level instructions on how to build the response topic such as the base of the topic tree.
@@ -81,19 +85,337 @@ pub struct ConnackProperties {
     pub authentication_data: Option<Vec<u8>>,
 }
 
+impl Connack {
+
+    /// Starts building a `CONNACK` with `reason_code`, checking that the properties go with it. See [ConnackBuilder].
+    pub fn builder(reason_code: ReasonCode) -> ConnackBuilder {
+        ConnackBuilder::new(reason_code)
+    }
+
+    /// Adds a reason string and user properties to tell the client more about the outcome, leaving out whatever
+    /// would make the packet exceed the client's `maximum_packet_size` as the spec requires. Both replace any already
+    /// set.
+    ///
+    /// # Examples
+    /// ```
+    /// use mqtt::packet::Connack;
+    /// use mqtt::types::{ReasonCode, UserProperties};
+    ///
+    /// let connack = Connack { session_present: false, reason_code: ReasonCode::NotAuthorized, properties: None }
+    ///     .with_diagnostics(Some("Not on the guest list".into()), UserProperties::new(), Some(20));
+    /// assert!(connack.properties.unwrap().reason_string.is_none());
+    /// ```
+    pub fn with_diagnostics(
+        self,
+        reason_string: Option<String>,
+        user_property: UserProperties,
+        maximum_packet_size: Option<u32>) -> Self
+    {
+        super::fit_diagnostics(self, reason_string, user_property, maximum_packet_size, |connack, reason_string, user_property| {
+            let properties = connack.properties.get_or_insert_with(ConnackProperties::default);
+            properties.reason_string = reason_string;
+            properties.user_property = user_property;
+        })
+    }
+}
+
+/// The properties with the defaults the spec gives them if the server leaves them out, which is usually what a client
+/// needs to know. The fields themselves keep telling whether a property was sent.
+///
+/// # Examples
+/// ```
+/// use mqtt::packet::ConnackProperties;
+/// use mqtt::types::QoS;
+///
+/// let mut properties = ConnackProperties::default();
+/// assert!(properties.retain_available());
+/// assert_eq!(QoS::ExactlyOnce, properties.maximum_qos());
+///
+/// properties.retain_available = Some(false);
+/// assert!(!properties.retain_available());
+/// ```
+impl ConnackProperties {
+
+    /// The most messages with QoS 1 and 2 the server processes at once, 65,535 unless sent.
+    pub fn receive_maximum(&self) -> u16 {
+        self.receive_maximum.unwrap_or(u16::MAX)
+    }
+
+    /// The highest QoS the server supports, [QoS 2](QoS::ExactlyOnce) unless sent.
+    pub fn maximum_qos(&self) -> QoS {
+        self.maximum_qos.unwrap_or(QoS::ExactlyOnce)
+    }
+
+    /// Whether the server supports retained messages, `true` unless sent.
+    pub fn retain_available(&self) -> bool {
+        self.retain_available.unwrap_or(true)
+    }
+
+    /// The highest topic alias the server accepts, `0` unless sent, meaning none at all.
+    pub fn topic_alias_maximum(&self) -> u16 {
+        self.topic_alias_maximum.unwrap_or(0)
+    }
+
+    /// Whether the server supports wildcard subscriptions, `true` unless sent.
+    pub fn wildcard_subscription_available(&self) -> bool {
+        self.wildcard_subscription_available.unwrap_or(true)
+    }
+
+    /// Whether the server supports subscription identifiers, `true` unless sent.
+    pub fn subscription_identifier_available(&self) -> bool {
+        self.subscription_identifier_available.unwrap_or(true)
+    }
+
+    /// Whether the server supports shared subscriptions, `true` unless sent.
+    pub fn shared_subscription_available(&self) -> bool {
+        self.shared_subscription_available.unwrap_or(true)
+    }
+}
+
+/// Builds a `CONNACK` for servers, making sure reason code and properties make sense together. Whatever describes
+/// the connection only goes with [ReasonCode::Success], a server reference only with
+/// [ReasonCode::UseAnotherServer] or [ReasonCode::ServerMoved], so that a client is never told about a session it
+/// doesn't get or sent elsewhere without being refused.
+///
+/// # Examples
+/// ```
+/// use mqtt::packet::Connack;
+/// use mqtt::types::{QoS, ReasonCode};
+///
+/// let connack = Connack::builder(ReasonCode::Success)
+///     .assigned_client_identifier("auto-1")
+///     .receive_maximum(100)
+///     .maximum_qos(QoS::AtLeastOnce)
+///     .build()
+///     .unwrap();
+/// let properties = connack.properties.unwrap();
+/// assert_eq!(Some("auto-1".to_string()), properties.assigned_client_identifier);
+/// assert_eq!(Some(QoS::AtLeastOnce), properties.maximum_qos);
+///
+/// let moved = Connack::builder(ReasonCode::ServerMoved).server_reference("broker2:1883").build().unwrap();
+/// assert_eq!(Some("broker2:1883".to_string()), moved.properties.unwrap().server_reference);
+///
+/// assert!(Connack::builder(ReasonCode::NotAuthorized).session_present().build().is_err());
+/// assert!(Connack::builder(ReasonCode::NotAuthorized).assigned_client_identifier("auto-1").build().is_err());
+/// assert!(Connack::builder(ReasonCode::Success).server_reference("broker2:1883").build().is_err());
+/// ```
+#[derive(Debug, Clone)]
+pub struct ConnackBuilder {
+    connack: Connack,
+}
+
+impl ConnackBuilder {
+
+    fn new(reason_code: ReasonCode) -> Self {
+        Self { connack: Connack { session_present: false, reason_code, properties: None } }
+    }
+
+    /// Only allowed with [ReasonCode::Success] [MQTT-3.2.2-6].
+    pub fn session_present(mut self) -> Self {
+        self.connack.session_present = true;
+        self
+    }
+
+    pub fn session_expiry_interval(mut self, session_expiry_interval: u32) -> Self {
+        self.properties().session_expiry_interval = Some(session_expiry_interval);
+        self
+    }
+
+    /// Must not be `0` [MQTT-3.2.2.3.3].
+    pub fn receive_maximum(mut self, receive_maximum: u16) -> Self {
+        self.properties().receive_maximum = Some(receive_maximum);
+        self
+    }
+
+    /// Only QoS 0 and 1 can be sent, a server supporting QoS 2 leaves the property out.
+    pub fn maximum_qos(mut self, maximum_qos: QoS) -> Self {
+        self.properties().maximum_qos = Some(maximum_qos);
+        self
+    }
+
+    pub fn retain_available(mut self, retain_available: bool) -> Self {
+        self.properties().retain_available = Some(retain_available);
+        self
+    }
+
+    /// Must not be `0` [MQTT-3.2.2.3.6].
+    pub fn maximum_packet_size(mut self, maximum_packet_size: u32) -> Self {
+        self.properties().maximum_packet_size = Some(maximum_packet_size);
+        self
+    }
+
+    pub fn assigned_client_identifier(mut self, assigned_client_identifier: impl Into<String>) -> Self {
+        self.properties().assigned_client_identifier = Some(assigned_client_identifier.into());
+        self
+    }
+
+    pub fn topic_alias_maximum(mut self, topic_alias_maximum: u16) -> Self {
+        self.properties().topic_alias_maximum = Some(topic_alias_maximum);
+        self
+    }
+
+    pub fn reason_string(mut self, reason_string: impl Into<String>) -> Self {
+        self.properties().reason_string = Some(reason_string.into());
+        self
+    }
+
+    /// Adds a user property, keeping those added before even if they have the same key.
+    pub fn user_property(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.properties().user_property.push(key.into(), value.into());
+        self
+    }
+
+    pub fn wildcard_subscription_available(mut self, available: bool) -> Self {
+        self.properties().wildcard_subscription_available = Some(available);
+        self
+    }
+
+    pub fn subscription_identifier_available(mut self, available: bool) -> Self {
+        self.properties().subscription_identifier_available = Some(available);
+        self
+    }
+
+    pub fn shared_subscription_available(mut self, available: bool) -> Self {
+        self.properties().shared_subscription_available = Some(available);
+        self
+    }
+
+    pub fn server_keep_alive(mut self, server_keep_alive: u16) -> Self {
+        self.properties().server_keep_alive = Some(server_keep_alive);
+        self
+    }
+
+    pub fn response_information(mut self, response_information: impl Into<String>) -> Self {
+        self.properties().response_information = Some(response_information.into());
+        self
+    }
+
+    /// Only allowed with [ReasonCode::UseAnotherServer] or [ReasonCode::ServerMoved].
+    pub fn server_reference(mut self, server_reference: impl Into<String>) -> Self {
+        self.properties().server_reference = Some(server_reference.into());
+        self
+    }
+
+    /// The authentication method of the `CONNECT`, with the data completing the exchange if there is any.
+    pub fn authentication(mut self, method: impl Into<String>, data: Option<Vec<u8>>) -> Self {
+        let properties = self.properties();
+        properties.authentication_method = Some(method.into());
+        properties.authentication_data = data;
+        self
+    }
+
+    /// Returns the `CONNACK`, or an error if it would confuse the client: a reason code the spec doesn't list for
+    /// `CONNACK`, session present or properties describing the connection without [ReasonCode::Success]
+    /// [MQTT-3.2.2-6], a server reference without [ReasonCode::UseAnotherServer] or [ReasonCode::ServerMoved], a
+    /// maximum QoS of 2, or a receive maximum or maximum packet size of `0`.
+    pub fn build(self) -> Result<Connack, MqttError> {
+        let connack = self.connack;
+        let reason_code = connack.reason_code;
+        let error = |message: String| Err(MqttError::ProtocolError(message));
+
+        if !is_connack_reason_code(reason_code) {
+            return error(format!("Reason code not allowed in CONNACK: {:?}", reason_code))
+        }
+        if connack.session_present && reason_code != ReasonCode::Success {
+            return error(format!("Session present with reason code {:?}", reason_code))
+        }
+
+        let Some(properties) = &connack.properties else { return Ok(connack) };
+        if reason_code != ReasonCode::Success {
+            if let Some(name) = connection_property(properties) {
+                return error(format!("{} only goes with reason code Success, not {:?}", name, reason_code))
+            }
+        }
+        if properties.server_reference.is_some()
+            && !matches!(reason_code, ReasonCode::UseAnotherServer | ReasonCode::ServerMoved)
+        {
+            return error(format!("Server reference with reason code {:?}", reason_code))
+        }
+        if properties.maximum_qos == Some(QoS::ExactlyOnce) {
+            return error("Maximum QoS must be 0 or 1, a server supporting QoS 2 leaves it out".to_string())
+        }
+        if properties.receive_maximum == Some(0) {
+            return error("Receive maximum must not be 0".to_string())
+        }
+        if properties.maximum_packet_size == Some(0) {
+            return error("Maximum packet size must not be 0".to_string())
+        }
+        Ok(connack)
+    }
+
+    fn properties(&mut self) -> &mut ConnackProperties {
+        self.connack.properties.get_or_insert_with(ConnackProperties::default)
+    }
+}
+
+/// The reason codes the spec lists for `CONNACK` [MQTT-3.2.2.2].
+fn is_connack_reason_code(reason_code: ReasonCode) -> bool {
+    matches!(reason_code, ReasonCode::Success
+        | ReasonCode::UnspecifiedError
+        | ReasonCode::MalformedPacket
+        | ReasonCode::ProtocolError
+        | ReasonCode::ImplementationSpecificError
+        | ReasonCode::UnsupportedProtocolVersion
+        | ReasonCode::ClientIdentifierInvalid
+        | ReasonCode::BadUserNameOrPassword
+        | ReasonCode::NotAuthorized
+        | ReasonCode::ServerUnavailable
+        | ReasonCode::ServerBusy
+        | ReasonCode::Banned
+        | ReasonCode::BadAuthenticationMethod
+        | ReasonCode::TopicNameInvalid
+        | ReasonCode::PacketTooLarge
+        | ReasonCode::QuotaExceeded
+        | ReasonCode::PayloadFormatInvalid
+        | ReasonCode::RetainNotSupported
+        | ReasonCode::QoSNotSupported
+        | ReasonCode::UseAnotherServer
+        | ReasonCode::ServerMoved
+        | ReasonCode::ConnectionRateExceeded)
+}
+
+/// The name of the first property set that describes an established connection, which a refused client has no use
+/// for.
+fn connection_property(properties: &ConnackProperties) -> Option<&'static str> {
+    [
+        ("Session expiry interval", properties.session_expiry_interval.is_some()),
+        ("Receive maximum", properties.receive_maximum.is_some()),
+        ("Maximum QoS", properties.maximum_qos.is_some()),
+        ("Retain available", properties.retain_available.is_some()),
+        ("Maximum packet size", properties.maximum_packet_size.is_some()),
+        ("Assigned client identifier", properties.assigned_client_identifier.is_some()),
+        ("Topic alias maximum", properties.topic_alias_maximum.is_some()),
+        ("Wildcard subscription available", properties.wildcard_subscription_available.is_some()),
+        ("Subscription identifier available", properties.subscription_identifier_available.is_some()),
+        ("Shared subscription available", properties.shared_subscription_available.is_some()),
+        ("Server keep alive", properties.server_keep_alive.is_some()),
+        ("Response information", properties.response_information.is_some()),
+    ].into_iter().find(|(_, set)| *set).map(|(name, _)| name)
+}
+
 impl TryFrom<&[u8]> for Connack {
-    
     type Error = MqttError;
 
     fn try_from(src: &[u8]) -> Result<Self, Self::Error> {
+        Self::decode_from(src, None)
+    }
+}
+
+impl Connack {
+
+    /// Decodes strictly, or leniently if there are `violations` to record what was tolerated in.
+    pub(crate) fn decode_from(src: &[u8], mut violations: Option<&mut Vec<Violation>>) -> Result<Self, MqttError> {
         if src[0] != FIRST_BYTE {
             return Err(MqttError::MalformedPacket(format!("First byte not a CONNACK packet: {:08b}", src[0])))
         }
 
-        let remaining_length = VariableByteInteger::try_from(&src[1..5])?;
+        let remaining_length = VariableByteInteger::try_from(&src[1..src.len().min(5)])?;
 
         // the index where the Variable Header begins
         let mut index = remaining_length.encoded_len() + 1;
+        if src.len() < index + 2 {
+            return Err(MqttError::MalformedPacket(format!("CONNACK too short for its variable header: {}", src.len())))
+        }
         
         // TODO should we actually do something with the session present flag if it is set? check the spec
         let session_present = src[index] != 0;
@@ -102,15 +424,18 @@ impl TryFrom<&[u8]> for Connack {
         let reason_code = ReasonCode::try_from(src[index])?;
         index += 1;
 
-        let prop_res: DecodingResult<ConnackProperties> = ConnackProperties::decode(&src[index..])?;
+        let prop_res: DecodingResult<ConnackProperties> = super::decode_properties(&src[index..], violations.as_deref_mut())?;
+        index += prop_res.bytes_read();
+        super::check_remaining_length(PacketType::CONNACK, &remaining_length, index, &mut violations)?;
 
         Ok(Connack { session_present, reason_code, properties: prop_res.value() })
     }
 }
 
-impl From<Connack> for Vec<u8> {
-    
-    fn from(connack: Connack) -> Self {
+impl TryFrom<Connack> for Vec<u8> {
+    type Error = MqttError;
+
+    fn try_from(connack: Connack) -> Result<Self, Self::Error> {
         let mut packet: Vec<u8> = Vec::new();
 
         packet.push(FIRST_BYTE);
@@ -119,7 +444,7 @@ impl From<Connack> for Vec<u8> {
         
         match connack.properties {
             Some(props) => {
-                packet.append(&mut props.into());
+                packet.append(&mut props.try_into()?);
             },
             None => {
                 packet.push(0)
@@ -128,7 +453,7 @@ impl From<Connack> for Vec<u8> {
 
         super::calculate_and_insert_length(&mut packet);
 
-        packet
+        Ok(packet)
     }
 }
 
@@ -146,32 +471,138 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn spec_defaults() {
+        let properties = ConnackProperties::default();
+        assert_eq!(u16::MAX, properties.receive_maximum());
+        assert_eq!(QoS::ExactlyOnce, properties.maximum_qos());
+        assert_eq!(0, properties.topic_alias_maximum());
+        assert!(properties.retain_available());
+        assert!(properties.wildcard_subscription_available());
+        assert!(properties.subscription_identifier_available());
+        assert!(properties.shared_subscription_available());
+
+        let properties = ConnackProperties {
+            receive_maximum: Some(10),
+            maximum_qos: Some(QoS::AtLeastOnce),
+            topic_alias_maximum: Some(5),
+            retain_available: Some(false),
+            wildcard_subscription_available: Some(false),
+            subscription_identifier_available: Some(false),
+            shared_subscription_available: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(10, properties.receive_maximum());
+        assert_eq!(QoS::AtLeastOnce, properties.maximum_qos());
+        assert_eq!(5, properties.topic_alias_maximum());
+        assert!(!properties.retain_available());
+        assert!(!properties.wildcard_subscription_available());
+        assert!(!properties.subscription_identifier_available());
+        assert!(!properties.shared_subscription_available());
+    }
+
+    #[test]
+    fn with_diagnostics() {
+        let connack = || Connack { session_present: false, reason_code: ReasonCode::Banned, properties: None };
+        let user_property = UserProperties::from([("retry".to_string(), "never".to_string())]);
+        let reason = || Some("Banned".to_string());
+
+        // 5 bytes without properties, the reason string takes 9 and the user property 15
+        let fits = |max| connack().with_diagnostics(reason(), user_property.clone(), Some(max)).properties.unwrap();
+        let all = fits(29);
+        assert_eq!((reason(), 1), (all.reason_string, all.user_property.len()));
+        let reason_only = fits(28);
+        assert_eq!((reason(), 0), (reason_only.reason_string, reason_only.user_property.len()));
+        let neither = fits(13);
+        assert_eq!((None, 0), (neither.reason_string, neither.user_property.len()));
+
+        let unlimited = connack().with_diagnostics(reason(), user_property.clone(), None);
+        assert_eq!(29, Vec::try_from(unlimited).unwrap().len());
+    }
+
+    #[test]
+    fn builder() {
+        let connack = Connack::builder(ReasonCode::Success)
+            .session_present()
+            .session_expiry_interval(60)
+            .topic_alias_maximum(0)
+            .retain_available(false)
+            .server_keep_alive(30)
+            .authentication("SCRAM-SHA-1", Some(vec![0xCA, 0xFE]))
+            .user_property("region", "eu")
+            .build()
+            .unwrap();
+        assert!(connack.session_present);
+        let properties = connack.properties.unwrap();
+        assert_eq!((Some(60), Some(0), Some(false)), (
+            properties.session_expiry_interval, properties.topic_alias_maximum, properties.retain_available));
+        assert_eq!(Some("SCRAM-SHA-1".to_string()), properties.authentication_method);
+        assert_eq!(Some(vec![0xCA, 0xFE]), properties.authentication_data);
+        assert_eq!(Some("eu"), properties.user_property.get("region"));
+
+        let refused = Connack::builder(ReasonCode::Banned).reason_string("Banned").build().unwrap();
+        assert_eq!(Connack { session_present: false, reason_code: ReasonCode::Banned, properties: Some(ConnackProperties {
+            reason_string: Some("Banned".into()),
+            ..Default::default()
+        })}, refused);
+        assert_eq!(None, Connack::builder(ReasonCode::Success).build().unwrap().properties);
+        assert!(Connack::builder(ReasonCode::UseAnotherServer).server_reference("other").build().is_ok());
+    }
+
+    #[test]
+    fn builder_errors() {
+        let error = |builder: ConnackBuilder, message: &str| {
+            assert_eq!(Err(MqttError::ProtocolError(message.to_string())), builder.build());
+        };
+        error(Connack::builder(ReasonCode::ContinueAuthentication), "Reason code not allowed in CONNACK: ContinueAuthentication");
+        error(Connack::builder(ReasonCode::ServerBusy).session_present(), "Session present with reason code ServerBusy");
+        error(
+            Connack::builder(ReasonCode::NotAuthorized).assigned_client_identifier("auto-1"),
+            "Assigned client identifier only goes with reason code Success, not NotAuthorized");
+        error(
+            Connack::builder(ReasonCode::ServerMoved).server_reference("other").server_keep_alive(10),
+            "Server keep alive only goes with reason code Success, not ServerMoved");
+        error(Connack::builder(ReasonCode::Success).server_reference("other"), "Server reference with reason code Success");
+        error(
+            Connack::builder(ReasonCode::Success).maximum_qos(QoS::ExactlyOnce),
+            "Maximum QoS must be 0 or 1, a server supporting QoS 2 leaves it out");
+        error(Connack::builder(ReasonCode::Success).receive_maximum(0), "Receive maximum must not be 0");
+        error(Connack::builder(ReasonCode::Success).maximum_packet_size(0), "Maximum packet size must not be 0");
+    }
+
+    #[test]
+    fn decode_maximum_packet_size_zero() {
+        assert_eq!(
+            Some(MqttError::ProtocolError("Property MaximumPacketSize (0x27) must not be 0".to_string())),
+            Connack::try_from(&[32, 8, 0, 0, 5, 39, 0, 0, 0, 0][..]).err());
+    }
+
     #[test]
     fn decode() -> Result<(), MqttError>{
         // the simplest of successful CONNACKs
         run_decode(
-            &vec![32, 3, 0, 0, 0], 
+            &[32, 3, 0, 0, 0], 
             false, 
             ReasonCode::Success,
             false)?;
 
         // session present flag set
         run_decode(
-            &vec![32, 3, 1, 0, 0], 
+            &[32, 3, 1, 0, 0], 
             true, 
             ReasonCode::Success,
             false)?;
 
         // Reason code: error
         run_decode(
-            &vec![32, 3, 0, 0x80, 0], 
+            &[32, 3, 0, 0x80, 0], 
             false, 
             ReasonCode::UnspecifiedError,
             false)?;
 
         // Reason Code: Bad Auth
         run_decode(
-            &vec![32, 3, 1, 0x8C, 0], // bad authentication
+            &[32, 3, 1, 0x8C, 0], // bad authentication
             true, 
             ReasonCode::BadAuthenticationMethod,
             false)?;
@@ -195,7 +626,7 @@ mod tests {
     #[test]
     fn encode() {
         let connack = Connack { session_present: false, reason_code: ReasonCode::Success, properties: None };
-        let bin: Vec<u8> = connack.into();
+        let bin: Vec<u8> = connack.try_into().unwrap();
         let expected = vec![32, 3, 0, 0, 0];
         assert_eq!(expected, bin);
     }
@@ -206,11 +637,25 @@ mod tests {
         properties.assigned_client_identifier = Some("generated-123456".into());
         properties.server_keep_alive = Some(135);
         let connack = Connack { session_present: true, reason_code: ReasonCode::Success, properties: Some(properties) };
-        let actual: Vec<u8> = connack.into();
+        let actual: Vec<u8> = connack.try_into().unwrap();
         let expect: Vec<u8> = vec![32, 25, 1, 0, 22, 18, 0, 16, 103, 101, 110, 101, 114, 97, 116, 101, 100, 45, 49, 50, 51, 52, 53, 54, 19, 0, 135];
         assert_eq!(expect, actual);
     }
 
+    #[test]
+    fn continue_authentication() {
+        let mut properties = ConnackProperties::default();
+        properties.authentication_method = Some("SCRAM-SHA-1".into());
+        properties.authentication_data = Some(vec![0xCA, 0xFE]);
+        let connack = Connack { session_present: false, reason_code: ReasonCode::ContinueAuthentication, properties: Some(properties) };
+
+        let encoded: Vec<u8> = connack.try_into().unwrap();
+        let decoded = run_decode(&encoded, false, ReasonCode::ContinueAuthentication, true).unwrap();
+        let properties = decoded.properties.unwrap();
+        assert_eq!(Some("SCRAM-SHA-1".to_string()), properties.authentication_method);
+        assert_eq!(Some(vec![0xCA, 0xFE]), properties.authentication_data);
+    }
+
     fn run_decode(binary: &[u8], session_present: bool, reason_code: ReasonCode, expect_properties: bool) -> Result<Connack, MqttError> {
         let connack = Connack::try_from(binary)?;
 
@@ -1,27 +1,30 @@
 //! Types representing the `CONNECT` control packet, which must be the first packet sent by a client when making *or*
 //! re-establishing a server connection.
 
-use std::collections::HashMap;
+use std::time::Duration;
 
 use mqtt_derive::MqttProperties;
 
-use crate::{error::MqttError, types::{QoS, BinaryData, UTF8String, MqttDataType}};
+use crate::{error::MqttError, types::{validate_topic, BinaryData, MqttDataType, QoS, TopicName, UTF8String, ValidationMode, UserProperties}};
 
-use super::{MqttControlPacket, PacketType, Decodeable, DecodingResult, remaining_length};
+use super::{
+    properties::check_authentication, MqttControlPacket, PacketType, ProtocolVersion, Decodeable, DecodingResult,
+    remaining_length, Violation,
+};
 
 /// 23 characters. The spec says longer client IDs _may_ be used, depending on the server, but servers are not
 /// required to, so we'll just cap it there for now.
 pub const CLIENT_ID_MAX_LENGTH: usize = 23;
 
 /// The static first byte of a CONNECT packet.
-const FIRST_BYTE: u8 = 0b00010000;
+const FIRST_BYTE: u8 = PacketType::CONNECT.to_first_byte(PacketType::CONNECT.required_flags());
 
 /// The first 6 bytes of the variable header are, ironically, static.
 const PROTO_NAME: [u8; 6] = [0, 4, 77, 81, 84, 84];
 
 /// For now we're only supporting MQTT5
 /// TODO add 3.1.1 and 3.1
-const PROTO_LEVEL: u8 = 5;
+const PROTO_LEVEL: u8 = ProtocolVersion::V5.level();
 
 /// A `CONNECT` MQTT control packet with support for encoding into and decoding from its binary format.
 /// 
@@ -36,7 +39,7 @@ const PROTO_LEVEL: u8 = 5;
 /// 
 /// // add more stuff here...
 /// 
-/// let encoded: Vec<u8> = packet.into();
+/// let encoded: Vec<u8> = packet.try_into().unwrap();
 /// 
 /// let decoded = Connect::try_from(&encoded[..]).unwrap();
 /// assert_eq!(77, decoded.keep_alive);
@@ -53,14 +56,20 @@ const PROTO_LEVEL: u8 = 5;
 /// 
 /// See the [MQTT spec](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901033) for details on
 /// the binary format.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
 pub struct Connect {
     
     /// Starting with version 5, MQTT allows sending an empty client ID, in which case one will be appointed by the 
     /// server, which must be used. See [CONNACK](super::Connack) for details.
+    ///
+    /// The client ID is always part of the packet, so there's no telling an absent one from an empty one: `None` and
+    /// `Some("")` are both sent as zero length, which decodes as `None`. See [Connect::requests_client_id].
     pub client_id: Option<String>,
 
     /// FIXME this really should be bounded as an enum or similar
+    #[cfg_attr(feature = "serde", serde(skip))]
     protocol_level: u8,
 
     /// Number of seconds before the server closes the connection unless the client has sent another packet.
@@ -77,15 +86,18 @@ pub struct Connect {
     /// inactivity.
     pub will: Option<LastWill>,
 
-    /// Authentication towards the server.
+    /// Authentication towards the server. `None` leaves the user name out along with its flag, whereas `Some("")`
+    /// sends an empty one, which is not the same to the server.
     pub username: Option<String>,
 
-    /// Authentication towards the server.
+    /// Authentication towards the server. Like the user name, `None` leaves it out and an empty one is sent as such.
     pub password: Option<Vec<u8>>,
 }
 
 /// Optional property values for the `CONNECT` packet.
-#[derive(Debug, PartialEq, MqttProperties)]
+#[derive(Debug, Clone, PartialEq, MqttProperties)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
 pub struct ConnectProperties {
     /// How long a previously established session may be picked up after connection loss in seconds.
     /// Defaults to '0'.
@@ -112,7 +124,7 @@ pub struct ConnectProperties {
     pub request_problem_information: Option<bool>,
 
     /// Application-specific key-value elements.
-    pub user_property: HashMap<String, String>,
+    pub user_property: UserProperties,
 
     /// Application-specific auth method definition.
     pub authentication_method: Option<String>,
@@ -123,12 +135,16 @@ pub struct ConnectProperties {
 
 /// An MQTT message (including properties) that is published by the broker in case it "loses" connection to the client.
 /// The client specifies topic, payload and properties with the connection itself.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
 pub struct LastWill {
     /// Quality of Service for the will message.
+    #[cfg_attr(feature = "serde", serde(default))]
     pub qos: QoS,
 
     /// Whether or not the server should retain the will message after publishing it.
+    #[cfg_attr(feature = "serde", serde(default))]
     pub retain: bool,
 
     pub properties: Option<WillProperties>,
@@ -138,10 +154,13 @@ pub struct LastWill {
 
     /// The actual will message, in an application-specific format.
     /// Also see [WillProperties::payload_format_indicator] and [WillProperties::content_type].
+    #[cfg_attr(feature = "serde", serde(default))]
     pub will_payload: Vec<u8>,
 }
 
-#[derive(Debug, PartialEq, MqttProperties)]
+#[derive(Debug, Clone, PartialEq, MqttProperties)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
 pub struct WillProperties {
     
     /// The grace period (in seconds) after the server has determined it has lost connection to the client before it 
@@ -168,12 +187,26 @@ pub struct WillProperties {
     pub correlation_data: Option<Vec<u8>>,
 
     /// Name of the topic to publish this will message to.
-    pub user_property: HashMap<String, String>,
+    pub user_property: UserProperties,
 }
 
+/// The will section of the `CONNECT` payload: will properties, topic and payload, in that order.
+/// QoS and retain of the will are part of the [ConnectFlags] instead.
+/// 
 /// This is used internally during encoding and decoding only.
 #[derive(Debug, PartialEq)]
-struct ConnectFlags {
+struct WillBlock {
+    properties: Option<WillProperties>,
+    topic: String,
+    payload: Vec<u8>,
+}
+
+/// The connect flags byte of the variable header, which says what the payload of a `CONNECT` holds.
+///
+/// Nothing to set when sending, encoding derives them from the packet. For servers that want to check what a client
+/// sent, see [Connect::flags].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConnectFlags {
     /// If a CONNECT packet is received with Clean Start is set to 1, the Client and Server MUST discard any existing 
     /// Session and start a new Session CONNACK is always set to 0 if Clean Start is set to 1.
     /// 
@@ -181,32 +214,32 @@ struct ConnectFlags {
     /// Identifier, the Server MUST resume communications with the Client based on state from the existing Session 
     /// [MQTT-3.1.2-5]. If a CONNECT packet is received with Clean Start set to 0 and there is no Session associated 
     /// with the Client Identifier, the Server MUST create a new Session [MQTT-3.1.2-6].
-    clean_start: bool,
+    pub clean_start: bool,
 
     ///  If the Will Flag is set to 1, the Will Properties, Will Topic, and Will Payload fields MUST be present in the 
     /// Payload [MQTT-3.1.2-9]. The Will Message MUST be removed from the stored Session State in the Server once it 
     /// has been published or the Server has received a DISCONNECT packet with a Reason Code of 0x00 (Normal 
     /// disconnection) from the Client.
-    will_flag: bool,
+    pub will_flag: bool,
 
     ///  If the Will Flag is set to 0, then the Will QoS MUST be set to 0 (0x00) [MQTT-3.1.2-11]. 
     /// If the Will Flag is set to 1, the value of Will QoS can be 0 (0x00), 1 (0x01) or 2 (0x02) [MQTT-3.1.2-12]. 
     /// A value of 3 (0x03) is a Malformed Packet.
-    will_qos: Option<QoS>,
+    pub will_qos: Option<QoS>,
 
     /// If the Will Flag is set to 0, then Will Retain MUST be set to 0 [MQTT-3.1.2-13]. If the Will Flag is set to 1 
     /// and Will Retain is set to 0, the Server MUST publish the Will Message as a non-retained message 
     /// [MQTT-3.1.2-14]. If the Will Flag is set to 1 and Will Retain is set to 1, the Server MUST publish the Will 
     /// Message as a retained message [MQTT-3.1.2-15].
-    will_retain: bool,
+    pub will_retain: bool,
 
     /// If the Password Flag is set to 0, a Password MUST NOT be present in the Payload [MQTT-3.1.2-18]. 
     /// If the Password Flag is set to 1, a Password MUST be present in the Payload [MQTT-3.1.2-19].
-    password_flag: bool,
+    pub password_flag: bool,
 
     /// If the User Name Flag is set to 0, a User Name MUST NOT be present in the Payload [MQTT-3.1.2-16]. 
     /// If the User Name Flag is set to 1, a User Name MUST be present in the Payload [MQTT-3.1.2-17].
-    username_flag: bool,
+    pub username_flag: bool,
 }
 
 impl Connect {
@@ -215,9 +248,7 @@ impl Connect {
     /// packet along with that id.
     pub fn with_client_id(client_id: String) -> Result<Self, MqttError> {
         validate_client_id(&client_id)?;
-        let mut packet = Connect::default();
-        packet.client_id = Some(client_id);
-        Ok(packet)
+        Ok(Connect { client_id: Some(client_id), ..Default::default() })
     }
 
     /// Convenience for `with_client_id(client_id.to_string())`.
@@ -226,10 +257,35 @@ impl Connect {
         Self::with_client_id(client_id.to_string())
     }
 
-    /// Inserts or updates a `user property`.
+    /// Adds a `user property`, after the ones already set.
     pub fn set_user_property(&mut self, key: String, value: String) {
         let props = self.properties.get_or_insert(ConnectProperties::default());
-        props.user_property.insert(key, value);
+        props.user_property.push(key, value);
+    }
+
+    /// Whether the client leaves it to the server to assign a client ID, by sending an empty one.
+    pub fn requests_client_id(&self) -> bool {
+        self.client_id.as_deref().unwrap_or_default().is_empty()
+    }
+
+    /// The connect flags this packet was received with, or is sent with: what the payload holds besides the client
+    /// identifier, along with QoS and retain of the will. Decoding keeps everything the flags announce, so these are
+    /// the same as the ones on the wire, an empty user name or password included.
+    pub fn flags(&self) -> ConnectFlags {
+        ConnectFlags::build(self)
+    }
+
+    /// Sets the keep alive in whole seconds, at most `u16::MAX` of them. Zero turns the keep alive mechanism off.
+    pub fn set_keep_alive(&mut self, keep_alive: Duration) -> Result<(), MqttError> {
+        self.keep_alive = super::interval_secs("Keep alive", keep_alive, u16::MAX.into())? as u16;
+        Ok(())
+    }
+
+    /// Sets the session expiry interval in whole seconds. `u32::MAX` seconds means the session doesn't expire.
+    pub fn set_session_expiry(&mut self, session_expiry: Duration) -> Result<(), MqttError> {
+        let secs = super::interval_secs("Session expiry interval", session_expiry, u32::MAX)?;
+        self.properties.get_or_insert_with(ConnectProperties::default).session_expiry_interval = Some(secs);
+        Ok(())
     }
 }
 
@@ -256,9 +312,14 @@ impl Default for Connect {
     }
 }
 
-impl From<Connect> for Vec<u8> {
+impl TryFrom<Connect> for Vec<u8> {
+    type Error = MqttError;
+
+    fn try_from(mut src: Connect) -> Result<Self, Self::Error> {
+        if let Some(p) = src.properties.as_mut() {
+            check_authentication("ConnectProperties", &p.authentication_method, &mut p.authentication_data, &mut None)?;
+        }
 
-    fn from(src: Connect) -> Self {
         let mut packet: Vec<u8> = Vec::new();
 
         // fixed header
@@ -283,7 +344,7 @@ impl From<Connect> for Vec<u8> {
         
         // properties
         if let Some(p) = src.properties {
-            packet.append(&mut p.into())
+            packet.append(&mut p.try_into()?)
         } else {
             packet.push(0)
         }
@@ -293,33 +354,23 @@ impl From<Connect> for Vec<u8> {
             Some(s) => UTF8String::from(s),
             None => UTF8String::new(),
         };
-        packet.append(&mut client_id.into());
+        packet.append(&mut client_id.try_into()?);
 
         if let Some(will) = src.will {
-            // FIXME include will properties, for now we're just setting them to '0' length
-            match will.properties {
-                Some(props) => packet.append(&mut props.into()),
-                None => packet.push(0),
-            }
-
-            packet.append(&mut UTF8String::from(will.will_topic).into());
-            // FIXME just letting this panic isn't really elegant
-            let payload = BinaryData::new(will.will_payload).unwrap();
-            packet.append(&mut payload.into());
+            packet.append(&mut WillBlock::from(will).try_into()?);
         }
 
         if let Some(uname) = src.username {
-            packet.append(&mut UTF8String::from(uname).into());
+            packet.append(&mut UTF8String::from(uname).try_into()?);
         }
 
         if let Some(pwd) = src.password {
-            let password = BinaryData::new(pwd).unwrap();
-            packet.append(&mut password.into());
+            packet.append(&mut BinaryData::from(pwd).try_into()?);
         }
 
         super::calculate_and_insert_length(&mut packet);
 
-        packet        
+        Ok(packet)
     }
 }
 
@@ -327,6 +378,14 @@ impl TryFrom<&[u8]> for Connect {
     type Error = MqttError;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        Self::decode_from(value, None)
+    }
+}
+
+impl Connect {
+
+    /// Decodes strictly, or leniently if there are `violations` to record what was tolerated in.
+    pub(crate) fn decode_from(value: &[u8], mut violations: Option<&mut Vec<Violation>>) -> Result<Self, MqttError> {
         let mut packet = Connect::default();
         let mut cursor: usize = 0;
 
@@ -338,7 +397,10 @@ impl TryFrom<&[u8]> for Connect {
         let remaining_length = remaining_length(&value[cursor..])?;
         cursor += remaining_length.encoded_len();
 
-        // protocol name and level
+        // protocol name and level, followed by at least the connect flags and keep alive
+        if value.len() < cursor + 10 {
+            return Err(MqttError::MalformedPacket(format!("CONNECT too short for its variable header: {}", value.len())))
+        }
         let mut cursor_stop = cursor + 6;
         let proto_name = &value[cursor..cursor_stop];
         cursor = cursor_stop;
@@ -354,18 +416,17 @@ impl TryFrom<&[u8]> for Connect {
         cursor += 1;
         cursor_stop = cursor + 2;
 
-        //let mut keep_alive: u16 = 0;
-        let keep_alive: u16 = match value[cursor..cursor_stop].try_into() {
-            Ok(a) => u16::from_be_bytes(a),
-            Err(e) => return Err(MqttError::Message(format!("Error reading [keep alive]: {:?}", e))),
-        };
-        packet.keep_alive = keep_alive;
+        packet.keep_alive = super::u16_from_be_bytes(&value[cursor..])?;
 
         // Properties
         cursor = cursor_stop;
-        let prop_res: DecodingResult<ConnectProperties> = ConnectProperties::decode(&value[cursor..])?;
+        let prop_res: DecodingResult<ConnectProperties> = super::decode_properties(&value[cursor..], violations.as_deref_mut())?;
         cursor += prop_res.bytes_read();
         packet.properties = prop_res.value();
+        if let Some(p) = packet.properties.as_mut() {
+            check_authentication(
+                "ConnectProperties", &p.authentication_method, &mut p.authentication_data, &mut violations)?;
+        }
 
         // PAYLOAD
         // The Payload of the CONNECT packet contains one or more length-prefixed fields, whose presence is determined 
@@ -381,37 +442,19 @@ impl TryFrom<&[u8]> for Connect {
         // clientID
         let client_id = UTF8String::try_from(&value[cursor..])?; 
         cursor += client_id.encoded_len();
-        packet.client_id = client_id.value;
+        packet.client_id = client_id.value.filter(|id| !id.is_empty());
         
         if flags.will_flag {
-            // Will Properties
-            let will_props_res: DecodingResult<WillProperties> = WillProperties::decode(&value[cursor..])?;
-            cursor += will_props_res.bytes_read();
-
-            // Will Topic
-            let will_topic = UTF8String::try_from(&value[cursor..])?;
-            cursor += will_topic.encoded_len();
-
-            // Will Payload
-            let will_payload = BinaryData::try_from(&value[cursor..])?;
-            cursor += will_payload.encoded_len();
-
-            let will = LastWill { 
-                qos: flags.will_qos.unwrap_or(QoS::AtLeastOnce),
-                retain: flags.will_retain,
-                properties: will_props_res.value(),
-                will_topic: will_topic.into(), 
-                will_payload: will_payload.clone_inner(),
-            };
-            packet.will = Some(will);
+            let will_res = super::decode_properties::<WillBlock>(&value[cursor..], violations.as_deref_mut())?;
+            cursor += will_res.bytes_read();
+            packet.will = will_res.value().map(|block| block.into_last_will(&flags));
         }
 
         if flags.username_flag {
+            // the flag is what says it's there, an empty one included
             let username = UTF8String::try_from(&value[cursor..])?;
             cursor += username.encoded_len();
-            if let Some(uname) = username.value {
-                packet.username = Some(uname)
-            }
+            packet.username = Some(username.value.unwrap_or_default());
         }
 
         if flags.password_flag {
@@ -420,11 +463,7 @@ impl TryFrom<&[u8]> for Connect {
             packet.password = Some(pwd.clone_inner());
         }
 
-        // the cursor should be at the end of the slice now
-        let remaining = value.len() - cursor;
-        if remaining > 0 {
-            println!("Done parsing CONNECT packet, there are {} bytes left in the input slice", remaining);
-        }
+        super::check_remaining_length(PacketType::CONNECT, &remaining_length, cursor, &mut violations)?;
 
         Ok(packet)
     }
@@ -432,14 +471,113 @@ impl TryFrom<&[u8]> for Connect {
 
 impl LastWill {
 
+    /// Creates a will with QoS 1 and no properties. Returns an error if `topic` isn't a valid [TopicName] in
+    /// [strict mode](ValidationMode::Strict).
     pub fn new(topic: String, payload: &[u8]) -> Result<Self, MqttError> {
+        let topic = TopicName::new(topic, ValidationMode::Strict)?;
         Ok(LastWill { 
             qos: QoS::AtLeastOnce, 
             retain: false,
             properties: None,
-            will_topic: topic, 
+            will_topic: topic.into(), 
             will_payload: payload.to_vec() })
     }
+
+    /// Sets `text` as the payload and marks it as UTF-8 with the payload format indicator, which makes the server
+    /// publish it as such.
+    pub fn set_text_payload(&mut self, text: &str) {
+        self.will_payload = text.as_bytes().to_vec();
+        self.properties.get_or_insert_with(WillProperties::default).payload_format_indicator = Some(true);
+    }
+
+    /// Sets how long the server waits in whole seconds after losing the connection before publishing the will.
+    pub fn set_will_delay(&mut self, will_delay: Duration) -> Result<(), MqttError> {
+        let secs = super::interval_secs("Will delay interval", will_delay, u32::MAX)?;
+        self.properties.get_or_insert_with(WillProperties::default).will_delay_interval = Some(secs);
+        Ok(())
+    }
+
+    /// Sets the lifetime of the will message in whole seconds.
+    pub fn set_message_expiry(&mut self, message_expiry: Duration) -> Result<(), MqttError> {
+        let secs = super::interval_secs("Message expiry interval", message_expiry, u32::MAX)?;
+        self.properties.get_or_insert_with(WillProperties::default).message_expiry_interval = Some(secs);
+        Ok(())
+    }
+}
+
+impl WillBlock {
+
+    /// Combines the block with QoS and retain from the `flags`.
+    fn into_last_will(self, flags: &ConnectFlags) -> LastWill {
+        LastWill {
+            qos: flags.will_qos.unwrap_or(QoS::AtLeastOnce),
+            retain: flags.will_retain,
+            properties: self.properties,
+            will_topic: self.topic,
+            will_payload: self.payload,
+        }
+    }
+}
+
+impl From<LastWill> for WillBlock {
+    fn from(will: LastWill) -> Self {
+        WillBlock { properties: will.properties, topic: will.will_topic, payload: will.will_payload }
+    }
+}
+
+impl TryFrom<WillBlock> for Vec<u8> {
+    type Error = MqttError;
+
+    fn try_from(block: WillBlock) -> Result<Self, Self::Error> {
+        let utf8 = block.properties.as_ref().is_some_and(|p| p.payload_format_indicator == Some(true));
+        if utf8 && std::str::from_utf8(&block.payload).is_err() {
+            return Err(MqttError::PayloadFormatInvalid(
+                "Will payload is not valid UTF-8, but its payload format indicator says it is".into()))
+        }
+
+        // the fields of a LastWill are public, so the topic may not have gone through LastWill::new
+        validate_topic(&block.topic, ValidationMode::Lenient)?;
+
+        let mut result = Vec::new();
+
+        match block.properties {
+            Some(props) => result.append(&mut props.try_into()?),
+            None => result.push(0),
+        }
+
+        result.append(&mut UTF8String::from(block.topic).try_into()?);
+        result.append(&mut BinaryData::from(block.payload).try_into()?);
+
+        Ok(result)
+    }
+}
+
+impl Decodeable for WillBlock {
+    fn decode(src: &[u8]) -> Result<DecodingResult<Self>, MqttError> {
+        Self::decode_with(src, None)
+    }
+
+    fn decode_lenient(src: &[u8], violations: &mut Vec<Violation>) -> Result<DecodingResult<Self>, MqttError> {
+        Self::decode_with(src, Some(violations))
+    }
+}
+
+impl WillBlock {
+    fn decode_with(src: &[u8], violations: Option<&mut Vec<Violation>>) -> Result<DecodingResult<Self>, MqttError> {
+        let props_res: DecodingResult<WillProperties> = super::decode_properties(src, violations)?;
+        let mut cursor = props_res.bytes_read();
+
+        let topic = UTF8String::try_from(&src[cursor..])?;
+        cursor += topic.encoded_len();
+        let topic: String = topic.into();
+        validate_topic(&topic, ValidationMode::Lenient)?;
+
+        let payload = BinaryData::try_from(&src[cursor..])?;
+        cursor += payload.encoded_len();
+
+        let block = WillBlock { properties: props_res.value(), topic, payload: payload.clone_inner() };
+        Ok(DecodingResult { bytes_read: cursor, value: Some(block) })
+    }
 }
 
 impl ConnectFlags {
@@ -448,13 +586,10 @@ impl ConnectFlags {
     const WILL_RETAIN_MASK: u8 = 0b00100000;
     const PASSWORD_MASK: u8 = 0b01000000;
     const USERNAME_MASK: u8 = 0b10000000;
-    const WILL_QOS_MASK: u8 = 0b00000011;
     const WILL_QOS_SHIFT: u8 = 3;
 
     fn build(packet: &Connect) -> Self {
-        let mut flags = ConnectFlags::default();
-
-        flags.clean_start = packet.clean_start;
+        let mut flags = ConnectFlags { clean_start: packet.clean_start, ..Default::default() };
 
         if let Some(w) = &packet.will {
             flags.will_flag = true;
@@ -463,7 +598,8 @@ impl ConnectFlags {
         }
 
         flags.username_flag = packet.username.is_some();
-        flags.password_flag = flags.username_flag && packet.password.is_some();
+        // unlike 3.1.1, version 5 allows a password without a user name
+        flags.password_flag = packet.password.is_some();
 
         flags
     }
@@ -496,12 +632,11 @@ impl TryFrom<&u8> for ConnectFlags {
         let will_retain = (value & Self::WILL_RETAIN_MASK) != 0;
         let password_flag = (value & Self::PASSWORD_MASK) != 0;
         let username_flag = (value & Self::USERNAME_MASK) != 0;
-        let will_qos_raw: u8 = (value >> Self::WILL_QOS_SHIFT) & Self::WILL_QOS_MASK;
 
         let will_qos = match will_flag {
-            true => Some(QoS::try_from(will_qos_raw)?),
+            true => Some(QoS::from_bits(*value, Self::WILL_QOS_SHIFT)?),
             false => {
-                if will_qos_raw != 0 {
+                if QoS::from_bits(*value, Self::WILL_QOS_SHIFT) != Ok(QoS::AtMostOnce) {
                     return Err(MqttError::MalformedPacket("Will QoS may only be set if will flag is set (MQTT-3.1.2-11)".to_string()))
                 }
                 None
@@ -531,9 +666,8 @@ impl From<ConnectFlags> for u8 {
             result |= ConnectFlags::PASSWORD_MASK;
         }
 
-        if flags.will_qos.is_some() {
-            let qos: u8 = flags.will_qos.unwrap().into();
-            result |= qos << ConnectFlags::WILL_QOS_SHIFT;
+        if let Some(will_qos) = flags.will_qos {
+            result |= will_qos.encode_bits(ConnectFlags::WILL_QOS_SHIFT);
         }
 
         result
@@ -548,14 +682,14 @@ fn validate_protocol(name: &[u8], level: u8) -> Result<(), MqttError> {
         }
     }
 
-    if level != PROTO_LEVEL {
+    if ProtocolVersion::try_from(level) != Ok(ProtocolVersion::V5) {
         return Err(MqttError::MalformedPacket(format!("Unsupported protocol level: {}", level)))
     }
 
     Ok(())
 }
 
-fn validate_client_id(client_id: &String) -> Result<(), MqttError> {
+fn validate_client_id(client_id: &str) -> Result<(), MqttError> {
     if !client_id.is_ascii() {
         return Err(MqttError::Message("ClientID may only contain alphanumeric ASCII characters".to_string()))
     } else if client_id.len() > CLIENT_ID_MAX_LENGTH {
@@ -565,19 +699,33 @@ fn validate_client_id(client_id: &String) -> Result<(), MqttError> {
     Ok(())
 }
 
-/// TODO: add test(s) for LastWill with properties
 #[cfg(test)]
 mod tests {
 
     use std::str::FromStr;
 
+    use crate::{packet::PropertyIdentifier, test_util::from_hex};
+
     use super::*;
 
+    /// Clean start, keep alive 60, session expiry and receive maximum properties, a retained will without properties,
+    /// username and password.
+    const WILL_FIXTURE: &str = "
+        10 56
+        00 04 4d 51 54 54 05 ee 00 3c
+        08 11 00 00 00 78 21 00 01
+        00 00
+        00
+        00 0a 2f 6c 61 73 74 2f 77 69 6c 6c
+        00 1c 7b 22 73 22 3a 22 73 65 6e 73 6f 72 22 2c 22 6c 22 3a 22 6b 69 74 63 68 65 6e 22 7d
+        00 06 6d 79 6e 61 6d 65
+        00 0c 73 75 70 65 72 53 65 63 72 65 74 21";
+
     #[test]
     fn encode_and_decode() {
         let mut packet = Connect::default();
         packet.keep_alive = 77;
-        let encoded: Vec<u8> = packet.into();
+        let encoded: Vec<u8> = packet.try_into().unwrap();
         let decoded = Connect::try_from(&encoded[..]).unwrap();
         assert_eq!(77, decoded.keep_alive);
     }
@@ -587,8 +735,8 @@ mod tests {
         let mut conn = Connect::with_client_id_str("ENCTST").unwrap();
         conn.clean_start = true;
 
-        let binary: Vec<u8> = conn.into();
-        assert!(binary.len() > 0);
+        let binary: Vec<u8> = conn.try_into().unwrap();
+        assert!(!binary.is_empty());
 
         let expect: Vec<u8> = vec![
             FIRST_BYTE,
@@ -606,7 +754,7 @@ mod tests {
 
     #[test]
     fn encode_with_will() {
-        let expect: Vec<u8> = vec![16,86,0,4,77,81,84,84,5,238,0,60,8,17,0,0,0,120,33,0,1,0,0,0,0,10,47,108,97,115,116,47,119,105,108,108,0,28,123,34,115,34,58,34,115,101,110,115,111,114,34,44,34,108,34,58,34,107,105,116,99,104,101,110,34,125,0,6,109,121,110,97,109,101,0,12,115,117,112,101,114,83,101,99,114,101,116,33];
+        let expect = from_hex(WILL_FIXTURE).unwrap();
         let mut packet = Connect::default();
         packet.clean_start = true;
         packet.keep_alive = 60;
@@ -625,10 +773,21 @@ mod tests {
         will.retain = true;
         packet.will = Some(will);
 
-        let actual: Vec<u8> = packet.into();
+        let actual: Vec<u8> = packet.try_into().unwrap();
         assert_eq!(expect, actual);
     }
 
+    #[test]
+    fn encode_binary_data_too_long() {
+        let mut packet = Connect::with_client_id_str("c").unwrap();
+        packet.password = Some(vec![0; 65_536]);
+        assert_eq!(Err(MqttError::BinaryDataTooLong { len: 65_536 }), Vec::try_from(packet.clone()));
+
+        packet.password = None;
+        packet.will = Some(LastWill::new("a/b".into(), &[0; 70_000]).unwrap());
+        assert_eq!(Err(MqttError::BinaryDataTooLong { len: 70_000 }), Vec::try_from(packet));
+    }
+
     #[test]
     fn decode() {
         //let binary: Vec<u8> = vec![16,19,0,4,77,81,84,84,5,2,0,0,0,0,6,87,85,80,80,68,73];
@@ -650,7 +809,7 @@ mod tests {
         let props = decoded.properties.as_ref().unwrap();
         assert_eq!(Some(32_u16), props.receive_maximum);
         assert_eq!(1, props.user_property.len());
-        assert_eq!(Some(&String::from_str("sensor").unwrap()), props.user_property.get("origin".into()));
+        assert_eq!(Some("sensor"), props.user_property.get("origin"));
         assert!(props.authentication_method.is_none());
         assert!(props.authentication_data.is_none());
         assert!(props.maximum_packet_size.is_none());
@@ -676,9 +835,41 @@ mod tests {
         assert_eq!(Some(vec![0,1,2,3,4,5,6,7]), props.authentication_data);
     }
 
+    #[test]
+    fn authentication_data_without_method() {
+        let binary = vec![16,30,0,4,77,81,84,84,5,2,0,60,11,22,0,8,0,1,2,3,4,5,6,7,0,6,65,85,84,72,73,68];
+        let error = MqttError::ProtocolError(
+            "Property AuthenticationData (0x16) without AuthenticationMethod (0x15) in ConnectProperties".to_string());
+        decode_expect_error(binary.clone(), error.clone());
+
+        let lenient = crate::packet::Packet::decode_lenient(&binary).unwrap();
+        assert_eq!(
+            vec![Violation::PropertyWithout {
+                properties: "ConnectProperties",
+                identifier: PropertyIdentifier::AuthenticationData,
+                missing: PropertyIdentifier::AuthenticationMethod,
+            }],
+            lenient.violations);
+        match lenient.value {
+            crate::packet::Packet::Connect(connect) => assert_eq!(None, connect.properties.unwrap().authentication_data),
+            other => panic!("expected CONNECT, got {:?}", other),
+        }
+
+        let mut connect = Connect::with_client_id_str("AUTHID").unwrap();
+        connect.properties = Some(ConnectProperties { authentication_data: Some(vec![1]), ..Default::default() });
+        assert_eq!(Err(error), Vec::try_from(connect));
+    }
+
+    #[test]
+    fn decode_receive_maximum_zero() {
+        decode_expect_error(
+            vec![16,16,0,4,77,81,84,84,5,2,0,60,3,33,0,0,0,0],
+            MqttError::ProtocolError("Property ReceiveMaximum (0x21) must not be 0".to_string()));
+    }
+
     #[test]
     fn decode_will() {
-        let binary = vec![16,86,0,4,77,81,84,84,5,238,0,60,8,17,0,0,0,120,33,0,1,0,0,0,0,10,47,108,97,115,116,47,119,105,108,108,0,28,123,34,115,34,58,34,115,101,110,115,111,114,34,44,34,108,34,58,34,107,105,116,99,104,101,110,34,125,0,6,109,121,110,97,109,101,0,12,115,117,112,101,114,83,101,99,114,101,116,33];
+        let binary = from_hex(WILL_FIXTURE).unwrap();
         let decoded = Connect::try_from(&binary[..]).unwrap();
         assert_eq!(Some("myname".into()), decoded.username);
         assert!(decoded.clean_start);
@@ -696,27 +887,113 @@ mod tests {
         assert_eq!(QoS::AtLeastOnce, will.qos);
     }
 
+    #[test]
+    fn encode_will_block() {
+        let block = WillBlock { properties: None, topic: "a/b".into(), payload: vec![1, 2] };
+        let encoded: Vec<u8> = block.try_into().unwrap();
+        assert_eq!(from_hex("00 0003 612f62 0002 0102").unwrap(), encoded);
+    }
+
+    #[test]
+    fn encode_will_block_with_properties() {
+        let encoded: Vec<u8> = will_block_with_properties().try_into().unwrap();
+        assert_eq!(from_hex(WILL_BLOCK_FIXTURE).unwrap(), encoded);
+    }
+
+    #[test]
+    fn decode_will_block_with_properties() {
+        // trailing bytes (username) must not be consumed
+        let mut binary = from_hex(WILL_BLOCK_FIXTURE).unwrap();
+        let block_len = binary.len();
+        binary.extend_from_slice(&[0, 1, 0x78]);
+
+        let result = WillBlock::decode(&binary).unwrap();
+        assert_eq!(block_len, result.bytes_read());
+        assert_eq!(Some(will_block_with_properties()), result.value());
+    }
+
+    #[test]
+    fn decode_will_block_errors() {
+        // topic length exceeds the input
+        assert!(WillBlock::decode(&from_hex("00 0005 612f").unwrap()).is_err());
+        // payload length exceeds the input
+        assert!(WillBlock::decode(&from_hex("00 0001 61 0003 01").unwrap()).is_err());
+        // U+0000 in the topic
+        assert_eq!(
+            Some(MqttError::MalformedPacket("Topic must not contain U+0000".to_string())),
+            WillBlock::decode(&from_hex("00 0002 6100 0000").unwrap()).err());
+    }
+
+    #[test]
+    fn last_will_validates_topic() {
+        assert!(LastWill::new("".into(), &[]).is_err());
+        assert!(LastWill::new("last\twill".into(), &[]).is_err());
+        assert!(LastWill::new("last/#".into(), &[]).is_err());
+
+        let mut will = LastWill::new("last/will".into(), &[]).unwrap();
+        will.will_topic = "last\0will".into();
+        assert_eq!(
+            Some(MqttError::MalformedPacket("Topic must not contain U+0000".to_string())),
+            Vec::try_from(WillBlock::from(will)).err());
+    }
+
+    #[test]
+    fn will_with_properties_round_trip() {
+        let mut will = LastWill::new("last/will".into(), &[0xCA, 0xFE]).unwrap();
+        will.qos = QoS::ExactlyOnce;
+        will.retain = true;
+        will.properties = will_block_with_properties().properties;
+
+        let mut packet = Connect::with_client_id_str("will-props").unwrap();
+        packet.will = Some(will);
+        packet.username = Some("user".into());
+
+        let encoded: Vec<u8> = packet.try_into().unwrap();
+        let decoded = Connect::try_from(&encoded[..]).unwrap();
+
+        let will = decoded.will.expect("Last Will should have been decoded!");
+        assert_eq!(QoS::ExactlyOnce, will.qos);
+        assert!(will.retain);
+        assert_eq!(will_block_with_properties().properties, will.properties);
+        assert_eq!("last/will", will.will_topic);
+        assert_eq!(vec![0xCA, 0xFE], will.will_payload);
+        assert_eq!(Some("user".into()), decoded.username);
+    }
+
+    /// Will delay interval 30, content type "text", topic "a/b", payload "hi".
+    const WILL_BLOCK_FIXTURE: &str = "
+        0c 18 00 00 00 1e 03 00 04 74 65 78 74
+        00 03 61 2f 62
+        00 02 68 69";
+
+    fn will_block_with_properties() -> WillBlock {
+        let mut properties = WillProperties::default();
+        properties.will_delay_interval = Some(30);
+        properties.content_type = Some("text".into());
+        WillBlock { properties: Some(properties), topic: "a/b".into(), payload: "hi".into() }
+    }
+
     #[test]
     fn decoding_errors() {
         // first byte does not match the spec
         decode_expect_error(
             vec![17], 
-            MqttError::MalformedPacket(format!("First byte not a CONNECT packet: 00010001")));
+            MqttError::MalformedPacket("First byte not a CONNECT packet: 00010001".to_string()));
         
         // message is shorter than the 'remeinaing length' field signifies
         decode_expect_error(
             vec![16,19,0,4,77,81,84,84,5,2,0,0,0,0,6,87,85,80,80,68],
-            MqttError::MalformedPacket(format!("Message too short, expected 19, but was 18 bytes")));
+            MqttError::MalformedPacket("Message too short, expected 19, but was 18 bytes".to_string()));
 
         // invalid protocol name
         decode_expect_error(
             vec![16,19,0,4,77,81,84,83,5,2,0,0,0,0,6,87,85,80,80,68,73],
-            MqttError::MalformedPacket(format!("Invalid Protocol Name sequence: [0, 4, 77, 81, 84, 83]")));
+            MqttError::MalformedPacket("Invalid Protocol Name sequence: [0, 4, 77, 81, 84, 83]".to_string()));
 
         // unsupported proto level
         decode_expect_error(
             vec![16,19,0,4,77,81,84,84,4,2,0,0,0,0,6,87,85,80,80,68,73],
-            MqttError::MalformedPacket(format!("Unsupported protocol level: 4")));
+            MqttError::MalformedPacket("Unsupported protocol level: 4".to_string()));
     }
 
     fn decode_expect_error(binary: Vec<u8>, expect: MqttError) {
@@ -742,13 +1019,107 @@ mod tests {
         packet.set_user_property("onekey".to_string(), "oneval".to_string());
         packet.set_user_property("twokey".to_string(), "twoval".to_string());
 
-        assert_eq!(2, packet.properties.unwrap().user_property.len());
-        // TODO actually assert the contents of the properties map
+        let properties = packet.properties.unwrap().user_property;
+        assert_eq!(vec![("onekey", "oneval"), ("twokey", "twoval")], properties.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn set_intervals() {
+        let mut packet = Connect::default();
+        packet.set_keep_alive(Duration::from_secs(90)).unwrap();
+        packet.set_session_expiry(Duration::from_secs(3600)).unwrap();
+        assert_eq!(90, packet.keep_alive);
+        assert_eq!(Some(3600), packet.properties.unwrap().session_expiry_interval);
+
+        let mut packet = Connect::default();
+        assert_eq!(
+            Err(MqttError::Message("Keep alive of 65536s exceeds the maximum of 65535 seconds".into())),
+            packet.set_keep_alive(Duration::from_secs(65_536)));
+        assert_eq!(
+            Err(MqttError::Message("Keep alive of 500ms is not a whole number of seconds".into())),
+            packet.set_keep_alive(Duration::from_millis(500)));
+        assert!(packet.set_session_expiry(Duration::from_secs(u64::from(u32::MAX) + 1)).is_err());
+        assert_eq!(Connect::default(), packet);
+
+        let mut will = LastWill::new("will".into(), &[]).unwrap();
+        will.set_will_delay(Duration::from_secs(5)).unwrap();
+        will.set_message_expiry(Duration::from_secs(60)).unwrap();
+        let properties = will.properties.unwrap();
+        assert_eq!((Some(5), Some(60)), (properties.will_delay_interval, properties.message_expiry_interval));
+    }
+
+    #[test]
+    fn will_payload_format() {
+        let mut will = LastWill::new("will".into(), &[]).unwrap();
+        will.set_text_payload("gone");
+        assert_eq!(b"gone".to_vec(), will.will_payload);
+        assert_eq!(Some(true), will.properties.as_ref().unwrap().payload_format_indicator);
+
+        let mut packet = Connect::default();
+        packet.will = Some(will.clone());
+        let encoded: Vec<u8> = packet.try_into().unwrap();
+        assert_eq!(Some(will.clone()), Connect::try_from(&encoded[..]).unwrap().will);
+
+        will.will_payload = vec![0xC3, 0x28];
+        let mut packet = Connect::default();
+        packet.will = Some(will.clone());
+        assert_eq!(
+            Err(MqttError::PayloadFormatInvalid(
+                "Will payload is not valid UTF-8, but its payload format indicator says it is".into())),
+            Vec::try_from(packet));
+
+        // bytes are fine as long as nobody claims they're text
+        will.properties = None;
+        let mut packet = Connect::default();
+        packet.will = Some(will);
+        assert!(Vec::try_from(packet).is_ok());
+    }
+
+    #[test]
+    fn flags_of_decoded() {
+        let mut packet = Connect::default();
+        packet.username = Some(String::new());
+        packet.password = Some(Vec::new());
+        packet.will = Some(LastWill::new("will".into(), &[]).unwrap());
+        let encoded: Vec<u8> = packet.try_into().unwrap();
+        let decoded = Connect::try_from(&encoded[..]).unwrap();
+        assert_eq!(ConnectFlags::try_from(&encoded[9]).unwrap(), decoded.flags());
+        assert_eq!((Some(String::new()), Some(Vec::new())), (decoded.username, decoded.password));
+
+        // a password without user name is fine with version 5
+        let mut packet = Connect::default();
+        packet.password = Some(b"secret".to_vec());
+        let encoded: Vec<u8> = packet.try_into().unwrap();
+        let flags = Connect::try_from(&encoded[..]).unwrap().flags();
+        assert!(flags.password_flag && !flags.username_flag && !flags.will_flag);
+    }
+
+    #[test]
+    fn absent_and_empty_fields() {
+        let round_trip = |packet: Connect| {
+            let encoded: Vec<u8> = packet.try_into().unwrap();
+            Connect::try_from(&encoded[..]).unwrap()
+        };
+
+        let decoded = round_trip(Connect::default());
+        assert!(decoded.requests_client_id());
+        assert_eq!((None, None, None), (decoded.client_id, decoded.username, decoded.password));
+
+        let mut packet = Connect::with_client_id_str("").unwrap();
+        packet.username = Some(String::new());
+        packet.password = Some(Vec::new());
+        let decoded = round_trip(packet);
+        assert_eq!(None, decoded.client_id);
+        assert_eq!((Some(String::new()), Some(Vec::new())), (decoded.username, decoded.password));
+
+        let decoded = round_trip(Connect::with_client_id_str("c").unwrap());
+        assert_eq!(Some("c".to_string()), decoded.client_id);
+        assert!(!decoded.requests_client_id());
     }
 
     #[test]
     fn decode_connect_flags() {
-        let mut map: HashMap<u8, ConnectFlags> = HashMap::new();
+        let mut map: std::collections::HashMap<u8, ConnectFlags> = std::collections::HashMap::new();
         map.insert(0b00000010, ConnectFlags{ clean_start: true, will_flag: false, will_qos: None, will_retain: false, password_flag: false, username_flag: false });
         map.insert(0b11101110, ConnectFlags{ clean_start: true, will_flag: true, will_qos: Some(QoS::AtLeastOnce), will_retain: true, password_flag: true, username_flag: true });
         map.insert(0b00110100, ConnectFlags{ clean_start: false, will_flag: true, will_qos: Some(QoS::ExactlyOnce), will_retain: true, password_flag: false, username_flag: false});
@@ -777,12 +1148,23 @@ mod tests {
     #[test]
     fn encode_connect_flags() {
         let flags_unset = ConnectFlags{ username_flag: false, password_flag: false, will_flag: false, will_qos: None, will_retain: false, clean_start: false };
-        assert_eq!(0b00000000_u8, flags_unset.into());
+        assert_eq!(0b00000000_u8, u8::from(flags_unset));
 
         let flags_set = ConnectFlags{ username_flag: true, password_flag: true, will_flag: true, will_qos: Some(QoS::ExactlyOnce), will_retain: true, clean_start: true };
-        assert_eq!(0b11110110_u8, flags_set.into());
+        assert_eq!(0b11110110_u8, u8::from(flags_set));
         
         let flags_will_only = ConnectFlags{ username_flag: false, password_flag: false, will_flag: true, will_qos: Some(QoS::AtLeastOnce), will_retain: false, clean_start: false };
-        assert_eq!(0b00001100_u8, flags_will_only.into());
+        assert_eq!(0b00001100_u8, u8::from(flags_will_only));
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserialize() {
+        let connect: Connect = serde_json::from_str(r#"{ "client_id": "c1", "will": { "will_topic": "gone" } }"#).unwrap();
+        assert_eq!(PROTO_LEVEL, connect.protocol_level);
+        assert_eq!(Connect::default().keep_alive, connect.keep_alive);
+        assert_eq!(Some("c1".to_string()), connect.client_id);
+        assert_eq!(QoS::AtMostOnce, connect.will.unwrap().qos);
+    }
+
 }
\ No newline at end of file
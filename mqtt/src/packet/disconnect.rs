@@ -1,19 +1,19 @@
-use std::collections::HashMap;
-
 use mqtt_derive::MqttProperties;
-use crate::{types::{ReasonCode, MqttDataType}, error::MqttError};
+use crate::{types::{ReasonCode, MqttDataType, UserProperties}, error::MqttError};
 
-use super::{MqttControlPacket, PacketType, Decodeable, DecodingResult, remaining_length};
+use super::{Connect, MqttControlPacket, PacketType, DecodingResult, remaining_length, Violation};
 
 /// The first byte with packet identifier and flags is static for DISCONNECT packets
-const FIRST_BYTE: u8 = 0b11100000;
+const FIRST_BYTE: u8 = PacketType::DISCONNECT.to_first_byte(PacketType::DISCONNECT.required_flags());
 
 /// A `DISCONNECT` message cleanly severs the connection between client and server.
 /// 
 /// May be sent by either the client or the server.
 /// 
 /// See [the spec](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901205)
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
 pub struct Disconnect {
     
     /// Details about the disconnect.
@@ -24,7 +24,9 @@ pub struct Disconnect {
 }
 
 /// Optional properties in the `DISCONNECT` packet variable header.
-#[derive(Debug, PartialEq, MqttProperties)]
+#[derive(Debug, Clone, PartialEq, MqttProperties)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
 pub struct DisconnectProperties {
 
     /// Sets the expiration for the current session for a potential re-connect.
@@ -35,13 +37,68 @@ pub struct DisconnectProperties {
     pub reason_string: Option<String>,
 
     /// Application-specific key-value elements.
-    pub user_property: HashMap<String, String>,
+    pub user_property: UserProperties,
 
     /// This is usually only populated if the `reason code` is `Server Busy` to indicate a potential other server
     /// to try out.
     pub server_reference: Option<String>,
 }
 
+impl Disconnect {
+    /// The shortest possible `DISCONNECT`: reason code and properties are omitted, which means 
+    /// [Success](crate::types::ReasonCode::Success) with no properties.
+    /// 
+    /// Equivalent to [Disconnect::default()], but can be written as-is without encoding anything.
+    pub const NORMAL_BYTES: [u8; 2] = [FIRST_BYTE, 0];
+
+    /// Adds a reason string and user properties to tell the other side why the connection ends, leaving out whatever
+    /// would make the packet exceed its `maximum_packet_size` as the spec requires. Both replace any already set.
+    pub fn with_diagnostics(
+        self,
+        reason_string: Option<String>,
+        user_property: UserProperties,
+        maximum_packet_size: Option<u32>) -> Self
+    {
+        super::fit_diagnostics(self, reason_string, user_property, maximum_packet_size, |disconnect, reason_string, user_property| {
+            let properties = disconnect.properties.get_or_insert_with(DisconnectProperties::default);
+            properties.reason_string = reason_string;
+            properties.user_property = user_property;
+        })
+    }
+
+    /// Checks a `DISCONNECT` sent by a client against the `CONNECT` it connected with: if that set a session expiry
+    /// interval of 0, or none at all which means the same, the `DISCONNECT` must not set a non-zero one
+    /// [MQTT-3.14.2-2]. A server receiving such a `DISCONNECT` treats it as a protocol error, but the session still
+    /// ends with the connection.
+    ///
+    /// ```
+    /// use mqtt::packet::{Connect, ConnectProperties, Disconnect, DisconnectProperties};
+    ///
+    /// let disconnect = Disconnect {
+    ///     properties: Some(DisconnectProperties { session_expiry_interval: Some(60), ..Default::default() }),
+    ///     ..Default::default()
+    /// };
+    /// assert!(disconnect.check_session_expiry(&Connect::default()).is_err());
+    ///
+    /// let mut connect = Connect::default();
+    /// connect.properties = Some(ConnectProperties { session_expiry_interval: Some(10), ..Default::default() });
+    /// assert!(disconnect.check_session_expiry(&connect).is_ok());
+    /// ```
+    pub fn check_session_expiry(&self, connect: &Connect) -> Result<(), MqttError> {
+        self.check_session_expiry_interval(connect.properties.as_ref().and_then(|p| p.session_expiry_interval))
+    }
+
+    /// [Disconnect::check_session_expiry] with the session expiry interval the `CONNECT` had.
+    pub(crate) fn check_session_expiry_interval(&self, connect_interval: Option<u32>) -> Result<(), MqttError> {
+        let interval = self.properties.as_ref().and_then(|p| p.session_expiry_interval).unwrap_or(0);
+        match interval != 0 && connect_interval.unwrap_or(0) == 0 {
+            true => Err(MqttError::ProtocolError(format!(
+                "Session expiry interval set to {} on DISCONNECT after connecting with 0", interval))),
+            false => Ok(()),
+        }
+    }
+}
+
 impl Default for Disconnect {
     /// Returns a [`Disconnect`] with [reason code success](crate::types::ReasonCode) and no properties.
     fn default() -> Self {
@@ -53,6 +110,14 @@ impl TryFrom<&[u8]> for Disconnect {
     type Error = MqttError;
 
     fn try_from(src: &[u8]) -> Result<Self, Self::Error> {
+        Self::decode_from(src, None)
+    }
+}
+
+impl Disconnect {
+
+    /// Decodes strictly, or leniently if there are `violations` to record what was tolerated in.
+    pub(crate) fn decode_from(src: &[u8], mut violations: Option<&mut Vec<Violation>>) -> Result<Self, MqttError> {
         let mut cursor = 0;
         if src[cursor] != FIRST_BYTE {
             return Err(MqttError::invalid_packet_identifier(Disconnect::packet_type(), &src[0]))
@@ -70,18 +135,22 @@ impl TryFrom<&[u8]> for Disconnect {
                 let reason_code = ReasonCode::try_from(src[cursor])?;
                 cursor += 1;
 
-                let prop_res: DecodingResult<DisconnectProperties> = DisconnectProperties::decode(&src[cursor..])?;
+                let prop_res: DecodingResult<DisconnectProperties> = super::decode_properties(&src[cursor..], violations.as_deref_mut())?;
+                cursor += prop_res.bytes_read();
 
                 (reason_code, prop_res.value())
             }
         };
+        super::check_remaining_length(PacketType::DISCONNECT, &remaining_length, cursor, &mut violations)?;
         
         Ok(Disconnect { reason_code, properties})
     }
 }
 
-impl From<Disconnect> for Vec<u8> {
-    fn from(src: Disconnect) -> Self {
+impl TryFrom<Disconnect> for Vec<u8> {
+    type Error = MqttError;
+
+    fn try_from(src: Disconnect) -> Result<Self, Self::Error> {
         let mut packet: Vec<u8> = Vec::new();
 
         packet.push(FIRST_BYTE);
@@ -89,7 +158,7 @@ impl From<Disconnect> for Vec<u8> {
         packet.push(src.reason_code.into());
 
         if let Some(props) = src.properties {
-            packet.append(&mut props.into());
+            packet.append(&mut props.try_into()?);
         } else {
             packet.push(0); // no properties => just add a zero
         }
@@ -97,7 +166,7 @@ impl From<Disconnect> for Vec<u8> {
         // then the "remaining length"
         super::calculate_and_insert_length(&mut packet);
 
-        packet
+        Ok(packet)
     }
 }
 
@@ -117,15 +186,37 @@ mod tests {
     #[test]
     fn encode_and_decode() {
         let packet = Disconnect::default();
-        let encoded: Vec<u8> = packet.into();
+        let encoded: Vec<u8> = packet.try_into().unwrap();
         let decoded = Disconnect::try_from(encoded.as_slice()).unwrap();
         assert_eq!(ReasonCode::Success, decoded.reason_code);
     }
 
+    #[test]
+    fn with_diagnostics() {
+        let disconnect = || Disconnect { reason_code: ReasonCode::ServerShuttingDown, ..Default::default() };
+        let user_property = UserProperties::from([("k".to_string(), "v".to_string())]);
+        let reason = || Some("Going down for maintenance".to_string());
+
+        // 4 bytes without properties, the reason string takes 29 and the user property 7
+        let packet = disconnect().with_diagnostics(reason(), user_property.clone(), Some(20));
+        let properties = packet.properties.unwrap();
+        assert_eq!(None, properties.reason_string);
+        assert_eq!(user_property, properties.user_property);
+
+        let packet = disconnect().with_diagnostics(reason(), user_property.clone(), Some(40));
+        assert_eq!(40, Vec::try_from(packet).unwrap().len());
+    }
+
+    #[test]
+    fn normal_bytes() {
+        let decoded = Disconnect::try_from(&Disconnect::NORMAL_BYTES[..]).unwrap();
+        assert_eq!(Disconnect::default(), decoded);
+    }
+
     #[test]
     fn encode() {
         let disconnect = Disconnect { reason_code: ReasonCode::NotAuthorized, properties: None };
-        let binary: Vec<u8> = disconnect.into();
+        let binary: Vec<u8> = disconnect.try_into().unwrap();
         let expected: Vec<u8> = vec![FIRST_BYTE, 2, 0x87, 0];
         assert_eq!(expected, binary);
     }
@@ -137,7 +228,7 @@ mod tests {
         properties.reason_string = Some("because".into());
         let disconnect = Disconnect { reason_code: ReasonCode::Success, properties: Some(properties) };
 
-        let encoded: Vec<u8> = disconnect.into();
+        let encoded: Vec<u8> = disconnect.try_into().unwrap();
         let expected: Vec<u8> = vec![FIRST_BYTE, 17, 0, 15, 17, 0, 0, 0, 180, 31, 0, 7, 98, 101, 99, 97, 117, 115, 101];
 
         assert_eq!(expected, encoded);
@@ -145,11 +236,24 @@ mod tests {
 
     #[test]
     fn decode() {
-        let binary: Vec<u8> = vec![FIRST_BYTE, 5, 0, 0, 2, 3, 4]; // just adding a few dummy values after the reason code
+        let binary: Vec<u8> = vec![FIRST_BYTE, 2, 0, 0];
         let disconnect = Disconnect::try_from(&binary[..]).unwrap();
         assert_eq!(ReasonCode::Success, disconnect.reason_code);
     }
 
+    #[test]
+    fn decode_trailing_bytes() {
+        let binary: Vec<u8> = vec![FIRST_BYTE, 5, 0, 0, 2, 3, 4]; // a few dummy values after the properties
+        assert_eq!(
+            Err(MqttError::MalformedPacket("DISCONNECT with a remaining length of 5, but 2 bytes decoded".into())),
+            Disconnect::try_from(&binary[..]));
+
+        let mut violations = Vec::new();
+        let disconnect = Disconnect::decode_from(&binary, Some(&mut violations)).unwrap();
+        assert_eq!(ReasonCode::Success, disconnect.reason_code);
+        assert_eq!(vec![Violation::RemainingLength { packet_type: PacketType::DISCONNECT, declared: 5, decoded: 2 }], violations);
+    }
+
     #[test]
     fn decode_implicit_success() {
         let binary: Vec<u8> = vec![FIRST_BYTE, 0];
@@ -194,10 +298,27 @@ mod tests {
         let bin: Vec<u8> = vec![32, 1, 0];
         let res = Disconnect::try_from(&bin[..]);
         assert!(res.is_err(), "expected a MalformedPacket error");
-        assert_eq!(Some(MqttError::MalformedPacket(format!("Invalid packet identifier for DISCONNECT: 00100000"))), res.err());
+        assert_eq!(Some(MqttError::MalformedPacket("Invalid packet identifier for DISCONNECT: 00100000".to_string())), res.err());
         
     }
 
+    #[test]
+    fn check_session_expiry() {
+        let disconnect = |interval| Disconnect {
+            reason_code: ReasonCode::Success,
+            properties: Some(DisconnectProperties { session_expiry_interval: interval, ..Default::default() }),
+        };
+        assert_eq!(
+            Err(MqttError::ProtocolError("Session expiry interval set to 30 on DISCONNECT after connecting with 0".into())),
+            disconnect(Some(30)).check_session_expiry_interval(None));
+        assert!(disconnect(Some(30)).check_session_expiry_interval(Some(0)).is_err());
+        assert!(disconnect(Some(30)).check_session_expiry_interval(Some(10)).is_ok());
+        assert!(disconnect(Some(0)).check_session_expiry_interval(Some(10)).is_ok());
+        assert!(disconnect(Some(0)).check_session_expiry_interval(None).is_ok());
+        assert!(disconnect(None).check_session_expiry_interval(None).is_ok());
+        assert!(Disconnect::default().check_session_expiry_interval(None).is_ok());
+    }
+
     #[test]
     fn disconnect_properties_default() {
         let packet = DisconnectProperties::default();
@@ -211,11 +332,11 @@ mod tests {
     #[test]
     fn encode_properties() {
         let mut props = DisconnectProperties::default();
-        props.user_property.insert("wuppdi".to_string(), "heppes".to_string());
+        props.user_property.push("wuppdi".to_string(), "heppes".to_string());
         props.session_expiry_interval = Some(120);
         props.reason_string = Some(String::from("Because you are a test"));
 
-        let vec: Vec<u8> = props.into();
+        let vec: Vec<u8> = props.try_into().unwrap();
         assert!(!vec.is_empty());
         assert_eq!(48, vec.len());
         
@@ -0,0 +1,285 @@
+//! A human readable breakdown of encoded packets, for analyzing captured traffic.
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use crate::{
+    error::MqttError,
+    types::ReasonCode,
+    wire::{self, FixedHeader},
+};
+
+use super::{DataRepresentation, Lenient, MqttProperty, Packet, TopicFilter};
+
+/// Number of bytes per line of a hex dump.
+const BYTES_PER_LINE: usize = 16;
+
+/// One packet as it was received: its bytes, the fixed header and the fields decoded from it.
+///
+/// Decoding is [lenient](Packet::decode_lenient), so that a packet violating the protocol is still shown along with
+/// what's wrong with it. `Display` prints the bytes in hex followed by the fields, one per line.
+///
+/// # Examples
+/// ```
+/// use mqtt::packet::PacketDump;
+///
+/// // a PUBACK for packet identifier 7 with reason code "No matching subscribers"
+/// let dump = PacketDump::new(&[0x40, 0x03, 0x00, 0x07, 0x10]).unwrap();
+/// assert_eq!(5, dump.len());
+/// assert!(dump.to_string().contains("reason code: No matching subscribers (0x10)"));
+/// ```
+#[derive(Debug)]
+pub struct PacketDump<'a> {
+    /// The whole packet, fixed header included.
+    pub bytes: &'a [u8],
+
+    pub header: FixedHeader,
+
+    /// The packet with the protocol violations tolerated, or why it couldn't be decoded at all.
+    pub decoded: Result<Lenient<Packet>, MqttError>,
+}
+
+impl<'a> PacketDump<'a> {
+
+    /// Dumps the packet at the beginning of `src`, any bytes after it are left for the next one. Fails only if there's
+    /// no packet to speak of: the fixed header is invalid, or `src` is shorter than the packet.
+    pub fn new(src: &'a [u8]) -> Result<Self, MqttError> {
+        let incomplete = || MqttError::MalformedPacket(format!("Incomplete packet of {} bytes", src.len()));
+        let (header, _) = wire::read_fixed_header(src)?.ok_or_else(incomplete)?;
+        let bytes = src.get(..header.packet_len()).ok_or_else(incomplete)?;
+        Ok(Self { bytes, header, decoded: Packet::decode_lenient(bytes) })
+    }
+
+    /// The length of the packet in bytes, where the next one starts.
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+impl Display for PacketDump<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        writeln!(f, "{} ({} bytes)", self.header.packet_type, self.bytes.len())?;
+        hex(f, 1, self.bytes)?;
+        writeln!(f, "  flags: {:04b}", self.header.flags)?;
+        writeln!(f, "  remaining length: {}", self.header.remaining_length)?;
+
+        match &self.decoded {
+            Ok(decoded) => {
+                fields(f, &decoded.value)?;
+                for violation in &decoded.violations {
+                    writeln!(f, "  violation: {}", violation)?;
+                }
+                Ok(())
+            },
+            Err(e) => writeln!(f, "  error: {}", e),
+        }
+    }
+}
+
+fn fields(f: &mut Formatter<'_>, packet: &Packet) -> FmtResult {
+    match packet {
+        Packet::Connect(p) => {
+            field(f, "client identifier", p.client_id.as_deref().map(quoted))?;
+            field(f, "keep alive", Some(format!("{}s", p.keep_alive)))?;
+            field(f, "clean start", Some(p.clean_start))?;
+            field(f, "username", p.username.as_deref().map(quoted))?;
+            // the password is the one thing not to print from a capture
+            field(f, "password", p.password.as_ref().map(|password| format!("{} bytes", password.len())))?;
+            properties(f, 1, p.properties.clone())?;
+            if let Some(will) = &p.will {
+                writeln!(f, "  will:")?;
+                writeln!(f, "    topic: {}", quoted(&will.will_topic))?;
+                writeln!(f, "    qos: {:?}, retain: {}", will.qos, will.retain)?;
+                properties(f, 2, will.properties.clone())?;
+                writeln!(f, "    payload: {} bytes", will.will_payload.len())?;
+                hex(f, 3, &will.will_payload)?;
+            }
+            Ok(())
+        },
+        Packet::Connack(p) => {
+            field(f, "session present", Some(p.session_present))?;
+            reason_code(f, p.reason_code)?;
+            properties(f, 1, p.properties.clone())
+        },
+        Packet::Publish(p) => {
+            writeln!(f, "  topic name: {}", quoted(&p.topic_name))?;
+            writeln!(f, "  qos: {:?}, dup: {}, retain: {}", p.qos_level, p.dup, p.retain)?;
+            field(f, "packet identifier", p.packet_identifier)?;
+            properties(f, 1, p.properties.clone())?;
+            writeln!(f, "  payload: {} bytes", p.payload.len())?;
+            hex(f, 2, &p.payload)
+        },
+        Packet::Puback(p) => acknowledgement(f, p.packet_identifier, p.reason_code, p.properties.clone()),
+        Packet::Pubrec(p) => acknowledgement(f, p.packet_identifier, p.reason_code, p.properties.clone()),
+        Packet::Pubrel(p) => acknowledgement(f, p.packet_identifier, p.reason_code, p.properties.clone()),
+        Packet::Pubcomp(p) => acknowledgement(f, p.packet_identifier, p.reason_code, p.properties.clone()),
+        Packet::Subscribe(p) => {
+            writeln!(f, "  packet identifier: {}", p.packet_identifier)?;
+            properties(f, 1, p.properties.clone())?;
+            p.topic_filter.iter().try_for_each(|filter| topic_filter(f, filter))
+        },
+        Packet::Suback(p) => {
+            writeln!(f, "  packet identifier: {}", p.packet_identifier)?;
+            properties(f, 1, p.properties.clone())?;
+            p.reason_codes.iter().try_for_each(|code| reason_code(f, *code))
+        },
+        Packet::Unsubscribe(p) => {
+            writeln!(f, "  packet identifier: {}", p.packet_identifier)?;
+            properties(f, 1, p.properties.clone())?;
+            p.topic_filter.iter().try_for_each(|filter| writeln!(f, "  topic filter: {}", quoted(filter)))
+        },
+        Packet::Unsuback(p) => {
+            writeln!(f, "  packet identifier: {}", p.packet_identifier)?;
+            properties(f, 1, p.properties.clone())?;
+            p.reason_codes.iter().try_for_each(|code| reason_code(f, *code))
+        },
+        Packet::Pingreq(_) | Packet::Pingresp(_) => Ok(()),
+        Packet::Disconnect(p) => {
+            reason_code(f, p.reason_code)?;
+            properties(f, 1, p.properties.clone())
+        },
+        Packet::Auth(p) => {
+            reason_code(f, p.reason_code)?;
+            properties(f, 1, p.properties.clone())
+        },
+    }
+}
+
+fn acknowledgement<P: Into<Vec<MqttProperty>>>(
+    f: &mut Formatter<'_>,
+    packet_identifier: u16,
+    code: ReasonCode,
+    props: Option<P>) -> FmtResult
+{
+    writeln!(f, "  packet identifier: {}", packet_identifier)?;
+    reason_code(f, code)?;
+    properties(f, 1, props)
+}
+
+fn topic_filter(f: &mut Formatter<'_>, filter: &TopicFilter) -> FmtResult {
+    writeln!(f, "  topic filter: {}", quoted(&filter.filter))?;
+    writeln!(
+        f,
+        "    maximum qos: {:?}, no local: {}, retain as published: {}, retain handling: {:?}",
+        filter.maximum_qos, filter.no_local, filter.retain_as_published, filter.retain_handling)
+}
+
+/// One line for each property, nothing at all if there are none.
+fn properties<P: Into<Vec<MqttProperty>>>(f: &mut Formatter<'_>, indent: usize, props: Option<P>) -> FmtResult {
+    let props: Vec<MqttProperty> = props.map(Into::into).unwrap_or_default();
+    if props.is_empty() {
+        return Ok(())
+    }
+
+    let indent = "  ".repeat(indent);
+    writeln!(f, "{}properties:", indent)?;
+    for property in props {
+        write!(f, "{}  {:?}: ", indent, property.identifier)?;
+        match property.value {
+            DataRepresentation::Byte(value) => writeln!(f, "{}", value)?,
+            DataRepresentation::TwoByteInt(value) => writeln!(f, "{}", value)?,
+            DataRepresentation::FourByteInt(value) => writeln!(f, "{}", value)?,
+            DataRepresentation::VariByteInt(value) => writeln!(f, "{}", value.value)?,
+            DataRepresentation::UTF8(value) => writeln!(f, "{}", quoted(&value.to_string()))?,
+            DataRepresentation::UTF8Pair(pair) => {
+                writeln!(f, "{} = {}", quoted(&pair.key.to_string()), quoted(&pair.value.to_string()))?
+            },
+            DataRepresentation::BinaryData(data) => writeln!(f, "{}", hex_string(data.as_bytes()))?,
+        }
+    }
+    Ok(())
+}
+
+fn reason_code(f: &mut Formatter<'_>, code: ReasonCode) -> FmtResult {
+    writeln!(f, "  reason code: {} (0x{:02X})", code, u8::from(code))
+}
+
+/// A line for a field that may be absent, none if it is.
+fn field<T: Display>(f: &mut Formatter<'_>, name: &str, value: Option<T>) -> FmtResult {
+    match value {
+        Some(value) => writeln!(f, "  {}: {}", name, value),
+        None => Ok(()),
+    }
+}
+
+/// Quoted and escaped, so that control characters and trailing whitespace show.
+fn quoted(s: &str) -> String {
+    format!("{:?}", s)
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<String>>().join(" ")
+}
+
+/// Sixteen bytes per line in hex, followed by the ones that are printable ASCII.
+fn hex(f: &mut Formatter<'_>, indent: usize, bytes: &[u8]) -> FmtResult {
+    let indent = "  ".repeat(indent);
+    for line in bytes.chunks(BYTES_PER_LINE) {
+        let text: String = line.iter()
+            .map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' })
+            .collect();
+        writeln!(f, "{}{:<width$}  {}", indent, hex_string(line), text, width = BYTES_PER_LINE * 3 - 1)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{packet::Publish, types::QoS};
+
+    use super::*;
+
+    #[test]
+    fn publish() {
+        let mut publish = Publish::new_unchecked("sensors/1".to_string(), "hello, world!");
+        publish.qos_level = QoS::AtLeastOnce;
+        publish.packet_identifier = Some(7);
+        let encoded: Vec<u8> = Packet::from(publish).try_into().unwrap();
+
+        let dump = PacketDump::new(&encoded).unwrap();
+        assert_eq!(encoded.len(), dump.len());
+        assert_eq!(
+            "PUBLISH (29 bytes)\n\
+            \x20 32 1b 00 09 73 65 6e 73 6f 72 73 2f 31 00 07 00  2...sensors/1...\n\
+            \x20 68 65 6c 6c 6f 2c 20 77 6f 72 6c 64 21           hello, world!\n\
+            \x20 flags: 0010\n\
+            \x20 remaining length: 27\n\
+            \x20 topic name: \"sensors/1\"\n\
+            \x20 qos: AtLeastOnce, dup: false, retain: false\n\
+            \x20 packet identifier: 7\n\
+            \x20 payload: 13 bytes\n\
+            \x20   68 65 6c 6c 6f 2c 20 77 6f 72 6c 64 21           hello, world!\n",
+            dump.to_string());
+    }
+
+    #[test]
+    fn followed_by_more() {
+        // a PINGRESP followed by the start of a PUBACK
+        let dump = PacketDump::new(&[0xD0, 0x00, 0x40, 0x02]).unwrap();
+        assert_eq!(2, dump.len());
+        assert!(matches!(dump.decoded, Ok(Lenient { value: Packet::Pingresp(_), .. })));
+    }
+
+    #[test]
+    fn violations_and_errors() {
+        // a PUBREL with its reserved flags cleared
+        let dump = PacketDump::new(&[0x60, 0x02, 0x00, 0x01]).unwrap();
+        assert!(dump.to_string().ends_with("  violation: PUBREL with flags 0000 instead of 0010\n"),
+            "{}", dump);
+
+        // a CONNECT that ends after the first byte of its protocol name
+        let dump = PacketDump::new(&[0x10, 0x03, 0x00, 0x04, 0x4D]).unwrap();
+        assert!(dump.decoded.is_err());
+        assert!(dump.to_string().contains("  error: "), "{}", dump);
+    }
+
+    #[test]
+    fn incomplete() {
+        assert!(PacketDump::new(&[]).is_err());
+        assert!(PacketDump::new(&[0x40, 0x02, 0x00]).is_err());
+        assert!(PacketDump::new(&[0x00, 0x00]).is_err());
+    }
+}
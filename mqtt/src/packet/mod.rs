@@ -28,10 +28,14 @@
 //! - Sender: `PUBREL`
 //! - Reciever: `PUBCOMP`
 
+#[cfg(feature = "arbitrary")]
+mod arbitrary;
 mod auth;
+mod codec;
 mod connack;
 mod connect;
 mod disconnect;
+mod dump;
 mod ping;
 mod properties;
 mod puback;
@@ -43,29 +47,41 @@ mod suback;
 mod subscribe;
 mod unsub;
 mod unsuback;
+mod version;
+mod violation;
 
-use std::fmt::Display;
+use std::{fmt::Display, time::Duration};
 
+use crate::wire::{push_be_u16, u16_from_be_bytes, u32_from_be_bytes};
 use crate::error::MqttError;
-use crate::types::{VariableByteInteger, MqttDataType};
+use crate::types::{MqttDataType, UserProperties, VariableByteInteger};
 
 pub use self::auth::{Auth, AuthProperties};
-pub use self::connack::{Connack, ConnackProperties};
-pub use self::connect::{Connect, ConnectProperties, LastWill, WillProperties};
+pub use self::codec::{encode_batch, write_batch, Packet, PacketIter};
+pub use self::connack::{Connack, ConnackBuilder, ConnackProperties};
+pub use self::connect::{Connect, ConnectFlags, ConnectProperties, LastWill, WillProperties};
 pub use self::disconnect::{Disconnect, DisconnectProperties};
+pub use self::dump::PacketDump;
 pub use self::ping::{Pingreq, Pingresp};
+pub use self::properties::{DataRepresentation, MqttProperty, PropertyIdentifier};
 pub use self::puback::{Puback, PubackProperties};
 pub use self::pubcomp::{Pubcomp, PubcompProperties};
-pub use self::publish::{Publish, PublishProperties};
+pub use self::publish::{Publish, PublishBuilder, PublishProperties, RawProperties};
 pub use self::pubrec::{Pubrec, PubrecProperties};
 pub use self::pubrel::{Pubrel, PubrelProperties};
 pub use self::suback::{Suback, SubackProperties};
 pub use self::subscribe::{Subscribe, SubscribeProperties, TopicFilter};
 pub use self::unsub::{Unsubscribe, UnsubscribeProperties};
 pub use self::unsuback::{Unsuback, UnsubackProperties};
+pub use self::version::{Capabilities, ProtocolVersion};
+pub use self::violation::{Lenient, Violation};
 
 /// MQTT control packet types.
-#[derive(Debug, PartialEq, Eq)]
+///
+/// Version 5 uses all fifteen values of the four bits the type is encoded in, but a later version may redefine the
+/// reserved `0`. Matches outside this crate need a wildcard arm for that.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum PacketType {
     CONNECT = 1,
     CONNACK = 2,
@@ -84,6 +100,75 @@ pub enum PacketType {
     AUTH = 15,
 }
 
+impl PacketType {
+
+    /// The flags the spec mandates in the lower four bits of the first byte: `0010` for `PUBREL`, `SUBSCRIBE` and
+    /// `UNSUBSCRIBE`, `0000` for everything else. `PUBLISH` is the exception, its flags carry DUP, QoS and RETAIN
+    /// instead, see [Publish].
+    pub const fn required_flags(&self) -> u8 {
+        match self {
+            PacketType::PUBREL | PacketType::SUBSCRIBE | PacketType::UNSUBSCRIBE => 0b0010,
+            _ => 0b0000,
+        }
+    }
+
+    /// The first byte of the fixed header: the packet type in the upper four bits and `flags` in the lower four. Any
+    /// higher bits of `flags` are ignored.
+    /// 
+    /// ```
+    /// use mqtt::packet::PacketType;
+    /// 
+    /// assert_eq!(0b01100010, PacketType::PUBREL.to_first_byte(PacketType::PUBREL.required_flags()));
+    /// assert_eq!(0b00111011, PacketType::PUBLISH.to_first_byte(0b1011));
+    /// ```
+    pub const fn to_first_byte(&self, flags: u8) -> u8 {
+        (*self as u8) << 4 | (flags & 0b1111)
+    }
+
+    /// Checks that `first_byte` is the first byte of a packet of this type with the
+    /// [required flags](Self::required_flags), which receivers must treat as a malformed packet otherwise
+    /// [MQTT-2.1.3-1]. The error names the flag bits that are wrong. The flags of `PUBLISH` aren't checked.
+    ///
+    /// ```
+    /// use mqtt::{error::MqttError, packet::PacketType};
+    ///
+    /// assert!(PacketType::PUBREL.check_first_byte(0b01100010).is_ok());
+    /// assert_eq!(
+    ///     Err(MqttError::MalformedPacket(
+    ///         "Invalid fixed header flags of PUBREL: 0000 instead of 0010, bit 1 must be set".to_string())),
+    ///     PacketType::PUBREL.check_first_byte(0b01100000));
+    /// ```
+    pub fn check_first_byte(&self, first_byte: u8) -> Result<(), MqttError> {
+        if first_byte >> 4 != *self as u8 {
+            return Err(MqttError::MalformedPacket(format!("First byte is not a {} one: {:08b}", self, first_byte)))
+        }
+
+        let flags = first_byte & 0b1111;
+        let required = self.required_flags();
+        if *self == PacketType::PUBLISH || flags == required {
+            return Ok(())
+        }
+
+        let bits = |mask: u8| {
+            let bits: Vec<String> = (0..4).filter(|bit| mask & 1 << bit != 0).map(|bit| bit.to_string()).collect();
+            match bits.len() {
+                1 => format!("bit {}", bits[0]),
+                _ => format!("bits {}", bits.join(", ")),
+            }
+        };
+        let mut wrong = Vec::new();
+        if required & !flags != 0 {
+            wrong.push(format!("{} must be set", bits(required & !flags)));
+        }
+        if flags & !required != 0 {
+            wrong.push(format!("{} must not be set", bits(flags & !required)));
+        }
+        Err(MqttError::MalformedPacket(format!(
+            "Invalid fixed header flags of {}: {:04b} instead of {:04b}, {}",
+            self, flags, required, wrong.join(" and "))))
+    }
+}
+
 impl TryFrom<u8> for PacketType {
     type Error = MqttError;
 
@@ -106,7 +191,7 @@ impl TryFrom<u8> for PacketType {
             13 => Ok(PacketType::PINGRESP),
             14 => Ok(PacketType::DISCONNECT),
             15 => Ok(PacketType::AUTH),
-            _=> Err(MqttError::Message(format!("undefined packet type: {}", shifted))),
+            _=> Err(MqttError::MalformedPacket(format!("undefined packet type: {}", shifted))),
         }
     }
 }
@@ -136,7 +221,7 @@ impl Display for PacketType {
 /// Common behavior for MQTT control packets.
 /// 
 /// At the very least, it is expected that a packet can be transformed into and parsed from binary format.
-pub trait MqttControlPacket<'a>: Into<Vec<u8>> + TryFrom<&'a [u8]> {
+pub trait MqttControlPacket<'a>: TryInto<Vec<u8>> + TryFrom<&'a [u8]> {
     
     /// Not sure we really need this...
     fn packet_type() -> PacketType;
@@ -169,14 +254,36 @@ pub trait Decodeable: Sized {
 
     /// Does what it says. May or may not return an actual value.
     fn decode(src: &[u8]) -> Result<DecodingResult<Self>, crate::error::MqttError>;
+
+    /// Like [decode](Decodeable::decode), but records the violations it can tolerate in `violations` instead of
+    /// failing. Decodes strictly unless implemented otherwise.
+    fn decode_lenient(src: &[u8], violations: &mut Vec<Violation>) -> Result<DecodingResult<Self>, crate::error::MqttError> {
+        let _ = violations;
+        Self::decode(src)
+    }
     
 }
 
+/// Decodes strictly without `violations` to record in, leniently with.
+fn decode_properties<T: Decodeable>(
+    src: &[u8],
+    violations: Option<&mut Vec<Violation>>,
+) -> Result<DecodingResult<T>, MqttError> {
+    match violations {
+        Some(violations) => T::decode_lenient(src, violations),
+        None => T::decode(src),
+    }
+}
+
 /// Decodes a [VariableByteInteger](crate::types::VariableByteInteger) from the beginning of the slice and compares
 /// the decoded value against the actual remaining length of the slice. If the remaining slice is shorter than the
 /// specified one, an error is returned.
 fn remaining_length(src: &[u8]) -> Result<VariableByteInteger, MqttError> {
-    let remain_len = VariableByteInteger::try_from(&src[..])?;
+    // a single byte for packets shorter than 128 bytes, by far the most common
+    let remain_len = match src.first() {
+        Some(byte) if byte & 128 == 0 => VariableByteInteger { value: (*byte).into() },
+        _ => VariableByteInteger::try_from(src)?,
+    };
     let actual_len = (src.len() - remain_len.encoded_len()) as u32;
 
     if remain_len.value > actual_len {
@@ -187,75 +294,140 @@ fn remaining_length(src: &[u8]) -> Result<VariableByteInteger, MqttError> {
     Ok(remain_len)
 }
 
-/// Converts `val` into two Big-Endian bytes and appends them to `vec`.
-/// TODO this should be moved somewhere together with all the other general read, parse, push and encode functions
-fn push_be_u16(val: u16, vec: &mut Vec<u8>) {
-    for b in val.to_be_bytes() {
-        vec.push(b)
+/// Compares the bytes decoded up to `end`, the index after the last field, against the remaining length of the fixed
+/// header. A difference means the packet is malformed: either it has trailing bytes no field accounts for, or its
+/// fields don't fit into the length it declares.
+fn check_remaining_length(
+    packet_type: PacketType,
+    remaining_length: &VariableByteInteger,
+    end: usize,
+    violations: &mut Option<&mut Vec<Violation>>,
+) -> Result<(), MqttError> {
+    let decoded = end - LENGTH_START_INDEX - remaining_length.encoded_len();
+    match decoded == remaining_length.value as usize {
+        true => Ok(()),
+        false => violation::tolerate(
+            violations,
+            Violation::RemainingLength { packet_type, declared: remaining_length.value, decoded }),
     }
 }
 
-/// Converts the first two bytes of the slice into a big-endian u16.
-/// returns an error if the slice is shorter than 2 bytes
-fn u16_from_be_bytes(src: &[u8]) -> Result<u16, MqttError> {
-    let index = std::mem::size_of::<u16>();
-    if index > src.len() {
-        return Err(MqttError::Message(format!("Source slice too short for u16: {}", src.len())))
+/// Converts `duration` into the seconds of an interval field, which can't be longer than `max`. A fraction of a second
+/// is an error rather than rounded away, so that a few milliseconds don't silently turn into `0` and with it into
+/// "no interval" for some of the fields.
+fn interval_secs(name: &str, duration: Duration, max: u32) -> Result<u32, MqttError> {
+    if duration.subsec_nanos() != 0 {
+        return Err(MqttError::Message(format!("{} of {:?} is not a whole number of seconds", name, duration)))
     }
-
-    let (int_bytes, _) = src.split_at(index);
-    match int_bytes.try_into() {
-        Ok(a) => Ok(u16::from_be_bytes(a)),
-        Err(e) => Err(MqttError::Message(format!("Error decoding u16: {:?}", e))),
+    match u32::try_from(duration.as_secs()) {
+        Ok(secs) if secs <= max => Ok(secs),
+        _ => Err(MqttError::Message(format!("{} of {:?} exceeds the maximum of {} seconds", name, duration, max))),
     }
 }
 
-/// Converts the first four bytes of the slice into a big-endian u32.
-/// returns an error if the slice is shorter than 4 bytes
-fn u32_from_be_bytes(src: &[u8]) -> Result<u32, MqttError> {
-    let index = std::mem::size_of::<u32>();
-    if index > src.len() {
-        return Err(MqttError::Message(format!("Source slice too short for u16!")))
-    }
+/// The fixed header part of an MQTT packet includes the 'remaining length' starting with the second byte
+const LENGTH_START_INDEX: usize = 1;
 
-    let (int_bytes, _) = src.split_at(index);
-    match int_bytes.try_into() {
-        Ok(a) => Ok(u32::from_be_bytes(a)),
-        Err(e) => Err(MqttError::Message(format!("Error decoding u32: {:?}", e))),
+/// Subtracts 1 from the vec's length (because we're assuming the first byte is the packet type and flags), creates a
+/// [`VariableByteInteger`] from it and then calls [`insert()`].
+fn calculate_and_insert_length(packet: &mut Vec<u8>) {
+    let remaining = packet.len() - 1;
+    // packets shorter than 128 bytes, by far the most common, have a single length byte
+    if remaining < 128 {
+        packet.insert(LENGTH_START_INDEX, remaining as u8);
+        return
     }
+    let (bytes, len) = VariableByteInteger { value: remaining as u32 }.to_bytes();
+    packet.splice(LENGTH_START_INDEX..LENGTH_START_INDEX, bytes[..len].iter().copied());
 }
 
-/// Converts `val` into four Big-Endian bytes and appends them to `vec`.
-/// TODO this should be moved somewhere together with all the other general read, parse, push and encode functions
-fn push_be_u32(val: u32, vec: &mut Vec<u8>) {
-    for b in val.to_be_bytes() {
-        vec.push(b)
+/// Sets the reason string and user properties of a packet with `apply`, leaving out as much as necessary to stay within
+/// `maximum_packet_size` [MQTT-3.2.2-19, MQTT-3.2.2-20, MQTT-3.14.2-3, MQTT-3.14.2-4]. Both are tried first, then only
+/// the reason string, then only the user properties and finally neither.
+fn fit_diagnostics<P, F>(
+    packet: P,
+    reason_string: Option<String>,
+    user_property: UserProperties,
+    maximum_packet_size: Option<u32>,
+    apply: F) -> P
+where
+    P: Clone + TryInto<Vec<u8>>,
+    F: Fn(&mut P, Option<String>, UserProperties),
+{
+    let candidates = [
+        (reason_string.clone(), user_property.clone()),
+        (reason_string, UserProperties::new()),
+        (None, user_property),
+    ];
+
+    for (reason_string, user_property) in candidates {
+        let mut candidate = packet.clone();
+        apply(&mut candidate, reason_string, user_property);
+        // a candidate that can't be encoded doesn't fit either
+        let size = TryInto::<Vec<u8>>::try_into(candidate.clone()).map_or(usize::MAX, |bytes| bytes.len());
+        if maximum_packet_size.is_none_or(|max| size <= max as usize) {
+            return candidate
+        }
     }
+
+    let mut packet = packet;
+    apply(&mut packet, None, UserProperties::new());
+    packet
 }
 
-/// The fixed header part of an MQTT packet includes the 'remaining length' starting with the second byte
-const LENGTH_START_INDEX: usize = 1;
+/// Packets with a reason string and user properties, both of which the sender may leave out to keep the packet within
+/// the receiver's maximum packet size.
+trait Diagnostics: Clone + TryInto<Vec<u8>, Error = MqttError> {
+    /// Removes reason string and user properties from the packet and returns them.
+    fn take_diagnostics(&mut self) -> (Option<String>, UserProperties);
 
-/// Subtracts 1 from the vec's length (because we're assuming the first byte is the packet type and flags), creates a
-/// [`VariableByteInteger`] from it and then calls [`insert()`].
-fn calculate_and_insert_length(packet: &mut Vec<u8>) {
-    encode_and_insert(VariableByteInteger { value: (packet.len() - 1) as u32 }, LENGTH_START_INDEX, packet)
+    fn set_diagnostics(&mut self, reason_string: Option<String>, user_property: UserProperties);
 }
 
-/// Encodes `val` into its binary representation and appends the resulting bytes to `vec`.
-fn encode_and_append<T: Into<Vec<u8>>>(val: T, vec: &mut Vec<u8>) {
-    vec.append(&mut val.into())
+/// Implements [Diagnostics] for packets with a `properties: Option<...>` field whose properties have public
+/// `reason_string` and `user_property` fields.
+macro_rules! diagnostics {
+    ($($packet:ty),*) => {
+        $(impl Diagnostics for $packet {
+            fn take_diagnostics(&mut self) -> (Option<String>, UserProperties) {
+                self.properties.as_mut()
+                    .map(|properties| (properties.reason_string.take(), std::mem::take(&mut properties.user_property)))
+                    .unwrap_or_default()
+            }
+
+            fn set_diagnostics(&mut self, reason_string: Option<String>, user_property: UserProperties) {
+                let properties = self.properties.get_or_insert_with(Default::default);
+                properties.reason_string = reason_string;
+                properties.user_property = user_property;
+            }
+        })*
+    };
 }
 
-/// Encodes `val` into its binary representation and then inserts those bytes at the specified index.
-/// To encode and append at the end of the 
-fn encode_and_insert<T: Into<Vec<u8>>>(val: T, start_index: usize, vec: &mut Vec<u8>) {
-    let mut index = start_index;
-    let encoded: Vec<u8> = val.into();
-    for b in encoded {
-        vec.insert(index, b);
-        index += 1;
+diagnostics!(Connack, Puback, Pubrec, Pubrel, Pubcomp, Unsuback, Disconnect, Auth);
+
+/// Encodes a packet, leaving out as much of its reason string and user properties as it takes to stay within
+/// `maximum_packet_size`. May still be larger than that if the rest of the packet is.
+fn encode_fitted<P: Diagnostics>(packet: P, maximum_packet_size: u32) -> Result<Vec<u8>, MqttError> {
+    let mut packet = packet;
+    let (reason_string, user_property) = packet.take_diagnostics();
+    if reason_string.is_none() && user_property.is_empty() {
+        return packet.try_into()
     }
+    fit_diagnostics(packet, reason_string, user_property, Some(maximum_packet_size), P::set_diagnostics).try_into()
+}
+
+/// The number of bytes a property block takes up when encoded, its length included. No properties at all take up
+/// one, the length `0`.
+fn properties_len(properties: impl Iterator<Item = MqttProperty>) -> usize {
+    let len: usize = properties.map(|property| property.encoded_len()).sum();
+    crate::wire::variable_byte_integer_len(len as u32) + len
+}
+
+/// Puts the length of the properties encoded into `vec` in front of them, as the Variable Byte Integer it's encoded as.
+fn insert_properties_length(vec: &mut Vec<u8>) {
+    let (bytes, len) = VariableByteInteger::from(vec.len() as u32).to_bytes();
+    vec.splice(0..0, bytes[..len].iter().copied());
 }
 
 #[cfg(test)]
@@ -285,7 +457,7 @@ mod tests {
 
     #[test]
     fn test_packet_from_u8() {
-        assert_eq!(Some(MqttError::Message("undefined packet type: 0".to_string())), PacketType::try_from(0b00000000).err());
+        assert_eq!(Some(MqttError::MalformedPacket("undefined packet type: 0".to_string())), PacketType::try_from(0b00000000).err());
 
         do_test_packet_from_u8(0b00010000, PacketType::CONNECT);
         // just doing this to test that the last four bits are ignored
@@ -307,6 +479,43 @@ mod tests {
         do_test_packet_from_u8(0b11110101, PacketType::AUTH);
     }
 
+    #[test]
+    fn packet_type_to_first_byte() {
+        for first_byte in 0b00010000..=0b11111111_u8 {
+            let packet_type = PacketType::try_from(first_byte).unwrap();
+            assert_eq!(first_byte, packet_type.to_first_byte(first_byte));
+        }
+
+        assert_eq!(0b11100000, PacketType::DISCONNECT.to_first_byte(0b11110000));
+        assert_eq!(0b10000010, PacketType::SUBSCRIBE.to_first_byte(PacketType::SUBSCRIBE.required_flags()));
+        assert_eq!(0b10100010, PacketType::UNSUBSCRIBE.to_first_byte(PacketType::UNSUBSCRIBE.required_flags()));
+        assert_eq!(0, PacketType::PUBLISH.required_flags());
+    }
+
+    #[test]
+    fn check_first_byte() {
+        for packet_type in [PacketType::PUBREL, PacketType::SUBSCRIBE, PacketType::UNSUBSCRIBE, PacketType::PUBACK] {
+            assert_eq!(Ok(()), packet_type.check_first_byte(packet_type.to_first_byte(packet_type.required_flags())));
+        }
+        assert_eq!(Ok(()), PacketType::PUBLISH.check_first_byte(0b00111101));
+
+        let error = |detail: &str| Err(MqttError::MalformedPacket(detail.to_string()));
+        assert_eq!(
+            error("Invalid fixed header flags of SUBSCRIBE: 1011 instead of 0010, bits 0, 3 must not be set"),
+            PacketType::SUBSCRIBE.check_first_byte(0b10001011));
+        assert_eq!(
+            error(concat!(
+                "Invalid fixed header flags of UNSUBSCRIBE: 0100 instead of 0010, ",
+                "bit 1 must be set and bit 2 must not be set")),
+            PacketType::UNSUBSCRIBE.check_first_byte(0b10100100));
+        assert_eq!(
+            error("Invalid fixed header flags of PUBACK: 0010 instead of 0000, bit 1 must not be set"),
+            PacketType::PUBACK.check_first_byte(0b01000010));
+        assert_eq!(
+            error("First byte is not a PUBREL one: 01010010"),
+            PacketType::PUBREL.check_first_byte(0b01010010));
+    }
+
     fn do_test_packet_from_u8(numeric: u8, expected: PacketType) {
         let res = PacketType::try_from(numeric);
         assert_eq!(expected, res.unwrap());
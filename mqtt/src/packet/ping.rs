@@ -1,13 +1,36 @@
 use crate::error::MqttError;
 
-use super::MqttControlPacket;
+use super::{MqttControlPacket, PacketType};
 
+/// A `PINGREQ` packet, sent by the client to keep the connection alive.
+/// 
+/// There is nothing to configure, so the encoded packet is available as a constant as well:
+/// ```
+/// use mqtt::packet::Pingreq;
+/// 
+/// let encoded: Vec<u8> = Pingreq {}.into();
+/// assert_eq!(&Pingreq::BYTES[..], &encoded[..]);
+/// ```
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
 pub struct Pingreq {}
 
+/// A `PINGRESP` packet, the server's response to [Pingreq].
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
 pub struct Pingresp {}
 
-const PINGREQ: [u8; 2] = [0b11000000, 0];
-const PINGRESP: [u8; 2] = [0b11010000, 0];
+impl Pingreq {
+    /// The binary representation of any `PINGREQ`.
+    pub const BYTES: [u8; 2] = [PacketType::PINGREQ.to_first_byte(PacketType::PINGREQ.required_flags()), 0];
+}
+
+impl Pingresp {
+    /// The binary representation of any `PINGRESP`.
+    pub const BYTES: [u8; 2] = [PacketType::PINGRESP.to_first_byte(PacketType::PINGRESP.required_flags()), 0];
+}
 
 impl MqttControlPacket<'_> for Pingreq {
     fn packet_type() -> super::PacketType {
@@ -17,10 +40,7 @@ impl MqttControlPacket<'_> for Pingreq {
 
 impl From<Pingreq> for Vec<u8> {
     fn from(_: Pingreq) -> Self {
-        let mut result = Vec::with_capacity(2);
-        result.push(PINGREQ[0]);
-        result.push(PINGREQ[1]);
-        result
+        Pingreq::BYTES.to_vec()
     }
 }
 
@@ -28,7 +48,7 @@ impl TryFrom<&[u8]> for Pingreq {
     type Error = MqttError;
     
     fn try_from(src: &[u8]) -> Result<Self, Self::Error> {
-        if src != &PINGREQ[..] {
+        if src != &Pingreq::BYTES[..] {
             return Err(MqttError::MalformedPacket(format!("Invalid PINGREQ packet: {:?}", src)))
         }
         Ok(Pingreq{})
@@ -43,10 +63,7 @@ impl MqttControlPacket<'_> for Pingresp {
 
 impl From<Pingresp> for Vec<u8> {
     fn from(_: Pingresp) -> Self {
-        let mut result = Vec::with_capacity(2);
-        result.push(0b11010000);
-        result.push(0);
-        result
+        Pingresp::BYTES.to_vec()
     }
 }
 
@@ -54,7 +71,7 @@ impl TryFrom<&[u8]> for Pingresp {
     type Error = MqttError;
 
     fn try_from(src: &[u8]) -> Result<Self, Self::Error> {
-        if src != &PINGRESP[..] {
+        if src != &Pingresp::BYTES[..] {
             return Err(MqttError::MalformedPacket(format!("Invalid PINGRESP packet: {:?}", src)))
         }
         Ok(Pingresp{})
@@ -1,16 +1,25 @@
-//! This is internal API - types and utils to work with properties, which can occur in almost any MQTT control packet
-//! as well the the last will. Since all packets work on the same (sub-) set of these properties, they're collected 
-//! here to allow packets to work with them efficiently.
+//! Types and utils to work with properties, which can occur in almost any MQTT control packet as well the the last
+//! will. Since all packets work on the same (sub-) set of these properties, they're collected here to allow packets to
+//! work with them efficiently.
+//!
+//! Only [MqttProperty] and what it consists of are public, for code that handles properties without knowing the
+//! packet they belong to. Every properties struct converts into a `Vec<MqttProperty>` and back.
 
 use crate::{
     error::MqttError,
     types::{BinaryData, MqttDataType, UTF8String, VariableByteInteger, UTF8StringPair},
+    wire,
 };
 
-use super::{encode_and_append, u16_from_be_bytes, u32_from_be_bytes};
+use super::{u16_from_be_bytes, u32_from_be_bytes, violation, Violation};
 
-/// Numeric IDs.
+/// Numeric IDs. The spec defines them as variable byte integers, even though all of the current ones fit into a single
+/// byte, see [PropertyIdentifier::value].
+///
+/// That leaves room for more, so matches outside this crate need a wildcard arm.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(u32)]
+#[non_exhaustive]
 pub enum PropertyIdentifier {
     PayloadFormatIndicator = 1,
     MessageExpiryInterval = 2,
@@ -42,7 +51,19 @@ pub enum PropertyIdentifier {
 }
 
 /// MQTT control packets may include optional properties as part of the variable header.
-#[derive(Debug)]
+///
+/// # Examples
+/// ```
+/// use mqtt::packet::{DataRepresentation, MqttProperty, PropertyIdentifier, PubackProperties};
+///
+/// let properties = PubackProperties { reason_string: Some("because".into()), ..Default::default() };
+/// let generic: Vec<MqttProperty> = properties.into();
+/// assert_eq!(PropertyIdentifier::ReasonString, generic[0].identifier);
+///
+/// let typed = PubackProperties::try_from(generic).unwrap();
+/// assert_eq!(Some("because".to_string()), typed.reason_string);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
 pub struct MqttProperty {
     /// One of the defined IDs.
     pub identifier: PropertyIdentifier,
@@ -53,7 +74,7 @@ pub struct MqttProperty {
 
 /// We need this enum as a wrapper around the actual datatypes so we can get some type of polymorphism
 /// going without having to manually unscrew the lid off the heap and hardwire bits just to have the compiler scream at us.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum DataRepresentation {
     /// Single byte value
     Byte(u8),
@@ -86,7 +107,7 @@ pub fn parse_properties<F>(src: &[u8], mut f: F) -> Result<usize, MqttError>
 where
     F: FnMut(MqttProperty) -> Result<(), MqttError>
 {
-    if src.len() == 0 {
+    if src.is_empty() {
         return Ok(0)
     }
     
@@ -94,11 +115,16 @@ where
     let length: usize = properties_length.value.try_into().unwrap();
 
     let remain = &src[properties_length.encoded_len()..];
+    if length > remain.len() {
+        return Err(MqttError::MalformedPacket(
+            format!("Property length {} exceeds the remaining {} bytes", length, remain.len())))
+    }
     let mut cursor = 0;
 
     while cursor < length {
-        let identifier = PropertyIdentifier::try_from(&remain[cursor])?;
-        cursor += 1;
+        let encoded_identifier = VariableByteInteger::try_from(&remain[cursor..])?;
+        let identifier = PropertyIdentifier::try_from(encoded_identifier.value)?;
+        cursor += encoded_identifier.encoded_len();
 
         let value = match identifier {
             PropertyIdentifier::PayloadFormatIndicator | 
@@ -109,7 +135,8 @@ where
             PropertyIdentifier::WildcardSubscriptionAvailable |
             PropertyIdentifier::SubscriptionIdentifierAvailable |
             PropertyIdentifier::SharedSubscriptionAvailable => {
-                DataRepresentation::Byte(remain[cursor])
+                DataRepresentation::Byte(*remain.get(cursor).ok_or_else(|| MqttError::MalformedPacket(
+                    format!("Property {:?} is missing its value", identifier)))?)
             },
             PropertyIdentifier::ServerKeepAlive |
             PropertyIdentifier::ReceiveMaximum |
@@ -145,23 +172,112 @@ where
         };
 
         cursor += value.encoded_len();
-        f(MqttProperty { identifier, value })?;
+        let property = MqttProperty { identifier, value };
+        property.validate()?;
+        f(property)?;
     }
 
     Ok(properties_length.encoded_len() + cursor)
 }
 
-pub fn encode_and_append_property(identifier: PropertyIdentifier, value: DataRepresentation, target: &mut Vec<u8>) -> u32 {
-    // yeah, this isn't super safe...
-    let len = value.encoded_len() as u32 + 1;
-    let mut property: Vec<u8> = MqttProperty { identifier, value }.into();
-    target.append(&mut property);
-    len
+impl MqttProperty {
+
+    /// Checks the value against the rules the spec has for this property beyond its data type. Currently these are
+    /// the properties that must not be `0`: Receive Maximum [MQTT-3.1.2.11.3, MQTT-3.2.2.3.3], Maximum Packet Size
+    /// [MQTT-3.1.2.11.4, MQTT-3.2.2.3.6], Topic Alias [MQTT-3.3.2-8] and Subscription Identifier [MQTT-3.3.2.3.8].
+    pub(crate) fn validate(&self) -> Result<(), MqttError> {
+        let zero = match (self.identifier, &self.value) {
+            (PropertyIdentifier::ReceiveMaximum, DataRepresentation::TwoByteInt(v)) => *v == 0,
+            (PropertyIdentifier::TopicAlias, DataRepresentation::TwoByteInt(v)) => *v == 0,
+            (PropertyIdentifier::MaximumPacketSize, DataRepresentation::FourByteInt(v)) => *v == 0,
+            (PropertyIdentifier::SubscriptionIdentifier, DataRepresentation::VariByteInt(v)) => v.value == 0,
+            _ => false,
+        };
+
+        match zero {
+            true => Err(MqttError::ProtocolError(format!(
+                "Property {:?} (0x{:02X}) must not be 0", self.identifier, self.identifier.value()))),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Authentication data may only be sent along with an authentication method [MQTT-3.1.2.11.10, MQTT-3.15.2.2.3].
+/// Decoding leniently drops the data if the method is missing.
+pub(crate) fn check_authentication(
+    properties: &'static str,
+    method: &Option<String>,
+    data: &mut Option<Vec<u8>>,
+    violations: &mut Option<&mut Vec<Violation>>,
+) -> Result<(), MqttError> {
+    if method.is_some() || data.is_none() {
+        return Ok(())
+    }
+    violation::tolerate(violations, Violation::PropertyWithout {
+        properties,
+        identifier: PropertyIdentifier::AuthenticationData,
+        missing: PropertyIdentifier::AuthenticationMethod,
+    })?;
+    *data = None;
+    Ok(())
+}
+
+/// Encodes a property straight into `target`, returning the number of bytes written. Nothing is written if the value
+/// doesn't fit its data type, such as a string longer than 65,535 bytes.
+pub fn encode_and_append_property(
+    identifier: PropertyIdentifier,
+    value: DataRepresentation,
+    target: &mut Vec<u8>,
+) -> Result<u32, MqttError> {
+    let start = target.len();
+    let result = write_property(identifier, &value, target);
+    if result.is_err() {
+        target.truncate(start);
+    }
+    result?;
+    // a property can't be longer than an identifier plus a string pair of 65,535 bytes each
+    Ok((target.len() - start) as u32)
+}
+
+fn write_property(
+    identifier: PropertyIdentifier,
+    value: &DataRepresentation,
+    target: &mut Vec<u8>,
+) -> Result<(), MqttError> {
+    VariableByteInteger { value: identifier.value() }.write_to(target);
+    match value {
+        DataRepresentation::Byte(b) => target.push(*b),
+        DataRepresentation::TwoByteInt(i) => wire::push_be_u16(*i, target),
+        DataRepresentation::FourByteInt(i) => wire::push_be_u32(*i, target),
+        DataRepresentation::VariByteInt(v) => wire::push_variable_byte_integer(v.value, target)?,
+        DataRepresentation::UTF8(s) => wire::push_string(s.value.as_deref().unwrap_or_default(), target)?,
+        DataRepresentation::UTF8Pair(pair) => {
+            wire::push_string(pair.key.value.as_deref().unwrap_or_default(), target)?;
+            wire::push_string(pair.value.value.as_deref().unwrap_or_default(), target)?;
+        },
+        DataRepresentation::BinaryData(data) => wire::push_binary(data.as_bytes(), target)?,
+    }
+    Ok(())
+}
+
+impl PropertyIdentifier {
+
+    /// The numeric ID, as encoded into a variable byte integer.
+    pub const fn value(&self) -> u32 {
+        *self as u32
+    }
 }
 
 impl TryFrom<&u8> for PropertyIdentifier {
     type Error = MqttError;
     fn try_from(value: &u8) -> Result<Self, Self::Error> {
+        Self::try_from(u32::from(*value))
+    }
+}
+
+impl TryFrom<u32> for PropertyIdentifier {
+    type Error = MqttError;
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
         let result: PropertyIdentifier = match value {
             1 => Self::PayloadFormatIndicator,
             2 => Self::MessageExpiryInterval,
@@ -191,7 +307,7 @@ impl TryFrom<&u8> for PropertyIdentifier {
             41 => Self::SubscriptionIdentifierAvailable,
             42 => Self::SharedSubscriptionAvailable,
             _ => {
-                return Err(MqttError::Message(format!(
+                return Err(MqttError::MalformedPacket(format!(
                     "Unknown property identifier: {}",
                     value
                 )))
@@ -205,7 +321,7 @@ impl TryFrom<&u8> for PropertyIdentifier {
 /// FIXME we really should introduce a separate trait for defining encodeable elements, not "abuse" the MqttDataType
 impl MqttDataType for PropertyIdentifier {
     fn encoded_len(&self) -> usize {
-        1 // right now all values are < 128. will have to change to VBI eventually
+        VariableByteInteger { value: self.value() }.encoded_len()
     }
 }
 
@@ -213,12 +329,12 @@ impl DataRepresentation {
 
     /// Returns [`DataRepresentation::TwoByteInt`]
     fn decode_as_u16(src: &[u8]) -> Result<Self, MqttError> {
-        Ok(Self::TwoByteInt(u16_from_be_bytes(&src)?))
+        Ok(Self::TwoByteInt(u16_from_be_bytes(src)?))
     }
 
     /// Returns [`DataRepresentation::FourByteInt`]
     fn decode_as_u32(src: &[u8]) -> Result<Self, MqttError> {
-        Ok(Self::FourByteInt(u32_from_be_bytes(&src)?))
+        Ok(Self::FourByteInt(u32_from_be_bytes(src)?))
     }
 }
 
@@ -259,32 +375,72 @@ impl MqttDataType for MqttProperty {
     }
 }
 
-impl From<MqttProperty> for Vec<u8> {
-    fn from(src: MqttProperty) -> Self {
-        let mut result = Vec::new();
-
-        // this works for now, because all IDs have a numeric value < 127
-        // technically, this should be a Variable Byte Integer, though
-        result.push(src.identifier as u8);
-
-        match src.value {
-            DataRepresentation::Byte(b) => result.push(b),
-            DataRepresentation::TwoByteInt(i) => super::push_be_u16(i, &mut result),
-            DataRepresentation::FourByteInt(i) => super::push_be_u32(i, &mut result),
-            DataRepresentation::VariByteInt(v) => encode_and_append(v, &mut result),
-            DataRepresentation::UTF8(v) => encode_and_append(v, &mut result),
-            DataRepresentation::UTF8Pair(v) => encode_and_append(v, &mut result),
-            DataRepresentation::BinaryData(v) => encode_and_append(v, &mut result),
-        }
-        
-        result
+impl TryFrom<MqttProperty> for Vec<u8> {
+    type Error = MqttError;
+
+    fn try_from(src: MqttProperty) -> Result<Self, Self::Error> {
+        let mut result = Vec::with_capacity(src.encoded_len());
+        write_property(src.identifier, &src.value, &mut result)?;
+        Ok(result)
     }
 }
 #[cfg(test)]
 mod tests {
 
+    use crate::packet::{PublishProperties, SubackProperties};
+
     use super::*;
 
+    #[test]
+    fn generic_round_trip() {
+        let mut properties = PublishProperties {
+            content_type: Some("text/plain".into()),
+            subscription_identifier: vec![VariableByteInteger { value: 1 }, VariableByteInteger { value: 7 }],
+            ..Default::default()
+        };
+        properties.user_property.push("k".into(), "v".into());
+
+        let generic: Vec<MqttProperty> = properties.clone().into();
+        assert_eq!(4, generic.len());
+        assert_eq!(properties, PublishProperties::try_from(generic).unwrap());
+    }
+
+    #[test]
+    fn len_and_iter() {
+        let mut properties = PublishProperties::default();
+        assert!(properties.is_empty());
+        assert_eq!(0, properties.iter().count());
+
+        properties.message_expiry_interval = Some(60);
+        properties.subscription_identifier = vec![VariableByteInteger { value: 1 }, VariableByteInteger { value: 2 }];
+        assert!(!properties.is_empty());
+        assert_eq!(3, properties.len());
+
+        let identifiers: Vec<PropertyIdentifier> = properties.iter().map(|p| p.identifier).collect();
+        assert_eq!(
+            vec![PropertyIdentifier::MessageExpiryInterval, PropertyIdentifier::SubscriptionIdentifier, PropertyIdentifier::SubscriptionIdentifier],
+            identifiers);
+    }
+
+    #[test]
+    fn generic_invalid() {
+        let reason_string = || MqttProperty {
+            identifier: PropertyIdentifier::ReasonString,
+            value: DataRepresentation::UTF8(UTF8String::from("r")),
+        };
+        assert_eq!(
+            Err(MqttError::ProtocolError("Duplicate property identifier 0x1F for SubackProperties".into())),
+            SubackProperties::try_from(vec![reason_string(), reason_string()]));
+
+        let topic_alias = MqttProperty { identifier: PropertyIdentifier::TopicAlias, value: DataRepresentation::TwoByteInt(1) };
+        assert_eq!(
+            Err(MqttError::ProtocolError("Unexpected property identifier 0x23 for SubackProperties".into())),
+            SubackProperties::try_from(vec![topic_alias]));
+
+        let receive_maximum = MqttProperty { identifier: PropertyIdentifier::ReceiveMaximum, value: DataRepresentation::TwoByteInt(0) };
+        assert!(crate::packet::ConnectProperties::try_from(vec![receive_maximum]).is_err());
+    }
+
     #[test]
     fn encode_property() {
         test_encode(PropertyIdentifier::PayloadFormatIndicator, DataRepresentation::Byte(1), vec![1, 1]);
@@ -297,9 +453,59 @@ mod tests {
         );
     }
     
+    #[test]
+    fn encode_into_buffer() {
+        let mut target = vec![0xFF];
+        let pair = UTF8StringPair::new("k".into(), "v".into());
+        let user_property = DataRepresentation::UTF8Pair(pair);
+        assert_eq!(Ok(7), encode_and_append_property(PropertyIdentifier::UserProperty, user_property, &mut target));
+        assert_eq!(vec![0xFF, 38, 0, 1, b'k', 0, 1, b'v'], target);
+
+        // nothing of a property that can't be encoded is left behind
+        let too_long = UTF8String::from("a".repeat(70_000));
+        assert_eq!(
+            Err(MqttError::StringTooLong { len: 70_000 }),
+            encode_and_append_property(
+                PropertyIdentifier::ContentType, DataRepresentation::UTF8(too_long), &mut target));
+        assert_eq!(8, target.len());
+    }
+
+    #[test]
+    fn identifier_values() {
+        assert_eq!(38, PropertyIdentifier::UserProperty.value());
+        assert_eq!(Ok(PropertyIdentifier::UserProperty), PropertyIdentifier::try_from(38));
+        assert_eq!(Ok(PropertyIdentifier::ContentType), PropertyIdentifier::try_from(&3));
+        assert!(PropertyIdentifier::try_from(128).is_err());
+    }
+
+    #[test]
+    fn parse_multi_byte_identifier() {
+        // identifier 128 encoded in two bytes, followed by a byte value
+        assert_eq!(
+            Err(MqttError::MalformedPacket("Unknown property identifier: 128".to_string())),
+            parse_properties(&[3, 0x80, 0x01, 1], |_| Ok(())));
+    }
+
+    #[test]
+    fn zero_values() {
+        for (src, name) in [
+            (vec![3, 33, 0, 0], "ReceiveMaximum (0x21)"),
+            (vec![5, 39, 0, 0, 0, 0], "MaximumPacketSize (0x27)"),
+            (vec![3, 35, 0, 0], "TopicAlias (0x23)"),
+            (vec![2, 11, 0], "SubscriptionIdentifier (0x0B)"),
+        ] {
+            assert_eq!(
+                Err(MqttError::ProtocolError(format!("Property {} must not be 0", name))),
+                parse_properties(&src, |_| Ok(())));
+        }
+
+        assert_eq!(Ok(4), parse_properties(&[3, 33, 0, 1], |_| Ok(())));
+        assert_eq!(Ok(4), parse_properties(&[3, 34, 0, 0], |_| Ok(())), "Topic Alias Maximum may be 0");
+    }
+
     fn test_encode(identifier: PropertyIdentifier, value: DataRepresentation, expected: Vec<u8>) {
         let prop = MqttProperty { identifier, value };
-        let encoded: Vec<u8> = prop.into();
+        let encoded: Vec<u8> = prop.try_into().unwrap();
         assert_eq!(expected, encoded);
     }
 }
\ No newline at end of file
@@ -1,23 +1,26 @@
-use std::collections::HashMap;
-
 use mqtt_derive::MqttProperties;
 
-use crate::{types::{ReasonCode, MqttDataType}, error::MqttError, packet::Decodeable};
+use crate::{types::{ReasonCode, MqttDataType, UserProperties}, error::MqttError};
 
-use super::MqttControlPacket;
+use super::{MqttControlPacket, PacketType, Violation};
 
 /// `PUBACK` is the response to a `PUBLISH` that was sent with [crate::types::QoS::AtLeastOnce].
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
 pub struct Puback {
     pub packet_identifier: u16,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub reason_code: ReasonCode,
     pub properties: Option<PubackProperties>,
 }
 
-#[derive(Debug, MqttProperties)]
+#[derive(Debug, Clone, PartialEq, MqttProperties)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
 pub struct PubackProperties {
     pub reason_string: Option<String>,
-    pub user_property: HashMap<String, String>,
+    pub user_property: UserProperties,
 }
 
 impl MqttControlPacket<'_> for Puback {
@@ -27,7 +30,7 @@ impl MqttControlPacket<'_> for Puback {
 }
 
 /// Fixed first byte of the header
-const FIRST_BYTE: u8 = 0b01000000;
+const FIRST_BYTE: u8 = PacketType::PUBACK.to_first_byte(PacketType::PUBACK.required_flags());
 
 impl Puback {
 
@@ -52,8 +55,10 @@ impl Puback {
     }
 }
 
-impl From<Puback> for Vec<u8> {
-    fn from(puback: Puback) -> Self {
+impl TryFrom<Puback> for Vec<u8> {
+    type Error = MqttError;
+
+    fn try_from(puback: Puback) -> Result<Self, Self::Error> {
         let mut result: Vec<u8> = Vec::new();
 
         result.push(FIRST_BYTE);
@@ -63,18 +68,18 @@ impl From<Puback> for Vec<u8> {
         //
         // "Byte 3 in the Variable Header is the PUBACK Reason Code. If the Remaining Length is 2, then there is
         // no Reason Code and the value of 0x00 (Success) is used."
-        if ReasonCode::Success != puback.reason_code || puback.properties.is_some() {
+        if ReasonCode::Success != puback.reason_code || puback.properties.as_ref().is_some_and(|p| !p.is_empty()) {
             result.push(puback.reason_code.into());
 
             match puback.properties {
-                Some(props) => result.append(&mut props.into()),
+                Some(props) => result.append(&mut props.try_into()?),
                 None => result.push(0),
             }
         }
 
         super::calculate_and_insert_length(&mut result);
 
-        result
+        Ok(result)
     }
 }
 
@@ -82,6 +87,14 @@ impl TryFrom<&[u8]> for Puback {
     type Error = MqttError;
 
     fn try_from(src: &[u8]) -> Result<Self, Self::Error> {
+        Self::decode_from(src, None)
+    }
+}
+
+impl Puback {
+
+    /// Decodes strictly, or leniently if there are `violations` to record what was tolerated in.
+    pub(crate) fn decode_from(src: &[u8], mut violations: Option<&mut Vec<Violation>>) -> Result<Self, MqttError> {
         let mut cursor = 0;
 
         match src[cursor] {
@@ -103,9 +116,13 @@ impl TryFrom<&[u8]> for Puback {
                 let reason_code = ReasonCode::try_from(src[cursor])?;
                 cursor += reason_code.encoded_len();
 
-                (reason_code, PubackProperties::decode(&src[cursor..])?.value())
+                let prop_res = super::decode_properties::<PubackProperties>(&src[cursor..], violations.as_deref_mut())?;
+                cursor += prop_res.bytes_read();
+
+                (reason_code, prop_res.value())
             }
         };
+        super::check_remaining_length(PacketType::PUBACK, &remain_len, cursor, &mut violations)?;
 
         // includes validation of reason codes
         let mut result = Self::new(packet_identifier, reason_code)?;
@@ -123,12 +140,12 @@ mod tests {
     #[test]
     fn encode_and_decode() {
         let puback = Puback::new(123, ReasonCode::Success).unwrap();
-        let encoded: Vec<u8> = puback.into();
+        let encoded: Vec<u8> = puback.try_into().unwrap();
         assert_eq!(encoded, vec![64, 2, 0, 123]);
 
         let decoded = Puback::try_from(&encoded[..]).unwrap();
         assert_eq!(123_u16, decoded.packet_identifier);
-        assert_eq!(0x00_u8, decoded.reason_code.into());
+        assert_eq!(0x00_u8, u8::from(decoded.reason_code));
         assert!(decoded.properties.is_none());
     }
 
@@ -137,16 +154,24 @@ mod tests {
         let mut puback = Puback::new(6397, ReasonCode::UnspecifiedError).unwrap();
         let mut properties = PubackProperties::default();
         properties.reason_string = Some("too lazy at the moment, apologies".into());
-        properties.user_property.insert("options".into(), "none, really".into());
+        properties.user_property.push("options".into(), "none, really".into());
         puback.properties = Some(properties);
 
-        let encoded: Vec<u8> = puback.into();
+        let encoded: Vec<u8> = puback.try_into().unwrap();
         let decoded = Puback::try_from(&encoded[..]).unwrap();
         assert_eq!(6397_u16, decoded.packet_identifier);
-        assert_eq!(0x80_u8, decoded.reason_code.into());
+        assert_eq!(0x80_u8, u8::from(decoded.reason_code));
         assert!(decoded.properties.is_some());
     }
 
+    #[test]
+    fn encode_empty_properties_short() {
+        let mut puback = Puback::new(1, ReasonCode::Success).unwrap();
+        puback.properties = Some(PubackProperties::default());
+        let encoded: Vec<u8> = puback.try_into().unwrap();
+        assert_eq!(vec![0x40, 0x02, 0x00, 0x01], encoded);
+    }
+
     #[test]
     fn reason_code_validation() {
         assert!(Puback::new(123, ReasonCode::AdministrativeAction).is_err());
@@ -1,10 +1,8 @@
-use std::collections::HashMap;
-
 use mqtt_derive::MqttProperties;
 
-use crate::{types::{ReasonCode, MqttDataType}, error::MqttError};
+use crate::{types::{ReasonCode, MqttDataType, UserProperties}, error::MqttError};
 
-use super::{MqttControlPacket, Decodeable};
+use super::{MqttControlPacket, PacketType, Violation};
 
 /// `PUBCOMP` is the final message in the flow initiated with `PUBLISH` sent with [crate::types::QoS::ExactlyOnce].
 /// 
@@ -13,21 +11,26 @@ use super::{MqttControlPacket, Decodeable};
 /// - `PUBREC` <--
 /// - `PUBREL` -->
 /// - `PUBCOMP` <-- 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
 pub struct Pubcomp {
     pub packet_identifier: u16,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub reason_code: ReasonCode,
     pub properties: Option<PubcompProperties>,
 }
 
-#[derive(Debug, MqttProperties)]
+#[derive(Debug, Clone, PartialEq, MqttProperties)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
 pub struct PubcompProperties {
     pub reason_string: Option<String>,
-    pub user_property: HashMap<String, String>,
+    pub user_property: UserProperties,
 }
 
 /// Fixed first byte of the header
-const FIRST_BYTE: u8 = 0b01110000;
+const FIRST_BYTE: u8 = PacketType::PUBCOMP.to_first_byte(PacketType::PUBCOMP.required_flags());
 
 impl MqttControlPacket<'_> for Pubcomp {
     fn packet_type() -> super::PacketType {
@@ -51,25 +54,27 @@ impl Pubcomp {
     }
 }
 
-impl From<Pubcomp> for Vec<u8> {
-    fn from(pubcomp: Pubcomp) -> Self {
+impl TryFrom<Pubcomp> for Vec<u8> {
+    type Error = MqttError;
+
+    fn try_from(pubcomp: Pubcomp) -> Result<Self, Self::Error> {
         let mut result: Vec<u8> = Vec::new();
 
         result.push(FIRST_BYTE);
         super::push_be_u16(pubcomp.packet_identifier, &mut result);
         
-        if pubcomp.reason_code != ReasonCode::Success || pubcomp.properties.is_some() {
+        if pubcomp.reason_code != ReasonCode::Success || pubcomp.properties.as_ref().is_some_and(|p| !p.is_empty()) {
             result.push(pubcomp.reason_code.into());
 
             match pubcomp.properties {
-                Some(props) => result.append(&mut props.into()),
+                Some(props) => result.append(&mut props.try_into()?),
                 None => result.push(0),
             }
         }
 
         super::calculate_and_insert_length(&mut result);
 
-        result
+        Ok(result)
     }
 }
 
@@ -77,6 +82,14 @@ impl TryFrom<&[u8]> for Pubcomp {
     type Error = MqttError;
 
     fn try_from(src: &[u8]) -> Result<Self, Self::Error> {
+        Self::decode_from(src, None)
+    }
+}
+
+impl Pubcomp {
+
+    /// Decodes strictly, or leniently if there are `violations` to record what was tolerated in.
+    pub(crate) fn decode_from(src: &[u8], mut violations: Option<&mut Vec<Violation>>) -> Result<Self, MqttError> {
         let mut cursor = 0;
 
         match src[cursor] {
@@ -96,9 +109,13 @@ impl TryFrom<&[u8]> for Pubcomp {
                 let reason_code = ReasonCode::try_from(src[cursor])?;
                 cursor += reason_code.encoded_len();
 
-                (reason_code, PubcompProperties::decode(&src[cursor..])?.value())
+                let prop_res = super::decode_properties::<PubcompProperties>(&src[cursor..], violations.as_deref_mut())?;
+                cursor += prop_res.bytes_read();
+
+                (reason_code, prop_res.value())
             }
         };
+        super::check_remaining_length(PacketType::PUBCOMP, &remain_len, cursor, &mut violations)?;
         
         let mut result = Self::new(packet_identifier, reason_code)?;
         result.properties = properties;
@@ -116,12 +133,12 @@ mod tests {
     fn encode_and_decode() {
         let pubcomp = Pubcomp::new(123, ReasonCode::Success).unwrap();
         // [112, 4, 0, 123, 0, 0]
-        let encoded: Vec<u8> = pubcomp.into();
+        let encoded: Vec<u8> = pubcomp.try_into().unwrap();
         assert_eq!(0b01110000, encoded[0]);
 
         let decoded = Pubcomp::try_from(&encoded[..]).unwrap();
         assert_eq!(123_u16, decoded.packet_identifier);
-        assert_eq!(0x00_u8, decoded.reason_code.into());
+        assert_eq!(0x00_u8, u8::from(decoded.reason_code));
         assert!(decoded.properties.is_none());
     }
 
@@ -130,13 +147,13 @@ mod tests {
         let mut pubcomp = Pubcomp::new(6397, ReasonCode::PacketIdentifierNotFound).unwrap();
         let mut properties = PubcompProperties::default();
         properties.reason_string = Some("too lazy at the moment, apologies".into());
-        properties.user_property.insert("options".into(), "none, really".into());
+        properties.user_property.push("options".into(), "none, really".into());
         pubcomp.properties = Some(properties);
 
-        let encoded: Vec<u8> = pubcomp.into();
+        let encoded: Vec<u8> = pubcomp.try_into().unwrap();
         let decoded = Pubcomp::try_from(&encoded[..]).unwrap();
         assert_eq!(6397_u16, decoded.packet_identifier);
-        assert_eq!(0x92_u8, decoded.reason_code.into());
+        assert_eq!(0x92_u8, u8::from(decoded.reason_code));
         assert!(decoded.properties.is_some());
     }
 
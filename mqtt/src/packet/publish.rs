@@ -1,10 +1,10 @@
-use std::collections::HashMap;
+use std::time::Duration;
 
 use mqtt_derive::MqttProperties;
 
-use crate::{types::{QoS, VariableByteInteger, UTF8String, MqttDataType}, error::MqttError};
+use crate::{types::{check_no_wildcards, validate_topic, MqttDataType, Payload, QoS, TopicName, UTF8String, ValidationMode, VariableByteInteger, UserProperties}, error::MqttError};
 
-use super::{remaining_length, Decodeable, DecodingResult, MqttControlPacket};
+use super::{remaining_length, violation, DecodingResult, MqttControlPacket, PacketType, Violation};
 
 /// An MQTT `PUBLISH` packet is used to send a specific message to a topic.
 /// 
@@ -13,26 +13,31 @@ use super::{remaining_length, Decodeable, DecodingResult, MqttControlPacket};
 /// ```
 /// use mqtt::packet::Publish;
 /// 
-/// let publish = Publish::new(
-///     "/some/topic/name".into(),
-///     vec![0, 1, 2, 3, 4],
-/// );
+/// let publish = Publish::builder("/some/topic/name")
+///     .payload(vec![0, 1, 2, 3, 4])
+///     .build()
+///     .unwrap();
 /// 
 /// ```
 ///  
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
 pub struct Publish {
     // FIXED HEADER
     /// If `true` this message is considered an attempted re-delivery.
     /// Defaults to `false`, and **must** be so if QoS is `0`.
+    #[cfg_attr(feature = "serde", serde(default))]
     pub dup: bool,
 
     /// QoS for this message.
+    #[cfg_attr(feature = "serde", serde(default))]
     pub qos_level: QoS,
 
     /// Whether the server should keep this message for future subscribers or not.
     /// Defaults to `false`.
     /// Also read the spec on a lot more additional info about message retention.
+    #[cfg_attr(feature = "serde", serde(default))]
     pub retain: bool,
 
     // VARIABLE HEADER
@@ -58,19 +63,47 @@ pub struct Publish {
     /// data is application specific. The length of the Payload can be calculated by subtracting the length of the
     /// Variable Header from the Remaining Length field that is in the Fixed Header. It is valid for a PUBLISH
     /// packet to contain a zero length Payload.
-    pub payload: Vec<u8>,
+    /// 
+    /// Clones of the packet share the payload, see [Payload].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub payload: Payload,
+
+    /// The properties as they were received, only kept by [Publish::decode_retaining_properties].
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub raw_properties: Option<RawProperties>,
+}
+
+/// The property block of a `PUBLISH` exactly as it was received, length included. Encoding the packet again writes it
+/// as it is, unless the [properties](Publish::properties) were changed since, so that a proxy forwarding messages
+/// doesn't alter anything it doesn't touch: not the order of the properties, nor duplicates it would otherwise drop
+/// when decoding leniently.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawProperties {
+    bytes: Vec<u8>,
+    /// what the bytes were decoded into, to tell whether the properties were changed since
+    decoded: Option<PublishProperties>,
+}
+
+impl RawProperties {
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
 }
 
 /// See [the MQTT spec](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html) about properties.
-#[derive(Debug, MqttProperties)]
+#[derive(Debug, Clone, PartialEq, MqttProperties)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
 pub struct PublishProperties {
     pub payload_format_indicator: Option<bool>,
     pub message_expiry_interval: Option<u32>,
     pub topic_alias: Option<u16>,
     pub response_topic: Option<String>,
     pub correlation_data: Option<Vec<u8>>,
-    pub user_property: HashMap<String, String>,
-    pub subscription_identifier: Option<VariableByteInteger>,
+    pub user_property: UserProperties,
+    /// Set by the server, one for each matching subscription that was made with an identifier.
+    pub subscription_identifier: Vec<VariableByteInteger>,
     pub content_type: Option<String>,
 }
 
@@ -82,14 +115,20 @@ impl MqttControlPacket<'_> for Publish {
 
 impl Publish {
 
-    const PACKET_TYPE: u8 = 0b00110000;
     const DUP_FLAG_MASK: u8 = 0b00001000;
     const RETAIN_FLAG_MASK: u8 = 0b00000001;
-    const QOS_MASK: u8 = 0b00000110;
+    const QOS_SHIFT: u8 = 1;
 
     /// Creates a new Publish packet using sane defaults for everything but the supplied values.
     /// [Publish] doesn't implement `Default` primarily because a "meaningful" topic name is a must.
-    pub fn new(topic_name: String, payload: Vec<u8>) -> Self {
+    #[deprecated(note = "the topic name isn't validated, use `Publish::with_topic_name` or `Publish::builder` instead")]
+    pub fn new(topic_name: String, payload: impl Into<Payload>) -> Self {
+        Self::new_unchecked(topic_name, payload)
+    }
+
+    /// Same as [Publish::new], for the crate's own use where the topic name is known to be valid or deliberately
+    /// isn't, such as in tests.
+    pub(crate) fn new_unchecked(topic_name: String, payload: impl Into<Payload>) -> Self {
         Self {
             dup:false,
             qos_level: QoS::AtMostOnce,
@@ -97,33 +136,229 @@ impl Publish {
             topic_name,
             packet_identifier: None,
             properties: None,
-            payload,
+            payload: payload.into(),
+            raw_properties: None,
+        }
+    }
+
+    /// Starts building a message to `topic_name`, with properties set one at a time. See [PublishBuilder].
+    pub fn builder(topic_name: impl Into<String>) -> PublishBuilder {
+        PublishBuilder::new(topic_name.into())
+    }
+
+    /// Creates a new Publish packet using sane defaults for everything but the supplied values, with a topic name that
+    /// has already been [validated](TopicName).
+    pub fn with_topic_name(topic_name: TopicName, payload: impl Into<Payload>) -> Self {
+        Self::new_unchecked(topic_name.into(), payload)
+    }
+
+    /// A message without topic name, which the receiver looks up by the topic alias it was mapped to earlier on the same
+    /// connection.
+    /// 
+    /// # Examples
+    /// ```
+    /// use mqtt::packet::Publish;
+    /// 
+    /// let publish = Publish::with_alias(3, "hello");
+    /// let encoded: Vec<u8> = publish.clone().try_into().unwrap();
+    /// assert_eq!(publish, Publish::try_from(&encoded[..]).unwrap());
+    /// 
+    /// // without an alias, there is no telling which topic the message belongs to
+    /// let mut publish = Publish::with_alias(3, "hello");
+    /// publish.properties = None;
+    /// let encoded: Result<Vec<u8>, _> = publish.try_into();
+    /// assert!(encoded.is_err());
+    /// ```
+    pub fn with_alias(topic_alias: u16, payload: impl Into<Payload>) -> Self {
+        let mut publish = Self::new_unchecked(String::new(), payload);
+        publish.properties = Some(PublishProperties { topic_alias: Some(topic_alias), ..Default::default() });
+        publish
+    }
+
+    /// Sets the lifetime of the message in whole seconds.
+    pub fn set_message_expiry(&mut self, message_expiry: Duration) -> Result<(), MqttError> {
+        let secs = super::interval_secs("Message expiry interval", message_expiry, u32::MAX)?;
+        self.properties.get_or_insert_with(PublishProperties::default).message_expiry_interval = Some(secs);
+        Ok(())
+    }
+
+    /// A zero-length topic name is only allowed together with a topic alias [MQTT-3.3.2.3.4].
+    fn check_topic_or_alias(&self) -> Result<(), MqttError> {
+        let has_alias = self.properties.as_ref().is_some_and(|p| p.topic_alias.is_some());
+        match self.topic_name.is_empty() && !has_alias {
+            true => Err(MqttError::ProtocolError("PUBLISH has neither topic name nor topic alias".to_string())),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Builds a [Publish], setting properties without spelling out [PublishProperties]. The rules that involve more than
+/// one of the values are checked by [build](PublishBuilder::build), which fails with the first one broken.
+///
+/// A QoS 1 or 2 message may be built without a packet identifier, to be given one when it's about to be sent. That's
+/// for the caller to take care of, such as a [ClientSession](crate::session::ClientSession) tracking packet
+/// identifiers in use.
+///
+/// # Examples
+/// ```
+/// use std::time::Duration;
+/// use mqtt::packet::Publish;
+/// use mqtt::types::QoS;
+///
+/// let publish = Publish::builder("sensors/1/temperature")
+///     .payload(r#"{"celsius": 21.5}"#)
+///     .qos(QoS::AtLeastOnce)
+///     .retain()
+///     .expiry(Duration::from_secs(3600))
+///     .content_type("application/json")
+///     .user_property("unit", "celsius")
+///     .build()
+///     .unwrap();
+/// assert_eq!(QoS::AtLeastOnce, publish.qos_level);
+/// assert!(publish.retain);
+/// let properties = publish.properties.unwrap();
+/// assert_eq!(Some(3600), properties.message_expiry_interval);
+/// assert_eq!(Some("celsius"), properties.user_property.get("unit"));
+///
+/// assert!(Publish::builder("sensors/+/temperature").build().is_err());
+/// assert!(Publish::builder("a/b").packet_identifier(1).build().is_err());
+/// ```
+#[derive(Debug, Clone)]
+pub struct PublishBuilder {
+    publish: Publish,
+    expiry: Option<Duration>,
+}
+
+impl PublishBuilder {
+
+    fn new(topic_name: String) -> Self {
+        Self { publish: Publish::new_unchecked(topic_name, Payload::default()), expiry: None }
+    }
+
+    pub fn payload(mut self, payload: impl Into<Payload>) -> Self {
+        self.publish.payload = payload.into();
+        self
+    }
+
+    pub fn qos(mut self, qos: QoS) -> Self {
+        self.publish.qos_level = qos;
+        self
+    }
+
+    pub fn retain(mut self) -> Self {
+        self.publish.retain = true;
+        self
+    }
+
+    /// Only allowed with QoS 1 or 2 [MQTT-2.2.1-2].
+    pub fn packet_identifier(mut self, packet_identifier: u16) -> Self {
+        self.publish.packet_identifier = Some(packet_identifier);
+        self
+    }
+
+    /// The lifetime of the message in whole seconds, see [Publish::set_message_expiry].
+    pub fn expiry(mut self, message_expiry: Duration) -> Self {
+        self.expiry = Some(message_expiry);
+        self
+    }
+
+    /// Marks the payload as UTF-8, which it then has to be [MQTT-3.3.2-4].
+    pub fn utf8(mut self) -> Self {
+        self.properties().payload_format_indicator = Some(true);
+        self
+    }
+
+    pub fn content_type(mut self, content_type: impl Into<String>) -> Self {
+        self.properties().content_type = Some(content_type.into());
+        self
+    }
+
+    pub fn response_topic(mut self, response_topic: impl Into<String>) -> Self {
+        self.properties().response_topic = Some(response_topic.into());
+        self
+    }
+
+    pub fn correlation(mut self, correlation_data: impl Into<Vec<u8>>) -> Self {
+        self.properties().correlation_data = Some(correlation_data.into());
+        self
+    }
+
+    /// Adds a user property, keeping those added before even if they have the same key.
+    pub fn user_property(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.properties().user_property.push(key.into(), value.into());
+        self
+    }
+
+    /// Adds all user properties, such as those an application sends with every message.
+    pub fn user_properties(mut self, user_properties: &UserProperties) -> Self {
+        for (key, value) in user_properties.iter() {
+            self.properties().user_property.push(key.to_string(), value.to_string());
+        }
+        self
+    }
+
+    /// Returns the message, or an error if it breaks a rule: a topic name that doesn't pass
+    /// [strict validation](ValidationMode::Strict) or contains wildcards [MQTT-3.3.2-2], a packet identifier with QoS
+    /// 0 [MQTT-2.2.1-2], a response topic with wildcards [MQTT-3.3.2-14] or a payload marked as UTF-8 that isn't. An
+    /// expiry that isn't a whole number of seconds is an error as well.
+    pub fn build(self) -> Result<Publish, MqttError> {
+        let mut publish = self.publish;
+        validate_topic(&publish.topic_name, ValidationMode::Strict)?;
+        check_no_wildcards(&publish.topic_name)?;
+        if publish.qos_level == QoS::AtMostOnce && publish.packet_identifier.is_some() {
+            return Err(MqttError::ProtocolError("A QoS 0 PUBLISH must not have a packet identifier".to_string()))
+        }
+        if let Some(expiry) = self.expiry {
+            publish.set_message_expiry(expiry)?;
+        }
+
+        if let Some(properties) = &publish.properties {
+            if let Some(response_topic) = &properties.response_topic {
+                check_response_topic(response_topic)?;
+            }
+            if properties.payload_format_indicator == Some(true) && std::str::from_utf8(&publish.payload).is_err() {
+                return Err(MqttError::ProtocolError("Payload marked as UTF-8 isn't".to_string()))
+            }
         }
+        Ok(publish)
+    }
+
+    fn properties(&mut self) -> &mut PublishProperties {
+        self.publish.properties.get_or_insert_with(PublishProperties::default)
     }
 }
 
-impl From<Publish> for Vec<u8> {
-    fn from(publish: Publish) -> Self {
+fn check_response_topic(topic: &str) -> Result<(), MqttError> {
+    validate_topic(topic, ValidationMode::Strict)?;
+    match topic.contains(['+', '#']) {
+        true => Err(MqttError::ProtocolError(format!("Response topic must not contain wildcards: {}", topic))),
+        false => Ok(()),
+    }
+}
+
+impl TryFrom<Publish> for Vec<u8> {
+    type Error = MqttError;
+
+    fn try_from(publish: Publish) -> Result<Self, Self::Error> {
+        publish.check_topic_or_alias()?;
+        check_no_wildcards(&publish.topic_name)?;
         let mut result = Vec::new();
         
-        let mut first_byte = Publish::PACKET_TYPE;
+        let mut flags = 0;
         if publish.dup {
-            first_byte |= Publish::DUP_FLAG_MASK;
+            flags |= Publish::DUP_FLAG_MASK;
         }
 
-        let qos: u8 = publish.qos_level.into();
-        // shift qos bits to match their alignment in the resulting byte 
-        // and OR them to the resulting byte
-        first_byte |= qos << 1;
+        flags |= publish.qos_level.encode_bits(Publish::QOS_SHIFT);
 
         if publish.retain {
-            first_byte |= Publish::RETAIN_FLAG_MASK;
+            flags |= Publish::RETAIN_FLAG_MASK;
         }
-        result.push(first_byte);
+        result.push(PacketType::PUBLISH.to_first_byte(flags));
 
-        result.append(&mut UTF8String::from(publish.topic_name.as_str()).into());
+        result.append(&mut UTF8String::from(publish.topic_name.as_str()).try_into()?);
 
-        if qos > 0 {
+        if publish.qos_level != QoS::AtMostOnce {
             if let Some(pid) = publish.packet_identifier {
                 super::push_be_u16(pid, &mut result)
             } else {
@@ -133,17 +368,17 @@ impl From<Publish> for Vec<u8> {
             }
         }
 
-        match publish.properties {
-            Some(p) => result.append(&mut p.into()),
-            None => result.push(0),
+        match (publish.raw_properties, publish.properties) {
+            (Some(raw), properties) if raw.decoded == properties => result.extend_from_slice(&raw.bytes),
+            (_, Some(p)) => result.append(&mut p.try_into()?),
+            (_, None) => result.push(0),
         }
 
-        let mut payload = publish.payload;
-        result.append(&mut payload);
+        result.extend_from_slice(&publish.payload);
 
         super::calculate_and_insert_length(&mut result);
 
-        result
+        Ok(result)
     }
 }
 
@@ -151,79 +386,124 @@ impl TryFrom<&[u8]> for Publish {
     type Error = MqttError;
 
     fn try_from(src: &[u8]) -> Result<Self, Self::Error> {
+        Self::decode_from(src, None)
+    }
+}
+
+impl Publish {
+
+    /// The number of bytes the property block takes up when encoded, the retained one if it's still what gets sent.
+    pub(crate) fn properties_len(&self) -> usize {
+        match (&self.raw_properties, &self.properties) {
+            (Some(raw), properties) if &raw.decoded == properties => raw.bytes.len(),
+            (_, properties) => super::properties_len(properties.iter().flat_map(|p| p.iter())),
+        }
+    }
+
+    /// Decodes like `try_from`, and also keeps the property block as it is in
+    /// [raw_properties](Publish::raw_properties). Encoding the packet writes it back byte for byte, as long as the
+    /// decoded properties weren't changed in the meantime.
+    ///
+    /// ```
+    /// use mqtt::packet::Publish;
+    /// use mqtt::test_util::from_hex;
+    ///
+    /// // a content type, then two user properties with the same key
+    /// let bytes = from_hex("30 18 00 01 61 12 03 00 01 78 26 00 01 6b 00 01 31 26 00 01 6b 00 01 32 68 69").unwrap();
+    /// let mut publish = Publish::decode_retaining_properties(&bytes).unwrap();
+    /// publish.payload = "ho".into();
+    ///
+    /// let encoded: Vec<u8> = publish.try_into().unwrap();
+    /// assert_eq!(bytes[..bytes.len() - 2], encoded[..encoded.len() - 2]);
+    /// ```
+    pub fn decode_retaining_properties(src: &[u8]) -> Result<Self, MqttError> {
+        Self::decode_with(src, None, true)
+    }
+
+    /// Decodes strictly, or leniently if there are `violations` to record what was tolerated in.
+    pub(crate) fn decode_from(src: &[u8], violations: Option<&mut Vec<Violation>>) -> Result<Self, MqttError> {
+        Self::decode_with(src, violations, false)
+    }
+
+    fn decode_with(
+        src: &[u8],
+        mut violations: Option<&mut Vec<Violation>>,
+        retain_properties: bool) -> Result<Self, MqttError>
+    {
         let mut cursor = 0;
-        let packet_type = src[cursor] & Self::PACKET_TYPE;
-        if packet_type != Self::PACKET_TYPE {
+        if src[cursor] >> 4 != PacketType::PUBLISH as u8 {
             return Err(MqttError::MalformedPacket(
-                format!("Packet type is not a CONNECT packet: {:b}", packet_type)))
+                format!("First byte is not a PUBLISH one: {:b}", src[cursor])))
         }
-        let dup = 1 == src[cursor] | Self::DUP_FLAG_MASK;
-        let retain = 1 == src[cursor] | Self::RETAIN_FLAG_MASK;
+        let dup = src[cursor] & Self::DUP_FLAG_MASK != 0;
+        let retain = src[cursor] & Self::RETAIN_FLAG_MASK != 0;
 
-        let qos_level = QoS::try_from((src[cursor] & Self::QOS_MASK) >> 1)?;
+        let qos_level = QoS::from_bits(src[cursor], Self::QOS_SHIFT)?;
         cursor += 1;
 
         let remain_len = remaining_length(&src[cursor..])?;
         cursor += remain_len.encoded_len();
-        let mut payload_len = remain_len.value as usize;
+        let cursor_stop = cursor + remain_len.value as usize;
 
         // topic name
-        /* TODO!
-The Topic Name in the PUBLISH packet MUST NOT contain wildcard characters [MQTT-3.3.2-2].
-1536
-1537
-1538 according to the matching process defined in section 4.7 [MQTT-3.3.2-3].
-1539 However, as the Server is permitted to map the Topic Name to another name, it might not be the same as
-1540 the Topic Name in the original PUBLISH packet.
-1541
-1542 To reduce the size of the PUBLISH packet the sender can use a Topic Alias. The Topic Alias is described
-1543 in section 3.3.2.3.4. It is a Protocol Error if the Topic Name is zero length and there is no Topic Alias.
-1544        
-        */
         let topic_name_res = UTF8String::try_from(&src[cursor..])?;
         cursor += topic_name_res.encoded_len();
-        payload_len -= topic_name_res.encoded_len();
 
-        let topic_name = match topic_name_res.value {
-            Some(v) => v,
-            None => String::new(),
-        };
+        let topic_name = topic_name_res.value.unwrap_or_default();
+        // an empty topic name is only allowed together with a topic alias, checked once the properties are decoded
+        if !topic_name.is_empty() {
+            validate_topic(&topic_name, ValidationMode::Lenient)?;
+            if check_no_wildcards(&topic_name).is_err() {
+                violation::tolerate(&mut violations, Violation::TopicNameWildcard { topic: topic_name.clone() })?;
+            }
+        }
 
         // packet ident
         // only present in case QoS is > 0
         let packet_identifier = match qos_level {
             QoS::AtMostOnce => None,
             _=> {
-                let pid = super::u16_from_be_bytes(&src[cursor..cursor + 2])?;
+                let pid = super::u16_from_be_bytes(&src[cursor..])?;
                 cursor += pid.encoded_len();
-                payload_len -= pid.encoded_len();
                 Some(pid)
             },
         };
 
         // properties
-        let prop_res: DecodingResult<PublishProperties> = PublishProperties::decode(&src[cursor..])?;
+        let prop_res: DecodingResult<PublishProperties> = super::decode_properties(&src[cursor..], violations.as_deref_mut())?;
+        let properties_start = cursor;
         cursor += prop_res.bytes_read();
-        payload_len -= prop_res.bytes_read();
-
-        // payload
-        let payload: Vec<u8> = src[cursor..cursor + payload_len].to_vec();
-
-        Ok(Self {
+        let properties = prop_res.value();
+        let raw_properties = retain_properties.then(|| RawProperties {
+            bytes: src[properties_start..cursor].to_vec(),
+            decoded: properties.clone(),
+        });
+
+        // payload, whatever the remaining length leaves after the variable header
+        let payload = Payload::from(src.get(cursor..cursor_stop).unwrap_or_default());
+        cursor = cursor.max(cursor_stop);
+        super::check_remaining_length(PacketType::PUBLISH, &remain_len, cursor, &mut violations)?;
+
+        let publish = Self {
             dup,
             qos_level,
             retain,
             topic_name,
             packet_identifier,
-            properties: prop_res.value(),
+            properties,
             payload,
-        })
+            raw_properties,
+        };
+        publish.check_topic_or_alias()?;
+        Ok(publish)
     }
 }
 
 #[cfg(test)]
 mod tests {
 
+    use crate::{packet::Decodeable, test_util::from_hex};
+
     use super::*;
 
     #[test]
@@ -231,7 +511,7 @@ mod tests {
         let publish = test_packet();
         let topic_name = publish.topic_name.clone();
         
-        let encoded: Vec<u8> = publish.into();
+        let encoded: Vec<u8> = publish.try_into().unwrap();
         let decoded = Publish::try_from(&encoded[..]).unwrap();
 
         assert_eq!(topic_name, decoded.topic_name);
@@ -240,8 +520,12 @@ mod tests {
     /// the simplest form of a PUBLISH packet with just a topic and payload, no DUP, Qos 0, no retain, no properties
     #[test]
     fn encode() {
-        let packet: Vec<u8> = test_packet().into();
-        let expect: Vec<u8> = vec![48,40,0,15,115,111,109,101,47,116,111,112,105,99,47,110,97,109,101,0,123,34,115,111,109,101,34,58,49,44,34,102,111,111,34,58,34,98,97,114,34,125];
+        let packet: Vec<u8> = test_packet().try_into().unwrap();
+        let expect = from_hex("
+            30 28
+            00 0f 73 6f 6d 65 2f 74 6f 70 69 63 2f 6e 61 6d 65
+            00
+            7b 22 73 6f 6d 65 22 3a 31 2c 22 66 6f 6f 22 3a 22 62 61 72 22 7d").unwrap();
         assert_eq!(expect, packet);
     }
     
@@ -250,7 +534,7 @@ mod tests {
         let mut publish = test_packet();
         publish.qos_level = QoS::AtLeastOnce;
         publish.packet_identifier = Some(8123);
-        let packet: Vec<u8> = publish.into();
+        let packet: Vec<u8> = publish.try_into().unwrap();
         let expect: Vec<u8> = vec![
             0b00110010, // qos bits set to 01
             42, 0, 15, 115, 111, 109, 101, 47, 116, 111, 112, 105, 99, 47, 110, 97, 109, 101,
@@ -267,7 +551,7 @@ mod tests {
     fn encode_ignore_packet_id() {
         let mut publish = test_packet();
         publish.packet_identifier = Some(8123);
-        let packet: Vec<u8> = publish.into();
+        let packet: Vec<u8> = publish.try_into().unwrap();
         let expect: Vec<u8> = vec![
             48, 40, 0, 15, 115, 111, 109, 101, 47, 116, 111, 112, 105, 99, 47, 110, 97, 109, 101,
             // packet id would be here
@@ -278,15 +562,40 @@ mod tests {
         assert_eq!(expect, packet);
     }
 
+    #[test]
+    fn encode_string_too_long() {
+        let mut publish = test_packet();
+        publish.properties = Some(PublishProperties { content_type: Some("a".repeat(70_000)), ..Default::default() });
+        assert_eq!(Err(MqttError::StringTooLong { len: 70_000 }), Vec::try_from(publish));
+    }
+
+    #[test]
+    fn encode_correlation_data_too_long() {
+        let mut publish = test_packet();
+        publish.properties = Some(PublishProperties { correlation_data: Some(vec![0; 65_536]), ..Default::default() });
+        assert_eq!(Err(MqttError::BinaryDataTooLong { len: 65_536 }), Vec::try_from(publish));
+    }
+
     #[test]
     fn decode() {
         let msg: Vec<u8> = vec![48, 20, 0, 11, 47, 115, 111, 109, 101, 47, 116, 111, 112, 105, 99, 0, 115, 101, 114, 118, 117, 115];
         let publ = Publish::try_from(&msg[..]).unwrap();
-        assert_eq!(false, publ.dup);
+        assert!(!publ.dup);
         assert!(publ.packet_identifier.is_none());
         assert!(publ.properties.is_none());
         assert_eq!(String::from("/some/topic"), publ.topic_name);
-        assert_eq!(String::from("servus"), String::from_utf8(publ.payload).unwrap());
+        assert_eq!(String::from("servus"), String::from_utf8(publ.payload.into()).unwrap());
+    }
+
+    #[test]
+    fn decode_dup_and_retain() {
+        // QoS 1, topic "a", packet identifier 1, no properties
+        let flags = [(0x32, false, false), (0x3A, true, false), (0x33, false, true), (0x3B, true, true)];
+        for (first_byte, dup, retain) in flags {
+            let msg: Vec<u8> = vec![first_byte, 6, 0, 1, 97, 0, 1, 0];
+            let publ = Publish::try_from(&msg[..]).unwrap();
+            assert_eq!((dup, retain), (publ.dup, publ.retain), "first byte {:08b}", first_byte);
+        }
     }
 
     #[test]
@@ -296,6 +605,43 @@ mod tests {
         assert!(res.is_err());
     }
 
+    #[test]
+    fn decode_qos_3() {
+        let msg: Vec<u8> = vec![0b00110110, 6, 0, 1, 97, 0, 1, 0];
+        assert_eq!(
+            Some(MqttError::MalformedPacket("illegal value for QoS: 3".to_string())), 
+            Publish::try_from(&msg[..]).err());
+    }
+
+    #[test]
+    fn decode_topic_validation() {
+        let msg: Vec<u8> = vec![0b00110000, 5, 0, 2, 97, 0, 0];
+        assert_eq!(
+            Some(MqttError::MalformedPacket("Topic must not contain U+0000".to_string())),
+            Publish::try_from(&msg[..]).err());
+
+        // discouraged, but allowed when decoding
+        let msg: Vec<u8> = vec![0b00110000, 5, 0, 2, 97, 1, 0];
+        assert_eq!("a\u{1}", Publish::try_from(&msg[..]).unwrap().topic_name);
+    }
+
+    #[test]
+    fn topic_or_alias() {
+        let error = Some(MqttError::ProtocolError("PUBLISH has neither topic name nor topic alias".to_string()));
+        let result: Result<Vec<u8>, MqttError> = Publish::new_unchecked(String::new(), vec![1]).try_into();
+        assert_eq!(error, result.err());
+
+        // empty topic, no properties
+        let msg: Vec<u8> = vec![0b00110000, 4, 0, 0, 0, 1];
+        assert_eq!(error, Publish::try_from(&msg[..]).err());
+
+        // empty topic, topic alias 3
+        let msg: Vec<u8> = vec![0b00110000, 7, 0, 0, 3, 0x23, 0, 3, 1];
+        let decoded = Publish::try_from(&msg[..]).unwrap();
+        assert_eq!(Publish::with_alias(3, vec![1]), decoded);
+        assert_eq!(msg, Vec::<u8>::try_from(decoded).unwrap());
+    }
+
     #[test]
     fn encode_first_byte() {
         do_encode_first_byte(false, false, None, 0b00110000);
@@ -308,19 +654,79 @@ mod tests {
     #[test]
     fn encode_properties() {
         let empty: PublishProperties = PublishProperties::default();
-        let vempty: Vec<u8> = empty.into();
+        let vempty: Vec<u8> = empty.try_into().unwrap();
         assert_eq!(vec![0_u8], vempty);
 
         let mut props: PublishProperties = PublishProperties::default();
         props.payload_format_indicator = Some(true);
-        props.user_property.insert("debug".to_string(), "true".to_string());
+        props.user_property.push("debug".to_string(), "true".to_string());
         props.topic_alias = Some(334);
 
         let expect: Vec<u8> = vec![19,1,1,35,1,78,38,0,5,100,101,98,117,103,0,4,116,114,117,101];
-        let actual: Vec<u8> = props.into();
+        let actual: Vec<u8> = props.try_into().unwrap();
         assert_eq!(expect, actual);
     }
 
+    #[test]
+    fn multiple_subscription_identifiers() {
+        let mut props: PublishProperties = PublishProperties::default();
+        props.subscription_identifier = vec![VariableByteInteger::from(1), VariableByteInteger::from(200)];
+
+        let encoded: Vec<u8> = props.try_into().unwrap();
+        assert_eq!(vec![5, 11, 1, 11, 200, 1], encoded);
+
+        let decoded = PublishProperties::decode(&encoded).unwrap().value().unwrap();
+        assert_eq!(vec![1, 200], decoded.subscription_identifier.iter().map(|id| id.value).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn set_message_expiry() {
+        let mut publish = test_packet();
+        publish.set_message_expiry(Duration::from_secs(30)).unwrap();
+        assert_eq!(Some(30), publish.properties.unwrap().message_expiry_interval);
+        assert!(test_packet().set_message_expiry(Duration::from_secs_f32(0.5)).is_err());
+    }
+
+    #[test]
+    fn user_property_order_and_duplicates() {
+        let mut props: PublishProperties = PublishProperties::default();
+        props.user_property.push("z".to_string(), "1".to_string());
+        props.user_property.push("a".to_string(), "2".to_string());
+        props.user_property.push("z".to_string(), "3".to_string());
+
+        let encoded: Vec<u8> = props.try_into().unwrap();
+        let decoded = PublishProperties::decode(&encoded).unwrap().value().unwrap();
+        assert_eq!(vec![("z", "1"), ("a", "2"), ("z", "3")], decoded.user_property.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn retain_properties() {
+        // a content type before the user property, which encoding the decoded properties would swap
+        let bytes = from_hex("32 13 00 01 61 00 07 0b 03 00 01 78 26 00 01 6b 00 01 31 68 69").unwrap();
+        let retained = Publish::decode_retaining_properties(&bytes).unwrap();
+        assert_eq!(&bytes[7..19], retained.raw_properties.as_ref().unwrap().as_bytes());
+        assert_eq!(Some(7), retained.packet_identifier);
+
+        let decoded = Publish::try_from(&bytes[..]).unwrap();
+        assert_eq!(None, decoded.raw_properties);
+        assert_eq!(decoded.properties, retained.properties);
+        assert_ne!(bytes, Vec::<u8>::try_from(decoded).unwrap());
+
+        let mut forwarded = retained.clone();
+        forwarded.dup = true;
+        forwarded.payload = "ho".into();
+        let encoded = Vec::<u8>::try_from(forwarded).unwrap();
+        assert_eq!(bytes[1..19], encoded[1..19]);
+        assert_eq!(b"ho", &encoded[19..]);
+
+        // changed properties are encoded from scratch
+        let mut changed = retained;
+        changed.properties.as_mut().unwrap().topic_alias = Some(1);
+        let encoded = Vec::<u8>::try_from(changed).unwrap();
+        let decoded = Publish::try_from(&encoded[..]).unwrap();
+        assert_eq!(Some(1), decoded.properties.unwrap().topic_alias);
+    }
+
     /// another example from a 'real' mqtt broker
     #[test]
     fn decode_qos_1() {
@@ -329,18 +735,105 @@ mod tests {
         println!("{:?}", publ);
     }
 
+    #[test]
+    fn builder() {
+        let publish = Publish::builder("a/b")
+            .payload("ping")
+            .qos(QoS::ExactlyOnce)
+            .packet_identifier(9)
+            .utf8()
+            .response_topic("a/reply")
+            .correlation(vec![1, 2])
+            .user_property("k", "v")
+            .user_property("k", "w")
+            .build()
+            .unwrap();
+        assert_eq!(Some(9), publish.packet_identifier);
+        assert!(!publish.retain);
+        assert_eq!(b"ping", &publish.payload[..]);
+        let properties = publish.properties.as_ref().unwrap();
+        assert_eq!(Some(true), properties.payload_format_indicator);
+        assert_eq!(Some("a/reply"), properties.response_topic.as_deref());
+        assert_eq!(Some(vec![1, 2]), properties.correlation_data);
+        assert_eq!(vec!["v", "w"], properties.user_property.get_all("k").collect::<Vec<_>>());
+
+        // a QoS 1 or 2 message may get its packet identifier later, there are no properties unless set
+        let publish = Publish::builder("a/b").qos(QoS::AtLeastOnce).build().unwrap();
+        assert_eq!(None, publish.packet_identifier);
+        assert_eq!(None, publish.properties);
+    }
+
+    #[test]
+    fn builder_errors() {
+        assert_eq!(
+            Some(MqttError::TopicNameInvalid("Topic name must not contain wildcards: a/#".to_string())),
+            Publish::builder("a/#").build().err());
+        assert!(Publish::builder("").build().is_err());
+        assert_eq!(
+            Some(MqttError::ProtocolError("A QoS 0 PUBLISH must not have a packet identifier".to_string())),
+            Publish::builder("a").packet_identifier(1).build().err());
+        assert_eq!(
+            Some(MqttError::ProtocolError("Response topic must not contain wildcards: a/+".to_string())),
+            Publish::builder("a").response_topic("a/+").build().err());
+        assert_eq!(
+            Some(MqttError::ProtocolError("Payload marked as UTF-8 isn't".to_string())),
+            Publish::builder("a").payload(vec![0xFF]).utf8().build().err());
+        assert!(Publish::builder("a").expiry(Duration::from_millis(1500)).build().is_err());
+    }
+
+    #[test]
+    fn wildcard_topic_name() {
+        let error = MqttError::TopicNameInvalid("Topic name must not contain wildcards: a/+".to_string());
+        assert_eq!(Err(error.clone()), Vec::try_from(Publish::new_unchecked("a/+".into(), vec![])));
+        assert_eq!(crate::types::ReasonCode::TopicNameInvalid, error.reason_code());
+
+        // QoS 0 to "a/+", no properties and no payload
+        let encoded = from_hex("30 06 00 03 61 2f 2b 00").unwrap();
+        assert_eq!(Err(error), Publish::try_from(&encoded[..]));
+
+        let decoded = crate::packet::Packet::decode_lenient(&encoded).unwrap();
+        assert!(matches!(decoded.value, crate::packet::Packet::Publish(p) if p.topic_name == "a/+"));
+        assert_eq!(vec![Violation::TopicNameWildcard { topic: "a/+".into() }], decoded.violations);
+    }
+
     fn test_packet() -> Publish {
-        Publish::new("some/topic/name".into(), r#"{"some":1,"foo":"bar"}"#.to_string().into_bytes())
+        Publish::new_unchecked("some/topic/name".into(), r#"{"some":1,"foo":"bar"}"#.to_string().into_bytes())
     }
 
     fn do_encode_first_byte(dup: bool, retain: bool, qos: Option<QoS>, expected: u8) {
-        let mut publish = Publish::new("".into(), vec![]);
+        let mut publish = Publish::new_unchecked("a".into(), vec![]);
         publish.dup = dup;
         publish.retain = retain;
         if let Some(q) = qos {
             publish.qos_level = q
         }
-        let vec: Vec<u8> = publish.into();
+        let vec: Vec<u8> = publish.try_into().unwrap();
         assert_eq!(expected, vec[0]);
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserialize() {
+        let json = r#"{
+            "topic_name": "sensors/1",
+            "qos_level": 1,
+            "packet_identifier": 7,
+            "properties": { "content_type": "text/plain", "user_property": [["region", "eu"]] },
+            "payload": "01234567"
+        }"#;
+        let publish: Publish = serde_json::from_str(json).unwrap();
+        assert_eq!(QoS::AtLeastOnce, publish.qos_level);
+        assert!(!publish.retain);
+        assert_eq!(Some("eu"), publish.properties.as_ref().unwrap().user_property.get("region"));
+
+        let encoded: Vec<u8> = publish.clone().try_into().unwrap();
+        assert_eq!(from_hex("
+            32 30 00 09 73 65 6e 73 6f 72 73 2f 31 00 07 1a 26 00 06 72 65 67 69 6f 6e 00 02 65 75 03 00 0a 74 65 78 74
+            2f 70 6c 61 69 6e 30 31 32 33 34 35 36 37").unwrap(), encoded);
+        assert_eq!(publish, serde_json::from_str(&serde_json::to_string(&publish).unwrap()).unwrap());
+
+        assert!(serde_json::from_str::<Publish>(r#"{ "topic_name": "a", "qos": 1 }"#).is_err(), "unknown field");
+        assert!(serde_json::from_str::<Publish>(r#"{ "topic_name": "a", "qos_level": 3 }"#).is_err(), "invalid QoS");
+    }
+
 }
@@ -1,10 +1,8 @@
-use std::collections::HashMap;
-
 use mqtt_derive::MqttProperties;
 
-use crate::{types::{ReasonCode, MqttDataType}, error::MqttError, packet::Decodeable};
+use crate::{types::{ReasonCode, MqttDataType, UserProperties}, error::MqttError};
 
-use super::MqttControlPacket;
+use super::{MqttControlPacket, PacketType, Violation};
 
 /// `PUBREC` is the response to a `PUBLISH` that was sent with [crate::types::QoS::ExactlyOnce].
 /// Must be followed by [`PUBREL`](crate::packet::Pubrel).
@@ -14,17 +12,22 @@ use super::MqttControlPacket;
 /// - `PUBREC` <--
 /// - `PUBREL` -->
 /// - `PUBCOMP` <-- 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
 pub struct Pubrec {
     pub packet_identifier: u16,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub reason_code: ReasonCode,
     pub properties: Option<PubrecProperties>,
 }
 
-#[derive(Debug, MqttProperties)]
+#[derive(Debug, Clone, PartialEq, MqttProperties)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
 pub struct PubrecProperties {
     pub reason_string: Option<String>,
-    pub user_property: HashMap<String, String>,
+    pub user_property: UserProperties,
 }
 
 impl MqttControlPacket<'_> for Pubrec {
@@ -34,7 +37,7 @@ impl MqttControlPacket<'_> for Pubrec {
 }
 
 /// Fixed first byte of the header
-const FIRST_BYTE: u8 = 0b01010000;
+const FIRST_BYTE: u8 = PacketType::PUBREC.to_first_byte(PacketType::PUBREC.required_flags());
 
 impl Pubrec {
 
@@ -59,24 +62,26 @@ impl Pubrec {
     }
 }
 
-impl From<Pubrec> for Vec<u8> {
-    fn from(pubrec: Pubrec) -> Self {
+impl TryFrom<Pubrec> for Vec<u8> {
+    type Error = MqttError;
+
+    fn try_from(pubrec: Pubrec) -> Result<Self, Self::Error> {
         let mut result: Vec<u8> = Vec::new();
 
         result.push(FIRST_BYTE);
         super::push_be_u16(pubrec.packet_identifier, &mut result);
 
-        if pubrec.reason_code != ReasonCode::Success || pubrec.properties.is_some() {
+        if pubrec.reason_code != ReasonCode::Success || pubrec.properties.as_ref().is_some_and(|p| !p.is_empty()) {
             result.push(pubrec.reason_code.into());
             match pubrec.properties {
-                Some(props) => result.append(&mut props.into()),
+                Some(props) => result.append(&mut props.try_into()?),
                 None => result.push(0),
             }
         }
 
         super::calculate_and_insert_length(&mut result);
 
-        result
+        Ok(result)
     }
 }
 
@@ -84,6 +89,14 @@ impl TryFrom<&[u8]> for Pubrec {
     type Error = MqttError;
 
     fn try_from(src: &[u8]) -> Result<Self, Self::Error> {
+        Self::decode_from(src, None)
+    }
+}
+
+impl Pubrec {
+
+    /// Decodes strictly, or leniently if there are `violations` to record what was tolerated in.
+    pub(crate) fn decode_from(src: &[u8], mut violations: Option<&mut Vec<Violation>>) -> Result<Self, MqttError> {
         let mut cursor = 0;
 
         match src[cursor] {
@@ -103,9 +116,13 @@ impl TryFrom<&[u8]> for Pubrec {
                 let reason_code = ReasonCode::try_from(src[cursor])?;
                 cursor += reason_code.encoded_len();
 
-                (reason_code, PubrecProperties::decode(&src[cursor..])?.value())
+                let prop_res = super::decode_properties::<PubrecProperties>(&src[cursor..], violations.as_deref_mut())?;
+                cursor += prop_res.bytes_read();
+
+                (reason_code, prop_res.value())
             }
         };
+        super::check_remaining_length(PacketType::PUBREC, &remain_len, cursor, &mut violations)?;
 
         let mut result = Self::new(packet_identifier, reason_code)?;
         result.properties = properties;
@@ -122,13 +139,13 @@ mod tests {
     #[test]
     fn encode_and_decode() {
         let pubrec = Pubrec::new(123, ReasonCode::Success).unwrap();
-        let encoded: Vec<u8> = pubrec.into();
+        let encoded: Vec<u8> = pubrec.try_into().unwrap();
 
         assert_eq!(0b01010000, encoded[0]);
 
         let decoded = Pubrec::try_from(&encoded[..]).unwrap();
         assert_eq!(123_u16, decoded.packet_identifier);
-        assert_eq!(0x00_u8, decoded.reason_code.into());
+        assert_eq!(0x00_u8, u8::from(decoded.reason_code));
         assert!(decoded.properties.is_none());
     }
 
@@ -137,13 +154,13 @@ mod tests {
         let mut pubrec = Pubrec::new(6397, ReasonCode::UnspecifiedError).unwrap();
         let mut properties = PubrecProperties::default();
         properties.reason_string = Some("too lazy at the moment, apologies".into());
-        properties.user_property.insert("options".into(), "none, really".into());
+        properties.user_property.push("options".into(), "none, really".into());
         pubrec.properties = Some(properties);
 
-        let encoded: Vec<u8> = pubrec.into();
+        let encoded: Vec<u8> = pubrec.try_into().unwrap();
         let decoded = Pubrec::try_from(&encoded[..]).unwrap();
         assert_eq!(6397_u16, decoded.packet_identifier);
-        assert_eq!(0x80_u8, decoded.reason_code.into());
+        assert_eq!(0x80_u8, u8::from(decoded.reason_code));
         assert!(decoded.properties.is_some());
     }
 
@@ -1,10 +1,8 @@
-use std::collections::HashMap;
-
 use mqtt_derive::MqttProperties;
 
-use crate::{types::{ReasonCode, MqttDataType}, error::MqttError, packet::Decodeable};
+use crate::{types::{ReasonCode, MqttDataType, UserProperties}, error::MqttError};
 
-use super::MqttControlPacket;
+use super::{MqttControlPacket, PacketType, Violation};
 
 /// `PUBREL` is the response to a [`PUBREC`](crate::packet::Pubrec). 
 /// Applies only to messages published with [crate::types::QoS::ExactlyOnce].
@@ -14,17 +12,22 @@ use super::MqttControlPacket;
 /// - `PUBREC` <--
 /// - `PUBREL` -->
 /// - `PUBCOMP` <-- 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
 pub struct Pubrel {
     pub packet_identifier: u16,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub reason_code: ReasonCode,
     pub properties: Option<PubrelProperties>,
 }
 
-#[derive(Debug, MqttProperties)]
+#[derive(Debug, Clone, PartialEq, MqttProperties)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
 pub struct PubrelProperties {
     pub reason_string: Option<String>,
-    pub user_property: HashMap<String, String>,
+    pub user_property: UserProperties,
 }
 
 impl MqttControlPacket<'_> for Pubrel {
@@ -34,7 +37,7 @@ impl MqttControlPacket<'_> for Pubrel {
 }
 
 /// Fixed first byte of the header
-const FIRST_BYTE: u8 = 0b01100010;
+const FIRST_BYTE: u8 = PacketType::PUBREL.to_first_byte(PacketType::PUBREL.required_flags());
 
 impl Pubrel {
 
@@ -52,25 +55,27 @@ impl Pubrel {
     }
 }
 
-impl From<Pubrel> for Vec<u8> {
-    fn from(pubrel: Pubrel) -> Self {
+impl TryFrom<Pubrel> for Vec<u8> {
+    type Error = MqttError;
+
+    fn try_from(pubrel: Pubrel) -> Result<Self, Self::Error> {
         let mut result: Vec<u8> = Vec::new();
 
         result.push(FIRST_BYTE);
         super::push_be_u16(pubrel.packet_identifier, &mut result);
 
-        if pubrel.reason_code != ReasonCode::Success || pubrel.properties.is_some() {
+        if pubrel.reason_code != ReasonCode::Success || pubrel.properties.as_ref().is_some_and(|p| !p.is_empty()) {
             result.push(pubrel.reason_code.into());
 
             match pubrel.properties {
-                Some(props) => result.append(&mut props.into()),
+                Some(props) => result.append(&mut props.try_into()?),
                 None => result.push(0),
             }
         }
 
         super::calculate_and_insert_length(&mut result);
 
-        result
+        Ok(result)
     }
 }
 
@@ -78,12 +83,18 @@ impl TryFrom<&[u8]> for Pubrel {
     type Error = MqttError;
 
     fn try_from(src: &[u8]) -> Result<Self, Self::Error> {
+        Self::decode_from(src, None)
+    }
+}
+
+impl Pubrel {
+
+    /// Decodes strictly, or leniently if there are `violations` to record what was tolerated in.
+    pub(crate) fn decode_from(src: &[u8], mut violations: Option<&mut Vec<Violation>>) -> Result<Self, MqttError> {
         let mut cursor = 0;
 
-        match src[cursor] {
-            FIRST_BYTE => cursor += 1,
-            els => return Err(MqttError::MalformedPacket(format!("First byte is not a PUBREL one: {:b}", els)))
-        }
+        PacketType::PUBREL.check_first_byte(src[cursor])?;
+        cursor += 1;
 
         let remain_len = super::remaining_length(&src[cursor..])?;
         cursor += remain_len.encoded_len();
@@ -97,9 +108,13 @@ impl TryFrom<&[u8]> for Pubrel {
                 let reason_code = ReasonCode::try_from(src[cursor])?;
                 cursor += reason_code.encoded_len();
 
-                (reason_code, PubrelProperties::decode(&src[cursor..])?.value())
+                let prop_res = super::decode_properties::<PubrelProperties>(&src[cursor..], violations.as_deref_mut())?;
+                cursor += prop_res.bytes_read();
+
+                (reason_code, prop_res.value())
             }
         };
+        super::check_remaining_length(PacketType::PUBREL, &remain_len, cursor, &mut violations)?;
 
         let mut result = Self::new(packet_identifier, reason_code)?;
         result.properties = properties;
@@ -116,13 +131,13 @@ mod tests {
     #[test]
     fn encode_and_decode() {
         let pubrel = Pubrel::new(123, ReasonCode::Success).unwrap();
-        let encoded: Vec<u8> = pubrel.into();
+        let encoded: Vec<u8> = pubrel.try_into().unwrap();
 
         assert_eq!(0b01100010, encoded[0]);
 
         let decoded = Pubrel::try_from(&encoded[..]).unwrap();
         assert_eq!(123_u16, decoded.packet_identifier);
-        assert_eq!(0x00_u8, decoded.reason_code.into());
+        assert_eq!(0x00_u8, u8::from(decoded.reason_code));
         assert!(decoded.properties.is_none());
     }
 
@@ -131,16 +146,25 @@ mod tests {
         let mut pubrel = Pubrel::new(6397, ReasonCode::PacketIdentifierNotFound).unwrap();
         let mut properties = PubrelProperties::default();
         properties.reason_string = Some("too lazy at the moment, apologies".into());
-        properties.user_property.insert("options".into(), "none, really".into());
+        properties.user_property.push("options".into(), "none, really".into());
         pubrel.properties = Some(properties);
 
-        let encoded: Vec<u8> = pubrel.into();
+        let encoded: Vec<u8> = pubrel.try_into().unwrap();
         let decoded = Pubrel::try_from(&encoded[..]).unwrap();
         assert_eq!(6397_u16, decoded.packet_identifier);
-        assert_eq!(0x92_u8, decoded.reason_code.into());
+        assert_eq!(0x92_u8, u8::from(decoded.reason_code));
         assert!(decoded.properties.is_some());
     }
 
+    #[test]
+    fn decode_invalid_flags() {
+        assert_eq!(
+            Err(MqttError::MalformedPacket(
+                "Invalid fixed header flags of PUBREL: 0000 instead of 0010, bit 1 must be set".to_string())),
+            Pubrel::try_from(&[0b01100000, 2, 0, 1][..]));
+        assert!(Pubrel::try_from(&[0b01100011, 2, 0, 1][..]).is_err());
+    }
+
     #[test]
     fn reason_code_validation() {
         assert!(Pubrel::new(123, ReasonCode::AdministrativeAction).is_err());
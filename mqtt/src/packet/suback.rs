@@ -1,26 +1,37 @@
-use std::collections::HashMap;
-
 use mqtt_derive::MqttProperties;
 
-use crate::{types::{ReasonCode, MqttDataType}, error::MqttError};
-use super::{Decodeable, DecodingResult, MqttControlPacket};
+use crate::{types::{ReasonCode, MqttDataType, UserProperties}, error::MqttError};
+use super::{DecodingResult, Diagnostics, MqttControlPacket, PacketType, Violation};
 
 /// A `SUBACK` packet is sent by the Server to the Client to confirm receipt and processing of a `SUBSCRIBE` packet.
 /// 
 /// The payload ontains a list of [Reason Codes](crate::types::ReasonCode) that specify the maximum QoS level that was
 /// granted or the error which was found for each Subscription that was requested by the 
 /// [`SUBSCRIBE`](crate::packet::Subscribe).
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
 pub struct Suback {
     pub packet_identifier: u16,
     pub properties: Option<SubackProperties>,
     pub reason_codes: Vec<ReasonCode>,
 }
 
-#[derive(Debug, MqttProperties)]
+#[derive(Debug, Clone, PartialEq, MqttProperties)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
 pub struct SubackProperties {
     reason_string: Option<String>,
-    user_property: HashMap<String, String>,
+    user_property: UserProperties,
+}
+
+impl SubackProperties {
+
+    /// Sorts the user properties, for comparisons that don't care about their order.
+    #[cfg(any(test, feature = "test-util"))]
+    pub(crate) fn sort_user_properties(&mut self) {
+        self.user_property.sort();
+    }
 }
 
 impl MqttControlPacket<'_> for Suback {
@@ -29,15 +40,17 @@ impl MqttControlPacket<'_> for Suback {
     }
 }
 
-const FIRST_BYTE: u8 = 0b10010000;
+const FIRST_BYTE: u8 = PacketType::SUBACK.to_first_byte(PacketType::SUBACK.required_flags());
+
+impl TryFrom<Suback> for Vec<u8> {
+    type Error = MqttError;
 
-impl From<Suback> for Vec<u8> {
-    fn from(suback: Suback) -> Self {
+    fn try_from(suback: Suback) -> Result<Self, Self::Error> {
         let mut result = Vec::new();
         result.push(FIRST_BYTE);
         super::push_be_u16(suback.packet_identifier, &mut result);
         match suback.properties {
-            Some(p) => result.append(&mut p.into()),
+            Some(p) => result.append(&mut p.try_into()?),
             None => result.push(0),
         }
 
@@ -47,7 +60,7 @@ impl From<Suback> for Vec<u8> {
 
         super::calculate_and_insert_length(&mut result);
 
-        result
+        Ok(result)
     }
 }
 
@@ -55,6 +68,74 @@ impl TryFrom<&[u8]> for Suback {
     type Error = MqttError;
 
     fn try_from(src: &[u8]) -> Result<Self, Self::Error> {
+        Self::decode_from(src, None)
+    }
+}
+
+impl Diagnostics for Suback {
+    fn take_diagnostics(&mut self) -> (Option<String>, UserProperties) {
+        self.properties.as_mut()
+            .map(|properties| (properties.reason_string.take(), std::mem::take(&mut properties.user_property)))
+            .unwrap_or_default()
+    }
+
+    fn set_diagnostics(&mut self, reason_string: Option<String>, user_property: UserProperties) {
+        let properties = self.properties.get_or_insert_with(SubackProperties::default);
+        properties.reason_string = reason_string;
+        properties.user_property = user_property;
+    }
+}
+
+impl Suback {
+
+    /// Puts the `SUBACK`s to the parts of a [split](super::Subscribe::split) `SUBSCRIBE` back together, as if the
+    /// server had answered a single one: the reason codes in the order of the topic filters, under the packet
+    /// identifier of the first part. Reason strings are joined and user properties collected.
+    ///
+    /// The `SUBACK`s may be in any order, but there must be exactly one for each part, with a reason code for each of
+    /// its topic filters.
+    pub fn merge(parts: &[super::Subscribe], mut subacks: Vec<Suback>) -> Result<Suback, MqttError> {
+        if subacks.len() != parts.len() {
+            return Err(MqttError::ProtocolError(
+                format!("{} SUBACK packets for {} SUBSCRIBE packets", subacks.len(), parts.len())))
+        }
+
+        let mut merged = Suback {
+            packet_identifier: parts.first().map_or(0, |part| part.packet_identifier),
+            properties: None,
+            reason_codes: Vec::new(),
+        };
+        let mut reason_strings = Vec::new();
+        let mut user_property = UserProperties::new();
+        for part in parts {
+            let index = subacks.iter()
+                .position(|suback| suback.packet_identifier == part.packet_identifier)
+                .ok_or_else(|| MqttError::ProtocolError(
+                    format!("No SUBACK for packet identifier {}", part.packet_identifier)))?;
+            let mut suback = subacks.swap_remove(index);
+            if suback.reason_codes.len() != part.topic_filter.len() {
+                return Err(MqttError::ProtocolError(format!(
+                    "SUBACK {} has {} reason codes for {} topic filters",
+                    part.packet_identifier, suback.reason_codes.len(), part.topic_filter.len())))
+            }
+
+            merged.reason_codes.append(&mut suback.reason_codes);
+            let (reason_string, user_properties) = suback.take_diagnostics();
+            reason_strings.extend(reason_string);
+            for (key, value) in user_properties {
+                user_property.push(key, value);
+            }
+        }
+
+        if !reason_strings.is_empty() || !user_property.is_empty() {
+            let reason_string = Some(reason_strings.join("; ")).filter(|s| !s.is_empty());
+            merged.set_diagnostics(reason_string, user_property);
+        }
+        Ok(merged)
+    }
+
+    /// Decodes strictly, or leniently if there are `violations` to record what was tolerated in.
+    pub(crate) fn decode_from(src: &[u8], mut violations: Option<&mut Vec<Violation>>) -> Result<Self, MqttError> {
         let mut cursor = 0;
         match src[cursor] {
             FIRST_BYTE => cursor += 1,
@@ -68,7 +149,7 @@ impl TryFrom<&[u8]> for Suback {
         let packet_identifier = super::u16_from_be_bytes(&src[cursor..])?;
         cursor += packet_identifier.encoded_len();
 
-        let props_result: DecodingResult<SubackProperties> = SubackProperties::decode(&src[cursor..])?;
+        let props_result: DecodingResult<SubackProperties> = super::decode_properties(&src[cursor..], violations.as_deref_mut())?;
         let properties = props_result.value;
         cursor += props_result.bytes_read;
         
@@ -79,6 +160,8 @@ impl TryFrom<&[u8]> for Suback {
             cursor += 1;
         }
 
+        super::check_remaining_length(PacketType::SUBACK, &remain_len, cursor, &mut violations)?;
+
         Ok(Self {
             packet_identifier,
             properties,
@@ -89,15 +172,53 @@ impl TryFrom<&[u8]> for Suback {
 
 #[cfg(test)]
 mod tests {
+    use crate::packet::{Subscribe, TopicFilter};
+
     use super::*;
 
     #[test]
     fn encode_and_decode() {
         let suback = Suback{ packet_identifier: 2345, properties: None, reason_codes: vec![ReasonCode::Success] };
-        let encoded: Vec<u8> = suback.into();
+        let encoded: Vec<u8> = suback.try_into().unwrap();
         assert_eq!(encoded, vec![144, 4, 9, 41, 0, 0]);
         let decoded = Suback::try_from(&encoded[..]).unwrap();
         assert_eq!(2345, decoded.packet_identifier);
         assert_eq!(ReasonCode::Success, decoded.reason_codes[0]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn merge() {
+        let part = |packet_identifier, filters: &[&str]| Subscribe {
+            packet_identifier,
+            properties: None,
+            topic_filter: filters.iter().map(|f| TopicFilter::new_unchecked(f.to_string())).collect(),
+        };
+        let parts = vec![part(1, &["a", "b"]), part(7, &["c"])];
+
+        let user_property = UserProperties::from(vec![("k".to_string(), "v".to_string())]);
+        let reason_codes = vec![ReasonCode::NotAuthorized];
+        let mut denied = Suback { packet_identifier: 7, properties: None, reason_codes };
+        denied.set_diagnostics(Some("no c".into()), user_property.clone());
+        let granted = Suback {
+            packet_identifier: 1,
+            properties: None,
+            reason_codes: vec![ReasonCode::GrantedQoS1, ReasonCode::Success],
+        };
+
+        let mut merged = Suback::merge(&parts, vec![denied.clone(), granted.clone()]).unwrap();
+        assert_eq!(1, merged.packet_identifier);
+        assert_eq!(vec![ReasonCode::GrantedQoS1, ReasonCode::Success, ReasonCode::NotAuthorized], merged.reason_codes);
+        assert_eq!((Some("no c".to_string()), user_property), merged.take_diagnostics());
+
+        assert_eq!(
+            Err(MqttError::ProtocolError("1 SUBACK packets for 2 SUBSCRIBE packets".into())),
+            Suback::merge(&parts, vec![granted.clone()]));
+        assert_eq!(
+            Err(MqttError::ProtocolError("No SUBACK for packet identifier 7".into())),
+            Suback::merge(&parts, vec![granted.clone(), granted.clone()]));
+        denied.reason_codes.push(ReasonCode::Success);
+        assert_eq!(
+            Err(MqttError::ProtocolError("SUBACK 7 has 2 reason codes for 1 topic filters".into())),
+            Suback::merge(&parts, vec![granted, denied]));
+    }
+}
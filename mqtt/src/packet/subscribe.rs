@@ -1,42 +1,56 @@
-use std::collections::HashMap;
-
 use mqtt_derive::MqttProperties;
 
-use crate::{types::{QoS, VariableByteInteger, UTF8String, MqttDataType}, error::MqttError};
-use super::{Decodeable, DecodingResult, MqttControlPacket};
+use crate::{
+    types::{validate_topic, MqttDataType, QoS, UTF8String, ValidationMode, VariableByteInteger, UserProperties},
+    error::MqttError,
+    wire,
+};
+use super::{violation, DecodingResult, MqttControlPacket, PacketType, Violation};
 
 /// A `SUBSCRIBE` packet from a client is the prerequisite to receiving messages through [crate::packet::Publish].
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
 pub struct Subscribe {
     pub packet_identifier: u16,
     pub properties: Option<SubscribeProperties>,
     pub topic_filter: Vec<TopicFilter>,
 }
 
-#[derive(Debug, MqttProperties)]
+#[derive(Debug, Clone, PartialEq, MqttProperties)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
 pub struct SubscribeProperties {
     pub subscription_identifier: Option<VariableByteInteger>,
-    pub user_property: HashMap<String, String>,
+    pub user_property: UserProperties,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
 pub struct TopicFilter {
     /// Topic name pattern, may onclude wildcards
     pub filter: String,
     /// Defaults to [0](crate::types::QoS::AtMostOnce)
+    #[cfg_attr(feature = "serde", serde(default))]
     pub maximum_qos: QoS,
     /// Default: `false`
+    #[cfg_attr(feature = "serde", serde(default))]
     pub no_local: bool,
     /// Default: `false`
+    #[cfg_attr(feature = "serde", serde(default))]
     pub retain_as_published: bool,
     /// Defaults to `0`
+    #[cfg_attr(feature = "serde", serde(default))]
     pub retain_handling: RetainHandling,
 }
 
 /// Defines how retained messages are to be dealt with by the server.
-#[derive(Debug, PartialEq, PartialOrd)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RetainHandling {
     /// Sends retained messages directly on subscribe
+    #[default]
     OnSubscribe = 0,
     /// Send only if this subscription does not yet exist
     NewSubOnly = 1,
@@ -51,24 +65,26 @@ impl MqttControlPacket<'_> for Subscribe {
 }
 
 /// Packet Type 1000 | Reserved 0000
-const FIRST_BYTE: u8 = 0b10000010;
+const FIRST_BYTE: u8 = PacketType::SUBSCRIBE.to_first_byte(PacketType::SUBSCRIBE.required_flags());
+
+impl TryFrom<Subscribe> for Vec<u8> {
+    type Error = MqttError;
 
-impl From<Subscribe> for Vec<u8> {
-    fn from(subscribe: Subscribe) -> Self {
+    fn try_from(subscribe: Subscribe) -> Result<Self, Self::Error> {
         let mut result = Vec::new();
         result.push(FIRST_BYTE);
         super::push_be_u16(subscribe.packet_identifier, &mut result);
         match subscribe.properties {
-            Some(props) => result.append(&mut props.into()),
+            Some(props) => result.append(&mut props.try_into()?),
             None => result.push(0),
         }
         for filter in subscribe.topic_filter {
-            result.append(&mut filter.into())
+            result.append(&mut filter.try_into()?)
         }
 
         super::calculate_and_insert_length(&mut result);
 
-        result
+        Ok(result)
     }
 }
 
@@ -76,12 +92,93 @@ impl TryFrom<&[u8]> for Subscribe {
     type Error = MqttError;
 
     fn try_from(src: &[u8]) -> Result<Self, Self::Error> {
-        let mut cursor = 0;
-        match src[cursor] {
-            FIRST_BYTE => cursor += 1,
-            els => return Err(MqttError::MalformedPacket(format!("First byte is not a SUBSCRIBE one: {:b}", els)))
+        Self::decode_from(src, None)
+    }
+}
+
+impl Subscribe {
+
+    /// Splits the topic filters into as many `SUBSCRIBE` packets as it takes for each of them to be no larger than
+    /// `maximum_packet_size`, such as the one announced by the server with its `CONNACK`. Filters are kept in order and
+    /// each part has the same properties.
+    ///
+    /// The first part keeps the packet identifier, every further one gets a fresh one from `packet_identifier`. A
+    /// packet that fits as it is comes back unchanged. Fails if a single topic filter is too large on its own.
+    ///
+    /// See [Suback::merge](super::Suback::merge) to put the answers back together.
+    ///
+    /// ```
+    /// use mqtt::packet::{Subscribe, TopicFilter};
+    /// use mqtt::types::ValidationMode;
+    ///
+    /// let topic_filter = (0..100)
+    ///     .map(|i| TopicFilter::validated(format!("sensors/{:03}", i), ValidationMode::Strict).unwrap())
+    ///     .collect();
+    /// let subscribe = Subscribe { packet_identifier: 1, properties: None, topic_filter };
+    ///
+    /// let mut next = 1;
+    /// let parts = subscribe.split(256, || { next += 1; next }).unwrap();
+    /// assert_eq!(vec![1, 2, 3, 4, 5, 6], parts.iter().map(|s| s.packet_identifier).collect::<Vec<_>>());
+    /// assert_eq!(100, parts.iter().map(|s| s.topic_filter.len()).sum::<usize>());
+    /// ```
+    pub fn split(
+        self,
+        maximum_packet_size: u32,
+        mut packet_identifier: impl FnMut() -> u16) -> Result<Vec<Subscribe>, MqttError>
+    {
+        let maximum = maximum_packet_size as usize;
+        let properties_len = match &self.properties {
+            Some(properties) => Vec::<u8>::try_from(properties.clone())?.len(),
+            None => 1,
+        };
+        // the fixed header grows along with the remaining length
+        let packet_len = |filters_len: usize| {
+            let remaining_length = 2 + properties_len + filters_len;
+            1 + wire::variable_byte_integer_len(remaining_length as u32) + remaining_length
+        };
+
+        let mut parts: Vec<Subscribe> = Vec::new();
+        let mut filters_len = 0;
+        for filter in self.topic_filter {
+            let len = filter.encoded_len();
+            if packet_len(len) > maximum {
+                return Err(MqttError::PacketTooLarge {
+                    packet_type: PacketType::SUBSCRIBE,
+                    size: packet_len(len),
+                    maximum: maximum_packet_size,
+                })
+            }
+
+            match parts.last_mut() {
+                Some(part) if packet_len(filters_len + len) <= maximum => {
+                    part.topic_filter.push(filter);
+                    filters_len += len;
+                },
+                _ => {
+                    let packet_identifier = match parts.is_empty() {
+                        true => self.packet_identifier,
+                        false => packet_identifier(),
+                    };
+                    let properties = self.properties.clone();
+                    parts.push(Subscribe { packet_identifier, properties, topic_filter: vec![filter] });
+                    filters_len = len;
+                },
+            }
         }
 
+        if parts.is_empty() {
+            let Subscribe { packet_identifier, properties, .. } = self;
+            parts.push(Subscribe { packet_identifier, properties, topic_filter: vec![] });
+        }
+        Ok(parts)
+    }
+
+    /// Decodes strictly, or leniently if there are `violations` to record what was tolerated in.
+    pub(crate) fn decode_from(src: &[u8], mut violations: Option<&mut Vec<Violation>>) -> Result<Self, MqttError> {
+        let mut cursor = 0;
+        PacketType::SUBSCRIBE.check_first_byte(src[cursor])?;
+        cursor += 1;
+
         let remain_len = super::remaining_length(&src[cursor..])?;
         cursor += remain_len.encoded_len();
         let cursor_stop = cursor + remain_len.value as usize;
@@ -89,18 +186,20 @@ impl TryFrom<&[u8]> for Subscribe {
         let packet_identifier = super::u16_from_be_bytes(&src[cursor..])?;
         cursor += packet_identifier.encoded_len();
 
-        let props_result: DecodingResult<SubscribeProperties> = SubscribeProperties::decode(&src[cursor..])?;
+        let props_result: DecodingResult<SubscribeProperties> = super::decode_properties(&src[cursor..], violations.as_deref_mut())?;
         let properties = props_result.value;
         cursor += props_result.bytes_read;
 
         let mut topic_filter = Vec::new();
 
         while cursor < cursor_stop {
-            let filter = TopicFilter::try_from(&src[cursor..])?;
+            let filter = TopicFilter::decode_from(&src[cursor..], &mut violations)?;
             cursor += filter.encoded_len();
             topic_filter.push(filter);
         }
 
+        super::check_remaining_length(PacketType::SUBSCRIBE, &remain_len, cursor, &mut violations)?;
+
         Ok(Self {
             packet_identifier,
             properties,
@@ -112,7 +211,14 @@ impl TryFrom<&[u8]> for Subscribe {
 impl TopicFilter {
 
     /// Creates a new filter with default options.
+    #[deprecated(note = "the filter isn't validated, use `TopicFilter::validated` instead")]
     pub fn new(filter: String) -> Self {
+        Self::new_unchecked(filter)
+    }
+
+    /// Same as [TopicFilter::new], for the crate's own use where the filter is known to be valid or deliberately
+    /// isn't, such as in tests.
+    pub(crate) fn new_unchecked(filter: String) -> Self {
         TopicFilter {
             filter,
             maximum_qos: QoS::AtMostOnce,
@@ -121,52 +227,31 @@ impl TopicFilter {
             retain_handling: RetainHandling::OnSubscribe,
         }
     }
-}
-
-impl From<TopicFilter> for Vec<u8> {
-    fn from(filter: TopicFilter) -> Self {
-        let mut res = Vec::new();
-        res.append(&mut UTF8String::from(filter.filter).into());
-
-        // setting bits 0 and 1 directly is just easier
-        let mut options: u8 = match filter.maximum_qos {
-            QoS::AtMostOnce => 0,
-            QoS::AtLeastOnce => 0b00000001,
-            QoS::ExactlyOnce => 0b00000010,
-        };
-
-        if filter.no_local {
-            options |= 0b00000100;
-        }
 
-        if filter.retain_as_published {
-            options |= 0b00001000;
-        }
-
-        options = match filter.retain_handling {
-            RetainHandling::OnSubscribe => options,//nothing to do here,
-            RetainHandling::NewSubOnly => options | 0b00010000,
-            RetainHandling::Never => options | 0b00100000,
-        };
-
-        res.push(options);
-
-        res
+    /// Creates a new filter with default options, after checking it according to [validate_topic].
+    pub fn validated(filter: String, mode: ValidationMode) -> Result<Self, MqttError> {
+        validate_topic(&filter, mode)?;
+        Ok(Self::new_unchecked(filter))
     }
-}
-
-impl TryFrom<&[u8]> for TopicFilter {
-    type Error = MqttError;
 
-    fn try_from(src: &[u8]) -> Result<Self, Self::Error> {
-        let filter_decoded = UTF8String::try_from(&src[..])?;
+    /// Decodes strictly, or leniently if there are `violations` to record what was tolerated in.
+    fn decode_from(src: &[u8], violations: &mut Option<&mut Vec<Violation>>) -> Result<Self, MqttError> {
+        let filter_decoded = UTF8String::try_from(src)?;
         let filter = match &filter_decoded.value {
             Some(f) => f.clone(),
             None => return Err(MqttError::ProtocolError("Topic Filter missing".into())),
         };
+        validate_topic(&filter, ValidationMode::Lenient)?;
 
-        let options = src[filter_decoded.encoded_len()];
-        let maximum_qos = QoS::try_from(options & 0b00000011)?;
+        let options = match src.get(filter_decoded.encoded_len()) {
+            Some(options) => *options,
+            None => return Err(MqttError::MalformedPacket(format!("Subscription options missing for {}", filter))),
+        };
+        if options & 0b11000000 != 0 {
+            violation::tolerate(violations, Violation::SubscriptionOptions { filter: filter.clone(), options })?;
+        }
+
+        let maximum_qos = QoS::from_bits(options, 0)?;
         let no_local = match (options & 0b00000100) >> 2 {
             0 => false,
             1 => true,
@@ -196,6 +281,43 @@ impl TryFrom<&[u8]> for TopicFilter {
     }
 }
 
+impl TryFrom<TopicFilter> for Vec<u8> {
+    type Error = MqttError;
+
+    fn try_from(filter: TopicFilter) -> Result<Self, Self::Error> {
+        let mut res = Vec::new();
+        res.append(&mut UTF8String::from(filter.filter).try_into()?);
+
+        let mut options = filter.maximum_qos.encode_bits(0);
+
+        if filter.no_local {
+            options |= 0b00000100;
+        }
+
+        if filter.retain_as_published {
+            options |= 0b00001000;
+        }
+
+        options = match filter.retain_handling {
+            RetainHandling::OnSubscribe => options,//nothing to do here,
+            RetainHandling::NewSubOnly => options | 0b00010000,
+            RetainHandling::Never => options | 0b00100000,
+        };
+
+        res.push(options);
+
+        Ok(res)
+    }
+}
+
+impl TryFrom<&[u8]> for TopicFilter {
+    type Error = MqttError;
+
+    fn try_from(src: &[u8]) -> Result<Self, Self::Error> {
+        Self::decode_from(src, &mut None)
+    }
+}
+
 impl MqttDataType for TopicFilter {
     fn encoded_len(&self) -> usize {
         // number of bytes of the string value, plus 2 bytes for the length field plus
@@ -207,17 +329,18 @@ impl MqttDataType for TopicFilter {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{packet::Packet, test_util::from_hex};
 
     #[test]
     fn encode_and_decode() {
-        let topic_filter: Vec<TopicFilter> = vec![TopicFilter::new("/some/topic".into())];
+        let topic_filter: Vec<TopicFilter> = vec![TopicFilter::new_unchecked("/some/topic".into())];
         let subscribe = Subscribe{
             packet_identifier: 637,
             properties: None,
             topic_filter,
         };
 
-        let encoded: Vec<u8> = subscribe.into();
+        let encoded: Vec<u8> = subscribe.try_into().unwrap();
 
         let decoded = Subscribe::try_from(&encoded[..]).unwrap();
         assert_eq!("/some/topic".to_string(), decoded.topic_filter[0].filter)
@@ -225,30 +348,112 @@ mod tests {
 
     #[test]
     fn encode_decode_topic_filter() {
-        let f1 = TopicFilter::new("/some/topic".into());
-        let e1: Vec<u8> = f1.into();
+        let f1 = TopicFilter::new_unchecked("/some/topic".into());
+        let e1: Vec<u8> = f1.try_into().unwrap();
         assert_eq!(e1, vec![0, 11, 47,115,111,109,101,47,116,111,112,105,99,0]);
 
         let d1 = TopicFilter::try_from(&e1[..]).unwrap();
         assert_eq!(d1.filter, "/some/topic".to_string());
         assert_eq!(d1.maximum_qos, QoS::AtMostOnce);
-        assert_eq!(d1.no_local, false);
-        assert_eq!(d1.retain_as_published, false);
+        assert!(!d1.no_local);
+        assert!(!d1.retain_as_published);
         assert_eq!(d1.retain_handling, RetainHandling::OnSubscribe);
 
-        let mut f2 = TopicFilter::new("/some/topic".into());
+        let mut f2 = TopicFilter::new_unchecked("/some/topic".into());
         f2.maximum_qos = QoS::AtLeastOnce;
         f2.no_local = true;
         f2.retain_as_published = true;
         f2.retain_handling = RetainHandling::Never;
-        let e2: Vec<u8> = f2.into();
+        let e2: Vec<u8> = f2.try_into().unwrap();
         assert_eq!(e2, vec![0, 11, 47,115,111,109,101,47,116,111,112,105,99,45]);
 
         let d2 = TopicFilter::try_from(&e2[..]).unwrap();
         assert_eq!(d2.filter, "/some/topic".to_string());
         assert_eq!(d2.maximum_qos, QoS::AtLeastOnce);
-        assert_eq!(d2.no_local, true);
-        assert_eq!(d2.retain_as_published, true);
+        assert!(d2.no_local);
+        assert!(d2.retain_as_published);
         assert_eq!(d2.retain_handling, RetainHandling::Never);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn validated() {
+        assert!(TopicFilter::validated("sport/+/\n".into(), ValidationMode::Lenient).is_ok());
+        assert!(TopicFilter::validated("sport/+/\n".into(), ValidationMode::Strict).is_err());
+        assert!(TopicFilter::validated("".into(), ValidationMode::Lenient).is_err());
+    }
+
+    #[test]
+    fn decode_reserved_options() {
+        // SUBSCRIBE to a/b with QoS 1 and bit 7 of the options set
+        let src = from_hex("82 09 0001 00 0003 612F62 81").unwrap();
+        assert_eq!(
+            Err(MqttError::MalformedPacket(
+                "Reserved bits set in subscription options 10000001 of topic filter a/b".to_string())),
+            Subscribe::try_from(&src[..]));
+
+        let decoded = Packet::decode_lenient(&src).unwrap();
+        match decoded.value {
+            Packet::Subscribe(subscribe) => assert_eq!(QoS::AtLeastOnce, subscribe.topic_filter[0].maximum_qos),
+            els => panic!("Expected SUBSCRIBE, got {:?}", els),
+        }
+        assert_eq!(
+            vec![Violation::SubscriptionOptions { filter: "a/b".to_string(), options: 0x81 }],
+            decoded.violations);
+
+        // bit 6 as well, of a filter after one without
+        let src = from_hex("82 0D 0001 00 0003 612F62 01 0001 63 41").unwrap();
+        assert!(Subscribe::try_from(&src[..]).is_err());
+        let decoded = Packet::decode_lenient(&src).unwrap();
+        assert_eq!(
+            vec![Violation::SubscriptionOptions { filter: "c".to_string(), options: 0x41 }],
+            decoded.violations);
+    }
+
+    #[test]
+    fn decode_null_character() {
+        let encoded = [0, 3, b'a', 0, b'b', 0];
+        assert_eq!(
+            Some(MqttError::MalformedPacket("Topic must not contain U+0000".to_string())),
+            TopicFilter::try_from(&encoded[..]).err());
+    }
+
+    #[test]
+    fn split() {
+        let subscribe = |properties| Subscribe {
+            packet_identifier: 1,
+            properties,
+            topic_filter: (0..10).map(|i| TopicFilter::new_unchecked(format!("t/{}", i))).collect(),
+        };
+        let filters = |parts: &[Subscribe]| parts.iter()
+            .flat_map(|part| part.topic_filter.iter().map(|f| f.filter.clone()))
+            .collect::<Vec<_>>();
+
+        // 5 bytes plus 6 for each filter
+        let mut next = 6;
+        let parts = subscribe(None).split(20, || { next += 1; next }).unwrap();
+        assert_eq!(vec![1, 7, 8, 9, 10], parts.iter().map(|p| p.packet_identifier).collect::<Vec<_>>());
+        assert_eq!((0..10).map(|i| format!("t/{}", i)).collect::<Vec<_>>(), filters(&parts));
+        for part in parts {
+            assert_eq!(17, Vec::<u8>::try_from(part).unwrap().len());
+        }
+
+        // the properties take up 8 bytes in each part
+        let properties = SubscribeProperties {
+            user_property: vec![("k".to_string(), "v".to_string())].into(),
+            ..Default::default()
+        };
+        let parts = subscribe(Some(properties.clone())).split(24, || 2).unwrap();
+        assert_eq!(5, parts.len());
+        for part in parts {
+            assert_eq!(Some(&properties), part.properties.as_ref());
+            assert_eq!(24, Vec::<u8>::try_from(part).unwrap().len());
+        }
+
+        let parts = subscribe(None).split(65, || panic!("no further part expected")).unwrap();
+        assert_eq!(vec![subscribe(None)], parts);
+
+        assert_eq!(
+            Err(MqttError::PacketTooLarge { packet_type: PacketType::SUBSCRIBE, size: 11, maximum: 10 }),
+            subscribe(None).split(10, || 2));
+    }
+}
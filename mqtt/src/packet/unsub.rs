@@ -1,24 +1,37 @@
-use std::collections::HashMap;
-
 use mqtt_derive::MqttProperties;
 
-use crate::{error::MqttError, types::{UTF8String, MqttDataType}};
+use crate::{error::MqttError, types::{validate_topic, MqttDataType, UTF8String, ValidationMode, UserProperties}};
 
-use super::{Decodeable, DecodingResult, MqttControlPacket};
+use super::{DecodingResult, MqttControlPacket, PacketType, Violation};
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
 pub struct Unsubscribe {
     pub packet_identifier: u16,
     pub properties: Option<UnsubscribeProperties>,
     pub topic_filter: Vec<String>,
 }
 
-#[derive(Debug, MqttProperties)]
+#[derive(Debug, Clone, PartialEq, MqttProperties)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
 pub struct UnsubscribeProperties{
-    pub user_property: HashMap<String, String>,
+    pub user_property: UserProperties,
 }
 
-const FIRST_BYTE: u8 = 0b10100010;
+const FIRST_BYTE: u8 = PacketType::UNSUBSCRIBE.to_first_byte(PacketType::UNSUBSCRIBE.required_flags());
+
+impl Unsubscribe {
+
+    /// Creates a new packet without properties, after checking each filter according to [validate_topic].
+    pub fn new(packet_identifier: u16, topic_filter: Vec<String>, mode: ValidationMode) -> Result<Self, MqttError> {
+        for filter in topic_filter.iter() {
+            validate_topic(filter, mode)?;
+        }
+        Ok(Self { packet_identifier, properties: None, topic_filter })
+    }
+}
 
 impl MqttControlPacket<'_> for Unsubscribe {
     fn packet_type() -> super::PacketType {
@@ -26,21 +39,23 @@ impl MqttControlPacket<'_> for Unsubscribe {
     }
 }
 
-impl From<Unsubscribe> for Vec<u8> {
-    fn from(unsub: Unsubscribe) -> Self {
+impl TryFrom<Unsubscribe> for Vec<u8> {
+    type Error = MqttError;
+
+    fn try_from(unsub: Unsubscribe) -> Result<Self, Self::Error> {
         let mut result = Vec::new();
         result.push(FIRST_BYTE);
         super::push_be_u16(unsub.packet_identifier, &mut result);
         match unsub.properties {
-            Some(props) => result.append(&mut props.into()),
+            Some(props) => result.append(&mut props.try_into()?),
             None => result.push(0),
         }
         for filter in unsub.topic_filter {
-            result.append(&mut UTF8String::from(filter).into())
+            result.append(&mut UTF8String::from(filter).try_into()?)
         }
         super::calculate_and_insert_length(&mut result);
 
-        result
+        Ok(result)
     }
 }
 
@@ -48,11 +63,17 @@ impl TryFrom<&[u8]> for Unsubscribe {
     type Error = MqttError;
 
     fn try_from(src: &[u8]) -> Result<Self, Self::Error> {
+        Self::decode_from(src, None)
+    }
+}
+
+impl Unsubscribe {
+
+    /// Decodes strictly, or leniently if there are `violations` to record what was tolerated in.
+    pub(crate) fn decode_from(src: &[u8], mut violations: Option<&mut Vec<Violation>>) -> Result<Self, MqttError> {
         let mut cursor = 0;
-        match src[cursor] {
-            FIRST_BYTE => cursor += 1,
-            els => return Err(MqttError::MalformedPacket(format!("First byte is not a UNSUBSCRIBE one: {:b}", els)))
-        }
+        PacketType::UNSUBSCRIBE.check_first_byte(src[cursor])?;
+        cursor += 1;
 
         let remain_len = super::remaining_length(&src[cursor..])?;
         cursor += remain_len.encoded_len();
@@ -61,7 +82,7 @@ impl TryFrom<&[u8]> for Unsubscribe {
         let packet_identifier = super::u16_from_be_bytes(&src[cursor..])?;
         cursor += packet_identifier.encoded_len();
 
-        let props_result: DecodingResult<UnsubscribeProperties> = UnsubscribeProperties::decode(&src[cursor..])?;
+        let props_result: DecodingResult<UnsubscribeProperties> = super::decode_properties(&src[cursor..], violations.as_deref_mut())?;
         let properties = props_result.value;
         cursor += props_result.bytes_read;
 
@@ -72,10 +93,13 @@ impl TryFrom<&[u8]> for Unsubscribe {
             cursor += filter.encoded_len();
 
             if let Some(v) = filter.value {
+                validate_topic(&v, ValidationMode::Lenient)?;
                 topic_filter.push(v);
             }
         }
 
+        super::check_remaining_length(PacketType::UNSUBSCRIBE, &remain_len, cursor, &mut violations)?;
+
         Ok(Self {
             packet_identifier,
             properties,
@@ -86,6 +110,8 @@ impl TryFrom<&[u8]> for Unsubscribe {
 
 #[cfg(test)]
 mod tests {
+    use crate::{error::MqttError, types::ValidationMode};
+
     use super::Unsubscribe;
 
 
@@ -97,8 +123,24 @@ mod tests {
             properties: None,
             topic_filter,
         };
-        let encoded: Vec<u8> = unsub.into();
+        let encoded: Vec<u8> = unsub.try_into().unwrap();
         let decoded = Unsubscribe::try_from(&encoded[..]).unwrap();
         assert_eq!(1782, decoded.packet_identifier);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn new_validates() {
+        assert!(Unsubscribe::new(1, vec!["a/#".into(), "b/\u{1}".into()], ValidationMode::Lenient).is_ok());
+        assert_eq!(
+            Some(MqttError::MalformedPacket("Topic contains control character U+0001".to_string())),
+            Unsubscribe::new(1, vec!["a/#".into(), "b/\u{1}".into()], ValidationMode::Strict).err());
+    }
+
+    #[test]
+    fn decode_null_character() {
+        let encoded = [0b10100010, 6, 0, 1, 0, 0, 1, 0];
+        assert_eq!(
+            Some(MqttError::MalformedPacket("Topic must not contain U+0000".to_string())),
+            Unsubscribe::try_from(&encoded[..]).err());
+    }
+}
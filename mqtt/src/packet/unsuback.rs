@@ -1,25 +1,27 @@
-use std::collections::HashMap;
-
 use mqtt_derive::MqttProperties;
 
-use crate::{types::{ReasonCode, MqttDataType}, error::MqttError};
+use crate::{types::{ReasonCode, MqttDataType, UserProperties}, error::MqttError};
 
-use super::{Decodeable, DecodingResult, MqttControlPacket};
+use super::{DecodingResult, MqttControlPacket, PacketType, Violation};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
 pub struct Unsuback {
     pub packet_identifier: u16,
     pub properties: Option<UnsubackProperties>,
     pub reason_codes: Vec<ReasonCode>,
 }
 
-#[derive(Debug, MqttProperties)]
+#[derive(Debug, Clone, PartialEq, MqttProperties)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
 pub struct UnsubackProperties {
     pub reason_string: Option<String>,
-    pub user_property: HashMap<String, String>,
+    pub user_property: UserProperties,
 }
 
-const FIRST_BYTE: u8 = 0b10110000;
+const FIRST_BYTE: u8 = PacketType::UNSUBACK.to_first_byte(PacketType::UNSUBACK.required_flags());
 
 impl MqttControlPacket<'_> for Unsuback {
     fn packet_type() -> super::PacketType {
@@ -27,13 +29,15 @@ impl MqttControlPacket<'_> for Unsuback {
     }
 }
 
-impl From<Unsuback> for Vec<u8> {
-    fn from(unsuback: Unsuback) -> Self {
+impl TryFrom<Unsuback> for Vec<u8> {
+    type Error = MqttError;
+
+    fn try_from(unsuback: Unsuback) -> Result<Self, Self::Error> {
         let mut result = Vec::new();
         result.push(FIRST_BYTE);
         super::push_be_u16(unsuback.packet_identifier, &mut result);
         match unsuback.properties {
-            Some(props) => result.append(&mut props.into()),
+            Some(props) => result.append(&mut props.try_into()?),
             None => result.push(0),
         }
         for code in unsuback.reason_codes {
@@ -42,7 +46,7 @@ impl From<Unsuback> for Vec<u8> {
 
         super::calculate_and_insert_length(&mut result);
 
-        result
+        Ok(result)
     }
 }
 
@@ -50,6 +54,14 @@ impl TryFrom<&[u8]> for Unsuback {
     type Error = MqttError;
 
     fn try_from(src: &[u8]) -> Result<Self, Self::Error> {
+        Self::decode_from(src, None)
+    }
+}
+
+impl Unsuback {
+
+    /// Decodes strictly, or leniently if there are `violations` to record what was tolerated in.
+    pub(crate) fn decode_from(src: &[u8], mut violations: Option<&mut Vec<Violation>>) -> Result<Self, MqttError> {
         let mut cursor = 0;
         match src[cursor] {
             FIRST_BYTE => cursor += 1,
@@ -63,7 +75,7 @@ impl TryFrom<&[u8]> for Unsuback {
         let packet_identifier = super::u16_from_be_bytes(&src[cursor..])?;
         cursor += packet_identifier.encoded_len();
 
-        let props_result: DecodingResult<UnsubackProperties> = UnsubackProperties::decode(&src[cursor..])?;
+        let props_result: DecodingResult<UnsubackProperties> = super::decode_properties(&src[cursor..], violations.as_deref_mut())?;
         let properties = props_result.value;
         cursor += props_result.bytes_read;
 
@@ -74,6 +86,8 @@ impl TryFrom<&[u8]> for Unsuback {
             cursor += 1;
         }        
 
+        super::check_remaining_length(PacketType::UNSUBACK, &remain_len, cursor, &mut violations)?;
+
         Ok(Self {
             packet_identifier,
             properties,
@@ -90,7 +104,7 @@ mod tests {
     #[test]
     fn encode_and_decode() {
         let unsuback = Unsuback { packet_identifier: 872, properties: None, reason_codes: vec![ReasonCode::Success, ReasonCode::NoSubscriptionExisted] };
-        let encoded: Vec<u8> = unsuback.into();
+        let encoded: Vec<u8> = unsuback.try_into().unwrap();
         let decoded = Unsuback::try_from(&encoded[..]).unwrap();
         assert_eq!(872, decoded.packet_identifier);
         assert_eq!(2, decoded.reason_codes.len());
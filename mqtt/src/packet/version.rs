@@ -0,0 +1,269 @@
+use std::fmt::Display;
+
+use crate::{error::MqttError, types::ReasonCode};
+
+use super::{subscribe::RetainHandling, Packet};
+
+/// The versions of MQTT, as sent in the protocol level byte of `CONNECT`.
+///
+/// This crate only speaks version 5, the older ones are known so they can be recognized and so that packets can be
+/// [checked](ProtocolVersion::check) for features the other side wouldn't understand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ProtocolVersion {
+    /// MQTT 3.1, protocol name `MQIsdp`.
+    V3_1 = 3,
+    /// MQTT 3.1.1, the first OASIS standard.
+    V3_1_1 = 4,
+    /// MQTT 5.
+    V5 = 5,
+}
+
+/// What a [ProtocolVersion] supports, as far as it differs between the versions.
+///
+/// | Capability | 3.1 | 3.1.1 | 5 |
+/// | ---------- | --- | ----- | - |
+/// | [properties](Self::properties) | - | - | ✓ |
+/// | [reason_codes](Self::reason_codes) | - | - | ✓ |
+/// | [session_expiry](Self::session_expiry) | - | - | ✓ |
+/// | [enhanced_authentication](Self::enhanced_authentication) | - | - | ✓ |
+/// | [subscription_options](Self::subscription_options) | - | - | ✓ |
+/// | [subscribe_failure](Self::subscribe_failure) | - | ✓ | ✓ |
+/// | [empty_client_id](Self::empty_client_id) | - | ✓ | ✓ |
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Properties in the variable header of packets and in the will.
+    pub properties: bool,
+
+    /// Reason codes other than success in acknowledgements and `DISCONNECT`, and the full range of them in `CONNACK`.
+    /// Older versions only know a handful of `CONNACK` return codes.
+    pub reason_codes: bool,
+
+    /// Sessions that outlive the connection for a set interval, instead of just the clean session flag.
+    pub session_expiry: bool,
+
+    /// The `AUTH` packet.
+    pub enhanced_authentication: bool,
+
+    /// No local, retain as published and retain handling in `SUBSCRIBE`.
+    pub subscription_options: bool,
+
+    /// The failure return code `0x80` in `SUBACK`.
+    pub subscribe_failure: bool,
+
+    /// An empty client identifier, for the server to assign one.
+    pub empty_client_id: bool,
+}
+
+impl ProtocolVersion {
+
+    /// The protocol level byte of `CONNECT`.
+    pub const fn level(&self) -> u8 {
+        *self as u8
+    }
+
+    /// The protocol name of `CONNECT`.
+    pub const fn protocol_name(&self) -> &'static str {
+        match self {
+            Self::V3_1 => "MQIsdp",
+            _ => "MQTT",
+        }
+    }
+
+    pub const fn capabilities(&self) -> Capabilities {
+        let v5 = matches!(self, Self::V5);
+        let v3_1_1 = !matches!(self, Self::V3_1);
+        Capabilities {
+            properties: v5,
+            reason_codes: v5,
+            session_expiry: v5,
+            enhanced_authentication: v5,
+            subscription_options: v5,
+            subscribe_failure: v3_1_1,
+            empty_client_id: v3_1_1,
+        }
+    }
+
+    /// Returns an error naming the first feature of `packet` this version doesn't support, which means the packet
+    /// can't be sent to a client or server speaking it.
+    ///
+    /// # Examples
+    /// ```
+    /// use mqtt::packet::{Packet, ProtocolVersion, Puback};
+    /// use mqtt::types::ReasonCode;
+    ///
+    /// let puback = Packet::from(Puback::new(1, ReasonCode::QuotaExceeded).unwrap());
+    /// assert!(ProtocolVersion::V5.check(&puback).is_ok());
+    /// assert!(ProtocolVersion::V3_1_1.check(&puback).is_err());
+    /// ```
+    pub fn check(&self, packet: &Packet) -> Result<(), MqttError> {
+        let capabilities = self.capabilities();
+        let unsupported = |feature: &str| Err(MqttError::ProtocolError(format!(
+            "{} {} not supported by MQTT {}", packet.packet_type(), feature, self)));
+
+        if !capabilities.properties && has_properties(packet) {
+            return unsupported("properties are")
+        }
+
+        match packet {
+            Packet::Auth(_) if !capabilities.enhanced_authentication => unsupported("packets are"),
+            Packet::Connect(p) if !capabilities.empty_client_id && p.requests_client_id() => {
+                unsupported("with an empty client identifier is")
+            },
+            Packet::Connack(p) if !capabilities.reason_codes && !connack_return_code(p.reason_code) => {
+                unsupported(&format!("reason code {:?} is", p.reason_code))
+            },
+            Packet::Puback(p) if !capabilities.reason_codes && p.reason_code != ReasonCode::Success => {
+                unsupported("reason codes are")
+            },
+            Packet::Pubrec(p) if !capabilities.reason_codes && p.reason_code != ReasonCode::Success => {
+                unsupported("reason codes are")
+            },
+            Packet::Pubrel(p) if !capabilities.reason_codes && p.reason_code != ReasonCode::Success => {
+                unsupported("reason codes are")
+            },
+            Packet::Pubcomp(p) if !capabilities.reason_codes && p.reason_code != ReasonCode::Success => {
+                unsupported("reason codes are")
+            },
+            Packet::Disconnect(p) if !capabilities.reason_codes && p.reason_code != ReasonCode::Success => {
+                unsupported("reason codes are")
+            },
+            Packet::Subscribe(p) if !capabilities.subscription_options && p.topic_filter.iter().any(|f| {
+                f.no_local || f.retain_as_published || f.retain_handling != RetainHandling::OnSubscribe
+            }) => unsupported("options other than QoS are"),
+            Packet::Suback(p) if !capabilities.reason_codes && p.reason_codes.iter().any(|c| !suback_return_code(*c, capabilities)) => {
+                unsupported("reason codes other than the granted QoS are")
+            },
+            Packet::Unsuback(p) if !capabilities.reason_codes && p.reason_codes.iter().any(|c| *c != ReasonCode::Success) => {
+                unsupported("reason codes are")
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Display for ProtocolVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::V3_1 => write!(f, "3.1"),
+            Self::V3_1_1 => write!(f, "3.1.1"),
+            Self::V5 => write!(f, "5"),
+        }
+    }
+}
+
+impl TryFrom<u8> for ProtocolVersion {
+    type Error = MqttError;
+
+    fn try_from(level: u8) -> Result<Self, Self::Error> {
+        match level {
+            3 => Ok(Self::V3_1),
+            4 => Ok(Self::V3_1_1),
+            5 => Ok(Self::V5),
+            _ => Err(MqttError::MalformedPacket(format!("Unknown protocol level: {}", level))),
+        }
+    }
+}
+
+fn has_properties(packet: &Packet) -> bool {
+    match packet {
+        Packet::Connect(p) => present(&p.properties) || p.will.as_ref().is_some_and(|w| present(&w.properties)),
+        Packet::Connack(p) => present(&p.properties),
+        Packet::Publish(p) => present(&p.properties),
+        Packet::Puback(p) => present(&p.properties),
+        Packet::Pubrec(p) => present(&p.properties),
+        Packet::Pubrel(p) => present(&p.properties),
+        Packet::Pubcomp(p) => present(&p.properties),
+        Packet::Subscribe(p) => present(&p.properties),
+        Packet::Suback(p) => present(&p.properties),
+        Packet::Unsubscribe(p) => present(&p.properties),
+        Packet::Unsuback(p) => present(&p.properties),
+        Packet::Disconnect(p) => present(&p.properties),
+        Packet::Auth(p) => present(&p.properties),
+        Packet::Pingreq(_) | Packet::Pingresp(_) => false,
+    }
+}
+
+/// Properties that hold nothing encode the same as none at all.
+fn present<T: Default + PartialEq>(properties: &Option<T>) -> bool {
+    properties.as_ref().is_some_and(|p| *p != T::default())
+}
+
+/// The reason codes with an equivalent among the `CONNACK` return codes of older versions.
+fn connack_return_code(reason_code: ReasonCode) -> bool {
+    matches!(
+        reason_code,
+        ReasonCode::Success |
+        ReasonCode::UnsupportedProtocolVersion |
+        ReasonCode::ClientIdentifierInvalid |
+        ReasonCode::ServerUnavailable |
+        ReasonCode::BadUserNameOrPassword |
+        ReasonCode::NotAuthorized
+    )
+}
+
+fn suback_return_code(reason_code: ReasonCode, capabilities: Capabilities) -> bool {
+    match reason_code {
+        ReasonCode::Success | ReasonCode::GrantedQoS1 | ReasonCode::GrantedQoS2 => true,
+        ReasonCode::UnspecifiedError => capabilities.subscribe_failure,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packet::{Auth, Connect, ConnectProperties, Suback, Subscribe, TopicFilter};
+
+    use super::*;
+
+    #[test]
+    fn level() {
+        for version in [ProtocolVersion::V3_1, ProtocolVersion::V3_1_1, ProtocolVersion::V5] {
+            assert_eq!(Ok(version), ProtocolVersion::try_from(version.level()));
+        }
+        assert!(ProtocolVersion::try_from(6).is_err());
+        assert_eq!("MQIsdp", ProtocolVersion::V3_1.protocol_name());
+        assert_eq!("MQTT", ProtocolVersion::V3_1_1.protocol_name());
+    }
+
+    #[test]
+    fn properties() {
+        let mut connect = Connect::default();
+        connect.client_id = Some("c".into());
+        connect.properties = Some(ConnectProperties { session_expiry_interval: Some(60), ..Default::default() });
+        let packet = Packet::from(connect);
+        assert!(ProtocolVersion::V5.check(&packet).is_ok());
+        assert_eq!(
+            Err(MqttError::ProtocolError("CONNECT properties are not supported by MQTT 3.1.1".into())),
+            ProtocolVersion::V3_1_1.check(&packet));
+    }
+
+    #[test]
+    fn empty_client_id() {
+        let packet = Packet::from(Connect::default());
+        assert!(ProtocolVersion::V3_1_1.check(&packet).is_ok());
+        assert!(ProtocolVersion::V3_1.check(&packet).is_err());
+    }
+
+    #[test]
+    fn auth() {
+        let packet = Packet::from(Auth { reason_code: ReasonCode::Success, properties: None });
+        assert!(ProtocolVersion::V3_1_1.check(&packet).is_err());
+    }
+
+    #[test]
+    fn subscription_options() {
+        let mut filter = TopicFilter::new_unchecked("a/b".into());
+        filter.no_local = true;
+        let packet = Packet::from(Subscribe { packet_identifier: 1, properties: None, topic_filter: vec![filter] });
+        assert!(ProtocolVersion::V3_1_1.check(&packet).is_err());
+        assert!(ProtocolVersion::V5.check(&packet).is_ok());
+    }
+
+    #[test]
+    fn suback_return_codes() {
+        let suback = |code| Packet::from(Suback { packet_identifier: 1, properties: None, reason_codes: vec![ReasonCode::GrantedQoS1, code] });
+        assert!(ProtocolVersion::V3_1_1.check(&suback(ReasonCode::UnspecifiedError)).is_ok());
+        assert!(ProtocolVersion::V3_1.check(&suback(ReasonCode::UnspecifiedError)).is_err());
+        assert!(ProtocolVersion::V3_1_1.check(&suback(ReasonCode::NotAuthorized)).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use std::fmt::Display;
+
+use crate::error::MqttError;
+
+use super::{PacketType, PropertyIdentifier};
+
+/// A protocol violation that [lenient decoding](super::Packet::decode_lenient) tolerated instead of rejecting the
+/// packet, so that proxies and conformance tooling can report what the other side got wrong.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// The flags of the fixed header differ from the ones the packet type requires, and were replaced by them.
+    ReservedBits {
+        packet_type: PacketType,
+        flags: u8,
+    },
+
+    /// A property that isn't allowed in this packet (or will), which was skipped.
+    UnexpectedProperty {
+        properties: &'static str,
+        identifier: u32,
+    },
+
+    /// A property that may only occur once, of which all but the first were skipped.
+    DuplicateProperty {
+        properties: &'static str,
+        identifier: u32,
+    },
+
+    /// The remaining length of the fixed header differs from the length of what was decoded. Bytes left over at the
+    /// end were skipped, fields running past it were kept.
+    RemainingLength {
+        packet_type: PacketType,
+        declared: u32,
+        decoded: usize,
+    },
+
+    /// A property that may only occur along with another one, which is missing. The property was skipped.
+    PropertyWithout {
+        properties: &'static str,
+        identifier: PropertyIdentifier,
+        missing: PropertyIdentifier,
+    },
+
+    /// The reserved bits 6 and 7 of the subscription options of a topic filter are set [MQTT-3.8.3-5]. They were
+    /// ignored.
+    SubscriptionOptions {
+        filter: String,
+        options: u8,
+    },
+
+    /// The topic name of a `PUBLISH` contains wildcard characters [MQTT-3.3.2-2]. It was kept as is.
+    TopicNameWildcard {
+        topic: String,
+    },
+}
+
+/// A decoded value along with the violations tolerated on the way.
+#[derive(Debug, PartialEq)]
+pub struct Lenient<T> {
+    pub value: T,
+    pub violations: Vec<Violation>,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Violation::ReservedBits { packet_type, flags } => write!(
+                f, "{} with flags {:04b} instead of {:04b}", packet_type, flags, packet_type.required_flags()),
+            Violation::UnexpectedProperty { properties, identifier } => write!(
+                f, "Unexpected property identifier 0x{:02X} for {}", identifier, properties),
+            Violation::DuplicateProperty { properties, identifier } => write!(
+                f, "Duplicate property identifier 0x{:02X} for {}", identifier, properties),
+            Violation::RemainingLength { packet_type, declared, decoded } => write!(
+                f, "{} with a remaining length of {}, but {} bytes decoded", packet_type, declared, decoded),
+            Violation::PropertyWithout { properties, identifier, missing } => write!(
+                f, "Property {:?} (0x{:02X}) without {:?} (0x{:02X}) in {}",
+                identifier, identifier.value(), missing, missing.value(), properties),
+            Violation::SubscriptionOptions { filter, options } => write!(
+                f, "Reserved bits set in subscription options {:08b} of topic filter {}", options, filter),
+            Violation::TopicNameWildcard { topic } => write!(f, "Topic name must not contain wildcards: {}", topic),
+        }
+    }
+}
+
+impl From<Violation> for MqttError {
+    fn from(violation: Violation) -> Self {
+        match violation {
+            Violation::ReservedBits { .. }
+            | Violation::RemainingLength { .. }
+            | Violation::SubscriptionOptions { .. } => MqttError::MalformedPacket(violation.to_string()),
+            Violation::TopicNameWildcard { .. } => MqttError::TopicNameInvalid(violation.to_string()),
+            _ => MqttError::ProtocolError(violation.to_string()),
+        }
+    }
+}
+
+/// Records `violation` when decoding leniently, otherwise returns it as the error strict decoding fails with.
+pub(crate) fn tolerate(violations: &mut Option<&mut Vec<Violation>>, violation: Violation) -> Result<(), MqttError> {
+    match violations {
+        Some(v) => {
+            v.push(violation);
+            Ok(())
+        },
+        None => Err(violation.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tolerate_strict_and_lenient() {
+        let violation = Violation::DuplicateProperty { properties: "PublishProperties", identifier: 0x01 };
+        assert_eq!(
+            Err(MqttError::ProtocolError("Duplicate property identifier 0x01 for PublishProperties".into())),
+            tolerate(&mut None, violation.clone()));
+
+        let mut violations = Vec::new();
+        assert!(tolerate(&mut Some(&mut violations), violation.clone()).is_ok());
+        assert_eq!(vec![violation], violations);
+    }
+}
@@ -0,0 +1,263 @@
+use crate::{
+    error::MqttError,
+    packet::{Publish, PublishProperties},
+    types::{validate_topic, ValidationMode},
+};
+
+/// Marks messages a bridge forwards with a user property naming the bridge, so that it recognizes them when they come
+/// back to it instead of forwarding them again. Two-way bridges between servers without
+/// [no_local](super::SubscriptionTree::matches_from) support, or chains of bridges, would otherwise pass messages
+/// around in circles.
+///
+/// Each bridge a message passes adds its name, so the property also records the route it took.
+///
+/// # Examples
+/// ```
+/// use mqtt::packet::Publish;
+/// use mqtt::router::BridgeTag;
+///
+/// let east = BridgeTag::new("east");
+/// let west = BridgeTag::new("west");
+///
+/// let mut publish = Publish::with_topic_name("a/b".try_into().unwrap(), "hello");
+/// assert!(east.forward(&mut publish));
+/// assert!(west.forward(&mut publish));
+///
+/// // back where it started
+/// assert!(!east.forward(&mut publish));
+/// assert_eq!(vec!["east", "west"], BridgeTag::route(&publish, BridgeTag::DEFAULT_PROPERTY));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BridgeTag {
+    /// Identifies the bridge, must be unique among all bridges a message may pass.
+    pub name: String,
+
+    /// Name of the user property that carries the tags. Defaults to `bridge-origin`.
+    pub property: String,
+}
+
+impl BridgeTag {
+
+    /// The name of the user property used unless another one is set.
+    pub const DEFAULT_PROPERTY: &'static str = "bridge-origin";
+
+    pub fn new(name: &str) -> Self {
+        Self { name: name.to_string(), property: Self::DEFAULT_PROPERTY.to_string() }
+    }
+
+    /// Whether the message has passed this bridge already.
+    pub fn is_tagged(&self, publish: &Publish) -> bool {
+        Self::route(publish, &self.property).contains(&self.name.as_str())
+    }
+
+    /// Adds the tag of this bridge to the message.
+    pub fn tag(&self, publish: &mut Publish) {
+        publish.properties.get_or_insert_with(PublishProperties::default)
+            .user_property.push(self.property.clone(), self.name.clone());
+    }
+
+    /// Tags the message unless it has passed this bridge already. Returns `false` if it has, in which case it must
+    /// not be forwarded.
+    pub fn forward(&self, publish: &mut Publish) -> bool {
+        if self.is_tagged(publish) {
+            return false
+        }
+        self.tag(publish);
+        true
+    }
+
+    /// The names of all bridges the message has passed, in order, as tagged with `property`.
+    pub fn route<'a>(publish: &'a Publish, property: &'a str) -> Vec<&'a str> {
+        match &publish.properties {
+            Some(properties) => properties.user_property.get_all(property).collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Maps the topics of messages a bridge forwards: from the topic filter it subscribes to on one server onto the topic
+/// to publish them to on the other.
+///
+/// A target without wildcards receives every message under that one topic. A target ending in `#` keeps the topic
+/// levels matched by the `#` of the source filter, which must end in `#` as well, and puts the levels in front of its
+/// own `#` before them.
+///
+/// # Examples
+/// ```
+/// use mqtt::packet::Publish;
+/// use mqtt::router::TopicRewrite;
+///
+/// let rewrite = TopicRewrite::new("sensors/#", "site-1/sensors/#").unwrap();
+/// assert_eq!(Some("site-1/sensors/kitchen/temperature".to_string()), rewrite.apply("sensors/kitchen/temperature"));
+/// assert_eq!(None, rewrite.apply("actors/kitchen/light"));
+///
+/// let mut publish = Publish::with_topic_name("sensors/hall".try_into().unwrap(), "20.5");
+/// assert!(rewrite.rewrite(&mut publish));
+/// assert_eq!("site-1/sensors/hall", publish.topic_name);
+///
+/// assert!(TopicRewrite::new("sensors/+/temperature", "all/#").is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicRewrite {
+    from: String,
+    to: String,
+}
+
+impl TopicRewrite {
+
+    /// Fails if `from` isn't a valid topic filter, or `to` isn't a valid topic name with an optional `#` as the last
+    /// level that `from` can fill.
+    pub fn new(from: &str, to: &str) -> Result<Self, MqttError> {
+        validate_filter(from)?;
+        validate_filter(to)?;
+        if to.contains('+') {
+            return Err(MqttError::Message(format!("Target topic must not contain '+': {}", to)))
+        }
+        if to.ends_with('#') && !from.ends_with('#') {
+            return Err(MqttError::Message(format!("Target topic {} needs a source filter ending in '#': {}", to, from)))
+        }
+        Ok(Self { from: from.to_string(), to: to.to_string() })
+    }
+
+    /// The topic filter messages are forwarded from.
+    pub fn filter(&self) -> &str {
+        &self.from
+    }
+
+    /// The topic to forward a message published to `topic` to, `None` if `topic` doesn't match the source filter or
+    /// would be forwarded to an empty topic, which only happens if `#` is all there is to the target.
+    pub fn apply(&self, topic: &str) -> Option<String> {
+        let from: Vec<&str> = self.from.split('/').collect();
+        let levels: Vec<&str> = topic.split('/').collect();
+
+        // a trailing '#' matches any number of levels, including none at all
+        let (fixed, multi_level) = match from.split_last() {
+            Some((&"#", fixed)) => (fixed, true),
+            _ => (&from[..], false),
+        };
+        let matches = match multi_level {
+            true => levels.len() >= fixed.len(),
+            false => levels.len() == fixed.len(),
+        };
+        if !matches || fixed.iter().zip(&levels).any(|(f, l)| *f != "+" && f != l) {
+            return None
+        }
+
+        match self.to.strip_suffix('#') {
+            Some(prefix) => match levels[fixed.len()..].join("/") {
+                rest if rest.is_empty() => Some(prefix.trim_end_matches('/').to_string()).filter(|t| !t.is_empty()),
+                rest => Some(format!("{}{}", prefix, rest)),
+            },
+            None => Some(self.to.clone()),
+        }
+    }
+
+    /// Prepares a message received from the source server to be published to the target server: changes its topic as
+    /// [TopicRewrite::apply] says and drops what only meant something on the connection it was received on, namely
+    /// packet identifier, `DUP` flag, topic alias and subscription identifiers. Returns `false`, leaving the message as
+    /// it is, if its topic doesn't match.
+    pub fn rewrite(&self, publish: &mut Publish) -> bool {
+        let topic = match self.apply(&publish.topic_name) {
+            Some(topic) => topic,
+            None => return false,
+        };
+
+        publish.topic_name = topic;
+        publish.packet_identifier = None;
+        publish.dup = false;
+        if let Some(properties) = publish.properties.as_mut() {
+            properties.topic_alias = None;
+            properties.subscription_identifier.clear();
+        }
+        true
+    }
+}
+
+/// A `#` may only be a level of its own, the last one.
+fn validate_filter(filter: &str) -> Result<(), MqttError> {
+    validate_topic(filter, ValidationMode::Strict)?;
+    match filter.find('#') {
+        Some(index) if index != filter.len() - 1 || !(index == 0 || filter[..index].ends_with('/')) =>
+            Err(MqttError::Message(format!("'#' must be the last level of the topic filter: {}", filter))),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn two_way_bridge() {
+        let bridge = BridgeTag::new("b1");
+        let mut publish = Publish::new_unchecked("a".into(), vec![]);
+        assert!(!bridge.is_tagged(&publish));
+        assert!(BridgeTag::route(&publish, BridgeTag::DEFAULT_PROPERTY).is_empty());
+
+        assert!(bridge.forward(&mut publish));
+        assert!(bridge.is_tagged(&publish));
+        assert!(!bridge.forward(&mut publish));
+        assert_eq!(vec!["b1"], BridgeTag::route(&publish, BridgeTag::DEFAULT_PROPERTY));
+    }
+
+    #[test]
+    fn custom_property() {
+        let mut bridge = BridgeTag::new("b1");
+        bridge.property = "via".into();
+        let mut publish = Publish::new_unchecked("a".into(), vec![]);
+        BridgeTag::new("b1").tag(&mut publish);
+
+        assert!(!bridge.is_tagged(&publish));
+        bridge.tag(&mut publish);
+        assert_eq!(vec!["b1"], BridgeTag::route(&publish, "via"));
+    }
+
+    #[test]
+    fn rewrite_topics() {
+        let rewrite = TopicRewrite::new("a/+/c/#", "x/#").unwrap();
+        assert_eq!(Some("x/d/e".to_string()), rewrite.apply("a/b/c/d/e"));
+        assert_eq!(Some("x".to_string()), rewrite.apply("a/b/c"));
+        assert_eq!(None, rewrite.apply("a/b"));
+        assert_eq!(None, rewrite.apply("a/b/x/d"));
+
+        let rewrite = TopicRewrite::new("a/+", "x/y").unwrap();
+        assert_eq!(Some("x/y".to_string()), rewrite.apply("a/b"));
+        assert_eq!(None, rewrite.apply("a/b/c"));
+
+        let rewrite = TopicRewrite::new("#", "mirror/#").unwrap();
+        assert_eq!(Some("mirror/a/b".to_string()), rewrite.apply("a/b"));
+        assert_eq!(Some("mirror//a".to_string()), rewrite.apply("/a"));
+
+        assert_eq!(None, TopicRewrite::new("a/#", "#").unwrap().apply("a"));
+    }
+
+    #[test]
+    fn invalid_rewrites() {
+        for (from, to) in [("", "a"), ("a", ""), ("a/#", "b/+"), ("a", "b/#"), ("a#", "b"), ("a/#/b", "c"), ("a/#", "b#")] {
+            assert!(TopicRewrite::new(from, to).is_err(), "{} -> {}", from, to);
+        }
+    }
+
+    #[test]
+    fn rewrite_publish() {
+        let mut publish = Publish::new_unchecked("a/b".into(), "hello");
+        publish.dup = true;
+        publish.packet_identifier = Some(7);
+        publish.properties = Some(PublishProperties {
+            topic_alias: Some(3),
+            subscription_identifier: vec![5.into()],
+            content_type: Some("text/plain".into()),
+            ..Default::default()
+        });
+
+        assert!(!TopicRewrite::new("x/#", "y/#").unwrap().rewrite(&mut publish));
+        assert_eq!("a/b", publish.topic_name);
+
+        assert!(TopicRewrite::new("a/#", "c/#").unwrap().rewrite(&mut publish));
+        assert_eq!("c/b", publish.topic_name);
+        assert_eq!((None, false), (publish.packet_identifier, publish.dup));
+        let properties = publish.properties.unwrap();
+        assert_eq!((None, 0), (properties.topic_alias, properties.subscription_identifier.len()));
+        assert_eq!(Some("text/plain".to_string()), properties.content_type);
+    }
+}
@@ -0,0 +1,17 @@
+//! Building blocks for servers: deciding which clients receive a message.
+//! 
+//! Like the [session module](crate::session), nothing in here does any I/O. Servers register the filters of every 
+//! `SUBSCRIBE` they receive and ask for the matching subscribers whenever a `PUBLISH` comes in.
+//! 
+//! Each subscriber gets its own clone of the incoming `PUBLISH` to [annotate](SubscriptionMatch::annotate), all of which
+//! share one [payload](crate::types::Payload).
+//! 
+//! Bridges between servers keep messages from going around in circles with `no_local` subscriptions, see
+//! [SubscriptionTree::matches_from], and by tagging what they forward with a [BridgeTag]. A [TopicRewrite] moves what
+//! they forward to other topics.
+
+mod bridge;
+mod tree;
+
+pub use self::bridge::{BridgeTag, TopicRewrite};
+pub use self::tree::{SubscriptionMatch, SubscriptionTree};
@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+
+use crate::{
+    packet::{Publish, PublishProperties, Subscribe, TopicFilter},
+    types::{QoS, VariableByteInteger},
+};
+
+const SINGLE_LEVEL: &str = "+";
+const MULTI_LEVEL: &str = "#";
+
+/// All subscriptions of all clients of a server, organized by topic level for matching topic names against filters.
+///
+/// Clients are identified by `C`, typically their client identifier. Each client has at most one subscription per
+/// filter, subscribing again replaces it.
+///
+/// # Examples
+/// ```
+/// use mqtt::packet::{Publish, TopicFilter};
+/// use mqtt::router::SubscriptionTree;
+/// use mqtt::types::ValidationMode;
+///
+/// let filter = |f: &str| TopicFilter::validated(f.into(), ValidationMode::Strict).unwrap();
+/// let mut tree = SubscriptionTree::new();
+/// tree.subscribe("client-a", &filter("sport/+/player1"), Some(7));
+/// tree.subscribe("client-a", &filter("sport/#"), Some(9));
+/// tree.subscribe("client-b", &filter("news/#"), None);
+///
+/// let matches = tree.matches("sport/tennis/player1");
+/// assert_eq!(1, matches.len());
+/// assert_eq!("client-a", matches[0].client);
+/// let mut identifiers = matches[0].subscription_identifiers.clone();
+/// identifiers.sort();
+/// assert_eq!(vec![7, 9], identifiers);
+///
+/// let mut publish = Publish::with_topic_name("sport/tennis/player1".try_into().unwrap(), vec![]);
+/// matches[0].annotate(&mut publish);
+/// assert_eq!(2, publish.properties.unwrap().subscription_identifier.len());
+/// ```
+#[derive(Debug)]
+pub struct SubscriptionTree<C> {
+    root: Node<C>,
+}
+
+/// A client with at least one subscription matching a topic name. If more than one of its subscriptions matches, they
+/// are combined.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubscriptionMatch<C> {
+    pub client: C,
+
+    /// The highest QoS granted by any of the matching subscriptions.
+    pub maximum_qos: QoS,
+
+    /// Identifiers of all matching subscriptions that were made with one, in no particular order.
+    pub subscription_identifiers: Vec<u32>,
+}
+
+#[derive(Debug)]
+struct Node<C> {
+    children: HashMap<String, Node<C>>,
+    subscriptions: Vec<Subscription<C>>,
+}
+
+#[derive(Debug)]
+struct Subscription<C> {
+    client: C,
+    maximum_qos: QoS,
+    identifier: Option<u32>,
+    no_local: bool,
+}
+
+impl<C: Clone + PartialEq> SubscriptionTree<C> {
+
+    pub fn new() -> Self {
+        Self { root: Node::default() }
+    }
+
+    /// Adds or replaces the subscription of `client` to `filter`, with the subscription identifier of the `SUBSCRIBE`
+    /// it came with.
+    pub fn subscribe(&mut self, client: C, filter: &TopicFilter, identifier: Option<u32>) {
+        let node = filter.filter.split('/').fold(&mut self.root, |node, level| {
+            node.children.entry(level.to_string()).or_default()
+        });
+
+        node.subscriptions.retain(|s| s.client != client);
+        node.subscriptions.push(Subscription {
+            client,
+            maximum_qos: filter.maximum_qos,
+            identifier,
+            no_local: filter.no_local,
+        });
+    }
+
+    /// Adds all filters of a `SUBSCRIBE` packet for `client`.
+    pub fn subscribe_all(&mut self, client: C, subscribe: &Subscribe) {
+        let identifier = subscribe.properties.as_ref()
+            .and_then(|p| p.subscription_identifier)
+            .map(|id| id.value);
+
+        for filter in subscribe.topic_filter.iter() {
+            self.subscribe(client.clone(), filter, identifier);
+        }
+    }
+
+    /// Removes the subscription of `client` to `filter`, returning `false` if there was none.
+    pub fn unsubscribe(&mut self, client: &C, filter: &str) -> bool {
+        let levels: Vec<&str> = filter.split('/').collect();
+        self.root.remove(client, &levels)
+    }
+
+    /// All clients subscribed to a filter matching `topic_name`, in no particular order.
+    ///
+    /// As required by the spec, wildcards at the first level don't match topic names starting with `$`.
+    pub fn matches(&self, topic_name: &str) -> Vec<SubscriptionMatch<C>> {
+        self.collect(topic_name, None)
+    }
+
+    /// Like [matches](Self::matches), for a message published by `publisher`: its own subscriptions made with
+    /// `no_local` don't match [MQTT-3.8.3-3]. A bridge subscribing that way doesn't get the messages it forwarded
+    /// handed back to it.
+    pub fn matches_from(&self, topic_name: &str, publisher: &C) -> Vec<SubscriptionMatch<C>> {
+        self.collect(topic_name, Some(publisher))
+    }
+
+    fn collect(&self, topic_name: &str, publisher: Option<&C>) -> Vec<SubscriptionMatch<C>> {
+        let levels: Vec<&str> = topic_name.split('/').collect();
+        let mut result = Vec::new();
+        self.root.collect(&levels, 0, publisher, &mut result);
+        result
+    }
+}
+
+impl<C: Clone + PartialEq> Default for SubscriptionTree<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C> SubscriptionMatch<C> {
+
+    /// Sets the subscription identifiers of a `PUBLISH` about to be sent to the matched client, replacing any it
+    /// already had.
+    pub fn annotate(&self, publish: &mut Publish) {
+        let identifiers: Vec<VariableByteInteger> = self.subscription_identifiers.iter()
+            .map(|id| VariableByteInteger::from(*id))
+            .collect();
+
+        match publish.properties.as_mut() {
+            Some(properties) => properties.subscription_identifier = identifiers,
+            None if identifiers.is_empty() => {},
+            None => publish.properties = Some(PublishProperties { subscription_identifier: identifiers, ..Default::default() }),
+        }
+    }
+}
+
+impl<C> Default for Node<C> {
+    fn default() -> Self {
+        Self { children: HashMap::new(), subscriptions: Vec::new() }
+    }
+}
+
+impl<C: Clone + PartialEq> Node<C> {
+
+    fn collect(&self, levels: &[&str], index: usize, publisher: Option<&C>, result: &mut Vec<SubscriptionMatch<C>>) {
+        // MQTT-4.7.2-1: wildcards at the first level must not match topics beginning with '$'
+        let wildcards = index > 0 || !levels[0].starts_with('$');
+
+        if wildcards {
+            // '#' also matches the parent level, so "a/#" matches "a"
+            if let Some(node) = self.children.get(MULTI_LEVEL) {
+                node.add_to(publisher, result);
+            }
+        }
+
+        if index == levels.len() {
+            self.add_to(publisher, result);
+            return
+        }
+
+        if let Some(node) = self.children.get(levels[index]) {
+            node.collect(levels, index + 1, publisher, result);
+        }
+
+        if wildcards {
+            if let Some(node) = self.children.get(SINGLE_LEVEL) {
+                node.collect(levels, index + 1, publisher, result);
+            }
+        }
+    }
+
+    fn add_to(&self, publisher: Option<&C>, result: &mut Vec<SubscriptionMatch<C>>) {
+        let matching = self.subscriptions.iter()
+            .filter(|s| !(s.no_local && publisher == Some(&s.client)));
+        for subscription in matching {
+            match result.iter_mut().find(|m| m.client == subscription.client) {
+                Some(existing) => {
+                    if subscription.maximum_qos > existing.maximum_qos {
+                        existing.maximum_qos = subscription.maximum_qos;
+                    }
+                    existing.subscription_identifiers.extend(subscription.identifier);
+                },
+                None => result.push(SubscriptionMatch {
+                    client: subscription.client.clone(),
+                    maximum_qos: subscription.maximum_qos,
+                    subscription_identifiers: subscription.identifier.into_iter().collect(),
+                }),
+            }
+        }
+    }
+
+    fn remove(&mut self, client: &C, levels: &[&str]) -> bool {
+        let (first, rest) = match levels.split_first() {
+            Some(split) => split,
+            None => {
+                let before = self.subscriptions.len();
+                self.subscriptions.retain(|s| &s.client != client);
+                return before != self.subscriptions.len()
+            },
+        };
+
+        let removed = match self.children.get_mut(*first) {
+            Some(child) => child.remove(client, rest),
+            None => return false,
+        };
+
+        // don't keep empty branches around
+        if self.children.get(*first).is_some_and(|c| c.children.is_empty() && c.subscriptions.is_empty()) {
+            self.children.remove(*first);
+        }
+
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packet::SubscribeProperties;
+
+    use super::*;
+
+    fn tree(filters: &[(&'static str, &str, Option<u32>)]) -> SubscriptionTree<&'static str> {
+        let mut tree = SubscriptionTree::new();
+        for (client, filter, id) in filters {
+            tree.subscribe(*client, &TopicFilter::new_unchecked(filter.to_string()), *id);
+        }
+        tree
+    }
+
+    fn clients(tree: &SubscriptionTree<&'static str>, topic: &str) -> Vec<&'static str> {
+        let mut clients: Vec<&str> = tree.matches(topic).into_iter().map(|m| m.client).collect();
+        clients.sort();
+        clients
+    }
+
+    #[test]
+    fn wildcards() {
+        let tree = tree(&[
+            ("exact", "sport/tennis/player1", None),
+            ("single", "sport/+/player1", None),
+            ("multi", "sport/#", None),
+            ("all", "#", None),
+            ("other", "news/+", None),
+        ]);
+
+        assert_eq!(vec!["all", "exact", "multi", "single"], clients(&tree, "sport/tennis/player1"));
+        assert_eq!(vec!["all", "multi"], clients(&tree, "sport"));
+        assert_eq!(vec!["all", "multi"], clients(&tree, "sport/tennis/player2"));
+        assert_eq!(vec!["all", "other"], clients(&tree, "news/today"));
+        assert_eq!(vec!["all"], clients(&tree, "news/today/late"));
+    }
+
+    #[test]
+    fn dollar_topics() {
+        let tree = tree(&[("all", "#", None), ("single", "+/monitor", None), ("sys", "$SYS/#", None)]);
+        assert_eq!(vec!["sys"], clients(&tree, "$SYS/monitor"));
+    }
+
+    #[test]
+    fn overlapping_subscriptions_are_combined() {
+        let mut tree = tree(&[("a", "sport/#", Some(1)), ("a", "sport/+", None), ("b", "sport/tennis", Some(3))]);
+        let mut filter = TopicFilter::new_unchecked("+/tennis".into());
+        filter.maximum_qos = QoS::ExactlyOnce;
+        tree.subscribe("a", &filter, Some(2));
+
+        let mut matches = tree.matches("sport/tennis");
+        matches.sort_by_key(|m| m.client);
+        assert_eq!(2, matches.len());
+
+        let mut ids = matches[0].subscription_identifiers.clone();
+        ids.sort();
+        assert_eq!(vec![1, 2], ids);
+        assert_eq!(QoS::ExactlyOnce, matches[0].maximum_qos);
+        assert_eq!(vec![3], matches[1].subscription_identifiers);
+    }
+
+    #[test]
+    fn no_local() {
+        let mut tree = tree(&[("a", "a/+", Some(1)), ("b", "a/+", None)]);
+        let mut filter = TopicFilter::new_unchecked("a/#".into());
+        filter.no_local = true;
+        tree.subscribe("bridge", &filter, None);
+
+        assert_eq!(3, tree.matches("a/b").len());
+        let mut clients: Vec<&str> = tree.matches_from("a/b", &"bridge").into_iter().map(|m| m.client).collect();
+        clients.sort();
+        assert_eq!(vec!["a", "b"], clients);
+
+        // only the subscription made with no_local is skipped
+        tree.subscribe("a", &filter, Some(2));
+        let matches = tree.matches_from("a/b", &"a");
+        let own = matches.iter().find(|m| m.client == "a").unwrap();
+        assert_eq!(vec![1], own.subscription_identifiers);
+    }
+
+    #[test]
+    fn resubscribe_replaces() {
+        let mut tree = tree(&[("a", "a/b", Some(1))]);
+        tree.subscribe("a", &TopicFilter::new_unchecked("a/b".into()), Some(2));
+        assert_eq!(vec![2], tree.matches("a/b")[0].subscription_identifiers);
+    }
+
+    #[test]
+    fn subscribe_all() {
+        let subscribe = Subscribe {
+            packet_identifier: 1,
+            properties: Some(SubscribeProperties { subscription_identifier: Some(VariableByteInteger::from(42)), ..Default::default() }),
+            topic_filter: vec![TopicFilter::new_unchecked("a/+".into()), TopicFilter::new_unchecked("b".into())],
+        };
+        let mut tree = SubscriptionTree::new();
+        tree.subscribe_all("a", &subscribe);
+
+        assert_eq!(vec![42], tree.matches("a/x")[0].subscription_identifiers);
+        assert_eq!(vec![42], tree.matches("b")[0].subscription_identifiers);
+    }
+
+    #[test]
+    fn unsubscribe() {
+        let mut tree = tree(&[("a", "a/b/c", None), ("b", "a/b/c", None)]);
+        assert!(!tree.unsubscribe(&"a", "a/b"));
+        assert!(tree.unsubscribe(&"a", "a/b/c"));
+        assert!(!tree.unsubscribe(&"a", "a/b/c"));
+        assert_eq!(vec!["b"], clients(&tree, "a/b/c"));
+
+        assert!(tree.unsubscribe(&"b", "a/b/c"));
+        assert!(tree.root.children.is_empty());
+    }
+
+    #[test]
+    fn annotate() {
+        let mut publish = Publish::new_unchecked("a".into(), vec![]);
+        let mut matched = SubscriptionMatch { client: "a", maximum_qos: QoS::AtMostOnce, subscription_identifiers: vec![] };
+        matched.annotate(&mut publish);
+        assert!(publish.properties.is_none());
+
+        matched.subscription_identifiers = vec![4, 5];
+        matched.annotate(&mut publish);
+        let ids: Vec<u32> = publish.properties.unwrap().subscription_identifier.iter().map(|id| id.value).collect();
+        assert_eq!(vec![4, 5], ids);
+    }
+}
@@ -0,0 +1,1889 @@
+use std::{collections::VecDeque, sync::Arc, time::{Duration, Instant}};
+
+use crate::{
+    error::MqttError,
+    packet::{
+        Auth, AuthProperties, Connack, Connect, ConnectProperties, Disconnect, Packet, PacketType, Puback, Pubcomp,
+        Publish, Pubrec, Pubrel, Suback, Subscribe, Unsuback, Unsubscribe,
+    },
+    types::{QoS, ReasonCode},
+    wire,
+};
+
+use super::{
+    inflight::InFlight, schedule::{self, AwaitingPubrel}, subscription::Subscriptions, Clock, Direction, DueAction,
+    InFlightMessage, NegotiatedSession, RateLimit, RateLimiter, ReauthStep, Received, Redirect, RedirectPolicy,
+    ServerReference, SessionSnapshot, SessionStats, Subscription, SystemClock, Timestamp, TopicAliasMap,
+    TopicAliasPolicy,
+};
+
+/// Options for a [ClientSession].
+#[derive(Debug, Default)]
+pub struct SessionConfig {
+    /// How aliases are assigned to topics of outgoing `PUBLISH` packets. Disabled by default.
+    pub topic_alias_policy: TopicAliasPolicy,
+
+    /// If `true`, outgoing QoS 1 and 2 messages are only reported as [completed](ClientSession::completed) in the 
+    /// order they were sent to their topic, even if the server acknowledges them out of order. Defaults to `false`.
+    ///
+    /// Incoming messages aren't affected: they are passed on to the application as they arrive, in the order the
+    /// server sends them.
+    pub ordered_delivery: bool,
+
+    /// Keep alive in seconds to request with `CONNECT`, used unless the server overrides it. `0` disables keep alive:
+    /// no `PINGREQ` is ever sent and the server doesn't time out an idle connection. If not set (the default), the
+    /// keep alive of the `CONNECT` as [prepared](ClientSession::prepare_connect) is left as it is and used instead.
+    pub keep_alive: Option<u16>,
+
+    /// If set, outgoing QoS 1 and 2 messages that aren't acknowledged within this time are sent again on the same
+    /// connection. Note that MQTT 5 only allows resending after reconnecting, so this is meant for servers known to
+    /// tolerate it. Disabled by default.
+    pub ack_timeout: Option<Duration>,
+
+    /// If set, incoming QoS 2 messages are forgotten if their `PUBREL` doesn't arrive within this time. Disabled by
+    /// default.
+    pub pubrel_timeout: Option<Duration>,
+
+    /// Whether [ClientSession::follow_redirect] lets the client move on to another server. Redirects are only
+    /// reported by default.
+    pub redirect_policy: RedirectPolicy,
+
+    /// What [ClientSession::prepare_subscribe] does with topic filters asking for a higher QoS than the server
+    /// supports. Left as they are by default.
+    pub subscribe_qos_policy: SubscribeQoSPolicy,
+
+    /// If `true`, [ClientSession::prepare_connect] asks the server for response information, the basis for
+    /// [response topics](ClientSession::response_topic) in request/response exchanges. Defaults to `false`.
+    pub request_response_information: bool,
+
+    /// If set, messages [queued](ClientSession::enqueue) are released by [ClientSession::advance] no faster than this.
+    /// Without it, they are released on the next call. Disabled by default.
+    pub rate_limit: Option<RateLimit>,
+
+    /// Most messages [ClientSession::enqueue] keeps waiting, further ones are dropped. Unlimited by default.
+    pub queue_limit: Option<usize>,
+
+    /// If set, [ClientSession::prepare_publish] compresses outgoing messages and [ClientSession::decode] decompresses
+    /// incoming ones. Disabled by default.
+    #[cfg(feature = "payload-compression")]
+    pub payload_compression: Option<super::PayloadCompression>,
+
+    /// Where the session takes the time from when it's created and for its [statistics](ClientSession::stats). The
+    /// [SystemClock] by default.
+    pub clock: Option<Arc<dyn Clock>>,
+
+    /// Called with the client identifier the server assigns with its `CONNACK`, for the application to persist and
+    /// connect with later on. Not set by default.
+    pub on_assigned_client_id: Option<ClientIdHook>,
+}
+
+/// A callback for [SessionConfig::on_assigned_client_id].
+///
+/// ```
+/// use std::sync::{Arc, Mutex};
+/// use mqtt::packet::{Connack, ConnackProperties};
+/// use mqtt::session::{ClientIdHook, ClientSession, SessionConfig};
+/// use mqtt::types::ReasonCode;
+///
+/// let stored = Arc::new(Mutex::new(None));
+/// let store = stored.clone();
+/// let mut session = ClientSession::new(SessionConfig {
+///     on_assigned_client_id: Some(ClientIdHook::new(move |id| *store.lock().unwrap() = Some(id.to_string()))),
+///     ..Default::default()
+/// });
+///
+/// let properties = ConnackProperties { assigned_client_identifier: Some("auto-1".into()), ..Default::default() };
+/// session.on_connack(&Connack { session_present: false, reason_code: ReasonCode::Success, properties: Some(properties) });
+/// assert_eq!(Some("auto-1".to_string()), *stored.lock().unwrap());
+/// ```
+#[derive(Clone)]
+pub struct ClientIdHook(Arc<dyn Fn(&str) + Send + Sync>);
+
+impl ClientIdHook {
+    pub fn new(f: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(f))
+    }
+}
+
+impl std::fmt::Debug for ClientIdHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ClientIdHook")
+    }
+}
+
+/// How to handle a `SUBSCRIBE` whose topic filters ask for a higher maximum QoS than the server announced with its
+/// `CONNACK`. That's legal, the server simply grants less, but pointless.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SubscribeQoSPolicy {
+    /// Send the topic filters unchanged.
+    #[default]
+    Keep,
+
+    /// Lower the maximum QoS of the topic filters to what the server supports.
+    Downgrade,
+
+    /// Refuse to send the `SUBSCRIBE`.
+    Strict,
+}
+
+/// Client-side protocol state of a connection to a server.
+/// 
+/// # Examples
+/// ```
+/// use mqtt::packet::{Connack, ConnackProperties, Publish};
+/// use mqtt::session::{ClientSession, SessionConfig, TopicAliasPolicy};
+/// use mqtt::types::ReasonCode;
+/// 
+/// let mut config = SessionConfig::default();
+/// config.topic_alias_policy = TopicAliasPolicy::Lru(10);
+/// let mut session = ClientSession::new(config);
+/// 
+/// let mut properties = ConnackProperties::default();
+/// properties.topic_alias_maximum = Some(5);
+/// session.on_connack(&Connack { session_present: false, reason_code: ReasonCode::Success, properties: Some(properties) });
+/// 
+/// let mut publish = Publish::with_topic_name("some/topic".try_into().unwrap(), vec![]);
+/// session.prepare_publish(&mut publish).unwrap();
+/// assert_eq!(Some(1), publish.properties.unwrap().topic_alias);
+/// ```
+#[derive(Debug)]
+pub struct ClientSession {
+    topic_aliases: TopicAliasMap,
+    in_flight: InFlight,
+    awaiting_pubrel: AwaitingPubrel,
+    stats: SessionStats,
+    /// as set in the [SessionConfig], overriding the one of the `CONNECT`
+    configured_keep_alive: Option<u16>,
+    requested_keep_alive: u16,
+    keep_alive: Option<Duration>,
+    /// as resolved from the last `CONNACK`
+    negotiated: Option<NegotiatedSession>,
+    ack_timeout: Option<Duration>,
+    pubrel_timeout: Option<Duration>,
+    /// The time of the last call to [ClientSession::advance].
+    now: Instant,
+    last_sent: Instant,
+    redirect_policy: RedirectPolicy,
+    /// redirects followed since the last successful `CONNACK`
+    redirects: u8,
+    subscribe_qos_policy: SubscribeQoSPolicy,
+    subscriptions: Subscriptions,
+    /// the maximum QoS the server supports
+    maximum_qos: QoS,
+    /// the largest packet the server accepts, unlimited if not set
+    maximum_packet_size: Option<u32>,
+    /// the largest packet the client accepts, as announced with the last `CONNECT`
+    incoming_maximum_packet_size: Option<u32>,
+    request_response_information: bool,
+    /// as sent by the server with its `CONNACK`
+    response_information: Option<String>,
+    /// messages waiting for the rate limiter, oldest first
+    queue: VecDeque<Publish>,
+    queue_limit: Option<usize>,
+    rate_limiter: Option<RateLimiter>,
+    #[cfg(feature = "payload-compression")]
+    payload_compression: Option<super::PayloadCompression>,
+    clock: Arc<dyn Clock>,
+    /// as assigned by the server with the last `CONNACK` that had one
+    assigned_client_id: Option<String>,
+    /// to continue the session with: as sent with the last `CONNECT`, assigned by the server or restored
+    client_id: Option<String>,
+    on_assigned_client_id: Option<ClientIdHook>,
+    /// as sent with the last `CONNECT`, which a `DISCONNECT` mustn't raise from 0
+    connect_session_expiry: Option<u32>,
+    /// as sent with the last `CONNECT`, which re-authentication must use again
+    authentication_method: Option<String>,
+    /// whether a re-authentication started with [ClientSession::reauthenticate] is still going on
+    reauthenticating: bool,
+}
+
+impl ClientSession {
+
+    pub fn new(config: SessionConfig) -> Self {
+        let clock = config.clock.unwrap_or_else(|| Arc::new(SystemClock));
+        let now = clock.now();
+        Self {
+            topic_aliases: TopicAliasMap::new(config.topic_alias_policy),
+            in_flight: InFlight::new(config.ordered_delivery),
+            awaiting_pubrel: AwaitingPubrel::default(),
+            stats: SessionStats::default(),
+            configured_keep_alive: config.keep_alive,
+            requested_keep_alive: config.keep_alive.unwrap_or(0),
+            keep_alive: None,
+            negotiated: None,
+            ack_timeout: config.ack_timeout,
+            pubrel_timeout: config.pubrel_timeout,
+            now,
+            last_sent: now,
+            redirect_policy: config.redirect_policy,
+            redirects: 0,
+            subscribe_qos_policy: config.subscribe_qos_policy,
+            subscriptions: Subscriptions::default(),
+            maximum_qos: QoS::ExactlyOnce,
+            maximum_packet_size: None,
+            incoming_maximum_packet_size: None,
+            request_response_information: config.request_response_information,
+            response_information: None,
+            queue: VecDeque::new(),
+            queue_limit: config.queue_limit,
+            rate_limiter: config.rate_limit.map(|limit| RateLimiter::new(limit, now)),
+            #[cfg(feature = "payload-compression")]
+            payload_compression: config.payload_compression,
+            clock,
+            assigned_client_id: None,
+            client_id: None,
+            on_assigned_client_id: config.on_assigned_client_id,
+            connect_session_expiry: None,
+            authentication_method: None,
+            reauthenticating: false,
+        }
+    }
+
+    /// The [clock](SessionConfig::clock) the session was created with, which an event loop should also take the
+    /// time for [advance](ClientSession::advance) from.
+    pub fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+
+    /// Should be called for every `CONNECT` right before sending it, to request what the [SessionConfig] asks for,
+    /// including its [keep alive](SessionConfig::keep_alive) if set. A `CONNECT` without a client identifier gets the
+    /// one of the session so far, such as the [assigned](ClientSession::assigned_client_id) one, so that reconnecting
+    /// continues the same session.
+    ///
+    /// The session expiry interval of the `CONNECT` is remembered to check a `DISCONNECT` against when
+    /// [encoding](ClientSession::encode) it, its authentication method to [re-authenticate](Self::reauthenticate)
+    /// with and its maximum packet size to [decode](ClientSession::decode) within.
+    pub fn prepare_connect(&mut self, connect: &mut Connect) {
+        if let Some(keep_alive) = self.configured_keep_alive {
+            connect.keep_alive = keep_alive;
+        }
+        self.requested_keep_alive = connect.keep_alive;
+        match &connect.client_id {
+            Some(id) if !id.is_empty() => self.client_id = Some(id.clone()),
+            _ => connect.client_id.clone_from(&self.client_id),
+        }
+        self.connect_session_expiry = connect.properties.as_ref().and_then(|p| p.session_expiry_interval);
+        self.incoming_maximum_packet_size = connect.properties.as_ref().and_then(|p| p.maximum_packet_size);
+        self.authentication_method = connect.properties.as_ref().and_then(|p| p.authentication_method.clone());
+        self.reauthenticating = false;
+        if self.request_response_information {
+            connect.properties.get_or_insert_with(ConnectProperties::default).request_response_information = Some(true);
+        }
+    }
+
+    /// Applies the values the server sent back with its `CONNACK`. Must be called for every new connection.
+    ///
+    /// If the server accepts the connection without a session present, the session state is discarded
+    /// [MQTT-3.2.2-5]: outgoing messages not completed yet are neither [redelivered](ClientSession::redeliver) nor
+    /// completed, incoming QoS 2 messages no longer await their `PUBREL`. Their packet identifiers mean nothing to the
+    /// server's new session, so resending them would mix up its flows. Check [ClientSession::in_flight] beforehand
+    /// to publish any of these messages again as new ones. The [subscriptions](ClientSession::subscriptions) are kept,
+    /// to be made again with [ClientSession::resubscribe_packets].
+    pub fn on_connack(&mut self, connack: &Connack) {
+        let topic_alias_maximum = connack.properties.as_ref().and_then(|p| p.topic_alias_maximum);
+        self.topic_aliases.reset(topic_alias_maximum);
+
+        let negotiated = NegotiatedSession::new(self.requested_keep_alive, connack);
+        self.keep_alive = negotiated.keep_alive_interval();
+        self.negotiated = Some(negotiated);
+
+        self.maximum_qos = connack.properties.as_ref().map_or(QoS::ExactlyOnce, |p| p.maximum_qos());
+
+        self.maximum_packet_size = connack.properties.as_ref().and_then(|p| p.maximum_packet_size);
+
+        self.response_information = connack.properties.as_ref().and_then(|p| p.response_information.clone());
+
+        self.subscriptions.on_reconnect();
+
+        if !connack.reason_code.is_err() {
+            self.redirects = 0;
+            if let Some(id) = connack.properties.as_ref().and_then(|p| p.assigned_client_identifier.as_ref()) {
+                if let Some(hook) = &self.on_assigned_client_id {
+                    (hook.0)(id);
+                }
+                self.assigned_client_id = Some(id.clone());
+                self.client_id = Some(id.clone());
+            }
+            if !connack.session_present {
+                self.in_flight.clear();
+                self.awaiting_pubrel = AwaitingPubrel::default();
+            }
+        }
+    }
+
+    /// The client identifier the server assigned because the client connected without one, kept across connections
+    /// of this session.
+    pub fn assigned_client_id(&self) -> Option<&str> {
+        self.assigned_client_id.as_deref()
+    }
+
+    /// Starts re-authenticating on the current connection, returning the `AUTH` to send. It uses the authentication
+    /// method of the `CONNECT` again, the only one allowed [MQTT-4.12.1-1]. Every `AUTH` the server answers with goes
+    /// to [ClientSession::on_auth]. A server refusing the client closes the connection with a `DISCONNECT` instead,
+    /// such as [BadAuthenticationMethod](ReasonCode::BadAuthenticationMethod) or
+    /// [NotAuthorized](ReasonCode::NotAuthorized).
+    ///
+    /// Returns a `ProtocolError` if the `CONNECT` had no authentication method or a re-authentication is already
+    /// going on.
+    pub fn reauthenticate(&mut self, authentication_data: Option<Vec<u8>>) -> Result<Auth, MqttError> {
+        if self.authentication_method.is_none() {
+            return Err(MqttError::ProtocolError(
+                "Re-authentication requires an authentication method in CONNECT".into()))
+        }
+        if self.reauthenticating {
+            return Err(MqttError::ProtocolError("Re-authentication already in progress".into()))
+        }
+        self.reauthenticating = true;
+        Ok(self.auth(ReasonCode::ReAuthenticate, authentication_data))
+    }
+
+    /// Must be called for every `AUTH` received after the `CONNACK`. A challenge has to be answered with
+    /// [ClientSession::respond_auth], a success ends the re-authentication.
+    ///
+    /// Returns a `ProtocolError` if the client isn't re-authenticating, as only clients may start it, if the server
+    /// switched to a different authentication method or for a reason code other than success or continue
+    /// authentication. The connection should then be closed with its [DISCONNECT](MqttError::to_disconnect).
+    pub fn on_auth(&mut self, auth: &Auth) -> Result<ReauthStep, MqttError> {
+        if !self.reauthenticating {
+            return Err(MqttError::ProtocolError("Server sent AUTH without the client re-authenticating".into()))
+        }
+        let (method, data) = auth.properties.as_ref()
+            .map(|p| (p.authentication_method.as_ref(), p.authentication_data.clone()))
+            .unwrap_or_default();
+        if method != self.authentication_method.as_ref() {
+            return Err(MqttError::ProtocolError(format!(
+                "Authentication method mismatch, expected {:?} but got {:?}", self.authentication_method, method)))
+        }
+
+        match auth.reason_code {
+            ReasonCode::ContinueAuthentication => Ok(ReauthStep::Challenge(data)),
+            ReasonCode::Success => {
+                self.reauthenticating = false;
+                Ok(ReauthStep::Done(data))
+            },
+            other => Err(MqttError::ProtocolError(format!("Unexpected AUTH reason code: {:?}", other))),
+        }
+    }
+
+    /// Builds the `AUTH` answering a [challenge](ReauthStep::Challenge) of the server.
+    ///
+    /// Returns a `ProtocolError` if the client isn't re-authenticating.
+    pub fn respond_auth(&self, authentication_data: Option<Vec<u8>>) -> Result<Auth, MqttError> {
+        if !self.reauthenticating {
+            return Err(MqttError::ProtocolError("No re-authentication to respond to".into()))
+        }
+        Ok(self.auth(ReasonCode::ContinueAuthentication, authentication_data))
+    }
+
+    /// Whether a re-authentication was [started](ClientSession::reauthenticate) and the server hasn't accepted it yet.
+    pub fn is_reauthenticating(&self) -> bool {
+        self.reauthenticating
+    }
+
+    fn auth(&self, reason_code: ReasonCode, authentication_data: Option<Vec<u8>>) -> Auth {
+        let properties = AuthProperties {
+            authentication_method: self.authentication_method.clone(),
+            authentication_data,
+            ..Default::default()
+        };
+        Auth { reason_code, properties: Some(properties) }
+    }
+
+    /// What to keep of the session to [resume](ClientSession::restore) it after a restart. Best taken and saved
+    /// whenever the subscriptions or the incoming messages awaiting their `PUBREL` change, and before exiting.
+    pub fn snapshot(&self) -> SessionSnapshot {
+        SessionSnapshot {
+            client_id: self.client_id.clone(),
+            subscriptions: self.subscriptions.active().to_vec(),
+            awaiting_pubrel: self.awaiting_pubrel.messages(self.now)
+                .map(|message| (message.packet_identifier, message.topic))
+                .collect(),
+        }
+    }
+
+    /// Takes up a session saved with [ClientSession::snapshot] by an earlier process. Must be called before
+    /// [preparing](ClientSession::prepare_connect) the `CONNECT`, which should have `clean_start` set to `false` and
+    /// gets the client identifier of the snapshot unless it has one of its own.
+    ///
+    /// If the server doesn't have the session anymore, its `CONNACK` discards the messages awaiting their `PUBREL`
+    /// like for any other session, while the subscriptions are kept to be
+    /// [made again](ClientSession::resubscribe_packets).
+    ///
+    /// # Examples
+    /// ```
+    /// use mqtt::packet::{Connect, Publish};
+    /// use mqtt::session::ClientSession;
+    /// use mqtt::types::QoS;
+    ///
+    /// let mut before = ClientSession::default();
+    /// before.prepare_connect(&mut Connect::with_client_id_str("sensor").unwrap());
+    /// let publish = Publish::builder("a").qos(QoS::ExactlyOnce).packet_identifier(7).build().unwrap();
+    /// before.on_incoming_publish(&publish);
+    ///
+    /// let mut after = ClientSession::default();
+    /// after.restore(before.snapshot());
+    /// let mut connect = Connect::default();
+    /// connect.clean_start = false;
+    /// after.prepare_connect(&mut connect);
+    /// assert_eq!(Some("sensor".to_string()), connect.client_id);
+    ///
+    /// // the PUBLISH is a redelivery should the server send it again
+    /// assert!(after.is_awaiting_pubrel(7));
+    /// ```
+    pub fn restore(&mut self, snapshot: SessionSnapshot) {
+        self.client_id = snapshot.client_id;
+        self.subscriptions.restore(snapshot.subscriptions);
+        self.awaiting_pubrel = AwaitingPubrel::default();
+        for (packet_identifier, topic) in snapshot.awaiting_pubrel {
+            self.stats.flows += 1;
+            self.awaiting_pubrel.track(packet_identifier, &topic, self.now, self.stats.flows);
+        }
+    }
+
+    /// The values in effect for the connection, such as its keep alive, once [ClientSession::on_connack] was called.
+    pub fn negotiated(&self) -> Option<&NegotiatedSession> {
+        self.negotiated.as_ref()
+    }
+
+    /// Decides whether to follow a redirect the server sent with its `CONNACK` or a `DISCONNECT`, returning the server
+    /// to connect to next. Each redirect followed counts against the limit of the [RedirectPolicy] until a connection
+    /// succeeds.
+    pub fn follow_redirect<'a>(&mut self, redirect: &'a Redirect) -> Option<&'a ServerReference> {
+        let limit = match self.redirect_policy {
+            RedirectPolicy::Report => return None,
+            RedirectPolicy::Follow(limit) => limit,
+        };
+        let reference = redirect.references.first()?;
+        if self.redirects >= limit {
+            return None
+        }
+        self.redirects += 1;
+        Some(reference)
+    }
+
+    /// Moves the session's clock forward to `now` and returns everything that has become due since the last call, in
+    /// the order it should be done. 
+    /// 
+    /// The session doesn't read the system time itself: any packet sent or received is considered to have happened
+    /// at the time of the last call to this method. An event loop should call it whenever it wakes up, and sleep no
+    /// longer than until [ClientSession::next_due].
+    pub fn advance(&mut self, now: Instant) -> Vec<DueAction> {
+        if now > self.now {
+            self.now = now;
+        }
+
+        let mut actions = Vec::new();
+
+        if let Some(timeout) = self.pubrel_timeout {
+            for id in self.awaiting_pubrel.expire(timeout, self.now) {
+                actions.push(DueAction::ExpirePubrel(id));
+            }
+        }
+
+        if let Some(timeout) = self.ack_timeout {
+            for mut packet in self.in_flight.overdue(timeout, self.now) {
+                self.stats.retransmissions += 1;
+                if let Packet::Publish(publish) = &mut packet {
+                    self.topic_aliases.apply(publish);
+                }
+                actions.push(DueAction::Retransmit(Box::new(packet)));
+            }
+        }
+
+        while let Some(publish) = self.queue.front() {
+            let now = self.now;
+            if !self.rate_limiter.as_mut().is_none_or(|limiter| limiter.try_acquire(queued_len(publish), now)) {
+                break
+            }
+            if let Some(publish) = self.queue.pop_front() {
+                actions.push(DueAction::Publish(Box::new(publish)));
+            }
+        }
+
+        // anything else sent right now resets the keep alive as well, expiring a PUBREL doesn't send anything
+        let sends = actions.iter().any(|action| matches!(action, DueAction::Retransmit(_) | DueAction::Publish(_)));
+        if !sends && self.keep_alive.is_some_and(|k| self.last_sent + k <= self.now) {
+            actions.push(DueAction::SendPingreq);
+        }
+
+        actions
+    }
+
+    /// When [ClientSession::advance] will return something next, if anything is pending at all.
+    pub fn next_due(&self) -> Option<Instant> {
+        [
+            self.keep_alive.map(|k| self.last_sent + k),
+            self.ack_timeout.and_then(|t| self.in_flight.next_due(t)),
+            self.pubrel_timeout.and_then(|t| self.awaiting_pubrel.next_due(t)),
+            self.queue.front().map(|publish| match &self.rate_limiter {
+                Some(limiter) => limiter.ready_at(queued_len(publish), self.now),
+                None => self.now,
+            }),
+        ].into_iter().flatten().min()
+    }
+
+    /// How long an event loop may wait from `now` before calling [ClientSession::advance] again, `None` if nothing
+    /// is pending, as with keep alive disabled. Never zero, so it can go straight into a read timeout, even if
+    /// something is overdue already.
+    pub fn time_until_due(&self, now: Instant) -> Option<Duration> {
+        schedule::time_until(self.next_due(), now)
+    }
+
+    /// Decodes a packet received from the server. If that fails, the connection must be closed with the returned
+    /// `DISCONNECT`, which carries the matching reason code (such as `0x81` for malformed packets) and the error 
+    /// message as reason string.
+    ///
+    /// Only the packet at the beginning of `src` is decoded, use [Packet::decode_with_len] to find out where the next
+    /// one starts.
+    ///
+    /// A packet larger than the [maximum](ClientSession::incoming_maximum_packet_size) the client announced fails
+    /// with `PacketTooLarge` as soon as `src` holds its fixed header, so that a reader can close the connection
+    /// without buffering the rest of it first.
+    pub fn decode(&mut self, src: &[u8]) -> Result<Packet, Disconnect> {
+        if let Some(maximum) = self.incoming_maximum_packet_size {
+            wire::check_packet_len(src, maximum).map_err(|e| e.to_disconnect())?;
+        }
+        let (packet, len) = Packet::decode_with_len(src).map_err(|e| e.to_disconnect())?;
+        self.on_packet_received(packet.packet_type(), len);
+        #[cfg(feature = "payload-compression")]
+        let packet = match packet {
+            Packet::Publish(mut publish) => {
+                self.decompress(&mut publish).map_err(|e| e.to_disconnect())?;
+                Packet::Publish(publish)
+            },
+            other => other,
+        };
+        Ok(packet)
+    }
+
+    /// Like [decode](ClientSession::decode), but stamps the packet with the [clock](SessionConfig::clock)'s time
+    /// right away, so that latency and message expiry can be based on when it arrived rather than on when the
+    /// application got around to it.
+    pub fn decode_received(&mut self, src: &[u8]) -> Result<Received<Packet>, Disconnect> {
+        let timestamp = Timestamp::now(self.clock.as_ref());
+        self.decode(src).map(|packet| Received { packet, timestamp })
+    }
+
+    /// Decompresses an incoming message as the [PayloadCompression](super::PayloadCompression) of the
+    /// [SessionConfig] says, for messages not [decoded](ClientSession::decode) by the session itself.
+    #[cfg(feature = "payload-compression")]
+    pub fn decompress(&self, publish: &mut Publish) -> Result<(), MqttError> {
+        match &self.payload_compression {
+            Some(compression) => compression.decompress(publish),
+            None => Ok(()),
+        }
+    }
+
+    /// Encodes a packet to send, keeping it within the [maximum packet size](ClientSession::maximum_packet_size) of the
+    /// server [MQTT-3.1.2-24]. Reason string and user properties are left out where the spec allows it, see
+    /// [Packet::encode_within].
+    ///
+    /// Anything else that's too large, usually a `PUBLISH`, fails with [PacketTooLarge](MqttError::PacketTooLarge)
+    /// rather than getting the connection closed by the server. A new message
+    /// [prepared](ClientSession::prepare_publish) before is forgotten again then: it's no longer in flight, and its
+    /// topic alias isn't assumed to be known.
+    ///
+    /// A `DISCONNECT` setting a session expiry interval after [connecting](ClientSession::prepare_connect) with 0
+    /// fails with a [ProtocolError](MqttError::ProtocolError), see [Disconnect::check_session_expiry].
+    pub fn encode<P: Into<Packet>>(&mut self, packet: P) -> Result<Vec<u8>, MqttError> {
+        let packet = packet.into();
+        if let Packet::Disconnect(disconnect) = &packet {
+            disconnect.check_session_expiry_interval(self.connect_session_expiry)?;
+        }
+        // what prepare_publish did, to be undone if a new message can't be sent
+        let prepared = match &packet {
+            Packet::Publish(publish) if !publish.dup => Some((
+                publish.packet_identifier.filter(|_| publish.qos_level != QoS::AtMostOnce),
+                publish.properties.as_ref()
+                    .and_then(|p| p.topic_alias)
+                    .filter(|_| !publish.topic_name.is_empty())
+                    .map(|alias| (publish.topic_name.clone(), alias)),
+            )),
+            _ => None,
+        };
+
+        let result = packet.encode_within(self.maximum_packet_size);
+        if let (Err(MqttError::PacketTooLarge { .. }), Some((packet_identifier, alias))) = (&result, prepared) {
+            if let Some(packet_identifier) = packet_identifier {
+                self.in_flight.forget(packet_identifier);
+            }
+            if let Some((topic, alias)) = alias {
+                self.topic_aliases.forget(&topic, alias);
+            }
+        }
+        result
+    }
+
+    /// Must be called for every packet written to the connection, for the [statistics](ClientSession::stats).
+    pub fn on_packet_sent(&mut self, packet_type: PacketType, bytes: usize) {
+        self.stats.record_sent(packet_type, bytes, self.clock.now());
+        self.last_sent = self.now;
+    }
+
+    /// Must be called for every packet received that wasn't [decoded](ClientSession::decode) by the session itself,
+    /// for the [statistics](ClientSession::stats).
+    pub fn on_packet_received(&mut self, packet_type: PacketType, bytes: usize) {
+        self.stats.record_received(packet_type, bytes, self.clock.now());
+    }
+
+    /// Checks a packet that arrived while the application was waiting for something else, such as a `PINGRESP`
+    /// without a `PINGREQ` sent. Most packets are legal at any time after the handshake and may simply be ignored if
+    /// there is nothing else to do with them.
+    ///
+    /// Returns the `DISCONNECT` to close the connection with if the packet is a protocol error: a second `CONNACK`
+    /// [MQTT-3.2.0-2], or a `CONNECT`, `SUBSCRIBE`, `UNSUBSCRIBE` or `PINGREQ`, which only clients send.
+    ///
+    /// # Examples
+    /// ```
+    /// use mqtt::packet::{Connack, Packet, Pingresp};
+    /// use mqtt::session::ClientSession;
+    /// use mqtt::types::ReasonCode;
+    ///
+    /// let session = ClientSession::default();
+    /// assert!(session.check_unsolicited(&Packet::Pingresp(Pingresp {})).is_ok());
+    ///
+    /// let connack = Connack { session_present: false, reason_code: ReasonCode::Success, properties: None };
+    /// let disconnect = session.check_unsolicited(&connack.into()).unwrap_err();
+    /// assert_eq!(ReasonCode::ProtocolError, disconnect.reason_code);
+    /// ```
+    pub fn check_unsolicited(&self, packet: &Packet) -> Result<(), Disconnect> {
+        let error = match packet {
+            Packet::Connack(_) => "Server sent a second CONNACK".to_string(),
+            Packet::Connect(_) | Packet::Subscribe(_) | Packet::Unsubscribe(_) | Packet::Pingreq(_) =>
+                format!("{} may only be sent by clients", packet.packet_type()),
+            _ => return Ok(()),
+        };
+        Err(MqttError::ProtocolError(error).to_disconnect())
+    }
+
+    /// Must be called for every `PUBLISH` right before sending it. Keeps track of QoS 1 and 2 messages until they are
+    /// acknowledged and applies topic aliases, as well as payload compression if configured.
+    /// 
+    /// Returns a `ProtocolError` if a QoS 1 or 2 message has no packet identifier or the identifier is still in use.
+    pub fn prepare_publish(&mut self, publish: &mut Publish) -> Result<(), MqttError> {
+        #[cfg(feature = "payload-compression")]
+        if let Some(compression) = &self.payload_compression {
+            compression.compress(publish)?;
+        }
+        self.in_flight.track(publish, self.now, self.stats.flows + 1)?;
+        if publish.qos_level != QoS::AtMostOnce {
+            self.stats.flows += 1;
+        }
+        self.topic_aliases.apply(publish);
+        Ok(())
+    }
+
+    /// Queues a message to be released by [ClientSession::advance] as the [rate limit](SessionConfig::rate_limit)
+    /// allows, in the order queued. Returns `false` and counts the message as [dropped](SessionStats::dropped) if the
+    /// [queue limit](SessionConfig::queue_limit) has been reached.
+    ///
+    /// Nothing is checked or tracked until the message is released, a QoS 1 or 2 message may get its packet identifier
+    /// then.
+    pub fn enqueue(&mut self, publish: Publish) -> bool {
+        if self.queue_limit.is_some_and(|limit| self.queue.len() >= limit) {
+            self.stats.dropped += 1;
+            return false
+        }
+        self.queue.push_back(publish);
+        true
+    }
+
+    /// Should be called for every `SUBSCRIBE` right before sending it, to apply the [SubscribeQoSPolicy] against the
+    /// maximum QoS of the server. The topic filters become [subscriptions](ClientSession::subscriptions) once the
+    /// server grants them with its `SUBACK`, see [ClientSession::on_suback].
+    ///
+    /// Returns an error under [SubscribeQoSPolicy::Strict] if any topic filter asks for more than the server supports.
+    pub fn prepare_subscribe(&mut self, subscribe: &mut Subscribe) -> Result<(), MqttError> {
+        let excessive = subscribe.topic_filter.iter_mut().filter(|f| f.maximum_qos > self.maximum_qos);
+        match self.subscribe_qos_policy {
+            SubscribeQoSPolicy::Keep => {},
+            SubscribeQoSPolicy::Downgrade => excessive.for_each(|f| f.maximum_qos = self.maximum_qos),
+            SubscribeQoSPolicy::Strict => if let Some(filter) = excessive.map(|f| &f.filter).next() {
+                return Err(MqttError::Message(format!(
+                    "Topic filter {} asks for a higher QoS than the server's maximum of {:?}",
+                    filter, self.maximum_qos)))
+            },
+        }
+        self.subscriptions.on_subscribe(subscribe);
+        Ok(())
+    }
+
+    /// Takes up the topic filters of the prepared `SUBSCRIBE` the server granted into the
+    /// [subscriptions](ClientSession::subscriptions). A topic filter subscribed to again replaces the earlier
+    /// subscription, unless the server refused it.
+    pub fn on_suback(&mut self, suback: &Suback) {
+        self.subscriptions.on_suback(suback);
+    }
+
+    /// Should be called for every `UNSUBSCRIBE` right before sending it, so that [ClientSession::on_unsuback] knows
+    /// which subscriptions it ends.
+    pub fn prepare_unsubscribe(&mut self, unsubscribe: &Unsubscribe) {
+        self.subscriptions.on_unsubscribe(unsubscribe);
+    }
+
+    /// Drops the topic filters of the prepared `UNSUBSCRIBE` from the [subscriptions](ClientSession::subscriptions),
+    /// unless the server refused to unsubscribe.
+    pub fn on_unsuback(&mut self, unsuback: &Unsuback) {
+        self.subscriptions.on_unsuback(unsuback);
+    }
+
+    /// The subscriptions the server granted, in the order they were first made. They outlive the connection: after
+    /// reconnecting without a session present, [ClientSession::resubscribe_packets] makes them again.
+    pub fn subscriptions(&self) -> &[Subscription] {
+        self.subscriptions.active()
+    }
+
+    /// The `SUBSCRIBE` packets that make all [subscriptions](ClientSession::subscriptions) again, to be sent after a
+    /// `CONNACK` without a session present. Topic filters are grouped by their subscription identifier and split up to
+    /// fit the [maximum packet size](ClientSession::maximum_packet_size) of the server, each packet taking its
+    /// identifier from `packet_identifier`.
+    ///
+    /// The packets still need to be [prepared](ClientSession::prepare_subscribe) like any other `SUBSCRIBE`.
+    pub fn resubscribe_packets(&self, packet_identifier: impl FnMut() -> u16) -> Result<Vec<Subscribe>, MqttError> {
+        self.subscriptions.resubscribe(self.maximum_packet_size, packet_identifier)
+    }
+
+    /// The highest QoS the server supports, as announced with its `CONNACK`.
+    pub fn maximum_qos(&self) -> QoS {
+        self.maximum_qos
+    }
+
+    /// The largest packet in bytes the client accepts, as announced with its `CONNECT`. Should be handed to
+    /// [PacketIter::maximum_packet_size](crate::packet::PacketIter::maximum_packet_size) when reading from the
+    /// connection, to reject larger packets before they're buffered.
+    pub fn incoming_maximum_packet_size(&self) -> Option<u32> {
+        self.incoming_maximum_packet_size
+    }
+
+    /// The largest packet in bytes the server accepts, as announced with its `CONNACK`. `None` if there is no limit
+    /// other than the protocol's own.
+    pub fn maximum_packet_size(&self) -> Option<u32> {
+        self.maximum_packet_size
+    }
+
+    /// The response information the server sent with its `CONNACK`, if it was
+    /// [requested](SessionConfig::request_response_information) and the server supports it. How to build response
+    /// topics from it is up to the application, see [ClientSession::response_topic] for the common way.
+    pub fn response_topic_base(&self) -> Option<&str> {
+        self.response_information.as_deref()
+    }
+
+    /// A response topic below the [response_topic_base](ClientSession::response_topic_base), such as
+    /// `base/suffix`. `None` if the server didn't send response information.
+    pub fn response_topic(&self, suffix: &str) -> Option<String> {
+        self.response_topic_base().map(|base| match base.ends_with('/') {
+            true => format!("{}{}", base, suffix),
+            false => format!("{}/{}", base, suffix),
+        })
+    }
+
+    /// Completes the flow of a QoS 1 message.
+    pub fn on_puback(&mut self, puback: &Puback) -> Result<(), MqttError> {
+        self.in_flight.on_puback(puback.packet_identifier)
+    }
+
+    /// Continues the flow of a QoS 2 message, returning the `PUBREL` to send. If the server rejected the message, 
+    /// there is nothing left to send and the flow is complete.
+    pub fn on_pubrec(&mut self, pubrec: &Pubrec) -> Result<Option<Pubrel>, MqttError> {
+        self.in_flight.on_pubrec(pubrec.packet_identifier, pubrec.reason_code, self.now)
+    }
+
+    /// Completes the flow of a QoS 2 message.
+    pub fn on_pubcomp(&mut self, pubcomp: &Pubcomp) -> Result<(), MqttError> {
+        self.in_flight.on_pubcomp(pubcomp.packet_identifier)
+    }
+
+    /// Must be called for every `PUBLISH` received from the server, to keep track of QoS 2 messages until their 
+    /// `PUBREL` arrives. Returns the acknowledgement to send: `PUBACK` for QoS 1, `PUBREC` for QoS 2 and nothing for
+    /// QoS 0 or a message without packet identifier.
+    ///
+    /// A QoS 2 message that is still [awaiting its PUBREL](Self::is_awaiting_pubrel) is a redelivery, which is
+    /// acknowledged again but must not be passed on to the application a second time.
+    pub fn on_incoming_publish(&mut self, publish: &Publish) -> Option<Packet> {
+        let packet_identifier = publish.packet_identifier?;
+        match publish.qos_level {
+            QoS::AtMostOnce => None,
+            QoS::AtLeastOnce => Puback::new(packet_identifier, ReasonCode::Success).ok().map(Packet::from),
+            QoS::ExactlyOnce => {
+                if !self.awaiting_pubrel.contains(packet_identifier) {
+                    self.stats.flows += 1;
+                    self.awaiting_pubrel.track(packet_identifier, &publish.topic_name, self.now, self.stats.flows);
+                }
+                Pubrec::new(packet_identifier, ReasonCode::Success).ok().map(Packet::from)
+            },
+        }
+    }
+
+    /// The ID of the flow a packet identifier currently belongs to, to tell apart flows that used the same packet
+    /// identifier at different times, such as before and after reconnecting. `None` if there is no such flow in
+    /// [flight](ClientSession::in_flight).
+    pub fn flow_id(&self, direction: Direction, packet_identifier: u16) -> Option<u64> {
+        match direction {
+            Direction::Outgoing => self.in_flight.flow_id(packet_identifier),
+            Direction::Incoming => self.awaiting_pubrel.flow_id(packet_identifier),
+        }
+    }
+
+    /// Whether the incoming QoS 2 message with this packet identifier has been received, but not released yet.
+    pub fn is_awaiting_pubrel(&self, packet_identifier: u16) -> bool {
+        self.awaiting_pubrel.contains(packet_identifier)
+    }
+
+    /// Completes the flow of an incoming QoS 2 message, returning the `PUBCOMP` to send. Its reason code is
+    /// `PacketIdentifierNotFound` if the session wasn't waiting for this `PUBREL`.
+    pub fn on_pubrel(&mut self, pubrel: &Pubrel) -> Result<Pubcomp, MqttError> {
+        let reason_code = match self.awaiting_pubrel.release(pubrel.packet_identifier) {
+            true => ReasonCode::Success,
+            false => ReasonCode::PacketIdentifierNotFound,
+        };
+        Pubcomp::new(pubrel.packet_identifier, reason_code)
+    }
+
+    /// Returns (and forgets) the packet identifiers of all outgoing messages whose flow has completed since the last 
+    /// call, see [SessionConfig::ordered_delivery].
+    pub fn completed(&mut self) -> Vec<u16> {
+        self.in_flight.take_completed()
+    }
+
+    /// Outgoing QoS 1 and 2 messages not completed yet in the order they were sent, followed by the incoming QoS 2
+    /// messages waiting for their `PUBREL` in the order they were received. Ages are as of the last call to
+    /// [ClientSession::advance].
+    pub fn in_flight(&self) -> impl Iterator<Item = InFlightMessage> + '_ {
+        self.in_flight.messages(self.now).chain(self.awaiting_pubrel.messages(self.now))
+    }
+
+    /// Abandons an outgoing QoS 1 message that hasn't been acknowledged yet, so it's neither
+    /// [redelivered](ClientSession::redeliver) nor [completed](ClientSession::completed). A `PUBACK` that arrives for
+    /// it anyway is ignored.
+    ///
+    /// QoS 2 messages can't be cancelled, once sent their flow has to be completed. Returns an error if there is no
+    /// unacknowledged QoS 1 message with this packet identifier.
+    pub fn cancel(&mut self, packet_identifier: u16) -> Result<(), MqttError> {
+        self.in_flight.cancel(packet_identifier)
+    }
+
+    /// Everything to resend when resuming a session on a new connection, in the order it was originally sent. Must be
+    /// called after [ClientSession::on_connack].
+    pub fn redeliver(&mut self) -> Vec<Packet> {
+        let mut packets = self.in_flight.redeliver(self.now);
+        self.stats.retransmissions += packets.len() as u64;
+        for packet in packets.iter_mut() {
+            if let Packet::Publish(publish) = packet {
+                self.topic_aliases.apply(publish);
+            }
+        }
+        packets
+    }
+
+    /// A snapshot of the session's counters.
+    pub fn stats(&self) -> SessionStats {
+        SessionStats { acks_outstanding: self.in_flight.len(), queued: self.queue.len(), ..self.stats.clone() }
+    }
+
+    /// Outgoing topic aliases of the current connection.
+    pub fn topic_aliases(&self) -> &TopicAliasMap {
+        &self.topic_aliases
+    }
+}
+
+impl Default for ClientSession {
+    fn default() -> Self {
+        Self::new(SessionConfig::default())
+    }
+}
+
+/// What a queued message costs in bytes per second, see [RateLimit::bytes_per_second].
+fn queued_len(publish: &Publish) -> usize {
+    publish.topic_name.len() + publish.payload.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packet::{ConnackProperties, PubackProperties, Pingresp, SubscribeProperties, TopicFilter};
+    use crate::types::VariableByteInteger;
+    use crate::session::{Direction, FlowState};
+
+    use crate::test_util::MockClock;
+
+    use super::*;
+
+    fn subscribe(qos: &[QoS]) -> Subscribe {
+        let topic_filter = qos.iter().enumerate().map(|(i, qos)| {
+            let mut filter = TopicFilter::new_unchecked(format!("t/{}", i));
+            filter.maximum_qos = *qos;
+            filter
+        }).collect();
+        Subscribe { packet_identifier: 1, properties: None, topic_filter }
+    }
+
+    fn session_with_maximum_qos(subscribe_qos_policy: SubscribeQoSPolicy, maximum_qos: Option<QoS>) -> ClientSession {
+        let mut session = ClientSession::new(SessionConfig { subscribe_qos_policy, ..Default::default() });
+        let properties = ConnackProperties { maximum_qos, ..Default::default() };
+        session.on_connack(&Connack { session_present: false, reason_code: ReasonCode::Success, properties: Some(properties) });
+        session
+    }
+
+    #[test]
+    fn subscribe_qos_keep() {
+        let mut session = session_with_maximum_qos(SubscribeQoSPolicy::Keep, Some(QoS::AtLeastOnce));
+        let mut packet = subscribe(&[QoS::ExactlyOnce]);
+        session.prepare_subscribe(&mut packet).unwrap();
+        assert_eq!(QoS::ExactlyOnce, packet.topic_filter[0].maximum_qos);
+    }
+
+    #[test]
+    fn subscribe_qos_downgrade() {
+        let mut session = session_with_maximum_qos(SubscribeQoSPolicy::Downgrade, Some(QoS::AtLeastOnce));
+        assert_eq!(QoS::AtLeastOnce, session.maximum_qos());
+
+        let mut packet = subscribe(&[QoS::AtMostOnce, QoS::ExactlyOnce, QoS::AtLeastOnce]);
+        session.prepare_subscribe(&mut packet).unwrap();
+        let granted: Vec<QoS> = packet.topic_filter.iter().map(|f| f.maximum_qos).collect();
+        assert_eq!(vec![QoS::AtMostOnce, QoS::AtLeastOnce, QoS::AtLeastOnce], granted);
+    }
+
+    #[test]
+    fn subscribe_qos_strict() {
+        let mut session = session_with_maximum_qos(SubscribeQoSPolicy::Strict, Some(QoS::AtMostOnce));
+        assert!(session.prepare_subscribe(&mut subscribe(&[QoS::AtMostOnce])).is_ok());
+        assert_eq!(
+            Some(MqttError::Message("Topic filter t/1 asks for a higher QoS than the server's maximum of AtMostOnce".into())),
+            session.prepare_subscribe(&mut subscribe(&[QoS::AtMostOnce, QoS::AtLeastOnce])).err());
+
+        // no maximum announced means QoS 2 is supported
+        let mut session = session_with_maximum_qos(SubscribeQoSPolicy::Strict, None);
+        assert!(session.prepare_subscribe(&mut subscribe(&[QoS::ExactlyOnce])).is_ok());
+    }
+
+    #[test]
+    fn subscriptions() {
+        let mut session = session_with_maximum_qos(SubscribeQoSPolicy::Keep, None);
+        let mut packet = subscribe(&[QoS::ExactlyOnce, QoS::AtLeastOnce, QoS::AtMostOnce]);
+        session.prepare_subscribe(&mut packet).unwrap();
+        assert!(session.subscriptions().is_empty());
+
+        // a SUBACK for another packet changes nothing
+        let reason_codes = vec![ReasonCode::GrantedQoS1, ReasonCode::NotAuthorized, ReasonCode::Success];
+        session.on_suback(&Suback { packet_identifier: 2, properties: None, reason_codes: reason_codes.clone() });
+        assert!(session.subscriptions().is_empty());
+
+        session.on_suback(&Suback { packet_identifier: 1, properties: None, reason_codes });
+        let granted: Vec<_> = session.subscriptions().iter()
+            .map(|s| (s.topic_filter.filter.as_str(), s.topic_filter.maximum_qos, s.granted_qos))
+            .collect();
+        assert_eq!(
+            vec![("t/0", QoS::ExactlyOnce, QoS::AtLeastOnce), ("t/2", QoS::AtMostOnce, QoS::AtMostOnce)],
+            granted);
+
+        // subscribing again replaces the subscription in place
+        let mut again = subscribe(&[QoS::AtLeastOnce]);
+        again.packet_identifier = 3;
+        session.prepare_subscribe(&mut again).unwrap();
+        let reason_codes = vec![ReasonCode::GrantedQoS1];
+        session.on_suback(&Suback { packet_identifier: 3, properties: None, reason_codes });
+        assert_eq!(2, session.subscriptions().len());
+        assert_eq!(QoS::AtLeastOnce, session.subscriptions()[0].topic_filter.maximum_qos);
+
+        let topic_filter = vec!["t/0".into(), "t/2".into()];
+        let unsubscribe = Unsubscribe { packet_identifier: 4, properties: None, topic_filter };
+        session.prepare_unsubscribe(&unsubscribe);
+        let reason_codes = vec![ReasonCode::Success, ReasonCode::NotAuthorized];
+        session.on_unsuback(&Unsuback { packet_identifier: 4, properties: None, reason_codes });
+        let filters: Vec<_> = session.subscriptions().iter().map(|s| s.topic_filter.filter.as_str()).collect();
+        assert_eq!(vec!["t/2"], filters);
+    }
+
+    #[test]
+    fn subscriptions_pending_on_reconnect() {
+        let mut session = session_with_maximum_qos(SubscribeQoSPolicy::Keep, None);
+        session.prepare_subscribe(&mut subscribe(&[QoS::AtMostOnce])).unwrap();
+
+        // the SUBACK can't arrive on the new connection, and the same packet identifier may be used for another one
+        session.on_connack(&connack(None));
+        session.on_suback(&Suback { packet_identifier: 1, properties: None, reason_codes: vec![ReasonCode::Success] });
+        assert!(session.subscriptions().is_empty());
+    }
+
+    #[test]
+    fn resubscribe_packets() {
+        let mut session = session_with_maximum_qos(SubscribeQoSPolicy::Keep, None);
+        let mut identified = subscribe(&[QoS::AtLeastOnce, QoS::AtLeastOnce]);
+        identified.properties = Some(SubscribeProperties {
+            subscription_identifier: Some(VariableByteInteger { value: 9 }),
+            ..Default::default()
+        });
+        let mut plain = subscribe(&[QoS::AtMostOnce, QoS::AtMostOnce, QoS::AtMostOnce]);
+        plain.packet_identifier = 2;
+        plain.topic_filter.remove(0);
+        for mut packet in [identified, plain] {
+            session.prepare_subscribe(&mut packet).unwrap();
+            let reason_codes = packet.topic_filter.iter().map(|_| ReasonCode::Success).collect();
+            session.on_suback(&Suback { packet_identifier: packet.packet_identifier, properties: None, reason_codes });
+        }
+
+        let mut next = 10;
+        let packets = session.resubscribe_packets(|| { next += 1; next }).unwrap();
+        assert_eq!(2, packets.len());
+        assert_eq!(11, packets[0].packet_identifier);
+        assert_eq!(Some(9), packets[0].properties.as_ref().and_then(|p| p.subscription_identifier).map(|id| id.value));
+        // t/1 was subscribed to again without the identifier
+        assert_eq!(vec!["t/0"], packets[0].topic_filter.iter().map(|f| f.filter.as_str()).collect::<Vec<_>>());
+        assert_eq!(QoS::AtLeastOnce, packets[0].topic_filter[0].maximum_qos);
+        assert_eq!(12, packets[1].packet_identifier);
+        assert_eq!(None, packets[1].properties);
+        assert_eq!(vec!["t/1", "t/2"], packets[1].topic_filter.iter().map(|f| f.filter.as_str()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn resubscribe_packets_within_maximum_packet_size() {
+        let mut session = ClientSession::default();
+        let mut connack = connack(None);
+        connack.properties.as_mut().unwrap().maximum_packet_size = Some(20);
+        session.on_connack(&connack);
+
+        let mut packet = subscribe(&[QoS::AtMostOnce; 5]);
+        session.prepare_subscribe(&mut packet).unwrap();
+        let reason_codes = vec![ReasonCode::Success; 5];
+        session.on_suback(&Suback { packet_identifier: 1, properties: None, reason_codes });
+
+        let mut next = 0;
+        let packets = session.resubscribe_packets(|| { next += 1; next }).unwrap();
+        // 5 bytes plus 6 for each filter
+        assert_eq!(vec![1, 2, 3], packets.iter().map(|p| p.packet_identifier).collect::<Vec<_>>());
+        assert_eq!(vec![2, 2, 1], packets.iter().map(|p| p.topic_filter.len()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn response_information() {
+        let mut session = ClientSession::new(SessionConfig { request_response_information: true, ..Default::default() });
+        let mut connect = Connect::default();
+        session.prepare_connect(&mut connect);
+        assert_eq!(Some(true), connect.properties.and_then(|p| p.request_response_information));
+        assert_eq!(None, session.response_topic("replies"));
+
+        let properties = ConnackProperties { response_information: Some("resp/client-1".into()), ..Default::default() };
+        session.on_connack(&Connack { session_present: false, reason_code: ReasonCode::Success, properties: Some(properties) });
+        assert_eq!(Some("resp/client-1"), session.response_topic_base());
+        assert_eq!(Some("resp/client-1/replies".to_string()), session.response_topic("replies"));
+
+        let mut connect = Connect::default();
+        ClientSession::default().prepare_connect(&mut connect);
+        assert_eq!(None, connect.properties);
+    }
+
+    #[test]
+    fn assigned_client_id() {
+        let assigned = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let hook = {
+            let assigned = assigned.clone();
+            ClientIdHook::new(move |id| assigned.lock().unwrap().push(id.to_string()))
+        };
+        let mut session = ClientSession::new(SessionConfig { on_assigned_client_id: Some(hook), ..Default::default() });
+        assert_eq!(None, session.assigned_client_id());
+
+        let properties = ConnackProperties { assigned_client_identifier: Some("auto-1".into()), ..Default::default() };
+        session.on_connack(&Connack { session_present: false, reason_code: ReasonCode::Success, properties: Some(properties) });
+        assert_eq!(Some("auto-1"), session.assigned_client_id());
+        assert_eq!(vec!["auto-1".to_string()], *assigned.lock().unwrap());
+
+        // reconnecting without a client identifier of its own reuses the assigned one
+        let mut connect = Connect::default();
+        session.prepare_connect(&mut connect);
+        assert_eq!(Some("auto-1".to_string()), connect.client_id);
+        connect.client_id = Some("own".into());
+        session.prepare_connect(&mut connect);
+        assert_eq!(Some("own".to_string()), connect.client_id);
+
+        // a refused connection assigns nothing, one without the property keeps what was assigned before
+        let properties = ConnackProperties { assigned_client_identifier: Some("auto-2".into()), ..Default::default() };
+        let refused = Connack { session_present: false, reason_code: ReasonCode::NotAuthorized, properties: Some(properties) };
+        session.on_connack(&refused);
+        session.on_connack(&Connack { session_present: true, reason_code: ReasonCode::Success, properties: None });
+        assert_eq!(Some("auto-1"), session.assigned_client_id());
+        assert_eq!(1, assigned.lock().unwrap().len());
+    }
+
+    #[test]
+    fn disconnect_session_expiry() {
+        use crate::packet::DisconnectProperties;
+
+        let disconnect = Disconnect {
+            reason_code: ReasonCode::Success,
+            properties: Some(DisconnectProperties { session_expiry_interval: Some(60), ..Default::default() }),
+        };
+        let mut session = ClientSession::default();
+        let mut connect = Connect::default();
+        session.prepare_connect(&mut connect);
+        assert!(matches!(session.encode(disconnect.clone()), Err(MqttError::ProtocolError(_))));
+        assert!(session.encode(Disconnect::default()).is_ok());
+
+        connect.properties = Some(ConnectProperties { session_expiry_interval: Some(10), ..Default::default() });
+        session.prepare_connect(&mut connect);
+        assert!(session.encode(disconnect).is_ok());
+    }
+
+    #[test]
+    fn ordered_delivery() {
+        let mut session = ClientSession::new(SessionConfig { ordered_delivery: true, ..Default::default() });
+        session.prepare_publish(&mut publish("a/b", 1, QoS::ExactlyOnce)).unwrap();
+        session.prepare_publish(&mut publish("a/b", 2, QoS::AtLeastOnce)).unwrap();
+        assert_eq!(2, session.in_flight().count());
+
+        session.on_puback(&Puback::new(2, ReasonCode::Success).unwrap()).unwrap();
+        assert!(session.completed().is_empty());
+
+        let pubrel = session.on_pubrec(&Pubrec::new(1, ReasonCode::Success).unwrap()).unwrap();
+        assert_eq!(Some(1), pubrel.map(|p| p.packet_identifier));
+        session.on_pubcomp(&Pubcomp::new(1, ReasonCode::Success).unwrap()).unwrap();
+
+        assert_eq!(vec![1, 2], session.completed());
+        assert_eq!(0, session.in_flight().count());
+    }
+
+    #[test]
+    fn in_flight_both_directions() {
+        let mut session = ClientSession::default();
+        session.prepare_publish(&mut publish("out", 1, QoS::AtLeastOnce)).unwrap();
+        session.on_incoming_publish(&publish("in", 1, QoS::ExactlyOnce));
+
+        let messages: Vec<(u16, Direction, FlowState, String)> = session.in_flight()
+            .map(|m| (m.packet_identifier, m.direction, m.state, m.topic))
+            .collect();
+        assert_eq!(vec![
+            (1, Direction::Outgoing, FlowState::AwaitingPuback, "out".to_string()),
+            (1, Direction::Incoming, FlowState::AwaitingPubrel, "in".to_string()),
+        ], messages);
+
+        session.cancel(1).unwrap();
+        assert!(session.redeliver().is_empty());
+        session.on_puback(&Puback::new(1, ReasonCode::Success).unwrap()).unwrap();
+        assert!(session.completed().is_empty());
+        assert_eq!(1, session.in_flight().count());
+    }
+
+    #[test]
+    fn follow_redirect() {
+        let redirect = Redirect {
+            reason_code: ReasonCode::UseAnotherServer,
+            references: vec!["other:1884".parse().unwrap()],
+        };
+        let mut session = ClientSession::default();
+        assert_eq!(None, session.follow_redirect(&redirect));
+
+        let mut session = ClientSession::new(SessionConfig { redirect_policy: RedirectPolicy::Follow(2), ..Default::default() });
+        assert!(session.follow_redirect(&redirect).is_some());
+        assert!(session.follow_redirect(&redirect).is_some());
+        assert_eq!(None, session.follow_redirect(&redirect));
+
+        session.on_connack(&Connack { session_present: false, reason_code: ReasonCode::Success, properties: None });
+        assert_eq!(Some(&redirect.references[0]), session.follow_redirect(&redirect));
+        assert_eq!(None, session.follow_redirect(&Redirect { references: vec![], ..redirect.clone() }));
+    }
+
+    #[test]
+    fn decode_error_to_disconnect() {
+        let mut session = ClientSession::default();
+        assert!(matches!(session.decode(&Pingresp::BYTES), Ok(Packet::Pingresp(_))));
+
+        // PUBLISH with QoS 3
+        let disconnect = session.decode(&[0b00110110, 6, 0, 1, 97, 0, 1, 0]).unwrap_err();
+        assert_eq!(ReasonCode::MalformedPacket, disconnect.reason_code);
+        assert_eq!(
+            Some("Malformed Packet: illegal value for QoS: 3".to_string()), 
+            disconnect.properties.and_then(|p| p.reason_string));
+    }
+
+    #[test]
+    fn decode_malformed_to_disconnect() {
+        let malformed: [&[u8]; 5] = [
+            // undefined packet type
+            &[0, 0],
+            // PUBLISH with a topic name of 5 bytes, but only one there
+            &[0b00110000, 3, 0, 5, 97],
+            // CONNACK with authentication data of 5 bytes, but only one there
+            &[0b00100000, 7, 0, 0, 4, 0x16, 0, 5, 1],
+            // PUBLISH with a topic name that isn't valid UTF-8
+            &[0b00110000, 5, 0, 2, 0xC3, 0x28, 0],
+            // PUBACK with undefined reason code 0x05
+            &[0b01000000, 3, 0, 1, 5],
+        ];
+        for src in malformed {
+            let disconnect = ClientSession::default().decode(src).unwrap_err();
+            assert_eq!(ReasonCode::MalformedPacket, disconnect.reason_code, "{:?}: {:?}", src, disconnect.properties);
+        }
+    }
+
+    #[test]
+    fn decode_within_incoming_maximum_packet_size() {
+        let mut session = ClientSession::default();
+        let mut connect = Connect::default();
+        connect.properties = Some(ConnectProperties { maximum_packet_size: Some(64), ..Default::default() });
+        session.prepare_connect(&mut connect);
+        assert_eq!(Some(64), session.incoming_maximum_packet_size());
+
+        // only the fixed header of a PUBLISH with 16,384 bytes to follow has arrived
+        let disconnect = session.decode(&[0x30, 0x80, 0x80, 0x01]).unwrap_err();
+        assert_eq!(ReasonCode::PacketTooLarge, disconnect.reason_code);
+        assert_eq!(
+            Some("Packet Too Large: PUBLISH of 16388 bytes exceeds the maximum of 64".to_string()),
+            disconnect.properties.and_then(|p| p.reason_string));
+        assert!(session.decode(&Pingresp::BYTES).is_ok());
+    }
+
+    #[test]
+    fn unsolicited_packets() {
+        use std::io::{Read, Write};
+        use crate::{packet::{Connect, DisconnectProperties, PacketIter, Pingreq}, test_util::MockBroker};
+
+        let disconnect = Disconnect {
+            reason_code: ReasonCode::ProtocolError,
+            properties: Some(DisconnectProperties {
+                reason_string: Some("Protocol Error: Server sent a second CONNACK".into()),
+                ..Default::default()
+            }),
+        };
+        let (mut client, broker) = MockBroker::new()
+            .expect(PacketType::CONNECT)
+            .send(connack(None))
+            .send(Pingresp {})
+            .send(connack(None))
+            .expect_packet(disconnect)
+            .spawn();
+
+        let mut session = ClientSession::default();
+        client.write_all(&Vec::try_from(Connect::default()).unwrap()).unwrap();
+
+        // CONNACK, PINGRESP and the second CONNACK
+        let mut inbound = Vec::new();
+        while PacketIter::new(&inbound).count() < 3 {
+            let mut buf = [0; 64];
+            let n = client.read(&mut buf).unwrap();
+            inbound.extend_from_slice(&buf[..n]);
+        }
+        let mut packets = PacketIter::new(&inbound).map(|p| p.unwrap().0);
+        match packets.next() {
+            Some(Packet::Connack(connack)) => session.on_connack(&connack),
+            other => panic!("expected CONNACK, got {:?}", other),
+        }
+
+        assert_eq!(Ok(()), session.check_unsolicited(&packets.next().unwrap()));
+        let disconnect = session.check_unsolicited(&packets.next().unwrap()).unwrap_err();
+        client.write_all(&Vec::try_from(disconnect).unwrap()).unwrap();
+        broker.join().unwrap();
+
+        for packet in [Packet::Pingreq(Pingreq {}), Packet::from(subscribe(&[QoS::AtMostOnce]))] {
+            assert_eq!(ReasonCode::ProtocolError, session.check_unsolicited(&packet).unwrap_err().reason_code);
+        }
+    }
+
+    fn reauth_connect(method: &str) -> Connect {
+        let mut connect = Connect::default();
+        connect.properties = Some(ConnectProperties { authentication_method: Some(method.into()), ..Default::default() });
+        connect
+    }
+
+    fn auth(reason_code: ReasonCode, method: &str, data: Option<&[u8]>) -> Auth {
+        let properties = AuthProperties {
+            authentication_method: Some(method.into()),
+            authentication_data: data.map(<[u8]>::to_vec),
+            ..Default::default()
+        };
+        Auth { reason_code, properties: Some(properties) }
+    }
+
+    /// Reads the next packet from the mock broker.
+    fn receive(client: &mut crate::transport::MemoryTransport, inbound: &mut Vec<u8>) -> Packet {
+        use std::io::Read;
+        loop {
+            if let Some(result) = crate::packet::PacketIter::new(inbound).next() {
+                let (packet, len) = result.unwrap();
+                inbound.drain(..len);
+                return packet
+            }
+            let mut buf = [0; 64];
+            let n = client.read(&mut buf).unwrap();
+            assert!(n > 0, "connection closed");
+            inbound.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    #[test]
+    fn reauthenticate() {
+        use std::io::Write;
+        use crate::test_util::MockBroker;
+
+        let (mut client, broker) = MockBroker::new()
+            .expect(PacketType::CONNECT)
+            .send(connack(None))
+            .expect_packet(auth(ReasonCode::ReAuthenticate, "token", Some(b"first")))
+            .send(auth(ReasonCode::ContinueAuthentication, "token", Some(b"nonce")))
+            .expect_packet(auth(ReasonCode::ContinueAuthentication, "token", Some(b"ecnon")))
+            .send(auth(ReasonCode::Success, "token", None))
+            .spawn();
+
+        let mut session = ClientSession::default();
+        let mut connect = reauth_connect("token");
+        session.prepare_connect(&mut connect);
+        client.write_all(&Vec::try_from(connect).unwrap()).unwrap();
+
+        let mut inbound = Vec::new();
+        match receive(&mut client, &mut inbound) {
+            Packet::Connack(connack) => session.on_connack(&connack),
+            other => panic!("expected CONNACK, got {:?}", other),
+        }
+
+        let auth = session.reauthenticate(Some(b"first".to_vec())).unwrap();
+        assert!(session.reauthenticate(None).is_err());
+        client.write_all(&Vec::try_from(auth).unwrap()).unwrap();
+        loop {
+            let auth = match receive(&mut client, &mut inbound) {
+                Packet::Auth(auth) => auth,
+                other => panic!("expected AUTH, got {:?}", other),
+            };
+            match session.on_auth(&auth).unwrap() {
+                ReauthStep::Challenge(data) => {
+                    let response = data.map(|mut d| { d.reverse(); d });
+                    client.write_all(&Vec::try_from(session.respond_auth(response).unwrap()).unwrap()).unwrap();
+                },
+                ReauthStep::Done(data) => {
+                    assert_eq!(None, data);
+                    break
+                },
+            }
+        }
+        assert!(!session.is_reauthenticating());
+        broker.join().unwrap();
+    }
+
+    #[test]
+    fn reauthenticate_bad_authentication_method() {
+        use std::io::Write;
+        use crate::test_util::MockBroker;
+
+        let refused = Disconnect { reason_code: ReasonCode::BadAuthenticationMethod, properties: None };
+        let (mut client, broker) = MockBroker::new()
+            .expect(PacketType::CONNECT)
+            .send(connack(None))
+            .expect(PacketType::AUTH)
+            .send(auth(ReasonCode::ContinueAuthentication, "other", None))
+            .expect_packet(MqttError::ProtocolError(
+                "Authentication method mismatch, expected Some(\"token\") but got Some(\"other\")".into()).to_disconnect())
+            .expect(PacketType::CONNECT)
+            .send(connack(None))
+            .expect(PacketType::AUTH)
+            .send(refused.clone())
+            .spawn();
+
+        let mut session = ClientSession::default();
+        let mut inbound = Vec::new();
+        for _ in 0..2 {
+            let mut connect = reauth_connect("token");
+            session.prepare_connect(&mut connect);
+            client.write_all(&Vec::try_from(connect).unwrap()).unwrap();
+            match receive(&mut client, &mut inbound) {
+                Packet::Connack(connack) => session.on_connack(&connack),
+                other => panic!("expected CONNACK, got {:?}", other),
+            }
+            assert!(!session.is_reauthenticating());
+
+            let auth = session.reauthenticate(None).unwrap();
+            client.write_all(&Vec::try_from(auth).unwrap()).unwrap();
+            match receive(&mut client, &mut inbound) {
+                // the server switched methods
+                Packet::Auth(auth) => {
+                    let disconnect = session.on_auth(&auth).unwrap_err().to_disconnect();
+                    client.write_all(&Vec::try_from(disconnect).unwrap()).unwrap();
+                },
+                // the server refused the method the client connected with
+                Packet::Disconnect(disconnect) => assert_eq!(refused, disconnect),
+                other => panic!("expected AUTH or DISCONNECT, got {:?}", other),
+            }
+        }
+        broker.join().unwrap();
+
+        // no AUTH without re-authenticating, nor re-authenticating without an authentication method
+        session.prepare_connect(&mut Connect::default());
+        assert!(session.on_auth(&auth(ReasonCode::Success, "token", None)).is_err());
+        assert!(session.reauthenticate(None).is_err());
+        assert!(session.respond_auth(None).is_err());
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        let mut session = ClientSession::default();
+        let mut packet = subscribe(&[QoS::ExactlyOnce, QoS::AtLeastOnce]);
+        session.prepare_subscribe(&mut packet).unwrap();
+        let reason_codes = vec![ReasonCode::GrantedQoS2, ReasonCode::GrantedQoS1];
+        session.on_suback(&Suback { packet_identifier: 1, properties: None, reason_codes });
+        let properties = ConnackProperties { assigned_client_identifier: Some("auto-1".into()), ..Default::default() };
+        session.on_connack(&Connack { session_present: false, reason_code: ReasonCode::Success, properties: Some(properties) });
+        session.on_incoming_publish(&publish("t/0", 5, QoS::ExactlyOnce));
+        session.on_incoming_publish(&publish("t/1", 6, QoS::AtLeastOnce));
+
+        let snapshot = session.snapshot();
+        assert_eq!(Some("auto-1".to_string()), snapshot.client_id);
+        assert_eq!(session.subscriptions(), &snapshot.subscriptions[..]);
+        assert_eq!(vec![(5, "t/0".to_string())], snapshot.awaiting_pubrel);
+        #[cfg(feature = "serde")]
+        assert_eq!(snapshot, serde_json::from_str(&serde_json::to_string(&snapshot).unwrap()).unwrap());
+
+        let mut restored = ClientSession::default();
+        restored.restore(snapshot.clone());
+        assert_eq!(snapshot, restored.snapshot());
+        assert!(restored.is_awaiting_pubrel(5));
+        assert_eq!(Some(1), restored.flow_id(Direction::Incoming, 5));
+
+        let mut connect = Connect::default();
+        connect.clean_start = false;
+        restored.prepare_connect(&mut connect);
+        assert_eq!(Some("auto-1".to_string()), connect.client_id);
+
+        // the server lost the session: the subscriptions are to be made again, the flows are gone
+        restored.on_connack(&Connack { session_present: false, reason_code: ReasonCode::Success, properties: None });
+        assert_eq!(2, restored.resubscribe_packets(|| 1).unwrap()[0].topic_filter.len());
+        assert!(!restored.is_awaiting_pubrel(5));
+    }
+
+    #[test]
+    fn stats() {
+        let mut session = ClientSession::default();
+        session.on_packet_sent(PacketType::CONNECT, 13);
+        session.decode(&[0b00100000, 3, 0, 0, 0]).unwrap();
+
+        session.prepare_publish(&mut publish("a/b", 1, QoS::AtLeastOnce)).unwrap();
+        session.on_packet_sent(PacketType::PUBLISH, 9);
+        session.prepare_publish(&mut publish("a/b", 2, QoS::AtLeastOnce)).unwrap();
+        session.on_packet_sent(PacketType::PUBLISH, 9);
+        match session.decode(&[0b01000000, 2, 0, 1]) {
+            Ok(Packet::Puback(puback)) => session.on_puback(&puback).unwrap(),
+            other => panic!("expected PUBACK, got {:?}", other),
+        }
+        session.redeliver();
+
+        let stats = session.stats();
+        assert_eq!(3, stats.packets_sent.total());
+        assert_eq!(2, stats.packets_sent.get(PacketType::PUBLISH));
+        assert_eq!(31, stats.bytes_sent);
+        assert_eq!(1, stats.packets_received.get(PacketType::CONNACK));
+        assert_eq!(1, stats.packets_received.get(PacketType::PUBACK));
+        assert_eq!(9, stats.bytes_received);
+        assert_eq!(1, stats.retransmissions);
+        assert_eq!(1, stats.acks_outstanding);
+        assert_eq!(2, stats.flows);
+    }
+
+    #[test]
+    fn flow_ids() {
+        let mut session = ClientSession::default();
+        session.prepare_publish(&mut publish("a", 1, QoS::AtMostOnce)).unwrap();
+        session.prepare_publish(&mut publish("a", 1, QoS::AtLeastOnce)).unwrap();
+        assert!(session.prepare_publish(&mut publish("a", 1, QoS::ExactlyOnce)).is_err());
+        assert_eq!(Some(1), session.flow_id(Direction::Outgoing, 1));
+
+        let mut incoming = publish("b", 1, QoS::ExactlyOnce);
+        session.on_incoming_publish(&incoming);
+        incoming.dup = true;
+        session.on_incoming_publish(&incoming);
+        assert_eq!(Some(2), session.flow_id(Direction::Incoming, 1));
+        assert_eq!(2, session.stats().flows);
+
+        // the same packet identifier after reconnecting without a session is another flow
+        session.on_connack(&Connack { session_present: false, reason_code: ReasonCode::Success, properties: None });
+        assert_eq!(None, session.flow_id(Direction::Outgoing, 1));
+        session.prepare_publish(&mut publish("a", 1, QoS::AtLeastOnce)).unwrap();
+        assert_eq!(Some(3), session.flow_id(Direction::Outgoing, 1));
+        assert_eq!(vec![3], session.in_flight().map(|m| m.flow_id).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn clock() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let mut session = ClientSession::new(SessionConfig {
+            keep_alive: Some(10),
+            clock: Some(Arc::new(clock.clone())),
+            ..Default::default()
+        });
+        session.on_connack(&connack(None));
+        assert_eq!(Some(start + Duration::from_secs(10)), session.next_due());
+
+        clock.advance(Duration::from_secs(4));
+        session.on_packet_received(PacketType::PUBLISH, 10);
+        assert_eq!(Some(start + Duration::from_secs(4)), session.stats().last_received);
+
+        clock.advance(Duration::from_secs(6));
+        assert!(matches!(session.advance(session.clock().now())[..], [DueAction::SendPingreq]));
+        session.on_packet_sent(PacketType::PINGREQ, 2);
+        assert_eq!(Some(start + Duration::from_secs(10)), session.stats().last_sent);
+        assert_eq!(Some(start + Duration::from_secs(20)), session.next_due());
+    }
+
+    #[test]
+    fn decode_received() {
+        let clock = MockClock::new();
+        let mut session = ClientSession::new(SessionConfig { clock: Some(Arc::new(clock.clone())), ..Default::default() });
+        session.on_connack(&connack(None));
+
+        clock.advance(Duration::from_secs(3));
+        let sent = clock.system_time() - Duration::from_millis(250);
+        let publish = Publish::builder("a").expiry(Duration::from_secs(10)).build().unwrap();
+        let received = session.decode_received(&Vec::try_from(publish).unwrap()).unwrap();
+        assert_eq!(Timestamp { instant: clock.now(), system_time: clock.system_time() }, received.timestamp);
+
+        let received = received.map(|packet| match packet {
+            Packet::Publish(publish) => publish,
+            other => panic!("expected PUBLISH, got {:?}", other),
+        });
+        assert_eq!(Some(Duration::from_millis(250)), received.latency_since(sent));
+        assert_eq!(None, received.latency_since(clock.system_time() + Duration::from_secs(1)));
+
+        // expiry counts from when the message arrived, not from when it's looked at
+        clock.advance(Duration::from_millis(3500));
+        assert_eq!(Some(clock.now() + Duration::from_millis(6500)), received.expires_at());
+        assert_eq!(Some(6), received.remaining_expiry(clock.now()));
+        assert!(!received.is_expired(clock.now()));
+        clock.advance(Duration::from_millis(6500));
+        assert_eq!(Some(0), received.remaining_expiry(clock.now()));
+        assert!(received.is_expired(clock.now()));
+
+        let encoded = Vec::try_from(Publish::new_unchecked("a".into(), "")).unwrap();
+        let received = session.decode_received(&encoded).unwrap();
+        let received = received.map(|packet| match packet {
+            Packet::Publish(publish) => publish,
+            other => panic!("expected PUBLISH, got {:?}", other),
+        });
+        assert_eq!(None, received.expires_at());
+        assert_eq!(None, received.remaining_expiry(clock.now()));
+        assert!(!received.is_expired(clock.now() + Duration::from_secs(u32::MAX.into())));
+    }
+
+    #[test]
+    fn redeliver_applies_new_aliases() {
+        let mut session = ClientSession::new(SessionConfig { topic_alias_policy: TopicAliasPolicy::Lru(5), ..Default::default() });
+        session.on_connack(&connack(Some(5)));
+
+        let mut first = publish("a/b", 1, QoS::AtLeastOnce);
+        session.prepare_publish(&mut first).unwrap();
+        let mut second = publish("a/b", 2, QoS::AtLeastOnce);
+        session.prepare_publish(&mut second).unwrap();
+        assert!(second.topic_name.is_empty());
+
+        // the new connection doesn't allow aliases, so both must carry the full topic again
+        session.on_connack(&connack(None));
+        for packet in session.redeliver() {
+            match packet {
+                Packet::Publish(publish) => {
+                    assert!(publish.dup);
+                    assert_eq!("a/b", publish.topic_name);
+                },
+                other => panic!("expected PUBLISH, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn session_not_present() {
+        let mut session = ClientSession::default();
+        session.on_connack(&connack(None));
+        session.prepare_publish(&mut publish("a", 1, QoS::AtLeastOnce)).unwrap();
+        session.prepare_publish(&mut publish("a", 2, QoS::ExactlyOnce)).unwrap();
+        session.on_incoming_publish(&publish("b", 7, QoS::ExactlyOnce));
+
+        // resumed
+        session.on_connack(&connack(None));
+        assert_eq!(3, session.in_flight().count());
+
+        // refused, the state is kept for the next attempt
+        session.on_connack(&Connack { session_present: false, reason_code: ReasonCode::ServerBusy, properties: None });
+        assert_eq!(3, session.in_flight().count());
+
+        // the server has lost the session
+        session.on_connack(&Connack { session_present: false, reason_code: ReasonCode::Success, properties: None });
+        assert_eq!(0, session.in_flight().count());
+        assert!(session.redeliver().is_empty());
+        assert!(!session.is_awaiting_pubrel(7));
+        assert_eq!(0, session.stats().acks_outstanding);
+
+        // the packet identifiers are free again, and late acknowledgements from the old session are errors
+        assert!(session.on_puback(&Puback::new(1, ReasonCode::Success).unwrap()).is_err());
+        session.prepare_publish(&mut publish("a", 1, QoS::AtLeastOnce)).unwrap();
+        assert_eq!(vec![1], session.in_flight().map(|m| m.packet_identifier).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn retransmit_after_ack_timeout() {
+        let timeout = Duration::from_secs(20);
+        let mut session = ClientSession::new(SessionConfig { ack_timeout: Some(timeout), ..Default::default() });
+        let start = Instant::now();
+        session.advance(start);
+        assert_eq!(None, session.next_due());
+
+        session.prepare_publish(&mut publish("a/b", 1, QoS::AtLeastOnce)).unwrap();
+        session.advance(start + Duration::from_secs(5));
+        session.prepare_publish(&mut publish("a/b", 2, QoS::ExactlyOnce)).unwrap();
+        assert_eq!(Some(start + timeout), session.next_due());
+
+        assert!(session.advance(start + Duration::from_secs(19)).is_empty());
+        match &session.advance(start + timeout)[..] {
+            [DueAction::Retransmit(packet)] => assert!(matches!(**packet, Packet::Publish(ref p) if p.dup && p.packet_identifier == Some(1))),
+            other => panic!("expected a retransmission, got {:?}", other),
+        }
+        assert_eq!(Some(start + Duration::from_secs(25)), session.next_due());
+
+        session.on_puback(&Puback::new(1, ReasonCode::Success).unwrap()).unwrap();
+        session.on_pubrec(&Pubrec::new(2, ReasonCode::Success).unwrap()).unwrap();
+        assert_eq!(Some(start + Duration::from_secs(40)), session.next_due());
+        match &session.advance(start + Duration::from_secs(40))[..] {
+            [DueAction::Retransmit(packet)] => assert!(matches!(**packet, Packet::Pubrel(ref p) if p.packet_identifier == 2)),
+            other => panic!("expected a retransmission, got {:?}", other),
+        }
+        assert_eq!(2, session.stats().retransmissions);
+    }
+
+    #[test]
+    fn keep_alive() {
+        let mut session = ClientSession::new(SessionConfig { keep_alive: Some(30), ..Default::default() });
+        let start = Instant::now();
+        session.advance(start);
+        session.on_packet_sent(PacketType::CONNECT, 13);
+        session.on_connack(&connack(None));
+        assert_eq!(Some(start + Duration::from_secs(30)), session.next_due());
+
+        session.advance(start + Duration::from_secs(10));
+        session.on_packet_sent(PacketType::PUBLISH, 9);
+        assert!(session.advance(start + Duration::from_secs(39)).is_empty());
+        assert!(matches!(session.advance(start + Duration::from_secs(40))[..], [DueAction::SendPingreq]));
+
+        session.on_packet_sent(PacketType::PINGREQ, 2);
+        assert_eq!(Some(start + Duration::from_secs(70)), session.next_due());
+    }
+
+    #[test]
+    fn server_keep_alive() {
+        let mut session = ClientSession::new(SessionConfig { keep_alive: Some(30), ..Default::default() });
+        let start = Instant::now();
+        session.advance(start);
+
+        let mut properties = ConnackProperties::default();
+        properties.server_keep_alive = Some(0);
+        session.on_connack(&Connack { session_present: false, reason_code: ReasonCode::Success, properties: Some(properties) });
+        assert_eq!(None, session.next_due());
+        assert!(session.advance(start + Duration::from_secs(3600)).is_empty());
+        assert!(session.negotiated().is_some_and(NegotiatedSession::is_keep_alive_disabled_by_server));
+    }
+
+    fn released(actions: Vec<DueAction>) -> Vec<String> {
+        actions.into_iter().map(|action| match action {
+            DueAction::Publish(publish) => publish.topic_name,
+            other => panic!("expected a queued message, got {:?}", other),
+        }).collect()
+    }
+
+    #[test]
+    fn maximum_packet_size() {
+        let mut session = ClientSession::new(SessionConfig {
+            topic_alias_policy: TopicAliasPolicy::Lru(10),
+            ..Default::default()
+        });
+        let mut limited = connack(Some(10));
+        limited.properties.as_mut().unwrap().maximum_packet_size = Some(32);
+        session.on_connack(&limited);
+        assert_eq!(Some(32), session.maximum_packet_size());
+
+        let mut small = publish("a", 1, QoS::AtLeastOnce);
+        session.prepare_publish(&mut small).unwrap();
+        assert!(session.encode(small).is_ok());
+
+        // neither in flight nor known by its alias after failing
+        let mut large = Publish::new_unchecked("b".into(), vec![0; 32]);
+        large.qos_level = QoS::ExactlyOnce;
+        large.packet_identifier = Some(2);
+        session.prepare_publish(&mut large).unwrap();
+        assert_eq!(Some(2), session.topic_aliases().get("b"));
+        assert!(matches!(
+            session.encode(large),
+            Err(MqttError::PacketTooLarge { packet_type: PacketType::PUBLISH, maximum: 32, .. })));
+        assert_eq!(vec![1], session.in_flight().map(|m| m.packet_identifier).collect::<Vec<_>>());
+        assert_eq!(None, session.topic_aliases().get("b"));
+
+        let mut puback = Puback::new(3, ReasonCode::Success).unwrap();
+        puback.properties = Some(PubackProperties { reason_string: Some("x".repeat(32)), ..Default::default() });
+        let encoded = session.encode(puback).unwrap();
+        assert_eq!(None, Puback::try_from(&encoded[..]).unwrap().properties.and_then(|p| p.reason_string));
+
+        // unlimited on a new connection that doesn't say otherwise
+        session.on_connack(&connack(None));
+        assert_eq!(None, session.maximum_packet_size());
+        assert!(session.encode(Publish::new_unchecked("b".into(), vec![0; 1000])).is_ok());
+    }
+
+    #[test]
+    fn queue() {
+        let mut session = ClientSession::default();
+        let start = Instant::now();
+        session.advance(start);
+        assert_eq!(None, session.next_due());
+
+        assert!(session.enqueue(Publish::new_unchecked("a".into(), "1")));
+        assert!(session.enqueue(Publish::new_unchecked("b".into(), "2")));
+        assert_eq!(2, session.stats().queued);
+        assert_eq!(Some(start), session.next_due());
+
+        // without a rate limit, everything goes out at once
+        assert_eq!(vec!["a", "b"], released(session.advance(start)));
+        assert_eq!(0, session.stats().queued);
+        assert_eq!(None, session.next_due());
+    }
+
+    #[test]
+    fn rate_limit() {
+        let rate_limit = RateLimit { messages_per_second: Some(2), bytes_per_second: None };
+        let mut session = ClientSession::new(SessionConfig {
+            rate_limit: Some(rate_limit),
+            queue_limit: Some(3),
+            ..Default::default()
+        });
+        let start = Instant::now();
+        session.advance(start);
+
+        for topic in ["a", "b", "c"] {
+            assert!(session.enqueue(Publish::new_unchecked(topic.into(), vec![])));
+        }
+        assert!(!session.enqueue(Publish::new_unchecked("d".into(), vec![])));
+        assert_eq!((3, 1), (session.stats().queued, session.stats().dropped));
+
+        assert_eq!(vec!["a", "b"], released(session.advance(start)));
+        assert_eq!(Some(start + Duration::from_millis(500)), session.next_due());
+        assert!(session.advance(start + Duration::from_millis(499)).is_empty());
+        assert_eq!(vec!["c"], released(session.advance(start + Duration::from_millis(500))));
+
+        // room again
+        assert!(session.enqueue(Publish::new_unchecked("e".into(), vec![])));
+        assert_eq!(Some(start + Duration::from_secs(1)), session.next_due());
+        assert_eq!((1, 1), (session.stats().queued, session.stats().dropped));
+    }
+
+    #[test]
+    fn keep_alive_of_connect() {
+        // without a keep alive in the config, the one of the CONNECT applies
+        let mut session = ClientSession::default();
+        let mut connect = Connect::default();
+        connect.keep_alive = 60;
+        session.prepare_connect(&mut connect);
+        assert_eq!(60, connect.keep_alive);
+
+        let start = Instant::now();
+        session.advance(start);
+        session.on_packet_sent(PacketType::CONNECT, 13);
+        session.on_connack(&connack(None));
+        assert_eq!(60, session.negotiated().unwrap().keep_alive());
+        assert_eq!(Some(start + Duration::from_secs(60)), session.next_due());
+        assert!(matches!(session.advance(start + Duration::from_secs(60))[..], [DueAction::SendPingreq]));
+    }
+
+    #[test]
+    fn keep_alive_disabled() {
+        let mut session = ClientSession::new(SessionConfig { keep_alive: Some(0), ..Default::default() });
+        let mut connect = Connect::default();
+        connect.keep_alive = 60;
+        session.prepare_connect(&mut connect);
+        assert_eq!(0, connect.keep_alive);
+
+        let start = Instant::now();
+        session.advance(start);
+        session.on_packet_sent(PacketType::CONNECT, 13);
+        session.on_connack(&connack(None));
+        assert_eq!(None, session.next_due());
+        assert_eq!(None, session.time_until_due(start));
+        for secs in [0, 1, 65_535, 1_000_000] {
+            assert!(session.advance(start + Duration::from_secs(secs)).is_empty());
+        }
+    }
+
+    #[test]
+    fn time_until_due() {
+        let mut session = ClientSession::new(SessionConfig { keep_alive: Some(30), ..Default::default() });
+        let mut connect = Connect::default();
+        session.prepare_connect(&mut connect);
+        assert_eq!(30, connect.keep_alive);
+
+        let start = Instant::now();
+        session.advance(start);
+        session.on_packet_sent(PacketType::CONNECT, 13);
+        session.on_connack(&connack(None));
+        assert_eq!(Some(Duration::from_secs(30)), session.time_until_due(start));
+        assert_eq!(Some(Duration::from_secs(1)), session.time_until_due(start + Duration::from_secs(29)));
+
+        // overdue, but never a zero timeout
+        assert_eq!(Some(Duration::from_millis(1)), session.time_until_due(start + Duration::from_secs(31)));
+    }
+
+    #[test]
+    fn incoming_acknowledgements() {
+        let mut session = ClientSession::default();
+        assert_eq!(None, session.on_incoming_publish(&Publish::new_unchecked("a".into(), vec![])));
+        assert_eq!(
+            Some(Packet::from(Puback::new(3, ReasonCode::Success).unwrap())),
+            session.on_incoming_publish(&publish("a", 3, QoS::AtLeastOnce)));
+
+        assert!(!session.is_awaiting_pubrel(4));
+        let pubrec = Some(Packet::from(Pubrec::new(4, ReasonCode::Success).unwrap()));
+        assert_eq!(pubrec, session.on_incoming_publish(&publish("a", 4, QoS::ExactlyOnce)));
+        assert!(session.is_awaiting_pubrel(4));
+
+        // a redelivery is acknowledged again
+        assert_eq!(pubrec, session.on_incoming_publish(&publish("a", 4, QoS::ExactlyOnce)));
+        assert_eq!(ReasonCode::Success, session.on_pubrel(&Pubrel::new(4, ReasonCode::Success).unwrap()).unwrap().reason_code);
+        assert!(!session.is_awaiting_pubrel(4));
+    }
+
+    #[test]
+    fn pubrel_timeout() {
+        let mut session = ClientSession::new(SessionConfig { pubrel_timeout: Some(Duration::from_secs(60)), ..Default::default() });
+        let start = Instant::now();
+        session.advance(start);
+        session.on_incoming_publish(&publish("a", 7, QoS::ExactlyOnce));
+        session.on_incoming_publish(&publish("a", 8, QoS::ExactlyOnce));
+        session.on_incoming_publish(&publish("a", 9, QoS::AtLeastOnce));
+
+        let pubcomp = session.on_pubrel(&Pubrel::new(8, ReasonCode::Success).unwrap()).unwrap();
+        assert_eq!(ReasonCode::Success, pubcomp.reason_code);
+
+        assert!(matches!(session.advance(start + Duration::from_secs(60))[..], [DueAction::ExpirePubrel(7)]));
+        let pubcomp = session.on_pubrel(&Pubrel::new(7, ReasonCode::Success).unwrap()).unwrap();
+        assert_eq!(ReasonCode::PacketIdentifierNotFound, pubcomp.reason_code);
+        assert_eq!(None, session.next_due());
+    }
+
+    #[test]
+    fn pubrel_timeout_with_keep_alive() {
+        let clock = MockClock::new();
+        let mut session = ClientSession::new(SessionConfig {
+            keep_alive: Some(30),
+            pubrel_timeout: Some(Duration::from_secs(30)),
+            clock: Some(Arc::new(clock.clone())),
+            ..Default::default()
+        });
+        session.on_connack(&connack(None));
+        session.on_incoming_publish(&publish("a", 7, QoS::ExactlyOnce));
+
+        // expiring the PUBREL sends nothing, so the PINGREQ is still due
+        clock.advance(Duration::from_secs(30));
+        assert!(matches!(
+            session.advance(session.clock().now())[..],
+            [DueAction::ExpirePubrel(7), DueAction::SendPingreq]));
+    }
+
+    fn connack(topic_alias_maximum: Option<u16>) -> Connack {
+        let mut properties = ConnackProperties::default();
+        properties.topic_alias_maximum = topic_alias_maximum;
+        Connack { session_present: true, reason_code: ReasonCode::Success, properties: Some(properties) }
+    }
+
+    fn publish(topic: &str, packet_identifier: u16, qos: QoS) -> Publish {
+        let mut publish = Publish::new_unchecked(topic.into(), vec![]);
+        publish.qos_level = qos;
+        publish.packet_identifier = Some(packet_identifier);
+        publish
+    }
+}
@@ -0,0 +1,26 @@
+use std::{fmt::Debug, time::{Instant, SystemTime}};
+
+/// Where the sessions take the time from when they aren't told it: when they're created and when they record a
+/// packet in their [statistics](super::SessionStats). Everything that becomes due, such as keep alive and
+/// retransmissions, is still driven by `advance(now)`, which an event loop would call with the same clock's time.
+///
+/// Tests replace the [SystemClock] with one they advance themselves, like `MockClock` of the `test-util` feature.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// The wall clock time, for [timestamps](super::Timestamp) to compare with those of other machines. The system's
+    /// by default.
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// The system's monotonic clock, the default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
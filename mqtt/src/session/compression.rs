@@ -0,0 +1,215 @@
+use std::{fmt::Debug, sync::Arc};
+
+use crate::{error::MqttError, packet::{Publish, PublishProperties}};
+
+/// A compression algorithm for [PayloadCompression], such as zstd or gzip from whichever crate the application uses.
+pub trait Compressor: Send + Sync {
+
+    /// Identifies the algorithm in the property that marks compressed messages, such as `"zstd"`.
+    fn name(&self) -> &str;
+
+    fn compress(&self, payload: &[u8]) -> Result<Vec<u8>, MqttError>;
+
+    fn decompress(&self, payload: &[u8]) -> Result<Vec<u8>, MqttError>;
+}
+
+/// Compresses the payloads of outgoing messages and decompresses incoming ones, marking compressed messages with a
+/// user property whose value is the [name](Compressor::name) of the algorithm. This is a convention between the
+/// applications on both ends, servers and the protocol itself are left untouched.
+///
+/// Messages whose payload format indicator says they are UTF-8 are sent as they are, since the compressed bytes
+/// wouldn't be. So are messages marked with the property already.
+///
+/// # Examples
+/// ```
+/// use std::sync::Arc;
+/// use mqtt::error::MqttError;
+/// use mqtt::packet::Publish;
+/// use mqtt::session::{Compressor, PayloadCompression};
+///
+/// /// Not much of a compression, just for show.
+/// struct Reverse;
+///
+/// impl Compressor for Reverse {
+///     fn name(&self) -> &str { "reverse" }
+///     fn compress(&self, payload: &[u8]) -> Result<Vec<u8>, MqttError> { Ok(payload.iter().rev().copied().collect()) }
+///     fn decompress(&self, payload: &[u8]) -> Result<Vec<u8>, MqttError> { self.compress(payload) }
+/// }
+///
+/// let compression = PayloadCompression::new(Arc::new(Reverse));
+/// let mut publish = Publish::with_topic_name("a/b".try_into().unwrap(), "abc");
+/// compression.compress(&mut publish).unwrap();
+/// assert_eq!(b"cba", &publish.payload[..]);
+/// assert_eq!(Some("reverse"), publish.properties.as_ref().unwrap().user_property.get("content-encoding"));
+///
+/// compression.decompress(&mut publish).unwrap();
+/// assert_eq!(b"abc", &publish.payload[..]);
+/// ```
+#[derive(Clone)]
+pub struct PayloadCompression {
+    compressor: Arc<dyn Compressor>,
+
+    /// Name of the user property that marks compressed messages. Defaults to `content-encoding`.
+    pub property: String,
+
+    /// Payloads shorter than this are sent uncompressed. Defaults to `0`.
+    pub min_size: usize,
+}
+
+impl PayloadCompression {
+
+    /// The name of the user property used unless another one is set.
+    pub const DEFAULT_PROPERTY: &'static str = "content-encoding";
+
+    pub fn new(compressor: Arc<dyn Compressor>) -> Self {
+        Self { compressor, property: Self::DEFAULT_PROPERTY.to_string(), min_size: 0 }
+    }
+
+    /// Compresses the payload and adds the property, unless the message is to be sent as it is.
+    pub fn compress(&self, publish: &mut Publish) -> Result<(), MqttError> {
+        let properties = publish.properties.as_ref();
+        let utf8 = properties.is_some_and(|p| p.payload_format_indicator == Some(true));
+        let marked = properties.is_some_and(|p| p.user_property.get(&self.property).is_some());
+        if utf8 || marked || publish.payload.len() < self.min_size {
+            return Ok(())
+        }
+
+        publish.payload = self.compressor.compress(&publish.payload)?.into();
+        publish.properties.get_or_insert_with(PublishProperties::default)
+            .user_property.push(self.property.clone(), self.compressor.name().to_string());
+        Ok(())
+    }
+
+    /// Decompresses the payload and removes the property if the message was compressed with this algorithm. Messages
+    /// without the property, or compressed with another algorithm, are left as they are.
+    ///
+    /// Returns a `PayloadFormatInvalid` error if the payload doesn't decompress.
+    pub fn decompress(&self, publish: &mut Publish) -> Result<(), MqttError> {
+        let properties = match publish.properties.as_mut() {
+            Some(p) if p.user_property.get(&self.property) == Some(self.compressor.name()) => p,
+            _ => return Ok(()),
+        };
+
+        let payload = self.compressor.decompress(&publish.payload).map_err(|e| MqttError::PayloadFormatInvalid(
+            format!("Payload doesn't decompress with {}: {}", self.compressor.name(), e)))?;
+        publish.payload = payload.into();
+        properties.user_property = std::mem::take(&mut properties.user_property).into_iter()
+            .filter(|(k, _)| *k != self.property)
+            .collect();
+        Ok(())
+    }
+}
+
+impl Debug for PayloadCompression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PayloadCompression")
+            .field("compressor", &self.compressor.name())
+            .field("property", &self.property)
+            .field("min_size", &self.min_size)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{packet::Packet, session::{ClientSession, SessionConfig}, types::UserProperties};
+
+    use super::*;
+
+    /// Run-length encoding as pairs of count and byte.
+    struct RunLength;
+
+    impl Compressor for RunLength {
+        fn name(&self) -> &str {
+            "rle"
+        }
+
+        fn compress(&self, payload: &[u8]) -> Result<Vec<u8>, MqttError> {
+            let mut result: Vec<u8> = Vec::new();
+            for byte in payload {
+                match result.len() {
+                    len if len >= 2 && result[len - 1] == *byte && result[len - 2] < u8::MAX => result[len - 2] += 1,
+                    _ => result.extend_from_slice(&[1, *byte]),
+                }
+            }
+            Ok(result)
+        }
+
+        fn decompress(&self, payload: &[u8]) -> Result<Vec<u8>, MqttError> {
+            if payload.len() % 2 != 0 {
+                return Err(MqttError::Message("odd length".to_string()))
+            }
+            Ok(payload.chunks(2).flat_map(|pair| std::iter::repeat_n(pair[1], pair[0].into())).collect())
+        }
+    }
+
+    fn compression() -> PayloadCompression {
+        PayloadCompression::new(Arc::new(RunLength))
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut publish = Publish::new_unchecked("a".into(), vec![7; 100]);
+        compression().compress(&mut publish).unwrap();
+        assert_eq!(vec![100, 7], publish.payload);
+        assert_eq!(
+            UserProperties::from([("content-encoding".to_string(), "rle".to_string())]),
+            publish.properties.as_ref().unwrap().user_property);
+
+        // sent already
+        compression().compress(&mut publish).unwrap();
+        assert_eq!(vec![100, 7], publish.payload);
+
+        compression().decompress(&mut publish).unwrap();
+        assert_eq!(vec![7; 100], publish.payload);
+        assert!(publish.properties.unwrap().user_property.is_empty());
+    }
+
+    #[test]
+    fn left_as_they_are() {
+        let mut compression = compression();
+        compression.min_size = 10;
+        let mut publish = Publish::new_unchecked("a".into(), vec![7; 9]);
+        compression.compress(&mut publish).unwrap();
+        assert_eq!(None, publish.properties);
+
+        let mut publish = Publish::new_unchecked("a".into(), vec![b'a'; 20]);
+        publish.properties = Some(PublishProperties { payload_format_indicator: Some(true), ..Default::default() });
+        compression.compress(&mut publish).unwrap();
+        assert_eq!(vec![b'a'; 20], publish.payload);
+
+        let mut publish = Publish::new_unchecked("a".into(), vec![1, 2, 3]);
+        let mut properties = PublishProperties::default();
+        properties.user_property.push("content-encoding".into(), "zstd".into());
+        publish.properties = Some(properties);
+        compression.decompress(&mut publish).unwrap();
+        assert_eq!(vec![1, 2, 3], publish.payload);
+    }
+
+    #[test]
+    fn applied_by_client_session() {
+        let config = SessionConfig { payload_compression: Some(compression()), ..Default::default() };
+        let mut session = ClientSession::new(config);
+
+        let mut publish = Publish::new_unchecked("a".into(), vec![7; 100]);
+        session.prepare_publish(&mut publish).unwrap();
+        assert_eq!(vec![100, 7], publish.payload);
+
+        let encoded: Vec<u8> = publish.try_into().unwrap();
+        match session.decode(&encoded) {
+            Ok(Packet::Publish(publish)) => assert_eq!(vec![7; 100], publish.payload),
+            other => panic!("expected a PUBLISH, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decompress_error() {
+        let mut publish = Publish::new_unchecked("a".into(), vec![1, 2, 3]);
+        let mut properties = PublishProperties::default();
+        properties.user_property.push("content-encoding".into(), "rle".into());
+        publish.properties = Some(properties);
+        assert_eq!(
+            Err(MqttError::PayloadFormatInvalid("Payload doesn't decompress with rle: odd length".to_string())),
+            compression().decompress(&mut publish));
+    }
+}
@@ -0,0 +1,306 @@
+use crate::{
+    error::MqttError,
+    packet::{Auth, AuthProperties, Connack, Connect, Packet, PacketType},
+    types::ReasonCode,
+};
+
+/// Where a [Handshake] currently stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeState {
+    /// `CONNECT` has been sent, nothing received yet.
+    AwaitingConnack,
+    /// The server has asked for more authentication data at least once.
+    Authenticating,
+    /// A `CONNACK` with a success reason code was received.
+    Connected,
+    /// A `CONNACK` with an error reason code was received.
+    Refused(ReasonCode),
+}
+
+/// What the owner of the connection has to do after a packet was handed to a [Handshake].
+#[derive(Debug)]
+pub enum HandshakeStep {
+    /// The server continues the authentication exchange with the contained data. Must be answered with
+    /// [Handshake::respond].
+    Challenge(Option<Vec<u8>>),
+    /// The handshake is over, the `CONNACK` tells whether it was successful.
+    Done(Box<Connack>),
+}
+
+/// What the owner of the connection has to do after an `AUTH` received while
+/// [re-authenticating](super::ClientSession::reauthenticate) was handed to [ClientSession::on_auth].
+///
+/// [ClientSession::on_auth]: super::ClientSession::on_auth
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReauthStep {
+    /// The server continues the exchange with the contained data. Must be answered with
+    /// [ClientSession::respond_auth](super::ClientSession::respond_auth).
+    Challenge(Option<Vec<u8>>),
+    /// The server accepted the client again, with any final data it sent along.
+    Done(Option<Vec<u8>>),
+}
+
+/// The exchange between sending `CONNECT` and receiving the final `CONNACK`, including any number of `AUTH` round
+/// trips in between if the client uses enhanced authentication.
+///
+/// # Examples
+/// ```
+/// use mqtt::packet::{Auth, AuthProperties, Connack, Connect, ConnectProperties, Packet};
+/// use mqtt::session::{Handshake, HandshakeState, HandshakeStep};
+/// use mqtt::types::ReasonCode;
+///
+/// let mut connect = Connect::default();
+/// let mut properties = ConnectProperties::default();
+/// properties.authentication_method = Some("SCRAM-SHA-1".into());
+/// connect.properties = Some(properties);
+///
+/// let mut handshake = Handshake::new(&connect);
+///
+/// let mut challenge = AuthProperties::default();
+/// challenge.authentication_method = Some("SCRAM-SHA-1".into());
+/// challenge.authentication_data = Some(vec![1, 2, 3]);
+/// let auth = Auth { reason_code: ReasonCode::ContinueAuthentication, properties: Some(challenge) };
+///
+/// match handshake.on_packet(Packet::Auth(auth)).unwrap() {
+///     HandshakeStep::Challenge(data) => {
+///         assert_eq!(Some(vec![1, 2, 3]), data);
+///         let _response = handshake.respond(Some(vec![4, 5, 6])).unwrap();
+///     },
+///     other => panic!("expected a challenge, got {:?}", other),
+/// }
+///
+/// let connack = Connack { session_present: false, reason_code: ReasonCode::Success, properties: None };
+/// assert!(matches!(handshake.on_packet(Packet::Connack(connack)), Ok(HandshakeStep::Done(_))));
+/// assert_eq!(HandshakeState::Connected, handshake.state());
+/// ```
+#[derive(Debug)]
+pub struct Handshake {
+    authentication_method: Option<String>,
+    state: HandshakeState,
+}
+
+impl Handshake {
+
+    /// Starts a handshake for a `CONNECT` that is about to be sent. Enhanced authentication is only allowed if it
+    /// has an authentication method.
+    pub fn new(connect: &Connect) -> Self {
+        Self {
+            authentication_method: connect.properties.as_ref().and_then(|p| p.authentication_method.clone()),
+            state: HandshakeState::AwaitingConnack,
+        }
+    }
+
+    pub fn state(&self) -> HandshakeState {
+        self.state
+    }
+
+    /// Checks the first byte received after sending `CONNECT`, before there's a whole packet to decode. A server has to
+    /// answer with `CONNACK` or `AUTH`, anything else means the other side most likely speaks a different protocol,
+    /// such as HTTP on the wrong port, whose response would otherwise fail to decode in confusing ways.
+    ///
+    /// # Examples
+    /// ```
+    /// use mqtt::session::Handshake;
+    ///
+    /// assert!(Handshake::check_first_byte(0x20).is_ok());
+    /// assert_eq!(
+    ///     "Peer does not speak MQTT (got 0x48 'H')",
+    ///     Handshake::check_first_byte(b'H').unwrap_err().to_string());
+    /// ```
+    pub fn check_first_byte(byte: u8) -> Result<(), MqttError> {
+        match PacketType::try_from(byte) {
+            Ok(PacketType::CONNACK | PacketType::AUTH) => Ok(()),
+            _ if byte.is_ascii_graphic() => Err(MqttError::Message(
+                format!("Peer does not speak MQTT (got 0x{:02X} '{}')", byte, byte as char))),
+            _ => Err(MqttError::Message(format!("Peer does not speak MQTT (got 0x{:02X})", byte))),
+        }
+    }
+
+    /// Advances the handshake with a packet received from the server. Only `CONNACK` and `AUTH` are allowed.
+    ///
+    /// A `CONNACK` or `AUTH` with reason code 0x18 (continue authentication) is treated as a challenge, every other
+    /// `CONNACK` ends the handshake.
+    ///
+    /// Returns a `ProtocolError` for any other packet, for challenges if the client didn't ask for enhanced
+    /// authentication or the server switched to a different method, and for anything received after the handshake
+    /// is over.
+    pub fn on_packet(&mut self, packet: Packet) -> Result<HandshakeStep, MqttError> {
+        if matches!(self.state, HandshakeState::Connected | HandshakeState::Refused(_)) {
+            return Err(MqttError::ProtocolError(format!("Handshake already finished, got {:?}", packet)))
+        }
+
+        match packet {
+            Packet::Connack(connack) if connack.reason_code == ReasonCode::ContinueAuthentication => {
+                let data = connack.properties
+                    .map(|p| (p.authentication_method, p.authentication_data))
+                    .unwrap_or_default();
+                self.challenge(data.0, data.1)
+            },
+            Packet::Connack(connack) => {
+                self.state = match connack.reason_code.is_err() {
+                    true => HandshakeState::Refused(connack.reason_code),
+                    false => HandshakeState::Connected,
+                };
+                Ok(HandshakeStep::Done(Box::new(connack)))
+            },
+            Packet::Auth(auth) if auth.reason_code == ReasonCode::ContinueAuthentication => {
+                let data = auth.properties
+                    .map(|p| (p.authentication_method, p.authentication_data))
+                    .unwrap_or_default();
+                self.challenge(data.0, data.1)
+            },
+            Packet::Auth(auth) =>
+                Err(MqttError::ProtocolError(format!("Unexpected AUTH reason code during handshake: {:?}", auth.reason_code))),
+            other =>
+                Err(MqttError::ProtocolError(format!("Expected CONNACK or AUTH, got {:?}", other))),
+        }
+    }
+
+    /// Builds the `AUTH` answering the last [challenge](HandshakeStep::Challenge).
+    ///
+    /// Returns a `ProtocolError` if there is no challenge to answer.
+    pub fn respond(&self, authentication_data: Option<Vec<u8>>) -> Result<Auth, MqttError> {
+        if self.state != HandshakeState::Authenticating {
+            return Err(MqttError::ProtocolError(format!("No authentication challenge to respond to: {:?}", self.state)))
+        }
+
+        let properties = AuthProperties {
+            authentication_method: self.authentication_method.clone(),
+            authentication_data,
+            ..Default::default()
+        };
+
+        Ok(Auth { reason_code: ReasonCode::ContinueAuthentication, properties: Some(properties) })
+    }
+
+    fn challenge(&mut self, method: Option<String>, data: Option<Vec<u8>>) -> Result<HandshakeStep, MqttError> {
+        let expected = match &self.authentication_method {
+            Some(m) => m,
+            None => return Err(MqttError::ProtocolError("Server continued authentication, but CONNECT had no authentication method".to_string())),
+        };
+
+        if method.as_ref() != Some(expected) {
+            return Err(MqttError::ProtocolError(format!("Authentication method mismatch, expected {} but got {:?}", expected, method)))
+        }
+
+        self.state = HandshakeState::Authenticating;
+        Ok(HandshakeStep::Challenge(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{packet::{ConnectProperties, PacketIter}, test_util::from_hex};
+
+    use super::*;
+
+    const METHOD: &str = "test";
+
+    /// Plays the server side from raw bytes, answering every challenge with the data reversed.
+    fn run(handshake: &mut Handshake, server: &[u8]) -> Result<(Vec<Auth>, Connack), MqttError> {
+        let mut sent = Vec::new();
+        for result in PacketIter::new(server) {
+            let (packet, _) = result?;
+            match handshake.on_packet(packet)? {
+                HandshakeStep::Challenge(data) => {
+                    let response = data.map(|mut d| { d.reverse(); d });
+                    sent.push(handshake.respond(response)?);
+                },
+                HandshakeStep::Done(connack) => return Ok((sent, *connack)),
+            }
+        }
+        panic!("server script ended before the handshake")
+    }
+
+    fn connect(method: Option<&str>) -> Connect {
+        let mut connect = Connect::default();
+        if let Some(m) = method {
+            connect.properties = Some(ConnectProperties { authentication_method: Some(m.to_string()), ..Default::default() });
+        }
+        connect
+    }
+
+    #[test]
+    fn plain_connack() {
+        let mut handshake = Handshake::new(&connect(None));
+        let (sent, connack) = run(&mut handshake, &from_hex("20 03 00 00 00").unwrap()).unwrap();
+        assert!(sent.is_empty());
+        assert_eq!(ReasonCode::Success, connack.reason_code);
+        assert_eq!(HandshakeState::Connected, handshake.state());
+    }
+
+    #[test]
+    fn check_first_byte() {
+        assert!(Handshake::check_first_byte(0x20).is_ok());
+        assert!(Handshake::check_first_byte(0xF0).is_ok());
+        assert_eq!(
+            Err(MqttError::Message("Peer does not speak MQTT (got 0x48 'H')".into())),
+            Handshake::check_first_byte(b'H'));
+        assert_eq!(
+            Err(MqttError::Message("Peer does not speak MQTT (got 0x15)".into())),
+            Handshake::check_first_byte(0x15));
+        assert!(Handshake::check_first_byte(0x30).is_err());
+    }
+
+    #[test]
+    fn auth_round_trips() {
+        // AUTH 0x18 [method "test", data 0102], AUTH 0x18 [method "test", data 03], CONNACK success
+        let server = from_hex("
+            f0 0e 18 0c 15 0004 74657374 16 0002 0102
+            f0 0d 18 0b 15 0004 74657374 16 0001 03
+            20 03 00 00 00").unwrap();
+
+        let mut handshake = Handshake::new(&connect(Some(METHOD)));
+        let (sent, connack) = run(&mut handshake, &server).unwrap();
+
+        assert_eq!(ReasonCode::Success, connack.reason_code);
+        assert_eq!(HandshakeState::Connected, handshake.state());
+        assert_eq!(2, sent.len());
+        let first = sent[0].properties.as_ref().unwrap();
+        assert_eq!(Some(METHOD.to_string()), first.authentication_method);
+        assert_eq!(Some(vec![2, 1]), first.authentication_data);
+        assert_eq!(Some(vec![3]), sent[1].properties.as_ref().unwrap().authentication_data);
+    }
+
+    #[test]
+    fn connack_continue_authentication() {
+        // CONNACK 0x18 [method "test", data 0a], CONNACK not authorized
+        let server = from_hex("
+            20 0e 00 18 0b 15 0004 74657374 16 0001 0a
+            20 03 00 87 00").unwrap();
+
+        let mut handshake = Handshake::new(&connect(Some(METHOD)));
+        let (sent, connack) = run(&mut handshake, &server).unwrap();
+
+        assert_eq!(1, sent.len());
+        assert_eq!(ReasonCode::NotAuthorized, connack.reason_code);
+        assert_eq!(HandshakeState::Refused(ReasonCode::NotAuthorized), handshake.state());
+    }
+
+    #[test]
+    fn unrequested_authentication() {
+        let server = from_hex("f0 0e 18 0c 15 0004 74657374 16 0002 0102").unwrap();
+        let mut handshake = Handshake::new(&connect(None));
+        assert_eq!(
+            Some(MqttError::ProtocolError("Server continued authentication, but CONNECT had no authentication method".to_string())),
+            run(&mut handshake, &server).err());
+    }
+
+    #[test]
+    fn method_mismatch() {
+        let server = from_hex("f0 0e 18 0c 15 0004 6f746865 16 0002 0102").unwrap();
+        let mut handshake = Handshake::new(&connect(Some(METHOD)));
+        assert!(matches!(run(&mut handshake, &server), Err(MqttError::ProtocolError(_))));
+    }
+
+    #[test]
+    fn unexpected_packets() {
+        let mut handshake = Handshake::new(&connect(None));
+        assert!(handshake.respond(None).is_err());
+        assert!(handshake.on_packet(Packet::Pingresp(crate::packet::Pingresp {})).is_err());
+
+        run(&mut handshake, &from_hex("20 03 00 00 00").unwrap()).unwrap();
+        let connack = Connack { session_present: false, reason_code: ReasonCode::Success, properties: None };
+        assert!(handshake.on_packet(Packet::Connack(connack)).is_err());
+    }
+}
@@ -0,0 +1,502 @@
+use std::{collections::VecDeque, time::{Duration, Instant}};
+
+use crate::{
+    error::MqttError,
+    packet::{Packet, Publish, Pubrel},
+    types::{QoS, ReasonCode},
+};
+
+/// Where a QoS 1 or 2 `PUBLISH` stands in its acknowledgement flow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowState {
+    /// Outgoing QoS 1, waiting for `PUBACK`.
+    AwaitingPuback,
+    /// Outgoing QoS 2, waiting for `PUBREC`.
+    AwaitingPubrec,
+    /// Outgoing QoS 2, `PUBREL` sent, waiting for `PUBCOMP`.
+    AwaitingPubcomp,
+    /// Outgoing, acknowledged by the server but not yet reported as completed because an earlier message to the same
+    /// topic is still in flight.
+    Resolved,
+    /// Incoming QoS 2, waiting for `PUBREL`.
+    AwaitingPubrel,
+}
+
+/// Which way a message in flight is going.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Sent by the client, waiting for the server.
+    Outgoing,
+    /// Received from the server, waiting for it to release the message.
+    Incoming,
+}
+
+/// A message whose acknowledgement flow hasn't completed yet, see
+/// [ClientSession::in_flight](super::ClientSession::in_flight).
+#[derive(Debug, Clone, PartialEq)]
+pub struct InFlightMessage {
+    pub packet_identifier: u16,
+    pub direction: Direction,
+    pub qos: QoS,
+    pub state: FlowState,
+    /// The full topic name for outgoing messages, the topic name as received for incoming ones, which is empty if the
+    /// server used a topic alias.
+    pub topic: String,
+    /// How long ago the message was first sent or received, retransmissions don't reset it.
+    pub age: Duration,
+    /// Tells the flow apart from others with the same packet identifier, such as one from before reconnecting, see
+    /// [SessionStats::flows](super::SessionStats::flows).
+    pub flow_id: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    packet_identifier: u16,
+    /// The message as it was handed to the session, with its full topic name.
+    publish: Publish,
+    state: FlowState,
+    /// When the message was first sent.
+    tracked_at: Instant,
+    /// When the packet the server has to acknowledge next was last sent.
+    sent_at: Instant,
+    flow_id: u64,
+}
+
+impl Entry {
+
+    /// What to send again to continue the flow: the `PUBLISH` with the `DUP` flag if the server hasn't received it
+    /// yet, the `PUBREL` if it has.
+    fn retransmission(&self) -> Option<Packet> {
+        match self.state {
+            FlowState::AwaitingPuback | FlowState::AwaitingPubrec => {
+                let mut publish = self.publish.clone();
+                publish.dup = true;
+                Some(publish.into())
+            },
+            FlowState::AwaitingPubcomp => Pubrel::new(self.packet_identifier, ReasonCode::Success)
+                .ok()
+                .map(Packet::from),
+            FlowState::Resolved | FlowState::AwaitingPubrel => None,
+        }
+    }
+}
+
+/// Outgoing QoS 1 and 2 messages that have been sent but not completed yet, in the order they were sent.
+#[derive(Debug)]
+pub(crate) struct InFlight {
+    entries: VecDeque<Entry>,
+    ordered: bool,
+    completed: VecDeque<u16>,
+    /// Packet identifiers of cancelled messages whose `PUBACK` may still arrive.
+    cancelled: Vec<u16>,
+}
+
+impl InFlight {
+
+    pub(crate) fn new(ordered: bool) -> Self {
+        InFlight { entries: VecDeque::new(), ordered, completed: VecDeque::new(), cancelled: Vec::new() }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Starts tracking `publish` as the flow with `flow_id`, which is expected to have its full topic name. Does
+    /// nothing for QoS 0.
+    pub(crate) fn track(&mut self, publish: &Publish, now: Instant, flow_id: u64) -> Result<(), MqttError> {
+        let state = match publish.qos_level {
+            QoS::AtMostOnce => return Ok(()),
+            QoS::AtLeastOnce => FlowState::AwaitingPuback,
+            QoS::ExactlyOnce => FlowState::AwaitingPubrec,
+        };
+
+        let packet_identifier = match publish.packet_identifier {
+            Some(id) => id,
+            None => return Err(MqttError::ProtocolError("PUBLISH with QoS > 0 requires a packet identifier".to_string())),
+        };
+
+        if self.entries.iter().any(|e| e.packet_identifier == packet_identifier) {
+            return Err(MqttError::ProtocolError(format!("Packet identifier already in use: {}", packet_identifier)))
+        }
+
+        // from now on, a PUBACK for this identifier belongs to the new message
+        self.cancelled.retain(|id| *id != packet_identifier);
+        self.entries.push_back(
+            Entry { packet_identifier, publish: publish.clone(), state, tracked_at: now, sent_at: now, flow_id });
+        Ok(())
+    }
+
+    /// Stops tracking a QoS 1 message that hasn't been acknowledged yet. Its `PUBACK` is ignored should it arrive after
+    /// all, unless the packet identifier has been used for another message in the meantime.
+    pub(crate) fn cancel(&mut self, packet_identifier: u16) -> Result<(), MqttError> {
+        let index = self.entries.iter()
+            .position(|e| e.packet_identifier == packet_identifier && e.state == FlowState::AwaitingPuback)
+            .ok_or_else(|| MqttError::Message(
+                format!("No unacknowledged QoS 1 message with packet identifier {}", packet_identifier)))?;
+        self.entries.remove(index);
+        self.cancelled.push(packet_identifier);
+        // later messages to the same topic may have been waiting for this one
+        self.release();
+        Ok(())
+    }
+
+    /// Stops tracking a message that was never sent after all, whatever its QoS. The server doesn't know about it, so
+    /// no acknowledgement is expected.
+    pub(crate) fn forget(&mut self, packet_identifier: u16) {
+        let unsent = |e: &Entry| e.packet_identifier == packet_identifier
+            && matches!(e.state, FlowState::AwaitingPuback | FlowState::AwaitingPubrec);
+        if let Some(index) = self.entries.iter().position(unsent) {
+            self.entries.remove(index);
+            self.release();
+        }
+    }
+
+    /// The messages in flight in the order they were sent, with their age at `now`.
+    pub(crate) fn messages(&self, now: Instant) -> impl Iterator<Item = InFlightMessage> + '_ {
+        self.entries.iter().map(move |entry| InFlightMessage {
+            packet_identifier: entry.packet_identifier,
+            direction: Direction::Outgoing,
+            qos: entry.publish.qos_level,
+            state: entry.state,
+            topic: entry.publish.topic_name.clone(),
+            age: now.saturating_duration_since(entry.tracked_at),
+            flow_id: entry.flow_id,
+        })
+    }
+
+    /// The flow of the message with this packet identifier, if it's in flight.
+    pub(crate) fn flow_id(&self, packet_identifier: u16) -> Option<u64> {
+        self.entries.iter().find(|e| e.packet_identifier == packet_identifier).map(|e| e.flow_id)
+    }
+
+    pub(crate) fn on_puback(&mut self, packet_identifier: u16) -> Result<(), MqttError> {
+        if let Some(index) = self.cancelled.iter().position(|id| *id == packet_identifier) {
+            self.cancelled.remove(index);
+            return Ok(())
+        }
+        self.expect(packet_identifier, FlowState::AwaitingPuback, "PUBACK")?.state = FlowState::Resolved;
+        self.release();
+        Ok(())
+    }
+
+    /// Returns the `PUBREL` to send, or `None` if the server rejected the message, which ends its flow.
+    pub(crate) fn on_pubrec(&mut self, packet_identifier: u16, reason_code: ReasonCode, now: Instant) -> Result<Option<Pubrel>, MqttError> {
+        let entry = self.expect(packet_identifier, FlowState::AwaitingPubrec, "PUBREC")?;
+        if reason_code.is_err() {
+            entry.state = FlowState::Resolved;
+            self.release();
+            return Ok(None)
+        }
+
+        entry.state = FlowState::AwaitingPubcomp;
+        entry.sent_at = now;
+        Ok(Some(Pubrel::new(packet_identifier, ReasonCode::Success)?))
+    }
+
+    pub(crate) fn on_pubcomp(&mut self, packet_identifier: u16) -> Result<(), MqttError> {
+        self.expect(packet_identifier, FlowState::AwaitingPubcomp, "PUBCOMP")?.state = FlowState::Resolved;
+        self.release();
+        Ok(())
+    }
+
+    /// Forgets all messages, completed ones included.
+    pub(crate) fn clear(&mut self) {
+        *self = InFlight::new(self.ordered);
+    }
+
+    /// Packet identifiers of completed messages, in the order they have been completed.
+    pub(crate) fn take_completed(&mut self) -> Vec<u16> {
+        self.completed.drain(..).collect()
+    }
+
+    /// Everything that needs to be resent after reconnecting, in the original order: `PUBLISH` with the `DUP` flag
+    /// for messages that haven't been received yet, `PUBREL` for those that have.
+    pub(crate) fn redeliver(&mut self, now: Instant) -> Vec<Packet> {
+        self.entries.iter_mut()
+            .filter_map(|entry| {
+                let packet = entry.retransmission()?;
+                entry.sent_at = now;
+                Some(packet)
+            })
+            .collect()
+    }
+
+    /// Everything that hasn't been acknowledged within `timeout`, in the original order. Restarts the timeout for
+    /// each of them.
+    pub(crate) fn overdue(&mut self, timeout: Duration, now: Instant) -> Vec<Packet> {
+        self.entries.iter_mut()
+            .filter(|entry| entry.sent_at + timeout <= now)
+            .filter_map(|entry| {
+                let packet = entry.retransmission()?;
+                entry.sent_at = now;
+                Some(packet)
+            })
+            .collect()
+    }
+
+    /// When the next message will be [overdue](InFlight::overdue).
+    pub(crate) fn next_due(&self, timeout: Duration) -> Option<Instant> {
+        self.entries.iter()
+            .filter(|entry| entry.state != FlowState::Resolved)
+            .map(|entry| entry.sent_at + timeout)
+            .min()
+    }
+
+    fn expect(&mut self, packet_identifier: u16, state: FlowState, packet: &str) -> Result<&mut Entry, MqttError> {
+        match self.entries.iter_mut().find(|e| e.packet_identifier == packet_identifier) {
+            Some(entry) if entry.state == state => Ok(entry),
+            Some(entry) => Err(MqttError::ProtocolError(
+                format!("Unexpected {} for packet identifier {} in state {:?}", packet, packet_identifier, entry.state))),
+            None => Err(MqttError::ProtocolError(format!("{} for unknown packet identifier {}", packet, packet_identifier))),
+        }
+    }
+
+    /// Moves resolved messages to the completed ones. If ordering is enabled, a message is only completed once all
+    /// earlier messages to the same topic are.
+    fn release(&mut self) {
+        let mut pending_topics: Vec<&str> = Vec::new();
+        let mut released: Vec<usize> = Vec::new();
+
+        for (index, entry) in self.entries.iter().enumerate() {
+            let topic = entry.publish.topic_name.as_str();
+            if entry.state == FlowState::Resolved && !(self.ordered && pending_topics.contains(&topic)) {
+                released.push(index);
+            } else if entry.state != FlowState::Resolved {
+                pending_topics.push(topic);
+            }
+        }
+
+        // removing from the back keeps the remaining indices valid
+        let mut ids: Vec<u16> = released.into_iter().rev()
+            .filter_map(|index| self.entries.remove(index))
+            .map(|entry| entry.packet_identifier)
+            .collect();
+        ids.reverse();
+        self.completed.extend(ids);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn qos0_not_tracked() {
+        let mut in_flight = InFlight::new(true);
+        in_flight.track(&Publish::new_unchecked("a".into(), vec![]), now(), 0).unwrap();
+        assert_eq!(0, in_flight.len());
+    }
+
+    #[test]
+    fn track_errors() {
+        let mut in_flight = InFlight::new(false);
+
+        let mut no_id = publish("a", 1, QoS::AtLeastOnce);
+        no_id.packet_identifier = None;
+        assert!(in_flight.track(&no_id, now(), 0).is_err());
+
+        in_flight.track(&publish("a", 1, QoS::AtLeastOnce), now(), 0).unwrap();
+        assert_eq!(
+            Some(MqttError::ProtocolError("Packet identifier already in use: 1".to_string())),
+            in_flight.track(&publish("b", 1, QoS::ExactlyOnce), now(), 0).err());
+    }
+
+    #[test]
+    fn unordered_completes_immediately() {
+        let mut in_flight = InFlight::new(false);
+        in_flight.track(&publish("a", 1, QoS::AtLeastOnce), now(), 0).unwrap();
+        in_flight.track(&publish("a", 2, QoS::AtLeastOnce), now(), 0).unwrap();
+
+        in_flight.on_puback(2).unwrap();
+        assert_eq!(vec![2], in_flight.take_completed());
+        in_flight.on_puback(1).unwrap();
+        assert_eq!(vec![1], in_flight.take_completed());
+        assert_eq!(0, in_flight.len());
+    }
+
+    #[test]
+    fn ordered_holds_back_out_of_order_acks() {
+        let mut in_flight = InFlight::new(true);
+        in_flight.track(&publish("a", 1, QoS::ExactlyOnce), now(), 0).unwrap();
+        in_flight.track(&publish("a", 2, QoS::AtLeastOnce), now(), 0).unwrap();
+        in_flight.track(&publish("b", 3, QoS::AtLeastOnce), now(), 0).unwrap();
+        in_flight.track(&publish("a", 4, QoS::AtLeastOnce), now(), 0).unwrap();
+
+        // different topic, not affected
+        in_flight.on_puback(3).unwrap();
+        assert_eq!(vec![3], in_flight.take_completed());
+
+        in_flight.on_puback(4).unwrap();
+        in_flight.on_puback(2).unwrap();
+        assert!(in_flight.take_completed().is_empty());
+
+        assert!(in_flight.on_pubrec(1, ReasonCode::Success, now()).unwrap().is_some());
+        assert!(in_flight.take_completed().is_empty());
+
+        in_flight.on_pubcomp(1).unwrap();
+        assert_eq!(vec![1, 2, 4], in_flight.take_completed());
+        assert_eq!(0, in_flight.len());
+    }
+
+    #[test]
+    fn rejected_pubrec_resolves() {
+        let mut in_flight = InFlight::new(true);
+        in_flight.track(&publish("a", 1, QoS::ExactlyOnce), now(), 0).unwrap();
+        in_flight.track(&publish("a", 2, QoS::AtLeastOnce), now(), 0).unwrap();
+        in_flight.on_puback(2).unwrap();
+
+        assert!(in_flight.on_pubrec(1, ReasonCode::QuotaExceeded, now()).unwrap().is_none());
+        assert_eq!(vec![1, 2], in_flight.take_completed());
+    }
+
+    #[test]
+    fn unexpected_acks() {
+        let mut in_flight = InFlight::new(false);
+        in_flight.track(&publish("a", 1, QoS::ExactlyOnce), now(), 0).unwrap();
+
+        assert_eq!(
+            Some(MqttError::ProtocolError("PUBACK for unknown packet identifier 2".to_string())),
+            in_flight.on_puback(2).err());
+        assert_eq!(
+            Some(MqttError::ProtocolError("Unexpected PUBCOMP for packet identifier 1 in state AwaitingPubrec".to_string())),
+            in_flight.on_pubcomp(1).err());
+    }
+
+    #[test]
+    fn cancel() {
+        let mut in_flight = InFlight::new(true);
+        in_flight.track(&publish("a", 1, QoS::AtLeastOnce), now(), 0).unwrap();
+        in_flight.track(&publish("a", 2, QoS::AtLeastOnce), now(), 0).unwrap();
+        in_flight.track(&publish("a", 3, QoS::ExactlyOnce), now(), 0).unwrap();
+        in_flight.on_puback(2).unwrap();
+
+        assert_eq!(
+            Some(MqttError::Message("No unacknowledged QoS 1 message with packet identifier 3".to_string())),
+            in_flight.cancel(3).err());
+        assert!(in_flight.cancel(2).is_err());
+
+        // the held back message no longer waits for the cancelled one
+        in_flight.cancel(1).unwrap();
+        assert_eq!(vec![2], in_flight.take_completed());
+        assert_eq!(1, in_flight.len());
+
+        // a late PUBACK is ignored once
+        in_flight.on_puback(1).unwrap();
+        assert!(in_flight.on_puback(1).is_err());
+        assert!(in_flight.take_completed().is_empty());
+    }
+
+    #[test]
+    fn forget() {
+        let mut in_flight = InFlight::new(true);
+        in_flight.track(&publish("a", 1, QoS::ExactlyOnce), now(), 0).unwrap();
+        in_flight.track(&publish("a", 2, QoS::AtLeastOnce), now(), 0).unwrap();
+        in_flight.on_puback(2).unwrap();
+
+        in_flight.forget(1);
+        assert_eq!(vec![2], in_flight.take_completed());
+        assert_eq!(0, in_flight.len());
+
+        // unlike a cancelled message, there is no acknowledgement to ignore
+        assert!(in_flight.on_pubrec(1, ReasonCode::Success, now()).is_err());
+        in_flight.track(&publish("a", 1, QoS::AtLeastOnce), now(), 0).unwrap();
+    }
+
+    #[test]
+    fn messages() {
+        let start = now();
+        let mut in_flight = InFlight::new(false);
+        in_flight.track(&publish("a", 1, QoS::AtLeastOnce), start, 7).unwrap();
+        in_flight.track(&publish("b", 2, QoS::ExactlyOnce), start + Duration::from_secs(3), 8).unwrap();
+        in_flight.on_pubrec(2, ReasonCode::Success, start + Duration::from_secs(4)).unwrap();
+
+        let messages: Vec<InFlightMessage> = in_flight.messages(start + Duration::from_secs(5)).collect();
+        assert_eq!(vec![
+            InFlightMessage {
+                packet_identifier: 1,
+                direction: Direction::Outgoing,
+                qos: QoS::AtLeastOnce,
+                state: FlowState::AwaitingPuback,
+                topic: "a".into(),
+                age: Duration::from_secs(5),
+                flow_id: 7,
+            },
+            InFlightMessage {
+                packet_identifier: 2,
+                direction: Direction::Outgoing,
+                qos: QoS::ExactlyOnce,
+                state: FlowState::AwaitingPubcomp,
+                topic: "b".into(),
+                age: Duration::from_secs(2),
+                flow_id: 8,
+            },
+        ], messages);
+        assert_eq!(Some(8), in_flight.flow_id(2));
+        assert_eq!(None, in_flight.flow_id(3));
+    }
+
+    #[test]
+    fn redeliver_in_order() {
+        let mut in_flight = InFlight::new(true);
+        in_flight.track(&publish("a", 1, QoS::ExactlyOnce), now(), 0).unwrap();
+        in_flight.track(&publish("a", 2, QoS::AtLeastOnce), now(), 0).unwrap();
+        in_flight.track(&publish("b", 3, QoS::ExactlyOnce), now(), 0).unwrap();
+        in_flight.track(&publish("a", 4, QoS::AtLeastOnce), now(), 0).unwrap();
+
+        in_flight.on_pubrec(1, ReasonCode::Success, now()).unwrap();
+        in_flight.on_puback(2).unwrap();
+
+        let packets = in_flight.redeliver(now());
+        assert_eq!(3, packets.len());
+        match &packets[..] {
+            [Packet::Pubrel(pubrel), Packet::Publish(b), Packet::Publish(a)] => {
+                assert_eq!(1, pubrel.packet_identifier);
+                assert_eq!((Some(3), true), (b.packet_identifier, b.dup));
+                assert_eq!((Some(4), true), (a.packet_identifier, a.dup));
+            },
+            other => panic!("unexpected redelivery: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn overdue() {
+        let start = now();
+        let timeout = Duration::from_secs(10);
+        let mut in_flight = InFlight::new(false);
+        in_flight.track(&publish("a", 1, QoS::AtLeastOnce), start, 0).unwrap();
+        in_flight.track(&publish("a", 2, QoS::ExactlyOnce), start + Duration::from_secs(2), 0).unwrap();
+        assert_eq!(Some(start + timeout), in_flight.next_due(timeout));
+
+        assert!(in_flight.overdue(timeout, start + Duration::from_secs(9)).is_empty());
+
+        let later = start + Duration::from_secs(10);
+        match &in_flight.overdue(timeout, later)[..] {
+            [Packet::Publish(p)] => assert_eq!((Some(1), true), (p.packet_identifier, p.dup)),
+            other => panic!("unexpected retransmission: {:?}", other),
+        }
+        assert_eq!(Some(start + Duration::from_secs(12)), in_flight.next_due(timeout));
+
+        // the PUBREL restarts the timeout of the second message
+        in_flight.on_pubrec(2, ReasonCode::Success, later).unwrap();
+        in_flight.on_puback(1).unwrap();
+        assert_eq!(Some(later + timeout), in_flight.next_due(timeout));
+        match &in_flight.overdue(timeout, later + timeout)[..] {
+            [Packet::Pubrel(p)] => assert_eq!(2, p.packet_identifier),
+            other => panic!("unexpected retransmission: {:?}", other),
+        }
+
+        in_flight.on_pubcomp(2).unwrap();
+        assert_eq!(None, in_flight.next_due(timeout));
+    }
+
+    fn now() -> Instant {
+        Instant::now()
+    }
+
+    fn publish(topic: &str, packet_identifier: u16, qos: QoS) -> Publish {
+        let mut publish = Publish::new_unchecked(topic.into(), vec![]);
+        publish.qos_level = qos;
+        publish.packet_identifier = Some(packet_identifier);
+        publish
+    }
+}
@@ -0,0 +1,46 @@
+//! Connection-level protocol state, independent of any actual I/O.
+//! 
+//! Where the [packet module](crate::packet) deals with encoding and decoding single control packets, the types in 
+//! here keep track of everything that spans more than one packet of a connection, such as values negotiated between
+//! `CONNECT` and `CONNACK` or topic aliases.
+//! 
+//! None of these types read from or write to the network themselves. They are meant to be driven by whatever 
+//! actually owns the connection, which hands them packets going in either direction.
+
+mod client;
+mod clock;
+#[cfg(feature = "payload-compression")]
+mod compression;
+mod handshake;
+mod inflight;
+mod negotiated;
+mod outcome;
+mod rate_limit;
+mod received;
+mod redirect;
+mod schedule;
+mod server;
+mod server_handshake;
+mod snapshot;
+mod stats;
+mod subscription;
+mod topic_alias;
+
+pub use self::client::{ClientIdHook, ClientSession, SessionConfig, SubscribeQoSPolicy};
+pub use self::clock::{Clock, SystemClock};
+#[cfg(feature = "payload-compression")]
+pub use self::compression::{Compressor, PayloadCompression};
+pub use self::handshake::{Handshake, HandshakeState, HandshakeStep, ReauthStep};
+pub use self::inflight::{Direction, FlowState, InFlightMessage};
+pub use self::negotiated::NegotiatedSession;
+pub use self::outcome::{PublishOutcome, SubscribeOutcome};
+pub use self::rate_limit::{RateLimit, RateLimiter};
+pub use self::received::{Received, Timestamp};
+pub use self::redirect::{Redirect, RedirectPolicy, ServerReference};
+pub use self::schedule::DueAction;
+pub use self::server::ServerSession;
+pub use self::server_handshake::{Accepted, Authenticator, ServerHandshake, ServerLimits, ServerReauthentication};
+pub use self::snapshot::{SessionSnapshot, SessionStore};
+pub use self::stats::{PacketCounts, SessionStats};
+pub use self::subscription::Subscription;
+pub use self::topic_alias::{TopicAliasMap, TopicAliasPolicy, TopicAliasResolver};
@@ -0,0 +1,110 @@
+use std::time::Duration;
+
+use crate::packet::Connack;
+
+use super::schedule;
+
+/// The values in effect for a connection, resolved from what the client asked for with its `CONNECT` and what the
+/// server answered with its `CONNACK`.
+///
+/// # Examples
+/// ```
+/// use mqtt::packet::{Connack, ConnackProperties};
+/// use mqtt::session::NegotiatedSession;
+/// use mqtt::types::ReasonCode;
+///
+/// let mut properties = ConnackProperties::default();
+/// properties.server_keep_alive = Some(0);
+/// let connack = Connack { session_present: false, reason_code: ReasonCode::Success, properties: Some(properties) };
+///
+/// let negotiated = NegotiatedSession::new(60, &connack);
+/// assert_eq!(0, negotiated.keep_alive());
+/// assert!(negotiated.is_keep_alive_disabled_by_server());
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NegotiatedSession {
+    /// Keep alive in seconds as requested with the `CONNECT`.
+    pub requested_keep_alive: u16,
+
+    /// Keep alive in seconds as sent by the server with its `CONNACK`, if it overrides the requested one.
+    pub server_keep_alive: Option<u16>,
+}
+
+impl NegotiatedSession {
+
+    pub fn new(requested_keep_alive: u16, connack: &Connack) -> Self {
+        Self {
+            requested_keep_alive,
+            server_keep_alive: connack.properties.as_ref().and_then(|p| p.server_keep_alive),
+        }
+    }
+
+    /// The keep alive in seconds the client must use: the server's if it sent one [MQTT-3.2.2-21], the requested one
+    /// otherwise. `0` means no keep alive at all.
+    pub fn keep_alive(&self) -> u16 {
+        self.server_keep_alive.unwrap_or(self.requested_keep_alive)
+    }
+
+    /// The [keep alive](NegotiatedSession::keep_alive) as a duration, `None` if disabled.
+    pub fn keep_alive_interval(&self) -> Option<Duration> {
+        schedule::keep_alive(self.keep_alive())
+    }
+
+    /// Whether the server replaced the requested keep alive with a different one.
+    pub fn is_keep_alive_overridden(&self) -> bool {
+        self.server_keep_alive.is_some_and(|keep_alive| keep_alive != self.requested_keep_alive)
+    }
+
+    /// Whether the client asked for a keep alive, but the server expects none: it won't time out an idle connection,
+    /// and the client mustn't send `PINGREQ` just to keep it open.
+    pub fn is_keep_alive_disabled_by_server(&self) -> bool {
+        self.server_keep_alive == Some(0) && self.requested_keep_alive != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{packet::ConnackProperties, types::ReasonCode};
+
+    use super::*;
+
+    fn connack(server_keep_alive: Option<u16>) -> Connack {
+        let properties = ConnackProperties { server_keep_alive, ..Default::default() };
+        Connack { session_present: false, reason_code: ReasonCode::Success, properties: Some(properties) }
+    }
+
+    #[test]
+    fn requested() {
+        let negotiated = NegotiatedSession::new(30, &connack(None));
+        assert_eq!(30, negotiated.keep_alive());
+        assert_eq!(Some(Duration::from_secs(30)), negotiated.keep_alive_interval());
+        assert!(!negotiated.is_keep_alive_overridden());
+        assert!(!negotiated.is_keep_alive_disabled_by_server());
+
+        let without_properties = Connack { session_present: false, reason_code: ReasonCode::Success, properties: None };
+        assert_eq!(30, NegotiatedSession::new(30, &without_properties).keep_alive());
+    }
+
+    #[test]
+    fn overridden() {
+        let negotiated = NegotiatedSession::new(30, &connack(Some(120)));
+        assert_eq!(120, negotiated.keep_alive());
+        assert!(negotiated.is_keep_alive_overridden());
+        assert!(!negotiated.is_keep_alive_disabled_by_server());
+
+        // the server may well confirm what was asked for
+        assert!(!NegotiatedSession::new(30, &connack(Some(30))).is_keep_alive_overridden());
+    }
+
+    #[test]
+    fn disabled_by_server() {
+        let negotiated = NegotiatedSession::new(30, &connack(Some(0)));
+        assert_eq!(0, negotiated.keep_alive());
+        assert_eq!(None, negotiated.keep_alive_interval());
+        assert!(negotiated.is_keep_alive_overridden());
+        assert!(negotiated.is_keep_alive_disabled_by_server());
+
+        // nothing to disable if the client didn't ask for keep alive
+        assert!(!NegotiatedSession::new(0, &connack(Some(0))).is_keep_alive_disabled_by_server());
+    }
+}
@@ -0,0 +1,184 @@
+use std::fmt::Display;
+
+use crate::{
+    packet::{Puback, Pubcomp, Pubrec, Suback},
+    types::{QoS, ReasonCode},
+};
+
+/// What became of a QoS 1 or 2 message, according to the reason code of its `PUBACK`, `PUBREC` or `PUBCOMP`.
+///
+/// # Examples
+/// ```
+/// use mqtt::packet::Puback;
+/// use mqtt::session::PublishOutcome;
+/// use mqtt::types::ReasonCode;
+///
+/// let puback = Puback::new(1, ReasonCode::NoMatchingSubscribers).unwrap();
+/// assert_eq!(PublishOutcome::NoMatchingSubscribers, PublishOutcome::from(&puback));
+/// assert!(!PublishOutcome::from(&puback).is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PublishOutcome {
+    /// The receiver accepted the message.
+    Acked,
+
+    /// The receiver accepted the message, but nobody is subscribed to its topic. Not an error: the message was
+    /// delivered as far as the sender is concerned.
+    NoMatchingSubscribers,
+
+    /// The receiver didn't accept the message, for the reason given.
+    Failed(ReasonCode),
+}
+
+impl PublishOutcome {
+
+    pub fn is_err(&self) -> bool {
+        matches!(self, PublishOutcome::Failed(_))
+    }
+
+    /// The outcome of a QoS 2 flow, which has a reason code in both the `PUBREC` and the `PUBCOMP`. The first one
+    /// that's an error decides, otherwise the `PUBREC` tells whether there were matching subscribers.
+    pub fn of_exactly_once(pubrec: &Pubrec, pubcomp: &Pubcomp) -> Self {
+        match (Self::from(pubrec), Self::from(pubcomp)) {
+            (PublishOutcome::Failed(reason_code), _) | (_, PublishOutcome::Failed(reason_code)) => {
+                PublishOutcome::Failed(reason_code)
+            },
+            (outcome, _) => outcome,
+        }
+    }
+}
+
+impl From<ReasonCode> for PublishOutcome {
+    fn from(reason_code: ReasonCode) -> Self {
+        match reason_code {
+            ReasonCode::NoMatchingSubscribers => PublishOutcome::NoMatchingSubscribers,
+            code if code.is_err() => PublishOutcome::Failed(code),
+            _ => PublishOutcome::Acked,
+        }
+    }
+}
+
+impl From<&Puback> for PublishOutcome {
+    fn from(puback: &Puback) -> Self {
+        Self::from(puback.reason_code)
+    }
+}
+
+impl From<&Pubrec> for PublishOutcome {
+    fn from(pubrec: &Pubrec) -> Self {
+        Self::from(pubrec.reason_code)
+    }
+}
+
+impl From<&Pubcomp> for PublishOutcome {
+    fn from(pubcomp: &Pubcomp) -> Self {
+        Self::from(pubcomp.reason_code)
+    }
+}
+
+impl Display for PublishOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PublishOutcome::Acked => write!(f, "acknowledged"),
+            PublishOutcome::NoMatchingSubscribers => write!(f, "acknowledged, no matching subscribers"),
+            PublishOutcome::Failed(code) => write!(f, "failed: {} (0x{:02X})", code, u8::from(*code)),
+        }
+    }
+}
+
+/// What became of one topic filter of a `SUBSCRIBE`, according to its reason code in the `SUBACK`.
+///
+/// # Examples
+/// ```
+/// use mqtt::packet::Suback;
+/// use mqtt::session::SubscribeOutcome;
+/// use mqtt::types::{QoS, ReasonCode};
+///
+/// let suback = Suback {
+///     packet_identifier: 1,
+///     properties: None,
+///     reason_codes: vec![ReasonCode::GrantedQoS1, ReasonCode::NotAuthorized],
+/// };
+/// assert_eq!(
+///     vec![SubscribeOutcome::Granted(QoS::AtLeastOnce), SubscribeOutcome::Failed(ReasonCode::NotAuthorized)],
+///     SubscribeOutcome::from_suback(&suback));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubscribeOutcome {
+    /// The subscription was made, with a maximum QoS that may be lower than the one asked for.
+    Granted(QoS),
+
+    /// The subscription was refused, for the reason given.
+    Failed(ReasonCode),
+}
+
+impl SubscribeOutcome {
+
+    /// One outcome for each topic filter, in the order of the `SUBSCRIBE`.
+    pub fn from_suback(suback: &Suback) -> Vec<Self> {
+        suback.reason_codes.iter().map(|code| Self::from(*code)).collect()
+    }
+
+    pub fn is_err(&self) -> bool {
+        matches!(self, SubscribeOutcome::Failed(_))
+    }
+}
+
+impl From<ReasonCode> for SubscribeOutcome {
+    /// A reason code a `SUBACK` mustn't contain counts as failed, the subscription can't be relied upon.
+    fn from(reason_code: ReasonCode) -> Self {
+        match reason_code {
+            ReasonCode::Success => SubscribeOutcome::Granted(QoS::AtMostOnce),
+            ReasonCode::GrantedQoS1 => SubscribeOutcome::Granted(QoS::AtLeastOnce),
+            ReasonCode::GrantedQoS2 => SubscribeOutcome::Granted(QoS::ExactlyOnce),
+            code => SubscribeOutcome::Failed(code),
+        }
+    }
+}
+
+impl Display for SubscribeOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SubscribeOutcome::Granted(qos) => write!(f, "granted {:?}", qos),
+            SubscribeOutcome::Failed(code) => write!(f, "failed: {} (0x{:02X})", code, u8::from(*code)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn publish() {
+        assert_eq!(PublishOutcome::Acked, PublishOutcome::from(ReasonCode::Success));
+        assert_eq!(PublishOutcome::NoMatchingSubscribers, PublishOutcome::from(ReasonCode::NoMatchingSubscribers));
+        assert_eq!(PublishOutcome::Failed(ReasonCode::QuotaExceeded), PublishOutcome::from(ReasonCode::QuotaExceeded));
+        assert_eq!("failed: Quota exceeded (0x97)", PublishOutcome::Failed(ReasonCode::QuotaExceeded).to_string());
+    }
+
+    #[test]
+    fn exactly_once() {
+        let pubrec = |code| Pubrec::new(1, code).unwrap();
+        let pubcomp = |code| Pubcomp::new(1, code).unwrap();
+        assert_eq!(
+            PublishOutcome::NoMatchingSubscribers,
+            PublishOutcome::of_exactly_once(&pubrec(ReasonCode::NoMatchingSubscribers), &pubcomp(ReasonCode::Success)));
+        assert_eq!(
+            PublishOutcome::Failed(ReasonCode::PacketIdentifierNotFound),
+            PublishOutcome::of_exactly_once(
+                &pubrec(ReasonCode::Success),
+                &pubcomp(ReasonCode::PacketIdentifierNotFound)));
+        assert_eq!(
+            PublishOutcome::Acked,
+            PublishOutcome::of_exactly_once(&pubrec(ReasonCode::Success), &pubcomp(ReasonCode::Success)));
+    }
+
+    #[test]
+    fn subscribe() {
+        assert_eq!(SubscribeOutcome::Granted(QoS::AtMostOnce), SubscribeOutcome::from(ReasonCode::Success));
+        assert_eq!(SubscribeOutcome::Granted(QoS::ExactlyOnce), SubscribeOutcome::from(ReasonCode::GrantedQoS2));
+        assert!(SubscribeOutcome::from(ReasonCode::TopciFilterInvalid).is_err());
+        assert!(SubscribeOutcome::from(ReasonCode::NoMatchingSubscribers).is_err());
+    }
+}
@@ -0,0 +1,194 @@
+use std::time::{Duration, Instant};
+
+/// How fast a [RateLimiter] lets messages through. Both limits apply at once, neither is set by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Messages per second, at least 1.
+    pub messages_per_second: Option<u32>,
+
+    /// Bytes per second, at least 1. A message counts with the length of its topic name plus its payload, the part
+    /// that grows with what's published. Headers and properties add a few bytes each on top of that.
+    pub bytes_per_second: Option<u32>,
+}
+
+/// A token bucket for each limit of a [RateLimit]. Each bucket holds up to one second's worth of tokens, so after
+/// being idle, up to that many messages or bytes may go out at once before the rate kicks in.
+///
+/// A message larger than the byte limit can't ever be covered by the bucket. It is let through once the bucket is
+/// full, leaving it in debt for as long as sending it takes at that rate.
+///
+/// Like the sessions, it doesn't read the system time itself but is told what time it is.
+///
+/// # Examples
+/// ```
+/// use std::time::{Duration, Instant};
+/// use mqtt::session::{RateLimit, RateLimiter};
+///
+/// let start = Instant::now();
+/// let mut limiter = RateLimiter::new(RateLimit { messages_per_second: Some(2), ..Default::default() }, start);
+/// assert!(limiter.try_acquire(10, start));
+/// assert!(limiter.try_acquire(10, start));
+/// assert!(!limiter.try_acquire(10, start));
+/// assert_eq!(start + Duration::from_millis(500), limiter.ready_at(10, start));
+/// assert!(limiter.try_acquire(10, start + Duration::from_millis(500)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    messages: Option<Bucket>,
+    bytes: Option<Bucket>,
+}
+
+impl RateLimiter {
+
+    /// Starts with full buckets at `now`.
+    pub fn new(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            messages: limit.messages_per_second.map(|rate| Bucket::new(rate, now)),
+            bytes: limit.bytes_per_second.map(|rate| Bucket::new(rate, now)),
+        }
+    }
+
+    /// Takes what a message of `bytes` costs and returns `true` if both limits allow sending it at `now`. Takes
+    /// nothing otherwise.
+    pub fn try_acquire(&mut self, bytes: usize, now: Instant) -> bool {
+        for bucket in self.buckets_mut() {
+            bucket.refill(now);
+        }
+        if self.ready_at(bytes, now) > now {
+            return false
+        }
+
+        if let Some(bucket) = &mut self.messages {
+            bucket.tokens -= 1.0;
+        }
+        if let Some(bucket) = &mut self.bytes {
+            bucket.tokens -= bytes as f64;
+        }
+        true
+    }
+
+    /// When a message of `bytes` will be allowed, `now` if it is already.
+    pub fn ready_at(&self, bytes: usize, now: Instant) -> Instant {
+        [
+            self.messages.as_ref().map(|bucket| bucket.ready_at(1.0, now)),
+            self.bytes.as_ref().map(|bucket| bucket.ready_at(bytes as f64, now)),
+        ].into_iter().flatten().max().unwrap_or(now)
+    }
+
+    fn buckets_mut(&mut self) -> impl Iterator<Item = &mut Bucket> {
+        self.messages.iter_mut().chain(self.bytes.iter_mut())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Bucket {
+    /// tokens per second, also the most the bucket holds
+    rate: f64,
+    /// negative after letting through more than the bucket held
+    tokens: f64,
+    /// when the tokens were last refilled
+    updated: Instant,
+}
+
+impl Bucket {
+
+    fn new(rate: u32, now: Instant) -> Self {
+        let rate = rate.max(1) as f64;
+        Self { rate, tokens: rate, updated: now }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if now > self.updated {
+            let elapsed = now.duration_since(self.updated).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+            self.updated = now;
+        }
+    }
+
+    /// When the bucket holds enough for `cost`, or is full if it never holds that much.
+    fn ready_at(&self, cost: f64, now: Instant) -> Instant {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        let missing = cost.min(self.rate) - (self.tokens + elapsed * self.rate).min(self.rate);
+        match missing > 0.0 {
+            true => now + Duration::from_secs_f64(missing / self.rate),
+            false => now,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(messages_per_second: Option<u32>, bytes_per_second: Option<u32>) -> (RateLimiter, Instant) {
+        let now = Instant::now();
+        (RateLimiter::new(RateLimit { messages_per_second, bytes_per_second }, now), now)
+    }
+
+    #[test]
+    fn unlimited() {
+        let (mut limiter, now) = limiter(None, None);
+        for _ in 0..10_000 {
+            assert!(limiter.try_acquire(1_000_000, now));
+        }
+        assert_eq!(now, limiter.ready_at(1_000_000, now));
+    }
+
+    #[test]
+    fn messages() {
+        let (mut limiter, start) = limiter(Some(10), None);
+        for _ in 0..10 {
+            assert!(limiter.try_acquire(100, start));
+        }
+        assert!(!limiter.try_acquire(100, start));
+        assert_eq!(start + Duration::from_millis(100), limiter.ready_at(100, start));
+
+        let later = start + Duration::from_millis(250);
+        assert!(limiter.try_acquire(100, later));
+        assert!(limiter.try_acquire(100, later));
+        assert!(!limiter.try_acquire(100, later));
+
+        // idle for long doesn't allow more than one second's worth
+        let much_later = later + Duration::from_secs(60);
+        assert_eq!(10, (0..20).filter(|_| limiter.try_acquire(1, much_later)).count());
+    }
+
+    #[test]
+    fn bytes() {
+        let (mut limiter, start) = limiter(None, Some(1_000));
+        assert!(limiter.try_acquire(600, start));
+        assert!(!limiter.try_acquire(600, start));
+        assert_eq!(start + Duration::from_millis(200), limiter.ready_at(600, start));
+        assert!(limiter.try_acquire(400, start));
+        assert!(!limiter.try_acquire(1, start));
+    }
+
+    #[test]
+    fn both() {
+        let (mut limiter, start) = limiter(Some(2), Some(100));
+        assert!(limiter.try_acquire(10, start));
+        assert!(!limiter.try_acquire(95, start));
+        assert!(limiter.try_acquire(10, start));
+
+        // the byte limit has room, the message limit doesn't
+        assert_eq!(start + Duration::from_millis(500), limiter.ready_at(10, start));
+    }
+
+    #[test]
+    fn larger_than_the_limit() {
+        let (mut limiter, start) = limiter(None, Some(100));
+        assert!(limiter.try_acquire(300, start));
+
+        // in debt for another two seconds, then one second to fill up again
+        assert_eq!(start + Duration::from_secs(3), limiter.ready_at(300, start));
+        assert!(!limiter.try_acquire(300, start + Duration::from_millis(2_900)));
+        assert!(limiter.try_acquire(300, start + Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn zero_rate() {
+        let (mut limiter, start) = limiter(Some(0), Some(0));
+        assert!(limiter.try_acquire(5, start));
+        assert_eq!(start + Duration::from_secs(5), limiter.ready_at(5, start));
+    }
+}
@@ -0,0 +1,85 @@
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::packet::Publish;
+
+use super::Clock;
+
+/// A point in time by both clocks: the monotonic one to measure durations with, such as how long a message has been
+/// waiting, and the wall clock to compare with times from other machines, such as a timestamp the sender put into a
+/// message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamp {
+    pub instant: Instant,
+    pub system_time: SystemTime,
+}
+
+impl Timestamp {
+
+    /// The current time of `clock`.
+    pub fn now(clock: &dyn Clock) -> Self {
+        Self { instant: clock.now(), system_time: clock.system_time() }
+    }
+}
+
+/// A packet along with the [Timestamp] it was received at, see
+/// [ClientSession::decode_received](super::ClientSession::decode_received).
+///
+/// # Examples
+/// ```
+/// use std::time::Duration;
+/// use mqtt::packet::{Packet, Publish};
+/// use mqtt::session::{ClientSession, Received};
+///
+/// let mut session = ClientSession::default();
+/// let publish = Publish::builder("a").expiry(Duration::from_secs(30)).build().unwrap();
+/// let received = session.decode_received(&Vec::try_from(publish).unwrap()).unwrap();
+///
+/// let message: Received<Publish> = received.map(|packet| match packet {
+///     Packet::Publish(publish) => publish,
+///     other => panic!("expected PUBLISH, got {:?}", other),
+/// });
+/// let later = message.timestamp.instant + Duration::from_secs(12);
+/// assert_eq!(Some(18), message.remaining_expiry(later));
+/// assert!(message.is_expired(later + Duration::from_secs(18)));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Received<P> {
+    pub packet: P,
+    pub timestamp: Timestamp,
+}
+
+impl<P> Received<P> {
+
+    /// Converts the packet, keeping the timestamp.
+    pub fn map<Q>(self, f: impl FnOnce(P) -> Q) -> Received<Q> {
+        Received { packet: f(self.packet), timestamp: self.timestamp }
+    }
+}
+
+impl Received<Publish> {
+
+    /// When the message expires by its message expiry interval, `None` if it doesn't.
+    pub fn expires_at(&self) -> Option<Instant> {
+        let interval = self.packet.properties.as_ref()?.message_expiry_interval?;
+        Some(self.timestamp.instant + Duration::from_secs(interval.into()))
+    }
+
+    /// Whether the message's expiry interval has passed at `now`, after which it shouldn't be processed or passed on
+    /// anymore.
+    pub fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at().is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// The message expiry interval to pass the message on with at `now`: what's left of the one it arrived with, in
+    /// whole seconds [MQTT-3.3.2-6]. `None` if it doesn't expire, `0` once it has.
+    pub fn remaining_expiry(&self, now: Instant) -> Option<u32> {
+        let left = self.expires_at()?.saturating_duration_since(now);
+        Some(left.as_secs().try_into().unwrap_or(u32::MAX))
+    }
+
+    /// How long the message took from `sent`, a wall clock time the sender put into it, until it was received. `None`
+    /// if the clocks of sender and receiver are so far apart that it seems to have arrived before it was sent.
+    pub fn latency_since(&self, sent: SystemTime) -> Option<Duration> {
+        self.timestamp.system_time.duration_since(sent).ok()
+    }
+}
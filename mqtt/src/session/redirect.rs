@@ -0,0 +1,216 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::{
+    error::MqttError,
+    packet::{Connack, Disconnect},
+    types::ReasonCode,
+};
+
+/// Whether a [ClientSession](super::ClientSession) lets its owner follow redirects to other servers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RedirectPolicy {
+    /// Redirects are only reported, never followed.
+    #[default]
+    Report,
+
+    /// Follow at most this many redirects in a row before giving up, to avoid going around in circles.
+    Follow(u8),
+}
+
+/// A server telling the client to use another server instead, either with its `CONNACK` or a `DISCONNECT`.
+///
+/// # Examples
+/// ```
+/// use mqtt::packet::{Disconnect, DisconnectProperties};
+/// use mqtt::session::Redirect;
+/// use mqtt::types::ReasonCode;
+///
+/// let disconnect = Disconnect {
+///     reason_code: ReasonCode::ServerMoved,
+///     properties: Some(DisconnectProperties { server_reference: Some("broker.example.com:8883".into()), ..Default::default() }),
+/// };
+///
+/// let redirect = Redirect::from_disconnect(&disconnect).unwrap().unwrap();
+/// assert!(redirect.is_permanent());
+/// assert_eq!(("broker.example.com".to_string(), 8883), redirect.references[0].addr(1883));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirect {
+    /// Either [ReasonCode::UseAnotherServer] or [ReasonCode::ServerMoved].
+    pub reason_code: ReasonCode,
+
+    /// The servers to use instead, in order of preference. Empty if the server didn't say where to go.
+    pub references: Vec<ServerReference>,
+}
+
+/// A server to use instead of the current one, as found in the `server_reference` property.
+///
+/// The spec leaves the format to the implementation, this follows its non-normative examples: a host name or IP
+/// address with an optional port, IPv6 addresses in square brackets, such as `myserver.xyz.org`,
+/// `10.10.151.22:8883` or `[fe80::9610:3eff:fe1c]:1883`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerReference {
+    pub host: String,
+    pub port: Option<u16>,
+}
+
+impl Redirect {
+
+    /// Returns a redirect if the `CONNACK` refuses the connection with one of the redirect reason codes, or an error
+    /// if its server reference can't be parsed.
+    pub fn from_connack(connack: &Connack) -> Result<Option<Self>, MqttError> {
+        let reference = connack.properties.as_ref().and_then(|p| p.server_reference.as_deref());
+        Self::new(connack.reason_code, reference)
+    }
+
+    /// Returns a redirect if the `DISCONNECT` has one of the redirect reason codes, or an error if its server
+    /// reference can't be parsed.
+    pub fn from_disconnect(disconnect: &Disconnect) -> Result<Option<Self>, MqttError> {
+        let reference = disconnect.properties.as_ref().and_then(|p| p.server_reference.as_deref());
+        Self::new(disconnect.reason_code, reference)
+    }
+
+    fn new(reason_code: ReasonCode, reference: Option<&str>) -> Result<Option<Self>, MqttError> {
+        match reason_code {
+            ReasonCode::UseAnotherServer | ReasonCode::ServerMoved => {
+                let references = match reference {
+                    Some(s) => ServerReference::parse_all(s)?,
+                    None => Vec::new(),
+                };
+                Ok(Some(Self { reason_code, references }))
+            },
+            _ => Ok(None),
+        }
+    }
+
+    /// `true` if the client should use the other server from now on, `false` if only for this connection.
+    pub fn is_permanent(&self) -> bool {
+        self.reason_code == ReasonCode::ServerMoved
+    }
+}
+
+impl ServerReference {
+
+    /// Parses a space separated list of references.
+    pub fn parse_all(s: &str) -> Result<Vec<Self>, MqttError> {
+        s.split_whitespace().map(Self::from_str).collect()
+    }
+
+    /// The host and port to connect to, with `default_port` if the reference doesn't have one.
+    pub fn addr(&self, default_port: u16) -> (String, u16) {
+        (self.host.clone(), self.port.unwrap_or(default_port))
+    }
+}
+
+impl FromStr for ServerReference {
+    type Err = MqttError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || MqttError::Message(format!("Invalid server reference: {}", s));
+
+        let (host, port) = match s.strip_prefix('[') {
+            // bracketed IPv6 address
+            Some(rest) => match rest.split_once(']') {
+                Some((host, "")) => (host, None),
+                Some((host, port)) => (host, Some(port.strip_prefix(':').ok_or_else(invalid)?)),
+                None => return Err(invalid()),
+            },
+            None => match s.split_once(':') {
+                // a bare IPv6 address has more than one colon and can't have a port
+                Some((host, port)) if !port.contains(':') => (host, Some(port)),
+                _ => (s, None),
+            },
+        };
+
+        if host.is_empty() || host.contains(char::is_whitespace) {
+            return Err(invalid())
+        }
+        let port = match port {
+            Some(p) => Some(p.parse::<u16>().map_err(|_| invalid())?),
+            None => None,
+        };
+
+        Ok(Self { host: host.to_string(), port })
+    }
+}
+
+impl Display for ServerReference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.host.contains(':') {
+            true => write!(f, "[{}]", self.host)?,
+            false => write!(f, "{}", self.host)?,
+        }
+        match self.port {
+            Some(port) => write!(f, ":{}", port),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packet::{ConnackProperties, DisconnectProperties};
+
+    use super::*;
+
+    fn reference(host: &str, port: Option<u16>) -> ServerReference {
+        ServerReference { host: host.into(), port }
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(reference("myserver.xyz.org", None), "myserver.xyz.org".parse().unwrap());
+        assert_eq!(reference("10.10.151.22", Some(8883)), "10.10.151.22:8883".parse().unwrap());
+        assert_eq!(reference("fe80::9610:3eff:fe1c", Some(1883)), "[fe80::9610:3eff:fe1c]:1883".parse().unwrap());
+        assert_eq!(reference("fe80::9610:3eff:fe1c", None), "[fe80::9610:3eff:fe1c]".parse().unwrap());
+        assert_eq!(reference("fe80::1", None), "fe80::1".parse().unwrap());
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for s in ["", ":1883", "host:", "host:port", "host:65536", "[fe80::1", "[fe80::1]1883", "[]:1883"] {
+            assert!(s.parse::<ServerReference>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn parse_all() {
+        let references = ServerReference::parse_all("10.10.151.22:8883 [fe80::9610:3eff:fe1c]:1883").unwrap();
+        assert_eq!(vec![reference("10.10.151.22", Some(8883)), reference("fe80::9610:3eff:fe1c", Some(1883))], references);
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for s in ["myserver.xyz.org", "10.10.151.22:8883", "[fe80::9610:3eff:fe1c]:1883", "[fe80::1]"] {
+            assert_eq!(s, s.parse::<ServerReference>().unwrap().to_string());
+        }
+    }
+
+    #[test]
+    fn from_connack() {
+        let mut connack = Connack {
+            session_present: false,
+            reason_code: ReasonCode::UseAnotherServer,
+            properties: Some(ConnackProperties { server_reference: Some("other:1884".into()), ..Default::default() }),
+        };
+        let redirect = Redirect::from_connack(&connack).unwrap().unwrap();
+        assert!(!redirect.is_permanent());
+        assert_eq!(vec![reference("other", Some(1884))], redirect.references);
+
+        connack.reason_code = ReasonCode::ServerBusy;
+        assert_eq!(None, Redirect::from_connack(&connack).unwrap());
+
+        connack.reason_code = ReasonCode::ServerMoved;
+        connack.properties = None;
+        assert!(Redirect::from_connack(&connack).unwrap().unwrap().references.is_empty());
+    }
+
+    #[test]
+    fn from_disconnect_invalid_reference() {
+        let disconnect = Disconnect {
+            reason_code: ReasonCode::UseAnotherServer,
+            properties: Some(DisconnectProperties { server_reference: Some("other:http".into()), ..Default::default() }),
+        };
+        assert!(Redirect::from_disconnect(&disconnect).is_err());
+    }
+}
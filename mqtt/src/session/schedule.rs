@@ -0,0 +1,142 @@
+use std::{collections::VecDeque, time::{Duration, Instant}};
+
+use crate::{packet::{Packet, Publish}, types::QoS};
+
+use super::{Direction, FlowState, InFlightMessage};
+
+/// Something a [ClientSession](super::ClientSession) wants done because a timer has run out, see
+/// [ClientSession::advance](super::ClientSession::advance).
+#[derive(Debug)]
+pub enum DueAction {
+    /// Send the packet again, it hasn't been acknowledged within the
+    /// [ack timeout](super::SessionConfig::ack_timeout). Either a `PUBLISH` with the `DUP` flag or a `PUBREL`.
+    Retransmit(Box<Packet>),
+
+    /// Nothing has been sent for the duration of the keep alive, send a `PINGREQ`.
+    SendPingreq,
+
+    /// The `PUBREL` for an incoming QoS 2 message with this packet identifier hasn't arrived within the
+    /// [PUBREL timeout](super::SessionConfig::pubrel_timeout). The session has forgotten about it.
+    ExpirePubrel(u16),
+
+    /// A message [queued](super::ClientSession::enqueue) earlier may go out now, as the
+    /// [rate limit](super::SessionConfig::rate_limit) allows. It still needs to be
+    /// [prepared](super::ClientSession::prepare_publish) like any other.
+    Publish(Box<Publish>),
+}
+
+/// The shortest time [ClientSession::time_until_due](super::ClientSession::time_until_due) and
+/// [ServerSession::time_until_due](super::ServerSession::time_until_due) return, as read timeouts can't be zero.
+const MINIMUM_WAIT: Duration = Duration::from_millis(1);
+
+/// A keep alive of `secs` seconds, `None` for `0`, which disables it.
+pub(crate) fn keep_alive(secs: u16) -> Option<Duration> {
+    match secs {
+        0 => None,
+        secs => Some(Duration::from_secs(secs.into())),
+    }
+}
+
+/// How long to wait from `now` until `due`, at least [MINIMUM_WAIT] even if it's overdue.
+pub(crate) fn time_until(due: Option<Instant>, now: Instant) -> Option<Duration> {
+    due.map(|due| due.saturating_duration_since(now).max(MINIMUM_WAIT))
+}
+
+/// Packet identifiers, topic names and flow IDs of incoming QoS 2 messages, in the order they were received, waiting
+/// for their `PUBREL`.
+#[derive(Debug, Default)]
+pub(crate) struct AwaitingPubrel {
+    entries: VecDeque<(u16, Instant, String, u64)>,
+}
+
+impl AwaitingPubrel {
+
+    /// Starts waiting for the `PUBREL`. A packet identifier already waiting, as with a duplicate `PUBLISH`, keeps its
+    /// original time and flow.
+    pub(crate) fn track(&mut self, packet_identifier: u16, topic: &str, now: Instant, flow_id: u64) {
+        if !self.contains(packet_identifier) {
+            self.entries.push_back((packet_identifier, now, topic.to_string(), flow_id));
+        }
+    }
+
+    /// Returns `false` if the packet identifier wasn't waiting for a `PUBREL`.
+    pub(crate) fn release(&mut self, packet_identifier: u16) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|(id, ..)| *id != packet_identifier);
+        before != self.entries.len()
+    }
+
+    pub(crate) fn contains(&self, packet_identifier: u16) -> bool {
+        self.entries.iter().any(|(id, ..)| *id == packet_identifier)
+    }
+
+    pub(crate) fn flow_id(&self, packet_identifier: u16) -> Option<u64> {
+        self.entries.iter().find(|(id, ..)| *id == packet_identifier).map(|(.., flow_id)| *flow_id)
+    }
+
+    /// Removes and returns everything that has been waiting for `timeout` or longer.
+    pub(crate) fn expire(&mut self, timeout: Duration, now: Instant) -> Vec<u16> {
+        let mut expired = Vec::new();
+        while let Some((id, received, ..)) = self.entries.front() {
+            if *received + timeout > now {
+                break
+            }
+            expired.push(*id);
+            self.entries.pop_front();
+        }
+        expired
+    }
+
+    pub(crate) fn next_due(&self, timeout: Duration) -> Option<Instant> {
+        self.entries.front().map(|(_, received, ..)| *received + timeout)
+    }
+
+    /// The messages waiting in the order they were received, with their age at `now`.
+    pub(crate) fn messages(&self, now: Instant) -> impl Iterator<Item = InFlightMessage> + '_ {
+        self.entries.iter().map(move |(id, received, topic, flow_id)| InFlightMessage {
+            packet_identifier: *id,
+            direction: Direction::Incoming,
+            qos: QoS::ExactlyOnce,
+            state: FlowState::AwaitingPubrel,
+            topic: topic.clone(),
+            age: now.saturating_duration_since(*received),
+            flow_id: *flow_id,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waiting() {
+        assert_eq!(None, keep_alive(0));
+        assert_eq!(Some(Duration::from_secs(60)), keep_alive(60));
+
+        let now = Instant::now();
+        assert_eq!(None, time_until(None, now));
+        assert_eq!(Some(Duration::from_secs(5)), time_until(Some(now + Duration::from_secs(5)), now));
+        assert_eq!(Some(MINIMUM_WAIT), time_until(Some(now), now));
+        assert_eq!(Some(MINIMUM_WAIT), time_until(Some(now), now + Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn expire_in_order() {
+        let start = Instant::now();
+        let timeout = Duration::from_secs(5);
+        let mut awaiting = AwaitingPubrel::default();
+        awaiting.track(1, "a", start, 0);
+        awaiting.track(2, "a", start + Duration::from_secs(1), 0);
+        awaiting.track(1, "a", start + Duration::from_secs(2), 0);
+        assert_eq!(Some(start + timeout), awaiting.next_due(timeout));
+
+        assert!(awaiting.expire(timeout, start + Duration::from_secs(4)).is_empty());
+        assert_eq!(vec![1], awaiting.expire(timeout, start + Duration::from_secs(5)));
+        assert_eq!(Some(start + Duration::from_secs(6)), awaiting.next_due(timeout));
+
+        assert!(awaiting.release(2));
+        assert!(!awaiting.release(2));
+        assert_eq!(None, awaiting.next_due(timeout));
+    }
+}
@@ -0,0 +1,176 @@
+use std::{sync::Arc, time::{Duration, Instant}};
+
+use crate::{packet::{Connect, Disconnect, Packet, Pingresp}, types::ReasonCode};
+
+use super::{schedule, Clock, SystemClock};
+
+/// The server side of a connection, for anything accepting clients such as a broker or a bridge: answers `PINGREQ`
+/// and watches the keep alive the client asked for.
+///
+/// Like the [ClientSession](super::ClientSession), it doesn't read the system time itself but is driven by
+/// [advance](ServerSession::advance).
+///
+/// # Examples
+/// ```
+/// use std::time::{Duration, Instant};
+/// use mqtt::packet::{Connect, Packet, Pingreq};
+/// use mqtt::session::ServerSession;
+/// use mqtt::types::ReasonCode;
+///
+/// let mut session = ServerSession::default();
+/// let start = Instant::now();
+/// session.advance(start);
+///
+/// let mut connect = Connect::default();
+/// connect.keep_alive = 10;
+/// session.on_connect(&connect, None);
+///
+/// let response = session.on_packet_received(&Packet::Pingreq(Pingreq {}));
+/// assert!(matches!(response, Some(Packet::Pingresp(_))));
+///
+/// // nothing more from the client for one and a half times the keep alive
+/// let disconnect = session.advance(start + Duration::from_secs(15)).unwrap();
+/// assert_eq!(ReasonCode::KeepAliveTimeout, disconnect.reason_code);
+/// ```
+#[derive(Debug)]
+pub struct ServerSession {
+    keep_alive: Option<Duration>,
+    /// The time of the last call to [ServerSession::advance].
+    now: Instant,
+    last_received: Instant,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for ServerSession {
+    fn default() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+}
+
+impl ServerSession {
+
+    /// Starts at the time of `clock`, which an event loop should also take the time for
+    /// [advance](ServerSession::advance) from.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        let now = clock.now();
+        Self { keep_alive: None, now, last_received: now, clock }
+    }
+
+    pub fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+
+    /// Starts watching the keep alive of a new connection: the one the client asked for with `CONNECT`, or
+    /// `server_keep_alive` if the server overrides it. In that case the `CONNACK` must tell the client so. A keep
+    /// alive of `0` means the connection never times out.
+    pub fn on_connect(&mut self, connect: &Connect, server_keep_alive: Option<u16>) {
+        self.keep_alive = schedule::keep_alive(server_keep_alive.unwrap_or(connect.keep_alive));
+        self.last_received = self.now;
+    }
+
+    /// Must be called for every packet received from the client. Returns the `PINGRESP` to send for a `PINGREQ`.
+    pub fn on_packet_received(&mut self, packet: &Packet) -> Option<Packet> {
+        self.last_received = self.now;
+        match packet {
+            Packet::Pingreq(_) => Some(Packet::Pingresp(Pingresp {})),
+            _ => None,
+        }
+    }
+
+    /// Moves the session's clock forward to `now`. Returns the `DISCONNECT` to close the connection with if nothing
+    /// has been received from the client for one and a half times the keep alive [MQTT-3.1.2-22].
+    pub fn advance(&mut self, now: Instant) -> Option<Disconnect> {
+        if now > self.now {
+            self.now = now;
+        }
+
+        match self.next_due() {
+            Some(due) if due <= self.now => Some(Disconnect { reason_code: ReasonCode::KeepAliveTimeout, properties: None }),
+            _ => None,
+        }
+    }
+
+    /// When the keep alive runs out unless the client sends something, if there is a keep alive at all.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.keep_alive.map(|k| self.last_received + k + k / 2)
+    }
+
+    /// How long to wait from `now` for the client before calling [ServerSession::advance] again, `None` without a
+    /// keep alive. Never zero, see [ClientSession::time_until_due](super::ClientSession::time_until_due).
+    pub fn time_until_due(&self, now: Instant) -> Option<Duration> {
+        schedule::time_until(self.next_due(), now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{packet::{Pingreq, Publish}, test_util::MockClock};
+
+    use super::*;
+
+    fn connect(keep_alive: u16) -> Connect {
+        let mut connect = Connect::default();
+        connect.keep_alive = keep_alive;
+        connect
+    }
+
+    #[test]
+    fn keep_alive() {
+        let mut session = ServerSession::default();
+        let start = Instant::now();
+        session.advance(start);
+        session.on_connect(&connect(20), None);
+        assert_eq!(Some(start + Duration::from_secs(30)), session.next_due());
+
+        session.advance(start + Duration::from_secs(25));
+        assert_eq!(None, session.on_packet_received(&Publish::new_unchecked("a".into(), vec![]).into()));
+        assert_eq!(None, session.advance(start + Duration::from_secs(54)));
+        assert_eq!(Some(Packet::Pingresp(Pingresp {})), session.on_packet_received(&Packet::Pingreq(Pingreq {})));
+
+        assert_eq!(None, session.advance(start + Duration::from_secs(83)));
+        let disconnect = session.advance(start + Duration::from_secs(84)).unwrap();
+        assert_eq!(ReasonCode::KeepAliveTimeout, disconnect.reason_code);
+    }
+
+    #[test]
+    fn server_keep_alive() {
+        let mut session = ServerSession::default();
+        let start = Instant::now();
+        session.advance(start);
+
+        session.on_connect(&connect(0), Some(10));
+        assert_eq!(Some(start + Duration::from_secs(15)), session.next_due());
+
+        session.on_connect(&connect(10), Some(0));
+        assert_eq!(None, session.next_due());
+        assert_eq!(None, session.advance(start + Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn clock() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let mut session = ServerSession::with_clock(Arc::new(clock.clone()));
+        session.on_connect(&connect(10), None);
+        assert_eq!(Some(start + Duration::from_secs(15)), session.next_due());
+
+        clock.advance(Duration::from_secs(15));
+        assert!(session.advance(session.clock().now()).is_some());
+    }
+
+    #[test]
+    fn keep_alive_disabled() {
+        let mut session = ServerSession::default();
+        let start = Instant::now();
+        session.advance(start);
+        session.on_connect(&connect(0), None);
+        assert_eq!(None, session.next_due());
+        assert_eq!(None, session.time_until_due(start));
+        let later = start + Duration::from_secs(1_000_000);
+        assert_eq!(None, session.advance(later));
+
+        session.on_connect(&connect(2), None);
+        assert_eq!(Some(Duration::from_secs(3)), session.time_until_due(later));
+        assert_eq!(Some(Duration::from_millis(1)), session.time_until_due(later + Duration::from_secs(5)));
+    }
+}
@@ -0,0 +1,419 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{
+    error::MqttError,
+    packet::{Auth, AuthProperties, Connack, ConnackProperties, Connect, Disconnect, DisconnectProperties},
+    types::ReasonCode,
+};
+
+/// Decides whether a client may connect, consulted by [ServerHandshake::accept].
+///
+/// Implemented for closures taking the `CONNECT` and the client identifier, which is the assigned one if the client
+/// didn't send its own.
+pub trait Authenticator {
+    /// Returns the reason code to refuse the connection with if the client may not connect, such as
+    /// [NotAuthorized](ReasonCode::NotAuthorized) or [BadUserNameOrPassword](ReasonCode::BadUserNameOrPassword).
+    fn authenticate(&self, connect: &Connect, client_id: &str) -> Result<(), ReasonCode>;
+}
+
+impl<F> Authenticator for F
+where
+    F: Fn(&Connect, &str) -> Result<(), ReasonCode>,
+{
+    fn authenticate(&self, connect: &Connect, client_id: &str) -> Result<(), ReasonCode> {
+        self(connect, client_id)
+    }
+}
+
+/// What a server imposes on the clients it accepts. Nothing is limited by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerLimits {
+    /// The longest client identifier in bytes that's accepted, longer ones are refused with
+    /// [ClientIdentifierInvalid](ReasonCode::ClientIdentifierInvalid). The spec requires servers to accept at least
+    /// 23 bytes [MQTT-3.1.3-5].
+    pub maximum_client_id_length: Option<usize>,
+
+    /// The longest keep alive in seconds a client may ask for. A client asking for more, or for none at all, is told
+    /// to use this one instead with the `CONNACK`.
+    pub maximum_keep_alive: Option<u16>,
+
+    /// How many QoS 1 and 2 messages the server processes at once, announced with the `CONNACK`.
+    pub receive_maximum: Option<u16>,
+
+    /// Prefix of the client identifiers assigned to clients that don't send one, followed by a number counting up.
+    /// `auto-` if not set. Must make them unique among all clients the server may have sessions for.
+    pub assigned_client_id_prefix: Option<String>,
+
+    /// The authentication methods of enhanced authentication that are accepted. A `CONNECT` naming one of them is
+    /// handed to the [Authenticator] to check its authentication data in a single step, a client asking for any
+    /// other is refused with [BadAuthenticationMethod](ReasonCode::BadAuthenticationMethod). Clients may
+    /// [re-authenticate](ServerReauthentication) with the method they connected with.
+    pub authentication_methods: Vec<String>,
+}
+
+/// A client accepted by [ServerHandshake::accept].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Accepted {
+    /// The client identifier the client sent, or the one assigned to it.
+    pub client_id: String,
+
+    /// The `CONNACK` to send. `session_present` is left for the server to set if it resumes an existing session, and
+    /// any other properties it wants to announce may be added.
+    pub connack: Connack,
+
+    /// The keep alive imposed on the client, if any, to hand to
+    /// [ServerSession::on_connect](super::ServerSession::on_connect).
+    pub server_keep_alive: Option<u16>,
+}
+
+/// The server side of the `CONNECT` handshake: checks a client against the [ServerLimits] and an [Authenticator] and
+/// builds the `CONNACK` to answer with. Assigns client identifiers to clients that leave it to the server.
+///
+/// Meant to be shared by all connections of a server, accepting clients from any number of threads at once. Enhanced
+/// authentication is limited to the [methods](ServerLimits::authentication_methods) completing without a challenge,
+/// clients asking for any other are refused with [BadAuthenticationMethod](ReasonCode::BadAuthenticationMethod)
+/// [MQTT-3.1.4-2 and 4.12].
+///
+/// # Examples
+/// ```
+/// use mqtt::packet::Connect;
+/// use mqtt::session::{ServerHandshake, ServerLimits};
+/// use mqtt::types::ReasonCode;
+///
+/// let limits = ServerLimits { maximum_keep_alive: Some(60), ..Default::default() };
+/// let handshake = ServerHandshake::new(limits, |connect: &Connect, _: &str| match connect.username.as_deref() {
+///     Some("guest") => Ok(()),
+///     _ => Err(ReasonCode::NotAuthorized),
+/// });
+///
+/// let mut connect = Connect::default();
+/// connect.username = Some("guest".into());
+/// let accepted = handshake.accept(&connect).unwrap();
+/// assert_eq!("auto-1", accepted.client_id);
+/// assert_eq!(Some(60), accepted.server_keep_alive);
+///
+/// connect.username = None;
+/// assert_eq!(ReasonCode::NotAuthorized, handshake.accept(&connect).unwrap_err().reason_code);
+/// ```
+#[derive(Debug)]
+pub struct ServerHandshake<A> {
+    limits: ServerLimits,
+    authenticator: A,
+    /// client identifiers assigned so far
+    assigned: AtomicU64,
+}
+
+impl<A: Authenticator> ServerHandshake<A> {
+
+    pub fn new(limits: ServerLimits, authenticator: A) -> Self {
+        Self { limits, authenticator, assigned: AtomicU64::new(0) }
+    }
+
+    pub fn limits(&self) -> &ServerLimits {
+        &self.limits
+    }
+
+    /// Decides about a client that sent `connect`. Returns the client identifier and the `CONNACK` to send if the
+    /// client is accepted, or the `CONNACK` refusing it after which the connection must be closed [MQTT-3.2.2-7].
+    pub fn accept(&self, connect: &Connect) -> Result<Accepted, Box<Connack>> {
+        let refuse = |reason_code| Box::new(Connack { session_present: false, reason_code, properties: None });
+
+        let authentication_method = connect.properties.as_ref().and_then(|p| p.authentication_method.clone());
+        if authentication_method.as_ref().is_some_and(|m| !self.limits.authentication_methods.contains(m)) {
+            return Err(refuse(ReasonCode::BadAuthenticationMethod))
+        }
+
+        let mut properties = ConnackProperties {
+            receive_maximum: self.limits.receive_maximum,
+            authentication_method,
+            ..Default::default()
+        };
+
+        let client_id = match connect.requests_client_id() {
+            true => {
+                let assigned = self.assign_client_id();
+                properties.assigned_client_identifier = Some(assigned.clone());
+                assigned
+            },
+            false => connect.client_id.clone().unwrap_or_default(),
+        };
+        if self.limits.maximum_client_id_length.is_some_and(|max| client_id.len() > max) {
+            return Err(refuse(ReasonCode::ClientIdentifierInvalid))
+        }
+
+        self.authenticator.authenticate(connect, &client_id).map_err(refuse)?;
+
+        let server_keep_alive = self.limits.maximum_keep_alive
+            .filter(|max| connect.keep_alive == 0 || connect.keep_alive > *max);
+        properties.server_keep_alive = server_keep_alive;
+
+        let properties = match properties == ConnackProperties::default() {
+            true => None,
+            false => Some(properties),
+        };
+        let connack = Connack { session_present: false, reason_code: ReasonCode::Success, properties };
+        Ok(Accepted { client_id, connack, server_keep_alive })
+    }
+
+    fn assign_client_id(&self) -> String {
+        let number = self.assigned.fetch_add(1, Ordering::Relaxed) + 1;
+        let prefix = self.limits.assigned_client_id_prefix.as_deref().unwrap_or("auto-");
+        format!("{}{}", prefix, number)
+    }
+}
+
+/// The server side of a re-authentication, started by a connected client with an `AUTH` with reason code 0x19
+/// (re-authenticate). Checks every `AUTH` of the client against the `CONNECT` it connected with and builds the packets
+/// to answer with, deciding about the authentication data is up to the server.
+///
+/// # Examples
+/// ```
+/// use mqtt::packet::{Auth, AuthProperties, Connect, ConnectProperties};
+/// use mqtt::session::ServerReauthentication;
+/// use mqtt::types::ReasonCode;
+///
+/// let mut connect = Connect::default();
+/// connect.properties = Some(ConnectProperties { authentication_method: Some("token".into()), ..Default::default() });
+///
+/// let properties = AuthProperties {
+///     authentication_method: Some("token".into()),
+///     authentication_data: Some(b"renewed".to_vec()),
+///     ..Default::default()
+/// };
+/// let auth = Auth { reason_code: ReasonCode::ReAuthenticate, properties: Some(properties) };
+///
+/// let (reauthentication, data) = ServerReauthentication::start(&connect, &auth).unwrap();
+/// assert_eq!(Some(b"renewed".to_vec()), data);
+/// assert_eq!(ReasonCode::Success, reauthentication.success().reason_code);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerReauthentication {
+    authentication_method: String,
+}
+
+impl ServerReauthentication {
+
+    /// Starts with the `AUTH` the client sent, returning the authentication data to check along with the exchange.
+    ///
+    /// Returns the `DISCONNECT` to close the connection with if the client is not allowed to re-authenticate like
+    /// this: with [BadAuthenticationMethod](ReasonCode::BadAuthenticationMethod) if the method isn't the one of the
+    /// `CONNECT` [MQTT-4.12.1-1], with a `ProtocolError` if the `CONNECT` had none at all [MQTT-4.12.0-7] or the
+    /// reason code isn't 0x19 (re-authenticate).
+    pub fn start(connect: &Connect, auth: &Auth) -> Result<(Self, Option<Vec<u8>>), Disconnect> {
+        let authentication_method = connect.properties.as_ref()
+            .and_then(|p| p.authentication_method.clone())
+            .ok_or_else(|| protocol_error("Re-authentication without an authentication method in CONNECT"))?;
+        let reauthentication = Self { authentication_method };
+        let data = reauthentication.check(auth, ReasonCode::ReAuthenticate)?;
+        Ok((reauthentication, data))
+    }
+
+    /// Checks the client's answer to a [challenge](ServerReauthentication::challenge), returning its authentication
+    /// data or the `DISCONNECT` to close the connection with, like [ServerReauthentication::start].
+    pub fn on_auth(&self, auth: &Auth) -> Result<Option<Vec<u8>>, Disconnect> {
+        self.check(auth, ReasonCode::ContinueAuthentication)
+    }
+
+    pub fn authentication_method(&self) -> &str {
+        &self.authentication_method
+    }
+
+    /// Builds the `AUTH` asking the client for more authentication data.
+    pub fn challenge(&self, authentication_data: Option<Vec<u8>>) -> Auth {
+        self.auth(ReasonCode::ContinueAuthentication, authentication_data)
+    }
+
+    /// Builds the `AUTH` telling the client it was accepted again, which ends the exchange.
+    pub fn success(&self) -> Auth {
+        self.auth(ReasonCode::Success, None)
+    }
+
+    fn check(&self, auth: &Auth, expected: ReasonCode) -> Result<Option<Vec<u8>>, Disconnect> {
+        if auth.reason_code != expected {
+            return Err(protocol_error(&format!("Expected AUTH with {:?}, got {:?}", expected, auth.reason_code)))
+        }
+        let (method, data) = auth.properties.as_ref()
+            .map(|p| (p.authentication_method.as_deref(), p.authentication_data.clone()))
+            .unwrap_or_default();
+        if method != Some(self.authentication_method.as_str()) {
+            let properties = DisconnectProperties {
+                reason_string: Some(format!(
+                    "Authentication method must stay {}, got {:?}", self.authentication_method, method)),
+                ..Default::default()
+            };
+            return Err(Disconnect { reason_code: ReasonCode::BadAuthenticationMethod, properties: Some(properties) })
+        }
+        Ok(data)
+    }
+
+    fn auth(&self, reason_code: ReasonCode, authentication_data: Option<Vec<u8>>) -> Auth {
+        let properties = AuthProperties {
+            authentication_method: Some(self.authentication_method.clone()),
+            authentication_data,
+            ..Default::default()
+        };
+        Auth { reason_code, properties: Some(properties) }
+    }
+}
+
+fn protocol_error(message: &str) -> Disconnect {
+    MqttError::ProtocolError(message.to_string()).to_disconnect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packet::ConnectProperties;
+
+    use super::*;
+
+    fn allow_all(_: &Connect, _: &str) -> Result<(), ReasonCode> {
+        Ok(())
+    }
+
+    fn connect(client_id: &str, keep_alive: u16) -> Connect {
+        let mut connect = Connect::with_client_id_str(client_id).unwrap();
+        connect.keep_alive = keep_alive;
+        connect
+    }
+
+    #[test]
+    fn accept_as_is() {
+        let handshake = ServerHandshake::new(ServerLimits::default(), allow_all);
+        let accepted = handshake.accept(&connect("client", 30)).unwrap();
+        assert_eq!("client", accepted.client_id);
+        assert_eq!(
+            Connack { session_present: false, reason_code: ReasonCode::Success, properties: None },
+            accepted.connack);
+        assert_eq!(None, accepted.server_keep_alive);
+    }
+
+    #[test]
+    fn assign_client_ids() {
+        let limits = ServerLimits { assigned_client_id_prefix: Some("broker-a-".into()), ..Default::default() };
+        let handshake = ServerHandshake::new(limits, allow_all);
+
+        for expected in ["broker-a-1", "broker-a-2"] {
+            let accepted = handshake.accept(&Connect::default()).unwrap();
+            assert_eq!(expected, accepted.client_id);
+            assert_eq!(Some(expected.to_string()), accepted.connack.properties.unwrap().assigned_client_identifier);
+        }
+        let handshake = ServerHandshake::new(ServerLimits::default(), allow_all);
+        assert_eq!("auto-1", handshake.accept(&connect("", 0)).unwrap().client_id);
+    }
+
+    #[test]
+    fn limits() {
+        let limits = ServerLimits {
+            maximum_client_id_length: Some(6),
+            maximum_keep_alive: Some(60),
+            receive_maximum: Some(10),
+            ..Default::default()
+        };
+        let handshake = ServerHandshake::new(limits, allow_all);
+
+        let accepted = handshake.accept(&connect("client", 30)).unwrap();
+        assert_eq!(None, accepted.server_keep_alive);
+        let properties = accepted.connack.properties.unwrap();
+        assert_eq!((Some(10), None), (properties.receive_maximum, properties.server_keep_alive));
+
+        for keep_alive in [0, 61] {
+            let accepted = handshake.accept(&connect("client", keep_alive)).unwrap();
+            assert_eq!(Some(60), accepted.server_keep_alive);
+            assert_eq!(Some(60), accepted.connack.properties.unwrap().server_keep_alive);
+        }
+
+        let refused = handshake.accept(&connect("client-7", 30)).unwrap_err();
+        assert_eq!(ReasonCode::ClientIdentifierInvalid, refused.reason_code);
+    }
+
+    #[test]
+    fn refuse() {
+        let handshake = ServerHandshake::new(ServerLimits::default(), |connect: &Connect, client_id: &str| {
+            match (client_id, connect.password.as_deref()) {
+                ("admin", Some(b"secret")) => Ok(()),
+                ("admin", _) => Err(ReasonCode::BadUserNameOrPassword),
+                _ => Err(ReasonCode::NotAuthorized),
+            }
+        });
+
+        let mut admin = connect("admin", 0);
+        admin.password = Some(b"secret".to_vec());
+        assert!(handshake.accept(&admin).is_ok());
+        admin.password = None;
+        assert_eq!(ReasonCode::BadUserNameOrPassword, handshake.accept(&admin).unwrap_err().reason_code);
+
+        let refused = handshake.accept(&connect("guest", 0)).unwrap_err();
+        assert_eq!(
+            Connack { session_present: false, reason_code: ReasonCode::NotAuthorized, properties: None },
+            *refused);
+
+        // no enhanced authentication
+        let mut enhanced = connect("admin", 0);
+        enhanced.properties = Some(ConnectProperties {
+            authentication_method: Some("SCRAM-SHA-1".into()),
+            ..Default::default()
+        });
+        assert_eq!(ReasonCode::BadAuthenticationMethod, handshake.accept(&enhanced).unwrap_err().reason_code);
+    }
+
+    fn enhanced(method: &str) -> Connect {
+        let mut connect = connect("client", 0);
+        connect.properties = Some(ConnectProperties { authentication_method: Some(method.into()), ..Default::default() });
+        connect
+    }
+
+    fn auth(reason_code: ReasonCode, method: &str, data: &[u8]) -> Auth {
+        let properties = AuthProperties {
+            authentication_method: Some(method.into()),
+            authentication_data: Some(data.to_vec()),
+            ..Default::default()
+        };
+        Auth { reason_code, properties: Some(properties) }
+    }
+
+    #[test]
+    fn authentication_methods() {
+        let limits = ServerLimits { authentication_methods: vec!["token".into()], ..Default::default() };
+        let handshake = ServerHandshake::new(limits, allow_all);
+
+        let accepted = handshake.accept(&enhanced("token")).unwrap();
+        assert_eq!(Some("token".to_string()), accepted.connack.properties.unwrap().authentication_method);
+        assert_eq!(
+            ReasonCode::BadAuthenticationMethod,
+            handshake.accept(&enhanced("SCRAM-SHA-1")).unwrap_err().reason_code);
+    }
+
+    #[test]
+    fn reauthenticate() {
+        let connect = enhanced("token");
+        let (reauthentication, data) =
+            ServerReauthentication::start(&connect, &auth(ReasonCode::ReAuthenticate, "token", b"a")).unwrap();
+        assert_eq!(Some(b"a".to_vec()), data);
+        assert_eq!(auth(ReasonCode::ContinueAuthentication, "token", b"b"), reauthentication.challenge(Some(b"b".to_vec())));
+
+        let answer = auth(ReasonCode::ContinueAuthentication, "token", b"c");
+        assert_eq!(Ok(Some(b"c".to_vec())), reauthentication.on_auth(&answer));
+
+        let success = reauthentication.success();
+        assert_eq!(ReasonCode::Success, success.reason_code);
+        assert_eq!(Some("token".to_string()), success.properties.unwrap().authentication_method);
+    }
+
+    #[test]
+    fn reauthenticate_errors() {
+        let token = enhanced("token");
+
+        let changed = ServerReauthentication::start(&token, &auth(ReasonCode::ReAuthenticate, "other", b"a"));
+        assert_eq!(ReasonCode::BadAuthenticationMethod, changed.unwrap_err().reason_code);
+
+        let (reauthentication, _) =
+            ServerReauthentication::start(&token, &auth(ReasonCode::ReAuthenticate, "token", b"a")).unwrap();
+        let changed = reauthentication.on_auth(&auth(ReasonCode::ContinueAuthentication, "other", b"b"));
+        assert_eq!(ReasonCode::BadAuthenticationMethod, changed.unwrap_err().reason_code);
+        let restarted = reauthentication.on_auth(&auth(ReasonCode::ReAuthenticate, "token", b"b"));
+        assert_eq!(ReasonCode::ProtocolError, restarted.unwrap_err().reason_code);
+
+        let plain = ServerReauthentication::start(&connect("client", 0), &auth(ReasonCode::ReAuthenticate, "token", b""));
+        assert_eq!(ReasonCode::ProtocolError, plain.unwrap_err().reason_code);
+    }
+}
@@ -0,0 +1,62 @@
+use crate::error::MqttError;
+
+use super::Subscription;
+
+/// What of a [ClientSession](super::ClientSession) has to outlive the process to resume the session after a restart,
+/// by connecting with `clean_start` set to `false`. Taken with
+/// [ClientSession::snapshot](super::ClientSession::snapshot) and handed back to
+/// [ClientSession::restore](super::ClientSession::restore).
+///
+/// Outgoing messages in flight aren't part of it, an application that needs them delivered across restarts has to
+/// keep them itself.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct SessionSnapshot {
+    /// The client identifier the session belongs to, whether the client chose it or the server assigned it.
+    pub client_id: Option<String>,
+
+    /// The subscriptions the server granted.
+    pub subscriptions: Vec<Subscription>,
+
+    /// Packet identifiers and topic names of incoming QoS 2 messages still waiting for their `PUBREL`, in the order
+    /// they were received. The server resends the `PUBREL` when the session is resumed, which must then be answered
+    /// with `PUBCOMP` without passing the message on a second time.
+    pub awaiting_pubrel: Vec<(u16, String)>,
+}
+
+/// Where a [SessionSnapshot] is kept between runs, such as a file or a database.
+///
+/// # Examples
+/// ```
+/// use std::cell::RefCell;
+/// use mqtt::error::MqttError;
+/// use mqtt::session::{SessionSnapshot, SessionStore};
+///
+/// #[derive(Default)]
+/// struct InMemory(RefCell<Option<SessionSnapshot>>);
+///
+/// impl SessionStore for InMemory {
+///     fn load(&self) -> Result<Option<SessionSnapshot>, MqttError> {
+///         Ok(self.0.borrow().clone())
+///     }
+///
+///     fn save(&self, snapshot: &SessionSnapshot) -> Result<(), MqttError> {
+///         *self.0.borrow_mut() = Some(snapshot.clone());
+///         Ok(())
+///     }
+/// }
+///
+/// let store = InMemory::default();
+/// assert_eq!(None, store.load().unwrap());
+/// let snapshot = SessionSnapshot { client_id: Some("client".into()), ..Default::default() };
+/// store.save(&snapshot).unwrap();
+/// assert_eq!(Some(snapshot), store.load().unwrap());
+/// ```
+pub trait SessionStore {
+    /// Returns the snapshot saved last, `None` if there is none and the session has to start from scratch.
+    fn load(&self) -> Result<Option<SessionSnapshot>, MqttError>;
+
+    /// Replaces whatever was saved before.
+    fn save(&self, snapshot: &SessionSnapshot) -> Result<(), MqttError>;
+}
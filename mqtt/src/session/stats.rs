@@ -0,0 +1,125 @@
+use std::{fmt::Display, time::Instant};
+
+use crate::packet::PacketType;
+
+/// Number of packets per [PacketType].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PacketCounts {
+    counts: [u64; 15],
+}
+
+impl PacketCounts {
+
+    /// Number of packets of the given type.
+    pub fn get(&self, packet_type: PacketType) -> u64 {
+        self.counts[Self::index(packet_type)]
+    }
+
+    /// Number of packets of all types.
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    fn increment(&mut self, packet_type: PacketType) {
+        self.counts[Self::index(packet_type)] += 1;
+    }
+
+    fn index(packet_type: PacketType) -> usize {
+        packet_type as usize - 1
+    }
+}
+
+/// Counters of a session, collected over the lifetime of a [ClientSession](super::ClientSession) across all of its
+/// connections.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SessionStats {
+    /// Packets sent to the server.
+    pub packets_sent: PacketCounts,
+
+    /// Packets received from the server.
+    pub packets_received: PacketCounts,
+
+    /// Encoded size of all packets sent.
+    pub bytes_sent: u64,
+
+    /// Encoded size of all packets received.
+    pub bytes_received: u64,
+
+    /// Messages sent again after reconnecting, as `PUBLISH` or `PUBREL`.
+    pub retransmissions: u64,
+
+    /// Acknowledgement flows started, of outgoing QoS 1 and 2 messages and incoming QoS 2 ones. Each flow is
+    /// identified by this count as of its start, its [flow ID](super::InFlightMessage::flow_id), which unlike its
+    /// packet identifier isn't used again.
+    pub flows: u64,
+
+    /// QoS 1 and 2 messages that haven't completed their acknowledgement flow yet.
+    pub acks_outstanding: usize,
+
+    /// Messages [queued](super::ClientSession::enqueue) that haven't been released yet.
+    pub queued: usize,
+
+    /// Messages not queued because the [queue limit](super::SessionConfig::queue_limit) was reached.
+    pub dropped: u64,
+
+    /// When the last packet was sent.
+    pub last_sent: Option<Instant>,
+
+    /// When the last packet was received.
+    pub last_received: Option<Instant>,
+}
+
+impl SessionStats {
+
+    pub(crate) fn record_sent(&mut self, packet_type: PacketType, bytes: usize, now: Instant) {
+        self.packets_sent.increment(packet_type);
+        self.bytes_sent += bytes as u64;
+        self.last_sent = Some(now);
+    }
+
+    pub(crate) fn record_received(&mut self, packet_type: PacketType, bytes: usize, now: Instant) {
+        self.packets_received.increment(packet_type);
+        self.bytes_received += bytes as u64;
+        self.last_received = Some(now);
+    }
+}
+
+impl Display for SessionStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "sent: {} packets, {} bytes", self.packets_sent.total(), self.bytes_sent)?;
+        writeln!(f, "received: {} packets, {} bytes", self.packets_received.total(), self.bytes_received)?;
+        write!(f, "flows: {}, retransmissions: {}, acks outstanding: {}",
+            self.flows, self.retransmissions, self.acks_outstanding)?;
+        if self.queued > 0 || self.dropped > 0 {
+            write!(f, "\nqueued: {}, dropped: {}", self.queued, self.dropped)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record() {
+        let mut stats = SessionStats::default();
+        let now = Instant::now();
+        stats.record_sent(PacketType::CONNECT, 20, now);
+        stats.record_sent(PacketType::PUBLISH, 10, now);
+        stats.record_sent(PacketType::PUBLISH, 12, now);
+        stats.record_received(PacketType::AUTH, 5, now);
+
+        assert_eq!(3, stats.packets_sent.total());
+        assert_eq!(2, stats.packets_sent.get(PacketType::PUBLISH));
+        assert_eq!(1, stats.packets_sent.get(PacketType::CONNECT));
+        assert_eq!(0, stats.packets_sent.get(PacketType::AUTH));
+        assert_eq!(42, stats.bytes_sent);
+
+        assert_eq!(1, stats.packets_received.get(PacketType::AUTH));
+        assert_eq!(5, stats.bytes_received);
+
+        assert_eq!(Some(now), stats.last_sent);
+        assert_eq!(Some(now), stats.last_received);
+    }
+}
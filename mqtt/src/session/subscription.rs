@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+
+use crate::{
+    error::MqttError,
+    packet::{Suback, Subscribe, SubscribeProperties, TopicFilter, Unsuback, Unsubscribe},
+    types::{QoS, ReasonCode, VariableByteInteger},
+};
+
+use super::SubscribeOutcome;
+
+/// A subscription the server granted, see [ClientSession::subscriptions](super::ClientSession::subscriptions).
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Subscription {
+    /// The topic filter with the options it was subscribed with, including the maximum QoS asked for.
+    pub topic_filter: TopicFilter,
+
+    /// The maximum QoS the server granted, which may be lower than the one asked for.
+    pub granted_qos: QoS,
+
+    /// The subscription identifier the `SUBSCRIBE` carried, if any.
+    pub subscription_identifier: Option<u32>,
+}
+
+/// The subscriptions of a client, along with the `SUBSCRIBE` and `UNSUBSCRIBE` packets still waiting for their
+/// acknowledgement.
+#[derive(Debug, Default)]
+pub(crate) struct Subscriptions {
+    /// in the order they were first granted
+    active: Vec<Subscription>,
+    /// the topic filters and subscription identifier of each `SUBSCRIBE` sent, by packet identifier
+    subscribing: HashMap<u16, (Vec<TopicFilter>, Option<u32>)>,
+    /// the topic filters of each `UNSUBSCRIBE` sent, by packet identifier
+    unsubscribing: HashMap<u16, Vec<String>>,
+}
+
+impl Subscriptions {
+
+    pub(crate) fn active(&self) -> &[Subscription] {
+        &self.active
+    }
+
+    /// Replaces the subscriptions with ones from a [snapshot](super::SessionSnapshot).
+    pub(crate) fn restore(&mut self, subscriptions: Vec<Subscription>) {
+        self.active = subscriptions;
+    }
+
+    pub(crate) fn on_subscribe(&mut self, subscribe: &Subscribe) {
+        let identifier = subscribe.properties.as_ref()
+            .and_then(|p| p.subscription_identifier)
+            .map(|id| id.value);
+        self.subscribing.insert(subscribe.packet_identifier, (subscribe.topic_filter.clone(), identifier));
+    }
+
+    /// Takes up the topic filters the server granted. A filter subscribed to again replaces the earlier subscription,
+    /// one the server refused leaves it as it was.
+    pub(crate) fn on_suback(&mut self, suback: &Suback) {
+        let Some((filters, subscription_identifier)) = self.subscribing.remove(&suback.packet_identifier) else {
+            return
+        };
+        for (topic_filter, outcome) in filters.into_iter().zip(SubscribeOutcome::from_suback(suback)) {
+            let SubscribeOutcome::Granted(granted_qos) = outcome else {
+                continue
+            };
+            let subscription = Subscription { topic_filter, granted_qos, subscription_identifier };
+            match self.active.iter_mut().find(|s| s.topic_filter.filter == subscription.topic_filter.filter) {
+                Some(existing) => *existing = subscription,
+                None => self.active.push(subscription),
+            }
+        }
+    }
+
+    pub(crate) fn on_unsubscribe(&mut self, unsubscribe: &Unsubscribe) {
+        self.unsubscribing.insert(unsubscribe.packet_identifier, unsubscribe.topic_filter.clone());
+    }
+
+    /// Drops the topic filters the server unsubscribed from, or didn't have a subscription for in the first place.
+    pub(crate) fn on_unsuback(&mut self, unsuback: &Unsuback) {
+        let Some(filters) = self.unsubscribing.remove(&unsuback.packet_identifier) else {
+            return
+        };
+        for (filter, reason_code) in filters.iter().zip(&unsuback.reason_codes) {
+            if matches!(reason_code, ReasonCode::Success | ReasonCode::NoSubscriptionExisted) {
+                self.active.retain(|s| &s.topic_filter.filter != filter);
+            }
+        }
+    }
+
+    /// Forgets the packets waiting for their acknowledgement, which won't arrive on a new connection.
+    pub(crate) fn on_reconnect(&mut self) {
+        self.subscribing.clear();
+        self.unsubscribing.clear();
+    }
+
+    /// One `SUBSCRIBE` for each subscription identifier, split up further if it doesn't fit `maximum_packet_size`.
+    pub(crate) fn resubscribe(
+        &self,
+        maximum_packet_size: Option<u32>,
+        mut packet_identifier: impl FnMut() -> u16,
+    ) -> Result<Vec<Subscribe>, MqttError> {
+        let mut by_identifier: Vec<(Option<u32>, Vec<TopicFilter>)> = Vec::new();
+        for subscription in &self.active {
+            let filter = subscription.topic_filter.clone();
+            match by_identifier.iter_mut().find(|(id, _)| *id == subscription.subscription_identifier) {
+                Some((_, filters)) => filters.push(filter),
+                None => by_identifier.push((subscription.subscription_identifier, vec![filter])),
+            }
+        }
+
+        let mut packets = Vec::new();
+        for (subscription_identifier, topic_filter) in by_identifier {
+            let properties = subscription_identifier.map(|value| SubscribeProperties {
+                subscription_identifier: Some(VariableByteInteger { value }),
+                ..Default::default()
+            });
+            let subscribe = Subscribe { packet_identifier: packet_identifier(), properties, topic_filter };
+            match maximum_packet_size {
+                Some(maximum) => packets.append(&mut subscribe.split(maximum, &mut packet_identifier)?),
+                None => packets.push(subscribe),
+            }
+        }
+        Ok(packets)
+    }
+}
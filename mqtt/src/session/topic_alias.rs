@@ -0,0 +1,470 @@
+//! Topic aliases allow replacing the topic name of a `PUBLISH` with a numeric value, after the mapping has been 
+//! established once per connection.
+//! See [the spec](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901113).
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{error::MqttError, packet::{Publish, PublishProperties}};
+
+/// Defines how a client assigns aliases to the topics it publishes to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TopicAliasPolicy {
+    /// Topic names are always sent in full.
+    Disabled,
+
+    /// Keeps aliases for up to `n` topics (or the server's `topic alias maximum`, whichever is lower). Once all are 
+    /// in use, the alias of the least recently published topic is re-assigned.
+    Lru(u16),
+}
+
+impl Default for TopicAliasPolicy {
+    /// Returns [TopicAliasPolicy::Disabled].
+    fn default() -> Self {
+        TopicAliasPolicy::Disabled
+    }
+}
+
+/// Outgoing topic aliases of a single connection.
+/// 
+/// Aliases are only valid for the lifetime of a network connection, so this needs to be [reset](Self::reset) with the
+/// server's `topic alias maximum` after every `CONNACK`.
+#[derive(Debug)]
+pub struct TopicAliasMap {
+    policy: TopicAliasPolicy,
+
+    /// The highest alias we may use, `0` if aliases must not be used at all.
+    maximum: u16,
+
+    /// Topic name -> (alias, last usage)
+    aliases: HashMap<String, (u16, u64)>,
+
+    /// Aliases assigned to a topic that were never sent after all, to be assigned again before any new one.
+    unused: Vec<u16>,
+
+    /// Incremented with each publish, used to determine the least recently used topic.
+    clock: u64,
+}
+
+impl TopicAliasMap {
+
+    /// Creates an empty map that won't assign any aliases until [reset](Self::reset) with a server maximum.
+    pub fn new(policy: TopicAliasPolicy) -> Self {
+        TopicAliasMap { policy, maximum: 0, aliases: HashMap::new(), unused: Vec::new(), clock: 0 }
+    }
+
+    /// Discards all existing aliases and sets the maximum as advertised by the server. A value of `0` or `None` 
+    /// disables aliases.
+    pub fn reset(&mut self, server_maximum: Option<u16>) {
+        self.aliases.clear();
+        self.unused.clear();
+        self.clock = 0;
+        self.maximum = match self.policy {
+            TopicAliasPolicy::Disabled => 0,
+            TopicAliasPolicy::Lru(n) => n.min(server_maximum.unwrap_or(0)),
+        };
+    }
+
+    /// The highest alias that may be assigned for this connection.
+    pub fn maximum(&self) -> u16 {
+        self.maximum
+    }
+
+    /// Returns the alias currently mapped to `topic`, if any.
+    pub fn get(&self, topic: &str) -> Option<u16> {
+        self.aliases.get(topic).map(|(alias, _)| *alias)
+    }
+
+    /// Applies an alias to an outgoing `PUBLISH`.
+    /// 
+    /// If the topic already has an alias, the topic name is removed from the packet and only the alias is sent. 
+    /// Otherwise a new alias is registered (evicting the least recently used one if necessary) and sent along with 
+    /// the full topic name, so the server learns the mapping.
+    /// 
+    /// Packets that already have an alias set or don't have a topic name are left untouched. An alias set along with
+    /// the topic name is taken over though, so that it isn't assigned to another topic behind the server's back.
+    pub fn apply(&mut self, publish: &mut Publish) {
+        if self.maximum == 0 || publish.topic_name.is_empty() {
+            return
+        }
+
+        if let Some(PublishProperties { topic_alias: Some(alias), .. }) = publish.properties {
+            self.reserve(&publish.topic_name, alias);
+            return
+        }
+
+        self.clock += 1;
+        let alias = match self.aliases.get_mut(&publish.topic_name) {
+            Some((alias, last_used)) => {
+                *last_used = self.clock;
+                publish.topic_name = String::new();
+                *alias
+            },
+            None => {
+                let alias = self.next_alias();
+                self.aliases.insert(publish.topic_name.clone(), (alias, self.clock));
+                alias
+            },
+        };
+
+        publish.properties.get_or_insert_with(PublishProperties::default).topic_alias = Some(alias);
+    }
+
+    /// Undoes the mapping of `topic` to `alias`, for an [applied](Self::apply) `PUBLISH` that was never sent after all.
+    /// Without that, later messages to the topic would only carry an alias the server doesn't know.
+    pub(crate) fn forget(&mut self, topic: &str, alias: u16) {
+        if self.get(topic) == Some(alias) {
+            self.aliases.remove(topic);
+            self.unused.push(alias);
+        }
+    }
+
+    /// Records an alias set explicitly along with `topic`: the server maps it to that topic from now on, whichever
+    /// topic it was assigned to before. Aliases beyond the maximum aren't ours to assign, so they don't matter.
+    fn reserve(&mut self, topic: &str, alias: u16) {
+        if alias == 0 || alias > self.maximum {
+            return
+        }
+        self.clock += 1;
+        self.unused.retain(|unused| *unused != alias);
+        self.aliases.retain(|_, (assigned, _)| *assigned != alias);
+        if let Some((previous, _)) = self.aliases.insert(topic.to_string(), (alias, self.clock)) {
+            self.unused.push(previous);
+        }
+    }
+
+    /// Returns an unused alias, or the one of the least recently used topic after removing its mapping.
+    fn next_alias(&mut self) -> u16 {
+        let assigned: HashSet<u16> = self.aliases.values().map(|(alias, _)| *alias).collect();
+        while let Some(alias) = self.unused.pop() {
+            if !assigned.contains(&alias) {
+                return alias
+            }
+        }
+        // explicitly set aliases may have left gaps
+        if let Some(alias) = (1..=self.maximum).find(|alias| !assigned.contains(alias)) {
+            return alias
+        }
+
+        let lru = self.aliases.iter()
+            .min_by_key(|(_, (_, last_used))| *last_used)
+            .map(|(topic, _)| topic.clone())
+            .expect("maximum is > 0, so there must be at least one alias");
+
+        let (alias, _) = self.aliases.remove(&lru).unwrap();
+        alias
+    }
+}
+
+/// Incoming topic aliases of a single connection, as used by the receiver of a `PUBLISH`: typically a server, but
+/// also a client that advertised a `topic alias maximum` with its `CONNECT`.
+/// 
+/// Resolves aliases back to topic names so that everything beyond the connection itself only ever sees full topics.
+#[derive(Debug)]
+pub struct TopicAliasResolver {
+    /// The `topic alias maximum` advertised to the sender.
+    maximum: u16,
+
+    /// Alias -> topic name
+    topics: HashMap<u16, String>,
+}
+
+impl TopicAliasResolver {
+
+    /// Creates a resolver for a connection, `maximum` being the value advertised to the other side (`0` if the 
+    /// property was not sent).
+    pub fn new(maximum: u16) -> Self {
+        TopicAliasResolver { maximum, topics: HashMap::new() }
+    }
+
+    /// Returns the topic name currently mapped to `alias`, if any.
+    pub fn get(&self, alias: u16) -> Option<&String> {
+        self.topics.get(&alias)
+    }
+
+    /// Processes the topic alias of an incoming `PUBLISH`.
+    /// 
+    /// A packet with both a topic name and an alias (re-)defines the mapping, a packet with only an alias has its topic
+    /// name filled in. Either way, the alias is removed from the properties afterwards, since it has no meaning 
+    /// outside of this connection.
+    /// 
+    /// Returns a [TopicAliasInvalid](MqttError::TopicAliasInvalid) error if the alias is `0`, exceeds the advertised
+    /// maximum or is unknown, and a `ProtocolError` for packets without topic name and alias. A rejected packet is
+    /// left as it was.
+    pub fn resolve(&mut self, publish: &mut Publish) -> Result<(), MqttError> {
+        let alias = match publish.properties.as_ref().and_then(|p| p.topic_alias) {
+            Some(alias) => alias,
+            None => {
+                if publish.topic_name.is_empty() {
+                    return Err(MqttError::ProtocolError("PUBLISH has neither topic name nor topic alias".to_string()))
+                }
+                return Ok(())
+            },
+        };
+
+        if alias == 0 || alias > self.maximum {
+            return Err(MqttError::TopicAliasInvalid(
+                format!("Topic alias {} out of range, maximum is {}", alias, self.maximum)))
+        }
+
+        if publish.topic_name.is_empty() {
+            match self.topics.get(&alias) {
+                Some(topic) => publish.topic_name = topic.clone(),
+                None => return Err(MqttError::TopicAliasInvalid(format!("Unknown topic alias: {}", alias))),
+            }
+        } else {
+            self.topics.insert(alias, publish.topic_name.clone());
+        }
+
+        if let Some(properties) = publish.properties.as_mut() {
+            properties.topic_alias = None;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::types::ReasonCode;
+
+    use super::*;
+
+    #[test]
+    fn disabled_by_default() {
+        let mut map = TopicAliasMap::new(TopicAliasPolicy::default());
+        map.reset(Some(10));
+        assert_eq!(0, map.maximum());
+
+        let mut publish = publish("a/b");
+        map.apply(&mut publish);
+        assert_eq!("a/b", publish.topic_name);
+        assert!(publish.properties.is_none());
+    }
+
+    #[test]
+    fn server_does_not_support_aliases() {
+        let mut map = TopicAliasMap::new(TopicAliasPolicy::Lru(10));
+        map.reset(None);
+        assert_eq!(0, map.maximum());
+
+        map.reset(Some(0));
+        assert_eq!(0, map.maximum());
+    }
+
+    #[test]
+    fn maximum_is_lower_of_policy_and_server() {
+        let mut map = TopicAliasMap::new(TopicAliasPolicy::Lru(10));
+        map.reset(Some(3));
+        assert_eq!(3, map.maximum());
+        map.reset(Some(300));
+        assert_eq!(10, map.maximum());
+    }
+
+    #[test]
+    fn register_then_use() {
+        let mut map = TopicAliasMap::new(TopicAliasPolicy::Lru(10));
+        map.reset(Some(10));
+
+        let mut first = publish("a/b");
+        map.apply(&mut first);
+        assert_eq!("a/b", first.topic_name);
+        assert_eq!(Some(1), first.properties.unwrap().topic_alias);
+
+        let mut second = publish("a/b");
+        map.apply(&mut second);
+        assert_eq!("", second.topic_name);
+        assert_eq!(Some(1), second.properties.unwrap().topic_alias);
+
+        let mut other = publish("c/d");
+        map.apply(&mut other);
+        assert_eq!("c/d", other.topic_name);
+        assert_eq!(Some(2), other.properties.unwrap().topic_alias);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut map = TopicAliasMap::new(TopicAliasPolicy::Lru(2));
+        map.reset(Some(10));
+
+        map.apply(&mut publish("a"));
+        map.apply(&mut publish("b"));
+        map.apply(&mut publish("a"));
+
+        // "b" is the least recently used, so "c" takes over its alias
+        let mut c = publish("c");
+        map.apply(&mut c);
+        assert_eq!("c", c.topic_name);
+        assert_eq!(Some(2), c.properties.unwrap().topic_alias);
+        assert_eq!(None, map.get("b"));
+        assert_eq!(Some(1), map.get("a"));
+    }
+
+    #[test]
+    fn forget() {
+        let mut map = TopicAliasMap::new(TopicAliasPolicy::Lru(10));
+        map.reset(Some(10));
+        let mut a = publish("a");
+        let mut b = publish("b");
+        map.apply(&mut a);
+        map.apply(&mut b);
+
+        // a isn't known to the server, so it gets an alias along with its topic name again
+        map.forget("a", 1);
+        assert_eq!(None, map.get("a"));
+        let mut c = publish("c");
+        map.apply(&mut c);
+        assert_eq!(Some(1), map.get("c"));
+        let mut a = publish("a");
+        map.apply(&mut a);
+        assert_eq!(("a", Some(3)), (a.topic_name.as_str(), a.properties.unwrap().topic_alias));
+
+        // only the current mapping
+        map.forget("b", 1);
+        assert_eq!(Some(2), map.get("b"));
+    }
+
+    #[test]
+    fn explicit_alias_untouched() {
+        let mut map = TopicAliasMap::new(TopicAliasPolicy::Lru(2));
+        map.reset(Some(10));
+
+        let mut publish = publish("a/b");
+        publish.properties = Some(PublishProperties { topic_alias: Some(7), ..Default::default() });
+        map.apply(&mut publish);
+        assert_eq!("a/b", publish.topic_name);
+        assert_eq!(Some(7), publish.properties.unwrap().topic_alias);
+        assert_eq!(None, map.get("a/b"));
+    }
+
+    #[test]
+    fn explicit_alias_reserved() {
+        let mut map = TopicAliasMap::new(TopicAliasPolicy::Lru(3));
+        map.reset(Some(10));
+        map.apply(&mut publish("a"));
+        map.apply(&mut aliased("b", 2));
+        assert_eq!(Some(2), map.get("b"));
+
+        // 2 is taken, and all aliases once "c" has the last one
+        let mut c = publish("c");
+        map.apply(&mut c);
+        assert_eq!(Some(3), c.properties.unwrap().topic_alias);
+        let mut d = publish("d");
+        map.apply(&mut d);
+        assert_eq!(Some(1), d.properties.unwrap().topic_alias);
+        assert_eq!(None, map.get("a"));
+
+        // an explicit alias remaps the topic that had it
+        map.apply(&mut aliased("e", 1));
+        assert_eq!((None, Some(1)), (map.get("d"), map.get("e")));
+
+        // a topic moved to another alias frees its previous one
+        map.apply(&mut aliased("b", 3));
+        assert_eq!((Some(3), None), (map.get("b"), map.get("c")));
+        let mut f = publish("f");
+        map.apply(&mut f);
+        assert_eq!(Some(2), f.properties.unwrap().topic_alias);
+
+        // the reserved mapping is used like any other
+        let mut b = publish("b");
+        map.apply(&mut b);
+        assert_eq!(("", Some(3)), (b.topic_name.as_str(), b.properties.unwrap().topic_alias));
+    }
+
+    #[test]
+    fn reset_discards_aliases() {
+        let mut map = TopicAliasMap::new(TopicAliasPolicy::Lru(2));
+        map.reset(Some(10));
+        map.apply(&mut publish("a/b"));
+        assert_eq!(Some(1), map.get("a/b"));
+
+        map.reset(Some(10));
+        assert_eq!(None, map.get("a/b"));
+    }
+
+    #[test]
+    fn resolve_without_alias() {
+        let mut resolver = TopicAliasResolver::new(5);
+        let mut publish = publish("a/b");
+        assert!(resolver.resolve(&mut publish).is_ok());
+        assert_eq!("a/b", publish.topic_name);
+
+        assert_eq!(
+            Some(MqttError::ProtocolError("PUBLISH has neither topic name nor topic alias".to_string())), 
+            resolver.resolve(&mut self::publish("")).err());
+    }
+
+    #[test]
+    fn resolve_registered_alias() {
+        let mut resolver = TopicAliasResolver::new(5);
+
+        let mut register = aliased("a/b", 3);
+        resolver.resolve(&mut register).unwrap();
+        assert_eq!("a/b", register.topic_name);
+        assert_eq!(None, register.properties.unwrap().topic_alias);
+        assert_eq!(Some(&"a/b".to_string()), resolver.get(3));
+
+        let mut aliased = aliased("", 3);
+        resolver.resolve(&mut aliased).unwrap();
+        assert_eq!("a/b", aliased.topic_name);
+        assert_eq!(None, aliased.properties.unwrap().topic_alias);
+    }
+
+    #[test]
+    fn resolve_redefined_alias() {
+        let mut resolver = TopicAliasResolver::new(5);
+        resolver.resolve(&mut aliased("a/b", 1)).unwrap();
+        resolver.resolve(&mut aliased("c/d", 1)).unwrap();
+
+        let mut publish = aliased("", 1);
+        resolver.resolve(&mut publish).unwrap();
+        assert_eq!("c/d", publish.topic_name);
+    }
+
+    #[test]
+    fn resolve_invalid_alias() {
+        let mut resolver = TopicAliasResolver::new(5);
+        assert_eq!(
+            Some(MqttError::TopicAliasInvalid("Topic alias 0 out of range, maximum is 5".to_string())), 
+            resolver.resolve(&mut aliased("a/b", 0)).err());
+        assert_eq!(
+            Some(MqttError::TopicAliasInvalid("Topic alias 6 out of range, maximum is 5".to_string())), 
+            resolver.resolve(&mut aliased("a/b", 6)).err());
+        assert_eq!(
+            Some(MqttError::TopicAliasInvalid("Unknown topic alias: 2".to_string())), 
+            resolver.resolve(&mut aliased("", 2)).err());
+
+        // a rejected packet keeps its alias
+        let mut unknown = aliased("", 2);
+        let error = resolver.resolve(&mut unknown).unwrap_err();
+        assert_eq!(Some(2), unknown.properties.unwrap().topic_alias);
+        assert_eq!(ReasonCode::TopicAliasInvalid, error.to_disconnect().reason_code);
+
+        // aliases are not allowed at all if we didn't advertise a maximum
+        let mut no_aliases = TopicAliasResolver::new(0);
+        assert!(no_aliases.resolve(&mut aliased("a/b", 1)).is_err());
+    }
+
+    #[test]
+    fn client_and_server_round_trip() {
+        let mut client = TopicAliasMap::new(TopicAliasPolicy::Lru(2));
+        client.reset(Some(2));
+        let mut server = TopicAliasResolver::new(2);
+
+        for topic in ["a", "b", "a", "c", "a", "b", "b"] {
+            let mut publish = publish(topic);
+            client.apply(&mut publish);
+            server.resolve(&mut publish).unwrap();
+            assert_eq!(topic, publish.topic_name);
+        }
+    }
+
+    fn aliased(topic: &str, alias: u16) -> Publish {
+        let mut publish = publish(topic);
+        publish.properties = Some(PublishProperties { topic_alias: Some(alias), ..Default::default() });
+        publish
+    }
+
+    fn publish(topic: &str) -> Publish {
+        Publish::new_unchecked(topic.into(), vec![1, 2, 3])
+    }
+}
@@ -0,0 +1,88 @@
+//! Counts heap allocations, so tests can hold hot code paths like decoding a `PUBLISH` to a budget and notice when a
+//! change makes them allocate more.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// A global allocator that counts allocations on each thread before handing them to the [System] allocator. Needs
+/// to be installed by the test binary, only one global allocator can be in place:
+///
+/// ```
+/// use mqtt::test_util::CountingAllocator;
+///
+/// #[global_allocator]
+/// static ALLOCATOR: CountingAllocator = CountingAllocator;
+/// ```
+///
+/// Counting is per thread, tests running in parallel don't skew each other's numbers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// Growing an allocation counts as another one, it may well have to move.
+fn count() {
+    // the thread local may already be gone while a thread shuts down
+    let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+}
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(Cell::get)
+}
+
+/// Runs `f` and counts the allocations it makes on the current thread, including those of values it returns.
+///
+/// Panics if the [CountingAllocator] isn't installed, rather than reporting zero allocations for everything.
+pub fn count_allocations<R>(f: impl FnOnce() -> R) -> (R, usize) {
+    let before = allocations();
+    drop(std::hint::black_box(Box::new(0u8)));
+    assert!(allocations() > before, "CountingAllocator must be installed as the #[global_allocator]");
+
+    let before = allocations();
+    let result = f();
+    (result, allocations() - before)
+}
+
+/// Runs `f` like [count_allocations] and panics if it allocates more than `budget` times. Returns what `f` returned.
+///
+/// ```no_run
+/// use mqtt::packet::Packet;
+/// use mqtt::test_util::assert_allocations;
+///
+/// let bytes = [0x30, 0x06, 0x00, 0x01, b'a', 0x00, b'h', b'i'];
+/// let packet = assert_allocations(3, "QoS 0 PUBLISH decode", || Packet::try_from(&bytes[..]));
+/// assert!(packet.is_ok());
+/// ```
+pub fn assert_allocations<R>(budget: usize, what: &str, f: impl FnOnce() -> R) -> R {
+    let (result, allocations) = count_allocations(f);
+    assert!(
+        allocations <= budget,
+        "{} made {} allocations, more than its budget of {}",
+        what, allocations, budget);
+    result
+}
@@ -0,0 +1,273 @@
+//! A scripted stand-in for a broker, for testing client code without a network.
+
+use std::{
+    io::{Read, Write},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    error::MqttError,
+    packet::{Packet, PacketIter, PacketType},
+    transport::{MemoryTransport, Transport},
+};
+
+use super::normalize;
+
+/// How long the broker waits for an expected packet unless [MockBroker::timeout] says otherwise.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Plays a fixed script of packets it expects from a client and packets it sends back, over a [MemoryTransport].
+///
+/// The script runs step by step on a thread of its own once [spawned](MockBroker::spawn). Anything unexpected ends it
+/// with an error, available from [MockHandle::join]. When the script is done, the broker closes the connection.
+///
+/// # Examples
+/// ```
+/// use std::io::{Read, Write};
+/// use mqtt::packet::{Connack, Connect, PacketType};
+/// use mqtt::test_util::MockBroker;
+/// use mqtt::types::ReasonCode;
+///
+/// let (mut client, broker) = MockBroker::new()
+///     .expect(PacketType::CONNECT)
+///     .send(Connack { session_present: false, reason_code: ReasonCode::Success, properties: None })
+///     .spawn();
+///
+/// client.write_all(&Vec::try_from(Connect::default()).unwrap()).unwrap();
+/// let mut connack = Vec::new();
+/// client.read_to_end(&mut connack).unwrap();
+/// assert_eq!(vec![0x20, 0x03, 0x00, 0x00, 0x00], connack);
+///
+/// broker.join().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct MockBroker {
+    script: Vec<Step>,
+    timeout: Duration,
+}
+
+/// The running script of a [MockBroker].
+#[derive(Debug)]
+pub struct MockHandle {
+    thread: JoinHandle<Result<(), MqttError>>,
+}
+
+#[derive(Debug)]
+enum Step {
+    Expect(PacketType),
+    ExpectPacket(Box<Packet>),
+    Send(Vec<u8>),
+}
+
+impl MockBroker {
+
+    pub fn new() -> Self {
+        Self { script: Vec::new(), timeout: DEFAULT_TIMEOUT }
+    }
+
+    /// Waits for the next packet from the client, which must be of the given type.
+    pub fn expect(mut self, packet_type: PacketType) -> Self {
+        self.script.push(Step::Expect(packet_type));
+        self
+    }
+
+    /// Waits for the next packet from the client, which must be equal to `packet` as per
+    /// [assert_packet_eq](crate::assert_packet_eq).
+    pub fn expect_packet<P: Into<Packet>>(mut self, packet: P) -> Self {
+        self.script.push(Step::ExpectPacket(Box::new(normalize(packet.into()))));
+        self
+    }
+
+    /// Sends a packet to the client.
+    ///
+    /// # Panics
+    /// If the packet can't be encoded.
+    pub fn send<P: Into<Packet>>(self, packet: P) -> Self {
+        let bytes = Vec::try_from(packet.into()).expect("packet to send must be encodable");
+        self.send_bytes(&bytes)
+    }
+
+    /// Sends raw bytes to the client, which don't need to be a valid packet or even a complete one.
+    pub fn send_bytes(mut self, bytes: &[u8]) -> Self {
+        self.script.push(Step::Send(bytes.to_vec()));
+        self
+    }
+
+    /// Sets how long to wait for each expected packet before giving up, 5 seconds by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Starts playing the script, returning the client's end of the connection.
+    pub fn spawn(self) -> (MemoryTransport, MockHandle) {
+        let (client, server) = MemoryTransport::pair();
+        let thread = thread::spawn(move || self.run(server));
+        (client, MockHandle { thread })
+    }
+
+    fn run(self, mut stream: MemoryTransport) -> Result<(), MqttError> {
+        stream.set_read_timeout(Some(self.timeout))
+            .map_err(|e| MqttError::Message(format!("cannot set read timeout: {}", e)))?;
+        let mut inbound = Vec::new();
+
+        for (index, step) in self.script.into_iter().enumerate() {
+            let fail = |message: String| MqttError::Message(format!("step {}: {}", index + 1, message));
+            match step {
+                Step::Expect(packet_type) => {
+                    let packet = receive(&mut stream, &mut inbound).map_err(fail)?;
+                    if packet.packet_type() != packet_type {
+                        return Err(fail(format!("expected {}, got {:?}", packet_type, packet)))
+                    }
+                },
+                Step::ExpectPacket(expected) => {
+                    let packet = normalize(receive(&mut stream, &mut inbound).map_err(fail)?);
+                    if packet != *expected {
+                        return Err(fail(format!("expected {:?}, got {:?}", expected, packet)))
+                    }
+                },
+                Step::Send(bytes) => {
+                    stream.write_all(&bytes).map_err(|e| fail(format!("cannot send: {}", e)))?;
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for MockBroker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockHandle {
+
+    /// Waits for the script to end, returning the first thing that didn't go according to it.
+    pub fn join(self) -> Result<(), MqttError> {
+        self.thread.join().unwrap_or_else(|_| Err(MqttError::Message("mock broker panicked".into())))
+    }
+}
+
+fn receive(stream: &mut MemoryTransport, inbound: &mut Vec<u8>) -> Result<Packet, String> {
+    loop {
+        let mut packets = PacketIter::new(inbound);
+        let next = packets.next();
+        let consumed = inbound.len() - packets.remaining();
+
+        match next {
+            Some(Ok((packet, _))) => {
+                inbound.drain(..consumed);
+                return Ok(packet)
+            },
+            Some(Err(e)) => return Err(format!("invalid packet: {}", e)),
+            None => {},
+        }
+
+        let mut buff = [0; 1024];
+        match stream.read(&mut buff) {
+            Ok(0) => return Err("connection closed by the client".into()),
+            Ok(len) => inbound.extend_from_slice(&buff[..len]),
+            Err(e) => return Err(format!("nothing received: {}", e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        packet::{Connack, Connect, Puback, Publish},
+        types::{QoS, ReasonCode},
+    };
+
+    use super::*;
+
+    fn connack() -> Connack {
+        Connack { session_present: false, reason_code: ReasonCode::Success, properties: None }
+    }
+
+    fn read_packet(stream: &mut MemoryTransport) -> Packet {
+        let mut inbound = Vec::new();
+        receive(stream, &mut inbound).unwrap()
+    }
+
+    #[test]
+    #[cfg(feature = "session")]
+    fn session_exchange() {
+        use crate::session::ClientSession;
+
+        let mut expected = Publish::new_unchecked("a/b".into(), vec![1, 2]);
+        expected.qos_level = QoS::AtLeastOnce;
+        expected.packet_identifier = Some(1);
+
+        let (mut client, broker) = MockBroker::new()
+            .expect(PacketType::CONNECT)
+            .send(connack())
+            .expect_packet(expected)
+            .send(Puback { packet_identifier: 1, reason_code: ReasonCode::Success, properties: None })
+            .spawn();
+
+        let mut session = ClientSession::default();
+        client.write_all(&Vec::try_from(Connect::default()).unwrap()).unwrap();
+        match read_packet(&mut client) {
+            Packet::Connack(connack) => session.on_connack(&connack),
+            other => panic!("expected CONNACK, got {:?}", other),
+        }
+
+        let mut publish = Publish::new_unchecked("a/b".into(), vec![1, 2]);
+        publish.qos_level = QoS::AtLeastOnce;
+        publish.packet_identifier = Some(1);
+        session.prepare_publish(&mut publish).unwrap();
+        client.write_all(&Vec::try_from(publish).unwrap()).unwrap();
+        assert_eq!(1, session.in_flight().count());
+
+        match read_packet(&mut client) {
+            Packet::Puback(puback) => session.on_puback(&puback).unwrap(),
+            other => panic!("expected PUBACK, got {:?}", other),
+        }
+        assert_eq!(0, session.in_flight().count());
+
+        broker.join().unwrap();
+    }
+
+    #[test]
+    fn unexpected_packet() {
+        let (mut client, broker) = MockBroker::new()
+            .expect(PacketType::CONNECT)
+            .send(connack())
+            .expect(PacketType::SUBSCRIBE)
+            .spawn();
+
+        client.write_all(&Vec::try_from(Connect::default()).unwrap()).unwrap();
+        client.write_all(&crate::packet::Pingreq::BYTES).unwrap();
+
+        let error = broker.join().unwrap_err().to_string();
+        assert!(error.contains("step 3"), "{}", error);
+    }
+
+    #[test]
+    fn client_goes_away() {
+        let (client, broker) = MockBroker::new().expect(PacketType::CONNECT).spawn();
+        drop(client);
+        assert!(broker.join().is_err());
+    }
+
+    #[test]
+    fn nothing_arrives() {
+        let (_client, broker) = MockBroker::new()
+            .timeout(Duration::from_millis(10))
+            .expect(PacketType::CONNECT)
+            .spawn();
+        assert!(broker.join().is_err());
+    }
+
+    #[test]
+    fn raw_bytes() {
+        let (mut client, broker) = MockBroker::new().send_bytes(&[0xD0]).send_bytes(&[0x00]).spawn();
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).unwrap();
+        assert_eq!(vec![0xD0, 0x00], received);
+        broker.join().unwrap();
+    }
+}
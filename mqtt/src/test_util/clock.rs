@@ -0,0 +1,110 @@
+//! A clock for tests to advance themselves, instead of sleeping until a keep alive or timeout runs out.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::session::Clock;
+
+/// A [Clock] that stands still until told to move. Clones share the same time, so a test can hand one to a session
+/// and keep another to advance. Its [system time](Clock::system_time) moves along with it from the system time it was
+/// created at.
+///
+/// ```
+/// use std::{sync::Arc, time::Duration};
+/// use mqtt::session::{ClientSession, Clock, DueAction, SessionConfig};
+/// use mqtt::test_util::MockClock;
+///
+/// let clock = MockClock::new();
+/// let config = SessionConfig { keep_alive: Some(10), clock: Some(Arc::new(clock.clone())), ..Default::default() };
+/// let mut session = ClientSession::new(config);
+/// # session.on_connack(&mqtt::packet::Connack {
+/// #     session_present: false, reason_code: mqtt::types::ReasonCode::Success, properties: None });
+///
+/// clock.advance(Duration::from_secs(10));
+/// assert!(matches!(session.advance(clock.now())[..], [DueAction::SendPingreq]));
+/// ```
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Arc<Mutex<Instant>>,
+    /// the time it started at by both clocks
+    start: (Instant, SystemTime),
+}
+
+impl MockClock {
+
+    /// Starts at the current system time, which is just as good as any other point in time.
+    pub fn new() -> Self {
+        Self::starting_at(Instant::now())
+    }
+
+    pub fn starting_at(start: Instant) -> Self {
+        Self { now: Arc::new(Mutex::new(start)), start: (start, SystemTime::now()) }
+    }
+
+    /// Moves the time forward by `duration` for all clones.
+    pub fn advance(&self, duration: Duration) {
+        *self.lock() += duration;
+    }
+
+    /// Sets the time for all clones, which may also move it backwards.
+    pub fn set(&self, now: Instant) {
+        *self.lock() = now;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Instant> {
+        // a test that panicked while holding the lock can't have left the time half-updated
+        self.now.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.lock()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        let (start, system_start) = self.start;
+        let now = self.now();
+        match now.checked_duration_since(start) {
+            Some(elapsed) => system_start + elapsed,
+            None => system_start - start.duration_since(now),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_between_clones() {
+        let start = Instant::now();
+        let clock = MockClock::starting_at(start);
+        let clone = clock.clone();
+        assert_eq!(start, clone.now());
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(start + Duration::from_secs(5), clone.now());
+
+        clone.set(start);
+        assert_eq!(start, clock.now());
+    }
+
+    #[test]
+    fn system_time_moves_along() {
+        let clock = MockClock::new();
+        let start = clock.system_time();
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(start + Duration::from_secs(5), clock.system_time());
+        clock.set(clock.now() - Duration::from_secs(7));
+        assert_eq!(start - Duration::from_secs(2), clock.system_time());
+    }
+}
@@ -0,0 +1,284 @@
+//! Scripted QoS 1 and 2 flows against a client session, for testing acknowledgement handling without a connection.
+
+use crate::{
+    error::MqttError,
+    packet::{Connack, Packet, Publish},
+    session::{ClientSession, FlowState, SessionConfig},
+    types::{QoS, ReasonCode},
+};
+
+use super::normalize;
+
+/// Plays a fixed script of packets through a [ClientSession] and checks what the session makes of them: the packets
+/// it answers with, the errors it returns, the messages it completes and those it passes on to the application.
+///
+/// Actions are [publish](FlowSim::publish), [receive](FlowSim::receive) and [reconnect](FlowSim::reconnect). What
+/// follows an action checks its outcome, until the next action comes along. An action failing without a
+/// [fails_with](FlowSim::fails_with) after it ends the script, packets sent in answer that aren't checked with
+/// [emits](FlowSim::emits) are fine.
+///
+/// The session starts out connected, with a `CONNACK` that doesn't limit anything.
+///
+/// # Examples
+/// ```
+/// use mqtt::packet::{Publish, Pubcomp, Pubrec, Pubrel};
+/// use mqtt::session::FlowState;
+/// use mqtt::test_util::FlowSim;
+/// use mqtt::types::{QoS, ReasonCode};
+///
+/// let publish = Publish::builder("a/b").qos(QoS::ExactlyOnce).packet_identifier(1).build().unwrap();
+/// FlowSim::new()
+///     .publish(publish.clone())
+///     .emits([publish])
+///     .receive(Pubrec::new(1, ReasonCode::Success).unwrap())
+///     .emits([Pubrel::new(1, ReasonCode::Success).unwrap()])
+///     .in_flight(&[(1, FlowState::AwaitingPubcomp)])
+///     .receive(Pubcomp::new(1, ReasonCode::Success).unwrap())
+///     .emits_nothing()
+///     .completes(&[1])
+///     .run()
+///     .unwrap();
+/// ```
+#[derive(Debug, Default)]
+pub struct FlowSim {
+    config: SessionConfig,
+    script: Vec<Step>,
+}
+
+#[derive(Debug)]
+enum Step {
+    Publish(Box<Publish>),
+    Receive(Box<Packet>),
+    Reconnect { session_present: bool },
+    Emits(Vec<Packet>),
+    FailsWith(MqttError),
+    Completes(Vec<u16>),
+    Delivers(Vec<u16>),
+    InFlight(Vec<(u16, FlowState)>),
+}
+
+impl FlowSim {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs the script against a session created with `config` instead of the default one.
+    pub fn with_config(config: SessionConfig) -> Self {
+        Self { config, script: Vec::new() }
+    }
+
+    /// The client sends a message, [prepared](ClientSession::prepare_publish) by the session. What it emits is the
+    /// message as it goes out.
+    pub fn publish(mut self, publish: Publish) -> Self {
+        self.script.push(Step::Publish(Box::new(publish)));
+        self
+    }
+
+    /// A packet arrives from the server: a `PUBLISH` or one of the acknowledgements, anything else fails the script.
+    /// What the session emits is its answer, if any.
+    pub fn receive<P: Into<Packet>>(mut self, packet: P) -> Self {
+        self.script.push(Step::Receive(Box::new(packet.into())));
+        self
+    }
+
+    /// The client connects again and the server says whether it still has the session. What the session emits is
+    /// what it [redelivers](ClientSession::redeliver).
+    pub fn reconnect(mut self, session_present: bool) -> Self {
+        self.script.push(Step::Reconnect { session_present });
+        self
+    }
+
+    /// The last action succeeded and the session emitted exactly `packets`, compared as per
+    /// [assert_packet_eq](crate::assert_packet_eq).
+    pub fn emits<P: Into<Packet>>(mut self, packets: impl IntoIterator<Item = P>) -> Self {
+        self.script.push(Step::Emits(packets.into_iter().map(|p| normalize(p.into())).collect()));
+        self
+    }
+
+    /// The last action succeeded without the session emitting anything.
+    pub fn emits_nothing(mut self) -> Self {
+        self.script.push(Step::Emits(Vec::new()));
+        self
+    }
+
+    /// The last action failed with `error`.
+    pub fn fails_with(mut self, error: MqttError) -> Self {
+        self.script.push(Step::FailsWith(error));
+        self
+    }
+
+    /// The outgoing messages with these packet identifiers were [completed](ClientSession::completed) since the last
+    /// check, in this order.
+    pub fn completes(mut self, packet_identifiers: &[u16]) -> Self {
+        self.script.push(Step::Completes(packet_identifiers.to_vec()));
+        self
+    }
+
+    /// The incoming messages with these packet identifiers were passed on to the application since the last check,
+    /// in this order. A QoS 2 message received again while awaiting its `PUBREL` isn't passed on a second time. QoS 0
+    /// messages count with packet identifier `0`.
+    pub fn delivers(mut self, packet_identifiers: &[u16]) -> Self {
+        self.script.push(Step::Delivers(packet_identifiers.to_vec()));
+        self
+    }
+
+    /// The messages [in flight](ClientSession::in_flight) are exactly these, in this order.
+    pub fn in_flight(mut self, messages: &[(u16, FlowState)]) -> Self {
+        self.script.push(Step::InFlight(messages.to_vec()));
+        self
+    }
+
+    /// Plays the script, returning the session as it is at the end for further checks. The error names the step that
+    /// didn't go as scripted, counting from 1.
+    pub fn run(self) -> Result<ClientSession, MqttError> {
+        let mut session = ClientSession::new(self.config);
+        session.on_connack(&connack(false));
+
+        // the outcome of the last action that hasn't been checked yet, along with its step
+        let mut outcome: Option<(usize, Result<Vec<Packet>, MqttError>)> = None;
+        let mut delivered = Vec::new();
+
+        for (index, step) in self.script.into_iter().enumerate() {
+            let number = index + 1;
+            let fail = |message: String| MqttError::Message(format!("Step {}: {}", number, message));
+
+            let result = match step {
+                Step::Publish(mut publish) => {
+                    session.prepare_publish(&mut publish).map(|_| vec![Packet::from(*publish)])
+                },
+                Step::Receive(packet) => match receive(&mut session, *packet, &mut delivered) {
+                    Err(ReceiveError::Unexpected(packet)) => {
+                        return Err(fail(format!("{} is not part of a QoS flow", packet.packet_type())))
+                    },
+                    Err(ReceiveError::Failed(e)) => Err(e),
+                    Ok(answer) => Ok(answer),
+                },
+                Step::Reconnect { session_present } => {
+                    session.on_connack(&connack(session_present));
+                    Ok(session.redeliver())
+                },
+                Step::Emits(expected) => {
+                    match outcome.take() {
+                        Some((_, Ok(emitted))) => {
+                            let emitted: Vec<Packet> = emitted.into_iter().map(normalize).collect();
+                            if emitted != expected {
+                                return Err(fail(format!("expected {:?}, emitted {:?}", expected, emitted)))
+                            }
+                        },
+                        Some((_, Err(e))) => return Err(fail(format!("expected {:?}, failed with {}", expected, e))),
+                        None => return Err(fail("nothing to check, no action since the last check".to_string())),
+                    }
+                    continue
+                },
+                Step::FailsWith(expected) => {
+                    match outcome.take() {
+                        Some((_, Err(e))) if e == expected => {},
+                        Some((_, Err(e))) => return Err(fail(format!("expected {}, failed with {}", expected, e))),
+                        Some((_, Ok(emitted))) => {
+                            return Err(fail(format!("expected {}, succeeded emitting {:?}", expected, emitted)))
+                        },
+                        None => return Err(fail("nothing to check, no action since the last check".to_string())),
+                    }
+                    continue
+                },
+                Step::Completes(expected) => {
+                    let completed = session.completed();
+                    if completed != expected {
+                        return Err(fail(format!("expected {:?} completed, got {:?}", expected, completed)))
+                    }
+                    continue
+                },
+                Step::Delivers(expected) => {
+                    let delivered: Vec<u16> = std::mem::take(&mut delivered);
+                    if delivered != expected {
+                        return Err(fail(format!("expected {:?} delivered, got {:?}", expected, delivered)))
+                    }
+                    continue
+                },
+                Step::InFlight(expected) => {
+                    let in_flight: Vec<(u16, FlowState)> =
+                        session.in_flight().map(|m| (m.packet_identifier, m.state)).collect();
+                    if in_flight != expected {
+                        return Err(fail(format!("expected {:?} in flight, got {:?}", expected, in_flight)))
+                    }
+                    continue
+                },
+            };
+
+            // a new action, the previous one's outcome is no longer checked
+            if let Some((step, Err(e))) = outcome.replace((number, result)) {
+                return Err(MqttError::Message(format!("Step {}: failed with {}", step, e)))
+            }
+        }
+
+        match outcome {
+            Some((step, Err(e))) => Err(MqttError::Message(format!("Step {}: failed with {}", step, e))),
+            _ => Ok(session),
+        }
+    }
+}
+
+enum ReceiveError {
+    Unexpected(Box<Packet>),
+    Failed(MqttError),
+}
+
+/// Hands a packet from the server to the session the way a client's event loop would, returning the answer.
+fn receive(
+    session: &mut ClientSession,
+    packet: Packet,
+    delivered: &mut Vec<u16>,
+) -> Result<Vec<Packet>, ReceiveError> {
+    let answer = match packet {
+        Packet::Publish(publish) => {
+            let packet_identifier = publish.packet_identifier.unwrap_or(0);
+            let redelivery = publish.qos_level == QoS::ExactlyOnce && session.is_awaiting_pubrel(packet_identifier);
+            let answer = session.on_incoming_publish(&publish);
+            if !redelivery {
+                delivered.push(packet_identifier);
+            }
+            Ok(answer)
+        },
+        Packet::Puback(puback) => session.on_puback(&puback).map(|_| None),
+        Packet::Pubrec(pubrec) => session.on_pubrec(&pubrec).map(|pubrel| pubrel.map(Packet::from)),
+        Packet::Pubcomp(pubcomp) => session.on_pubcomp(&pubcomp).map(|_| None),
+        Packet::Pubrel(pubrel) => session.on_pubrel(&pubrel).map(|pubcomp| Some(pubcomp.into())),
+        other => return Err(ReceiveError::Unexpected(Box::new(other))),
+    };
+    answer.map(|answer| answer.into_iter().collect()).map_err(ReceiveError::Failed)
+}
+
+fn connack(session_present: bool) -> Connack {
+    Connack { session_present, reason_code: ReasonCode::Success, properties: None }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packet::{Pingresp, Puback};
+
+    use super::*;
+
+    #[test]
+    fn failing_steps() {
+        let publish = Publish::builder("a").qos(QoS::AtLeastOnce).packet_identifier(1).build().unwrap();
+        let message = |result: Result<ClientSession, MqttError>| match result {
+            Err(MqttError::Message(message)) => message,
+            other => panic!("expected the script to fail, got {:?}", other.map(|_| ())),
+        };
+
+        assert!(message(FlowSim::new().publish(publish.clone()).emits_nothing().run())
+            .starts_with("Step 2: expected [], emitted [Publish(Publish { dup: false, qos_level: AtLeastOnce"));
+        assert_eq!(
+            "Step 1: failed with Protocol Error: PUBACK for unknown packet identifier 1",
+            message(FlowSim::new().receive(Puback::new(1, ReasonCode::Success).unwrap()).run()));
+        assert_eq!(
+            "Step 1: failed with Protocol Error: PUBACK for unknown packet identifier 1",
+            message(FlowSim::new().receive(Puback::new(1, ReasonCode::Success).unwrap()).publish(publish).run()));
+        assert_eq!(
+            "Step 1: PINGRESP is not part of a QoS flow",
+            message(FlowSim::new().receive(Pingresp {}).run()));
+        assert_eq!("Step 1: nothing to check, no action since the last check", message(FlowSim::new().emits_nothing().run()));
+        assert_eq!("Step 1: expected [3] completed, got []", message(FlowSim::new().completes(&[3]).run()));
+    }
+}
@@ -0,0 +1,130 @@
+//! Converts between binary packets and human-readable hex strings, so test fixtures and bug reports can be written as
+//! `10 13 00 04 4d 51 54 54 ...` instead of endless `vec![16, 19, 0, 4, 77, 81, 84, 84, ...]` literals.
+
+use crate::error::MqttError;
+
+/// Parses a string of hex digits into bytes.
+/// 
+/// Any whitespace is ignored, so fixtures can be grouped and spread over several lines as the reader sees fit. Each
+/// byte must be written as exactly two digits, upper- or lowercase.
+/// 
+/// ```
+/// use mqtt::test_util::from_hex;
+/// 
+/// assert_eq!(vec![0xC0, 0x00], from_hex("c0 00").unwrap());
+/// assert_eq!(vec![0xC0, 0x00], from_hex("C000").unwrap());
+/// ```
+pub fn from_hex(hex: &str) -> Result<Vec<u8>, MqttError> {
+    let digits: Vec<u8> = hex.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if digits.len() % 2 != 0 {
+        return Err(MqttError::Message(format!("Odd number of hex digits: {}", digits.len())))
+    }
+
+    let mut result = Vec::with_capacity(digits.len() / 2);
+    for pair in digits.chunks(2) {
+        result.push(hex_value(pair[0])? << 4 | hex_value(pair[1])?);
+    }
+
+    Ok(result)
+}
+
+/// Formats bytes as lowercase, space-separated pairs of hex digits. The result can be read back with [from_hex].
+/// 
+/// ```
+/// use mqtt::test_util::to_hex;
+/// 
+/// assert_eq!("e0 00", to_hex(&[224, 0]));
+/// ```
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<String>>().join(" ")
+}
+
+/// Hex conversions for anything that can be encoded into and decoded from binary, i.e. all of the
+/// [control packets](crate::packet) and the [data types](crate::types).
+/// 
+/// ```
+/// use mqtt::packet::Disconnect;
+/// use mqtt::test_util::Hex;
+/// use mqtt::types::ReasonCode;
+/// 
+/// let disconnect = Disconnect::from_hex("e0 02 87 00").unwrap();
+/// assert_eq!(ReasonCode::NotAuthorized, disconnect.reason_code);
+/// assert_eq!("e0 02 87 00", disconnect.to_hex());
+/// ```
+pub trait Hex: Sized {
+
+    /// Decodes the element from a hex string, see [from_hex] for the accepted format.
+    fn from_hex(hex: &str) -> Result<Self, MqttError>;
+
+    /// Encodes the element and formats the result using [to_hex].
+    ///
+    /// # Panics
+    /// If the element can't be encoded.
+    fn to_hex(self) -> String;
+}
+
+impl<T> Hex for T
+where
+    T: TryInto<Vec<u8>, Error: std::fmt::Debug> + for<'a> TryFrom<&'a [u8], Error = MqttError>,
+{
+    fn from_hex(hex: &str) -> Result<Self, MqttError> {
+        T::try_from(&from_hex(hex)?[..])
+    }
+
+    fn to_hex(self) -> String {
+        let encoded: Vec<u8> = self.try_into().expect("element must be encodable");
+        to_hex(&encoded)
+    }
+}
+
+fn hex_value(digit: u8) -> Result<u8, MqttError> {
+    match digit {
+        b'0'..=b'9' => Ok(digit - b'0'),
+        b'a'..=b'f' => Ok(digit - b'a' + 10),
+        b'A'..=b'F' => Ok(digit - b'A' + 10),
+        els => Err(MqttError::Message(format!("Invalid hex digit: {:?}", els as char))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::packet::{Pingreq, Puback};
+
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(Vec::<u8>::new(), from_hex("").unwrap());
+        assert_eq!(vec![0x10, 0x13, 0x00, 0xFF], from_hex("10 13 00 ff").unwrap());
+        assert_eq!(vec![0x10, 0x13, 0x00, 0xFF], from_hex("1013\n\t00FF").unwrap());
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(Some(MqttError::Message("Odd number of hex digits: 3".to_string())), from_hex("10 1").err());
+        assert_eq!(Some(MqttError::Message("Invalid hex digit: 'x'".to_string())), from_hex("0x10").err());
+    }
+
+    #[test]
+    fn format() {
+        assert_eq!("", to_hex(&[]));
+        assert_eq!("00 0a ff", to_hex(&[0, 10, 255]));
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(bytes, from_hex(&to_hex(&bytes)).unwrap());
+    }
+
+    #[test]
+    fn packets() {
+        assert_eq!("c0 00", Pingreq{}.to_hex());
+        assert!(Pingreq::from_hex("c0 00").is_ok());
+
+        let puback = Puback::from_hex("40 02 1f bb").unwrap();
+        assert_eq!(8123, puback.packet_identifier);
+        assert_eq!("40 02 1f bb", puback.to_hex());
+    }
+}
@@ -0,0 +1,29 @@
+//! Helpers for writing tests against this crate, both internally and in downstream crates.
+//! 
+//! Only available with the `test-util` feature enabled:
+//! ```toml
+//! [dev-dependencies]
+//! mqtt = { path = "../mqtt", features = ["test-util"] }
+//! ```
+
+mod alloc;
+#[cfg(feature = "transport")]
+mod broker;
+#[cfg(feature = "session")]
+mod clock;
+#[cfg(feature = "session")]
+mod flow_sim;
+mod hex;
+mod packet_eq;
+mod round_trip;
+
+pub use self::alloc::{assert_allocations, count_allocations, CountingAllocator};
+#[cfg(feature = "transport")]
+pub use self::broker::{MockBroker, MockHandle};
+#[cfg(feature = "session")]
+pub use self::clock::MockClock;
+#[cfg(feature = "session")]
+pub use self::flow_sim::FlowSim;
+pub use self::hex::{from_hex, to_hex, Hex};
+pub use self::packet_eq::normalize;
+pub use self::round_trip::check_round_trip;
@@ -0,0 +1,143 @@
+//! Structural comparison of packets that ignores differences without meaning on the wire.
+
+use crate::packet::Packet;
+
+/// Compares two packets structurally, see [normalize] for what is considered equivalent. Either side can be anything
+/// that converts into a [Packet], such as a decoded `Publish` on one side and the expected one on the other.
+///
+/// User properties are compared regardless of their order. This makes the macro useful for golden
+/// vectors from other implementations, whose encoding may legitimately differ byte for byte.
+///
+/// ```
+/// use mqtt::assert_packet_eq;
+/// use mqtt::packet::{Puback, PubackProperties};
+/// use mqtt::types::ReasonCode;
+///
+/// let short = Puback { packet_identifier: 7, reason_code: ReasonCode::Success, properties: None };
+/// let long = Puback { packet_identifier: 7, reason_code: ReasonCode::Success, properties: Some(PubackProperties::default()) };
+/// assert_packet_eq!(short, long);
+/// ```
+#[macro_export]
+macro_rules! assert_packet_eq {
+    ($left:expr, $right:expr $(,)?) => {
+        assert_eq!(
+            $crate::test_util::normalize($crate::packet::Packet::from($left)),
+            $crate::test_util::normalize($crate::packet::Packet::from($right)))
+    };
+    ($left:expr, $right:expr, $($arg:tt)+) => {
+        assert_eq!(
+            $crate::test_util::normalize($crate::packet::Packet::from($left)),
+            $crate::test_util::normalize($crate::packet::Packet::from($right)),
+            $($arg)+)
+    };
+}
+
+/// Brings a packet into a canonical form for comparison: user properties are sorted, and properties that are present
+/// but don't hold anything are removed, as they encode the same way as absent ones.
+pub fn normalize(packet: Packet) -> Packet {
+    // sorts the user properties before the default check, which then also covers them
+    macro_rules! none_if_default {
+        ($properties:expr) => {{
+            if let Some(properties) = $properties.as_mut() {
+                properties.user_property.sort();
+            }
+            none_if_default(&mut $properties);
+        }};
+    }
+
+    match packet {
+        Packet::Connect(mut p) => {
+            none_if_default!(p.properties);
+            if let Some(will) = p.will.as_mut() {
+                none_if_default!(will.properties);
+            }
+            Packet::Connect(p)
+        },
+        Packet::Connack(mut p) => { none_if_default!(p.properties); Packet::Connack(p) },
+        Packet::Publish(mut p) => {
+            none_if_default!(p.properties);
+            p.raw_properties = None;
+            Packet::Publish(p)
+        },
+        Packet::Puback(mut p) => { none_if_default!(p.properties); Packet::Puback(p) },
+        Packet::Pubrec(mut p) => { none_if_default!(p.properties); Packet::Pubrec(p) },
+        Packet::Pubrel(mut p) => { none_if_default!(p.properties); Packet::Pubrel(p) },
+        Packet::Pubcomp(mut p) => { none_if_default!(p.properties); Packet::Pubcomp(p) },
+        Packet::Subscribe(mut p) => { none_if_default!(p.properties); Packet::Subscribe(p) },
+        Packet::Suback(mut p) => {
+            if let Some(properties) = p.properties.as_mut() {
+                properties.sort_user_properties();
+            }
+            none_if_default(&mut p.properties);
+            Packet::Suback(p)
+        },
+        Packet::Unsubscribe(mut p) => { none_if_default!(p.properties); Packet::Unsubscribe(p) },
+        Packet::Unsuback(mut p) => { none_if_default!(p.properties); Packet::Unsuback(p) },
+        Packet::Disconnect(mut p) => { none_if_default!(p.properties); Packet::Disconnect(p) },
+        Packet::Auth(mut p) => { none_if_default!(p.properties); Packet::Auth(p) },
+        els => els,
+    }
+}
+
+fn none_if_default<T: Default + PartialEq>(properties: &mut Option<T>) {
+    if properties.as_ref().is_some_and(|p| *p == T::default()) {
+        *properties = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        packet::{Connect, ConnectProperties, Disconnect, LastWill, Publish, PublishProperties, Suback, WillProperties},
+        test_util::from_hex,
+        types::ReasonCode,
+    };
+
+    #[test]
+    fn user_property_order() {
+        let mut first = PublishProperties::default();
+        first.user_property.push("a".into(), "1".into());
+        first.user_property.push("b".into(), "2".into());
+        let mut second = PublishProperties::default();
+        second.user_property.push("b".into(), "2".into());
+        second.user_property.push("a".into(), "1".into());
+
+        let mut left = Publish::new_unchecked("t".into(), vec![1]);
+        left.properties = Some(first);
+        let mut right = Publish::new_unchecked("t".into(), vec![1]);
+        right.properties = Some(second);
+
+        crate::assert_packet_eq!(left, right);
+    }
+
+    #[test]
+    fn suback_user_property_order() {
+        // user properties a=1 and b=2, then the other way round
+        let left = Suback::try_from(&from_hex("90 12 0001 0E 26 0001 61 0001 31 26 0001 62 0001 32 00").unwrap()[..]);
+        let right = Suback::try_from(&from_hex("90 12 0001 0E 26 0001 62 0001 32 26 0001 61 0001 31 00").unwrap()[..]);
+        assert_ne!(left, right);
+
+        crate::assert_packet_eq!(left.unwrap(), right.unwrap());
+    }
+
+    #[test]
+    fn absent_and_default_properties() {
+        let mut will = LastWill::new("will".into(), &[]).unwrap();
+        will.properties = Some(WillProperties::default());
+        let mut left = Connect::default();
+        left.properties = Some(ConnectProperties::default());
+        left.will = Some(will);
+
+        let mut right = Connect::default();
+        right.will = Some(LastWill::new("will".into(), &[]).unwrap());
+
+        crate::assert_packet_eq!(left, right, "CONNECT with empty properties");
+    }
+
+    #[test]
+    #[should_panic]
+    fn different_packets() {
+        let busy = Disconnect { reason_code: ReasonCode::ServerBusy, ..Default::default() };
+        crate::assert_packet_eq!(Disconnect::default(), busy);
+    }
+}
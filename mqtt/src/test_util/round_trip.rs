@@ -0,0 +1,47 @@
+//! Checks that whatever decodes also encodes again, the core of a differential fuzzing harness.
+
+use crate::packet::Packet;
+
+use super::{normalize, to_hex};
+
+/// Decodes the packet at the start of `src` and encodes it again. Input that doesn't decode passes, as there's nothing
+/// to compare: whether rejecting it was right is up to a comparison with another implementation's decoder.
+///
+/// The encoded bytes should be the ones decoded, but may legitimately differ where the protocol allows more than one
+/// encoding, such as a Variable Byte Integer with trailing zero bytes or an empty property section instead of none.
+/// Then the re-encoded bytes must still decode into an [equivalent](normalize) packet.
+///
+/// The error describes the mismatch, with both encodings in hex.
+///
+/// ```
+/// use mqtt::test_util::check_round_trip;
+///
+/// // a PUBACK with a packet identifier of 1 and the default reason code
+/// assert_eq!(Ok(()), check_round_trip(&[0x40, 0x02, 0x00, 0x01]));
+/// // the same with the reason code and an empty property section, which is encoded differently but means the same
+/// assert_eq!(Ok(()), check_round_trip(&[0x40, 0x04, 0x00, 0x01, 0x00, 0x00]));
+/// // not a packet at all
+/// assert_eq!(Ok(()), check_round_trip(&[0xFF]));
+/// ```
+pub fn check_round_trip(src: &[u8]) -> Result<(), String> {
+    let Ok((decoded, len)) = Packet::decode_with_len(src) else {
+        return Ok(())
+    };
+    let src = &src[..len];
+
+    let encoded: Vec<u8> = decoded.try_into()
+        .map_err(|e| format!("Decoded {} but failed to encode it again: {}", to_hex(src), e))?;
+    if encoded == src {
+        return Ok(())
+    }
+
+    let original = Packet::try_from(src).map_err(|e| format!("Failed to decode {} again: {}", to_hex(src), e))?;
+    let reencoded = Packet::try_from(&encoded[..])
+        .map_err(|e| format!("Encoded {} as {}, which doesn't decode: {}", to_hex(src), to_hex(&encoded), e))?;
+    let (original, reencoded) = (normalize(original), normalize(reencoded));
+    match original == reencoded {
+        true => Ok(()),
+        false => Err(format!(
+            "Encoded {} as {}: decoded {:?}, re-encoded {:?}", to_hex(src), to_hex(&encoded), original, reencoded)),
+    }
+}
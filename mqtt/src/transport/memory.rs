@@ -0,0 +1,187 @@
+use std::{
+    collections::VecDeque,
+    io::{self, ErrorKind, Read, Write},
+    sync::{atomic::{AtomicUsize, Ordering}, Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use super::Transport;
+
+/// One end of a pair of connected in-memory streams: whatever is written to one can be read from the other.
+///
+/// Reads block until data arrives, like they would on a socket. Once either side is shut down or dropped along with
+/// all its clones, the other one reads the remaining data followed by end of stream (`Ok(0)`), and writes to it fail.
+///
+/// # Examples
+/// ```
+/// use std::io::{Read, Write};
+/// use mqtt::transport::MemoryTransport;
+///
+/// let (mut client, mut server) = MemoryTransport::pair();
+/// client.write_all(&[0xC0, 0x00]).unwrap();
+/// drop(client);
+///
+/// let mut received = Vec::new();
+/// server.read_to_end(&mut received).unwrap();
+/// assert_eq!(vec![0xC0, 0x00], received);
+/// ```
+#[derive(Debug)]
+pub struct MemoryTransport {
+    inbound: Arc<Pipe>,
+    outbound: Arc<Pipe>,
+    /// number of handles on this end, including clones
+    handles: Arc<AtomicUsize>,
+    read_timeout: Option<Duration>,
+}
+
+#[derive(Debug, Default)]
+struct Pipe {
+    state: Mutex<PipeState>,
+    changed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct PipeState {
+    bytes: VecDeque<u8>,
+    closed: bool,
+}
+
+impl MemoryTransport {
+
+    /// Creates both ends of a connection.
+    pub fn pair() -> (Self, Self) {
+        let a = Arc::new(Pipe::default());
+        let b = Arc::new(Pipe::default());
+        (Self::new(a.clone(), b.clone()), Self::new(b, a))
+    }
+
+    fn new(inbound: Arc<Pipe>, outbound: Arc<Pipe>) -> Self {
+        Self { inbound, outbound, handles: Arc::new(AtomicUsize::new(1)), read_timeout: None }
+    }
+
+    fn close(&self) {
+        for pipe in [&self.inbound, &self.outbound] {
+            pipe.state.lock().unwrap().closed = true;
+            pipe.changed.notify_all();
+        }
+    }
+}
+
+impl Read for MemoryTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let deadline = self.read_timeout.map(|t| Instant::now() + t);
+        let mut state = self.inbound.state.lock().unwrap();
+        while state.bytes.is_empty() && !state.closed {
+            state = match deadline {
+                None => self.inbound.changed.wait(state).unwrap(),
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return Err(ErrorKind::TimedOut.into())
+                    }
+                    self.inbound.changed.wait_timeout(state, remaining).unwrap().0
+                },
+            };
+        }
+
+        let len = buf.len().min(state.bytes.len());
+        for (target, byte) in buf.iter_mut().zip(state.bytes.drain(..len)) {
+            *target = byte;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for MemoryTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.outbound.state.lock().unwrap();
+        if state.closed {
+            return Err(ErrorKind::BrokenPipe.into())
+        }
+        state.bytes.extend(buf);
+        self.outbound.changed.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.read_timeout = timeout;
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.close();
+        Ok(())
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        self.handles.fetch_add(1, Ordering::SeqCst);
+        Ok(Box::new(Self {
+            inbound: self.inbound.clone(),
+            outbound: self.outbound.clone(),
+            handles: self.handles.clone(),
+            read_timeout: self.read_timeout,
+        }))
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        if self.handles.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn both_directions() {
+        let (mut a, mut b) = MemoryTransport::pair();
+        let echo = thread::spawn(move || {
+            let mut buf = [0; 8];
+            let len = b.read(&mut buf).unwrap();
+            b.write_all(&buf[..len]).unwrap();
+        });
+
+        a.write_all(&[1, 2, 3]).unwrap();
+        let mut buf = [0; 8];
+        assert_eq!(3, a.read(&mut buf).unwrap());
+        assert_eq!([1, 2, 3], buf[..3]);
+        echo.join().unwrap();
+
+        // the echo thread has dropped its end
+        assert_eq!(0, a.read(&mut buf).unwrap());
+        assert_eq!(ErrorKind::BrokenPipe, a.write(&[1]).unwrap_err().kind());
+    }
+
+    #[test]
+    fn clones_keep_the_connection_open() {
+        let (a, mut b) = MemoryTransport::pair();
+        let mut clone = a.try_clone().unwrap();
+        drop(a);
+
+        clone.write_all(&[1]).unwrap();
+        let mut buf = [0; 1];
+        assert_eq!(1, b.read(&mut buf).unwrap());
+
+        clone.shutdown().unwrap();
+        assert_eq!(0, b.read(&mut buf).unwrap());
+    }
+
+    #[test]
+    fn read_timeout() {
+        let (mut a, _b) = MemoryTransport::pair();
+        a.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+        assert_eq!(ErrorKind::TimedOut, a.read(&mut [0; 1]).unwrap_err().kind());
+    }
+}
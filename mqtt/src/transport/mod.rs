@@ -0,0 +1,55 @@
+//! The byte streams MQTT runs on.
+//!
+//! Everything else in this crate works on bytes and packets without caring where they come from. A [Transport] is
+//! what eventually carries them: anything that reads and writes chunks of bytes in order, plus the handful of socket
+//! operations a client needs on top of that.
+//!
+//! | Transport | Feature | Description |
+//! | --------- | ------- | ----------- |
+//! | [TcpTransport] | - | Plain TCP, usually port 1883 |
+//! | `TlsTransport` | `tls` | TCP secured by TLS, usually port 8883 |
+//! | [MemoryTransport] | - | In-process pair of connected streams, for tests and embedding a broker |
+
+mod memory;
+mod tcp;
+#[cfg(feature = "tls")]
+mod tls;
+
+use std::{io::{self, Read, Write}, time::Duration};
+
+pub use self::memory::MemoryTransport;
+pub use self::tcp::TcpTransport;
+#[cfg(feature = "tls")]
+pub use self::tls::TlsTransport;
+
+/// A connection MQTT packets can be sent over.
+///
+/// Reads block until at least one byte is available and return `Ok(0)` once the other side has closed the connection,
+/// same as a [TcpStream](std::net::TcpStream).
+pub trait Transport: Read + Write + Send {
+
+    /// Makes reads fail with [ErrorKind::TimedOut](io::ErrorKind::TimedOut) or
+    /// [ErrorKind::WouldBlock](io::ErrorKind::WouldBlock) if nothing arrives in time. `None` blocks indefinitely.
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Closes the connection in both directions.
+    fn shutdown(&mut self) -> io::Result<()>;
+
+    /// Another handle on the same connection, for reading on one thread while writing on another. Transports that
+    /// can't be shared this way return [ErrorKind::Unsupported](io::ErrorKind::Unsupported).
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>>;
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_read_timeout(timeout)
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        (**self).shutdown()
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        (**self).try_clone()
+    }
+}
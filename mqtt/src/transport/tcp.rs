@@ -0,0 +1,101 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use super::Transport;
+
+/// Plain TCP.
+#[derive(Debug)]
+pub struct TcpTransport {
+    stream: TcpStream,
+}
+
+impl TcpTransport {
+
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        TcpStream::connect(addr).map(Self::from)
+    }
+
+    /// Like [connect](Self::connect), but gives up on each of the addresses `addr` resolves to after `timeout`.
+    pub fn connect_timeout<A: ToSocketAddrs>(addr: A, timeout: Duration) -> io::Result<Self> {
+        let mut last_err = None;
+        for addr in addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(stream) => return Ok(Self::from(stream)),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "address resolved to nothing")))
+    }
+}
+
+impl From<TcpStream> for TcpTransport {
+    fn from(stream: TcpStream) -> Self {
+        Self { stream }
+    }
+}
+
+impl Read for TcpTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for TcpTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Transport for TcpTransport {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.stream.shutdown(Shutdown::Both)
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(Self { stream: self.stream.try_clone()? }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn loopback() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpTransport::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        let mut reader = client.try_clone().unwrap();
+        server.write_all(&[0xD0, 0x00]).unwrap();
+        let mut buf = [0; 2];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!([0xD0, 0x00], buf);
+
+        client.shutdown().unwrap();
+        assert_eq!(0, server.read(&mut buf).unwrap());
+    }
+
+    #[test]
+    fn connect_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        assert!(TcpTransport::connect_timeout(addr, Duration::from_secs(1)).is_ok());
+
+        drop(listener);
+        assert!(TcpTransport::connect_timeout(addr, Duration::from_secs(1)).is_err());
+    }
+}
@@ -0,0 +1,71 @@
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{Shutdown, TcpStream},
+    sync::Arc,
+    time::Duration,
+};
+
+use rustls::{pki_types::ServerName, ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use super::Transport;
+
+/// TCP secured by TLS, using [rustls](https://github.com/rustls/rustls).
+///
+/// A TLS session can't be shared between threads, so [Transport::try_clone] isn't supported.
+#[derive(Debug)]
+pub struct TlsTransport {
+    stream: StreamOwned<ClientConnection, TcpStream>,
+}
+
+impl TlsTransport {
+
+    /// Connects to `host` and verifies its certificate against the Mozilla root certificates.
+    pub fn connect(host: &str, port: u16) -> io::Result<Self> {
+        let roots = RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() };
+        let config = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Self::with_config(host, port, Arc::new(config))
+    }
+
+    /// Connects to `host` with a custom configuration, such as private root certificates or client authentication.
+    pub fn with_config(host: &str, port: u16, config: Arc<ClientConfig>) -> io::Result<Self> {
+        let server_name = ServerName::try_from(host.to_string())
+            .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+        let connection = ClientConnection::new(config, server_name).map_err(io::Error::other)?;
+        let socket = TcpStream::connect((host, port))?;
+        Ok(Self { stream: StreamOwned::new(connection, socket) })
+    }
+}
+
+impl Read for TlsTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for TlsTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Transport for TlsTransport {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.sock.set_read_timeout(timeout)
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.stream.conn.send_close_notify();
+        self.stream.flush()?;
+        self.stream.sock.shutdown(Shutdown::Both)
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Err(io::Error::new(ErrorKind::Unsupported, "a TLS connection can't be cloned"))
+    }
+}
@@ -1,4 +1,4 @@
-use crate::error::MqttError;
+use crate::{error::MqttError, wire::u16_from_be_bytes};
 
 use super::MqttDataType;
 
@@ -15,7 +15,7 @@ impl BinaryData {
     /// Returns an `MqttError` if the vector exceeds the maximum allowed number of bytes (65535).
     pub fn new(bytes: Vec<u8>) -> Result<Self, MqttError> {
         if bytes.len() > MAX_LENGTH {
-            return Err(MqttError::Message("Max length for binary elements is 65535".to_string()));
+            return Err(MqttError::BinaryDataTooLong { len: bytes.len() });
         }
 
         Ok(BinaryData { inner: bytes})
@@ -25,6 +25,11 @@ impl BinaryData {
     pub fn clone_inner(&self) -> Vec<u8> {
         self.inner.clone()
     }
+
+    /// The bytes, without their length.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.inner
+    }
 }
 
 impl MqttDataType for BinaryData {
@@ -34,12 +39,21 @@ impl MqttDataType for BinaryData {
     }
 }
 
-impl From<BinaryData> for Vec<u8> {
-    fn from(src: BinaryData) -> Self {
+/// Wraps the bytes without checking their length, which is left to encoding. Use [BinaryData::new] to fail early.
+impl From<Vec<u8>> for BinaryData {
+    fn from(bytes: Vec<u8>) -> Self {
+        BinaryData { inner: bytes }
+    }
+}
+
+impl TryFrom<BinaryData> for Vec<u8> {
+    type Error = MqttError;
+
+    fn try_from(src: BinaryData) -> Result<Self, Self::Error> {
         let len = src.inner.len();
+        let length = u16::try_from(len).map_err(|_| MqttError::BinaryDataTooLong { len })?;
+
         let mut result = Vec::with_capacity(len + 2);
-        
-        let length = len as u16;
         for b in length.to_be_bytes() {
             result.push(b)
         }
@@ -48,7 +62,7 @@ impl From<BinaryData> for Vec<u8> {
             result.push(b);
         }
 
-        result
+        Ok(result)
     }
 }
 
@@ -57,13 +71,13 @@ impl TryFrom<&[u8]> for BinaryData {
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
         if value.len() < 2 {
-            return Err(MqttError::Message("Binary data must be at least two bytes long!".to_string()))
+            return Err(MqttError::MalformedPacket("Binary data must be at least two bytes long!".to_string()))
         }
 
         let (len, val) = value.split_at(2);
-        let length = u16::from_be_bytes(len.try_into().unwrap()) as usize;
+        let length = u16_from_be_bytes(len)? as usize;
         if length > val.len() {
-            return Err(MqttError::Message(format!("Message too short. Specified [{}] and actual [{}] length mismatch", length, val.len())))
+            return Err(MqttError::MalformedPacket(format!("Message too short. Specified [{}] and actual [{}] length mismatch", length, val.len())))
         }
 
         let mut inner: Vec<u8> = Vec::with_capacity(length);
@@ -92,11 +106,17 @@ mod tests {
         let bytes = BinaryData::new(vec![0, 1, 2, 3, 4, 5]).unwrap();
 
         let expected: Vec<u8> = vec![0, 6, 0, 1, 2, 3, 4, 5];
-        let actual: Vec<u8> = bytes.into();
+        let actual: Vec<u8> = bytes.try_into().unwrap();
 
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn encode_too_long() {
+        assert_eq!(Err(MqttError::BinaryDataTooLong { len: 65536 }), Vec::try_from(BinaryData::from(vec![1; 65536])));
+        assert_eq!(Err(MqttError::BinaryDataTooLong { len: 65536 }), BinaryData::new(vec![1; 65536]));
+    }
+
     #[test]
     fn decode() {
         let bytes = [0, 5, 129, 90, 3, 240, 7];
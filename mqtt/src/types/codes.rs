@@ -1,3 +1,5 @@
+use std::fmt::Display;
+
 use crate::error::MqttError;
 
 use super::MqttDataType;
@@ -5,10 +7,17 @@ use super::MqttDataType;
 /// MQTT-3.2.2.2: Connect Reason Codes, a single byte numeric value.
 /// Anything above 0x80 is considered an error. 
 /// See the spec for details.
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+///
+/// Future versions of the spec may define new codes, so matches outside this crate need a wildcard arm. For codes not
+/// handled explicitly, [ReasonCode::is_err] still tells success from failure.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "u8", into = "u8"))]
+#[non_exhaustive]
 pub enum ReasonCode {
     /// 0x00 (0)
     /// In the context of a `SUBACK` packet this doubles as `Granted QoS 0`
+    #[default]
     Success = 0x00,
     /// 0x01 (1)
     GrantedQoS1 = 0x01,
@@ -101,7 +110,59 @@ impl ReasonCode {
     /// Returns `true` if the reason code has a numeric value of 0x80 or higher.
     pub fn is_err(&self) -> bool {
         let num = *self as u8;
-        num <= 128
+        num >= 0x80
+    }
+}
+
+/// The name the spec gives the reason code, such as "Not authorized". 
+impl Display for ReasonCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ReasonCode::Success => "Success",
+            ReasonCode::GrantedQoS1 => "Granted QoS 1",
+            ReasonCode::GrantedQoS2 => "Granted QoS 2",
+            ReasonCode::DisconnectWithWill => "Disconnect with Will Message",
+            ReasonCode::NoMatchingSubscribers => "No matching subscribers",
+            ReasonCode::NoSubscriptionExisted => "No subscription existed",
+            ReasonCode::ContinueAuthentication => "Continue authentication",
+            ReasonCode::ReAuthenticate => "Re-authenticate",
+            ReasonCode::UnspecifiedError => "Unspecified error",
+            ReasonCode::MalformedPacket => "Malformed Packet",
+            ReasonCode::ProtocolError => "Protocol Error",
+            ReasonCode::ImplementationSpecificError => "Implementation specific error",
+            ReasonCode::UnsupportedProtocolVersion => "Unsupported Protocol Version",
+            ReasonCode::ClientIdentifierInvalid => "Client Identifier not valid",
+            ReasonCode::BadUserNameOrPassword => "Bad User Name or Password",
+            ReasonCode::NotAuthorized => "Not authorized",
+            ReasonCode::ServerUnavailable => "Server unavailable",
+            ReasonCode::ServerBusy => "Server busy",
+            ReasonCode::Banned => "Banned",
+            ReasonCode::ServerShuttingDown => "Server shutting down",
+            ReasonCode::BadAuthenticationMethod => "Bad authentication method",
+            ReasonCode::KeepAliveTimeout => "Keep Alive timeout",
+            ReasonCode::SessionTakenOver => "Session taken over",
+            ReasonCode::TopciFilterInvalid => "Topic Filter invalid",
+            ReasonCode::TopicNameInvalid => "Topic Name invalid",
+            ReasonCode::PacketIdentifierInUse => "Packet Identifier in use",
+            ReasonCode::PacketIdentifierNotFound => "Packet Identifier not found",
+            ReasonCode::ReceiveMaximumExceeded => "Receive Maximum exceeded",
+            ReasonCode::TopicAliasInvalid => "Topic Alias invalid",
+            ReasonCode::PacketTooLarge => "Packet too large",
+            ReasonCode::MessageRateToohigh => "Message rate too high",
+            ReasonCode::QuotaExceeded => "Quota exceeded",
+            ReasonCode::AdministrativeAction => "Administrative action",
+            ReasonCode::PayloadFormatInvalid => "Payload format invalid",
+            ReasonCode::RetainNotSupported => "Retain not supported",
+            ReasonCode::QoSNotSupported => "QoS not supported",
+            ReasonCode::UseAnotherServer => "Use another server",
+            ReasonCode::ServerMoved => "Server moved",
+            ReasonCode::SharedSubscriptionsNotSupported => "Shared Subscriptions not supported",
+            ReasonCode::ConnectionRateExceeded => "Connection rate exceeded",
+            ReasonCode::MaximumConnectionTime => "Maximum connect time",
+            ReasonCode::SubscriptionIdentifiersNotSupported => "Subscription Identifiers not supported",
+            ReasonCode::WildcardSubscriptionsNotSupported => "Wildcard Subscriptions not supported",
+        };
+        f.write_str(name)
     }
 }
 
@@ -165,7 +226,7 @@ impl TryFrom<u8> for ReasonCode {
             160 => Ok(Self::MaximumConnectionTime),
             161 => Ok(Self::SubscriptionIdentifiersNotSupported),
             162 => Ok(Self::WildcardSubscriptionsNotSupported),
-            _=> Err(MqttError::Message(format!("Undefined Reason Code: {}", value))),
+            _=> Err(MqttError::MalformedPacket(format!("Undefined Reason Code: {}", value))),
         }        
     }
 }
@@ -185,10 +246,25 @@ mod tests {
         
         let err1 = ReasonCode::try_from(0xFF);
         assert!(err1.is_err());
-        assert_eq!(Some(MqttError::Message("Undefined Reason Code: 255".to_string())), err1.err());
+        assert_eq!(Some(MqttError::MalformedPacket("Undefined Reason Code: 255".to_string())), err1.err());
 
         let err2 = ReasonCode::try_from(0xBA);
         assert!(err2.is_err());
-        assert_eq!(Some(MqttError::Message("Undefined Reason Code: 186".to_string())), err2.err());
+        assert_eq!(Some(MqttError::MalformedPacket("Undefined Reason Code: 186".to_string())), err2.err());
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!("Success", ReasonCode::Success.to_string());
+        assert_eq!("Not authorized", ReasonCode::NotAuthorized.to_string());
+        assert_eq!("Topic Filter invalid", ReasonCode::TopciFilterInvalid.to_string());
+    }
+
+    #[test]
+    fn is_err() {
+        assert!(!ReasonCode::Success.is_err());
+        assert!(!ReasonCode::ReAuthenticate.is_err());
+        assert!(ReasonCode::UnspecifiedError.is_err());
+        assert!(ReasonCode::WildcardSubscriptionsNotSupported.is_err());
     }
 }
\ No newline at end of file
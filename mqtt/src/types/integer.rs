@@ -8,11 +8,48 @@ use super::MqttDataType;
 /// See [MQTT-1.5.5](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901011).
 /// 
 /// Internally uses a `u32`, but encodes to 1-4 bytes depending on the value.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct VariableByteInteger {
     pub value: u32,
 }
 
+impl VariableByteInteger {
+
+    /// Appends the encoded value to `buf`, without the allocation of converting it into a `Vec<u8>` of its own.
+    pub fn write_to(&self, buf: &mut Vec<u8>) {
+        let (bytes, len) = self.to_bytes();
+        buf.extend_from_slice(&bytes[..len]);
+    }
+
+    /// The encoded value in the first `len` bytes. One and two bytes, which cover lengths of up to 16 KiB, are
+    /// encoded without looping.
+    pub(crate) fn to_bytes(self) -> ([u8; 5], usize) {
+        let value = self.value;
+        if value < 128 {
+            return ([value as u8, 0, 0, 0, 0], 1)
+        }
+        if value < 16_384 {
+            return ([(value & 127) as u8 | 128, (value >> 7) as u8, 0, 0, 0], 2)
+        }
+
+        // five bytes for values beyond the spec's maximum, which aren't rejected (yet)
+        let mut bytes = [0; 5];
+        let mut len = 0;
+        let mut val = value;
+        while val > 0 {
+            bytes[len] = (val % 128) as u8;
+            val /= 128;
+            if val > 0 {
+                bytes[len] |= 128;
+            }
+            len += 1;
+        }
+        (bytes, len)
+    }
+}
+
 impl MqttDataType for VariableByteInteger {
     fn encoded_len(&self) -> usize {
         match self.value {
@@ -30,22 +67,16 @@ impl TryFrom<&[u8]> for VariableByteInteger {
     /// Attempts to read an unsigned integer (between 7 and 28 bits) value from one to four bytes
     /// according to the MQTT Spec 1.5.5.
     fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
-        // FIXME add validation (max length = 4)
-        let mut value: u32 = 0;
-        let mask: u8 = 127;
-        let mut multiplier: u32 = 1;
-
-        for byte in bytes {
-            let masked: u32 = (byte & mask) as u32;
-            value += (masked * multiplier) as u32;
-            multiplier *= 128;
-
-            // stop at the first byte where the LSB is no set
-            if byte & 128 == 0 {
-                break
-            }
+        // one and two bytes without looping
+        match bytes {
+            [b0, ..] if b0 & 128 == 0 => return Ok(VariableByteInteger { value: (*b0).into() }),
+            [b0, b1, ..] if b1 & 128 == 0 => {
+                return Ok(VariableByteInteger { value: u32::from(b0 & 127) | u32::from(*b1) << 7 })
+            },
+            _ => {},
         }
 
+        let (value, _) = crate::wire::read_variable_byte_integer(bytes)?;
         Ok(VariableByteInteger { value })
     }
 
@@ -62,22 +93,8 @@ impl From<VariableByteInteger> for Vec<u8> {
     /// Converts an unsigned integer (max 28 bits) into the binary representation according to MQTT Spec 1.5.5.
     fn from(src: VariableByteInteger) -> Self {
         // FIXME add validation to make sure the value does not exceed the max (268,435,455)
-        let mut res: Vec<u8> = Vec::new();
-        let mut val = src.value;
-
-        if val == 0 {
-            return vec![0]
-        }
-
-        while val > 0 {
-            let mut byte: u8 = (val % 128) as u8;
-            val = val / 128;
-            if val > 0 {
-                byte = byte | 128;
-            }
-            res.push(byte);
-        }
-        res
+        let (bytes, len) = src.to_bytes();
+        bytes[..len].to_vec()
     }
 }
 
@@ -119,10 +136,34 @@ mod tests {
 
     #[test]
     fn decode_vbi() {
-        do_test_decode_vbi(&vec![78], 78);
-        do_test_decode_vbi(&vec![129, 1], 129);
-        do_test_decode_vbi(&vec![0x80, 0x80, 0x80, 0x01], 2097152);
-        do_test_decode_vbi(&vec![0], 0);
+        do_test_decode_vbi(&[78], 78);
+        do_test_decode_vbi(&[129, 1], 129);
+        do_test_decode_vbi(&[0x80, 0x80, 0x80, 0x01], 2097152);
+        do_test_decode_vbi(&[0], 0);
+    }
+
+    #[test]
+    fn decode_vbi_too_long() {
+        assert!(VariableByteInteger::try_from(&[0xFF, 0xFF, 0xFF, 0xFF, 0x7F][..]).is_err());
+        assert!(VariableByteInteger::try_from(&[0xFF, 0xFF, 0xFF][..]).is_err());
+    }
+
+    #[test]
+    fn round_trip_all_lengths() {
+        let boundaries = [0, 1, 127, 128, 129, 16_383, 16_384, 2_097_151, 2_097_152, 268_435_455];
+        for value in boundaries.into_iter().chain((0..70_000).step_by(7)) {
+            let vbi = VariableByteInteger { value };
+            let encoded: Vec<u8> = vbi.into();
+            assert_eq!(vbi.encoded_len(), encoded.len(), "length of {}", value);
+            assert_eq!(value, VariableByteInteger::try_from(&encoded[..]).unwrap().value);
+
+            let mut buf = vec![0xAA];
+            vbi.write_to(&mut buf);
+            assert_eq!(encoded, buf[1..]);
+        }
+
+        // beyond the maximum, as before
+        assert_eq!(vec![0xFF, 0xFF, 0xFF, 0xFF, 0x0F], Vec::from(VariableByteInteger { value: u32::MAX }));
     }
 
     #[test]
@@ -12,6 +12,9 @@
 //! | Binary Data | `Vec<u8>` or `&[u8]` | [BinaryData](self::bytes::BinaryData) | A sequence of bytes, max length is 65,535 |
 //! | UTF-8 String | [String] | [UTF8String](self::string::UTF8String) |Max length 65,535 bytes (not characters!) |
 //! | UTF-8 String pair | (String, String) | [UTF8StringPair](self::string::UTF8StringPair) | Length restrictions count per each individually |
+//! | User Property | (String, String) | [UserProperties] | Ordered name-value pairs, names may repeat |
+//! | Topic Name | [String] | [TopicName](self::topic::TopicName) | UTF-8 String without U+0000, see [validate_topic] |
+//! | - | `Vec<u8>` or `&[u8]` | [Payload] | Application message of a `PUBLISH`, cheap to clone |
 //! 
 //! Where "wrapper" structs exists for their respective rust data types, it is for necessary additional logic in 
 //! encoding/decoding, such as the algorithm for [self::integer::VariableByteInteger] or additional length bytes for
@@ -24,15 +27,22 @@
 mod bytes;
 mod codes;
 mod integer;
+mod payload;
 mod string;
 mod qos;
+mod topic;
+mod user_property;
 
 pub use self::bytes::BinaryData;
 pub use self::codes::ReasonCode;
 pub use self::integer::VariableByteInteger;
+pub use self::payload::Payload;
 pub use self::string::UTF8String;
 pub use self::string::UTF8StringPair;
 pub use self::qos::QoS;
+pub use self::topic::{validate_topic, TopicName, ValidationMode};
+pub(crate) use self::topic::check_no_wildcards;
+pub use self::user_property::UserProperties;
 
 /// A data type as defined in the MQTT spec.
 /// 
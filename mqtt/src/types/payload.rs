@@ -0,0 +1,202 @@
+use std::{fmt::Debug, ops::Deref, sync::Arc};
+
+/// The application message of a `PUBLISH`. Cloning it shares the bytes instead of copying them, so that a message
+/// kept for retransmission or delivered to any number of subscribers is held in memory only once.
+///
+/// Dereferences to `[u8]` and compares equal to plain byte slices and vectors.
+///
+/// # Examples
+/// ```
+/// use mqtt::packet::Publish;
+/// use mqtt::types::Payload;
+///
+/// let publish = Publish::with_topic_name("a/b".try_into().unwrap(), "hello");
+/// let copies: Vec<Publish> = (0..1000).map(|_| publish.clone()).collect();
+///
+/// assert!(Payload::ptr_eq(&publish.payload, &copies[999].payload));
+/// assert_eq!(b"hello", &copies[0].payload[..]);
+/// ```
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct Payload {
+    inner: Arc<[u8]>,
+}
+
+impl Payload {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.inner
+    }
+
+    /// Whether both share the same bytes, i.e. one is a clone of the other.
+    pub fn ptr_eq(a: &Self, b: &Self) -> bool {
+        Arc::ptr_eq(&a.inner, &b.inner)
+    }
+}
+
+impl Deref for Payload {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl AsRef<[u8]> for Payload {
+    fn as_ref(&self) -> &[u8] {
+        &self.inner
+    }
+}
+
+/// Same as for the bytes themselves.
+impl Debug for Payload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl From<Vec<u8>> for Payload {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self { inner: bytes.into() }
+    }
+}
+
+impl From<&[u8]> for Payload {
+    fn from(bytes: &[u8]) -> Self {
+        Self { inner: bytes.into() }
+    }
+}
+
+impl<const N: usize> From<[u8; N]> for Payload {
+    fn from(bytes: [u8; N]) -> Self {
+        Self { inner: Arc::new(bytes) }
+    }
+}
+
+impl From<&str> for Payload {
+    fn from(text: &str) -> Self {
+        text.as_bytes().into()
+    }
+}
+
+impl From<String> for Payload {
+    fn from(text: String) -> Self {
+        text.into_bytes().into()
+    }
+}
+
+impl From<Payload> for Vec<u8> {
+    fn from(payload: Payload) -> Self {
+        payload.inner.to_vec()
+    }
+}
+
+impl PartialEq<[u8]> for Payload {
+    fn eq(&self, other: &[u8]) -> bool {
+        *self.inner == *other
+    }
+}
+
+impl PartialEq<&[u8]> for Payload {
+    fn eq(&self, other: &&[u8]) -> bool {
+        *self.inner == **other
+    }
+}
+
+impl PartialEq<Vec<u8>> for Payload {
+    fn eq(&self, other: &Vec<u8>) -> bool {
+        *self.inner == **other
+    }
+}
+
+impl PartialEq<Payload> for Vec<u8> {
+    fn eq(&self, other: &Payload) -> bool {
+        **self == *other.inner
+    }
+}
+
+impl<const N: usize> PartialEq<[u8; N]> for Payload {
+    fn eq(&self, other: &[u8; N]) -> bool {
+        *self.inner == *other
+    }
+}
+
+/// Serialized as bytes, such as an array of numbers in JSON.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Payload {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.inner)
+    }
+}
+
+/// Deserialized from bytes, or from a string for a payload that's text.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Payload {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct PayloadVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for PayloadVisitor {
+            type Value = Payload;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "a string or bytes")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, text: &str) -> Result<Payload, E> {
+                Ok(text.into())
+            }
+
+            fn visit_bytes<E: serde::de::Error>(self, bytes: &[u8]) -> Result<Payload, E> {
+                Ok(bytes.into())
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Payload, A::Error> {
+                let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+                while let Some(byte) = seq.next_element::<u8>()? {
+                    bytes.push(byte);
+                }
+                Ok(bytes.into())
+            }
+        }
+
+        deserializer.deserialize_any(PayloadVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_bytes() {
+        let payload = Payload::from(vec![1, 2, 3]);
+        let clone = payload.clone();
+        assert!(Payload::ptr_eq(&payload, &clone));
+        assert!(!Payload::ptr_eq(&payload, &Payload::from([1, 2, 3])));
+        assert_eq!(payload, Payload::from([1, 2, 3]));
+    }
+
+    #[test]
+    fn compares_to_bytes() {
+        let payload = Payload::from("abc");
+        assert_eq!(payload, vec![b'a', b'b', b'c']);
+        assert_eq!(vec![b'a', b'b', b'c'], payload);
+        assert_eq!(payload, *b"abc");
+        assert_eq!(payload, &b"abc"[..]);
+        assert_eq!(vec![b'a', b'b', b'c'], Vec::from(payload.clone()));
+        assert_eq!("[97, 98, 99]", format!("{:?}", payload));
+        assert!(Payload::new().is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        assert_eq!("[104,105]", serde_json::to_string(&Payload::from("hi")).unwrap());
+        assert_eq!(Payload::from("hi"), serde_json::from_str::<Payload>("[104, 105]").unwrap());
+        assert_eq!(Payload::from("hi"), serde_json::from_str::<Payload>("\"hi\"").unwrap());
+        assert!(serde_json::from_str::<Payload>("[256]").is_err());
+    }
+}
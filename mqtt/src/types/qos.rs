@@ -2,9 +2,12 @@ use crate::error::MqttError;
 
 /// Quality of Service levels.
 /// See [the spec](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901234).
-#[derive(Debug, PartialEq, PartialOrd, Copy, Clone)]
+#[derive(Debug, Default, PartialEq, PartialOrd, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "u8", into = "u8"))]
 pub enum QoS {
     /// 0
+    #[default]
     AtMostOnce = 0,
     /// 1
     AtLeastOnce = 1,
@@ -12,9 +15,40 @@ pub enum QoS {
     ExactlyOnce = 2,
 }
 
+impl QoS {
+
+    /// The QoS as the two bits `shift` places from the right of a byte, such as `1` for the first byte of a `PUBLISH`,
+    /// `3` for the will QoS in the `CONNECT` flags or `0` for subscription options. The other bits are left unset, to
+    /// be ORed with whatever else the byte holds.
+    ///
+    /// ```
+    /// use mqtt::types::QoS;
+    ///
+    /// assert_eq!(0b0000_0100, QoS::ExactlyOnce.encode_bits(1));
+    /// assert_eq!(0b0000_1000, QoS::AtLeastOnce.encode_bits(3));
+    /// ```
+    pub const fn encode_bits(self, shift: u8) -> u8 {
+        (self as u8) << shift
+    }
+
+    /// The QoS in the two bits `shift` places from the right of a byte, ignoring all other bits, the reverse of
+    /// [QoS::encode_bits]. Both bits set is a `MalformedPacket` error, as with [QoS::try_from].
+    ///
+    /// ```
+    /// use mqtt::types::QoS;
+    ///
+    /// assert_eq!(Ok(QoS::AtLeastOnce), QoS::from_bits(0b0011_0011, 1));
+    /// assert!(QoS::from_bits(0b0000_0110, 1).is_err());
+    /// ```
+    pub fn from_bits(byte: u8, shift: u8) -> Result<Self, MqttError> {
+        QoS::try_from((byte >> shift) & 0b11)
+    }
+}
+
 impl TryFrom<u8> for QoS {
     type Error = MqttError;
 
+    /// Any value other than `0`, `1` or `2` is a `MalformedPacket` error (reason code `0x81`).
     fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
             0 => Ok(QoS::AtMostOnce),
@@ -41,6 +75,25 @@ mod tests {
         assert_eq!(QoS::AtMostOnce, QoS::try_from(0_u8).unwrap());
         assert_eq!(QoS::AtLeastOnce, QoS::try_from(1_u8).unwrap());
         assert_eq!(QoS::ExactlyOnce, QoS::try_from(2_u8).unwrap());
-        assert!(QoS::try_from(3_u8).is_err());
+
+        let err = QoS::try_from(3_u8).unwrap_err();
+        assert_eq!(MqttError::MalformedPacket("illegal value for QoS: 3".to_string()), err);
+        assert_eq!(crate::types::ReasonCode::MalformedPacket, err.reason_code());
+    }
+
+    #[test]
+    fn bits() {
+        for qos in [QoS::AtMostOnce, QoS::AtLeastOnce, QoS::ExactlyOnce] {
+            for shift in 0..=6 {
+                let bits = qos.encode_bits(shift);
+                assert_eq!(0, bits & !(0b11 << shift), "{:?} at {}", qos, shift);
+                assert_eq!(Ok(qos), QoS::from_bits(bits, shift));
+                // whatever else is in the byte doesn't matter
+                assert_eq!(Ok(qos), QoS::from_bits(bits | !(0b11 << shift), shift));
+            }
+        }
+        assert_eq!(
+            Err(MqttError::MalformedPacket("illegal value for QoS: 3".to_string())),
+            QoS::from_bits(0b0001_1000, 3));
     }
 }
\ No newline at end of file
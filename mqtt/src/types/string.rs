@@ -1,20 +1,20 @@
 use std::fmt::Display;
 
-use crate::error::MqttError;
+use crate::{error::MqttError, wire::u16_from_be_bytes};
 
 use super::MqttDataType;
 
 /// A String with a max length of 65,535 bytes (not characters!).
 /// The encoded value also includes the length in two bytes.
 /// See [MQTT-1.5.4](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901010).
-#[derive(Debug, PartialEq, Hash, Eq)]
+#[derive(Debug, Clone, PartialEq, Hash, Eq)]
 pub struct UTF8String {
     pub value: Option<String>,
 }
 
 /// Just two [UTF8String]s in a row. 
 /// See [the spec](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901013).
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct UTF8StringPair {
     pub key: UTF8String,
     pub value: UTF8String,
@@ -30,6 +30,12 @@ impl MqttDataType for UTF8String {
     }
 }
 
+impl Default for UTF8String {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl UTF8String {
 
     const LENGTH_FIELD_SIZE: usize = 2;
@@ -54,24 +60,21 @@ impl From<&str> for UTF8String {
     }
 }
 
-impl From<UTF8String> for Vec<u8> {
-    fn from(src: UTF8String) -> Self {
+impl TryFrom<UTF8String> for Vec<u8> {
+    type Error = MqttError;
+
+    /// Fails with [MqttError::StringTooLong] if the string doesn't fit its two byte length prefix.
+    fn try_from(src: UTF8String) -> Result<Self, Self::Error> {
         match src.value {
             Some(utf8) => {
                 let bytes = utf8.as_bytes();
-                let mut result = Vec::new();
-                let length = bytes.len() as u16;
-                for b in length.to_be_bytes() {
-                    result.push(b)
-                }
-
-                for b in bytes {
-                    result.push(*b)
-                }
-        
-                result                
+                let length = u16::try_from(bytes.len()).map_err(|_| MqttError::StringTooLong { len: bytes.len() })?;
+                let mut result = Vec::with_capacity(bytes.len() + 2);
+                result.extend_from_slice(&length.to_be_bytes());
+                result.extend_from_slice(bytes);
+                Ok(result)
             },
-            None => vec![0, 0],
+            None => Ok(vec![0, 0]),
         }
     }
 }
@@ -79,10 +82,7 @@ impl From<UTF8String> for Vec<u8> {
 impl From<UTF8String> for String {
     /// Returns an empty `String` if [UTF8String.value] is `None`.
     fn from(src: UTF8String) -> Self {
-        match src.value {
-            Some(s) => s,
-            None => String::new(),
-        }
+        src.value.unwrap_or_default()
     }
 }
 
@@ -91,12 +91,19 @@ impl TryFrom<&[u8]> for UTF8String {
     type Error = MqttError;
     
     fn try_from(src: &[u8]) -> Result<Self, Self::Error> {
+        if src.len() < UTF8String::LENGTH_FIELD_SIZE {
+            return Err(MqttError::MalformedPacket("UTF-8 string must be at least two bytes long!".to_string()))
+        }
+
         let (len_slice, value) = src.split_at(UTF8String::LENGTH_FIELD_SIZE);
-        let length:usize = u16::from_be_bytes([len_slice[0], len_slice[1]]).into();
+        let length: usize = u16_from_be_bytes(len_slice)?.into();
+        if length > value.len() {
+            return Err(MqttError::MalformedPacket(format!("Message too short. Specified [{}] and actual [{}] length mismatch", length, value.len())))
+        }
         
         match String::from_utf8(value[..length].to_vec()) {
             Ok(s) => Ok(UTF8String::from(s)),
-            Err(e) => Err(MqttError::Message(format!("Error decoding bytes to String: {:?}", e))),
+            Err(e) => Err(MqttError::MalformedPacket(format!("Error decoding bytes to String: {:?}", e))),
         }
     }
 }
@@ -133,12 +140,14 @@ impl MqttDataType for UTF8StringPair {
 
 }
 
-impl From<UTF8StringPair> for Vec<u8> {
-    fn from(src: UTF8StringPair) -> Self {
-        let mut result: Vec<u8> = src.key.into();
-        let mut val: Vec<u8> = src.value.into();
+impl TryFrom<UTF8StringPair> for Vec<u8> {
+    type Error = MqttError;
+
+    fn try_from(src: UTF8StringPair) -> Result<Self, Self::Error> {
+        let mut result: Vec<u8> = src.key.try_into()?;
+        let mut val: Vec<u8> = src.value.try_into()?;
         result.append(&mut val);
-        result
+        Ok(result)
     }
 }
 
@@ -146,7 +155,7 @@ impl TryFrom<&[u8]> for UTF8StringPair {
     type Error = MqttError;
 
     fn try_from(src: &[u8]) -> Result<Self, Self::Error> {
-        let key = UTF8String::try_from(&src[..])?;
+        let key = UTF8String::try_from(src)?;
         let value = UTF8String::try_from(&src[key.encoded_len()..])?;
         
         Ok(UTF8StringPair { key, value })
@@ -165,7 +174,7 @@ mod tests {
         assert_eq!(6, utf8.encoded_len());
         
         let expect: Vec<u8> = vec![0, 4, 77, 81, 84, 84];
-        let actual: Vec<u8> = utf8.into();
+        let actual: Vec<u8> = utf8.try_into().unwrap();
         assert_eq!(expect, actual);
     }
 
@@ -173,10 +182,17 @@ mod tests {
     fn encode_empty() {
         let utf8 = UTF8String::new();
         let expect: Vec<u8> = vec![0, 0];
-        let actual: Vec<u8> = utf8.into();
+        let actual: Vec<u8> = utf8.try_into().unwrap();
         assert_eq!(expect, actual);
     }
 
+    #[test]
+    fn encode_too_long() {
+        let utf8 = UTF8String::from("a".repeat(65_536));
+        assert_eq!(Err(MqttError::StringTooLong { len: 65_536 }), Vec::try_from(utf8));
+        assert!(Vec::try_from(UTF8String::from("a".repeat(65_535))).is_ok());
+    }
+
     #[test]
     fn decode_utf8() {
         let source: Vec<u8> = vec![0, 4, 77, 81, 84, 84];
@@ -186,6 +202,16 @@ mod tests {
         assert_eq!(expect, actual);
     }
 
+    #[test]
+    fn decode_utf8_too_short() {
+        assert_eq!(
+            Some(MqttError::MalformedPacket("UTF-8 string must be at least two bytes long!".to_string())),
+            UTF8String::try_from(&[0][..]).err());
+        assert_eq!(
+            Some(MqttError::MalformedPacket("Message too short. Specified [4] and actual [2] length mismatch".to_string())),
+            UTF8String::try_from(&[0, 4, 77, 81][..]).err());
+    }
+
     #[test]
     fn equal_string() {
         let utf8 = UTF8String::from("MQTT");
@@ -195,6 +221,24 @@ mod tests {
     #[test]
     fn length() {
         assert_eq!(12, UTF8String::from("SOMESTRING").encoded_len());
+        // the euro sign takes three bytes
         assert_eq!(11, UTF8String::from("DOLLAR€").encoded_len());
     }
+
+    #[test]
+    fn multi_byte_characters() {
+        for s in ["€", "ümlaut", "日本語", "🦀", "a🦀b€c", "\u{10FFFF}"] {
+            let utf8 = UTF8String::from(s);
+            assert_eq!(2 + s.len(), utf8.encoded_len(), "{}", s);
+
+            let encoded: Vec<u8> = utf8.clone().try_into().unwrap();
+            assert_eq!(utf8.encoded_len(), encoded.len(), "{}", s);
+            assert_eq!(s.len(), usize::from(u16_from_be_bytes(&encoded[..2]).unwrap()), "{}", s);
+            assert_eq!(utf8, UTF8String::try_from(&encoded[..]).unwrap());
+        }
+
+        // the limit is in bytes: 21,845 characters of three bytes each
+        assert!(Vec::try_from(UTF8String::from("€".repeat(21_845))).is_ok());
+        assert_eq!(Err(MqttError::StringTooLong { len: 65_538 }), Vec::try_from(UTF8String::from("€".repeat(21_846))));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,190 @@
+use std::fmt::Display;
+
+use crate::error::MqttError;
+
+/// How thoroughly values such as topics are checked.
+///
+/// The spec distinguishes between things that **must not** appear, which are always rejected, and things that
+/// **should not**, which are only rejected in strict mode. Strict is what locally created values should use, lenient
+/// is used when decoding, so that a peer isn't disconnected for something the spec merely discourages.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationMode {
+    /// Rejects anything the spec forbids or discourages.
+    #[default]
+    Strict,
+    /// Only rejects what the spec forbids.
+    Lenient,
+}
+
+/// The name of a topic to publish to, validated according to the spec. Unlike topic filters, topic names never contain
+/// wildcards.
+///
+/// # Examples
+/// ```
+/// use mqtt::types::{TopicName, ValidationMode};
+///
+/// let topic = TopicName::try_from("some/topic").unwrap();
+/// assert_eq!("some/topic", topic.as_str());
+///
+/// // U+0000 is never allowed, control characters only in lenient mode
+/// assert!(TopicName::new("some\0topic", ValidationMode::Lenient).is_err());
+/// assert!(TopicName::new("some\u{7}topic", ValidationMode::Strict).is_err());
+/// assert!(TopicName::new("some\u{7}topic", ValidationMode::Lenient).is_ok());
+///
+/// // wildcards are for subscribing
+/// assert!(TopicName::new("some/+", ValidationMode::Lenient).is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TopicName(String);
+
+impl TopicName {
+
+    /// Validates `name` and wraps it, see [validate_topic] for the rules applied. Wildcard characters are rejected in
+    /// either mode with a `TopicNameInvalid` error [MQTT-3.3.2-2].
+    pub fn new<S: Into<String>>(name: S, mode: ValidationMode) -> Result<Self, MqttError> {
+        let name = name.into();
+        validate_topic(&name, mode)?;
+        check_no_wildcards(&name)?;
+        Ok(TopicName(name))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for TopicName {
+    type Error = MqttError;
+
+    /// Uses [strict validation](ValidationMode::Strict).
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        TopicName::new(value, ValidationMode::Strict)
+    }
+}
+
+impl TryFrom<&str> for TopicName {
+    type Error = MqttError;
+
+    /// Uses [strict validation](ValidationMode::Strict).
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        TopicName::new(value, ValidationMode::Strict)
+    }
+}
+
+impl From<TopicName> for String {
+    fn from(topic: TopicName) -> Self {
+        topic.0
+    }
+}
+
+impl AsRef<str> for TopicName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for TopicName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Checks the characters of a topic name or filter.
+///
+/// Topics must be at least one character and at most 65,535 bytes long and must not contain U+0000. In
+/// [strict mode](ValidationMode::Strict), control characters (U+0001 to U+001F and U+007F to U+009F) are rejected as
+/// well.
+///
+/// Returns a `MalformedPacket` error if any of these are violated.
+pub fn validate_topic(topic: &str, mode: ValidationMode) -> Result<(), MqttError> {
+    if topic.is_empty() {
+        return Err(MqttError::MalformedPacket("Topic must be at least one character long".to_string()))
+    }
+
+    if topic.len() > u16::MAX as usize {
+        return Err(MqttError::MalformedPacket(format!("Topic exceeds {} bytes: {}", u16::MAX, topic.len())))
+    }
+
+    for c in topic.chars() {
+        if c == '\0' {
+            return Err(MqttError::MalformedPacket("Topic must not contain U+0000".to_string()))
+        }
+
+        if mode == ValidationMode::Strict && c.is_control() {
+            return Err(MqttError::MalformedPacket(format!("Topic contains control character U+{:04X}", c as u32)))
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that a topic name doesn't contain the wildcard characters `+` and `#`, which only topic filters may contain
+/// [MQTT-3.3.2-2]. Returns a `TopicNameInvalid` error otherwise.
+pub(crate) fn check_no_wildcards(topic: &str) -> Result<(), MqttError> {
+    match topic.contains(['+', '#']) {
+        true => Err(MqttError::TopicNameInvalid(format!("Topic name must not contain wildcards: {}", topic))),
+        false => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid() {
+        for topic in ["a", "/", "some/topic", "sport/+/player1", "#", "ümlaut/€", " "] {
+            assert!(validate_topic(topic, ValidationMode::Strict).is_ok(), "{} should be valid", topic);
+        }
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(
+            Some(MqttError::MalformedPacket("Topic must be at least one character long".to_string())),
+            validate_topic("", ValidationMode::Lenient).err());
+    }
+
+    #[test]
+    fn too_long() {
+        let topic = "a".repeat(65_536);
+        assert_eq!(
+            Some(MqttError::MalformedPacket("Topic exceeds 65535 bytes: 65536".to_string())),
+            validate_topic(&topic, ValidationMode::Lenient).err());
+        assert!(validate_topic(&topic[1..], ValidationMode::Strict).is_ok());
+    }
+
+    #[test]
+    fn null_character() {
+        for mode in [ValidationMode::Strict, ValidationMode::Lenient] {
+            assert_eq!(
+                Some(MqttError::MalformedPacket("Topic must not contain U+0000".to_string())),
+                validate_topic("a/\0", mode).err());
+        }
+    }
+
+    #[test]
+    fn control_characters() {
+        for c in ['\u{1}', '\n', '\u{1F}', '\u{7F}', '\u{9F}'] {
+            let topic = format!("a/{}", c);
+            assert_eq!(
+                Some(MqttError::MalformedPacket(format!("Topic contains control character U+{:04X}", c as u32))),
+                validate_topic(&topic, ValidationMode::Strict).err());
+            assert!(validate_topic(&topic, ValidationMode::Lenient).is_ok());
+        }
+    }
+
+    #[test]
+    fn topic_name() {
+        let topic = TopicName::try_from("a/b".to_string()).unwrap();
+        assert_eq!("a/b", topic.to_string());
+        assert_eq!("a/b", String::from(topic));
+        assert!(TopicName::try_from("").is_err());
+
+        for topic in ["a/+", "#", "a+b"] {
+            assert_eq!(
+                Err(MqttError::TopicNameInvalid(format!("Topic name must not contain wildcards: {}", topic))),
+                TopicName::try_from(topic));
+        }
+    }
+}
@@ -0,0 +1,89 @@
+/// User properties as the spec defines them: name-value pairs in the order they were sent, where the same name may
+/// occur more than once [MQTT-3.3.2-18].
+///
+/// # Examples
+/// ```
+/// use mqtt::types::UserProperties;
+///
+/// let mut properties = UserProperties::new();
+/// properties.push("region".into(), "eu".into());
+/// properties.push("tag".into(), "a".into());
+/// properties.push("tag".into(), "b".into());
+///
+/// assert_eq!(Some("eu"), properties.get("region"));
+/// assert_eq!(vec!["a", "b"], properties.get_all("tag").collect::<Vec<_>>());
+/// assert_eq!(vec![("region", "eu"), ("tag", "a"), ("tag", "b")], properties.iter().collect::<Vec<_>>());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct UserProperties {
+    inner: Vec<(String, String)>,
+}
+
+impl UserProperties {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a pair at the end, regardless of whether the name already exists.
+    pub fn push(&mut self, key: String, value: String) {
+        self.inner.push((key, value));
+    }
+
+    /// The value of the first pair with this name.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.inner.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    /// The values of all pairs with this name, in order.
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> {
+        self.inner.iter().filter(move |(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    /// All pairs, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.inner.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Sorts the pairs by name and value, for comparisons where the order doesn't matter.
+    pub fn sort(&mut self) {
+        self.inner.sort();
+    }
+}
+
+impl IntoIterator for UserProperties {
+    type Item = (String, String);
+    type IntoIter = std::vec::IntoIter<(String, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.into_iter()
+    }
+}
+
+impl FromIterator<(String, String)> for UserProperties {
+    fn from_iter<T: IntoIterator<Item = (String, String)>>(iter: T) -> Self {
+        Self { inner: iter.into_iter().collect() }
+    }
+}
+
+impl From<Vec<(String, String)>> for UserProperties {
+    fn from(inner: Vec<(String, String)>) -> Self {
+        Self { inner }
+    }
+}
+
+impl<const N: usize> From<[(String, String); N]> for UserProperties {
+    fn from(pairs: [(String, String); N]) -> Self {
+        Self { inner: pairs.into() }
+    }
+}
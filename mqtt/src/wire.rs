@@ -0,0 +1,376 @@
+//! The primitives of the binary format, for tooling that works on MQTT bytes directly, such as proxies or protocol
+//! analyzers, without decoding whole packets. The [packets](crate::packet) and [data types](crate::types) are built
+//! on the same functions.
+//!
+//! Writing appends to a `Vec<u8>`. Reading takes a slice that starts with the value and returns it along with the
+//! number of bytes it took up, so the next value starts right after. Any further bytes are ignored. Input that is too
+//! short or otherwise invalid fails with [MalformedPacket](MqttError::MalformedPacket).
+//!
+//! | MQTT type | Write | Read |
+//! | --------- | ----- | ---- |
+//! | Two Byte Integer | [push_be_u16] | [u16_from_be_bytes] |
+//! | Four Byte Integer | [push_be_u32] | [u32_from_be_bytes] |
+//! | Variable Byte Integer | [push_variable_byte_integer] | [read_variable_byte_integer] |
+//! | UTF-8 Encoded String | [push_string] | [read_string] |
+//! | Binary Data | [push_binary] | [read_binary] |
+//! | Fixed header | [push_fixed_header] | [read_fixed_header] |
+//!
+//! # Examples
+//! ```
+//! use mqtt::{packet::PacketType, wire};
+//!
+//! // a PUBLISH with QoS 0 to "a/b" with the payload "hi"
+//! let mut buf = Vec::new();
+//! wire::push_fixed_header(PacketType::PUBLISH, 0, 8, &mut buf).unwrap();
+//! wire::push_string("a/b", &mut buf).unwrap();
+//! wire::push_variable_byte_integer(0, &mut buf).unwrap(); // no properties
+//! buf.extend_from_slice(b"hi");
+//!
+//! let (header, len) = wire::read_fixed_header(&buf).unwrap().unwrap();
+//! assert_eq!((PacketType::PUBLISH, 8), (header.packet_type, header.remaining_length));
+//! let (topic, _) = wire::read_string(&buf[len..]).unwrap();
+//! assert_eq!("a/b", topic);
+//! assert_eq!(Ok(Some(buf.len())), wire::packet_len(&buf));
+//! ```
+
+use crate::{error::MqttError, packet::PacketType, types::VariableByteInteger};
+
+/// The largest value a Variable Byte Integer can hold in its four bytes.
+pub const MAX_VARIABLE_BYTE_INTEGER: u32 = 268_435_455;
+
+/// Converts `val` into two Big-Endian bytes and appends them to `vec`.
+pub fn push_be_u16(val: u16, vec: &mut Vec<u8>) {
+    vec.extend_from_slice(&val.to_be_bytes())
+}
+
+/// Converts `val` into four Big-Endian bytes and appends them to `vec`.
+pub fn push_be_u32(val: u32, vec: &mut Vec<u8>) {
+    vec.extend_from_slice(&val.to_be_bytes())
+}
+
+/// Converts the first two bytes of the slice into a big-endian u16, any further bytes are ignored.
+///
+/// Returns a `MalformedPacket` error if the slice is shorter than 2 bytes.
+pub fn u16_from_be_bytes(src: &[u8]) -> Result<u16, MqttError> {
+    match src.first_chunk::<2>() {
+        Some(bytes) => Ok(u16::from_be_bytes(*bytes)),
+        None => Err(MqttError::MalformedPacket(format!("Source slice too short for u16: {}", src.len()))),
+    }
+}
+
+/// Converts the first four bytes of the slice into a big-endian u32, any further bytes are ignored.
+///
+/// Returns a `MalformedPacket` error if the slice is shorter than 4 bytes.
+pub fn u32_from_be_bytes(src: &[u8]) -> Result<u32, MqttError> {
+    match src.first_chunk::<4>() {
+        Some(bytes) => Ok(u32::from_be_bytes(*bytes)),
+        None => Err(MqttError::MalformedPacket(format!("Source slice too short for u32: {}", src.len()))),
+    }
+}
+
+/// The number of bytes `value` takes up as a Variable Byte Integer, from 1 to 4.
+pub fn variable_byte_integer_len(value: u32) -> usize {
+    match value {
+        0..=127 => 1,
+        128..=16_383 => 2,
+        16_384..=2_097_151 => 3,
+        _ => 4,
+    }
+}
+
+/// Appends `value` as a Variable Byte Integer. Fails if it's larger than [MAX_VARIABLE_BYTE_INTEGER].
+pub fn push_variable_byte_integer(value: u32, buf: &mut Vec<u8>) -> Result<(), MqttError> {
+    if value > MAX_VARIABLE_BYTE_INTEGER {
+        return Err(MqttError::Message(
+            format!("{} exceeds the maximum Variable Byte Integer of {}", value, MAX_VARIABLE_BYTE_INTEGER)))
+    }
+    VariableByteInteger { value }.write_to(buf);
+    Ok(())
+}
+
+/// Reads a Variable Byte Integer, which ends with the first byte that doesn't have its highest bit set and may be four
+/// bytes long at most.
+pub fn read_variable_byte_integer(src: &[u8]) -> Result<(u32, usize), MqttError> {
+    let mut value: u32 = 0;
+    for (index, byte) in src.iter().take(4).enumerate() {
+        value += u32::from(byte & 127) << (7 * index);
+        if byte & 128 == 0 {
+            return Ok((value, index + 1))
+        }
+    }
+
+    match src.len() < 4 {
+        true => Err(MqttError::MalformedPacket(format!("Variable Byte Integer incomplete after {} bytes", src.len()))),
+        false => Err(MqttError::MalformedPacket("Variable Byte Integer exceeds four bytes".to_string())),
+    }
+}
+
+/// Appends `s` with its two byte length. Fails with [StringTooLong](MqttError::StringTooLong) if it's longer than
+/// 65,535 bytes.
+pub fn push_string(s: &str, buf: &mut Vec<u8>) -> Result<(), MqttError> {
+    let len = u16::try_from(s.len()).map_err(|_| MqttError::StringTooLong { len: s.len() })?;
+    push_be_u16(len, buf);
+    buf.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+/// Reads a string prefixed by its two byte length. Fails if it's not valid UTF-8 or contains U+0000, which the spec
+/// rules out for all strings [MQTT-1.5.4-1, MQTT-1.5.4-2].
+pub fn read_string(src: &[u8]) -> Result<(&str, usize), MqttError> {
+    let (bytes, len) = read_binary(src)?;
+    let s = std::str::from_utf8(bytes)
+        .map_err(|e| MqttError::MalformedPacket(format!("String is not valid UTF-8: {}", e)))?;
+    if s.contains('\0') {
+        return Err(MqttError::MalformedPacket("String contains the null character U+0000".to_string()))
+    }
+    Ok((s, len))
+}
+
+/// Appends `data` with its two byte length. Fails with [BinaryDataTooLong](MqttError::BinaryDataTooLong) if it's
+/// longer than 65,535 bytes.
+pub fn push_binary(data: &[u8], buf: &mut Vec<u8>) -> Result<(), MqttError> {
+    let len = u16::try_from(data.len()).map_err(|_| MqttError::BinaryDataTooLong { len: data.len() })?;
+    push_be_u16(len, buf);
+    buf.extend_from_slice(data);
+    Ok(())
+}
+
+/// Reads binary data prefixed by its two byte length.
+pub fn read_binary(src: &[u8]) -> Result<(&[u8], usize), MqttError> {
+    let len = u16_from_be_bytes(src)? as usize;
+    match src.get(2..2 + len) {
+        Some(data) => Ok((data, 2 + len)),
+        None => Err(MqttError::MalformedPacket(
+            format!("Length of {} bytes exceeds the {} bytes remaining", len, src.len() - 2))),
+    }
+}
+
+/// The fixed header every packet starts with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedHeader {
+    pub packet_type: PacketType,
+
+    /// The lower four bits of the first byte, see [PacketType::required_flags].
+    pub flags: u8,
+
+    /// The number of bytes following the fixed header.
+    pub remaining_length: u32,
+}
+
+impl FixedHeader {
+
+    /// The total length of the packet, fixed header included.
+    pub fn packet_len(&self) -> usize {
+        1 + variable_byte_integer_len(self.remaining_length) + self.remaining_length as usize
+    }
+}
+
+/// Appends a fixed header. The flags aren't checked, so that packets violating the spec can be written too.
+pub fn push_fixed_header(
+    packet_type: PacketType,
+    flags: u8,
+    remaining_length: u32,
+    buf: &mut Vec<u8>) -> Result<(), MqttError>
+{
+    buf.push(packet_type.to_first_byte(flags));
+    push_variable_byte_integer(remaining_length, buf)
+}
+
+/// Reads the fixed header at the beginning of `src`, which may or may not be followed by the rest of the packet yet.
+/// `None` if `src` doesn't hold the whole fixed header. The flags aren't checked, see [PacketType::check_first_byte].
+pub fn read_fixed_header(src: &[u8]) -> Result<Option<(FixedHeader, usize)>, MqttError> {
+    let (first_byte, remaining_length, len) = match remaining_length(src)? {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+    let packet_type = PacketType::try_from(first_byte)?;
+    Ok(Some((FixedHeader { packet_type, flags: first_byte & 0b1111, remaining_length }, len)))
+}
+
+/// The total length of the packet at the beginning of `src` including the fixed header, or `None` if `src` doesn't
+/// contain the entire packet yet. Tells where the next packet starts without decoding this one, which is why the
+/// packet type isn't checked.
+pub fn packet_len(src: &[u8]) -> Result<Option<usize>, MqttError> {
+    Ok(announced_packet_len(src)?.filter(|total| src.len() >= *total))
+}
+
+/// Fails with [PacketTooLarge](MqttError::PacketTooLarge) if the fixed header at the beginning of `src` announces a
+/// packet of more than `maximum` bytes, as soon as the fixed header is there and whether or not the rest of the packet
+/// is. A reader can close the connection right away instead of buffering up to 256 MB of a packet it has to reject
+/// anyway. `Ok` as long as the fixed header is incomplete.
+///
+/// # Examples
+/// ```
+/// use mqtt::{error::MqttError, packet::PacketType, wire};
+///
+/// // the fixed header of a PUBLISH with 16,384 bytes to follow, none of which have arrived
+/// let header = [0x30, 0x80, 0x80, 0x01];
+/// assert_eq!(
+///     Err(MqttError::PacketTooLarge { packet_type: PacketType::PUBLISH, size: 16_388, maximum: 1024 }),
+///     wire::check_packet_len(&header, 1024));
+/// assert!(wire::check_packet_len(&header[..3], 1024).is_ok());
+/// ```
+pub fn check_packet_len(src: &[u8], maximum: u32) -> Result<(), MqttError> {
+    match announced_packet_len(src)? {
+        Some(size) if size > maximum as usize => {
+            let packet_type = PacketType::try_from(src[0])?;
+            Err(MqttError::PacketTooLarge { packet_type, size, maximum })
+        },
+        _ => Ok(()),
+    }
+}
+
+/// The total length of the packet as announced by the fixed header at the beginning of `src`, whether or not `src`
+/// holds all of it. `None` if it doesn't even hold the whole fixed header.
+pub(crate) fn announced_packet_len(src: &[u8]) -> Result<Option<usize>, MqttError> {
+    Ok(remaining_length(src)?.map(|(_, remaining_length, len)| len + remaining_length as usize))
+}
+
+/// The first byte, the remaining length and the length of the fixed header.
+fn remaining_length(src: &[u8]) -> Result<Option<(u8, u32, usize)>, MqttError> {
+    let first_byte = match src.first() {
+        Some(byte) => *byte,
+        None => return Ok(None),
+    };
+
+    // the remaining length may not have been received completely
+    let length_bytes = &src[1..];
+    if length_bytes.len() < 4 && length_bytes.iter().all(|byte| byte & 128 != 0) {
+        return Ok(None)
+    }
+    let (remaining_length, len) = read_variable_byte_integer(length_bytes)
+        .map_err(|_| MqttError::MalformedPacket("Remaining length exceeds four bytes".to_string()))?;
+    Ok(Some((first_byte, remaining_length, 1 + len)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn u16_exact_length() {
+        assert_eq!(Ok(0x0102), u16_from_be_bytes(&[1, 2]));
+        assert_eq!(Ok(u16::MAX), u16_from_be_bytes(&[0xFF, 0xFF]));
+    }
+
+    #[test]
+    fn u16_longer_slice() {
+        assert_eq!(Ok(0x0102), u16_from_be_bytes(&[1, 2, 3]));
+    }
+
+    #[test]
+    fn u16_too_short() {
+        assert_eq!(
+            Err(MqttError::MalformedPacket("Source slice too short for u16: 1".to_string())),
+            u16_from_be_bytes(&[1]));
+        assert!(u16_from_be_bytes(&[]).is_err());
+    }
+
+    #[test]
+    fn u32_exact_length() {
+        assert_eq!(Ok(0x01020304), u32_from_be_bytes(&[1, 2, 3, 4]));
+        assert_eq!(Ok(0x01020304), u32_from_be_bytes(&[1, 2, 3, 4, 5]));
+    }
+
+    #[test]
+    fn u32_too_short() {
+        assert_eq!(
+            Err(MqttError::MalformedPacket("Source slice too short for u32: 3".to_string())),
+            u32_from_be_bytes(&[1, 2, 3]));
+    }
+
+    #[test]
+    fn push() {
+        let mut vec = vec![0xAA];
+        push_be_u16(0x0102, &mut vec);
+        push_be_u32(0x03040506, &mut vec);
+        assert_eq!(vec![0xAA, 1, 2, 3, 4, 5, 6], vec);
+    }
+
+    #[test]
+    fn variable_byte_integers() {
+        for (value, encoded) in [
+            (0, vec![0x00]),
+            (127, vec![0x7F]),
+            (128, vec![0x80, 0x01]),
+            (16_383, vec![0xFF, 0x7F]),
+            (16_384, vec![0x80, 0x80, 0x01]),
+            (2_097_152, vec![0x80, 0x80, 0x80, 0x01]),
+            (MAX_VARIABLE_BYTE_INTEGER, vec![0xFF, 0xFF, 0xFF, 0x7F]),
+        ] {
+            let mut buf = Vec::new();
+            push_variable_byte_integer(value, &mut buf).unwrap();
+            assert_eq!(encoded, buf);
+            assert_eq!(encoded.len(), variable_byte_integer_len(value));
+
+            buf.push(0xFF);
+            assert_eq!(Ok((value, encoded.len())), read_variable_byte_integer(&buf));
+        }
+
+        assert!(push_variable_byte_integer(MAX_VARIABLE_BYTE_INTEGER + 1, &mut Vec::new()).is_err());
+        assert_eq!(
+            Err(MqttError::MalformedPacket("Variable Byte Integer incomplete after 2 bytes".to_string())),
+            read_variable_byte_integer(&[0x80, 0x80]));
+        assert_eq!(
+            Err(MqttError::MalformedPacket("Variable Byte Integer exceeds four bytes".to_string())),
+            read_variable_byte_integer(&[0x80, 0x80, 0x80, 0x80, 0x01]));
+    }
+
+    #[test]
+    fn strings() {
+        let mut buf = Vec::new();
+        push_string("ä/b", &mut buf).unwrap();
+        assert_eq!(vec![0, 4, 0xC3, 0xA4, b'/', b'b'], buf);
+        buf.push(0);
+        assert_eq!(Ok(("ä/b", 6)), read_string(&buf));
+
+        assert_eq!(Ok(("", 2)), read_string(&[0, 0]));
+        assert!(read_string(&[0, 2, 0xC3]).is_err());
+        assert!(read_string(&[0, 1, 0xC3]).is_err());
+        assert!(read_string(&[0, 1, 0]).is_err());
+        assert_eq!(
+            Err(MqttError::StringTooLong { len: 65_536 }),
+            push_string(&"a".repeat(65_536), &mut Vec::new()));
+    }
+
+    #[test]
+    fn binary() {
+        let mut buf = Vec::new();
+        push_binary(&[0, 0xFF], &mut buf).unwrap();
+        assert_eq!(vec![0, 2, 0, 0xFF], buf);
+        assert_eq!(Ok((&[0, 0xFF][..], 4)), read_binary(&buf));
+
+        assert_eq!(
+            Err(MqttError::MalformedPacket("Length of 2 bytes exceeds the 1 bytes remaining".to_string())),
+            read_binary(&[0, 2, 1]));
+        assert!(read_binary(&[0]).is_err());
+        assert_eq!(
+            Err(MqttError::BinaryDataTooLong { len: 65_536 }),
+            push_binary(&[0; 65_536], &mut Vec::new()));
+    }
+
+    #[test]
+    fn fixed_header() {
+        let mut buf = Vec::new();
+        push_fixed_header(PacketType::PUBREL, 0b0010, 200, &mut buf).unwrap();
+        assert_eq!(vec![0b01100010, 0xC8, 0x01], buf);
+
+        let header = FixedHeader { packet_type: PacketType::PUBREL, flags: 0b0010, remaining_length: 200 };
+        assert_eq!(Ok(Some((header, 3))), read_fixed_header(&buf));
+        assert_eq!(203, header.packet_len());
+
+        // incomplete
+        assert_eq!(Ok(None), read_fixed_header(&[]));
+        assert_eq!(Ok(None), read_fixed_header(&buf[..2]));
+        assert_eq!(Ok(None), packet_len(&buf));
+        buf.resize(203, 0);
+        assert_eq!(Ok(Some(203)), packet_len(&buf));
+        buf.push(0);
+        assert_eq!(Ok(Some(203)), packet_len(&buf));
+
+        assert_eq!(
+            Err(MqttError::MalformedPacket("Remaining length exceeds four bytes".to_string())),
+            read_fixed_header(&[0x30, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]));
+        assert!(read_fixed_header(&[0x00, 0x00]).is_err());
+    }
+}
@@ -0,0 +1,89 @@
+//! Allocation budgets for encoding and decoding the packets that make up most of the traffic. A test failing here
+//! means a change made one of them allocate more than it used to: either that's on purpose and the budget goes up, or
+//! it's a regression.
+//!
+//! Budgets are exact as of writing. Lowering one after making a path cheaper keeps it from creeping back.
+
+use mqtt::{
+    packet::{Packet, Puback, Publish},
+    test_util::{assert_allocations, count_allocations, from_hex, CountingAllocator},
+    types::{QoS, ReasonCode},
+};
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// QoS 0 to "sensors/1" with an 8 byte payload.
+const PUBLISH_QOS0: &str = "30 14 00 09 73 65 6e 73 6f 72 73 2f 31 00 30 31 32 33 34 35 36 37";
+
+/// QoS 1 to "sensors/1" with packet identifier 7, a content type and an 8 byte payload.
+const PUBLISH_QOS1: &str = "
+    32 23 00 09 73 65 6e 73 6f 72 73 2f 31 00 07 0d 03 00 0a 74 65 78 74 2f 70 6c 61 69 6e
+    30 31 32 33 34 35 36 37";
+
+/// A CONNACK with most of the properties a server announces, including an assigned client identifier, a reason
+/// string, a user property and response information.
+const CONNACK: &str = "
+    20 4a 00 00 47 21 00 0a 24 01 25 01 27 00 00 04 00 12 00 08 63 6c 69 65 6e 74 2d 31 22 00 05 1f 00 07 77 65 6c
+    63 6f 6d 65 26 00 06 72 65 67 69 6f 6e 00 02 65 75 28 01 29 01 2a 00 13 00 3c 1a 00 0a 72 65 73 70 6f 6e 73 65
+    73 2f";
+
+/// Decodes the fixture outside of any budget.
+fn decode(hex: &str) -> (Vec<u8>, Packet) {
+    let bytes = from_hex(hex).unwrap();
+    let packet = Packet::try_from(&bytes[..]).unwrap();
+    (bytes, packet)
+}
+
+#[test]
+fn decode_publish() {
+    let (bytes, _) = decode(PUBLISH_QOS0);
+    let packet = assert_allocations(2, "QoS 0 PUBLISH decode", || Packet::try_from(&bytes[..]).unwrap());
+    assert_eq!(QoS::AtMostOnce, publish(packet).qos_level);
+
+    let (bytes, _) = decode(PUBLISH_QOS1);
+    let packet = assert_allocations(4, "QoS 1 PUBLISH decode", || Packet::try_from(&bytes[..]).unwrap());
+    assert_eq!(Some(7), publish(packet).packet_identifier);
+}
+
+#[test]
+fn encode_publish() {
+    let (bytes, packet) = decode(PUBLISH_QOS0);
+    let encoded = assert_allocations(5, "QoS 0 PUBLISH encode", || Vec::try_from(packet).unwrap());
+    assert_eq!(bytes, encoded);
+
+    let (bytes, packet) = decode(PUBLISH_QOS1);
+    let encoded = assert_allocations(8, "QoS 1 PUBLISH encode", || Vec::try_from(packet).unwrap());
+    assert_eq!(bytes, encoded);
+}
+
+#[test]
+fn connack() {
+    let (bytes, packet) = decode(CONNACK);
+    let encoded = assert_allocations(8, "CONNACK encode", || Vec::try_from(packet).unwrap());
+    assert_eq!(bytes, encoded);
+    assert_allocations(9, "CONNACK decode", || Packet::try_from(&bytes[..]).unwrap());
+}
+
+#[test]
+fn acknowledgements() {
+    let puback = Packet::from(Puback { packet_identifier: 7, reason_code: ReasonCode::Success, properties: None });
+    let bytes = assert_allocations(1, "PUBACK encode", || Vec::try_from(puback).unwrap());
+    assert_allocations(0, "PUBACK decode", || Packet::try_from(&bytes[..]).unwrap());
+    assert_allocations(0, "PINGREQ decode", || Packet::try_from(&[0xC0, 0x00][..]).unwrap());
+}
+
+#[test]
+fn counts() {
+    let (_, allocations) = count_allocations(|| vec![0u8; 16]);
+    assert_eq!(1, allocations);
+    let (_, allocations) = count_allocations(|| 1 + 1);
+    assert_eq!(0, allocations);
+}
+
+fn publish(packet: Packet) -> Publish {
+    match packet {
+        Packet::Publish(publish) => publish,
+        other => panic!("expected a PUBLISH, got {}", other.packet_type()),
+    }
+}
@@ -0,0 +1,84 @@
+//! Packets generated from pseudo-random input the way a fuzzer would, as a quick stand-in for an actual fuzzing run:
+//! whatever we encode has to decode again, and survive the round trip a differential harness checks.
+
+#![cfg(feature = "arbitrary")]
+
+use arbitrary::{Arbitrary, Unstructured};
+use mqtt::{
+    assert_packet_eq,
+    packet::Packet,
+    test_util::{check_round_trip, to_hex},
+};
+
+const RUNS: u64 = 2_000;
+
+/// The same input on every run, so that a failure can be reproduced.
+fn input(seed: u64) -> Vec<u8> {
+    let mut state = seed;
+    let len = 16 + (seed % 512) as usize;
+    (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+            (state >> 56) as u8
+        })
+        .collect()
+}
+
+#[test]
+fn generated_packets_round_trip() {
+    let mut encoded_count = 0;
+    for seed in 0..RUNS {
+        let input = input(seed);
+        let Ok(packet) = Packet::arbitrary(&mut Unstructured::new(&input)) else {
+            continue
+        };
+        let packet_type = packet.packet_type();
+        let Ok(encoded) = Vec::<u8>::try_from(packet) else {
+            continue
+        };
+        encoded_count += 1;
+
+        let decoded = Packet::try_from(&encoded[..])
+            .unwrap_or_else(|e| panic!("seed {}: {:?} encoded as {} rejected: {}", seed, packet_type, to_hex(&encoded), e));
+        assert_eq!(packet_type, decoded.packet_type(), "seed {}", seed);
+        if let Err(e) = check_round_trip(&encoded) {
+            panic!("seed {}: {}", seed, e);
+        }
+    }
+    // the generator is of little use if most of what it makes can't be encoded
+    assert!(encoded_count > RUNS * 9 / 10, "only {} of {} generated packets encoded", encoded_count, RUNS);
+}
+
+#[test]
+fn encoded_size_matches_encoding() {
+    for seed in 0..RUNS {
+        let input = input(seed);
+        let Ok(packet) = Packet::arbitrary(&mut Unstructured::new(&input)) else {
+            continue
+        };
+        let (packet_type, size) = (packet.packet_type(), packet.encoded_size());
+        let Ok(encoded) = Vec::<u8>::try_from(packet) else {
+            continue
+        };
+        assert_eq!(encoded.len(), size, "seed {}: {:?} encoded as {}", seed, packet_type, to_hex(&encoded));
+        assert!(Packet::try_from(&encoded[..]).is_ok(), "seed {}: {:?} encoded as {}", seed, packet_type, to_hex(&encoded));
+    }
+}
+
+#[test]
+fn same_input_same_packet() {
+    let input = input(42);
+    let first = Packet::arbitrary(&mut Unstructured::new(&input)).unwrap();
+    let second = Packet::arbitrary(&mut Unstructured::new(&input)).unwrap();
+    assert_packet_eq!(first, second);
+}
+
+#[test]
+fn round_trip_of_raw_input() {
+    // bytes a fuzzer would feed the decoder directly, most of which aren't packets
+    for seed in 0..RUNS {
+        if let Err(e) = check_round_trip(&input(seed)) {
+            panic!("seed {}: {}", seed, e);
+        }
+    }
+}
@@ -0,0 +1,312 @@
+//! QoS 1 and 2 edge cases played through [FlowSim]: duplicates, acknowledgements out of order or for unknown packet
+//! identifiers, and redelivery after reconnecting as described in the spec's section on message delivery retry
+//! [MQTT-4.4].
+
+#![cfg(feature = "session")]
+
+use mqtt::{
+    error::MqttError,
+    packet::{Packet, Puback, Pubcomp, Publish, Pubrec, Pubrel},
+    session::{FlowState, SessionConfig},
+    test_util::FlowSim,
+    types::{QoS, ReasonCode},
+};
+
+fn publish(topic: &str, packet_identifier: u16, qos: QoS) -> Publish {
+    let mut publish = Publish::with_topic_name(topic.try_into().unwrap(), vec![1, 2, 3]);
+    publish.qos_level = qos;
+    publish.packet_identifier = Some(packet_identifier);
+    publish
+}
+
+fn duplicate(mut publish: Publish) -> Publish {
+    publish.dup = true;
+    publish
+}
+
+fn puback(packet_identifier: u16) -> Puback {
+    Puback::new(packet_identifier, ReasonCode::Success).unwrap()
+}
+
+fn pubrec(packet_identifier: u16, reason_code: ReasonCode) -> Pubrec {
+    Pubrec::new(packet_identifier, reason_code).unwrap()
+}
+
+fn pubrel(packet_identifier: u16) -> Pubrel {
+    Pubrel::new(packet_identifier, ReasonCode::Success).unwrap()
+}
+
+fn pubcomp(packet_identifier: u16, reason_code: ReasonCode) -> Pubcomp {
+    Pubcomp::new(packet_identifier, reason_code).unwrap()
+}
+
+fn protocol_error(message: &str) -> MqttError {
+    MqttError::ProtocolError(message.to_string())
+}
+
+#[test]
+fn qos1_outgoing() {
+    FlowSim::new()
+        .publish(publish("a", 1, QoS::AtLeastOnce))
+        .emits([publish("a", 1, QoS::AtLeastOnce)])
+        .in_flight(&[(1, FlowState::AwaitingPuback)])
+        .receive(puback(1))
+        .emits_nothing()
+        .completes(&[1])
+        .in_flight(&[])
+        // the packet identifier is free again once the flow is complete [MQTT-2.2.1-3]
+        .publish(publish("a", 1, QoS::AtLeastOnce))
+        .receive(puback(1))
+        .completes(&[1])
+        .run()
+        .unwrap();
+}
+
+#[test]
+fn qos1_duplicate_and_unknown_puback() {
+    FlowSim::new()
+        .publish(publish("a", 1, QoS::AtLeastOnce))
+        .receive(puback(1))
+        .receive(puback(1))
+        .fails_with(protocol_error("PUBACK for unknown packet identifier 1"))
+        .receive(puback(7))
+        .fails_with(protocol_error("PUBACK for unknown packet identifier 7"))
+        .completes(&[1])
+        .run()
+        .unwrap();
+}
+
+#[test]
+fn packet_identifier_in_use() {
+    FlowSim::new()
+        .publish(publish("a", 1, QoS::AtLeastOnce))
+        .publish(publish("b", 1, QoS::ExactlyOnce))
+        .fails_with(protocol_error("Packet identifier already in use: 1"))
+        .in_flight(&[(1, FlowState::AwaitingPuback)])
+        .run()
+        .unwrap();
+}
+
+#[test]
+fn acks_out_of_order() {
+    FlowSim::new()
+        .publish(publish("a", 1, QoS::AtLeastOnce))
+        .publish(publish("a", 2, QoS::AtLeastOnce))
+        .publish(publish("a", 3, QoS::AtLeastOnce))
+        .receive(puback(3))
+        .receive(puback(1))
+        .completes(&[3, 1])
+        .in_flight(&[(2, FlowState::AwaitingPuback)])
+        .run()
+        .unwrap();
+}
+
+#[test]
+fn acks_out_of_order_with_ordered_delivery() {
+    FlowSim::with_config(SessionConfig { ordered_delivery: true, ..Default::default() })
+        .publish(publish("a", 1, QoS::ExactlyOnce))
+        .publish(publish("a", 2, QoS::AtLeastOnce))
+        .publish(publish("b", 3, QoS::AtLeastOnce))
+        .receive(puback(2))
+        .receive(puback(3))
+        // another topic isn't held back
+        .completes(&[3])
+        .in_flight(&[(1, FlowState::AwaitingPubrec), (2, FlowState::Resolved)])
+        .receive(pubrec(1, ReasonCode::Success))
+        .receive(pubcomp(1, ReasonCode::Success))
+        .completes(&[1, 2])
+        .run()
+        .unwrap();
+}
+
+#[test]
+fn qos2_outgoing() {
+    FlowSim::new()
+        .publish(publish("a", 1, QoS::ExactlyOnce))
+        .in_flight(&[(1, FlowState::AwaitingPubrec)])
+        .receive(pubrec(1, ReasonCode::Success))
+        .emits([pubrel(1)])
+        .in_flight(&[(1, FlowState::AwaitingPubcomp)])
+        .completes(&[])
+        .receive(pubcomp(1, ReasonCode::Success))
+        .emits_nothing()
+        .completes(&[1])
+        .run()
+        .unwrap();
+}
+
+#[test]
+fn qos2_acks_in_the_wrong_state() {
+    FlowSim::new()
+        .publish(publish("a", 1, QoS::ExactlyOnce))
+        .receive(puback(1))
+        .fails_with(protocol_error("Unexpected PUBACK for packet identifier 1 in state AwaitingPubrec"))
+        .receive(pubcomp(1, ReasonCode::Success))
+        .fails_with(protocol_error("Unexpected PUBCOMP for packet identifier 1 in state AwaitingPubrec"))
+        .receive(pubrec(1, ReasonCode::Success))
+        .emits([pubrel(1)])
+        .receive(pubrec(1, ReasonCode::Success))
+        .fails_with(protocol_error("Unexpected PUBREC for packet identifier 1 in state AwaitingPubcomp"))
+        .receive(pubcomp(2, ReasonCode::Success))
+        .fails_with(protocol_error("PUBCOMP for unknown packet identifier 2"))
+        .in_flight(&[(1, FlowState::AwaitingPubcomp)])
+        .run()
+        .unwrap();
+}
+
+#[test]
+fn qos2_rejected_by_pubrec() {
+    // a PUBREC with an error reason code acknowledges the message, which must not be retransmitted [MQTT-4.4.0-2]
+    FlowSim::new()
+        .publish(publish("a", 1, QoS::ExactlyOnce))
+        .receive(pubrec(1, ReasonCode::QuotaExceeded))
+        .emits_nothing()
+        .completes(&[1])
+        .reconnect(true)
+        .emits_nothing()
+        .run()
+        .unwrap();
+}
+
+#[test]
+fn redelivery_with_session_present() {
+    // unacknowledged PUBLISH packets are resent with the DUP flag and PUBREL packets as they were, with their original
+    // packet identifiers and in their original order [MQTT-4.4.0-1, MQTT-3.3.1-1]
+    FlowSim::new()
+        .publish(publish("a", 1, QoS::AtLeastOnce))
+        .publish(publish("a", 2, QoS::ExactlyOnce))
+        .publish(publish("a", 3, QoS::ExactlyOnce))
+        .receive(pubrec(2, ReasonCode::Success))
+        .reconnect(true)
+        .emits::<Packet>([
+            duplicate(publish("a", 1, QoS::AtLeastOnce)).into(),
+            pubrel(2).into(),
+            duplicate(publish("a", 3, QoS::ExactlyOnce)).into(),
+        ])
+        .receive(puback(1))
+        .receive(pubcomp(2, ReasonCode::Success))
+        .receive(pubrec(3, ReasonCode::Success))
+        .emits([pubrel(3)])
+        .receive(pubcomp(3, ReasonCode::Success))
+        .completes(&[1, 2, 3])
+        .run()
+        .unwrap();
+}
+
+#[test]
+fn no_redelivery_without_session_present() {
+    FlowSim::new()
+        .publish(publish("a", 1, QoS::AtLeastOnce))
+        .publish(publish("a", 2, QoS::ExactlyOnce))
+        .receive(publish("b", 5, QoS::ExactlyOnce))
+        .reconnect(false)
+        .emits_nothing()
+        .in_flight(&[])
+        // acknowledgements from the old session mean nothing to the new one
+        .receive(puback(1))
+        .fails_with(protocol_error("PUBACK for unknown packet identifier 1"))
+        .receive(pubrel(5))
+        .emits([pubcomp(5, ReasonCode::PacketIdentifierNotFound)])
+        .completes(&[])
+        .run()
+        .unwrap();
+}
+
+#[test]
+fn qos0_incoming() {
+    let mut qos0 = Publish::with_topic_name("a".try_into().unwrap(), vec![]);
+    qos0.packet_identifier = None;
+    FlowSim::new()
+        .receive(qos0.clone())
+        .emits_nothing()
+        .receive(qos0)
+        .delivers(&[0, 0])
+        .in_flight(&[])
+        .run()
+        .unwrap();
+}
+
+#[test]
+fn qos1_incoming_duplicate() {
+    // at least once: a resent message is acknowledged and passed on again
+    FlowSim::new()
+        .receive(publish("a", 1, QoS::AtLeastOnce))
+        .emits([puback(1)])
+        .receive(duplicate(publish("a", 1, QoS::AtLeastOnce)))
+        .emits([puback(1)])
+        .delivers(&[1, 1])
+        .in_flight(&[])
+        .run()
+        .unwrap();
+}
+
+#[test]
+fn qos2_incoming_duplicate() {
+    // exactly once: a resent message is acknowledged again, but only passed on the first time [MQTT-4.3.3-10]
+    FlowSim::new()
+        .receive(publish("a", 1, QoS::ExactlyOnce))
+        .emits([pubrec(1, ReasonCode::Success)])
+        .in_flight(&[(1, FlowState::AwaitingPubrel)])
+        .receive(duplicate(publish("a", 1, QoS::ExactlyOnce)))
+        .emits([pubrec(1, ReasonCode::Success)])
+        .delivers(&[1])
+        .receive(pubrel(1))
+        .emits([pubcomp(1, ReasonCode::Success)])
+        .in_flight(&[])
+        // once released, the packet identifier starts a new message
+        .receive(publish("a", 1, QoS::ExactlyOnce))
+        .delivers(&[1])
+        .run()
+        .unwrap();
+}
+
+#[test]
+fn qos2_incoming_duplicate_pubrel() {
+    // the server resends PUBREL if it missed the PUBCOMP, which still has to be answered
+    FlowSim::new()
+        .receive(publish("a", 1, QoS::ExactlyOnce))
+        .receive(pubrel(1))
+        .emits([pubcomp(1, ReasonCode::Success)])
+        .receive(pubrel(1))
+        .emits([pubcomp(1, ReasonCode::PacketIdentifierNotFound)])
+        .delivers(&[1])
+        .run()
+        .unwrap();
+}
+
+#[test]
+fn qos2_incoming_across_reconnect() {
+    // the session still awaits the PUBREL after resuming, a redelivered PUBLISH isn't passed on again
+    FlowSim::new()
+        .receive(publish("a", 1, QoS::ExactlyOnce))
+        .delivers(&[1])
+        .reconnect(true)
+        .emits_nothing()
+        .in_flight(&[(1, FlowState::AwaitingPubrel)])
+        .receive(duplicate(publish("a", 1, QoS::ExactlyOnce)))
+        .emits([pubrec(1, ReasonCode::Success)])
+        .delivers(&[])
+        .receive(pubrel(1))
+        .emits([pubcomp(1, ReasonCode::Success)])
+        .run()
+        .unwrap();
+}
+
+#[test]
+fn packet_identifiers_per_direction() {
+    // the client's and the server's packet identifiers are independent of each other
+    FlowSim::new()
+        .publish(publish("a", 1, QoS::ExactlyOnce))
+        .receive(publish("b", 1, QoS::ExactlyOnce))
+        .emits([pubrec(1, ReasonCode::Success)])
+        .receive(pubrec(1, ReasonCode::Success))
+        .emits([pubrel(1)])
+        .receive(pubrel(1))
+        .emits([pubcomp(1, ReasonCode::Success)])
+        .in_flight(&[(1, FlowState::AwaitingPubcomp)])
+        .receive(pubcomp(1, ReasonCode::Success))
+        .completes(&[1])
+        .delivers(&[1])
+        .run()
+        .unwrap();
+}
@@ -0,0 +1,80 @@
+//! EMQX 5.
+
+use mqtt::{packet::Packet, types::ReasonCode};
+
+use super::{decode, replay};
+
+/// Maximum packet size 1MB, receive maximum 32, retain, wildcard subscriptions, subscription identifiers and shared
+/// subscriptions explicitly available, topic alias maximum 65535.
+const CONNACK: &str = "20 16 00 00 13 27 00 10 00 00 21 00 20 25 01 28 01 29 01 2a 01 22 ff ff";
+
+/// No matching subscribers, with a reason code but without property length.
+const PUBACK_NO_SUBSCRIBERS: &str = "40 03 00 01 10";
+
+/// Granted QoS 1 for the first filter, not authorized for the second.
+const SUBACK_PARTIAL: &str = "90 05 00 02 00 01 87";
+
+/// No subscription existed.
+const UNSUBACK: &str = "b0 04 00 03 00 11";
+
+/// Session taken over, without property length.
+const DISCONNECT_TAKEN_OVER: &str = "e0 01 8e";
+
+#[test]
+fn connack() {
+    match decode(CONNACK) {
+        Packet::Connack(connack) => {
+            let properties = connack.properties.unwrap();
+            assert_eq!(Some(1_048_576), properties.maximum_packet_size);
+            assert_eq!(Some(32), properties.receive_maximum);
+            assert_eq!(Some(65535), properties.topic_alias_maximum);
+            assert_eq!(
+                (Some(true), Some(true), Some(true), Some(true)),
+                (
+                    properties.retain_available,
+                    properties.wildcard_subscription_available,
+                    properties.subscription_identifier_available,
+                    properties.shared_subscription_available,
+                ));
+        },
+        other => panic!("unexpected packet: {:?}", other),
+    }
+    replay(CONNACK);
+}
+
+#[test]
+fn puback_without_property_length() {
+    match decode(PUBACK_NO_SUBSCRIBERS) {
+        Packet::Puback(puback) => assert_eq!(ReasonCode::NoMatchingSubscribers, puback.reason_code),
+        other => panic!("unexpected packet: {:?}", other),
+    }
+    // we always send the property length along with the reason code, which is just as valid
+    replay(PUBACK_NO_SUBSCRIBERS);
+}
+
+#[test]
+fn suback_partial() {
+    match decode(SUBACK_PARTIAL) {
+        Packet::Suback(suback) => assert_eq!(vec![ReasonCode::GrantedQoS1, ReasonCode::NotAuthorized], suback.reason_codes),
+        other => panic!("unexpected packet: {:?}", other),
+    }
+    assert_eq!(SUBACK_PARTIAL, replay(SUBACK_PARTIAL));
+}
+
+#[test]
+fn unsuback() {
+    match decode(UNSUBACK) {
+        Packet::Unsuback(unsuback) => assert_eq!(vec![ReasonCode::NoSubscriptionExisted], unsuback.reason_codes),
+        other => panic!("unexpected packet: {:?}", other),
+    }
+    assert_eq!(UNSUBACK, replay(UNSUBACK));
+}
+
+#[test]
+fn disconnect_without_property_length() {
+    match decode(DISCONNECT_TAKEN_OVER) {
+        Packet::Disconnect(disconnect) => assert_eq!(ReasonCode::SessionTakenOver, disconnect.reason_code),
+        other => panic!("unexpected packet: {:?}", other),
+    }
+    replay(DISCONNECT_TAKEN_OVER);
+}
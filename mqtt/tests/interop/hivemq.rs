@@ -0,0 +1,57 @@
+//! HiveMQ Community Edition 2023.
+
+use mqtt::{packet::Packet, types::ReasonCode};
+
+use super::{decode, replay};
+
+/// Receive maximum 10, topic alias maximum 5, maximum packet size 268,435,460 and an assigned client identifier.
+const CONNACK: &str = "
+    20 3d 00 00 3a 21 00 0a 22 00 05 27 10 00 00 04 12 00 2c 68 6d 71 5f 32 45 47 62 53 5f 31 5f 36 64 32 63 39 64
+    30 62 63 31 61 38 66 30 64 62 65 32 63 34 61 35 66 36 34 66 37 39 66 61 65 34";
+
+/// Keep alive timeout, with a reason string.
+const DISCONNECT_KEEP_ALIVE: &str = "
+    e0 4c 8d 4a 1f 00 47 54 68 65 20 63 6c 69 65 6e 74 20 77 61 73 20 69 64 6c 65 20 66 6f 72 20 74 6f 6f 20 6c 6f
+    6e 67 20 77 69 74 68 6f 75 74 20 73 65 6e 64 69 6e 67 20 61 6e 20 4d 51 54 54 20 63 6f 6e 74 72 6f 6c 20 70 61
+    63 6b 65 74";
+
+/// No matching subscribers, without property length.
+const PUBREC_NO_SUBSCRIBERS: &str = "50 03 00 05 10";
+
+#[test]
+fn connack() {
+    match decode(CONNACK) {
+        Packet::Connack(connack) => {
+            let properties = connack.properties.unwrap();
+            // just above the maximum remaining length, which makes no practical difference
+            assert_eq!(Some(268_435_460), properties.maximum_packet_size);
+            assert_eq!(Some("hmq_2EGbS_1_6d2c9d0bc1a8f0dbe2c4a5f64f79fae4"), properties.assigned_client_identifier.as_deref());
+        },
+        other => panic!("unexpected packet: {:?}", other),
+    }
+    replay(CONNACK);
+}
+
+#[test]
+fn disconnect_with_reason_string() {
+    match decode(DISCONNECT_KEEP_ALIVE) {
+        Packet::Disconnect(disconnect) => {
+            assert_eq!(ReasonCode::KeepAliveTimeout, disconnect.reason_code);
+            assert_eq!(
+                Some("The client was idle for too long without sending an MQTT control packet"),
+                disconnect.properties.unwrap().reason_string.as_deref());
+        },
+        other => panic!("unexpected packet: {:?}", other),
+    }
+    replay(DISCONNECT_KEEP_ALIVE);
+}
+
+#[test]
+fn pubrec_without_property_length() {
+    match decode(PUBREC_NO_SUBSCRIBERS) {
+        Packet::Pubrec(pubrec) => assert_eq!(ReasonCode::NoMatchingSubscribers, pubrec.reason_code),
+        other => panic!("unexpected packet: {:?}", other),
+    }
+    // we always send the property length along with the reason code, which is just as valid
+    replay(PUBREC_NO_SUBSCRIBERS);
+}
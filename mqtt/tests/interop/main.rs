@@ -0,0 +1,30 @@
+//! Packets as popular brokers send them, to make sure quirks that are legal but uncommon keep decoding, and that what
+//! we encode from them is accepted back.
+//!
+//! Each broker has its own module with hex fixtures of packets in the shape that broker sends them. When a broker turns
+//! out to send something we don't handle, the packet goes in here along with the fix.
+
+mod emqx;
+mod hivemq;
+mod mosquitto;
+
+use mqtt::{
+    assert_packet_eq,
+    packet::Packet,
+    test_util::{from_hex, to_hex},
+};
+
+/// Decodes the fixture, failing the test if it isn't accepted.
+fn decode(hex: &str) -> Packet {
+    let bytes = from_hex(hex).expect("fixture must be valid hex");
+    Packet::try_from(&bytes[..]).unwrap_or_else(|e| panic!("fixture {} rejected: {}", hex, e))
+}
+
+/// Decodes the fixture, encodes the result and decodes that again, which has to yield the same packet. Returns our
+/// encoding, for fixtures we are expected to reproduce byte for byte.
+fn replay(hex: &str) -> String {
+    let encoded: Vec<u8> = decode(hex).try_into().expect("decoded packet must be encodable");
+    let decoded = Packet::try_from(&encoded[..]).expect("encoded packet must be accepted back");
+    assert_packet_eq!(decode(hex), decoded, "round trip of {}", hex);
+    to_hex(&encoded)
+}
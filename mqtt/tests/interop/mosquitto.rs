@@ -0,0 +1,87 @@
+//! Eclipse Mosquitto 2.0.
+
+use mqtt::{
+    packet::{Packet, Puback, Pubcomp, Pubrec, Suback},
+    types::ReasonCode,
+};
+
+use super::{decode, replay};
+
+/// Receive maximum 20, topic alias maximum 10.
+const CONNACK: &str = "20 09 00 00 06 21 00 14 22 00 0a";
+
+/// Same as [CONNACK], plus the identifier assigned to a client that connected without one.
+const CONNACK_ASSIGNED_ID: &str = "
+    20 35 00 00 32 21 00 14 22 00 0a 12 00 29 61 75 74 6f 2d 35 41 35 43 33 43 38 42 2d 35 44 35 42 2d 34 42 36 36
+    2d 41 33 41 30 2d 30 37 41 32 43 30 45 31 44 33 46 37";
+
+/// Success without reason code and property length, the short form of all acknowledgements.
+const PUBACK: &str = "40 02 00 01";
+const PUBREC: &str = "50 02 00 02";
+const PUBCOMP: &str = "70 02 00 02";
+
+/// Granted QoS 1, with an empty property length.
+const SUBACK: &str = "90 04 00 01 00 01";
+
+#[test]
+fn connack() {
+    match decode(CONNACK) {
+        Packet::Connack(connack) => {
+            assert_eq!(ReasonCode::Success, connack.reason_code);
+            let properties = connack.properties.unwrap();
+            assert_eq!(Some(20), properties.receive_maximum);
+            assert_eq!(Some(10), properties.topic_alias_maximum);
+        },
+        other => panic!("unexpected packet: {:?}", other),
+    }
+    assert_eq!(CONNACK, replay(CONNACK));
+}
+
+#[test]
+fn connack_assigned_client_identifier() {
+    match decode(CONNACK_ASSIGNED_ID) {
+        Packet::Connack(connack) => assert_eq!(
+            Some("auto-5A5C3C8B-5D5B-4B66-A3A0-07A2C0E1D3F7"),
+            connack.properties.unwrap().assigned_client_identifier.as_deref()),
+        other => panic!("unexpected packet: {:?}", other),
+    }
+    replay(CONNACK_ASSIGNED_ID);
+}
+
+#[test]
+fn short_acks() {
+    match decode(PUBACK) {
+        Packet::Puback(Puback { packet_identifier, reason_code, properties }) => {
+            assert_eq!((1, ReasonCode::Success, None), (packet_identifier, reason_code, properties))
+        },
+        other => panic!("unexpected packet: {:?}", other),
+    }
+    match decode(PUBREC) {
+        Packet::Pubrec(Pubrec { packet_identifier, reason_code, properties }) => {
+            assert_eq!((2, ReasonCode::Success, None), (packet_identifier, reason_code, properties))
+        },
+        other => panic!("unexpected packet: {:?}", other),
+    }
+    match decode(PUBCOMP) {
+        Packet::Pubcomp(Pubcomp { packet_identifier, reason_code, properties }) => {
+            assert_eq!((2, ReasonCode::Success, None), (packet_identifier, reason_code, properties))
+        },
+        other => panic!("unexpected packet: {:?}", other),
+    }
+
+    // we send the short form just the same
+    for fixture in [PUBACK, PUBREC, PUBCOMP] {
+        assert_eq!(fixture, replay(fixture));
+    }
+}
+
+#[test]
+fn suback() {
+    match decode(SUBACK) {
+        Packet::Suback(Suback { packet_identifier, reason_codes, .. }) => {
+            assert_eq!((1, vec![ReasonCode::GrantedQoS1]), (packet_identifier, reason_codes))
+        },
+        other => panic!("unexpected packet: {:?}", other),
+    }
+    assert_eq!(SUBACK, replay(SUBACK));
+}